@@ -10,12 +10,18 @@ use rten_tensor::prelude::*;
 use rten_tensor::Tensor;
 
 mod dim_size;
+mod npy;
+mod quantize;
 use dim_size::DimSize;
+use quantize::{parse_calibration_input, parse_granularity, quantize_model, QuantizeArgs};
 
 struct Args {
     /// Model file to load.
     model: String,
 
+    /// Arguments for the `quantize` subcommand, if used.
+    quantize: Option<QuantizeArgs>,
+
     /// Whether to enable graph optimizations
     optimize: bool,
 
@@ -54,6 +60,7 @@ fn parse_args() -> Result<Args, lexopt::Error> {
     let mut input_sizes = Vec::new();
     let mut optimize = true;
     let mut prepack_weights = false;
+    let mut quantize_args = QuantizeArgs::default();
 
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
@@ -81,16 +88,33 @@ fn parse_args() -> Result<Args, lexopt::Error> {
                     DimSize::parse(&value).map_err(|err| lexopt::Error::Custom(err.into()))?;
                 input_sizes.push(size);
             }
+            Long("granularity") => {
+                let value = parser.value()?.string()?;
+                quantize_args.granularity =
+                    parse_granularity(&value).map_err(|err| lexopt::Error::Custom(err.into()))?;
+            }
+            Long("calibrate") => {
+                let value = parser.value()?.string()?;
+                let input = parse_calibration_input(&value)
+                    .map_err(|err| lexopt::Error::Custom(err.into()))?;
+                quantize_args.calibration.push(input);
+            }
+            Long("no-reduce-range") => quantize_args.reduce_range = false,
+            Long("quantize-conv") => quantize_args.quantize_conv = true,
             Short('h') | Long("help") => {
                 println!(
                     "Inspect and run RTen models.
 
 Usage: {bin_name} [OPTIONS] <model>
+       {bin_name} quantize [OPTIONS] <model> [<output>]
 
 Args:
   <model>
     Path to '.rten' model to inspect and run.
 
+  <output>
+    Path to write quantized model to. Defaults to `<model>.quant.rten`.
+
 Options:
   -h, --help     Print help
 
@@ -114,6 +138,23 @@ Options:
 
   -v, --verbose  Enable verbose logging
   -V, --version  Display RTen version
+
+Quantize options:
+  --calibrate <input_name=path.npy>
+                 Use static quantization with a calibration input read from
+                 a `.npy` file. Repeat to provide multiple inputs or samples.
+                 The Nth file for each input forms the Nth sample.
+
+  --granularity <tensor|channel|block:N>
+                 Granularity of weight scales. Defaults to `channel`.
+                 Block quantization applies to MatMul weights only.
+
+  --no-reduce-range
+                 Use the full 8-bit range for weights. Quantized models may
+                 then produce incorrect results on x64 CPUs without VNNI.
+
+  --quantize-conv
+                 Quantize Conv operators as well as MatMul operators
 ",
                     bin_name = parser.bin_name().unwrap_or("rten")
                 );
@@ -123,7 +164,18 @@ Options:
         }
     }
 
+    let quantize = if values.front().map(|v| v.as_str()) == Some("quantize") {
+        values.pop_front();
+        Some(quantize_args)
+    } else {
+        None
+    };
+
     let model = values.pop_front().ok_or("missing `<model>` arg")?;
+    let quantize = quantize.map(|mut args| {
+        args.output = values.pop_front();
+        args
+    });
 
     DimSize::sort_dedup(&mut input_sizes);
 
@@ -134,6 +186,7 @@ Options:
         n_iters,
         optimize,
         prepack_weights,
+        quantize,
         quiet,
         timing,
         verbose,
@@ -448,11 +501,21 @@ fn print_input_output_list(model: &Model, node_ids: &[NodeId]) {
 /// cargo run -p rten-cli --release output.rten
 /// ```
 ///
+/// It can also quantize models:
+///
+/// ```
+/// cargo run -p rten-cli --release quantize output.rten
+/// ```
+///
 /// To get detailed timing information set the `RTEN_TIMING` env var before
 /// running. See `docs/profiling.md`.
 fn main() -> Result<(), Box<dyn Error>> {
     let args = parse_args()?;
 
+    if let Some(quantize_args) = &args.quantize {
+        return quantize_model(&args.model, quantize_args, args.quiet);
+    }
+
     let mut model_opts = ModelOptions::with_all_ops();
     model_opts.enable_optimization(args.optimize);
     model_opts.prepack_weights(args.prepack_weights);
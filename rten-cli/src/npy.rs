@@ -0,0 +1,154 @@
+//! Reading of tensors from NumPy `.npy` files.
+//!
+//! See <https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html>.
+
+use rten::Output;
+use rten_tensor::Tensor;
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Return the value of `key` in the Python dict literal that forms the
+/// header of a `.npy` file.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+    let value = header[start..].trim_start();
+    let end = if value.starts_with('(') {
+        value.find(')')? + 1
+    } else {
+        value.find([',', '}'])?
+    };
+    Some(value[..end].trim())
+}
+
+/// Parse the shape tuple from a `.npy` header, eg. `(1, 3, 224, 224)`.
+fn parse_shape(shape: &str) -> Result<Vec<usize>, String> {
+    shape
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| format!("invalid shape \"{}\"", shape))?
+        .split(',')
+        .map(|dim| dim.trim())
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| {
+            dim.parse()
+                .map_err(|_| format!("invalid dimension \"{}\"", dim))
+        })
+        .collect()
+}
+
+/// Convert little-endian element data into a tensor.
+fn tensor_from_bytes<T, const N: usize>(
+    shape: &[usize],
+    data: &[u8],
+    from_le_bytes: fn([u8; N]) -> T,
+) -> Result<Tensor<T>, String> {
+    let len: usize = shape.iter().product();
+    if data.len() != len * N {
+        return Err(format!(
+            "expected {} bytes of data but found {}",
+            len * N,
+            data.len()
+        ));
+    }
+    let elements: Vec<T> = data
+        .chunks_exact(N)
+        .map(|chunk| from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    Ok(Tensor::from_data(shape, elements))
+}
+
+/// Parse the contents of a `.npy` file.
+///
+/// Supports C-ordered arrays of `float32`, `int32`, `int8` and `uint8`
+/// elements, which are the types RTen models accept as inputs.
+pub fn parse_npy(bytes: &[u8]) -> Result<Output, String> {
+    let rest = bytes
+        .strip_prefix(MAGIC)
+        .ok_or("not a .npy file".to_string())?;
+    let (header_len, rest) = match rest {
+        [1, _, a, b, rest @ ..] => (u16::from_le_bytes([*a, *b]) as usize, rest),
+        [2 | 3, _, a, b, c, d, rest @ ..] => (u32::from_le_bytes([*a, *b, *c, *d]) as usize, rest),
+        _ => return Err("unsupported .npy version".into()),
+    };
+    if rest.len() < header_len {
+        return Err("truncated .npy header".into());
+    }
+    let (header, data) = rest.split_at(header_len);
+    let header = std::str::from_utf8(header).map_err(|_| "invalid .npy header".to_string())?;
+
+    if header_value(header, "fortran_order") != Some("False") {
+        return Err("Fortran-ordered arrays are not supported".into());
+    }
+    let dtype = header_value(header, "descr")
+        .map(|d| d.trim_matches('\''))
+        .ok_or("missing \"descr\" in .npy header".to_string())?;
+    let shape = header_value(header, "shape")
+        .ok_or("missing \"shape\" in .npy header".to_string())
+        .and_then(parse_shape)?;
+
+    let output = match dtype {
+        "<f4" => tensor_from_bytes(&shape, data, f32::from_le_bytes)?.into(),
+        "<i4" => tensor_from_bytes(&shape, data, i32::from_le_bytes)?.into(),
+        "|i1" => tensor_from_bytes(&shape, data, i8::from_le_bytes)?.into(),
+        "|u1" => tensor_from_bytes(&shape, data, u8::from_le_bytes)?.into(),
+        _ => return Err(format!("unsupported data type \"{}\"", dtype)),
+    };
+    Ok(output)
+}
+
+/// Read a tensor from a `.npy` file.
+pub fn read_npy(path: &str) -> Result<Output, String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    parse_npy(&bytes).map_err(|err| format!("failed to parse {}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use rten::Output;
+    use rten_tensor::Tensor;
+
+    use super::parse_npy;
+
+    fn npy_file(descr: &str, shape: &str, data: &[u8]) -> Vec<u8> {
+        let header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}\n",
+            descr, shape
+        );
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_npy() {
+        let data: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let output = parse_npy(&npy_file("<f4", "(2, 3)", &data)).unwrap();
+        assert_eq!(
+            output,
+            Output::from(Tensor::from([[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0]]))
+        );
+
+        let output = parse_npy(&npy_file("|u1", "(3,)", &[1, 2, 3])).unwrap();
+        assert_eq!(output, Output::from(Tensor::from([1u8, 2, 3])));
+
+        let output = parse_npy(&npy_file("<i4", "()", &7i32.to_le_bytes())).unwrap();
+        assert_eq!(output, Output::from(Tensor::from(7i32)));
+    }
+
+    #[test]
+    fn test_parse_npy_errors() {
+        assert!(parse_npy(b"not numpy").is_err());
+        assert!(parse_npy(&npy_file("<f8", "(1,)", &[0; 8])).is_err());
+        assert!(parse_npy(&npy_file("<f4", "(2,)", &[0; 4])).is_err());
+
+        let mut fortran = npy_file("<f4", "(1,)", &[0; 4]);
+        let pos = fortran.windows(5).position(|w| w == b"False").unwrap();
+        fortran[pos..pos + 5].copy_from_slice(b"True ");
+        assert!(parse_npy(&fortran).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use std::error::Error;
+
+use rten::quantization::{Granularity, QuantizeMode, QuantizeOptions};
+use rten::{InputOrOutput, Model, ModelOptions, NodeId};
+
+use crate::npy::read_npy;
+
+/// Arguments for the `quantize` subcommand.
+pub struct QuantizeArgs {
+    /// Path to write quantized model to.
+    pub output: Option<String>,
+
+    /// Granularity of weight scales.
+    pub granularity: Granularity,
+
+    /// Whether to restrict weights to 7 bits.
+    pub reduce_range: bool,
+
+    /// Whether to quantize `Conv` operators.
+    pub quantize_conv: bool,
+
+    /// Calibration inputs as `(input_name, npy_path)` pairs.
+    ///
+    /// If non-empty, static quantization is used. The Nth file specified for
+    /// each input forms the Nth calibration sample.
+    pub calibration: Vec<(String, String)>,
+}
+
+impl Default for QuantizeArgs {
+    fn default() -> Self {
+        QuantizeArgs {
+            output: None,
+            granularity: Granularity::PerChannel,
+            reduce_range: true,
+            quantize_conv: false,
+            calibration: Vec::new(),
+        }
+    }
+}
+
+/// Parse a calibration input in the form `input_name=path.npy`.
+pub fn parse_calibration_input(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), path.to_string()))
+        }
+        _ => Err(format!(
+            "Invalid calibration input \"{}\". Expected `name=path.npy`",
+            value
+        )),
+    }
+}
+
+/// Values for each model input in one calibration sample.
+type CalibrationSample = Vec<(NodeId, InputOrOutput<'static>)>;
+
+/// Load calibration samples for `model`.
+///
+/// Each input of the model must be given the same number of files.
+fn load_calibration(
+    model: &Model,
+    inputs: &[(String, String)],
+) -> Result<Vec<CalibrationSample>, Box<dyn Error>> {
+    let mut samples: Vec<CalibrationSample> = Vec::new();
+    let mut counts: Vec<(NodeId, usize)> = Vec::new();
+
+    for (name, path) in inputs {
+        let id = model.node_id(name)?;
+        if !model.input_ids().contains(&id) {
+            return Err(format!("\"{}\" is not a model input", name).into());
+        }
+        let count = match counts.iter_mut().find(|(input_id, _)| *input_id == id) {
+            Some((_, count)) => count,
+            None => {
+                counts.push((id, 0));
+                &mut counts.last_mut().unwrap().1
+            }
+        };
+        if *count == samples.len() {
+            samples.push(Vec::new());
+        }
+        samples[*count].push((id, read_npy(path)?.into()));
+        *count += 1;
+    }
+
+    if counts.iter().any(|&(_, count)| count != samples.len()) {
+        return Err("Each model input must have the same number of calibration files".into());
+    }
+
+    Ok(samples)
+}
+
+/// Parse a weight granularity in the form `tensor`, `channel` or `block:N`.
+pub fn parse_granularity(value: &str) -> Result<Granularity, String> {
+    match value {
+        "tensor" => Ok(Granularity::PerTensor),
+        "channel" => Ok(Granularity::PerChannel),
+        _ => {
+            let block_size = value
+                .strip_prefix("block:")
+                .and_then(|size| size.parse().ok())
+                .filter(|&size: &usize| size > 0)
+                .ok_or_else(|| format!("Invalid granularity \"{}\"", value))?;
+            Ok(Granularity::Block(block_size))
+        }
+    }
+}
+
+/// Return the default output path for a quantized model.
+fn default_output_path(model_path: &str) -> String {
+    let stem = model_path.strip_suffix(".rten").unwrap_or(model_path);
+    format!("{}.quant.rten", stem)
+}
+
+/// Quantize the model at `model_path` and save the result.
+pub fn quantize_model(
+    model_path: &str,
+    args: &QuantizeArgs,
+    quiet: bool,
+) -> Result<(), Box<dyn Error>> {
+    // Quantization looks for un-fused `MatMul` and `Conv` operators, so
+    // quantize the un-optimized graph.
+    let model: Model = ModelOptions::with_all_ops()
+        .enable_optimization(false)
+        .load_file(model_path)?;
+
+    let calibration = load_calibration(&model, &args.calibration)?;
+    let mode = if calibration.is_empty() {
+        QuantizeMode::Dynamic
+    } else {
+        QuantizeMode::Static
+    };
+
+    let orig_params = model.total_params();
+    let quantized = QuantizeOptions::default()
+        .mode(mode)
+        .granularity(args.granularity)
+        .reduce_range(args.reduce_range)
+        .quantize_conv(args.quantize_conv)
+        .quantize(model, &calibration)?;
+
+    let output_path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| default_output_path(model_path));
+    quantized.save(&output_path)?;
+
+    if !quiet {
+        let orig_size = std::fs::metadata(model_path)?.len();
+        let quantized_size = std::fs::metadata(&output_path)?.len();
+        println!(
+            "Quantized model with {} params. Saved to {} ({:.1} MB, was {:.1} MB)",
+            orig_params,
+            output_path,
+            quantized_size as f64 / 1e6,
+            orig_size as f64 / 1e6,
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rten::quantization::Granularity;
+
+    use super::{default_output_path, parse_calibration_input, parse_granularity};
+
+    #[test]
+    fn test_parse_granularity() {
+        assert_eq!(parse_granularity("tensor"), Ok(Granularity::PerTensor));
+        assert_eq!(parse_granularity("channel"), Ok(Granularity::PerChannel));
+        assert_eq!(parse_granularity("block:32"), Ok(Granularity::Block(32)));
+        assert!(parse_granularity("block:0").is_err());
+        assert!(parse_granularity("block").is_err());
+        assert!(parse_granularity("row").is_err());
+    }
+
+    #[test]
+    fn test_parse_calibration_input() {
+        assert_eq!(
+            parse_calibration_input("input=data/0.npy"),
+            Ok(("input".to_string(), "data/0.npy".to_string()))
+        );
+        assert!(parse_calibration_input("input").is_err());
+        assert!(parse_calibration_input("=data/0.npy").is_err());
+        assert!(parse_calibration_input("input=").is_err());
+    }
+
+    #[test]
+    fn test_default_output_path() {
+        assert_eq!(default_output_path("model.rten"), "model.quant.rten");
+        assert_eq!(default_output_path("dir/model"), "dir/model.quant.rten");
+    }
+}
@@ -6,7 +6,9 @@ use rten::ops::FloatOperators;
 use rten::{InputOrOutput, Model, NodeId};
 use rten_tensor::prelude::*;
 use rten_tensor::*;
-use rten_text::tokenizer::{EncodeOptions, Encoded, Tokenizer};
+use rten_text::tokenizer::{
+    BatchEncodeOptions, BatchEncoded, Tokenizer, TruncationOptions, TruncationStrategy,
+};
 
 struct Args {
     model: String,
@@ -75,45 +77,33 @@ struct Answer<'a> {
     text: &'a str,
 }
 
-/// Extract the the spans of the context from `query_context` which best
-/// answer the query.
+/// Extract the the spans of the context which best answer the query.
 ///
-/// `query_context` is the tokenized query and context, `model` is a BERT model
-/// finetuned for extractive QA. `n_best` is the number of results to return.
+/// `encoded` is the tokenized query and context, where each row contains
+/// the query and a chunk of the context. `model` is a BERT model finetuned
+/// for extractive QA. `n_best` is the number of results to return for each
+/// row.
 fn extract_nbest_answers<'a>(
-    query_context: Encoded<'a>,
+    encoded: &BatchEncoded,
+    query: &str,
+    context: &'a str,
     model: &Model,
     n_best: usize,
 ) -> Result<Vec<Answer<'a>>, Box<dyn Error>> {
-    let batch = 1;
-    let input_ids: Tensor<i32> = query_context
-        .token_ids()
-        .iter()
-        .map(|tid| *tid as i32)
-        .collect::<Tensor<_>>()
-        .into_shape([1, query_context.token_ids().len()].as_slice());
-    let attention_mask = Tensor::full(&[batch, input_ids.len()], 1i32);
-
     let input_ids_id = model.node_id("input_ids")?;
     let attention_mask_id = model.node_id("attention_mask")?;
     let start_logits_id = model.node_id("start_logits")?;
     let end_logits_id = model.node_id("end_logits")?;
 
     let mut inputs: Vec<(NodeId, InputOrOutput)> = vec![
-        (input_ids_id, input_ids.view().into()),
-        (attention_mask_id, attention_mask.view().into()),
+        (input_ids_id, encoded.input_ids.view().into()),
+        (attention_mask_id, encoded.attention_mask.view().into()),
     ];
 
-    // Generate token type IDs if this model needs them. The original BERT
-    // uses them, DistilBERT for example does not.
-    let type_ids: Tensor<i32>;
+    // Add token type IDs if this model needs them. The original BERT uses
+    // them, DistilBERT for example does not.
     if let Some(type_ids_id) = model.find_node("token_type_ids") {
-        type_ids = query_context
-            .token_type_ids()
-            .map(|tid| tid as i32)
-            .collect::<Tensor<_>>()
-            .into_shape([1, query_context.token_ids().len()].as_slice());
-        inputs.push((type_ids_id, type_ids.view().into()));
+        inputs.push((type_ids_id, encoded.token_type_ids.view().into()));
     }
 
     let [start_logits, end_logits] = model.run_n(inputs, [start_logits_id, end_logits_id], None)?;
@@ -122,24 +112,18 @@ fn extract_nbest_answers<'a>(
     let mut start_logits: NdTensor<f32, 2> = start_logits.try_into()?;
     let mut end_logits: NdTensor<f32, 2> = end_logits.try_into()?;
 
-    // Mask of positions that are part of the context, excluding the final `[SEP]`.
-    let mut context_mask: Vec<bool> = query_context
-        .token_type_ids()
-        .map(|ttid| ttid == 1)
-        .collect();
-    context_mask[start_logits.len() - 1] = false;
-
-    // Set logits for positions outside of context to a large negative value
-    // before applying softmax, so those positions don't affect the values
-    // of in-context positions.
-    for (pos, (start, end)) in start_logits
-        .iter_mut()
-        .zip(end_logits.iter_mut())
-        .enumerate()
-    {
-        if !context_mask[pos] {
-            *start = -10_000.0;
-            *end = -10_000.0;
+    // Set logits for positions outside of the context to a large negative
+    // value before applying softmax, so those positions don't affect the
+    // values of in-context positions. This excludes the query, the final
+    // `[SEP]` and padding.
+    for (row, offsets) in encoded.offsets.iter().enumerate() {
+        let seq_len = offsets.len();
+        for pos in 0..start_logits.size(1) {
+            let in_context = pos < seq_len - 1 && encoded.token_type_ids[[row, pos]] == 1;
+            if !in_context {
+                start_logits[[row, pos]] = -10_000.0;
+                end_logits[[row, pos]] = -10_000.0;
+            }
         }
     }
 
@@ -157,40 +141,46 @@ fn extract_nbest_answers<'a>(
     // [1] https://github.com/huggingface/transformers/blob/df5c5c62ae253055336f5bb0828ca8e3e15ab6bd/src/transformers/pipelines/question_answering.py#L72
     let max_answer_len = 15;
     let min_start = 1; // Ignore [CLS] token at start.
-    let max_end = end_probs.size(1) - 1; // Ignore [SEP] token at end.
-    let mut span_scores: Vec<(usize, usize, f32)> = start_probs
-        .slice((0, min_start..max_end))
-        .iter()
-        .enumerate()
-        .map(|(start_pos, start_score)| {
-            let start_pos = start_pos + min_start;
-            let (relative_end_pos, end_score) = end_probs
-                .slice((0, start_pos..(start_pos + max_answer_len).min(max_end)))
-                .iter()
-                .enumerate()
-                .max_by(|(_pos_a, score_a), (_pos_b, score_b)| score_a.total_cmp(score_b))
-                .unwrap();
-            let end_pos = relative_end_pos + start_pos;
-
-            let span_score = start_score * end_score;
-
-            (start_pos, end_pos, span_score)
-        })
-        .collect();
-    span_scores.sort_by(|(_, _, score_a), (_, _, score_b)| score_a.total_cmp(score_b).reverse());
-
-    let n_best_answers: Vec<_> = span_scores
-        .into_iter()
-        .take(n_best)
-        .map(|(start_pos, end_pos, score)| {
-            let text = query_context
-                .text_for_token_range(start_pos..end_pos + 1)
-                .expect("failed to get answer text");
-            Answer { score, text }
-        })
-        .collect();
-
-    Ok(n_best_answers)
+    let mut answers = Vec::new();
+    for (row, offsets) in encoded.offsets.iter().enumerate() {
+        let max_end = offsets.len() - 1; // Ignore [SEP] token at end.
+        let mut span_scores: Vec<(usize, usize, f32)> = start_probs
+            .slice((row, min_start..max_end))
+            .iter()
+            .enumerate()
+            .map(|(start_pos, start_score)| {
+                let start_pos = start_pos + min_start;
+                let (relative_end_pos, end_score) = end_probs
+                    .slice((row, start_pos..(start_pos + max_answer_len).min(max_end)))
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_pos_a, score_a), (_pos_b, score_b)| score_a.total_cmp(score_b))
+                    .unwrap();
+                let end_pos = relative_end_pos + start_pos;
+
+                let span_score = start_score * end_score;
+
+                (start_pos, end_pos, span_score)
+            })
+            .collect();
+        span_scores
+            .sort_by(|(_, _, score_a), (_, _, score_b)| score_a.total_cmp(score_b).reverse());
+
+        // Offsets of context tokens are relative to the start of the query.
+        answers.extend(
+            span_scores
+                .into_iter()
+                .take(n_best)
+                .map(|(start_pos, end_pos, score)| {
+                    let start = offsets[start_pos] - query.len();
+                    let end = offsets[end_pos + 1] - query.len();
+                    let text = context.get(start..end).expect("failed to get answer text");
+                    Answer { score, text }
+                }),
+        );
+    }
+
+    Ok(answers)
 }
 
 /// This example finds passages in a document that best answer a given query,
@@ -229,26 +219,29 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // Tokenize the query and context, breaking the context up into chunks to
     // fit the model's context length.
-    let enc_opts = EncodeOptions {
-        // Max chunk length chosen as 384 to match what is used by the original
-        // BERT training scripts + the Hugging Face QA pipeline.
-        max_chunk_len: Some(384),
-
-        // Overlap controls how many tokens successive chunks overlap by.
-        // This can avoid the model failing to find answers if the answer
-        // crosses a chunk boundary.
-        overlap: 0,
-        ..Default::default()
-    };
-    let encoded =
-        tokenizer.encode_chunks((args.query.as_str(), context.as_str()).into(), enc_opts)?;
-
-    let mut answers = Vec::new();
-    for chunk in encoded {
-        let nbest_per_chunk = args.n_best;
-        let chunk_answers = extract_nbest_answers(chunk, &model, nbest_per_chunk)?;
-        answers.extend(chunk_answers.into_iter());
-    }
+    let encoded = tokenizer.encode_batch(
+        [(args.query.as_str(), context.as_str())],
+        BatchEncodeOptions {
+            truncation: Some(TruncationOptions {
+                // Max chunk length chosen as 384 to match what is used by the
+                // original BERT training scripts + the Hugging Face QA
+                // pipeline.
+                max_length: 384,
+
+                // Split the context into chunks and repeat the query in each.
+                strategy: TruncationStrategy::OnlySecond,
+                return_overflowing: true,
+
+                // Stride controls how many tokens successive chunks overlap
+                // by. This can avoid the model failing to find answers if the
+                // answer crosses a chunk boundary.
+                stride: 0,
+            }),
+            ..Default::default()
+        },
+    )?;
+
+    let mut answers = extract_nbest_answers(&encoded, &args.query, &context, &model, args.n_best)?;
     answers.sort_by(|ans_a, ans_b| ans_a.score.total_cmp(&ans_b.score).reverse());
 
     println!("Question: {}", args.query);
@@ -6,7 +6,8 @@ use rten_imageio::read_image;
 use rten_imageproc::normalize_image;
 use rten_tensor::prelude::*;
 use rten_tensor::NdTensor;
-use rten_text::Tokenizer;
+use rten_text::tokenizer::{BatchEncodeOptions, BatchEncoded, PaddingOptions};
+use rten_text::{TokenId, Tokenizer};
 
 struct Args {
     model: String,
@@ -157,47 +158,37 @@ fn main() -> Result<(), Box<dyn Error>> {
         pixel_values.slice_mut(i).copy_from(&image);
     }
 
-    let start_of_text = tokenizer.get_token_id("<|startoftext|>")?;
+    // The tokenizer's post-processor adds `<|startoftext|>` and
+    // `<|endoftext|>` tokens around each caption. Shorter captions are padded
+    // using `<|endoftext|>`.
     let end_of_text = tokenizer.get_token_id("<|endoftext|>")?;
+    let BatchEncoded {
+        input_ids,
+        attention_mask,
+        ..
+    } = tokenizer.encode_batch(
+        args.captions.iter().map(|caption| caption.as_str()),
+        BatchEncodeOptions {
+            padding: PaddingOptions {
+                pad_id: end_of_text,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )?;
 
-    let mut encoded_captions = Vec::new();
-    for caption in &args.captions {
-        let mut tokens = tokenizer.encode(caption.as_str(), None)?.into_token_ids();
-        tokens.insert(0, start_of_text);
-        tokens.push(end_of_text);
-
-        if args.debug_tokens {
+    if args.debug_tokens {
+        for (tokens, mask) in input_ids.axis_iter(0).zip(attention_mask.axis_iter(0)) {
+            let tokens: Vec<TokenId> = tokens
+                .iter()
+                .zip(mask.iter())
+                .filter(|(_id, mask)| **mask != 0)
+                .map(|(id, _mask)| *id as TokenId)
+                .collect();
             let decoded = tokenizer.decode(&tokens).unwrap();
             println!("tokens {:?} decoded \"{}\"", tokens, decoded);
         }
-
-        encoded_captions.push(tokens);
     }
-    let max_seq_len = encoded_captions
-        .iter()
-        .map(|tokens| tokens.len())
-        .max()
-        .unwrap_or(0);
-
-    let input_ids = NdTensor::from_fn(
-        [encoded_captions.len(), max_seq_len],
-        |[caption_idx, pos]| {
-            encoded_captions[caption_idx]
-                .get(pos)
-                .copied()
-                .unwrap_or(end_of_text) as i32
-        },
-    );
-    let attention_mask = NdTensor::from_fn(
-        [encoded_captions.len(), max_seq_len],
-        |[caption_idx, pos]| {
-            if encoded_captions[caption_idx].len() > pos {
-                1i32
-            } else {
-                0i32
-            }
-        },
-    );
 
     let input_ids_id = model.node_id("input_ids")?;
     let pixel_values_id = model.node_id("pixel_values")?;
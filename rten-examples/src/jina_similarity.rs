@@ -4,8 +4,8 @@ use std::error::Error;
 use rten::ops::concat;
 use rten::{FloatOperators, InputOrOutput, Model, NodeId, Operators, TensorPool};
 use rten_tensor::prelude::*;
-use rten_tensor::{NdTensor, Tensor};
-use rten_text::tokenizer::{EncodeOptions, Tokenizer};
+use rten_tensor::NdTensor;
+use rten_text::tokenizer::{BatchEncodeOptions, BatchEncoded, Tokenizer, TruncationOptions};
 
 struct Args {
     model: String,
@@ -78,44 +78,24 @@ fn embed_sentence_batch(
     model: &Model,
     max_seq_len: usize,
 ) -> Result<NdTensor<f32, 2>, Box<dyn Error>> {
-    // Tokenize input sequences
-    let mut encoded = Vec::new();
-    for &sentence in sentences {
-        encoded.push(tokenizer.encode(
-            sentence,
-            Some(EncodeOptions {
-                max_chunk_len: Some(max_seq_len),
+    // Tokenize input sequences and generate padded (batch, token_id) inputs
+    // and an attention mask, set to 1 for non-padding tokens and 0 for
+    // padding tokens.
+    let BatchEncoded {
+        input_ids,
+        attention_mask,
+        token_type_ids,
+        ..
+    } = tokenizer.encode_batch(
+        sentences.iter().copied(),
+        BatchEncodeOptions {
+            truncation: Some(TruncationOptions {
+                max_length: max_seq_len,
                 ..Default::default()
             }),
-        )?);
-    }
-
-    // Generate (batch, token_id) input.
-    let max_sequence_len = encoded
-        .iter()
-        .map(|enc| enc.token_ids().len())
-        .max()
-        .unwrap_or(0);
-    let batch = sentences.len();
-    let mut input_ids = Tensor::zeros(&[batch, max_sequence_len]);
-    for (i, encoded) in encoded.iter().enumerate() {
-        let token_ids = encoded.token_ids();
-        for (tid, input_id) in token_ids
-            .iter()
-            .zip(input_ids.slice_mut((i, ..token_ids.len())).iter_mut())
-        {
-            *input_id = *tid as i32;
-        }
-    }
-
-    // Generate attention mask, set to 1 for non-padding tokens and 0 for
-    // padding tokens.
-    let mut attention_mask = NdTensor::zeros([batch, max_sequence_len]);
-    for (i, encoded) in encoded.iter().enumerate() {
-        attention_mask
-            .slice_mut((i, ..encoded.token_ids().len()))
-            .fill(1i32);
-    }
+            ..Default::default()
+        },
+    )?;
 
     let input_ids_id = model.node_id("input_ids")?;
     let attention_mask_id = model.node_id("attention_mask")?;
@@ -125,12 +105,10 @@ fn embed_sentence_batch(
         (attention_mask_id, attention_mask.view().into()),
     ];
 
-    // Generate token type IDs if this model needs them. These are all zeros
+    // Add token type IDs if this model needs them. These are all zeros
     // since each item has just one sequence.
-    let type_ids: NdTensor<i32, 2>;
     if let Some(type_ids_id) = model.find_node("token_type_ids") {
-        type_ids = NdTensor::zeros([batch, max_sequence_len]);
-        inputs.push((type_ids_id, type_ids.view().into()));
+        inputs.push((type_ids_id, token_type_ids.view().into()));
     }
 
     let output_id = model.node_id("last_hidden_state")?;
@@ -143,11 +121,11 @@ fn embed_sentence_batch(
     // since they can have different lengths.
     let mean_pooled: Vec<_> = last_hidden_state
         .axis_iter(0)
-        .zip(encoded.iter())
-        .map(|(item, input)| {
+        .zip(attention_mask.axis_iter(0))
+        .map(|(item, mask)| {
             // Take the mean of the non-padding elements along the sequence
             // dimension.
-            let seq_len = input.token_ids().len();
+            let seq_len = mask.iter().filter(|x| **x != 0).count();
             item.slice(..seq_len)
                 .reduce_mean(Some(&[0]), false /* keep_dims */)
                 .unwrap()
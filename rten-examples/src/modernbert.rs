@@ -4,7 +4,8 @@ use std::error::Error;
 use rten::{Model, Operators};
 use rten_tensor::prelude::*;
 use rten_tensor::NdTensor;
-use rten_text::{TokenId, Tokenizer, TokenizerError};
+use rten_text::tokenizer::BatchEncoded;
+use rten_text::{TokenId, Tokenizer};
 
 struct Args {
     model: String,
@@ -98,45 +99,27 @@ fn main() -> Result<(), Box<dyn Error>> {
     let sep_token = tokenizer.get_token_id("[SEP]")?;
     let mask_token = tokenizer.get_token_id("[MASK]")?;
 
-    // Encode input, replacing occurrences of "[MASK]" by the corresponding
-    // special token ID.
-
-    let tokenize_piece = |piece| -> Result<Vec<TokenId>, TokenizerError> {
-        let ids = tokenizer
-            .encode(piece, None)?
-            .into_token_ids()
-            .into_iter()
-            // `Tokenizer::encode` adds [CLS] and [SEP] tokens. Remove these
-            // as we only want to insert them around the whole input ID sequence.
-            .filter(|id| ![cls_token, sep_token].contains(id))
-            .collect();
-        Ok(ids)
-    };
-
-    let mut input_ids = Vec::from([cls_token]);
-    let mut mask_indices = Vec::new();
-    let mut remainder = args.input.as_str();
-    while let Some(mask_pos) = remainder.find("[MASK]") {
-        // `trim_end` replicates the behavior of the `lstrip` attribute for
-        // the `[MASK]` special token in tokenizer.json.
-        let piece_str = remainder[..mask_pos].trim_end();
-        input_ids.extend(tokenize_piece(piece_str)?);
-        mask_indices.push(input_ids.len());
-        input_ids.push(mask_token);
-        remainder = &remainder[mask_pos + "[MASK]".len()..];
-    }
-    if !remainder.is_empty() {
-        input_ids.extend(tokenize_piece(remainder)?);
-    }
-    input_ids.push(sep_token);
+    // Encode input. Occurrences of "[MASK]" are replaced by the corresponding
+    // special token ID, and [CLS] and [SEP] tokens are added around the
+    // input.
+    let BatchEncoded {
+        input_ids,
+        attention_mask,
+        token_type_ids,
+        ..
+    } = tokenizer.encode_batch([args.input.as_str()], Default::default())?;
 
     if args.show_token_ids {
-        println!("Input IDs: {:?}", input_ids);
+        println!("Input IDs: {:?}", input_ids.to_vec());
     }
 
-    // Prepare model inputs
-    let input_ids = NdTensor::from_data([1, input_ids.len()], input_ids).map(|id| *id as i32);
-    let attention_mask = NdTensor::full(input_ids.shape(), 1);
+    let mask_indices: Vec<usize> = input_ids
+        .slice(0)
+        .iter()
+        .enumerate()
+        .filter(|(_pos, id)| **id as TokenId == mask_token)
+        .map(|(pos, _id)| pos)
+        .collect();
 
     let input_ids_id = model.node_id("input_ids")?;
     let attention_mask_id = model.node_id("attention_mask")?;
@@ -151,7 +134,6 @@ fn main() -> Result<(), Box<dyn Error>> {
     // works with older BERT models that do. If using such a model, such as
     // "bert-base-uncased", provide this input.
     if let Ok(token_type_ids_id) = model.node_id("token_type_ids") {
-        let token_type_ids = NdTensor::full(input_ids.shape(), 0);
         model_inputs.push((token_type_ids_id, token_type_ids.into()));
     }
 
@@ -0,0 +1,888 @@
+//! Generate token sequences for a batch of prompts concurrently.
+
+use std::collections::VecDeque;
+
+use rten::{Input, InputOrOutput, NodeId, Output, RunOptions};
+use rten_tensor::prelude::*;
+use rten_tensor::{NdTensor, Tensor};
+
+use crate::filter::LogitsFilter;
+use crate::generator::{
+    find_kv_caches, wrap_error, GeneratorConfig, GeneratorError, KvCache, TokenId,
+};
+use crate::kv_quant::KvCacheFormat;
+use crate::model::Model;
+use crate::sampler::{ArgMaxSampler, Sampler};
+
+/// State of one sequence in a [`BatchGenerator`].
+struct Sequence {
+    /// Prompt and generated tokens, excluding padding.
+    tokens: Vec<TokenId>,
+
+    /// Number of tokens generated so far.
+    n_generated: usize,
+
+    /// Attention mask for all positions that have been passed to the model
+    /// so far. This is zero for padding positions.
+    attention_mask: Vec<i32>,
+
+    /// Token IDs to pass to the model on the next run. `None` entries are
+    /// padding positions, which are replaced with the pad token when the
+    /// model is run and masked out.
+    input_ids: Vec<Option<TokenId>>,
+
+    /// True if a stop condition has been reached for this sequence.
+    done: bool,
+}
+
+/// Output items from a [`BatchGenerator`].
+///
+/// Each item is a tuple of `(sequence_index, token_id)`, where
+/// `sequence_index` is the index of the prompt in the batch.
+pub type BatchGeneratorItem = Result<(usize, TokenId), GeneratorError>;
+
+/// Generates token ID sequences for a batch of prompts using a transformer
+/// decoder model.
+///
+/// This is similar to [`Generator`](crate::Generator), but decodes several
+/// prompts at once by running the model with a batch size equal to the
+/// number of prompts. This is more efficient than running a separate
+/// generator for each prompt.
+///
+/// Prompts can have different lengths. Shorter prompts are padded on the
+/// left, and the `attention_mask` and `position_ids` model inputs are set so
+/// that the padding is ignored. The model must have an `attention_mask`
+/// input if the prompts have different lengths.
+///
+/// The generator is an iterator which yields `(sequence_index, token_id)`
+/// items. Each run of the model produces one token for each sequence that has
+/// not yet finished. A sequence finishes when it generates one of the tokens
+/// set with [`with_stop_tokens`](Self::with_stop_tokens) or reaches the limit
+/// set with [`with_max_tokens`](Self::with_max_tokens). Stop tokens are not
+/// included in the output. The iterator ends when all sequences have
+/// finished.
+pub struct BatchGenerator<'a> {
+    model: &'a dyn Model,
+
+    run_options: Option<RunOptions>,
+
+    /// Additional constant model inputs (eg. encoder outputs) passed to the
+    /// model at each step.
+    constant_inputs: Vec<(NodeId, InputOrOutput<'a>)>,
+
+    /// Additional model inputs computed using constant propagation. This is
+    /// `None` if the cache is out of date.
+    constant_prop_inputs: Option<Vec<(NodeId, Output)>>,
+
+    input_ids_input: NodeId,
+    logits_output: NodeId,
+    attention_mask_input: Option<NodeId>,
+    position_ids_input: Option<NodeId>,
+    cache_position_input: Option<NodeId>,
+    use_cache_input: Option<NodeId>,
+
+    /// Filter used to modify logits before sampling.
+    logits_filter: Option<Box<dyn LogitsFilter + 'a>>,
+
+    /// Sampler used to get the next token ID from the output logits.
+    sampler: Box<dyn Sampler + 'a>,
+
+    /// Token used to pad prompts and finished sequences.
+    pad_token: TokenId,
+
+    /// Tokens which end a sequence.
+    stop_tokens: Vec<TokenId>,
+
+    /// Maximum number of tokens to generate for each sequence.
+    max_tokens: Option<usize>,
+
+    sequences: Vec<Sequence>,
+
+    /// Whether prompts have been set since the logits filter was last
+    /// notified of the start of the sequences.
+    new_prompts: bool,
+
+    /// Position ID associated with the first token in each sequence's
+    /// `input_ids`.
+    input_offset: usize,
+
+    /// Tokens generated in the most recent run that have not yet been
+    /// returned from the iterator.
+    pending: VecDeque<(usize, TokenId)>,
+
+    /// Self-attention key-value cache.
+    kv_cache: Vec<KvCache>,
+
+    /// Cross-attention key-value cache.
+    encoder_kv_cache: Vec<KvCache>,
+}
+
+impl<'a> BatchGenerator<'a> {
+    /// Create a batch generator using the default names for model inputs and
+    /// outputs.
+    ///
+    /// See [`Generator::from_model`](crate::Generator::from_model) for
+    /// details of the expected model inputs and outputs.
+    pub fn from_model(model: &'a dyn Model) -> Result<BatchGenerator<'a>, GeneratorError> {
+        Self::from_model_config(model, GeneratorConfig::default())
+    }
+
+    /// Create a batch generator with custom names for model inputs.
+    pub fn from_model_config(
+        model: &'a dyn Model,
+        config: GeneratorConfig,
+    ) -> Result<BatchGenerator<'a>, GeneratorError> {
+        let model_inputs = &config.model_inputs;
+
+        let input_ids_input =
+            model
+                .find_node(model_inputs.input_ids)
+                .ok_or(GeneratorError::InputNotFound(
+                    model_inputs.input_ids.to_string(),
+                ))?;
+
+        let logits_output =
+            model
+                .find_node(model_inputs.logits)
+                .ok_or(GeneratorError::OutputNotFound(
+                    model_inputs.logits.to_string(),
+                ))?;
+
+        // Caches are re-allocated with the correct batch size when the
+        // prompts are set.
+        let (kv_cache, encoder_kv_cache) = find_kv_caches(model, model_inputs, 0)?;
+
+        Ok(BatchGenerator {
+            model,
+            run_options: None,
+            constant_inputs: Vec::new(),
+            constant_prop_inputs: Some(Vec::new()),
+            input_ids_input,
+            logits_output,
+            attention_mask_input: model.find_node(model_inputs.attention_mask),
+            position_ids_input: model.find_node(model_inputs.position_ids),
+            cache_position_input: model.find_node(model_inputs.cache_position),
+            use_cache_input: model.find_node(model_inputs.use_cache_flag),
+            logits_filter: None,
+            sampler: Box::new(ArgMaxSampler {}),
+            pad_token: 0,
+            stop_tokens: Vec::new(),
+            max_tokens: None,
+            sequences: Vec::new(),
+            new_prompts: false,
+            input_offset: 0,
+            pending: VecDeque::new(),
+            kv_cache,
+            encoder_kv_cache,
+        })
+    }
+
+    /// Set the prompts for the sequences in the batch.
+    ///
+    /// The batch size is equal to the number of prompts. Each prompt must
+    /// contain at least one token.
+    ///
+    /// Returns an error if the prompts have different lengths and the model
+    /// does not have an attention mask input, as the padding could not be
+    /// masked out.
+    pub fn with_prompts<P: AsRef<[TokenId]>>(
+        mut self,
+        prompts: &[P],
+    ) -> Result<Self, GeneratorError> {
+        let max_len = prompts.iter().map(|p| p.as_ref().len()).max().unwrap_or(0);
+        let min_len = prompts.iter().map(|p| p.as_ref().len()).min().unwrap_or(0);
+
+        if min_len != max_len && self.attention_mask_input.is_none() {
+            return Err(GeneratorError::InvalidConfig(
+                "prompts must have the same length if the model has no attention mask input"
+                    .to_string(),
+            ));
+        }
+
+        self.sequences = prompts
+            .iter()
+            .map(|prompt| {
+                let prompt = prompt.as_ref();
+                let pad_len = max_len - prompt.len();
+                Sequence {
+                    tokens: prompt.to_vec(),
+                    n_generated: 0,
+                    attention_mask: Vec::new(),
+                    input_ids: std::iter::repeat_n(None, pad_len)
+                        .chain(prompt.iter().copied().map(Some))
+                        .collect(),
+                    done: false,
+                }
+            })
+            .collect();
+        self.new_prompts = true;
+        self.input_offset = 0;
+        self.pending.clear();
+
+        let batch_size = self.sequences.len();
+        for entry in self.kv_cache.iter_mut() {
+            entry.reset(batch_size);
+        }
+        for entry in self.encoder_kv_cache.iter_mut() {
+            entry.reset(batch_size);
+        }
+
+        Ok(self)
+    }
+
+    /// Set the token ID used to pad prompts to the same length.
+    ///
+    /// This is also used as the input for sequences which have finished
+    /// while others in the batch are still being generated. Padding positions
+    /// are masked out, so the choice of token usually does not affect the
+    /// output. The default is 0.
+    pub fn with_pad_token(mut self, pad_token: TokenId) -> Self {
+        self.pad_token = pad_token;
+        self
+    }
+
+    /// Set the tokens which end a sequence, such as end-of-text tokens.
+    pub fn with_stop_tokens(mut self, stop_tokens: &[TokenId]) -> Self {
+        self.stop_tokens = stop_tokens.to_vec();
+        self
+    }
+
+    /// Set the maximum number of tokens to generate for each sequence.
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Add a constant input which is provided to the model at each iteration.
+    ///
+    /// The input must have a batch size matching the number of prompts, if
+    /// it has a batch dimension.
+    pub fn with_constant_input(mut self, input_id: NodeId, value: Input<'a>) -> Self {
+        self.constant_prop_inputs = None;
+        self.constant_inputs.push((input_id, value.into()));
+        self
+    }
+
+    /// Set the filter used to process model output logits before passing them
+    /// to the sampler to select a token ID.
+    ///
+    /// The filter is applied separately to each sequence in the batch.
+    pub fn with_logits_filter<F: LogitsFilter + 'a>(mut self, filter: F) -> Self {
+        self.logits_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Set the sampler used to sample the next token ID from the output logits.
+    pub fn with_sampler<S: Sampler + 'a>(mut self, sampler: S) -> Self {
+        self.sampler = Box::new(sampler);
+        self
+    }
+
+    /// Set execution options used when running model inference.
+    pub fn with_run_options(mut self, opts: Option<RunOptions>) -> Self {
+        self.run_options = opts;
+        self
+    }
+
+    /// Set the format used to store the self-attention KV cache between runs.
+    ///
+    /// See [`Generator::with_kv_cache_format`](crate::Generator::with_kv_cache_format).
+    pub fn with_kv_cache_format(mut self, format: KvCacheFormat) -> Self {
+        for entry in self.kv_cache.iter_mut() {
+            entry.set_format(format);
+        }
+        self
+    }
+
+    /// Return the number of sequences in the batch.
+    pub fn batch_size(&self) -> usize {
+        self.sequences.len()
+    }
+
+    /// Return the prompt and generated tokens for the sequence at `index`.
+    ///
+    /// Padding tokens are not included.
+    pub fn tokens(&self, index: usize) -> &[TokenId] {
+        &self.sequences[index].tokens
+    }
+
+    /// Return the number of positions in the key-value cache which are used
+    /// by the sequence at `index`.
+    ///
+    /// This excludes padding positions.
+    pub fn sequence_len(&self, index: usize) -> usize {
+        self.sequences[index]
+            .attention_mask
+            .iter()
+            .filter(|m| **m != 0)
+            .count()
+    }
+
+    /// Return true if the sequence at `index` has reached a stop condition.
+    pub fn is_finished(&self, index: usize) -> bool {
+        self.sequences[index].done
+    }
+
+    /// Run the model and generate the next token for each unfinished
+    /// sequence.
+    fn generate_next_tokens(&mut self) -> Result<(), GeneratorError> {
+        let batch_size = self.sequences.len();
+        let input_len = self.sequences[0].input_ids.len();
+
+        if let Some(index) = self.sequences.iter().position(|seq| seq.tokens.is_empty()) {
+            return Err(wrap_error(
+                format!("prompt for sequence {} is empty", index),
+                "invalid prompt",
+            ));
+        }
+
+        if self.new_prompts {
+            if let Some(filter) = &self.logits_filter {
+                let prompts: Vec<&[TokenId]> = self
+                    .sequences
+                    .iter()
+                    .map(|seq| seq.tokens.as_slice())
+                    .collect();
+                filter.start_sequences(&prompts);
+            }
+            self.new_prompts = false;
+        }
+
+        let input_ids = NdTensor::from_fn([batch_size, input_len], |[b, i]| {
+            self.sequences[b].input_ids[i].unwrap_or(self.pad_token) as i32
+        });
+
+        // Update attention masks. When there is no KV cache, the full
+        // sequence is passed to the model on each run.
+        for seq in self.sequences.iter_mut() {
+            if self.kv_cache.is_empty() {
+                seq.attention_mask.clear();
+            }
+            seq.attention_mask
+                .extend(seq.input_ids.iter().map(|id| id.is_some() as i32));
+        }
+
+        let input_positions = self.input_offset..self.input_offset + input_len;
+        let mut model_inputs: Vec<(NodeId, InputOrOutput)> =
+            vec![(self.input_ids_input, input_ids.view().into())];
+
+        let mut varying_inputs: Vec<(NodeId, Tensor<i32>)> = Vec::new();
+        if let Some(attention_mask_input) = self.attention_mask_input {
+            let attention_mask = NdTensor::from_fn([batch_size, input_positions.end], |[b, i]| {
+                self.sequences[b].attention_mask[i]
+            });
+            varying_inputs.push((attention_mask_input, attention_mask.into()));
+        }
+
+        if let Some(position_ids_input) = self.position_ids_input {
+            // Position IDs count only the non-padding positions. Padding
+            // positions are assigned a dummy value, following the convention
+            // of Hugging Face Transformers.
+            let mut position_ids = NdTensor::zeros([batch_size, input_len]);
+            for (b, seq) in self.sequences.iter().enumerate() {
+                let mut pos = seq.attention_mask[..input_positions.start]
+                    .iter()
+                    .filter(|m| **m != 0)
+                    .count() as i32;
+                for (i, &mask) in seq.attention_mask[input_positions.clone()]
+                    .iter()
+                    .enumerate()
+                {
+                    if mask != 0 {
+                        position_ids[[b, i]] = pos;
+                        pos += 1;
+                    } else {
+                        position_ids[[b, i]] = 1;
+                    }
+                }
+            }
+            varying_inputs.push((position_ids_input, position_ids.into()));
+        }
+
+        if let Some(cache_position_input) = self.cache_position_input {
+            let cache_position =
+                NdTensor::from_fn([input_len], |[pos]| (input_positions.start + pos) as i32);
+            varying_inputs.push((cache_position_input, cache_position.into()));
+        }
+
+        if let Some(use_cache_input) = self.use_cache_input {
+            let use_cache = Tensor::from(if input_positions.start == 0 { 0i32 } else { 1 });
+            varying_inputs.push((use_cache_input, use_cache));
+        }
+
+        // Propagate constants on the first run.
+        if self.constant_prop_inputs.is_none() {
+            let inputs = self
+                .model
+                .partial_run(
+                    self.constant_inputs.clone(),
+                    &[self.logits_output],
+                    self.run_options.clone(),
+                )
+                .map_err(|err| {
+                    wrap_error(
+                        err,
+                        "failed to partially evaluate model with constant inputs",
+                    )
+                })?;
+            self.constant_prop_inputs = Some(inputs);
+        }
+
+        if let Some(constants) = self.constant_prop_inputs.as_ref() {
+            model_inputs.extend(
+                constants
+                    .iter()
+                    .map(|(node_id, output)| (*node_id, output.as_input().into())),
+            );
+        }
+
+        model_inputs.extend(
+            varying_inputs
+                .iter()
+                .map(|(node_id, value)| (*node_id, value.view().into())),
+        );
+
+        for entry in self.kv_cache.iter_mut() {
+            entry.take_input(&mut model_inputs);
+        }
+        for entry in self.encoder_kv_cache.iter() {
+            entry.view_input(&mut model_inputs);
+        }
+
+        let model_outputs: Vec<NodeId> = [self.logits_output]
+            .into_iter()
+            .chain(self.kv_cache.iter().map(|entry| entry.output_id))
+            .chain(self.encoder_kv_cache.iter().map(|entry| entry.output_id))
+            .collect();
+
+        let mut outputs = self
+            .model
+            .run(model_inputs, &model_outputs, self.run_options.clone())
+            .map_err(|e| wrap_error(e, "failed to run model"))?;
+
+        let logits: NdTensor<f32, 3> = outputs
+            .remove(0)
+            .try_into()
+            .map_err(|e| wrap_error(e, "failed to extract logits from model outputs"))?;
+        if logits.size(0) != batch_size {
+            return Err(GeneratorError::ShapeMismatch(format!(
+                "logits batch size {} does not match number of prompts {}",
+                logits.size(0),
+                batch_size
+            )));
+        }
+
+        // Sample the next token for each unfinished sequence.
+        let next_ids: Vec<Option<TokenId>> = self
+            .sequences
+            .iter()
+            .enumerate()
+            .map(|(b, seq)| {
+                if seq.done {
+                    return None;
+                }
+                let last_logits = logits.slice((b, -1));
+                let filtered_logits = self
+                    .logits_filter
+                    .as_ref()
+                    .and_then(|f| f.filter(last_logits, &seq.tokens))
+                    .map(|l| l.into_cow())
+                    .unwrap_or(last_logits.as_cow());
+                Some(self.sampler.sample(filtered_logits.view()))
+            })
+            .collect();
+
+        for cache_entry in self.kv_cache.iter_mut() {
+            cache_entry.update(outputs.remove(0))?;
+        }
+        for cache_entry in self.encoder_kv_cache.iter_mut() {
+            cache_entry.update_encoder(outputs.remove(0))?;
+        }
+
+        // Update per-sequence state and inputs for the next iteration.
+        let has_kv_cache = !self.kv_cache.is_empty();
+        for (b, (seq, next_id)) in self.sequences.iter_mut().zip(next_ids).enumerate() {
+            let next_input = match next_id {
+                Some(id) if self.stop_tokens.contains(&id) => {
+                    seq.done = true;
+                    None
+                }
+                Some(id) => {
+                    seq.tokens.push(id);
+                    seq.n_generated += 1;
+                    if self.max_tokens.is_some_and(|max| seq.n_generated >= max) {
+                        seq.done = true;
+                    }
+                    self.pending.push_back((b, id));
+                    Some(id)
+                }
+                None => None,
+            };
+
+            if has_kv_cache {
+                seq.input_ids = vec![next_input];
+            } else {
+                seq.input_ids.push(next_input);
+            }
+        }
+        if has_kv_cache {
+            self.input_offset += input_len;
+        }
+
+        // Let the filter free state for finished sequences.
+        if let Some(filter) = &self.logits_filter {
+            let sequences: Vec<&[TokenId]> = self
+                .sequences
+                .iter()
+                .filter(|seq| !seq.done)
+                .map(|seq| seq.tokens.as_slice())
+                .collect();
+            filter.retain_sequences(&sequences);
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for BatchGenerator<'_> {
+    type Item = BatchGeneratorItem;
+
+    /// Return the next generated token, running the model if there are no
+    /// pending tokens from the previous run.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(Ok(item));
+            }
+            if self.sequences.iter().all(|seq| seq.done) {
+                return None;
+            }
+            if let Err(err) = self.generate_next_tokens() {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::error::Error;
+
+    use rten::{Dimension, InputOrOutput, NodeId, Output, RunOptions};
+    use rten_tensor::prelude::*;
+    use rten_tensor::{NdTensor, NdTensorView};
+
+    use super::BatchGenerator;
+    use crate::generator::{GeneratorConfig, GeneratorError, ModelInputsConfig, TokenId};
+    use crate::kv_quant::KvCacheFormat;
+    use crate::model::{Model, NodeInfo};
+
+    /// Fake decoder with a KV cache which predicts `token + 1` as the next
+    /// token after `token`.
+    ///
+    /// The KV cache contains the position ID of each token, so tests can
+    /// check that the cache was passed back to the model correctly.
+    struct FakeBatchModel {
+        nodes: Vec<NodeInfo>,
+        input_ids: Vec<NodeId>,
+        n_vocab: usize,
+        use_kv_cache: bool,
+
+        // Inference inputs for each step
+        inputs: RefCell<Vec<HashMap<String, Output>>>,
+    }
+
+    impl FakeBatchModel {
+        fn new(n_vocab: usize, use_kv_cache: bool) -> FakeBatchModel {
+            let dims = [
+                Dimension::Symbolic("batch".to_string()),
+                Dimension::Fixed(1),
+                Dimension::Symbolic("seq".to_string()),
+                Dimension::Fixed(1),
+            ];
+            let mut inputs = vec![
+                NodeInfo::from_name_shape("input_ids", &[]),
+                NodeInfo::from_name_shape("attention_mask", &[]),
+                NodeInfo::from_name_shape("position_ids", &[]),
+            ];
+            if use_kv_cache {
+                inputs.push(NodeInfo::from_name_shape("past_key_values.0.key", &dims));
+            }
+            let n_inputs = inputs.len();
+            let mut nodes = inputs;
+            nodes.push(NodeInfo::from_name_shape("logits", &[]));
+            nodes.push(NodeInfo::from_name_shape("present.0.key", &dims));
+
+            FakeBatchModel {
+                nodes,
+                input_ids: (0..n_inputs as u32).map(NodeId::from_u32).collect(),
+                n_vocab,
+                use_kv_cache,
+                inputs: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn get_input(&self, step: usize, name: &str) -> Option<NdTensor<i32, 2>> {
+            let inputs = self.inputs.borrow();
+            let input = inputs.get(step)?.get(name)?.clone();
+            input.try_into().ok()
+        }
+    }
+
+    impl Model for FakeBatchModel {
+        fn find_node(&self, name: &str) -> Option<NodeId> {
+            self.nodes
+                .iter()
+                .position(|info| info.name() == name)
+                .map(|pos| NodeId::from_u32(pos as u32))
+        }
+
+        fn node_info(&self, id: NodeId) -> Option<NodeInfo> {
+            self.nodes.get(id.as_usize()).cloned()
+        }
+
+        fn input_ids(&self) -> &[NodeId] {
+            &self.input_ids
+        }
+
+        fn run(
+            &self,
+            inputs: Vec<(NodeId, InputOrOutput)>,
+            outputs: &[NodeId],
+            _opts: Option<RunOptions>,
+        ) -> Result<Vec<Output>, Box<dyn Error>> {
+            let inputs: HashMap<String, Output> = inputs
+                .into_iter()
+                .map(|(id, value)| {
+                    (
+                        self.nodes[id.as_usize()].name().to_string(),
+                        value.to_output(),
+                    )
+                })
+                .collect();
+
+            let input_ids: NdTensor<i32, 2> = inputs["input_ids"].clone().try_into()?;
+            let position_ids: NdTensor<i32, 2> = inputs["position_ids"].clone().try_into()?;
+            let [batch, seq] = input_ids.shape();
+
+            let logits = NdTensor::from_fn([batch, seq, self.n_vocab], |[b, i, k]| {
+                let next = (input_ids[[b, i]] as usize + 1) % self.n_vocab;
+                if k == next {
+                    1.
+                } else {
+                    0.
+                }
+            });
+
+            let new_kv = NdTensor::from_fn([batch, 1, seq, 1], |[b, _, i, _]| {
+                position_ids[[b, i]] as f32
+            });
+            let kv = if self.use_kv_cache {
+                let past: NdTensor<f32, 4> = inputs["past_key_values.0.key"].clone().try_into()?;
+                if past.size(0) != batch {
+                    return Err("KV cache batch size mismatch".into());
+                }
+                let mut kv = NdTensor::zeros([batch, 1, past.size(2) + seq, 1]);
+                kv.slice_mut((.., .., ..past.size(2))).copy_from(&past);
+                kv.slice_mut((.., .., past.size(2)..)).copy_from(&new_kv);
+                kv
+            } else {
+                new_kv
+            };
+
+            self.inputs.borrow_mut().push(inputs);
+
+            let mut result = vec![Output::FloatTensor(logits.into())];
+            if outputs.len() > 1 {
+                result.push(Output::FloatTensor(kv.into()));
+            }
+            Ok(result)
+        }
+
+        fn partial_run(
+            &self,
+            _inputs: Vec<(NodeId, InputOrOutput)>,
+            _outputs: &[NodeId],
+            _opts: Option<RunOptions>,
+        ) -> Result<Vec<(NodeId, Output)>, Box<dyn Error>> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Group generator outputs by sequence index.
+    fn collect_sequences(
+        generator: BatchGenerator,
+        batch_size: usize,
+    ) -> Result<Vec<Vec<TokenId>>, Box<dyn Error>> {
+        let mut sequences = vec![Vec::new(); batch_size];
+        for item in generator {
+            let (index, token) = item?;
+            sequences[index].push(token);
+        }
+        Ok(sequences)
+    }
+
+    #[test]
+    fn test_batch_generator() -> Result<(), Box<dyn Error>> {
+        for use_kv_cache in [true, false] {
+            let model = FakeBatchModel::new(20, use_kv_cache);
+            let prompts: [&[TokenId]; 3] = [&[1, 2, 3], &[5], &[10, 11]];
+
+            let generator = BatchGenerator::from_model(&model)?
+                .with_prompts(&prompts)?
+                .with_stop_tokens(&[8])
+                .with_max_tokens(4);
+            let sequences = collect_sequences(generator, prompts.len())?;
+
+            assert_eq!(sequences[0], [4, 5, 6, 7]);
+            assert_eq!(sequences[1], [6, 7]);
+            assert_eq!(sequences[2], [12, 13, 14, 15]);
+
+            // Check inputs for the first run. Shorter prompts are left-padded.
+            let input_ids = model.get_input(0, "input_ids").unwrap();
+            assert_eq!(
+                input_ids,
+                NdTensor::from([[1, 2, 3], [0, 0, 5], [0, 10, 11]])
+            );
+            let attention_mask = model.get_input(0, "attention_mask").unwrap();
+            assert_eq!(
+                attention_mask,
+                NdTensor::from([[1, 1, 1], [0, 0, 1], [0, 1, 1]])
+            );
+            let position_ids = model.get_input(0, "position_ids").unwrap();
+            assert_eq!(
+                position_ids,
+                NdTensor::from([[0, 1, 2], [1, 1, 0], [1, 0, 1]])
+            );
+
+            if use_kv_cache {
+                // Check inputs for the second run.
+                let input_ids = model.get_input(1, "input_ids").unwrap();
+                assert_eq!(input_ids, NdTensor::from([[4], [6], [12]]));
+                let attention_mask = model.get_input(1, "attention_mask").unwrap();
+                assert_eq!(
+                    attention_mask,
+                    NdTensor::from([[1, 1, 1, 1], [0, 0, 1, 1], [0, 1, 1, 1]])
+                );
+                let position_ids = model.get_input(1, "position_ids").unwrap();
+                assert_eq!(position_ids, NdTensor::from([[3], [1], [2]]));
+
+                // After the second sequence has finished, it is fed padding
+                // tokens which are masked out.
+                let input_ids = model.get_input(3, "input_ids").unwrap();
+                assert_eq!(input_ids, NdTensor::from([[6], [0], [14]]));
+                let attention_mask = model.get_input(3, "attention_mask").unwrap();
+                assert_eq!(
+                    attention_mask.slice((1, 3..)),
+                    NdTensorView::from(&[1, 1, 0])
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_generator_kv_cache_format() -> Result<(), Box<dyn Error>> {
+        let model = FakeBatchModel::new(20, true);
+        let prompts: [&[TokenId]; 2] = [&[1, 2, 3], &[5]];
+
+        // The format is kept when caches are re-allocated for the prompts.
+        let generator = BatchGenerator::from_model(&model)?
+            .with_kv_cache_format(KvCacheFormat::Int8PerToken)
+            .with_prompts(&prompts)?
+            .with_max_tokens(3);
+        let sequences = collect_sequences(generator, prompts.len())?;
+
+        assert_eq!(sequences[0], [4, 5, 6]);
+        assert_eq!(sequences[1], [6, 7, 8]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_generator_pad_token() -> Result<(), Box<dyn Error>> {
+        let model = FakeBatchModel::new(20, true);
+        let prompts: [&[TokenId]; 2] = [&[1, 2, 3], &[5]];
+
+        // The pad token can be set before or after the prompts.
+        let generator = BatchGenerator::from_model(&model)?
+            .with_prompts(&prompts)?
+            .with_pad_token(19)
+            .with_max_tokens(1);
+        collect_sequences(generator, prompts.len())?;
+
+        let input_ids = model.get_input(0, "input_ids").unwrap();
+        assert_eq!(input_ids, NdTensor::from([[1, 2, 3], [19, 19, 5]]));
+        let attention_mask = model.get_input(0, "attention_mask").unwrap();
+        assert_eq!(attention_mask, NdTensor::from([[1, 1, 1], [0, 0, 1]]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_generator_sequence_state() -> Result<(), Box<dyn Error>> {
+        let model = FakeBatchModel::new(20, true);
+        let prompts = [vec![1, 2, 3], vec![5]];
+        let mut generator = BatchGenerator::from_model(&model)?
+            .with_prompts(&prompts)?
+            .with_max_tokens(2);
+
+        assert_eq!(generator.batch_size(), 2);
+        assert_eq!(generator.next().unwrap()?, (0, 4));
+        assert_eq!(generator.next().unwrap()?, (1, 6));
+        assert_eq!(generator.sequence_len(0), 3);
+        assert_eq!(generator.sequence_len(1), 1);
+
+        assert_eq!(generator.by_ref().count(), 2);
+        assert!(generator.is_finished(0));
+        assert!(generator.is_finished(1));
+        assert_eq!(generator.tokens(0), [1, 2, 3, 4, 5]);
+        assert_eq!(generator.tokens(1), [5, 6, 7]);
+        assert_eq!(generator.sequence_len(0), 4);
+        assert_eq!(generator.sequence_len(1), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_generator_empty_prompt() -> Result<(), Box<dyn Error>> {
+        let model = FakeBatchModel::new(20, true);
+        let prompts: [&[TokenId]; 2] = [&[1, 2], &[]];
+        let mut generator = BatchGenerator::from_model(&model)?.with_prompts(&prompts)?;
+
+        let err = generator.next().unwrap().err().unwrap();
+        assert!(err.to_string().contains("prompt for sequence 1 is empty"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_generator_no_attention_mask() -> Result<(), Box<dyn Error>> {
+        let model = FakeBatchModel::new(20, true);
+        let config = || GeneratorConfig {
+            model_inputs: ModelInputsConfig {
+                attention_mask: "not_an_input",
+                ..Default::default()
+            },
+        };
+
+        // Prompts of the same length don't need padding.
+        let prompts: [&[TokenId]; 2] = [&[1, 2], &[5, 6]];
+        let generator = BatchGenerator::from_model_config(&model, config())?
+            .with_prompts(&prompts)?
+            .with_max_tokens(1);
+        let sequences = collect_sequences(generator, prompts.len())?;
+        assert_eq!(sequences, [[3], [7]]);
+
+        // Prompts of different lengths need an attention mask to ignore
+        // padding.
+        let prompts: [&[TokenId]; 2] = [&[1, 2], &[5]];
+        let result = BatchGenerator::from_model_config(&model, config())?.with_prompts(&prompts);
+        assert!(matches!(result, Err(GeneratorError::InvalidConfig(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,788 @@
+//! Beam search decoding.
+
+use rten::{Input, InputOrOutput, NodeId, Output, RunOptions};
+use rten_tensor::prelude::*;
+use rten_tensor::{NdTensor, NdTensorView, Tensor};
+
+use crate::filter::LogitsFilter;
+use crate::generator::{
+    find_kv_caches, wrap_error, GeneratorConfig, GeneratorError, KvCache, TokenId,
+};
+use crate::kv_quant::KvCacheFormat;
+use crate::model::Model;
+
+/// Configuration for [`BeamSearch`].
+#[derive(Clone, Debug)]
+pub struct BeamSearchConfig {
+    /// Number of candidate sequences ("beams") that are kept at each step.
+    pub beam_width: usize,
+
+    /// Maximum number of tokens to generate.
+    pub max_tokens: usize,
+
+    /// Exponent applied to the sequence length when normalizing scores.
+    ///
+    /// The score of a finished sequence is `sum(log_probs) / len^length_penalty`.
+    /// Values > 0.0 favor longer sequences, values < 0.0 favor shorter
+    /// sequences and 0.0 disables length normalization.
+    pub length_penalty: f32,
+
+    /// If true, stop as soon as `beam_width` sequences have finished.
+    /// Otherwise stop when none of the unfinished sequences can reach a
+    /// better score than the finished ones.
+    pub early_stopping: bool,
+
+    /// Number of finished sequences to return, ordered from best to worst.
+    /// This must be <= `beam_width`.
+    pub n_best: usize,
+
+    /// Tokens which end a sequence, such as end-of-text tokens.
+    pub eos_tokens: Vec<TokenId>,
+}
+
+impl Default for BeamSearchConfig {
+    fn default() -> Self {
+        BeamSearchConfig {
+            beam_width: 4,
+            max_tokens: 100,
+            length_penalty: 1.0,
+            early_stopping: false,
+            n_best: 1,
+            eos_tokens: Vec::new(),
+        }
+    }
+}
+
+/// A finished sequence produced by [`BeamSearch`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeamHypothesis {
+    /// Generated tokens, excluding the prompt and end-of-sequence token.
+    pub tokens: Vec<TokenId>,
+
+    /// Length-normalized log probability of the sequence.
+    pub score: f32,
+}
+
+/// A candidate sequence which has not yet finished.
+struct Beam {
+    /// Generated tokens, excluding the prompt.
+    tokens: Vec<TokenId>,
+
+    /// Sum of log probabilities of `tokens`.
+    log_prob: f32,
+}
+
+/// Generates token sequences using beam search.
+///
+/// Beam search keeps track of the `beam_width` most probable sequences at
+/// each step, which often produces better output than sampling one token at a
+/// time, at the cost of running the model with a batch size of `beam_width`.
+/// It is commonly used for tasks such as translation and image captioning.
+///
+/// The model inputs and outputs are configured in the same way as for
+/// [`Generator`](crate::Generator). Both self-attention and cross-attention
+/// (`encoder: true`) key-value caches are supported. Caches are duplicated
+/// and reordered as beams are extended.
+///
+/// Constant inputs with a batch size of 1, such as encoder outputs, are
+/// repeated along the batch dimension to match the number of beams.
+pub struct BeamSearch<'a> {
+    model: &'a dyn Model,
+
+    config: BeamSearchConfig,
+
+    run_options: Option<RunOptions>,
+
+    /// Additional constant model inputs passed to the model at each step.
+    constant_inputs: Vec<(NodeId, Input<'a>)>,
+
+    input_ids_input: NodeId,
+    logits_output: NodeId,
+    attention_mask_input: Option<NodeId>,
+    position_ids_input: Option<NodeId>,
+    cache_position_input: Option<NodeId>,
+    use_cache_input: Option<NodeId>,
+
+    /// Filter used to modify logits before computing token probabilities.
+    logits_filter: Option<Box<dyn LogitsFilter + 'a>>,
+
+    prompt: Vec<TokenId>,
+
+    /// Self-attention key-value cache.
+    kv_cache: Vec<KvCache>,
+
+    /// Cross-attention key-value cache.
+    encoder_kv_cache: Vec<KvCache>,
+}
+
+impl<'a> BeamSearch<'a> {
+    /// Create a beam search decoder using the default names for model inputs
+    /// and outputs.
+    ///
+    /// See [`Generator::from_model`](crate::Generator::from_model) for
+    /// details of the expected model inputs and outputs.
+    pub fn from_model(
+        model: &'a dyn Model,
+        config: BeamSearchConfig,
+    ) -> Result<BeamSearch<'a>, GeneratorError> {
+        Self::from_model_config(model, GeneratorConfig::default(), config)
+    }
+
+    /// Create a beam search decoder with custom names for model inputs.
+    pub fn from_model_config(
+        model: &'a dyn Model,
+        generator_config: GeneratorConfig,
+        config: BeamSearchConfig,
+    ) -> Result<BeamSearch<'a>, GeneratorError> {
+        if config.beam_width == 0 {
+            return Err(GeneratorError::InvalidConfig(
+                "beam width must be > 0".to_string(),
+            ));
+        }
+        if config.n_best == 0 || config.n_best > config.beam_width {
+            return Err(GeneratorError::InvalidConfig(
+                "n_best must be in the range [1, beam_width]".to_string(),
+            ));
+        }
+
+        let model_inputs = &generator_config.model_inputs;
+
+        let input_ids_input =
+            model
+                .find_node(model_inputs.input_ids)
+                .ok_or(GeneratorError::InputNotFound(
+                    model_inputs.input_ids.to_string(),
+                ))?;
+
+        let logits_output =
+            model
+                .find_node(model_inputs.logits)
+                .ok_or(GeneratorError::OutputNotFound(
+                    model_inputs.logits.to_string(),
+                ))?;
+
+        // The first step is run with a batch size of one. The caches are
+        // then duplicated for each beam.
+        let batch_size = 1;
+        let (kv_cache, encoder_kv_cache) = find_kv_caches(model, model_inputs, batch_size)?;
+
+        Ok(BeamSearch {
+            model,
+            config,
+            run_options: None,
+            constant_inputs: Vec::new(),
+            input_ids_input,
+            logits_output,
+            attention_mask_input: model.find_node(model_inputs.attention_mask),
+            position_ids_input: model.find_node(model_inputs.position_ids),
+            cache_position_input: model.find_node(model_inputs.cache_position),
+            use_cache_input: model.find_node(model_inputs.use_cache_flag),
+            logits_filter: None,
+            prompt: Vec::new(),
+            kv_cache,
+            encoder_kv_cache,
+        })
+    }
+
+    /// Set the initial sequence of tokens passed to the model.
+    ///
+    /// For encoder-decoder models this is usually the decoder start token.
+    pub fn with_prompt(mut self, prompt: &[TokenId]) -> Self {
+        self.prompt = prompt.to_vec();
+        self
+    }
+
+    /// Add a constant input which is provided to the model at each step.
+    ///
+    /// A common use case is to pass the outputs of an encoder model to
+    /// an auto-regressive decoder.
+    pub fn with_constant_input(mut self, input_id: NodeId, value: Input<'a>) -> Self {
+        self.constant_inputs.push((input_id, value));
+        self
+    }
+
+    /// Set the filter used to process model output logits before computing
+    /// token probabilities.
+    pub fn with_logits_filter<F: LogitsFilter + 'a>(mut self, filter: F) -> Self {
+        self.logits_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Set execution options used when running model inference.
+    pub fn with_run_options(mut self, opts: Option<RunOptions>) -> Self {
+        self.run_options = opts;
+        self
+    }
+
+    /// Set the format used to store the self-attention KV cache between runs.
+    ///
+    /// See [`Generator::with_kv_cache_format`](crate::Generator::with_kv_cache_format).
+    pub fn with_kv_cache_format(mut self, format: KvCacheFormat) -> Self {
+        for entry in self.kv_cache.iter_mut() {
+            entry.set_format(format);
+        }
+        self
+    }
+
+    /// Run beam search and return the `n_best` finished sequences, ordered
+    /// from best to worst.
+    pub fn run(mut self) -> Result<Vec<BeamHypothesis>, GeneratorError> {
+        if self.prompt.is_empty() {
+            return Err(wrap_error("prompt is empty", "invalid prompt"));
+        }
+
+        let mut beams = vec![Beam {
+            tokens: Vec::new(),
+            log_prob: 0.,
+        }];
+        if let Some(filter) = &self.logits_filter {
+            filter.start_sequences(&[&self.prompt]);
+        }
+        let mut finished: Vec<BeamHypothesis> = Vec::new();
+
+        // Position of the first token in the next model input.
+        let mut input_offset = 0;
+
+        // Constant inputs, expanded to match the current number of beams.
+        let mut expanded_inputs: Option<(usize, Vec<(NodeId, InputOrOutput<'a>)>)> = None;
+
+        for step in 0..self.config.max_tokens {
+            let n_beams = beams.len();
+
+            // Inputs for this step. With a KV cache only the new tokens are
+            // passed, otherwise the whole sequence is passed each time.
+            let input_ids: NdTensor<i32, 2> = if step > 0 && !self.kv_cache.is_empty() {
+                NdTensor::from_fn([n_beams, 1], |[b, _]| {
+                    *beams[b].tokens.last().unwrap() as i32
+                })
+            } else {
+                let seq_len = self.prompt.len() + step;
+                NdTensor::from_fn([n_beams, seq_len], |[b, i]| {
+                    let id = if i < self.prompt.len() {
+                        self.prompt[i]
+                    } else {
+                        beams[b].tokens[i - self.prompt.len()]
+                    };
+                    id as i32
+                })
+            };
+            let input_positions = input_offset..input_offset + input_ids.size(1);
+
+            if expanded_inputs
+                .as_ref()
+                .is_none_or(|(batch, _)| *batch != n_beams)
+            {
+                let inputs = self
+                    .constant_inputs
+                    .iter()
+                    .map(|(id, value)| (*id, repeat_batch(value, n_beams)))
+                    .collect();
+                expanded_inputs = Some((n_beams, inputs));
+            }
+
+            let mut model_inputs: Vec<(NodeId, InputOrOutput)> =
+                vec![(self.input_ids_input, input_ids.view().into())];
+            if let Some((_, inputs)) = expanded_inputs.as_ref() {
+                model_inputs.extend(
+                    inputs
+                        .iter()
+                        .map(|(id, value)| (*id, value.as_input().into())),
+                );
+            }
+
+            let mut varying_inputs: Vec<(NodeId, Tensor<i32>)> = Vec::new();
+            if let Some(attention_mask_input) = self.attention_mask_input {
+                let attention_mask = NdTensor::full([n_beams, input_positions.end], 1i32);
+                varying_inputs.push((attention_mask_input, attention_mask.into()));
+            }
+            if let Some(position_ids_input) = self.position_ids_input {
+                let position_ids = NdTensor::from_fn([n_beams, input_positions.len()], |[_, i]| {
+                    (input_positions.start + i) as i32
+                });
+                varying_inputs.push((position_ids_input, position_ids.into()));
+            }
+            if let Some(cache_position_input) = self.cache_position_input {
+                let cache_position = NdTensor::from_fn([input_positions.len()], |[i]| {
+                    (input_positions.start + i) as i32
+                });
+                varying_inputs.push((cache_position_input, cache_position.into()));
+            }
+            if let Some(use_cache_input) = self.use_cache_input {
+                let use_cache = Tensor::from(if input_positions.start == 0 { 0i32 } else { 1 });
+                varying_inputs.push((use_cache_input, use_cache));
+            }
+            model_inputs.extend(
+                varying_inputs
+                    .iter()
+                    .map(|(id, value)| (*id, value.view().into())),
+            );
+
+            for entry in self.kv_cache.iter_mut() {
+                entry.take_input(&mut model_inputs);
+            }
+            for entry in self.encoder_kv_cache.iter() {
+                entry.view_input(&mut model_inputs);
+            }
+
+            let model_outputs: Vec<NodeId> = [self.logits_output]
+                .into_iter()
+                .chain(self.kv_cache.iter().map(|entry| entry.output_id))
+                .chain(self.encoder_kv_cache.iter().map(|entry| entry.output_id))
+                .collect();
+
+            let mut outputs = self
+                .model
+                .run(model_inputs, &model_outputs, self.run_options.clone())
+                .map_err(|e| wrap_error(e, "failed to run model"))?;
+
+            let logits: NdTensor<f32, 3> = outputs
+                .remove(0)
+                .try_into()
+                .map_err(|e| wrap_error(e, "failed to extract logits from model outputs"))?;
+            if logits.size(0) != n_beams {
+                return Err(GeneratorError::ShapeMismatch(format!(
+                    "logits batch size {} does not match number of beams {}",
+                    logits.size(0),
+                    n_beams
+                )));
+            }
+
+            for cache_entry in self.kv_cache.iter_mut() {
+                cache_entry.update(outputs.remove(0))?;
+            }
+            for cache_entry in self.encoder_kv_cache.iter_mut() {
+                cache_entry.update_encoder(outputs.remove(0))?;
+            }
+
+            if !self.kv_cache.is_empty() {
+                input_offset += input_ids.size(1);
+            }
+
+            // Collect the best candidate extensions of each beam. Taking
+            // `2 * beam_width` candidates per beam ensures there are enough
+            // unfinished candidates even if some end the sequence.
+            let n_candidates = 2 * self.config.beam_width;
+            let mut candidates: Vec<(f32, usize, TokenId)> = Vec::new();
+            for (b, beam) in beams.iter().enumerate() {
+                let last_logits = logits.slice((b, -1));
+                let prev_tokens: Vec<TokenId> = self
+                    .prompt
+                    .iter()
+                    .chain(beam.tokens.iter())
+                    .copied()
+                    .collect();
+                let filtered_logits = self
+                    .logits_filter
+                    .as_ref()
+                    .and_then(|f| f.filter(last_logits, &prev_tokens))
+                    .map(|l| l.into_cow())
+                    .unwrap_or(last_logits.as_cow());
+                let log_probs = log_softmax(filtered_logits.view());
+                candidates.extend(
+                    top_k(&log_probs, n_candidates)
+                        .into_iter()
+                        .map(|(token, lp)| (beam.log_prob + lp, b, token)),
+                );
+            }
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+            // Select the beams for the next step. End-of-sequence tokens are
+            // only accepted if they rank among the top `beam_width` candidates.
+            let mut next_beams = Vec::with_capacity(self.config.beam_width);
+            let mut parents = Vec::with_capacity(self.config.beam_width);
+            for (rank, &(log_prob, parent, token)) in candidates.iter().enumerate() {
+                if next_beams.len() >= self.config.beam_width {
+                    break;
+                }
+                if log_prob == f32::NEG_INFINITY {
+                    break;
+                }
+                if self.config.eos_tokens.contains(&token) {
+                    if rank < self.config.beam_width {
+                        finished.push(BeamHypothesis {
+                            tokens: beams[parent].tokens.clone(),
+                            score: self.normalize_score(log_prob, beams[parent].tokens.len() + 1),
+                        });
+                    }
+                    continue;
+                }
+                let mut tokens = beams[parent].tokens.clone();
+                tokens.push(token);
+                next_beams.push(Beam { tokens, log_prob });
+                parents.push(parent);
+            }
+            beams = next_beams;
+
+            // Let the filter free state for beams which were not selected.
+            if let Some(filter) = &self.logits_filter {
+                let sequences: Vec<Vec<TokenId>> = beams
+                    .iter()
+                    .map(|beam| [self.prompt.as_slice(), &beam.tokens].concat())
+                    .collect();
+                let sequences: Vec<&[TokenId]> = sequences.iter().map(|s| s.as_slice()).collect();
+                filter.retain_sequences(&sequences);
+            }
+
+            if beams.is_empty() || self.is_done(&finished, &beams, step + 1) {
+                break;
+            }
+
+            // Reorder and duplicate the caches to match the new beams.
+            for entry in self.kv_cache.iter_mut() {
+                entry.select_batch(&parents);
+            }
+            for entry in self.encoder_kv_cache.iter_mut() {
+                // Cross-attention caches are the same for every beam, so
+                // only need updating if the number of beams changes.
+                if entry.batch_size() != Some(parents.len()) {
+                    entry.select_batch(&vec![0; parents.len()]);
+                }
+            }
+        }
+
+        // If the token limit was reached, include unfinished sequences.
+        if finished.len() < self.config.n_best {
+            for beam in beams {
+                let score = self.normalize_score(beam.log_prob, beam.tokens.len());
+                finished.push(BeamHypothesis {
+                    tokens: beam.tokens,
+                    score,
+                });
+            }
+        }
+
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        finished.truncate(self.config.n_best);
+
+        Ok(finished)
+    }
+
+    /// Compute the length-normalized score for a sequence.
+    fn normalize_score(&self, log_prob: f32, len: usize) -> f32 {
+        log_prob / (len.max(1) as f32).powf(self.config.length_penalty)
+    }
+
+    /// Return true if beam search can stop, given the finished and
+    /// unfinished sequences after `len` steps.
+    fn is_done(&self, finished: &[BeamHypothesis], beams: &[Beam], len: usize) -> bool {
+        if finished.len() < self.config.beam_width {
+            return false;
+        }
+        if self.config.early_stopping {
+            return true;
+        }
+
+        let mut scores: Vec<f32> = finished.iter().map(|h| h.score).collect();
+        scores.sort_by(|a, b| b.total_cmp(a));
+        let worst_finished = scores[self.config.beam_width - 1];
+        let best_unfinished = beams
+            .iter()
+            .map(|b| self.normalize_score(b.log_prob, len))
+            .fold(f32::NEG_INFINITY, f32::max);
+        worst_finished >= best_unfinished
+    }
+}
+
+/// Return a copy of `value` repeated `n` times along the batch dimension, if
+/// it has a batch size of 1.
+fn repeat_batch<'a>(value: &Input<'a>, n: usize) -> InputOrOutput<'a> {
+    fn repeat<T: Clone>(tensor: rten_tensor::TensorView<T>, n: usize) -> Tensor<T> {
+        let mut shape = tensor.shape().to_vec();
+        shape[0] = n;
+        tensor.broadcast(shape.as_slice()).to_tensor()
+    }
+
+    if n == 1 || value.ndim() == 0 || value.shape()[0] != 1 {
+        return value.clone().into();
+    }
+
+    let output: Output = match value {
+        Input::FloatTensor(t) => repeat(t.view(), n).into(),
+        Input::Int32Tensor(t) => repeat(t.view(), n).into(),
+        Input::Int8Tensor(t) => repeat(t.view(), n).into(),
+        Input::UInt8Tensor(t) => repeat(t.view(), n).into(),
+    };
+    output.into()
+}
+
+/// Compute the log of the softmax of `logits`.
+fn log_softmax(logits: NdTensorView<f32, 1>) -> Vec<f32> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|x| (x - max).exp()).sum::<f32>().ln();
+    logits.iter().map(|x| x - max - log_sum).collect()
+}
+
+/// Return the `k` largest values in `log_probs` as `(index, value)` pairs.
+fn top_k(log_probs: &[f32], k: usize) -> Vec<(TokenId, f32)> {
+    let mut indexed: Vec<(TokenId, f32)> = log_probs
+        .iter()
+        .enumerate()
+        .map(|(i, lp)| (i as TokenId, *lp))
+        .collect();
+    let k = k.min(indexed.len());
+    if k < indexed.len() {
+        indexed.select_nth_unstable_by(k, |a, b| b.1.total_cmp(&a.1));
+        indexed.truncate(k);
+    }
+    indexed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use rten::{Dimension, Input, InputOrOutput, NodeId, Output, RunOptions};
+    use rten_tensor::prelude::*;
+    use rten_tensor::{NdTensor, Tensor};
+
+    use super::{repeat_batch, BeamHypothesis, BeamSearch, BeamSearchConfig};
+    use crate::generator::GeneratorError;
+    use crate::model::{Model, NodeInfo};
+
+    const N_VOCAB: usize = 5;
+    const EOS: u32 = 0;
+
+    /// Return next-token probabilities given the current token and the token
+    /// before it.
+    fn next_token_probs(prev: Option<i32>, cur: i32) -> [f32; N_VOCAB] {
+        match (prev, cur) {
+            (None, 4) => [0.1, 0.5, 0.4, 0., 0.],
+            (Some(4), 1) => [0.25, 0., 0.35, 0.4, 0.],
+            (Some(4), 2) => [0.3, 0.1, 0., 0.6, 0.],
+            (Some(2), 3) => [0.9, 0.1, 0., 0., 0.],
+            (Some(1), 3) => [0.1, 0.9, 0., 0., 0.],
+            _ => [1., 0., 0., 0., 0.],
+        }
+    }
+
+    /// Fake encoder-decoder model whose output depends on the previous two
+    /// tokens. The previous token is read from the self-attention KV cache,
+    /// so the output will be incorrect if the cache is not reordered to
+    /// match the beams.
+    struct FakeBeamModel {
+        nodes: Vec<NodeInfo>,
+        input_ids: Vec<NodeId>,
+    }
+
+    impl FakeBeamModel {
+        fn new() -> FakeBeamModel {
+            let dims = [
+                Dimension::Symbolic("batch".to_string()),
+                Dimension::Fixed(1),
+                Dimension::Symbolic("seq".to_string()),
+                Dimension::Fixed(1),
+            ];
+            let names = [
+                "input_ids",
+                "past_key_values.0.decoder.key",
+                "past_key_values.0.encoder.key",
+                "logits",
+                "present.0.decoder.key",
+                "present.0.encoder.key",
+            ];
+            FakeBeamModel {
+                nodes: names
+                    .iter()
+                    .map(|name| NodeInfo::from_name_shape(name, &dims))
+                    .collect(),
+                input_ids: (0..3).map(NodeId::from_u32).collect(),
+            }
+        }
+    }
+
+    impl Model for FakeBeamModel {
+        fn find_node(&self, name: &str) -> Option<NodeId> {
+            self.nodes
+                .iter()
+                .position(|info| info.name() == name)
+                .map(|pos| NodeId::from_u32(pos as u32))
+        }
+
+        fn node_info(&self, id: NodeId) -> Option<NodeInfo> {
+            self.nodes.get(id.as_usize()).cloned()
+        }
+
+        fn input_ids(&self) -> &[NodeId] {
+            &self.input_ids
+        }
+
+        fn run(
+            &self,
+            inputs: Vec<(NodeId, InputOrOutput)>,
+            _outputs: &[NodeId],
+            _opts: Option<RunOptions>,
+        ) -> Result<Vec<Output>, Box<dyn Error>> {
+            let get = |idx: u32| -> Output {
+                inputs
+                    .iter()
+                    .find(|(id, _)| *id == NodeId::from_u32(idx))
+                    .map(|(_, value)| value.to_output())
+                    .unwrap()
+            };
+            let input_ids: NdTensor<i32, 2> = get(0).try_into()?;
+            let past: NdTensor<f32, 4> = get(1).try_into()?;
+            let encoder_past: NdTensor<f32, 4> = get(2).try_into()?;
+            let [batch, seq] = input_ids.shape();
+
+            if past.size(0) != batch {
+                return Err("self-attention cache has wrong batch size".into());
+            }
+            let first_run = past.size(2) == 0;
+            if !first_run && encoder_past.size(0) != batch {
+                return Err("cross-attention cache has wrong batch size".into());
+            }
+
+            let logits = NdTensor::from_fn([batch, seq, N_VOCAB], |[b, i, k]| {
+                let prev = if i > 0 {
+                    Some(input_ids[[b, i - 1]])
+                } else if past.size(2) > 0 {
+                    Some(past[[b, 0, past.size(2) - 1, 0]] as i32)
+                } else {
+                    None
+                };
+                next_token_probs(prev, input_ids[[b, i]])[k].ln()
+            });
+
+            let mut kv = NdTensor::zeros([batch, 1, past.size(2) + seq, 1]);
+            kv.slice_mut((.., .., ..past.size(2))).copy_from(&past);
+            for b in 0..batch {
+                for i in 0..seq {
+                    kv[[b, 0, past.size(2) + i, 0]] = input_ids[[b, i]] as f32;
+                }
+            }
+
+            // Encoder cache is only returned on the first run.
+            let encoder_kv = if first_run {
+                NdTensor::<f32, 4>::zeros([batch, 1, 3, 1])
+            } else {
+                NdTensor::zeros([batch, 1, 0, 0])
+            };
+
+            Ok(vec![
+                Output::FloatTensor(logits.into()),
+                Output::FloatTensor(kv.into()),
+                Output::FloatTensor(encoder_kv.into()),
+            ])
+        }
+
+        fn partial_run(
+            &self,
+            _inputs: Vec<(NodeId, InputOrOutput)>,
+            _outputs: &[NodeId],
+            _opts: Option<RunOptions>,
+        ) -> Result<Vec<(NodeId, Output)>, Box<dyn Error>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn check_hypotheses(actual: &[BeamHypothesis], expected: &[(&[u32], f32)]) {
+        assert_eq!(actual.len(), expected.len());
+        for (hyp, (tokens, prob)) in actual.iter().zip(expected) {
+            assert_eq!(hyp.tokens, *tokens);
+            assert!(
+                (hyp.score - prob.ln()).abs() < 1e-4,
+                "score {} does not match {}",
+                hyp.score,
+                prob.ln()
+            );
+        }
+    }
+
+    #[test]
+    fn test_beam_search() -> Result<(), Box<dyn Error>> {
+        let model = FakeBeamModel::new();
+        let config = BeamSearchConfig {
+            beam_width: 2,
+            max_tokens: 3,
+            length_penalty: 0.,
+            early_stopping: true,
+            n_best: 2,
+            eos_tokens: vec![EOS],
+        };
+        let hypotheses = BeamSearch::from_model(&model, config)?
+            .with_prompt(&[4])
+            .run()?;
+
+        // Greedy decoding would choose 1 as the first token. Beam search
+        // finds the more likely sequence starting with 2.
+        check_hypotheses(&hypotheses, &[(&[2, 3], 0.216), (&[1, 3, 1], 0.18)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_beam_search_greedy() -> Result<(), Box<dyn Error>> {
+        let model = FakeBeamModel::new();
+        let config = BeamSearchConfig {
+            beam_width: 1,
+            length_penalty: 0.,
+            eos_tokens: vec![EOS],
+            ..Default::default()
+        };
+        let hypotheses = BeamSearch::from_model(&model, config)?
+            .with_prompt(&[4])
+            .run()?;
+
+        // With a beam width of 1, beam search is equivalent to greedy
+        // decoding.
+        check_hypotheses(&hypotheses, &[(&[1, 3, 1], 0.5 * 0.4 * 0.9)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_beam_search_length_penalty() -> Result<(), Box<dyn Error>> {
+        let model = FakeBeamModel::new();
+        let config = BeamSearchConfig {
+            beam_width: 2,
+            length_penalty: 1.,
+            early_stopping: false,
+            eos_tokens: vec![EOS],
+            ..Default::default()
+        };
+        let hypotheses = BeamSearch::from_model(&model, config)?
+            .with_prompt(&[4])
+            .run()?;
+
+        // Scores are divided by the sequence length, including the EOS token,
+        // which favors the longer sequence.
+        assert_eq!(hypotheses.len(), 1);
+        assert_eq!(hypotheses[0].tokens, [1, 3, 1]);
+        assert!((hypotheses[0].score - 0.18f32.ln() / 4.).abs() < 1e-4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_beam_search_invalid_config() {
+        let model = FakeBeamModel::new();
+        for (beam_width, n_best) in [(0, 1), (2, 0), (2, 3)] {
+            let config = BeamSearchConfig {
+                beam_width,
+                n_best,
+                ..Default::default()
+            };
+            let result = BeamSearch::from_model(&model, config);
+            assert!(matches!(result, Err(GeneratorError::InvalidConfig(_))));
+        }
+    }
+
+    #[test]
+    fn test_repeat_batch() {
+        let encoder_output = NdTensor::from([[[1., 2.], [3., 4.]]]);
+        let input: Input = encoder_output.view().into();
+
+        let repeated = repeat_batch(&input, 3).to_output();
+        let repeated: Tensor<f32> = repeated.try_into().unwrap();
+        assert_eq!(repeated.shape(), [3, 2, 2]);
+        for b in 0..3 {
+            assert_eq!(repeated.slice(b), encoder_output.slice(0).as_dyn());
+        }
+
+        // Inputs without a batch size of 1 are passed through unchanged.
+        let scalar = Tensor::from(1i32);
+        let input: Input = scalar.view().into();
+        let output: Tensor<i32> = repeat_batch(&input, 3).to_output().try_into().unwrap();
+        assert_eq!(output, scalar);
+    }
+}
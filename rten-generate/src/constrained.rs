@@ -0,0 +1,657 @@
+//! Constrained decoding using grammars, regular expressions and JSON schemas.
+//!
+//! [`ConstrainedFilter`] is a [`LogitsFilter`] which restricts generated text
+//! to strings matched by a [`Grammar`]. At each step, tokens that would make
+//! the output invalid are suppressed.
+//!
+//! ```no_run
+//! use rten_generate::constrained::{ConstrainedFilter, Grammar};
+//! use rten_text::Tokenizer;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let tokenizer = Tokenizer::from_file("tokenizer.json")?;
+//! let grammar = Grammar::from_json_schema(r#"{
+//!     "type": "object",
+//!     "properties": {"answer": {"type": "boolean"}},
+//!     "required": ["answer"]
+//! }"#)?;
+//! let eos_token = tokenizer.get_token_id("<|endoftext|>")?;
+//! let filter = ConstrainedFilter::new(grammar, &tokenizer).with_eos_tokens(&[eos_token]);
+//!
+//! // Pass `filter` to `Generator::with_logits_filter`.
+//! # Ok(()) }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use rten_tensor::prelude::*;
+use rten_tensor::{NdTensor, NdTensorView};
+use rten_text::Tokenizer;
+
+use crate::filter::LogitsFilter;
+use crate::generator::TokenId;
+
+mod grammar;
+mod json_schema;
+
+use grammar::MatchState;
+pub use grammar::{Grammar, GrammarError};
+
+/// Prefix tree of the byte sequences for tokens in a vocabulary.
+struct TokenTrie {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    /// Child nodes, as `(byte, node_index)` pairs.
+    children: Vec<(u8, usize)>,
+
+    /// Tokens whose byte sequence ends at this node.
+    tokens: Vec<TokenId>,
+}
+
+impl TokenTrie {
+    fn new() -> TokenTrie {
+        TokenTrie {
+            nodes: vec![TrieNode::default()],
+        }
+    }
+
+    fn insert(&mut self, bytes: &[u8], token: TokenId) {
+        let mut node = 0;
+        for &byte in bytes {
+            node = match self.nodes[node].children.iter().find(|(b, _)| *b == byte) {
+                Some((_, child)) => *child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(TrieNode::default());
+                    self.nodes[node].children.push((byte, child));
+                    child
+                }
+            };
+        }
+        self.nodes[node].tokens.push(token);
+    }
+}
+
+/// Parse a byte fallback token such as `<0x41>`.
+fn parse_byte_token(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+/// Vocabulary information used to compute token masks.
+struct Vocab {
+    /// Byte sequence for each token, or `None` if the token cannot be
+    /// generated.
+    token_bytes: Vec<Option<Vec<u8>>>,
+    trie: TokenTrie,
+}
+
+/// Node in the tree of generated token sequences seen by the filter.
+struct StateNode {
+    /// Match state after the tokens on the path to this node, or `None` if
+    /// they don't match the grammar.
+    state: Option<MatchState>,
+
+    /// Child nodes, as `(token_id, node_index)` pairs.
+    children: Vec<(TokenId, usize)>,
+}
+
+/// Match states for the sequences seen by the filter.
+///
+/// Generated tokens form a tree rooted at each prompt, so sequences in a
+/// batch, beams which share a prefix and tokens that are later discarded, as
+/// happens with speculative decoding, each have their own state.
+#[derive(Default)]
+struct Progress {
+    /// Prompt of each sequence, which is not constrained, and the index of
+    /// its root node.
+    prompts: Vec<(Vec<TokenId>, usize)>,
+    nodes: Vec<StateNode>,
+}
+
+impl Progress {
+    /// Add a root node for a sequence starting with `prompt`.
+    fn add_prompt(&mut self, prompt: &[TokenId], state: MatchState) -> usize {
+        let root = self.nodes.len();
+        self.nodes.push(StateNode {
+            state: Some(state),
+            children: Vec::new(),
+        });
+        self.prompts.push((prompt.to_vec(), root));
+        root
+    }
+
+    /// Find the longest prompt that `tokens` starts with and return its
+    /// length and root node.
+    fn find_prompt(&self, tokens: &[TokenId]) -> Option<(usize, usize)> {
+        self.prompts
+            .iter()
+            .filter(|(prompt, _)| tokens.starts_with(prompt))
+            .max_by_key(|(prompt, _)| prompt.len())
+            .map(|(prompt, root)| (prompt.len(), *root))
+    }
+
+    /// Return the child of `node` reached by `token`, if it exists.
+    fn child(&self, node: usize, token: TokenId) -> Option<usize> {
+        self.nodes[node]
+            .children
+            .iter()
+            .find(|(t, _)| *t == token)
+            .map(|(_, child)| *child)
+    }
+
+    /// Remove nodes which are not on the path to any of `sequences`, and
+    /// prompts which none of `sequences` start with.
+    fn retain(&mut self, sequences: &[&[TokenId]]) {
+        let mut keep = vec![false; self.nodes.len()];
+        for tokens in sequences {
+            let Some((prompt_len, mut node)) = self.find_prompt(tokens) else {
+                continue;
+            };
+            keep[node] = true;
+            for &token in &tokens[prompt_len..] {
+                let Some(child) = self.child(node, token) else {
+                    break;
+                };
+                node = child;
+                keep[node] = true;
+            }
+        }
+
+        // Compact the remaining nodes and update references to them.
+        let mut new_index = vec![usize::MAX; self.nodes.len()];
+        let mut nodes = Vec::with_capacity(keep.iter().filter(|k| **k).count());
+        for (i, node) in std::mem::take(&mut self.nodes).into_iter().enumerate() {
+            if keep[i] {
+                new_index[i] = nodes.len();
+                nodes.push(node);
+            }
+        }
+        for node in &mut nodes {
+            node.children.retain_mut(|(_, child)| {
+                *child = new_index[*child];
+                *child != usize::MAX
+            });
+        }
+        self.nodes = nodes;
+        self.prompts.retain_mut(|(_, root)| {
+            *root = new_index[*root];
+            *root != usize::MAX
+        });
+    }
+}
+
+/// A [`LogitsFilter`] which constrains generated text to match a
+/// [`Grammar`].
+///
+/// At each step, the logits of tokens that would make the output fail to
+/// match the grammar are set to `f32::NEG_INFINITY`. End-of-sequence tokens,
+/// configured using [`with_eos_tokens`](Self::with_eos_tokens), are only
+/// allowed once the output is a complete match. Token masks are cached for
+/// each state of the grammar, so revisiting a state is cheap.
+///
+/// The filter tracks the match state of each sequence it is called with, so
+/// it can be used with [`BatchGenerator`](crate::BatchGenerator) and
+/// [`BeamSearch`](crate::BeamSearch) as well as a single
+/// [`Generator`](crate::Generator). A sequence is identified by its prompt.
+/// Generators report the prompts using
+/// [`start_sequences`](LogitsFilter::start_sequences), which discards the
+/// state of earlier sequences, so each response to a prompt added with
+/// [`Generator::append_prompt`](crate::Generator::append_prompt) is
+/// constrained separately. After each step, the state of tokens which are no
+/// longer part of any sequence is freed, as reported by
+/// [`retain_sequences`](LogitsFilter::retain_sequences).
+///
+/// If the filter is called with a sequence that does not start with a known
+/// prompt, the whole sequence is treated as a new prompt.
+pub struct ConstrainedFilter<'a> {
+    grammar: Grammar,
+    tokenizer: &'a Tokenizer,
+    eos_tokens: Vec<TokenId>,
+
+    /// Vocabulary data. This is initialized on first use, when the size of
+    /// the model's vocabulary is known.
+    vocab: RefCell<Option<Vocab>>,
+    progress: RefCell<Progress>,
+    mask_cache: RefCell<HashMap<MatchState, Rc<[bool]>>>,
+}
+
+impl<'a> ConstrainedFilter<'a> {
+    /// Create a filter which constrains output to match `grammar`, using
+    /// `tokenizer` to map token IDs to text.
+    pub fn new(grammar: Grammar, tokenizer: &'a Tokenizer) -> Self {
+        ConstrainedFilter {
+            grammar,
+            tokenizer,
+            eos_tokens: Vec::new(),
+            vocab: RefCell::new(None),
+            progress: RefCell::new(Progress::default()),
+            mask_cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Set the tokens which end generation.
+    ///
+    /// These tokens are only allowed when the output matches the grammar, and
+    /// are always allowed after a token that violates the grammar has been
+    /// generated.
+    pub fn with_eos_tokens(mut self, eos_tokens: &[TokenId]) -> Self {
+        self.eos_tokens = eos_tokens.to_vec();
+        self
+    }
+
+    fn init_vocab(&self, vocab_size: usize) {
+        let mut vocab = self.vocab.borrow_mut();
+        if vocab
+            .as_ref()
+            .is_some_and(|vocab| vocab.token_bytes.len() == vocab_size)
+        {
+            return;
+        }
+
+        let prefix = self.find_prefix_token(vocab_size);
+        let mut trie = TokenTrie::new();
+        let token_bytes: Vec<_> = (0..vocab_size as TokenId)
+            .map(|id| {
+                if self.eos_tokens.contains(&id) {
+                    return None;
+                }
+                // Tokens which decode to nothing are excluded, as they would
+                // allow generation to continue indefinitely.
+                let bytes = self
+                    .token_bytes(id, prefix.as_ref())
+                    .filter(|b| !b.is_empty())?;
+                trie.insert(&bytes, id);
+                Some(bytes)
+            })
+            .collect();
+
+        *vocab = Some(Vocab { token_bytes, trie });
+        self.mask_cache.borrow_mut().clear();
+    }
+
+    /// Find a token to decode before each token in the vocabulary, together
+    /// with its decoded text.
+    ///
+    /// Decoders treat the first token of a sequence differently from the
+    /// rest. For example the Metaspace decoder removes a leading space and
+    /// the WordPiece decoder only strips the `##` prefix from subsequent
+    /// tokens. Decoding a token after a prefix gives the text it adds when
+    /// generated.
+    fn find_prefix_token(&self, vocab_size: usize) -> Option<(TokenId, String)> {
+        (0..vocab_size as TokenId)
+            .filter(|id| !self.eos_tokens.contains(id))
+            .find_map(|id| {
+                let text = self.tokenizer.decode(&[id]).ok()?;
+                let is_word = !text.is_empty() && text.chars().all(|c| c.is_alphanumeric());
+                is_word.then_some((id, text))
+            })
+    }
+
+    /// Return the bytes that token `id` adds to the output when generated.
+    fn token_bytes(&self, id: TokenId, prefix: Option<&(TokenId, String)>) -> Option<Vec<u8>> {
+        let model = self.tokenizer.model();
+
+        // Byte fallback tokens (eg. `<0xE2>`) may be part of a multi-byte
+        // UTF-8 sequence, so can't be decoded to text individually.
+        let token_str = model.get_token_str(id);
+        if let Some(byte) = token_str.as_deref().and_then(parse_byte_token) {
+            if self.tokenizer.decode(&[id]).ok() != token_str {
+                return Some(vec![byte]);
+            }
+        }
+
+        let text = match prefix {
+            Some((prefix_id, prefix_text)) => self
+                .tokenizer
+                .decode(&[*prefix_id, id])
+                .ok()
+                .and_then(|text| {
+                    text.strip_prefix(prefix_text.as_str())
+                        .map(|t| t.to_string())
+                }),
+            None => self.tokenizer.decode(&[id]).ok(),
+        };
+        match text {
+            Some(text) => Some(text.into_bytes()),
+            // Tokens in byte-level vocabularies may also be part of a
+            // multi-byte UTF-8 sequence.
+            None => model.decode_bytes(&[id]).ok(),
+        }
+    }
+
+    /// Return the match state for a sequence, updating the state tree with
+    /// tokens that have not been seen before.
+    fn update_state(&self, prev_tokens: &[TokenId]) -> Option<MatchState> {
+        let mut progress = self.progress.borrow_mut();
+        let progress = &mut *progress;
+
+        // Find the prompt that `prev_tokens` continues, or start a new
+        // sequence if there is none.
+        let (prompt_len, mut node) = progress.find_prompt(prev_tokens).unwrap_or_else(|| {
+            let root = progress.add_prompt(prev_tokens, self.grammar.initial_state());
+            (prev_tokens.len(), root)
+        });
+
+        let vocab = self.vocab.borrow();
+        let vocab = vocab.as_ref().unwrap();
+        for &token in &prev_tokens[prompt_len..] {
+            node = match progress.child(node, token) {
+                Some(child) => child,
+                None => {
+                    let bytes = vocab
+                        .token_bytes
+                        .get(token as usize)
+                        .and_then(|bytes| bytes.as_deref());
+                    let state = match (&progress.nodes[node].state, bytes) {
+                        (Some(state), Some(bytes)) => self.grammar.advance_bytes(state, bytes),
+                        _ => None,
+                    };
+                    let child = progress.nodes.len();
+                    progress.nodes.push(StateNode {
+                        state,
+                        children: Vec::new(),
+                    });
+                    progress.nodes[node].children.push((token, child));
+                    child
+                }
+            };
+        }
+        progress.nodes[node].state.clone()
+    }
+
+    /// Return the mask of allowed tokens in a given state.
+    fn token_mask(&self, state: &MatchState) -> Rc<[bool]> {
+        if let Some(mask) = self.mask_cache.borrow().get(state) {
+            return mask.clone();
+        }
+
+        let vocab = self.vocab.borrow();
+        let vocab = vocab.as_ref().unwrap();
+        let mut mask = vec![false; vocab.token_bytes.len()];
+
+        // Walk the trie, pruning subtrees whose prefix doesn't match.
+        let mut pending = vec![(0, state.clone())];
+        while let Some((node, state)) = pending.pop() {
+            for &(byte, child) in &vocab.trie.nodes[node].children {
+                let Some(next_state) = self.grammar.advance_byte(&state, byte) else {
+                    continue;
+                };
+                for &token in &vocab.trie.nodes[child].tokens {
+                    mask[token as usize] = true;
+                }
+                pending.push((child, next_state));
+            }
+        }
+
+        if self.grammar.is_accepting(state) {
+            for &eos in &self.eos_tokens {
+                if let Some(allowed) = mask.get_mut(eos as usize) {
+                    *allowed = true;
+                }
+            }
+        }
+
+        let mask: Rc<[bool]> = mask.into();
+        self.mask_cache
+            .borrow_mut()
+            .insert(state.clone(), mask.clone());
+        mask
+    }
+}
+
+impl LogitsFilter for ConstrainedFilter<'_> {
+    fn start_sequences(&self, prompts: &[&[TokenId]]) {
+        let mut progress = Progress::default();
+        for prompt in prompts {
+            progress.add_prompt(prompt, self.grammar.initial_state());
+        }
+        self.progress.replace(progress);
+    }
+
+    fn retain_sequences(&self, sequences: &[&[TokenId]]) {
+        self.progress.borrow_mut().retain(sequences);
+    }
+
+    fn filter(
+        &self,
+        logits: NdTensorView<f32, 1>,
+        prev_tokens: &[TokenId],
+    ) -> Option<NdTensor<f32, 1>> {
+        self.init_vocab(logits.size(0));
+
+        let mask: Rc<[bool]> = match self.update_state(prev_tokens) {
+            Some(state) => self.token_mask(&state),
+            // If the output no longer matches the grammar, only allow ending
+            // the sequence.
+            None => (0..logits.size(0) as TokenId)
+                .map(|id| self.eos_tokens.contains(&id))
+                .collect(),
+        };
+
+        Some(NdTensor::from_fn(logits.shape(), |[i]| {
+            if mask[i] {
+                logits[[i]]
+            } else {
+                f32::NEG_INFINITY
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rten_tensor::prelude::*;
+    use rten_tensor::NdTensor;
+    use rten_text::decoders::{self, Decoder};
+    use rten_text::models::WordPiece;
+    use rten_text::Tokenizer;
+
+    use super::{ConstrainedFilter, Grammar};
+    use crate::filter::LogitsFilter;
+    use crate::generator::TokenId;
+
+    fn create_tokenizer(vocab: &[&str]) -> Tokenizer {
+        create_tokenizer_with_decoder(vocab, Box::new(decoders::Fuse::new()))
+    }
+
+    fn create_tokenizer_with_decoder(vocab: &[&str], decoder: Box<dyn Decoder>) -> Tokenizer {
+        let vocab: HashMap<_, _> = vocab
+            .iter()
+            .enumerate()
+            .map(|(i, token)| (token.to_string(), i as TokenId))
+            .collect();
+        let model = WordPiece::from_vocab(vocab, Default::default());
+        Tokenizer::new(model, Default::default()).with_decoder(decoder)
+    }
+
+    /// Return the IDs of tokens which are allowed by `filter`.
+    fn allowed_tokens(
+        filter: &ConstrainedFilter,
+        vocab_size: usize,
+        prev: &[TokenId],
+    ) -> Vec<TokenId> {
+        let logits = NdTensor::full([vocab_size], 1.0);
+        let output = filter.filter(logits.view(), prev).unwrap();
+        output
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.is_finite())
+            .map(|(i, _)| i as TokenId)
+            .collect()
+    }
+
+    #[test]
+    fn test_constrained_filter() {
+        let vocab = [
+            "[EOS]", "{", "}", "\"a\"", ":", " ", "true", "false", "1", "tr", "ue",
+        ];
+        let tokenizer = create_tokenizer(&vocab);
+        let grammar = Grammar::from_json_schema(
+            r#"{"type": "object", "properties": {"a": {"type": "boolean"}}, "required": ["a"]}"#,
+        )
+        .unwrap();
+        let filter = ConstrainedFilter::new(grammar, &tokenizer).with_eos_tokens(&[0]);
+
+        // Tokens are looked up by text for readability.
+        let id = |tok: &str| vocab.iter().position(|t| *t == tok).unwrap() as TokenId;
+        let ids = |toks: &[&str]| -> Vec<TokenId> { toks.iter().map(|t| id(t)).collect() };
+
+        // The prompt is not constrained.
+        let mut tokens = vec![id("1"), id("1")];
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &tokens), ids(&["{"]));
+
+        tokens.push(id("{"));
+        assert_eq!(
+            allowed_tokens(&filter, vocab.len(), &tokens),
+            ids(&["\"a\"", " "])
+        );
+
+        tokens.extend(ids(&["\"a\"", ":"]));
+        assert_eq!(
+            allowed_tokens(&filter, vocab.len(), &tokens),
+            ids(&[" ", "true", "false", "tr"])
+        );
+
+        // Multi-token values.
+        tokens.push(id("tr"));
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &tokens), ids(&["ue"]));
+
+        // Discarding generated tokens rolls back the state.
+        assert_eq!(
+            allowed_tokens(&filter, vocab.len(), &tokens[..tokens.len() - 1]),
+            ids(&[" ", "true", "false", "tr"])
+        );
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &tokens), ids(&["ue"]));
+
+        // EOS is only allowed once the output is complete.
+        tokens.extend(ids(&["ue", "}"]));
+        assert_eq!(
+            allowed_tokens(&filter, vocab.len(), &tokens),
+            ids(&["[EOS]"])
+        );
+
+        // Starting a new sequence resets the state.
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[]), ids(&["{"]));
+
+        // If the output doesn't match, only EOS is allowed.
+        filter.start_sequences(&[&[]]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[]), ids(&["{"]));
+        assert_eq!(
+            allowed_tokens(&filter, vocab.len(), &[id("1")]),
+            ids(&["[EOS]"])
+        );
+    }
+
+    #[test]
+    fn test_constrained_filter_regex() {
+        let vocab = ["[EOS]", "a", "b", "ab", "ba", "c"];
+        let tokenizer = create_tokenizer(&vocab);
+        let grammar = Grammar::from_regex("(ab)+").unwrap();
+        let filter = ConstrainedFilter::new(grammar, &tokenizer).with_eos_tokens(&[0]);
+
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[]), [1, 3]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1]), [2, 4]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1, 4]), [2, 4]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1, 4, 2]), [0, 1, 3]);
+    }
+
+    #[test]
+    fn test_constrained_filter_decoders() {
+        // Tokens are decoded as they would appear after a previous token, so
+        // WordPiece tokens without a `##` prefix start with a space.
+        let vocab = ["[EOS]", "a", "##b", "b"];
+        let tokenizer =
+            create_tokenizer_with_decoder(&vocab, Box::new(decoders::WordPiece::default()));
+        let grammar = Grammar::from_regex(" ab+").unwrap();
+        let filter = ConstrainedFilter::new(grammar, &tokenizer).with_eos_tokens(&[0]);
+
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[]), [1]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1]), [2]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1, 2]), [0, 2]);
+
+        // Metaspace tokens, and byte fallback tokens which are part of a
+        // multi-byte character.
+        let vocab = ["[EOS]", "▁a", "b", "▁b", "<0xC3>", "<0xA9>"];
+        let decoder = decoders::Sequence::from_vec(vec![
+            Box::new(decoders::Metaspace::new('▁', true)),
+            Box::new(decoders::ByteFallback::new()),
+            Box::new(decoders::Fuse::new()),
+        ]);
+        let tokenizer = create_tokenizer_with_decoder(&vocab, Box::new(decoder));
+        let grammar = Grammar::from_regex(" a( b|é)*").unwrap();
+        let filter = ConstrainedFilter::new(grammar, &tokenizer).with_eos_tokens(&[0]);
+
+        // Multi-byte characters are only matched once all their bytes are
+        // known, so the first byte of one is always allowed.
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[]), [1, 4]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1]), [0, 3, 4]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1, 4]), [5]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1, 4, 5]), [0, 3, 4]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[1, 5]), [0]);
+    }
+
+    #[test]
+    fn test_constrained_filter_multiple_sequences() {
+        let vocab = ["[EOS]", "a", "b", "ab", "ba", "c"];
+        let tokenizer = create_tokenizer(&vocab);
+        let grammar = Grammar::from_regex("(ab)+").unwrap();
+        let filter = ConstrainedFilter::new(grammar, &tokenizer).with_eos_tokens(&[0]);
+
+        // Sequences in a batch, identified by their prompts.
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5]), [1, 3]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[2]), [1, 3]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5, 1]), [2, 4]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[2, 3]), [0, 1, 3]);
+
+        // Beams which share a prefix.
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5, 1, 2]), [0, 1, 3]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5, 1, 4]), [2, 4]);
+        assert_eq!(
+            allowed_tokens(&filter, vocab.len(), &[5, 1, 2, 3]),
+            [0, 1, 3]
+        );
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[2, 3, 5]), [0]);
+    }
+
+    #[test]
+    fn test_constrained_filter_lifecycle() {
+        let vocab = ["[EOS]", "a", "b", "ab", "ba", "c"];
+        let tokenizer = create_tokenizer(&vocab);
+        let grammar = Grammar::from_regex("(ab)+").unwrap();
+        let filter = ConstrainedFilter::new(grammar, &tokenizer).with_eos_tokens(&[0]);
+
+        filter.start_sequences(&[&[5]]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5]), [1, 3]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5, 1]), [2, 4]);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5, 3]), [0, 1, 3]);
+        assert_eq!(filter.progress.borrow().nodes.len(), 3);
+
+        // States of tokens which are not part of a retained sequence are
+        // freed.
+        filter.retain_sequences(&[&[5, 1]]);
+        assert_eq!(filter.progress.borrow().nodes.len(), 2);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5, 1]), [2, 4]);
+
+        // Tokens in a new prompt, such as one added with
+        // `Generator::append_prompt`, are not constrained.
+        filter.start_sequences(&[&[5, 1, 5]]);
+        assert_eq!(filter.progress.borrow().nodes.len(), 1);
+        assert_eq!(allowed_tokens(&filter, vocab.len(), &[5, 1, 5]), [1, 3]);
+
+        // Prompts which no retained sequence starts with are removed.
+        filter.retain_sequences(&[]);
+        assert_eq!(filter.progress.borrow().prompts.len(), 0);
+        assert_eq!(filter.progress.borrow().nodes.len(), 0);
+    }
+}
@@ -0,0 +1,1160 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Errors that occur when parsing or compiling a [`Grammar`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum GrammarError {
+    /// The grammar, regex or schema source could not be parsed. The fields
+    /// are the byte offset in the source where the error occurred and an
+    /// error message.
+    ParseError(usize, String),
+
+    /// A rule referenced a rule name that is not defined.
+    UndefinedRule(String),
+
+    /// The grammar does not define a `root` rule.
+    MissingRoot,
+
+    /// A rule is left-recursive, meaning it can refer to itself before
+    /// consuming any input. Such rules are not supported.
+    LeftRecursion(String),
+
+    /// The JSON schema is invalid or uses features which are not supported.
+    InvalidSchema(String),
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ParseError(pos, msg) => write!(f, "parse error at {}: {}", pos, msg),
+            Self::UndefinedRule(name) => write!(f, "undefined rule \"{}\"", name),
+            Self::MissingRoot => write!(f, "grammar has no \"root\" rule"),
+            Self::LeftRecursion(name) => write!(f, "rule \"{}\" is left-recursive", name),
+            Self::InvalidSchema(msg) => write!(f, "invalid JSON schema: {}", msg),
+        }
+    }
+}
+
+impl Error for GrammarError {}
+
+/// A set of characters, specified as a list of inclusive ranges.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct CharSet {
+    ranges: Vec<(char, char)>,
+
+    /// If true, the set matches all characters _not_ in `ranges`.
+    negated: bool,
+}
+
+impl CharSet {
+    pub fn char(ch: char) -> CharSet {
+        Self::range(ch, ch)
+    }
+
+    pub fn range(start: char, end: char) -> CharSet {
+        CharSet {
+            ranges: vec![(start, end)],
+            negated: false,
+        }
+    }
+
+    /// Create a set which matches any character.
+    pub fn any() -> CharSet {
+        CharSet {
+            ranges: Vec::new(),
+            negated: true,
+        }
+    }
+
+    /// Create a set which matches all characters except those in `ranges`.
+    pub fn not(ranges: &[(char, char)]) -> CharSet {
+        CharSet {
+            ranges: ranges.to_vec(),
+            negated: true,
+        }
+    }
+
+    pub fn from_ranges(ranges: &[(char, char)]) -> CharSet {
+        CharSet {
+            ranges: ranges.to_vec(),
+            negated: false,
+        }
+    }
+
+    pub fn matches(&self, ch: char) -> bool {
+        let in_ranges = self
+            .ranges
+            .iter()
+            .any(|&(start, end)| ch >= start && ch <= end);
+        in_ranges != self.negated
+    }
+}
+
+/// Expression in a grammar rule.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    /// Match a single character from a set.
+    Chars(CharSet),
+
+    /// Match a rule by name.
+    Rule(String),
+
+    /// Match a sequence of expressions. An empty sequence matches the empty
+    /// string.
+    Seq(Vec<Expr>),
+
+    /// Match any of several expressions.
+    Alt(Vec<Expr>),
+
+    /// Match an expression repeated between `min` and `max` times.
+    Repeat {
+        expr: Box<Expr>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+impl Expr {
+    /// Create an expression which matches a literal string.
+    pub fn literal(text: &str) -> Expr {
+        Expr::Seq(
+            text.chars()
+                .map(|ch| Expr::Chars(CharSet::char(ch)))
+                .collect(),
+        )
+    }
+
+    /// Create an expression which matches the empty string or `expr`.
+    pub fn optional(expr: Expr) -> Expr {
+        Self::repeat(expr, 0, Some(1))
+    }
+
+    pub fn repeat(expr: Expr, min: u32, max: Option<u32>) -> Expr {
+        Expr::Repeat {
+            expr: Box::new(expr),
+            min,
+            max,
+        }
+    }
+
+    pub fn rule(name: &str) -> Expr {
+        Expr::Rule(name.to_string())
+    }
+}
+
+/// Element of a sequence in a compiled grammar.
+#[derive(Clone, Debug, PartialEq)]
+enum Element {
+    Chars(CharSet),
+    Rule(usize),
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    name: String,
+    alternatives: Vec<Vec<Element>>,
+}
+
+/// Position of the next element to match in an alternative of a rule.
+#[derive(Clone, Copy, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
+struct Pos {
+    rule: u32,
+    alt: u32,
+    idx: u32,
+}
+
+/// A stack of positions within nested rules. The top of the stack (the last
+/// element) is the position of the next character to match. An empty stack
+/// means the input has been fully matched.
+type Stack = Vec<Pos>;
+
+/// State of an incremental match against a [`Grammar`].
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub(crate) struct MatchState {
+    /// Sorted list of possible parse stacks.
+    stacks: Vec<Stack>,
+
+    /// Bytes of an incomplete UTF-8 character.
+    pending: Vec<u8>,
+}
+
+/// A context-free grammar which constrains generated text.
+///
+/// Grammars can be created from a GBNF-style grammar ([`from_gbnf`](Self::from_gbnf)),
+/// a regular expression ([`from_regex`](Self::from_regex)) or a JSON schema
+/// ([`from_json_schema`](Self::from_json_schema)).
+///
+/// Matching uses a pushdown automaton operating on Unicode characters, so
+/// grammars must not be left-recursive.
+#[derive(Clone, Debug)]
+pub struct Grammar {
+    rules: Vec<Rule>,
+    root: usize,
+}
+
+impl Grammar {
+    /// Parse a grammar in the GBNF format used by llama.cpp.
+    ///
+    /// A grammar consists of rules of the form `name ::= expr`. The grammar
+    /// must define a `root` rule which matches the whole output. Expressions
+    /// can contain:
+    ///
+    /// - String literals (`"abc"`) and character classes (`[a-z]`, `[^"]`)
+    /// - `.` to match any character
+    /// - References to other rules by name
+    /// - Groups (`(a b)`) and alternatives (`a | b`)
+    /// - Repetition operators `*`, `+`, `?`, `{m}`, `{m,}` and `{m,n}`
+    ///
+    /// Comments start with `#` and extend to the end of the line.
+    ///
+    /// ```
+    /// use rten_generate::constrained::Grammar;
+    ///
+    /// let grammar = Grammar::from_gbnf(r#"
+    ///     root ::= answer "."
+    ///     answer ::= "yes" | "no"
+    /// "#).unwrap();
+    /// assert!(grammar.matches("yes."));
+    /// assert!(!grammar.matches("maybe."));
+    /// ```
+    pub fn from_gbnf(source: &str) -> Result<Grammar, GrammarError> {
+        let rules = Parser::new(source, Syntax::Gbnf).parse_grammar()?;
+        Self::from_rules(rules, "root")
+    }
+
+    /// Create a grammar which matches strings that fully match a regular
+    /// expression.
+    ///
+    /// The supported syntax includes literals, character classes (including
+    /// `\d`, `\w` and `\s`), `.`, groups, alternation and the repetition
+    /// operators `*`, `+`, `?` and `{m,n}`. Anchors at the start and end of
+    /// the pattern are ignored since the whole output is always matched.
+    /// Backreferences and lookaround are not supported.
+    pub fn from_regex(pattern: &str) -> Result<Grammar, GrammarError> {
+        let expr = parse_regex(pattern)?;
+        Self::from_rules(vec![("root".to_string(), expr)], "root")
+    }
+
+    /// Create a grammar which matches JSON documents that conform to a JSON
+    /// schema.
+    ///
+    /// The following schema features are supported:
+    ///
+    /// - `type`, including arrays of types
+    /// - `properties` and `required` for objects. Properties are generated in
+    ///   the order they are defined in the schema and additional properties
+    ///   are not generated.
+    /// - `items`, `minItems` and `maxItems` for arrays
+    /// - `minLength`, `maxLength` and `pattern` for strings
+    /// - `enum` and `const`
+    /// - `anyOf`, `oneOf` and `allOf` with a single entry
+    /// - Local references (`$ref`) to `#`, `#/$defs/{name}` and
+    ///   `#/definitions/{name}`, including recursive references
+    ///
+    /// Other keywords (eg. `minimum`, `format`) are ignored. An empty schema
+    /// matches any JSON value. The generated JSON may contain at most one
+    /// space between tokens.
+    pub fn from_json_schema(schema: &str) -> Result<Grammar, GrammarError> {
+        let rules = super::json_schema::schema_to_rules(schema)?;
+        Self::from_rules(rules, "root")
+    }
+
+    /// Compile a grammar from a list of `(name, expr)` rules.
+    pub(crate) fn from_rules(
+        rules: Vec<(String, Expr)>,
+        root: &str,
+    ) -> Result<Grammar, GrammarError> {
+        Compiler::new(&rules)?.compile(&rules, root)
+    }
+
+    /// Return true if `text` is matched by the grammar.
+    pub fn matches(&self, text: &str) -> bool {
+        self.advance_bytes(&self.initial_state(), text.as_bytes())
+            .is_some_and(|state| self.is_accepting(&state))
+    }
+
+    /// Return the state at the start of the input.
+    pub(crate) fn initial_state(&self) -> MatchState {
+        let mut stacks = Vec::new();
+        for alt in 0..self.rules[self.root].alternatives.len() {
+            self.expand(
+                vec![Pos {
+                    rule: self.root as u32,
+                    alt: alt as u32,
+                    idx: 0,
+                }],
+                &mut stacks,
+            );
+        }
+        stacks.sort();
+        stacks.dedup();
+        MatchState {
+            stacks,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Return true if the input consumed to reach `state` is a complete match.
+    pub(crate) fn is_accepting(&self, state: &MatchState) -> bool {
+        state.pending.is_empty() && state.stacks.iter().any(|s| s.is_empty())
+    }
+
+    /// Advance the match by a sequence of bytes.
+    ///
+    /// Returns `None` if the bytes cannot be matched.
+    pub(crate) fn advance_bytes(&self, state: &MatchState, bytes: &[u8]) -> Option<MatchState> {
+        let mut state = state.clone();
+        for &byte in bytes {
+            state = self.advance_byte(&state, byte)?;
+        }
+        Some(state)
+    }
+
+    /// Advance the match by a single byte of UTF-8 encoded input.
+    ///
+    /// Returns `None` if the byte cannot be matched.
+    pub(crate) fn advance_byte(&self, state: &MatchState, byte: u8) -> Option<MatchState> {
+        let mut pending = state.pending.clone();
+        pending.push(byte);
+
+        let char_len = match pending[0] {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return None,
+        };
+        if pending.len() > 1 && (byte & 0xC0) != 0x80 {
+            return None;
+        }
+        if pending.len() < char_len {
+            return Some(MatchState {
+                stacks: state.stacks.clone(),
+                pending,
+            });
+        }
+
+        let ch = std::str::from_utf8(&pending).ok()?.chars().next()?;
+        let stacks = self.advance_char(&state.stacks, ch);
+        if stacks.is_empty() {
+            return None;
+        }
+        Some(MatchState {
+            stacks,
+            pending: Vec::new(),
+        })
+    }
+
+    fn advance_char(&self, stacks: &[Stack], ch: char) -> Vec<Stack> {
+        let mut next = Vec::new();
+        for stack in stacks {
+            let Some(top) = stack.last() else {
+                continue;
+            };
+            let Element::Chars(chars) = self.element(top) else {
+                unreachable!("stack top should be a character element");
+            };
+            if !chars.matches(ch) {
+                continue;
+            }
+            let mut stack = stack.clone();
+            stack.last_mut().unwrap().idx += 1;
+            self.expand(stack, &mut next);
+        }
+        next.sort();
+        next.dedup();
+        next
+    }
+
+    fn element(&self, pos: &Pos) -> &Element {
+        &self.rules[pos.rule as usize].alternatives[pos.alt as usize][pos.idx as usize]
+    }
+
+    fn alt_len(&self, pos: &Pos) -> usize {
+        self.rules[pos.rule as usize].alternatives[pos.alt as usize].len()
+    }
+
+    /// Expand rule references at the top of `stack` until every resulting
+    /// stack is either empty or has a character element at the top, and add
+    /// the results to `out`.
+    fn expand(&self, mut stack: Stack, out: &mut Vec<Stack>) {
+        // Pop positions which have reached the end of their alternative.
+        while stack
+            .last()
+            .is_some_and(|top| top.idx as usize == self.alt_len(top))
+        {
+            stack.pop();
+        }
+
+        let Some(top) = stack.last().copied() else {
+            out.push(stack);
+            return;
+        };
+
+        match self.element(&top) {
+            Element::Chars(_) => out.push(stack),
+            Element::Rule(rule) => {
+                // Move past the rule reference in the parent and pop any
+                // positions that are then complete before pushing the child.
+                // This keeps the stack bounded for right-recursive rules.
+                stack.last_mut().unwrap().idx += 1;
+                let mut base = stack;
+                while base
+                    .last()
+                    .is_some_and(|top| top.idx as usize == self.alt_len(top))
+                {
+                    base.pop();
+                }
+
+                for alt in 0..self.rules[*rule].alternatives.len() {
+                    let mut child = base.clone();
+                    child.push(Pos {
+                        rule: *rule as u32,
+                        alt: alt as u32,
+                        idx: 0,
+                    });
+                    self.expand(child, out);
+                }
+            }
+        }
+    }
+}
+
+/// Compiles named [`Expr`] rules into a [`Grammar`].
+struct Compiler {
+    rules: Vec<Rule>,
+    rule_ids: HashMap<String, usize>,
+}
+
+impl Compiler {
+    fn new(rules: &[(String, Expr)]) -> Result<Compiler, GrammarError> {
+        let mut rule_ids = HashMap::new();
+        let mut compiled = Vec::new();
+        for (name, _) in rules {
+            if rule_ids.contains_key(name) {
+                return Err(GrammarError::ParseError(
+                    0,
+                    format!("rule \"{}\" is defined more than once", name),
+                ));
+            }
+            rule_ids.insert(name.clone(), compiled.len());
+            compiled.push(Rule {
+                name: name.clone(),
+                alternatives: Vec::new(),
+            });
+        }
+        Ok(Compiler {
+            rules: compiled,
+            rule_ids,
+        })
+    }
+
+    fn compile(mut self, rules: &[(String, Expr)], root: &str) -> Result<Grammar, GrammarError> {
+        let root = *self.rule_ids.get(root).ok_or(GrammarError::MissingRoot)?;
+        for (name, expr) in rules {
+            let alternatives = self.alternatives(expr)?;
+            let id = self.rule_ids[name];
+            self.rules[id].alternatives = alternatives;
+        }
+        self.check_left_recursion()?;
+        Ok(Grammar {
+            rules: self.rules,
+            root,
+        })
+    }
+
+    fn alternatives(&mut self, expr: &Expr) -> Result<Vec<Vec<Element>>, GrammarError> {
+        match expr {
+            Expr::Alt(exprs) => exprs
+                .iter()
+                .map(|expr| {
+                    let mut seq = Vec::new();
+                    self.sequence(expr, &mut seq)?;
+                    Ok(seq)
+                })
+                .collect(),
+            _ => {
+                let mut seq = Vec::new();
+                self.sequence(expr, &mut seq)?;
+                Ok(vec![seq])
+            }
+        }
+    }
+
+    /// Add a helper rule for a sub-expression and return its ID.
+    fn add_rule(&mut self, alternatives: Vec<Vec<Element>>) -> usize {
+        let id = self.rules.len();
+        self.rules.push(Rule {
+            name: format!("_{}", id),
+            alternatives,
+        });
+        id
+    }
+
+    /// Convert an expression into a single element, creating a helper rule
+    /// if needed.
+    fn element(&mut self, expr: &Expr) -> Result<Element, GrammarError> {
+        match expr {
+            Expr::Chars(chars) => Ok(Element::Chars(chars.clone())),
+            Expr::Rule(name) => self.rule_ref(name),
+            _ => {
+                let alternatives = self.alternatives(expr)?;
+                Ok(Element::Rule(self.add_rule(alternatives)))
+            }
+        }
+    }
+
+    fn rule_ref(&self, name: &str) -> Result<Element, GrammarError> {
+        self.rule_ids
+            .get(name)
+            .map(|id| Element::Rule(*id))
+            .ok_or_else(|| GrammarError::UndefinedRule(name.to_string()))
+    }
+
+    fn sequence(&mut self, expr: &Expr, out: &mut Vec<Element>) -> Result<(), GrammarError> {
+        match expr {
+            Expr::Chars(chars) => out.push(Element::Chars(chars.clone())),
+            Expr::Rule(name) => out.push(self.rule_ref(name)?),
+            Expr::Seq(exprs) => {
+                for expr in exprs {
+                    self.sequence(expr, out)?;
+                }
+            }
+            Expr::Alt(_) => {
+                let alternatives = self.alternatives(expr)?;
+                out.push(Element::Rule(self.add_rule(alternatives)));
+            }
+            Expr::Repeat { expr, min, max } => {
+                if max.is_some_and(|max| max < *min) {
+                    return Err(GrammarError::ParseError(
+                        0,
+                        format!("invalid repetition range {{{},{}}}", min, max.unwrap()),
+                    ));
+                }
+                let elem = self.element(expr)?;
+                for _ in 0..*min {
+                    out.push(elem.clone());
+                }
+                match max {
+                    // `x*` is compiled as `r ::= x r | ""`.
+                    None => {
+                        let id = self.add_rule(Vec::new());
+                        self.rules[id].alternatives =
+                            vec![vec![elem, Element::Rule(id)], Vec::new()];
+                        out.push(Element::Rule(id));
+                    }
+                    // `x{0,n}` is compiled as `r_n ::= x r_{n-1} | ""`.
+                    Some(max) => {
+                        let mut tail: Option<usize> = None;
+                        for _ in *min..*max {
+                            let mut seq = vec![elem.clone()];
+                            if let Some(tail) = tail {
+                                seq.push(Element::Rule(tail));
+                            }
+                            tail = Some(self.add_rule(vec![seq, Vec::new()]));
+                        }
+                        if let Some(tail) = tail {
+                            out.push(Element::Rule(tail));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that no rule can refer to itself without first consuming input.
+    fn check_left_recursion(&self) -> Result<(), GrammarError> {
+        // Find rules which can match the empty string.
+        let mut nullable = vec![false; self.rules.len()];
+        let elem_nullable = |nullable: &[bool], elem: &Element| match elem {
+            Element::Chars(_) => false,
+            Element::Rule(id) => nullable[*id],
+        };
+        loop {
+            let mut changed = false;
+            for (id, rule) in self.rules.iter().enumerate() {
+                if !nullable[id]
+                    && rule
+                        .alternatives
+                        .iter()
+                        .any(|alt| alt.iter().all(|elem| elem_nullable(&nullable, elem)))
+                {
+                    nullable[id] = true;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        // Find the rules that each rule can reference before consuming input.
+        let left_refs: Vec<Vec<usize>> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let mut refs = Vec::new();
+                for alt in &rule.alternatives {
+                    for elem in alt {
+                        if let Element::Rule(id) = elem {
+                            refs.push(*id);
+                        }
+                        if !elem_nullable(&nullable, elem) {
+                            break;
+                        }
+                    }
+                }
+                refs
+            })
+            .collect();
+
+        // Search for cycles.
+        #[derive(Clone, Copy, PartialEq)]
+        enum Visit {
+            Unvisited,
+            InProgress,
+            Done,
+        }
+
+        fn visit(id: usize, left_refs: &[Vec<usize>], visited: &mut [Visit]) -> Result<(), usize> {
+            match visited[id] {
+                Visit::Done => return Ok(()),
+                Visit::InProgress => return Err(id),
+                Visit::Unvisited => {}
+            }
+            visited[id] = Visit::InProgress;
+            for &child in &left_refs[id] {
+                visit(child, left_refs, visited)?;
+            }
+            visited[id] = Visit::Done;
+            Ok(())
+        }
+
+        let mut visited = vec![Visit::Unvisited; self.rules.len()];
+        for id in 0..self.rules.len() {
+            if let Err(rule) = visit(id, &left_refs, &mut visited) {
+                return Err(GrammarError::LeftRecursion(self.rules[rule].name.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Syntax {
+    Gbnf,
+    Regex,
+}
+
+/// Recursive descent parser for GBNF grammars and regular expressions.
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+    syntax: Syntax,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str, syntax: Syntax) -> Parser<'a> {
+        Parser {
+            source,
+            pos: 0,
+            syntax,
+        }
+    }
+
+    fn error<T>(&self, msg: impl Into<String>) -> Result<T, GrammarError> {
+        Err(GrammarError::ParseError(self.pos, msg.into()))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        if self.source[self.pos..].starts_with(prefix) {
+            self.pos += prefix.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, prefix: &str) -> Result<(), GrammarError> {
+        if self.eat(prefix) {
+            Ok(())
+        } else {
+            self.error(format!("expected \"{}\"", prefix))
+        }
+    }
+
+    /// Skip whitespace and comments in GBNF grammars.
+    fn skip_space(&mut self) {
+        if self.syntax != Syntax::Gbnf {
+            return;
+        }
+        while let Some(ch) = self.peek() {
+            if ch.is_whitespace() {
+                self.next_char();
+            } else if ch == '#' {
+                while self.next_char().is_some_and(|ch| ch != '\n') {}
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn is_name_char(ch: char) -> bool {
+        ch.is_ascii_alphanumeric() || ch == '_' || ch == '-'
+    }
+
+    fn parse_name(&mut self) -> Result<String, GrammarError> {
+        let start = self.pos;
+        while self.peek().is_some_and(Self::is_name_char) {
+            self.next_char();
+        }
+        if self.pos == start {
+            return self.error("expected rule name");
+        }
+        Ok(self.source[start..self.pos].to_string())
+    }
+
+    /// Return true if the input at the current position is the start of a
+    /// new rule definition (`name ::=`).
+    fn at_rule_start(&self) -> bool {
+        let rest = &self.source[self.pos..];
+        let name_len = rest
+            .find(|ch: char| !Self::is_name_char(ch))
+            .unwrap_or(rest.len());
+        name_len > 0 && rest[name_len..].trim_start().starts_with("::=")
+    }
+
+    fn parse_grammar(&mut self) -> Result<Vec<(String, Expr)>, GrammarError> {
+        let mut rules = Vec::new();
+        self.skip_space();
+        while self.peek().is_some() {
+            let name = self.parse_name()?;
+            self.skip_space();
+            self.expect("::=")?;
+            self.skip_space();
+            let expr = self.parse_alternatives(0)?;
+            rules.push((name, expr));
+        }
+        Ok(rules)
+    }
+
+    /// Parse alternatives separated by `|`. `depth` is the number of
+    /// enclosing groups.
+    fn parse_alternatives(&mut self, depth: usize) -> Result<Expr, GrammarError> {
+        let mut alternatives = vec![self.parse_sequence(depth)?];
+        while self.eat("|") {
+            self.skip_space();
+            alternatives.push(self.parse_sequence(depth)?);
+        }
+        if alternatives.len() == 1 {
+            Ok(alternatives.pop().unwrap())
+        } else {
+            Ok(Expr::Alt(alternatives))
+        }
+    }
+
+    fn parse_sequence(&mut self, depth: usize) -> Result<Expr, GrammarError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_space();
+            let Some(ch) = self.peek() else {
+                break;
+            };
+            if ch == '|' || ch == ')' {
+                break;
+            }
+            if self.syntax == Syntax::Gbnf && depth == 0 && self.at_rule_start() {
+                break;
+            }
+            let term = self.parse_term(depth)?;
+            let term = self.parse_quantifier(term)?;
+            items.push(term);
+        }
+        if items.len() == 1 {
+            Ok(items.pop().unwrap())
+        } else {
+            Ok(Expr::Seq(items))
+        }
+    }
+
+    fn parse_term(&mut self, depth: usize) -> Result<Expr, GrammarError> {
+        let ch = self.peek().unwrap();
+        match (self.syntax, ch) {
+            (_, '(') => {
+                self.next_char();
+                if self.syntax == Syntax::Regex && self.eat("?") && !self.eat(":") {
+                    return self.error("lookaround and named groups are not supported");
+                }
+                self.skip_space();
+                let expr = self.parse_alternatives(depth + 1)?;
+                self.skip_space();
+                self.expect(")")?;
+                Ok(expr)
+            }
+            (_, '[') => {
+                self.next_char();
+                self.parse_class().map(Expr::Chars)
+            }
+            (Syntax::Gbnf, '.') => {
+                self.next_char();
+                Ok(Expr::Chars(CharSet::any()))
+            }
+            (Syntax::Regex, '.') => {
+                self.next_char();
+                Ok(Expr::Chars(CharSet::not(&[('\n', '\n')])))
+            }
+            (Syntax::Gbnf, '"') => {
+                self.next_char();
+                let mut items = Vec::new();
+                loop {
+                    match self.next_char() {
+                        None => return self.error("unterminated string literal"),
+                        Some('"') => break,
+                        Some('\\') => items.push(Expr::Chars(CharSet::char(self.parse_escape()?))),
+                        Some(ch) => items.push(Expr::Chars(CharSet::char(ch))),
+                    }
+                }
+                Ok(Expr::Seq(items))
+            }
+            (Syntax::Gbnf, ch) if Self::is_name_char(ch) => Ok(Expr::Rule(self.parse_name()?)),
+            (Syntax::Regex, '\\') => {
+                self.next_char();
+                if let Some(class) = self.parse_class_escape() {
+                    return Ok(Expr::Chars(class));
+                }
+                Ok(Expr::Chars(CharSet::char(self.parse_escape()?)))
+            }
+            (Syntax::Regex, '*' | '+' | '?' | '{') => self.error("quantifier without operand"),
+            (Syntax::Regex, ch) => {
+                self.next_char();
+                Ok(Expr::Chars(CharSet::char(ch)))
+            }
+            (Syntax::Gbnf, ch) => self.error(format!("unexpected character '{}'", ch)),
+        }
+    }
+
+    fn parse_quantifier(&mut self, mut expr: Expr) -> Result<Expr, GrammarError> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => {
+                    self.next_char();
+                    (0, None)
+                }
+                Some('+') => {
+                    self.next_char();
+                    (1, None)
+                }
+                Some('?') => {
+                    self.next_char();
+                    (0, Some(1))
+                }
+                Some('{') => {
+                    self.next_char();
+                    let min = self.parse_number()?;
+                    let max = if self.eat(",") {
+                        if self.peek() == Some('}') {
+                            None
+                        } else {
+                            Some(self.parse_number()?)
+                        }
+                    } else {
+                        Some(min)
+                    };
+                    self.expect("}")?;
+                    if max.is_some_and(|max| max < min) {
+                        return self.error("invalid repetition range");
+                    }
+                    (min, max)
+                }
+                _ => return Ok(expr),
+            };
+
+            // Lazy and possessive modifiers don't affect the set of matched
+            // strings.
+            if self.syntax == Syntax::Regex && !self.eat("?") {
+                self.eat("+");
+            }
+            expr = Expr::repeat(expr, min, max);
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<u32, GrammarError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+            self.next_char();
+        }
+        self.source[start..self.pos]
+            .parse()
+            .or_else(|_| self.error("expected number"))
+    }
+
+    /// Parse a character escape, after the backslash.
+    fn parse_escape(&mut self) -> Result<char, GrammarError> {
+        let Some(ch) = self.next_char() else {
+            return self.error("unterminated escape sequence");
+        };
+        let hex_char = |parser: &mut Self, len: usize| {
+            let digits = parser.source.get(parser.pos..parser.pos + len);
+            let ch = digits
+                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                .and_then(char::from_u32);
+            match ch {
+                Some(ch) => {
+                    parser.pos += len;
+                    Ok(ch)
+                }
+                None => parser.error("invalid hex escape"),
+            }
+        };
+        match ch {
+            'n' => Ok('\n'),
+            'r' => Ok('\r'),
+            't' => Ok('\t'),
+            'x' => hex_char(self, 2),
+            'u' => hex_char(self, 4),
+            'U' => hex_char(self, 8),
+            ch if ch.is_ascii_alphanumeric() => {
+                self.error(format!("unsupported escape sequence \\{}", ch))
+            }
+            ch => Ok(ch),
+        }
+    }
+
+    /// Parse a regex class escape such as `\d`, after the backslash.
+    fn parse_class_escape(&mut self) -> Option<CharSet> {
+        if self.syntax != Syntax::Regex {
+            return None;
+        }
+        const DIGIT: &[(char, char)] = &[('0', '9')];
+        const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+        const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+        let class = match self.peek()? {
+            'd' => CharSet::from_ranges(DIGIT),
+            'D' => CharSet::not(DIGIT),
+            'w' => CharSet::from_ranges(WORD),
+            'W' => CharSet::not(WORD),
+            's' => CharSet::from_ranges(SPACE),
+            'S' => CharSet::not(SPACE),
+            _ => return None,
+        };
+        self.next_char();
+        Some(class)
+    }
+
+    /// Parse a character class, after the opening `[`.
+    fn parse_class(&mut self) -> Result<CharSet, GrammarError> {
+        let negated = self.eat("^");
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let start = match self.next_char() {
+                None => return self.error("unterminated character class"),
+                Some(']') if !first => break,
+                Some('\\') => {
+                    if let Some(class) = self.parse_class_escape() {
+                        if class.negated {
+                            return self
+                                .error("negated class escapes are not supported in classes");
+                        }
+                        ranges.extend(class.ranges);
+                        first = false;
+                        continue;
+                    }
+                    self.parse_escape()?
+                }
+                Some(ch) => ch,
+            };
+            first = false;
+
+            let end = if self.peek() == Some('-') && !self.source[self.pos + 1..].starts_with(']') {
+                self.next_char();
+                match self.next_char() {
+                    None => return self.error("unterminated character class"),
+                    Some('\\') => self.parse_escape()?,
+                    Some(ch) => ch,
+                }
+            } else {
+                start
+            };
+            if end < start {
+                return self.error("invalid character range");
+            }
+            ranges.push((start, end));
+        }
+        Ok(CharSet { ranges, negated })
+    }
+}
+
+pub(crate) fn parse_regex(pattern: &str) -> Result<Expr, GrammarError> {
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let pattern = match pattern.strip_suffix('$') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => pattern,
+    };
+    let mut parser = Parser::new(pattern, Syntax::Regex);
+    let expr = parser.parse_alternatives(0)?;
+    if parser.peek().is_some() {
+        return parser.error("unmatched ')'");
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+
+    use super::{Grammar, GrammarError};
+
+    #[test]
+    fn test_gbnf_grammar() {
+        let grammar = Grammar::from_gbnf(
+            r#"
+            # A comma-separated list of numbers.
+            root ::= "[" (number ("," " "? number)*)? "]"
+            number ::= "-"? [0-9]+
+            "#,
+        )
+        .unwrap();
+
+        for valid in ["[]", "[1]", "[1, -23,4]"] {
+            assert!(grammar.matches(valid), "{} should match", valid);
+        }
+        for invalid in ["", "[", "[1,]", "[a]", "[1] "] {
+            assert!(!grammar.matches(invalid), "{} should not match", invalid);
+        }
+    }
+
+    #[test]
+    fn test_gbnf_recursive_grammar() {
+        let grammar = Grammar::from_gbnf(
+            r#"
+            root ::= list
+            list ::= "(" (item (" " item)*)? ")"
+            item ::= list | [a-z]+
+            "#,
+        )
+        .unwrap();
+
+        assert!(grammar.matches("(a (b c) ((d)))"));
+        assert!(!grammar.matches("(a (b c)"));
+    }
+
+    #[test]
+    fn test_grammar_errors() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            grammar: &'a str,
+            expected: GrammarError,
+        }
+
+        let cases = [
+            Case {
+                grammar: r#"foo ::= "a""#,
+                expected: GrammarError::MissingRoot,
+            },
+            Case {
+                grammar: r#"root ::= foo"#,
+                expected: GrammarError::UndefinedRule("foo".into()),
+            },
+            Case {
+                grammar: r#"root ::= root "a" | "b""#,
+                expected: GrammarError::LeftRecursion("root".into()),
+            },
+            Case {
+                grammar: r#"root ::= "a"? root"#,
+                expected: GrammarError::LeftRecursion("root".into()),
+            },
+            Case {
+                grammar: r#"root ::= "abc"#,
+                expected: GrammarError::ParseError(13, "unterminated string literal".into()),
+            },
+        ];
+
+        cases.test_each(|case| {
+            let result = Grammar::from_gbnf(case.grammar);
+            assert_eq!(result.err(), Some(case.expected.clone()));
+        })
+    }
+
+    #[test]
+    fn test_regex() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            pattern: &'a str,
+            matches: &'a [&'a str],
+            non_matches: &'a [&'a str],
+        }
+
+        let cases = [
+            Case {
+                pattern: r"\d{3}-\d{4}",
+                matches: &["555-1234"],
+                non_matches: &["555-123", "5551234"],
+            },
+            Case {
+                pattern: r"^(cat|dog)s?$",
+                matches: &["cat", "dogs"],
+                non_matches: &["cats!", "bird"],
+            },
+            Case {
+                pattern: r"[a-zA-Z_][\w]*",
+                matches: &["_foo1", "Bar"],
+                non_matches: &["1foo", ""],
+            },
+            Case {
+                pattern: r"[^a-c]+\.",
+                matches: &["xyz.", "é."],
+                non_matches: &["abc.", "x"],
+            },
+            Case {
+                pattern: r"a{2,}b{0,2}",
+                matches: &["aa", "aaab", "aabb"],
+                non_matches: &["a", "aabbb"],
+            },
+            Case {
+                pattern: r"(?:ab)+?",
+                matches: &["ab", "abab"],
+                non_matches: &["aba"],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let grammar = Grammar::from_regex(case.pattern).unwrap();
+            for text in case.matches {
+                assert!(grammar.matches(text), "{} should match", text);
+            }
+            for text in case.non_matches {
+                assert!(!grammar.matches(text), "{} should not match", text);
+            }
+        })
+    }
+
+    #[test]
+    fn test_advance_partial_utf8() {
+        let grammar = Grammar::from_regex("é+").unwrap();
+        let bytes = "é".as_bytes();
+
+        let state = grammar.initial_state();
+        let partial = grammar.advance_byte(&state, bytes[0]).unwrap();
+        assert!(!grammar.is_accepting(&partial));
+        let full = grammar.advance_byte(&partial, bytes[1]).unwrap();
+        assert!(grammar.is_accepting(&full));
+
+        // Invalid UTF-8 is rejected.
+        assert!(grammar.advance_byte(&state, 0xFF).is_none());
+        assert!(grammar.advance_byte(&partial, b'a').is_none());
+    }
+}
@@ -0,0 +1,523 @@
+//! Conversion of JSON schemas into grammars.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+use super::grammar::{parse_regex, CharSet, Expr, GrammarError};
+
+/// Convert a JSON schema into a list of grammar rules. The top-level rule is
+/// called `root`.
+pub(crate) fn schema_to_rules(schema: &str) -> Result<Vec<(String, Expr)>, GrammarError> {
+    let schema: Value = serde_json::from_str(schema)
+        .map_err(|err| GrammarError::InvalidSchema(format!("invalid JSON: {}", err)))?;
+
+    let mut converter = SchemaConverter {
+        root: &schema,
+        rules: base_rules(),
+        ref_rules: HashMap::new(),
+        next_rule_id: 0,
+    };
+    let root = converter.convert(&schema)?;
+    converter.rules.push(("root".to_string(), root));
+    Ok(converter.rules)
+}
+
+/// Return rules for primitive values and generic JSON values.
+fn base_rules() -> Vec<(String, Expr)> {
+    let lit = Expr::literal;
+    let rule = Expr::rule;
+    let seq = Expr::Seq;
+    let digits = || Expr::repeat(Expr::Chars(CharSet::range('0', '9')), 1, None);
+    let int_part = || {
+        Expr::Alt(vec![
+            lit("0"),
+            seq(vec![
+                Expr::Chars(CharSet::range('1', '9')),
+                Expr::repeat(Expr::Chars(CharSet::range('0', '9')), 0, None),
+            ]),
+        ])
+    };
+    let hex_digit = Expr::Chars(CharSet::from_ranges(&[('0', '9'), ('A', 'F'), ('a', 'f')]));
+
+    // Pattern for a value in a comma-separated list.
+    let list = |item: Expr| {
+        Expr::optional(seq(vec![
+            item.clone(),
+            rule("ws"),
+            Expr::repeat(seq(vec![lit(","), rule("ws"), item, rule("ws")]), 0, None),
+        ]))
+    };
+    let member = seq(vec![
+        rule("string"),
+        rule("ws"),
+        lit(":"),
+        rule("ws"),
+        rule("value"),
+    ]);
+
+    vec![
+        ("ws".into(), Expr::optional(lit(" "))),
+        (
+            "char".into(),
+            Expr::Alt(vec![
+                Expr::Chars(CharSet::not(&[('"', '"'), ('\\', '\\'), ('\0', '\x1f')])),
+                seq(vec![
+                    lit("\\"),
+                    Expr::Alt(vec![
+                        Expr::Chars(CharSet::from_ranges(&[
+                            ('"', '"'),
+                            ('\\', '\\'),
+                            ('/', '/'),
+                            ('b', 'b'),
+                            ('f', 'f'),
+                            ('n', 'n'),
+                            ('r', 'r'),
+                            ('t', 't'),
+                        ])),
+                        seq(vec![lit("u"), Expr::repeat(hex_digit, 4, Some(4))]),
+                    ]),
+                ]),
+            ]),
+        ),
+        (
+            "string".into(),
+            seq(vec![
+                lit("\""),
+                Expr::repeat(rule("char"), 0, None),
+                lit("\""),
+            ]),
+        ),
+        (
+            "integer".into(),
+            seq(vec![Expr::optional(lit("-")), int_part()]),
+        ),
+        (
+            "number".into(),
+            seq(vec![
+                Expr::optional(lit("-")),
+                int_part(),
+                Expr::optional(seq(vec![lit("."), digits()])),
+                Expr::optional(seq(vec![
+                    Expr::Chars(CharSet::from_ranges(&[('E', 'E'), ('e', 'e')])),
+                    Expr::optional(Expr::Chars(CharSet::from_ranges(&[('+', '+'), ('-', '-')]))),
+                    digits(),
+                ])),
+            ]),
+        ),
+        ("boolean".into(), Expr::Alt(vec![lit("true"), lit("false")])),
+        ("null".into(), lit("null")),
+        (
+            "object".into(),
+            seq(vec![lit("{"), rule("ws"), list(member), lit("}")]),
+        ),
+        (
+            "array".into(),
+            seq(vec![lit("["), rule("ws"), list(rule("value")), lit("]")]),
+        ),
+        (
+            "value".into(),
+            Expr::Alt(
+                ["object", "array", "string", "number", "boolean", "null"]
+                    .into_iter()
+                    .map(rule)
+                    .collect(),
+            ),
+        ),
+    ]
+}
+
+struct SchemaConverter<'a> {
+    /// Root schema, used to resolve references.
+    root: &'a Value,
+
+    rules: Vec<(String, Expr)>,
+
+    /// Map of reference to rule name for references that have been
+    /// converted.
+    ref_rules: HashMap<String, String>,
+
+    next_rule_id: usize,
+}
+
+impl<'a> SchemaConverter<'a> {
+    fn new_rule_name(&mut self, prefix: &str) -> String {
+        let name = format!("{}-{}", prefix, self.next_rule_id);
+        self.next_rule_id += 1;
+        name
+    }
+
+    fn convert(&mut self, schema: &'a Value) -> Result<Expr, GrammarError> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok(Expr::rule("value")),
+            Value::Object(obj) => obj,
+            _ => {
+                return Err(GrammarError::InvalidSchema(format!(
+                    "unsupported schema {}",
+                    schema
+                )))
+            }
+        };
+
+        if let Some(reference) = schema.get("$ref") {
+            let reference = reference
+                .as_str()
+                .ok_or_else(|| GrammarError::InvalidSchema("\"$ref\" must be a string".into()))?;
+            return self.convert_ref(reference);
+        }
+
+        if let Some(value) = schema.get("const") {
+            return Ok(json_literal(value));
+        }
+
+        if let Some(values) = schema.get("enum") {
+            let values = values
+                .as_array()
+                .ok_or_else(|| GrammarError::InvalidSchema("\"enum\" must be an array".into()))?;
+            return Ok(Expr::Alt(values.iter().map(json_literal).collect()));
+        }
+
+        for key in ["anyOf", "oneOf"] {
+            if let Some(schemas) = schema.get(key) {
+                let schemas = schemas.as_array().ok_or_else(|| {
+                    GrammarError::InvalidSchema(format!("\"{}\" must be an array", key))
+                })?;
+                let alternatives = schemas
+                    .iter()
+                    .map(|schema| self.convert(schema))
+                    .collect::<Result<_, _>>()?;
+                return Ok(Expr::Alt(alternatives));
+            }
+        }
+
+        if let Some(schemas) = schema.get("allOf") {
+            return match schemas.as_array().map(|s| s.as_slice()) {
+                Some([schema]) => self.convert(schema),
+                _ => Err(GrammarError::InvalidSchema(
+                    "\"allOf\" is only supported with a single schema".into(),
+                )),
+            };
+        }
+
+        match schema.get("type") {
+            Some(Value::String(type_name)) => self.convert_type(type_name, schema),
+            Some(Value::Array(type_names)) => {
+                let alternatives = type_names
+                    .iter()
+                    .map(|type_name| match type_name {
+                        Value::String(type_name) => self.convert_type(type_name, schema),
+                        _ => Err(GrammarError::InvalidSchema(
+                            "\"type\" must be a string or array of strings".into(),
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Expr::Alt(alternatives))
+            }
+            Some(_) => Err(GrammarError::InvalidSchema(
+                "\"type\" must be a string or array of strings".into(),
+            )),
+            None if schema.contains_key("properties") => self.convert_type("object", schema),
+            None if schema.contains_key("items") => self.convert_type("array", schema),
+            None => Ok(Expr::rule("value")),
+        }
+    }
+
+    fn convert_ref(&mut self, reference: &str) -> Result<Expr, GrammarError> {
+        if let Some(name) = self.ref_rules.get(reference) {
+            return Ok(Expr::rule(name));
+        }
+
+        let target = if reference == "#" {
+            Some(self.root)
+        } else if let Some(name) = reference.strip_prefix("#/$defs/") {
+            self.root.get("$defs").and_then(|defs| defs.get(name))
+        } else if let Some(name) = reference.strip_prefix("#/definitions/") {
+            self.root.get("definitions").and_then(|defs| defs.get(name))
+        } else {
+            None
+        };
+        let target = target.ok_or_else(|| {
+            GrammarError::InvalidSchema(format!("unable to resolve reference \"{}\"", reference))
+        })?;
+
+        // Register the rule name before converting the target to support
+        // recursive references.
+        let name = self.new_rule_name("ref");
+        self.ref_rules.insert(reference.to_string(), name.clone());
+        let expr = self.convert(target)?;
+        self.rules.push((name.clone(), expr));
+
+        Ok(Expr::rule(&name))
+    }
+
+    fn convert_type(
+        &mut self,
+        type_name: &str,
+        schema: &'a Map<String, Value>,
+    ) -> Result<Expr, GrammarError> {
+        let get_u32 = |key: &str| -> Result<Option<u32>, GrammarError> {
+            match schema.get(key) {
+                None => Ok(None),
+                Some(val) => val
+                    .as_u64()
+                    .and_then(|val| val.try_into().ok())
+                    .map(Some)
+                    .ok_or_else(|| {
+                        GrammarError::InvalidSchema(format!(
+                            "\"{}\" must be a non-negative integer",
+                            key
+                        ))
+                    }),
+            }
+        };
+
+        match type_name {
+            "string" => {
+                if let Some(pattern) = schema.get("pattern") {
+                    let pattern = pattern.as_str().ok_or_else(|| {
+                        GrammarError::InvalidSchema("\"pattern\" must be a string".into())
+                    })?;
+                    let expr = parse_regex(pattern)?;
+                    return Ok(Expr::Seq(vec![
+                        Expr::literal("\""),
+                        expr,
+                        Expr::literal("\""),
+                    ]));
+                }
+                let min_len = get_u32("minLength")?.unwrap_or(0);
+                let max_len = get_u32("maxLength")?;
+                if min_len == 0 && max_len.is_none() {
+                    return Ok(Expr::rule("string"));
+                }
+                Ok(Expr::Seq(vec![
+                    Expr::literal("\""),
+                    Expr::repeat(Expr::rule("char"), min_len, max_len),
+                    Expr::literal("\""),
+                ]))
+            }
+            "number" | "integer" | "boolean" | "null" => Ok(Expr::rule(type_name)),
+            "object" => self.convert_object(schema),
+            "array" => {
+                let item = match schema.get("items") {
+                    Some(items) => self.convert(items)?,
+                    None => Expr::rule("value"),
+                };
+                let min_items = get_u32("minItems")?.unwrap_or(0);
+                let max_items = get_u32("maxItems")?;
+
+                let ws = Expr::rule("ws");
+                let rest = Expr::Seq(vec![
+                    Expr::literal(","),
+                    ws.clone(),
+                    item.clone(),
+                    ws.clone(),
+                ]);
+                let items = Expr::Seq(vec![
+                    item,
+                    ws.clone(),
+                    Expr::repeat(
+                        rest,
+                        min_items.saturating_sub(1),
+                        max_items.map(|max| max.saturating_sub(1)),
+                    ),
+                ]);
+                let items = match (min_items, max_items) {
+                    (_, Some(0)) => Expr::Seq(Vec::new()),
+                    (0, _) => Expr::optional(items),
+                    _ => items,
+                };
+                Ok(Expr::Seq(vec![
+                    Expr::literal("["),
+                    ws,
+                    items,
+                    Expr::literal("]"),
+                ]))
+            }
+            _ => Err(GrammarError::InvalidSchema(format!(
+                "unsupported type \"{}\"",
+                type_name
+            ))),
+        }
+    }
+
+    fn convert_object(&mut self, schema: &'a Map<String, Value>) -> Result<Expr, GrammarError> {
+        let Some(properties) = schema.get("properties") else {
+            return Ok(Expr::rule("object"));
+        };
+        let properties = properties.as_object().ok_or_else(|| {
+            GrammarError::InvalidSchema("\"properties\" must be an object".into())
+        })?;
+        let required: Vec<&str> = match schema.get("required") {
+            Some(Value::Array(names)) => names.iter().filter_map(|name| name.as_str()).collect(),
+            Some(_) => {
+                return Err(GrammarError::InvalidSchema(
+                    "\"required\" must be an array".into(),
+                ))
+            }
+            None => Vec::new(),
+        };
+
+        let ws = Expr::rule("ws");
+        let mut props = Vec::new();
+        for (key, prop_schema) in properties {
+            let value = self.convert(prop_schema)?;
+            let expr = Expr::Seq(vec![
+                json_literal(&Value::String(key.clone())),
+                ws.clone(),
+                Expr::literal(":"),
+                ws.clone(),
+                value,
+                ws.clone(),
+            ]);
+            props.push((expr, required.contains(&key.as_str())));
+        }
+
+        // Generate rules of the form `{prefix}-{i}-{first}` which match the
+        // properties starting from the i-th, where `first` indicates whether
+        // any property has been generated yet and so whether a comma is
+        // needed before the next one.
+        let prefix = self.new_rule_name("object");
+        let rule_name = |i: usize, first: bool| format!("{}-{}-{}", prefix, i, first);
+        for i in (0..=props.len()).rev() {
+            for first in [true, false] {
+                let expr = if let Some((prop, required)) = props.get(i) {
+                    let prop = if first {
+                        prop.clone()
+                    } else {
+                        Expr::Seq(vec![Expr::literal(","), ws.clone(), prop.clone()])
+                    };
+                    let with_prop = Expr::Seq(vec![prop, Expr::rule(&rule_name(i + 1, false))]);
+                    if *required {
+                        with_prop
+                    } else {
+                        Expr::Alt(vec![with_prop, Expr::rule(&rule_name(i + 1, first))])
+                    }
+                } else {
+                    Expr::Seq(Vec::new())
+                };
+                self.rules.push((rule_name(i, first), expr));
+            }
+        }
+
+        Ok(Expr::Seq(vec![
+            Expr::literal("{"),
+            ws,
+            Expr::rule(&rule_name(0, true)),
+            Expr::literal("}"),
+        ]))
+    }
+}
+
+/// Return an expression which matches the JSON serialization of `value`.
+fn json_literal(value: &Value) -> Expr {
+    Expr::literal(&value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+
+    use super::super::Grammar;
+
+    #[test]
+    fn test_json_schema() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            schema: &'a str,
+            valid: &'a [&'a str],
+            invalid: &'a [&'a str],
+        }
+
+        let cases = [
+            // Empty schema
+            Case {
+                schema: "{}",
+                valid: &[
+                    r#"{"a": [1, 2.5e-3, true, null], "b": {"c": "d\"e"}}"#,
+                    "[]",
+                    "-12",
+                ],
+                invalid: &["{", "01", "[1,]", r#"{"a"}"#, "'a'"],
+            },
+            // Object with required and optional properties
+            Case {
+                schema: r#"{
+                    "type": "object",
+                    "properties": {
+                        "name": {"type": "string", "maxLength": 3},
+                        "age": {"type": "integer"},
+                        "tags": {"type": "array", "items": {"enum": ["a", "b"]}}
+                    },
+                    "required": ["age"]
+                }"#,
+                valid: &[
+                    r#"{"name": "Bob", "age": 42, "tags": ["a", "b"]}"#,
+                    r#"{"age": 42}"#,
+                    r#"{"age":42,"tags":[]}"#,
+                ],
+                invalid: &[
+                    r#"{"name": "Bob"}"#,
+                    r#"{"name": "Robert", "age": 42}"#,
+                    r#"{"age": 4.2}"#,
+                    r#"{"age": 42, "tags": ["c"]}"#,
+                    // Properties are generated in the order they are defined.
+                    r#"{"age": 42, "name": "Bob"}"#,
+                ],
+            },
+            // Arrays with length limits
+            Case {
+                schema: r#"{"type": "array", "items": {"type": "boolean"}, "minItems": 1, "maxItems": 2}"#,
+                valid: &["[true]", "[true, false]"],
+                invalid: &["[]", "[true, false, true]"],
+            },
+            // String patterns and type unions
+            Case {
+                schema: r#"{"anyOf": [{"type": "string", "pattern": "^[0-9]{2}$"}, {"type": ["null", "number"]}]}"#,
+                valid: &[r#""42""#, "null", "1.5"],
+                invalid: &[r#""4""#, "true"],
+            },
+            // Recursive references
+            Case {
+                schema: r##"{
+                    "$defs": {
+                        "node": {
+                            "type": "object",
+                            "properties": {
+                                "value": {"const": 1},
+                                "children": {"type": "array", "items": {"$ref": "#/$defs/node"}}
+                            },
+                            "required": ["value"]
+                        }
+                    },
+                    "$ref": "#/$defs/node"
+                }"##,
+                valid: &[
+                    r#"{"value": 1}"#,
+                    r#"{"value": 1, "children": [{"value": 1, "children": []}]}"#,
+                ],
+                invalid: &[r#"{"value": 2}"#],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let grammar = Grammar::from_json_schema(case.schema).unwrap();
+            for text in case.valid {
+                assert!(grammar.matches(text), "{} should match", text);
+            }
+            for text in case.invalid {
+                assert!(!grammar.matches(text), "{} should not match", text);
+            }
+        })
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        for schema in [
+            "[",
+            r#"{"type": "date"}"#,
+            r##"{"$ref": "#/$defs/missing"}"##,
+        ] {
+            assert!(Grammar::from_json_schema(schema).is_err());
+        }
+    }
+}
@@ -1,7 +1,11 @@
 //! Filters for processing model outputs prior to sampling.
 //!
 //! This module defines the [`LogitsFilter`] trait implemented by all filters,
-//! plus convenience functions to simplify implementing filters.
+//! plus convenience functions to simplify implementing filters and filters
+//! for common penalties. Multiple filters can be combined using
+//! [`FilterChain`].
+
+use std::collections::{HashMap, HashSet};
 
 use rten_tensor::prelude::*;
 use rten_tensor::{NdTensor, NdTensorView};
@@ -22,6 +26,28 @@ pub trait LogitsFilter {
         logits: NdTensorView<f32, 1>,
         prev_tokens: &[TokenId],
     ) -> Option<NdTensor<f32, 1>>;
+
+    /// Called by generators when generation starts for a new set of
+    /// sequences.
+    ///
+    /// `prompts` contains the initial tokens of each sequence, which were
+    /// provided as input rather than generated. This is called before the
+    /// first step, and again with the whole sequence if new input is added
+    /// using [`Generator::append_prompt`](crate::Generator::append_prompt).
+    /// Filters which track state for each sequence can discard the state of
+    /// earlier sequences.
+    ///
+    /// The default implementation does nothing.
+    fn start_sequences(&self, _prompts: &[&[TokenId]]) {}
+
+    /// Called by generators after each step with the current sequences.
+    ///
+    /// Later calls to [`filter`](Self::filter) usually pass extensions of
+    /// `sequences` or their prefixes, so filters which track state for each
+    /// sequence can discard the state of other token sequences.
+    ///
+    /// The default implementation does nothing.
+    fn retain_sequences(&self, _sequences: &[&[TokenId]]) {}
 }
 
 struct TokenIdFilter<F: Fn(TokenId) -> bool> {
@@ -51,12 +77,235 @@ pub fn token_id_filter<F: Fn(TokenId) -> bool>(predicate: F) -> impl LogitsFilte
     TokenIdFilter { predicate }
 }
 
+/// Count the number of occurrences of each token in `tokens`.
+fn token_counts(tokens: &[TokenId]) -> HashMap<TokenId, usize> {
+    let mut counts = HashMap::new();
+    for &token in tokens {
+        *counts.entry(token).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Modify the logits for tokens which appear in `prev_tokens`.
+///
+/// `adjust` is called with the current logit and occurrence count for each
+/// token that has been seen before and returns the updated logit. Returns
+/// `None` if `prev_tokens` is empty.
+fn adjust_seen_tokens(
+    logits: NdTensorView<f32, 1>,
+    prev_tokens: &[TokenId],
+    adjust: impl Fn(f32, usize) -> f32,
+) -> Option<NdTensor<f32, 1>> {
+    if prev_tokens.is_empty() {
+        return None;
+    }
+
+    let mut output = logits.to_tensor();
+    for (token, count) in token_counts(prev_tokens) {
+        if let Some(logit) = output.get_mut([token as usize]) {
+            *logit = adjust(*logit, count);
+        }
+    }
+    Some(output)
+}
+
+struct RepetitionPenaltyFilter {
+    penalty: f32,
+}
+
+impl LogitsFilter for RepetitionPenaltyFilter {
+    fn filter(
+        &self,
+        logits: NdTensorView<f32, 1>,
+        prev_tokens: &[TokenId],
+    ) -> Option<NdTensor<f32, 1>> {
+        adjust_seen_tokens(logits, prev_tokens, |logit, _count| {
+            if logit < 0. {
+                logit * self.penalty
+            } else {
+                logit / self.penalty
+            }
+        })
+    }
+}
+
+/// Create a filter which penalizes tokens that have previously occurred.
+///
+/// Positive logits for previously seen tokens are divided by `penalty` and
+/// negative logits are multiplied by it, so values > 1.0 discourage
+/// repetition. See <https://arxiv.org/abs/1909.05858>.
+pub fn repetition_penalty_filter(penalty: f32) -> impl LogitsFilter {
+    assert!(penalty > 0., "penalty must be positive");
+    RepetitionPenaltyFilter { penalty }
+}
+
+struct FrequencyPenaltyFilter {
+    penalty: f32,
+}
+
+impl LogitsFilter for FrequencyPenaltyFilter {
+    fn filter(
+        &self,
+        logits: NdTensorView<f32, 1>,
+        prev_tokens: &[TokenId],
+    ) -> Option<NdTensor<f32, 1>> {
+        adjust_seen_tokens(logits, prev_tokens, |logit, count| {
+            logit - self.penalty * count as f32
+        })
+    }
+}
+
+/// Create a filter which subtracts `penalty` from the logit of each token
+/// for every time it has previously occurred.
+pub fn frequency_penalty_filter(penalty: f32) -> impl LogitsFilter {
+    FrequencyPenaltyFilter { penalty }
+}
+
+struct PresencePenaltyFilter {
+    penalty: f32,
+}
+
+impl LogitsFilter for PresencePenaltyFilter {
+    fn filter(
+        &self,
+        logits: NdTensorView<f32, 1>,
+        prev_tokens: &[TokenId],
+    ) -> Option<NdTensor<f32, 1>> {
+        adjust_seen_tokens(logits, prev_tokens, |logit, _count| logit - self.penalty)
+    }
+}
+
+/// Create a filter which subtracts `penalty` from the logit of each token
+/// that has previously occurred, regardless of how many times.
+pub fn presence_penalty_filter(penalty: f32) -> impl LogitsFilter {
+    PresencePenaltyFilter { penalty }
+}
+
+struct NoRepeatNgramFilter {
+    n: usize,
+}
+
+impl LogitsFilter for NoRepeatNgramFilter {
+    fn filter(
+        &self,
+        logits: NdTensorView<f32, 1>,
+        prev_tokens: &[TokenId],
+    ) -> Option<NdTensor<f32, 1>> {
+        if prev_tokens.len() < self.n {
+            return None;
+        }
+
+        // The n-gram that would be completed by the next token, minus its
+        // last element.
+        let prefix = &prev_tokens[prev_tokens.len() - (self.n - 1)..];
+        let banned: HashSet<TokenId> = prev_tokens
+            .windows(self.n)
+            .filter(|ngram| &ngram[..self.n - 1] == prefix)
+            .map(|ngram| ngram[self.n - 1])
+            .collect();
+
+        if banned.is_empty() {
+            return None;
+        }
+
+        let mut output = logits.to_tensor();
+        for token in banned {
+            if let Some(logit) = output.get_mut([token as usize]) {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+        Some(output)
+    }
+}
+
+/// Create a filter which prevents any n-gram of length `n` from occurring
+/// more than once, by suppressing tokens which would repeat one.
+pub fn no_repeat_ngram_filter(n: usize) -> impl LogitsFilter {
+    assert!(n > 0, "n-gram size must be at least 1");
+    NoRepeatNgramFilter { n }
+}
+
+/// A [`LogitsFilter`] which applies a sequence of filters in order.
+///
+/// The output of each filter is passed as the input to the next.
+///
+/// ```
+/// use rten_generate::filter::{
+///     repetition_penalty_filter, token_id_filter, FilterChain,
+/// };
+///
+/// let filter = FilterChain::new()
+///     .append(repetition_penalty_filter(1.2))
+///     .append(token_id_filter(|id| id != 0));
+/// ```
+#[derive(Default)]
+pub struct FilterChain<'a> {
+    filters: Vec<Box<dyn LogitsFilter + 'a>>,
+}
+
+impl<'a> FilterChain<'a> {
+    /// Create an empty filter chain.
+    ///
+    /// An empty chain returns the logits unmodified.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a filter to the end of the chain.
+    pub fn append<F: LogitsFilter + 'a>(mut self, filter: F) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Return the number of filters in the chain.
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Return true if the chain contains no filters.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+impl LogitsFilter for FilterChain<'_> {
+    fn start_sequences(&self, prompts: &[&[TokenId]]) {
+        for filter in &self.filters {
+            filter.start_sequences(prompts);
+        }
+    }
+
+    fn retain_sequences(&self, sequences: &[&[TokenId]]) {
+        for filter in &self.filters {
+            filter.retain_sequences(sequences);
+        }
+    }
+
+    fn filter(
+        &self,
+        logits: NdTensorView<f32, 1>,
+        prev_tokens: &[TokenId],
+    ) -> Option<NdTensor<f32, 1>> {
+        let mut output: Option<NdTensor<f32, 1>> = None;
+        for filter in &self.filters {
+            let input = output.as_ref().map(|o| o.view()).unwrap_or(logits);
+            if let Some(filtered) = filter.filter(input, prev_tokens) {
+                output = Some(filtered);
+            }
+        }
+        output
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use rten_tensor::prelude::*;
     use rten_tensor::NdTensor;
 
-    use super::{token_id_filter, LogitsFilter};
+    use super::{
+        frequency_penalty_filter, no_repeat_ngram_filter, presence_penalty_filter,
+        repetition_penalty_filter, token_id_filter, FilterChain, LogitsFilter,
+    };
 
     #[test]
     fn test_token_id_filter() {
@@ -74,4 +323,86 @@ mod tests {
             ]))
         );
     }
+
+    #[test]
+    fn test_repetition_penalty_filter() {
+        let logits = NdTensor::from([2., -2., 2., -2.]);
+        let filter = repetition_penalty_filter(2.);
+
+        assert_eq!(filter.filter(logits.view(), &[]), None);
+
+        // Repeated occurrences are penalized only once.
+        let output = filter.filter(logits.view(), &[0, 1, 1]);
+        assert_eq!(output, Some(NdTensor::from([1., -4., 2., -2.])));
+    }
+
+    #[test]
+    fn test_frequency_penalty_filter() {
+        let logits = NdTensor::from([1., 1., 1., 1.]);
+        let filter = frequency_penalty_filter(0.5);
+        let output = filter.filter(logits.view(), &[0, 1, 1, 1]);
+        assert_eq!(output, Some(NdTensor::from([0.5, -0.5, 1., 1.])));
+    }
+
+    #[test]
+    fn test_presence_penalty_filter() {
+        let logits = NdTensor::from([1., 1., 1., 1.]);
+        let filter = presence_penalty_filter(0.5);
+        let output = filter.filter(logits.view(), &[0, 1, 1, 1]);
+        assert_eq!(output, Some(NdTensor::from([0.5, 0.5, 1., 1.])));
+    }
+
+    #[test]
+    fn test_no_repeat_ngram_filter() {
+        let logits = NdTensor::from([0., 1., 2., 3.]);
+        let filter = no_repeat_ngram_filter(3);
+
+        // Too few tokens to form an n-gram.
+        assert_eq!(filter.filter(logits.view(), &[1, 2]), None);
+
+        // No previous n-gram starts with the last two tokens.
+        assert_eq!(filter.filter(logits.view(), &[1, 2, 3, 2]), None);
+
+        // "1 2 3" and "1 2 0" have occurred, so after "1 2" tokens 0 and 3 are
+        // suppressed.
+        let output = filter.filter(logits.view(), &[1, 2, 3, 1, 2, 0, 1, 2]);
+        assert_eq!(
+            output,
+            Some(NdTensor::from([
+                f32::NEG_INFINITY,
+                1.,
+                2.,
+                f32::NEG_INFINITY
+            ]))
+        );
+
+        // With n=1, every previously seen token is suppressed.
+        let filter = no_repeat_ngram_filter(1);
+        let output = filter.filter(logits.view(), &[2]);
+        assert_eq!(
+            output,
+            Some(NdTensor::from([0., 1., f32::NEG_INFINITY, 3.]))
+        );
+    }
+
+    #[test]
+    fn test_filter_chain() {
+        let logits = NdTensor::from([1., 1., 1., 1.]);
+
+        let empty = FilterChain::new();
+        assert!(empty.is_empty());
+        assert_eq!(empty.filter(logits.view(), &[0]), None);
+
+        let chain = FilterChain::new()
+            .append(presence_penalty_filter(0.5))
+            .append(token_id_filter(|id| id != 3))
+            .append(frequency_penalty_filter(0.25));
+        assert_eq!(chain.len(), 3);
+
+        let output = chain.filter(logits.view(), &[0, 0, 1]);
+        assert_eq!(
+            output,
+            Some(NdTensor::from([0., 0.25, 1., f32::NEG_INFINITY]))
+        );
+    }
 }
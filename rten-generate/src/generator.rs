@@ -1,20 +1,25 @@
 //! Tools to run the generation loop for an auto-regressive model.
 
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt;
 use std::ops::Range;
 
 use rten::{Dimension, Input, InputOrOutput, NodeId, Output, RunOptions};
 use rten_tensor::prelude::*;
-use rten_tensor::{NdTensor, Tensor};
+use rten_tensor::{CowNdTensor, NdTensor, NdTensorView, Tensor};
 
 #[cfg(feature = "text-decoder")]
 use rten_text::{Tokenizer, TokenizerError};
 
 use crate::filter::LogitsFilter;
-use crate::metrics::Metrics;
+use crate::kv_quant::{KvCacheFormat, QuantizedKvCache};
+use crate::logprobs::{Logprobs, TokenLogprobs};
+use crate::metrics::{Metrics, SpeculativeStats};
 use crate::model::Model;
 use crate::sampler::{ArgMaxSampler, Sampler};
+use crate::snapshot::{GeneratorSnapshot, KvCacheEntry};
+use crate::speculative::{sample_probs, verify_draft_token, Draft};
 
 #[cfg(feature = "text-decoder")]
 use crate::text_decoder::TextDecoder;
@@ -37,6 +42,12 @@ pub enum GeneratorError {
     /// An error occurred while generating the next token.
     GenerateError(Box<dyn Error>),
 
+    /// A [`GeneratorSnapshot`] is not compatible with the generator's model.
+    InvalidSnapshot(String),
+
+    /// A configuration option has an invalid value.
+    InvalidConfig(String),
+
     /// An error occurred while decoding tokens.
     #[cfg(feature = "text-decoder")]
     DecodeError(TokenizerError),
@@ -49,6 +60,8 @@ impl fmt::Display for GeneratorError {
             GeneratorError::OutputNotFound(name) => write!(f, "model output not found: {}", name),
             GeneratorError::ShapeMismatch(err) => write!(f, "shape mismatch: {}", err),
             GeneratorError::GenerateError(err) => write!(f, "generation error: {}", err),
+            GeneratorError::InvalidSnapshot(err) => write!(f, "invalid snapshot: {}", err),
+            GeneratorError::InvalidConfig(err) => write!(f, "invalid configuration: {}", err),
             #[cfg(feature = "text-decoder")]
             GeneratorError::DecodeError(err) => write!(f, "decode error: {}", err),
         }
@@ -76,7 +89,21 @@ impl std::fmt::Display for ErrorContext {
     }
 }
 
-enum KvCacheData {
+/// Wrap an error that occurred during generation with a description of the
+/// step which failed.
+pub(crate) fn wrap_error<E>(error: E, context: &str) -> GeneratorError
+where
+    E: Into<Box<dyn Error>>,
+{
+    let error_ctx = ErrorContext {
+        error: error.into(),
+        context: context.to_string(),
+    };
+    GeneratorError::GenerateError(error_ctx.into())
+}
+
+#[derive(Clone)]
+pub(crate) enum KvCacheData {
     /// Key-value cache with shape `[batch, seq_len, channels]`.
     ///
     /// In this configuration the channels for all heads are combined into the
@@ -112,7 +139,7 @@ impl KvCacheData {
     }
 
     /// Return the current sequence length of the cache.
-    fn sequence_len(&self) -> usize {
+    pub(crate) fn sequence_len(&self) -> usize {
         match self {
             KvCacheData::BatchSeqChans(data) => data.size(1),
             KvCacheData::BatchHeadSeqChans(data) => data.size(2),
@@ -131,9 +158,147 @@ impl KvCacheData {
         }
     }
 
+    /// Return an empty cache with the same number of heads and channels as
+    /// this one, for a given batch size.
+    fn empty_with_batch_size(&self, batch_size: usize) -> KvCacheData {
+        let (n_heads, size) = match self {
+            KvCacheData::BatchSeqChans(data) => (None, data.size(2)),
+            KvCacheData::BatchHeadSeqChans(data) => (Some(data.size(1)), data.size(3)),
+        };
+        KvCacheData::with_capacity(batch_size, n_heads, size, 1 /* seq_len_capacity */)
+    }
+
+    /// Return true if this cache has the batch size, number of heads and
+    /// channels expected for a model input with shape `input_shape`.
+    fn is_compatible_with(&self, batch_size: usize, input_shape: &[Dimension]) -> bool {
+        match (self, input_shape) {
+            (KvCacheData::BatchSeqChans(data), [_, _, Dimension::Fixed(size)]) => {
+                let [batch, _seq, chans] = data.shape();
+                batch == batch_size && chans == *size
+            }
+            (
+                KvCacheData::BatchHeadSeqChans(data),
+                [_, Dimension::Fixed(n_heads), _, Dimension::Fixed(size)],
+            ) => {
+                let [batch, heads, _seq, chans] = data.shape();
+                batch == batch_size && heads == *n_heads && chans == *size
+            }
+            _ => false,
+        }
+    }
+
+    /// Return the batch size of the cache.
+    fn batch_size(&self) -> usize {
+        match self {
+            KvCacheData::BatchSeqChans(data) => data.size(0),
+            KvCacheData::BatchHeadSeqChans(data) => data.size(0),
+        }
+    }
+
+    /// Create a new cache by selecting entries along the batch dimension.
+    ///
+    /// The same entry may be selected multiple times. The new buffer has
+    /// spare capacity along the sequence dimension.
+    fn select_batch(&self, indices: &[usize]) -> KvCacheData {
+        let capacity = (self.sequence_len() * 2).max(1);
+        match self {
+            KvCacheData::BatchSeqChans(data) => {
+                let [_batch, seq, chans] = data.shape();
+                let mut selected = Vec::with_capacity(indices.len() * seq * chans);
+                for &idx in indices {
+                    selected.extend(data.slice(idx).iter().copied());
+                }
+                let selected = NdTensor::from_data([indices.len(), seq, chans], selected);
+                let mut new_data =
+                    NdTensor::with_capacity([indices.len(), capacity, chans], 1 /* seq dim */);
+                new_data.append(1, &selected).expect("should have capacity");
+                KvCacheData::BatchSeqChans(new_data)
+            }
+            KvCacheData::BatchHeadSeqChans(data) => {
+                let [_batch, n_heads, seq, chans] = data.shape();
+                let mut selected = Vec::with_capacity(indices.len() * n_heads * seq * chans);
+                for &idx in indices {
+                    selected.extend(data.slice(idx).iter().copied());
+                }
+                let selected = NdTensor::from_data([indices.len(), n_heads, seq, chans], selected);
+                let mut new_data = NdTensor::with_capacity(
+                    [indices.len(), n_heads, capacity, chans],
+                    2, /* seq dim */
+                );
+                new_data.append(2, &selected).expect("should have capacity");
+                KvCacheData::BatchHeadSeqChans(new_data)
+            }
+        }
+    }
+
+    /// Convert a KV cache tensor returned by the model into a cache entry.
+    ///
+    /// `err_context` describes the cache in errors if the output has the
+    /// wrong type or number of dimensions.
+    fn from_output(output: Output, err_context: &str) -> Result<KvCacheData, GeneratorError> {
+        match output.ndim() {
+            3 => Ok(KvCacheData::BatchSeqChans(
+                output.try_into().map_err(|e| wrap_error(e, err_context))?,
+            )),
+            4 => Ok(KvCacheData::BatchHeadSeqChans(
+                output.try_into().map_err(|e| wrap_error(e, err_context))?,
+            )),
+            ndim => Err(wrap_error(
+                format!("KV cache has {} dims, expected 3 or 4", ndim),
+                err_context,
+            )),
+        }
+    }
+
+    /// Discard entries after the first `seq_len` positions.
+    ///
+    /// This retains the buffer's capacity.
+    fn truncate(&mut self, seq_len: usize) {
+        let seq_len = seq_len.min(self.sequence_len());
+        match self {
+            KvCacheData::BatchSeqChans(data) => data.clip_dim(1 /* seq dim */, 0..seq_len),
+            KvCacheData::BatchHeadSeqChans(data) => data.clip_dim(2 /* seq dim */, 0..seq_len),
+        }
+    }
+
+    /// Create a new cache with the entries in `range` removed from the
+    /// sequence dimension.
+    ///
+    /// The new buffer has space to store sequences of length `capacity`.
+    fn remove_positions(&self, range: Range<usize>, capacity: usize) -> KvCacheData {
+        let seq_len = self.sequence_len();
+        let capacity = capacity.max(seq_len - range.len());
+        match self {
+            KvCacheData::BatchSeqChans(data) => {
+                let [batch, _seq, chans] = data.shape();
+                let mut new_data =
+                    NdTensor::with_capacity([batch, capacity, chans], 1 /* seq dim */);
+                for kept in [0..range.start, range.end..seq_len] {
+                    new_data
+                        .append(1, &data.slice((.., kept)))
+                        .expect("should have capacity");
+                }
+                KvCacheData::BatchSeqChans(new_data)
+            }
+            KvCacheData::BatchHeadSeqChans(data) => {
+                let [batch, n_heads, _seq, chans] = data.shape();
+                let mut new_data = NdTensor::with_capacity(
+                    [batch, n_heads, capacity, chans],
+                    2, /* seq dim */
+                );
+                for kept in [0..range.start, range.end..seq_len] {
+                    new_data
+                        .append(2, &data.slice((.., .., kept)))
+                        .expect("should have capacity");
+                }
+                KvCacheData::BatchHeadSeqChans(new_data)
+            }
+        }
+    }
+
     /// Clone this cache into a new buffer with space to store sequences of
     /// a given size.
-    fn clone_with_capacity(&self, max_sequence_len: usize) -> KvCacheData {
+    pub(crate) fn clone_with_capacity(&self, max_sequence_len: usize) -> KvCacheData {
         let max_sequence_len = max_sequence_len.max(self.sequence_len());
         match self {
             KvCacheData::BatchSeqChans(data) => {
@@ -157,16 +322,285 @@ impl KvCacheData {
 }
 
 /// Key-value cache for a single layer of a transformer model.
-struct KvCache {
+pub(crate) struct KvCache {
     /// Input ID for this cache entry.
-    input_id: NodeId,
+    pub(crate) input_id: NodeId,
 
     /// Output ID for this cache entry.
-    output_id: NodeId,
+    pub(crate) output_id: NodeId,
 
     /// The cached keys and values. This is set to `None` during inference, as
-    /// the model temporarily takes ownership of it.
-    cache: Option<KvCacheData>,
+    /// the model temporarily takes ownership of it, or if the cache is stored
+    /// in a quantized format.
+    pub(crate) cache: Option<KvCacheData>,
+
+    /// The cached keys and values, if stored in a format other than f32.
+    quantized: Option<QuantizedKvCache>,
+}
+
+impl KvCache {
+    /// Move the cached keys and values into `model_inputs`.
+    ///
+    /// The model takes ownership of the KV-cache tensor during the run so it
+    /// can efficiently append the entry for the current step, without copying
+    /// the existing buffer.
+    ///
+    /// If the cache is quantized, a dequantized copy of the whole cache is
+    /// passed to the model instead. This is freed when the model's output is
+    /// saved with [`update`](Self::update).
+    pub(crate) fn take_input(&mut self, model_inputs: &mut Vec<(NodeId, InputOrOutput)>) {
+        let cache = match self.quantized.as_ref() {
+            Some(quantized) => Some(quantized.dequantize(quantized.sequence_len() + 1)),
+            None => self.cache.take(),
+        };
+        match cache {
+            Some(KvCacheData::BatchSeqChans(cache)) => {
+                model_inputs.push((self.input_id, cache.into()));
+            }
+            Some(KvCacheData::BatchHeadSeqChans(cache)) => {
+                model_inputs.push((self.input_id, cache.into()));
+            }
+            None => {}
+        }
+    }
+
+    /// Add a view of the cached keys and values to `model_inputs`.
+    pub(crate) fn view_input<'a>(&'a self, model_inputs: &mut Vec<(NodeId, InputOrOutput<'a>)>) {
+        match &self.cache {
+            Some(KvCacheData::BatchSeqChans(cache)) => {
+                model_inputs.push((self.input_id, cache.into()));
+            }
+            Some(KvCacheData::BatchHeadSeqChans(cache)) => {
+                model_inputs.push((self.input_id, cache.into()));
+            }
+            None => {}
+        }
+    }
+
+    /// Reorder entries along the batch dimension of the cache.
+    ///
+    /// Entry `i` in the new cache is a copy of entry `indices[i]` in the
+    /// current cache.
+    pub(crate) fn select_batch(&mut self, indices: &[usize]) {
+        if let Some(quantized) = self.quantized.as_mut() {
+            *quantized = quantized.select_batch(indices);
+        } else if let Some(cache) = self.cache.as_mut() {
+            *cache = cache.select_batch(indices);
+        }
+    }
+
+    /// Return the batch size of the cache, or `None` if the cache is
+    /// currently owned by the model.
+    pub(crate) fn batch_size(&self) -> Option<usize> {
+        if let Some(quantized) = self.quantized.as_ref() {
+            return Some(quantized.batch_size());
+        }
+        self.cache.as_ref().map(|c| c.batch_size())
+    }
+
+    /// Discard cache entries after the first `seq_len` positions.
+    pub(crate) fn truncate(&mut self, seq_len: usize) {
+        if let Some(quantized) = self.quantized.as_mut() {
+            quantized.truncate(seq_len);
+        } else if let Some(cache) = self.cache.as_mut() {
+            cache.truncate(seq_len);
+        }
+    }
+
+    /// Remove cache entries for positions in `range`.
+    ///
+    /// The cache is copied into a new buffer with space to store sequences of
+    /// length `capacity`.
+    pub(crate) fn remove_positions(&mut self, range: Range<usize>, capacity: usize) {
+        if let Some(quantized) = self.quantized.as_mut() {
+            quantized.remove_positions(range);
+        } else if let Some(cache) = self.cache.as_mut() {
+            *cache = cache.remove_positions(range, capacity);
+        }
+    }
+
+    /// Replace the cache with an empty cache for a given batch size.
+    pub(crate) fn reset(&mut self, batch_size: usize) {
+        if let Some(quantized) = self.quantized.as_mut() {
+            *quantized = quantized.empty_with_batch_size(batch_size);
+        } else if let Some(cache) = self.cache.as_mut() {
+            *cache = cache.empty_with_batch_size(batch_size);
+        }
+    }
+
+    /// Return a copy of the cached keys and values in f32 format.
+    pub(crate) fn to_data(&self) -> Option<KvCacheData> {
+        if let Some(quantized) = self.quantized.as_ref() {
+            return Some(quantized.dequantize(0));
+        }
+        self.cache
+            .as_ref()
+            .map(|cache| cache.clone_with_capacity(0))
+    }
+
+    /// Change the format used to store the cache, converting existing
+    /// entries.
+    ///
+    /// This has no effect if the cache is currently owned by the model.
+    pub(crate) fn set_format(&mut self, format: KvCacheFormat) {
+        let Some(data) = self.to_data() else {
+            return;
+        };
+        if format == KvCacheFormat::F32 {
+            self.quantized = None;
+            let capacity = (data.sequence_len() * 2).max(1);
+            self.cache = Some(data.clone_with_capacity(capacity));
+        } else {
+            self.quantized = Some(QuantizedKvCache::from_data(format, &data));
+            self.cache = None;
+        }
+    }
+
+    /// Replace the cached keys and values, converting them to the cache's
+    /// storage format.
+    pub(crate) fn set_data(&mut self, data: &KvCacheData) {
+        if let Some(quantized) = self.quantized.as_mut() {
+            *quantized = QuantizedKvCache::from_data(quantized.format(), data);
+        } else {
+            let capacity = (data.sequence_len() * 2).max(1);
+            self.cache = Some(data.clone_with_capacity(capacity));
+        }
+    }
+
+    /// Save the updated self-attention cache returned by the model.
+    ///
+    /// The KV cache tensors returned from the model should be the same as the
+    /// passed in tensors, but extended by one or more elements along the
+    /// sequence axis.
+    pub(crate) fn update(&mut self, output: Output) -> Result<(), GeneratorError> {
+        let mut kv_cache =
+            KvCacheData::from_output(output, "failed to save self-attention KV-cache")?;
+
+        // Quantize the new entries. The existing entries are assumed to be
+        // unchanged, as they were passed to the model as input.
+        if let Some(quantized) = self.quantized.as_mut() {
+            let n_cached = quantized.sequence_len();
+            if quantized.is_compatible(&kv_cache) && kv_cache.sequence_len() >= n_cached {
+                quantized.append(&kv_cache, n_cached);
+            } else {
+                *quantized = QuantizedKvCache::from_data(quantized.format(), &kv_cache);
+            }
+            return Ok(());
+        }
+
+        // Grow the KV cache buffer if it has reached the limit of its
+        // pre-allocated sequence length.
+        //
+        // Double the capacity each time to amortize the costs of copying
+        // the previous buffer.
+        if !kv_cache.has_capacity(kv_cache.sequence_len() + 1) {
+            kv_cache = kv_cache.clone_with_capacity(kv_cache.sequence_len() * 2);
+        }
+
+        self.cache = Some(kv_cache);
+        Ok(())
+    }
+
+    /// Save the cross-attention cache returned by the model.
+    pub(crate) fn update_encoder(&mut self, output: Output) -> Result<(), GeneratorError> {
+        if output.is_empty() {
+            // Optimum-exported models only return encoder KV-cache tensors
+            // on the first run and dummy empty tensors on subsequent runs.
+            // Ignore these and continue to use the value from the first run.
+            return Ok(());
+        }
+        let kv_cache = KvCacheData::from_output(output, "failed to save cross-attention KV-cache")?;
+        self.cache = Some(kv_cache);
+        Ok(())
+    }
+}
+
+/// Find the model inputs and outputs used for key-value caches.
+///
+/// Returns a tuple of `(self_attention_caches, cross_attention_caches)`, with
+/// empty f32 caches allocated for a given batch size.
+pub(crate) fn find_kv_caches(
+    model: &dyn Model,
+    model_inputs: &ModelInputsConfig,
+    batch_size: usize,
+) -> Result<(Vec<KvCache>, Vec<KvCache>), GeneratorError> {
+    let mut kv_cache = Vec::new();
+    let mut encoder_kv_cache = Vec::new();
+    for &input_id in model.input_ids() {
+        let input_info = model
+            .node_info(input_id)
+            .ok_or(GeneratorError::InputNotFound(format!(
+                "input ID {}",
+                input_id
+            )))?;
+
+        let name = input_info.name();
+
+        let Some(kv_pattern) = model_inputs
+            .kv_caches
+            .iter()
+            .find(|pat| name.starts_with(pat.input.prefix) && name.ends_with(pat.input.suffix))
+        else {
+            continue;
+        };
+
+        let (n_heads, size) = match *input_info.shape() {
+            [_, Dimension::Fixed(n_heads), _, Dimension::Fixed(size)] => (Some(n_heads), size),
+            [_, _, Dimension::Fixed(size)] => (None, size),
+            _ => {
+                return Err(GeneratorError::ShapeMismatch(format!("input \"{}\" has unexpected shape. expected (batch, past_seq_len, chans) or (batch, heads, past_seq_len, chans) where `heads` and `size` are fixed", name)));
+            }
+        };
+
+        let prefix = kv_pattern.input.prefix;
+
+        let layer_index_start = prefix.len();
+        let layer_index_end = name.len() - kv_pattern.input.suffix.len();
+        let layer_index_str = &name[layer_index_start..layer_index_end];
+        let Ok(layer_index) = layer_index_str.parse::<u32>() else {
+            continue;
+        };
+
+        let output_prefix = kv_pattern.output.prefix;
+        let output_suffix = kv_pattern.output.suffix;
+
+        let output_name = format!("{}{}{}", output_prefix, layer_index, output_suffix);
+        let output_id = model
+            .find_node(&output_name)
+            .ok_or(GeneratorError::OutputNotFound(output_name))?;
+
+        // Initial sequence length capacity for KV cache buffer.
+        //
+        // For models that execute different operations on the first vs
+        // subsequent iterations (eg. Hugging Face "merged" models with
+        // past and no-past branches) the input buffer may not be used in
+        // the first iteration. Instead we need to reserve capacity once
+        // the model returns the initial KV cache.
+        //
+        // For other simpler models the input KV cache buffer is used for
+        // all iterations, in which case we would ideally reserve capacity
+        // up-front based on the max expected sequence length.
+        let max_seq_len = 1;
+
+        let kv_cache_entry = KvCache {
+            input_id,
+            output_id,
+            cache: Some(KvCacheData::with_capacity(
+                batch_size,
+                n_heads,
+                size,
+                max_seq_len,
+            )),
+            quantized: None,
+        };
+
+        if kv_pattern.encoder {
+            encoder_kv_cache.push(kv_cache_entry);
+        } else {
+            kv_cache.push(kv_cache_entry);
+        }
+    }
+    Ok((kv_cache, encoder_kv_cache))
 }
 
 /// Specifies a pattern for the name of a key-value cache input or output.
@@ -240,6 +674,7 @@ pub struct ModelInputsConfig<'a> {
 
 /// Contains essential configuration needed for a `Generator` to execute a
 /// model, such as the roles of different inputs and outputs.
+#[derive(Default)]
 pub struct GeneratorConfig<'a> {
     /// Specifies names and roles of model inputs and outputs.
     pub model_inputs: ModelInputsConfig<'a>,
@@ -302,6 +737,34 @@ impl Default for ModelInputsConfig<'_> {
     }
 }
 
+/// Specifies how a [`Generator`] handles sequences which exceed the maximum
+/// context length set using [`with_context_limit`](Generator::with_context_limit).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// Evict the oldest tokens so that only the most recent tokens remain.
+    SlidingWindow,
+
+    /// Keep the first `n_sink` tokens and evict the oldest tokens after
+    /// them.
+    ///
+    /// Models often assign a large amount of attention to the first few
+    /// tokens, so retaining these "attention sinks" can preserve output
+    /// quality better than a plain sliding window. See
+    /// <https://arxiv.org/abs/2309.17453>.
+    AttentionSink { n_sink: usize },
+
+    /// Discard the KV cache and re-process the most recent `n_keep` tokens.
+    ///
+    /// `n_keep` must be less than the context limit. Truncation happens
+    /// every `max_len - n_keep` tokens, so a smaller value means the kept
+    /// tokens are re-processed less often.
+    ///
+    /// This is slower than the other policies when the limit is reached, as
+    /// the kept tokens are run through the model again, but the positions of
+    /// the kept tokens are encoded correctly.
+    Truncate { n_keep: usize },
+}
+
 /// Generates a token ID sequence using a transformer decoder model.
 ///
 /// This is an iterator that runs the model on each call to [`Iterator::next`]
@@ -342,6 +805,33 @@ impl Default for ModelInputsConfig<'_> {
 /// the token with the highest probability. The sampler can be configured using
 /// [`with_sampler`](Self::with_sampler).
 ///
+/// ## Speculative decoding
+///
+/// Generation can be accelerated by using a smaller draft model to propose
+/// several tokens, which are then verified by the main model in a single
+/// run. See [`with_draft`](Self::with_draft).
+///
+/// ## Log probabilities
+///
+/// Use [`logprobs`](Self::logprobs) to get the log probability of each
+/// generated token and the most likely alternatives, eg. for confidence
+/// scoring or computing perplexity.
+///
+/// ## Context length limits
+///
+/// By default the sequence processed by the model grows without limit. Use
+/// [`with_context_limit`](Self::with_context_limit) to evict old tokens
+/// once the sequence reaches the model's maximum context length.
+///
+/// ## Prefix caching
+///
+/// The KV cache and token history of a generator can be saved using
+/// [`snapshot`](Self::snapshot) and later restored into the same or a new
+/// generator using [`restore_snapshot`](Self::restore_snapshot). This avoids
+/// re-processing prompt prefixes that are shared between requests, such as a
+/// system prompt or the earlier turns of a conversation. Snapshots can also be
+/// saved to disk. See [`GeneratorSnapshot`].
+///
 /// ## Key-value caches and generation performance
 ///
 /// To enable efficient decoding, the model should have inputs and outputs for
@@ -374,6 +864,11 @@ pub struct Generator<'a> {
     /// Position ID associated with the first token in `input_ids`.
     input_offset: usize,
 
+    /// Tokens which have been processed by the model and whose keys and
+    /// values are stored in the KV cache. The length of this is always
+    /// `input_offset`.
+    cached_tokens: Vec<TokenId>,
+
     /// Input node IDs
     input_ids_input: NodeId,
 
@@ -390,6 +885,10 @@ pub struct Generator<'a> {
     /// and sampling.
     prev_tokens: Vec<u32>,
 
+    /// Whether prompt tokens have been added since the logits filter was
+    /// last notified of the start of a sequence.
+    new_prompt: bool,
+
     /// Self-attention key-value cache. This is extended on each iteration.
     kv_cache: Vec<KvCache>,
 
@@ -398,6 +897,24 @@ pub struct Generator<'a> {
     /// This is used by encoder-decoder models. The cross-attention values
     /// are computed on the first run and reused in subsequent runs.
     encoder_kv_cache: Vec<KvCache>,
+
+    /// Draft model used for speculative decoding.
+    draft: Option<Box<Draft<'a>>>,
+
+    /// Tokens which have been generated but not yet returned from the
+    /// iterator. Speculative decoding can generate several tokens per step.
+    pending_tokens: VecDeque<TokenId>,
+
+    /// Maximum sequence length and the policy for evicting tokens when it is
+    /// exceeded.
+    context_limit: Option<(usize, OverflowPolicy)>,
+
+    /// Number of alternative tokens to return log probabilities for, or
+    /// `None` if log probabilities are not computed.
+    logprobs_top_n: Option<usize>,
+
+    /// Log probabilities for each token in `pending_tokens`, if enabled.
+    pending_logprobs: VecDeque<TokenLogprobs>,
 }
 
 impl<'a> Generator<'a> {
@@ -433,10 +950,7 @@ impl<'a> Generator<'a> {
     ///  - `present.N.key` - (batch, head, past_seq_len + 1, size) updated key vector cache
     ///  - `present.N.value` - (batch, head, past_seq_len + 1, size) updated value vector cache
     pub fn from_model(model: &'a dyn Model) -> Result<Generator<'a>, GeneratorError> {
-        let config = GeneratorConfig {
-            model_inputs: ModelInputsConfig::default(),
-        };
-        Self::from_model_config(model, config)
+        Self::from_model_config(model, GeneratorConfig::default())
     }
 
     /// Create a generator that iteratively produces tokens using a model.
@@ -465,81 +979,7 @@ impl<'a> Generator<'a> {
 
         // Find inputs and corresponding outputs for key-value cache.
         let batch_size = 1;
-        let mut kv_cache = Vec::new();
-        let mut encoder_kv_cache = Vec::new();
-        for &input_id in model.input_ids() {
-            let input_info = model
-                .node_info(input_id)
-                .ok_or(GeneratorError::InputNotFound(format!(
-                    "input ID {}",
-                    input_id
-                )))?;
-
-            let name = input_info.name();
-
-            let Some(kv_pattern) = model_inputs
-                .kv_caches
-                .iter()
-                .find(|pat| name.starts_with(pat.input.prefix) && name.ends_with(pat.input.suffix))
-            else {
-                continue;
-            };
-
-            let (n_heads, size) = match *input_info.shape() {
-                [_, Dimension::Fixed(n_heads), _, Dimension::Fixed(size)] => (Some(n_heads), size),
-                [_, _, Dimension::Fixed(size)] => (None, size),
-                _ => {
-                    return Err(GeneratorError::ShapeMismatch(format!("input \"{}\" has unexpected shape. expected (batch, past_seq_len, chans) or (batch, heads, past_seq_len, chans) where `heads` and `size` are fixed", name)));
-                }
-            };
-
-            let prefix = kv_pattern.input.prefix;
-
-            let layer_index_start = prefix.len();
-            let layer_index_end = name.len() - kv_pattern.input.suffix.len();
-            let layer_index_str = &name[layer_index_start..layer_index_end];
-            let Ok(layer_index) = layer_index_str.parse::<u32>() else {
-                continue;
-            };
-
-            let output_prefix = kv_pattern.output.prefix;
-            let output_suffix = kv_pattern.output.suffix;
-
-            let output_name = format!("{}{}{}", output_prefix, layer_index, output_suffix);
-            let output_id = model
-                .find_node(&output_name)
-                .ok_or(GeneratorError::OutputNotFound(output_name))?;
-
-            // Initial sequence length capacity for KV cache buffer.
-            //
-            // For models that execute different operations on the first vs
-            // subsequent iterations (eg. Hugging Face "merged" models with
-            // past and no-past branches) the input buffer may not be used in
-            // the first iteration. Instead we need to reserve capacity once
-            // the model returns the initial KV cache.
-            //
-            // For other simpler models the input KV cache buffer is used for
-            // all iterations, in which case we would ideally reserve capacity
-            // up-front based on the max expected sequence length.
-            let max_seq_len = 1;
-
-            let kv_cache_entry = KvCache {
-                input_id,
-                output_id,
-                cache: Some(KvCacheData::with_capacity(
-                    batch_size,
-                    n_heads,
-                    size,
-                    max_seq_len,
-                )),
-            };
-
-            if kv_pattern.encoder {
-                encoder_kv_cache.push(kv_cache_entry);
-            } else {
-                kv_cache.push(kv_cache_entry);
-            }
-        }
+        let (kv_cache, encoder_kv_cache) = find_kv_caches(model, model_inputs, batch_size)?;
 
         let mut generator = Generator {
             model,
@@ -557,11 +997,18 @@ impl<'a> Generator<'a> {
             input_ids: vec![],
             input_ids_input,
             input_offset: 0,
+            cached_tokens: Vec::new(),
             logits_output,
             kv_cache,
             encoder_kv_cache,
             prev_tokens: Vec::new(),
+            new_prompt: true,
             sampler: Box::new(ArgMaxSampler {}),
+            draft: None,
+            pending_tokens: VecDeque::new(),
+            context_limit: None,
+            logprobs_top_n: None,
+            pending_logprobs: VecDeque::new(),
         };
 
         let attention_mask_input = model.find_node(model_inputs.attention_mask);
@@ -609,6 +1056,7 @@ impl<'a> Generator<'a> {
     /// [`append_prompt`](Self::append_prompt) instead.
     pub fn with_prompt(mut self, prompt: &[TokenId]) -> Self {
         self.input_ids = prompt.to_vec();
+        self.new_prompt = true;
         self
     }
 
@@ -618,6 +1066,13 @@ impl<'a> Generator<'a> {
     /// alternates between encoded user input and model-generated output.
     pub fn append_prompt(&mut self, prompt: &[TokenId]) {
         self.input_ids.extend(prompt);
+        self.new_prompt = true;
+
+        // Before generation starts, the token history is initialized from
+        // the whole prompt on the first step.
+        if !self.prev_tokens.is_empty() {
+            self.prev_tokens.extend(prompt);
+        }
     }
 
     /// Add a constant input which is provided to the model at each iteration.
@@ -653,30 +1108,615 @@ impl<'a> Generator<'a> {
         self
     }
 
-    /// Set the sampler used to sample the next token ID from the output logits.
-    pub fn with_sampler<S: Sampler + 'a>(mut self, sampler: S) -> Self {
-        self.sampler = Box::new(sampler);
-        self
+    /// Set the sampler used to sample the next token ID from the output logits.
+    pub fn with_sampler<S: Sampler + 'a>(mut self, sampler: S) -> Self {
+        self.sampler = Box::new(sampler);
+        self
+    }
+
+    /// Set execution options used when running model inference.
+    pub fn with_run_options(mut self, opts: Option<RunOptions>) -> Self {
+        self.run_options = opts;
+        self
+    }
+
+    /// Set the format used to store the self-attention KV cache between runs.
+    ///
+    /// Using a format other than the default f32 format reduces the memory
+    /// used by the cache between runs, but not the peak memory usage during a
+    /// run. See [`KvCacheFormat`].
+    pub fn with_kv_cache_format(mut self, format: KvCacheFormat) -> Self {
+        for entry in self.kv_cache.iter_mut() {
+            entry.set_format(format);
+        }
+        self
+    }
+
+    /// Limit the length of the sequence processed by the model.
+    ///
+    /// Before each run of the model, if the processed tokens plus new input
+    /// tokens exceed `max_len`, tokens are evicted from the KV cache and
+    /// pending input according to `policy`. Position inputs such as
+    /// `position_ids`, `cache_position` and `attention_mask` are computed
+    /// relative to the retained sequence, so their values never exceed
+    /// `max_len`.
+    ///
+    /// With the [`SlidingWindow`](OverflowPolicy::SlidingWindow) and
+    /// [`AttentionSink`](OverflowPolicy::AttentionSink) policies, the keys
+    /// and values of retained tokens are not recomputed, so they keep the
+    /// position encodings from when they were first processed.
+    ///
+    /// Returns an error if `max_len` is zero or the policy does not leave
+    /// room for new tokens, ie. if `n_sink` or `n_keep` is not less than
+    /// `max_len`. If a draft model is used, the policy must also retain at
+    /// least `n_draft_tokens + 1` of the most recent tokens. See
+    /// [`with_draft`](Self::with_draft).
+    pub fn with_context_limit(
+        mut self,
+        max_len: usize,
+        policy: OverflowPolicy,
+    ) -> Result<Self, GeneratorError> {
+        if max_len == 0 {
+            return Err(GeneratorError::InvalidConfig(
+                "max_len must be > 0".to_string(),
+            ));
+        }
+        match policy {
+            OverflowPolicy::SlidingWindow => {}
+            OverflowPolicy::AttentionSink { n_sink } => {
+                if n_sink >= max_len {
+                    return Err(GeneratorError::InvalidConfig(
+                        "n_sink must be less than max_len".to_string(),
+                    ));
+                }
+            }
+            OverflowPolicy::Truncate { n_keep } => {
+                if n_keep == 0 || n_keep >= max_len {
+                    return Err(GeneratorError::InvalidConfig(
+                        "n_keep must be in the range [1, max_len)".to_string(),
+                    ));
+                }
+            }
+        }
+        self.context_limit = Some((max_len, policy));
+        self.check_draft_context_limit()?;
+        Ok(self)
+    }
+
+    /// Check that the context limit does not evict pending draft tokens.
+    ///
+    /// When verifying draft tokens, the target model is run with the last
+    /// accepted token followed by the draft tokens, so these must all be
+    /// retained.
+    fn check_draft_context_limit(&self) -> Result<(), GeneratorError> {
+        let (Some((max_len, policy)), Some(draft)) = (self.context_limit, &self.draft) else {
+            return Ok(());
+        };
+        let n_recent = match policy {
+            OverflowPolicy::SlidingWindow => max_len,
+            OverflowPolicy::AttentionSink { n_sink } => max_len - n_sink,
+            OverflowPolicy::Truncate { n_keep } => n_keep,
+        };
+        if n_recent <= draft.n_tokens {
+            return Err(GeneratorError::InvalidConfig(format!(
+                "context limit must retain more than n_draft_tokens ({}) recent tokens",
+                draft.n_tokens
+            )));
+        }
+        Ok(())
+    }
+
+    /// Return an iterator which yields each generated token along with its
+    /// log probability and the log probabilities of the `top_n` most likely
+    /// tokens.
+    ///
+    /// See [`TokenLogprobs`] for details of how the log probabilities are
+    /// computed.
+    ///
+    /// If this generator uses a draft model and has already produced tokens
+    /// which have not yet been returned, those tokens are discarded and
+    /// generated again so that their log probabilities can be computed.
+    pub fn logprobs(mut self, top_n: usize) -> Logprobs<'a> {
+        if self.pending_logprobs.len() < self.pending_tokens.len() {
+            self.discard_pending_tokens();
+        }
+        self.logprobs_top_n = Some(top_n);
+        Logprobs::new(self)
+    }
+
+    /// Enable speculative decoding using a draft model.
+    ///
+    /// In each step the draft model proposes `n_draft_tokens` tokens. These
+    /// are then verified by running this generator's model once, and the
+    /// longest prefix that is consistent with this model's outputs is
+    /// accepted, along with one additional token sampled from this model.
+    /// Rejection sampling is used so that the output follows the same
+    /// distribution as decoding without a draft model.
+    ///
+    /// `draft` is a generator created for the draft model, eg. using
+    /// [`from_model`](Self::from_model). The draft model must use the same
+    /// tokenizer as this generator's model. The prompt, logits filter and
+    /// sampler of this generator are also used for the draft model, so these
+    /// do not need to be configured on `draft`. The sampler's
+    /// [`probs`](Sampler::probs) method is used to compute token
+    /// probabilities, and random choices are made using a generator obtained
+    /// from [`fork_rng`](Sampler::fork_rng), so output is reproducible if the
+    /// sampler is seeded.
+    ///
+    /// Speculative decoding is most effective when both models have KV
+    /// caches.
+    ///
+    /// Returns an error if `n_draft_tokens` is zero, or if a
+    /// [context limit](Self::with_context_limit) is set which does not retain
+    /// at least `n_draft_tokens + 1` of the most recent tokens.
+    pub fn with_draft(
+        mut self,
+        draft: Generator<'a>,
+        n_draft_tokens: usize,
+    ) -> Result<Self, GeneratorError> {
+        if n_draft_tokens == 0 {
+            return Err(GeneratorError::InvalidConfig(
+                "n_draft_tokens must be > 0".to_string(),
+            ));
+        }
+        self.draft = Some(Box::new(Draft {
+            generator: draft,
+            n_tokens: n_draft_tokens,
+            rng: None,
+            stats: SpeculativeStats::default(),
+        }));
+        self.check_draft_context_limit()?;
+        Ok(self)
+    }
+
+    /// Return statistics about tokens proposed by the draft model, if
+    /// speculative decoding is enabled.
+    ///
+    /// See [`with_draft`](Self::with_draft).
+    pub fn speculative_stats(&self) -> Option<SpeculativeStats> {
+        self.draft.as_ref().map(|draft| draft.stats)
+    }
+
+    /// Record timing metrics and speculative decoding statistics.
+    ///
+    /// This is the same as [`GeneratorUtils::profile`], except that
+    /// statistics from [`speculative_stats`](Self::speculative_stats) are
+    /// also recorded in `metrics`. This requires calling `profile` on the
+    /// generator before wrapping it in other adapters.
+    pub fn profile<'m>(
+        self,
+        metrics: &'m mut Metrics,
+    ) -> impl Iterator<Item = GeneratorItem> + use<'a, 'm> {
+        Profiler::wrap(self, metrics).with_stats(Generator::speculative_stats)
+    }
+
+    /// Run the model on the pending input tokens to populate the KV cache,
+    /// without generating a token.
+    ///
+    /// The last pending token is not processed, as the model's output for it
+    /// is needed to generate the next token. This is typically used after
+    /// setting a prompt and before calling [`snapshot`](Self::snapshot).
+    ///
+    /// This has no effect if the model does not have a KV cache.
+    pub fn prefill(&mut self) -> Result<(), GeneratorError> {
+        if self.prev_tokens.is_empty() {
+            self.prev_tokens.extend(self.input_ids.iter());
+        }
+        if self.kv_cache.is_empty() || self.input_ids.len() <= 1 {
+            return Ok(());
+        }
+
+        let last_token = self.input_ids.pop();
+        let result = self.run_model();
+        self.input_ids.extend(last_token);
+        result.map(|_| ())
+    }
+
+    /// Save the KV cache and token history of this generator.
+    ///
+    /// The snapshot can be restored into this generator, or a new generator
+    /// for the same model, using [`restore_snapshot`](Self::restore_snapshot).
+    /// Tokens that have not yet been processed by the model are included in
+    /// the snapshot but not in the KV cache. Use [`prefill`](Self::prefill)
+    /// to process a prompt before saving a snapshot.
+    ///
+    /// The snapshot does not include the generator's configuration (filters,
+    /// sampler, constant inputs etc.) or the state of a draft model.
+    pub fn snapshot(&self) -> GeneratorSnapshot {
+        let kv_cache = self
+            .kv_cache
+            .iter()
+            .map(|entry| (entry, false))
+            .chain(self.encoder_kv_cache.iter().map(|entry| (entry, true)))
+            .filter_map(|(entry, encoder)| {
+                let name = self.model.node_info(entry.input_id)?.name().to_string();
+                let data = entry.to_data()?;
+                Some(KvCacheEntry {
+                    name,
+                    encoder,
+                    data,
+                })
+            })
+            .collect();
+
+        GeneratorSnapshot {
+            tokens: self.sequence_tokens(),
+            n_cached_tokens: self.input_offset,
+            prev_tokens: self.prev_tokens.clone(),
+            kv_cache,
+        }
+    }
+
+    /// Replace the KV cache and token history of this generator with those
+    /// from a snapshot created by [`snapshot`](Self::snapshot).
+    ///
+    /// After restoring a snapshot, further input can be added using
+    /// [`append_prompt`](Self::append_prompt). Returns an error if the
+    /// snapshot was created for a model with different KV cache inputs.
+    pub fn restore_snapshot(&mut self, snapshot: &GeneratorSnapshot) -> Result<(), GeneratorError> {
+        let n_cached = snapshot.n_cached_tokens;
+        if self.kv_cache.is_empty() && n_cached > 0 {
+            return Err(GeneratorError::InvalidSnapshot(
+                "snapshot has a KV cache but model does not".to_string(),
+            ));
+        }
+
+        let mut caches = Vec::with_capacity(self.kv_cache.len() + self.encoder_kv_cache.len());
+        let entries = self
+            .kv_cache
+            .iter()
+            .map(|entry| (entry, false))
+            .chain(self.encoder_kv_cache.iter().map(|entry| (entry, true)));
+        for (entry, encoder) in entries {
+            let info = self.model.node_info(entry.input_id);
+            let name = info
+                .as_ref()
+                .map(|info| info.name().to_string())
+                .unwrap_or_default();
+            let snapshot_entry = snapshot
+                .kv_cache
+                .iter()
+                .find(|se| se.name == name && se.encoder == encoder)
+                .ok_or_else(|| {
+                    GeneratorError::InvalidSnapshot(format!("missing KV cache \"{}\"", name))
+                })?;
+
+            let batch_size = 1;
+            if info.is_some_and(|info| {
+                !snapshot_entry
+                    .data
+                    .is_compatible_with(batch_size, info.shape())
+            }) {
+                return Err(GeneratorError::InvalidSnapshot(format!(
+                    "KV cache \"{}\" has a different batch size, number of heads or head size than the model input",
+                    name
+                )));
+            }
+
+            let seq_len = snapshot_entry.data.sequence_len();
+            if !encoder && seq_len != n_cached {
+                return Err(GeneratorError::InvalidSnapshot(format!(
+                    "KV cache \"{}\" has length {}, expected {}",
+                    name, seq_len, n_cached
+                )));
+            }
+            caches.push(&snapshot_entry.data);
+        }
+
+        for (entry, cache) in self
+            .kv_cache
+            .iter_mut()
+            .chain(self.encoder_kv_cache.iter_mut())
+            .zip(caches)
+        {
+            entry.set_data(cache);
+        }
+
+        self.input_offset = n_cached;
+        self.cached_tokens = snapshot.tokens[..n_cached].to_vec();
+        self.input_ids = snapshot.tokens[n_cached..].to_vec();
+        self.prev_tokens = snapshot.prev_tokens.clone();
+        self.pending_tokens.clear();
+        self.pending_logprobs.clear();
+
+        Ok(())
+    }
+
+    /// Return the length of the token sequence processed so far, including
+    /// tokens which have not yet been passed to the model.
+    fn sequence_len(&self) -> usize {
+        self.input_offset + self.input_ids.len()
+    }
+
+    /// Return the token sequence processed so far, including tokens which
+    /// have not yet been passed to the model.
+    fn sequence_tokens(&self) -> Vec<TokenId> {
+        [self.cached_tokens.as_slice(), self.input_ids.as_slice()].concat()
+    }
+
+    /// Discard all but the first `len` tokens of the sequence, rolling back
+    /// the KV cache if needed.
+    fn truncate_sequence(&mut self, len: usize) {
+        if len < self.input_offset {
+            for entry in self.kv_cache.iter_mut() {
+                entry.truncate(len);
+            }
+            self.input_offset = len;
+            self.cached_tokens.truncate(len);
+            self.input_ids.clear();
+        } else {
+            self.input_ids.truncate(len - self.input_offset);
+        }
+    }
+
+    /// Roll back the sequence to remove tokens which have been generated but
+    /// not yet returned.
+    fn discard_pending_tokens(&mut self) {
+        let n_pending = self.pending_tokens.len();
+        if n_pending == 0 {
+            return;
+        }
+        self.pending_tokens.clear();
+        self.pending_logprobs.clear();
+        self.prev_tokens
+            .truncate(self.prev_tokens.len() - n_pending);
+
+        // The last token that was returned has not been passed to the model
+        // yet, so keep it as input for the next step.
+        let tokens = self.sequence_tokens();
+        let len = tokens.len() - n_pending;
+        self.truncate_sequence(len - 1);
+        self.input_ids.push(tokens[len - 1]);
+    }
+
+    /// Evict tokens from the KV cache and pending input if the sequence
+    /// exceeds the context limit.
+    fn apply_context_limit(&mut self) {
+        let Some((max_len, policy)) = self.context_limit else {
+            return;
+        };
+        let seq_len = self.sequence_len();
+        if seq_len <= max_len {
+            return;
+        }
+        let n_evict = seq_len - max_len;
+
+        let evict = match policy {
+            OverflowPolicy::SlidingWindow => 0..n_evict,
+            OverflowPolicy::AttentionSink { n_sink } => n_sink..n_sink + n_evict,
+            OverflowPolicy::Truncate { n_keep } => {
+                let tokens = self.sequence_tokens();
+                self.truncate_sequence(0);
+                self.input_ids = tokens[seq_len - n_keep..].to_vec();
+                return;
+            }
+        };
+
+        // Evict tokens from the KV cache. The cache needs space for one
+        // extra entry so that it doesn't need to be re-allocated after the
+        // next run.
+        let n_cached = self.input_offset;
+        let cache_evict = evict.start.min(n_cached)..evict.end.min(n_cached);
+        if !cache_evict.is_empty() {
+            for entry in self.kv_cache.iter_mut() {
+                entry.remove_positions(cache_evict.clone(), max_len + 1);
+            }
+            self.cached_tokens.drain(cache_evict.clone());
+            self.input_offset -= cache_evict.len();
+        }
+
+        // Evict tokens from the pending input.
+        let input_evict = evict.start.max(n_cached) - n_cached..evict.end.max(n_cached) - n_cached;
+        self.input_ids.drain(input_evict);
+    }
+
+    /// Apply the logits filter to model outputs.
+    fn filter_logits<'l>(
+        &self,
+        logits: NdTensorView<'l, f32, 1>,
+        prev_tokens: &[TokenId],
+    ) -> CowNdTensor<'l, f32, 1> {
+        self.logits_filter
+            .as_ref()
+            .and_then(|f| f.filter(logits, prev_tokens))
+            .map(|l| l.into_cow())
+            .unwrap_or(logits.as_cow())
+    }
+
+    /// Apply the logits filter and return the probabilities of each token
+    /// according to the sampler.
+    fn token_probs(
+        &self,
+        logits: NdTensorView<f32, 1>,
+        prev_tokens: &[TokenId],
+    ) -> NdTensor<f32, 1> {
+        let filtered_logits = self.filter_logits(logits, prev_tokens);
+        self.sampler.probs(filtered_logits.view())
+    }
+
+    /// Generate the next token and return it with its log probabilities.
+    pub(crate) fn generate_next_token_logprobs(&mut self) -> Result<TokenLogprobs, GeneratorError> {
+        self.generate_next_token()?;
+        Ok(self
+            .pending_logprobs
+            .pop_front()
+            .expect("should have computed log probabilities"))
+    }
+
+    /// Run the model and generate the next token.
+    ///
+    /// If log probabilities are enabled, they are added to `pending_logprobs`
+    /// and should be removed by the caller.
+    fn generate_next_token(&mut self) -> Result<TokenId, GeneratorError> {
+        if let Some(token) = self.pending_tokens.pop_front() {
+            return Ok(token);
+        }
+
+        if self.prev_tokens.is_empty() {
+            self.prev_tokens.extend(self.input_ids.iter());
+        }
+
+        // Tokens up to this point were provided as input, so should not be
+        // constrained by filters as if they were generated.
+        if self.new_prompt {
+            if let Some(filter) = &self.logits_filter {
+                filter.start_sequences(&[&self.prev_tokens]);
+            }
+            self.new_prompt = false;
+        }
+
+        if let Some(mut draft) = self.draft.take() {
+            let result = self.generate_speculative(&mut draft);
+            self.draft = Some(draft);
+            result?;
+            return Ok(self
+                .pending_tokens
+                .pop_front()
+                .expect("should have generated a token"));
+        }
+
+        let logits = self.run_model()?;
+
+        // Apply filtering to model outputs.
+        let filtered_logits = self.filter_logits(logits.slice((0, -1)), &self.prev_tokens);
+
+        // Sample output token.
+        let next_id = self.sampler.sample(filtered_logits.view());
+
+        if let Some(top_n) = self.logprobs_top_n {
+            self.pending_logprobs.push_back(TokenLogprobs::from_logits(
+                filtered_logits.view(),
+                next_id,
+                top_n,
+            ));
+        }
+
+        // Update the token IDs for the next iteration.
+        self.prev_tokens.push(next_id);
+        self.input_ids.push(next_id);
+        self.retain_filter_sequence();
+
+        Ok(next_id)
     }
 
-    /// Set execution options used when running model inference.
-    pub fn with_run_options(mut self, opts: Option<RunOptions>) -> Self {
-        self.run_options = opts;
-        self
+    /// Generate tokens using speculative decoding and add them to
+    /// `pending_tokens`.
+    fn generate_speculative(&mut self, draft: &mut Draft<'a>) -> Result<(), GeneratorError> {
+        // Pass tokens that were added since the last step (eg. the prompt) to
+        // the draft model. If the sequences have diverged, eg. because a
+        // snapshot was restored or tokens were evicted, roll back the draft
+        // model first. At least one token is always passed, as the draft
+        // model needs an input to predict from.
+        let tokens = self.sequence_tokens();
+        let n_common = draft
+            .generator
+            .sequence_tokens()
+            .iter()
+            .zip(&tokens)
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(tokens.len().saturating_sub(1));
+        draft.generator.truncate_sequence(n_common);
+        draft.generator.input_ids.extend(&tokens[n_common..]);
+
+        let rng = draft.rng.get_or_insert_with(|| self.sampler.fork_rng());
+
+        // Propose tokens using the draft model.
+        let mut context = self.prev_tokens.clone();
+        let mut draft_tokens = Vec::with_capacity(draft.n_tokens);
+        let mut draft_probs = Vec::with_capacity(draft.n_tokens);
+        for _ in 0..draft.n_tokens {
+            let logits = draft.generator.run_model()?;
+            let probs = self.token_probs(logits.slice((0, -1)), &context);
+            let token = sample_probs(rng, probs.view());
+            draft.generator.input_ids.push(token);
+            context.push(token);
+            draft_tokens.push(token);
+            draft_probs.push(probs);
+        }
+
+        // Verify the proposed tokens by running the target model once.
+        self.input_ids.extend(&draft_tokens);
+        let logits = self.run_model()?;
+        let first_pos = logits.size(1) - draft_tokens.len() - 1;
+
+        let mut accepted = Vec::with_capacity(draft_tokens.len() + 1);
+        let mut context = self.prev_tokens.clone();
+        let mut replacement = None;
+        for (i, (&token, q)) in draft_tokens.iter().zip(&draft_probs).enumerate() {
+            let p = self.token_probs(logits.slice((0, first_pos + i)), &context);
+            replacement = verify_draft_token(rng, token, p.view(), q.view());
+            if replacement.is_some() {
+                break;
+            }
+            accepted.push(token);
+            context.push(token);
+        }
+        let n_accepted = accepted.len();
+
+        // If all draft tokens were accepted, sample an extra token from the
+        // target model's final output.
+        let next_token = replacement.unwrap_or_else(|| {
+            let p = self.token_probs(logits.slice((0, -1)), &context);
+            sample_probs(rng, p.view())
+        });
+        accepted.push(next_token);
+
+        if let Some(top_n) = self.logprobs_top_n {
+            let mut context = self.prev_tokens.clone();
+            for (i, &token) in accepted.iter().enumerate() {
+                let filtered_logits =
+                    self.filter_logits(logits.slice((0, first_pos + i)), &context);
+                self.pending_logprobs.push_back(TokenLogprobs::from_logits(
+                    filtered_logits.view(),
+                    token,
+                    top_n,
+                ));
+                context.push(token);
+            }
+        }
+
+        // Roll back both models to remove rejected tokens, then add the new
+        // token for the next step.
+        //
+        // The lengths are computed relative to the current sequences, as
+        // tokens may have been evicted from the start if a context limit is
+        // set.
+        let n_rejected = draft_tokens.len() - n_accepted;
+        self.truncate_sequence(self.sequence_len() - n_rejected);
+        draft
+            .generator
+            .truncate_sequence(draft.generator.sequence_len() - n_rejected);
+        self.input_ids.push(next_token);
+        draft.generator.input_ids.push(next_token);
+
+        draft.stats.draft_tokens += draft_tokens.len();
+        draft.stats.accepted_tokens += n_accepted;
+        draft.stats.target_runs += 1;
+
+        self.prev_tokens.extend(&accepted);
+        self.pending_tokens.extend(accepted);
+        self.retain_filter_sequence();
+
+        Ok(())
     }
 
-    /// Run the model and generate the next token.
-    fn generate_next_token(&mut self) -> Result<TokenId, GeneratorError> {
-        fn wrap_error<E>(error: E, context: &str) -> GeneratorError
-        where
-            E: Into<Box<dyn Error>>,
-        {
-            let error_ctx = ErrorContext {
-                error: error.into(),
-                context: context.to_string(),
-            };
-            GeneratorError::GenerateError(error_ctx.into())
+    /// Notify the logits filter of the current sequence after a step, so it
+    /// can free state for discarded tokens.
+    fn retain_filter_sequence(&self) {
+        if let Some(filter) = &self.logits_filter {
+            filter.retain_sequences(&[&self.prev_tokens]);
         }
+    }
+
+    /// Run the model using the pending input tokens and update the KV cache.
+    ///
+    /// Returns the logits for each input position, with shape
+    /// `[batch, seq, vocab]`. If the model has a KV cache, the pending input
+    /// tokens are cleared. Otherwise they are retained, as models without a
+    /// KV cache must be passed the full sequence on each run.
+    fn run_model(&mut self) -> Result<NdTensor<f32, 3>, GeneratorError> {
+        self.apply_context_limit();
 
         let batch_size = 1;
         let input_ids: NdTensor<i32, 2> = self
@@ -723,33 +1763,14 @@ impl<'a> Generator<'a> {
             }));
         }
 
-        // Add key-value cache from previous run. The model takes ownership
-        // of the KV-cache tensor during the run so it can efficiently append
-        // the entry for the current step, without copying the existing buffer.
+        // Add key-value cache from previous run.
         for entry in self.kv_cache.iter_mut() {
-            let cache = entry.cache.take();
-            match cache {
-                Some(KvCacheData::BatchSeqChans(cache)) => {
-                    model_inputs.push((entry.input_id, cache.into()));
-                }
-                Some(KvCacheData::BatchHeadSeqChans(cache)) => {
-                    model_inputs.push((entry.input_id, cache.into()));
-                }
-                None => {}
-            }
+            entry.take_input(&mut model_inputs);
         }
 
         // Add cross-attention key-value cache.
         for entry in self.encoder_kv_cache.iter() {
-            match &entry.cache {
-                Some(KvCacheData::BatchSeqChans(cache)) => {
-                    model_inputs.push((entry.input_id, cache.into()));
-                }
-                Some(KvCacheData::BatchHeadSeqChans(cache)) => {
-                    model_inputs.push((entry.input_id, cache.into()));
-                }
-                None => {}
-            }
+            entry.view_input(&mut model_inputs);
         }
 
         // Run the model and collect outputs and updated KV cache.
@@ -764,99 +1785,28 @@ impl<'a> Generator<'a> {
             .run(model_inputs, &model_outputs, self.run_options.clone())
             .map_err(|e| wrap_error(e, "failed to run model"))?;
 
-        // Apply filtering to model outputs.
-        if self.prev_tokens.is_empty() {
-            self.prev_tokens.extend(self.input_ids.iter());
-        }
         let logits: NdTensor<f32, 3> = outputs
             .remove(0)
             .try_into()
             .map_err(|e| wrap_error(e, "failed to extract logits from model outputs"))?;
-        let last_logits = logits.slice((0, -1));
-        let filtered_logits = self
-            .logits_filter
-            .as_ref()
-            .and_then(|f| f.filter(last_logits, &self.prev_tokens))
-            .map(|l| l.into_cow())
-            .unwrap_or(last_logits.as_cow());
-
-        // Sample output token.
-        let next_id = self.sampler.sample(filtered_logits.view());
 
         // Update the self-attention key-value cache.
-        //
-        // The KV cache tensors returned from the model should be the same as
-        // the passed in tensors, but extended by one element along the sequence
-        // axis.
         for cache_entry in self.kv_cache.iter_mut() {
-            let output = outputs.remove(0);
-
-            let err_context = "failed to save self-attention KV-cache";
-            let mut kv_cache = match output.ndim() {
-                3 => KvCacheData::BatchSeqChans(
-                    output.try_into().map_err(|e| wrap_error(e, err_context))?,
-                ),
-                4 => KvCacheData::BatchHeadSeqChans(
-                    output.try_into().map_err(|e| wrap_error(e, err_context))?,
-                ),
-                ndim => {
-                    return Err(wrap_error(
-                        format!("KV cache has {} dims, expected 3 or 4", ndim),
-                        err_context,
-                    ));
-                }
-            };
-
-            // Grow the KV cache buffer if it has reached the limit of its
-            // pre-allocated sequence length.
-            //
-            // Double the capacity each time to amortize the costs of copying
-            // the previous buffer.
-            if !kv_cache.has_capacity(kv_cache.sequence_len() + 1) {
-                kv_cache = kv_cache.clone_with_capacity(kv_cache.sequence_len() * 2);
-            }
-
-            cache_entry.cache = Some(kv_cache);
+            cache_entry.update(outputs.remove(0))?;
         }
 
         // Update the cross-attention key-value cache.
         for cache_entry in self.encoder_kv_cache.iter_mut() {
-            let output = outputs.remove(0);
-            if output.is_empty() {
-                // Optimum-exported models only return encoder KV-cache tensors
-                // on the first run and dummy empty tensors on subsequent runs.
-                // Ignore these and continue to use the value from the first run.
-                continue;
-            }
-
-            let err_context = "failed to save cross-attention KV-cache";
-            let kv_cache = match output.ndim() {
-                3 => KvCacheData::BatchSeqChans(
-                    output.try_into().map_err(|e| wrap_error(e, err_context))?,
-                ),
-                4 => KvCacheData::BatchHeadSeqChans(
-                    output.try_into().map_err(|e| wrap_error(e, err_context))?,
-                ),
-                ndim => {
-                    return Err(wrap_error(
-                        format!("KV cache has {} dims, expected 3 or 4", ndim),
-                        err_context,
-                    ));
-                }
-            };
-            cache_entry.cache = Some(kv_cache);
+            cache_entry.update_encoder(outputs.remove(0))?;
         }
 
-        // Update the token IDs and sequence offset for the next iteration.
-        self.prev_tokens.push(next_id);
+        // Update the sequence offset for the next iteration.
         if !self.kv_cache.is_empty() {
             self.input_offset += self.input_ids.len();
-            self.input_ids = vec![next_id];
-        } else {
-            self.input_ids.push(next_id);
+            self.cached_tokens.append(&mut self.input_ids);
         }
 
-        Ok(next_id)
+        Ok(logits)
     }
 }
 
@@ -884,6 +1834,9 @@ pub trait GeneratorUtils: Iterator<Item = GeneratorItem> + Sized {
     }
 
     /// Decode the tokens to text using a tokenizer.
+    ///
+    /// To stop generation when the text contains a given string, use
+    /// [`TextDecoder::stop_on_strings`].
     #[cfg(feature = "text-decoder")]
     fn decode(self, tokenizer: &Tokenizer) -> TextDecoder<Self> {
         TextDecoder::wrap(self, tokenizer)
@@ -904,11 +1857,24 @@ impl<I: Iterator<Item = GeneratorItem>> GeneratorUtils for I {}
 struct Profiler<'a, G: Iterator> {
     generator: G,
     metrics: &'a mut Metrics,
+
+    /// Function which returns speculative decoding statistics from the
+    /// generator.
+    stats: Option<fn(&G) -> Option<SpeculativeStats>>,
 }
 
 impl<'a, G: Iterator> Profiler<'a, G> {
     fn wrap(generator: G, metrics: &'a mut Metrics) -> Profiler<'a, G> {
-        Profiler { generator, metrics }
+        Profiler {
+            generator,
+            metrics,
+            stats: None,
+        }
+    }
+
+    fn with_stats(mut self, stats: fn(&G) -> Option<SpeculativeStats>) -> Self {
+        self.stats = Some(stats);
+        self
     }
 }
 
@@ -919,6 +1885,9 @@ impl<G: Iterator> Iterator for Profiler<'_, G> {
         let start = std::time::Instant::now();
         let item = self.generator.next()?;
         self.metrics.add_step_duration(start.elapsed());
+        if let Some(stats) = self.stats.and_then(|stats| stats(&self.generator)) {
+            self.metrics.set_speculative_stats(stats);
+        }
         Some(item)
     }
 }
@@ -933,11 +1902,15 @@ mod tests {
     use rten::{Dimension, InputOrOutput, NodeId, Output, RunOptions};
     use rten_tensor::prelude::*;
     use rten_tensor::{NdTensor, NdTensorView};
+    use rten_testing::TestCases;
 
-    use super::{Generator, GeneratorUtils};
+    use super::{Generator, GeneratorError, GeneratorUtils, KvCacheData, OverflowPolicy};
     use crate::filter::LogitsFilter;
+    use crate::kv_quant::KvCacheFormat;
     use crate::metrics::Metrics;
     use crate::model::{Model, NodeInfo};
+    use crate::sampler::TopKSampler;
+    use crate::snapshot::GeneratorSnapshot;
 
     struct FakeModel {
         nodes: Vec<NodeInfo>,
@@ -1396,7 +2369,39 @@ mod tests {
             &output_token_ids,
         );
 
-        let mut generator = Generator::from_model(&model)?.with_prompt(&prompt);
+        // Filter which records the token history and the sequences it is
+        // notified about.
+        #[derive(Default)]
+        struct RecordFilter {
+            prev_tokens: RefCell<Vec<u32>>,
+            prompts: RefCell<Vec<Vec<u32>>>,
+            retained: RefCell<Vec<u32>>,
+        }
+        impl LogitsFilter for &RecordFilter {
+            fn start_sequences(&self, prompts: &[&[u32]]) {
+                assert_eq!(prompts.len(), 1);
+                self.prompts.borrow_mut().push(prompts[0].to_vec());
+            }
+
+            fn retain_sequences(&self, sequences: &[&[u32]]) {
+                assert_eq!(sequences.len(), 1);
+                self.retained.replace(sequences[0].to_vec());
+            }
+
+            fn filter(
+                &self,
+                _logits: NdTensorView<f32, 1>,
+                prev_tokens: &[u32],
+            ) -> Option<NdTensor<f32, 1>> {
+                self.prev_tokens.replace(prev_tokens.to_vec());
+                None
+            }
+        }
+        let filter = RecordFilter::default();
+
+        let mut generator = Generator::from_model(&model)?
+            .with_prompt(&prompt)
+            .with_logits_filter(&filter);
 
         generator.next();
         generator.append_prompt(&[100]);
@@ -1404,6 +2409,23 @@ mod tests {
         generator.append_prompt(&[101, 102]);
         generator.next();
 
+        // The token history includes the appended inputs.
+        assert_eq!(
+            filter.prev_tokens.borrow().as_slice(),
+            [99, 0, 100, 1, 101, 102]
+        );
+
+        // The filter is notified when inputs are added, so they are not
+        // treated as generated tokens, and of the sequence after each step.
+        assert_eq!(
+            filter.prompts.borrow().as_slice(),
+            [vec![99], vec![99, 0, 100], vec![99, 0, 100, 1, 101, 102]]
+        );
+        assert_eq!(
+            filter.retained.borrow().as_slice(),
+            [99, 0, 100, 1, 101, 102, 2]
+        );
+
         let input_id = model.find_node("input_ids").unwrap();
 
         // The input to the first step is just the prompt.
@@ -1567,4 +2589,641 @@ mod tests {
 
         Ok(())
     }
+
+    /// Fake model which predicts the next token using a lookup table.
+    ///
+    /// Unlike [`FakeModel`], the outputs are computed from the inputs, so
+    /// this can be used with any sequence of inputs. The KV cache entries for
+    /// each position are filled with the token ID at that position.
+    struct LookupModel {
+        nodes: Vec<NodeInfo>,
+        input_ids: Vec<NodeId>,
+
+        // Map of token ID to next token ID.
+        next_token: Vec<u32>,
+
+        // Number of inference runs.
+        runs: Cell<usize>,
+
+        // Number of input tokens processed across all runs.
+        tokens_processed: Cell<usize>,
+
+        // Maximum sequence length, including KV cache, across all runs.
+        max_seq_len: Cell<usize>,
+
+        // Maximum position ID across all runs.
+        max_position_id: Cell<i32>,
+    }
+
+    impl LookupModel {
+        const N_HEADS: usize = 2;
+        const N_EMBED: usize = 4;
+
+        fn new(next_token: &[u32], kv_cache: bool) -> LookupModel {
+            let mut inputs = vec![
+                NodeInfo::from_name_shape("input_ids", &[]),
+                NodeInfo::from_name_shape("position_ids", &[]),
+            ];
+            let mut outputs = vec![NodeInfo::from_name_shape("logits", &[])];
+
+            if kv_cache {
+                let dims = [
+                    Dimension::Symbolic("batch".to_string()),
+                    Dimension::Fixed(Self::N_HEADS),
+                    Dimension::Symbolic("seq".to_string()),
+                    Dimension::Fixed(Self::N_EMBED),
+                ];
+                for name in ["key", "value"] {
+                    let input_name = format!("past_key_values.0.{}", name);
+                    inputs.push(NodeInfo::from_name_shape(&input_name, &dims));
+                    let output_name = format!("present.0.{}", name);
+                    outputs.push(NodeInfo::from_name_shape(&output_name, &dims));
+                }
+            }
+
+            let input_ids = (0..inputs.len())
+                .map(|id| NodeId::from_u32(id as u32))
+                .collect();
+
+            LookupModel {
+                nodes: [inputs, outputs].concat(),
+                input_ids,
+                next_token: next_token.to_vec(),
+                runs: Cell::new(0),
+                tokens_processed: Cell::new(0),
+                max_seq_len: Cell::new(0),
+                max_position_id: Cell::new(0),
+            }
+        }
+    }
+
+    impl Model for LookupModel {
+        fn find_node(&self, name: &str) -> Option<NodeId> {
+            self.nodes
+                .iter()
+                .position(|info| info.name() == name)
+                .map(|pos| NodeId::from_u32(pos as u32))
+        }
+
+        fn node_info(&self, id: NodeId) -> Option<NodeInfo> {
+            self.nodes.get(id.as_usize()).cloned()
+        }
+
+        fn input_ids(&self) -> &[NodeId] {
+            &self.input_ids
+        }
+
+        fn run(
+            &self,
+            inputs: Vec<(NodeId, InputOrOutput)>,
+            outputs: &[NodeId],
+            _opts: Option<RunOptions>,
+        ) -> Result<Vec<Output>, Box<dyn Error>> {
+            self.runs.set(self.runs.get() + 1);
+
+            let mut token_ids: Option<NdTensor<i32, 2>> = None;
+            let mut past: Option<NdTensor<f32, 4>> = None;
+            for (id, input) in inputs {
+                let input = input.to_output();
+                match self.node_info(id).unwrap().name() {
+                    "input_ids" => token_ids = Some(input.try_into()?),
+                    "position_ids" => {
+                        let position_ids: NdTensor<i32, 2> = input.try_into()?;
+                        let max_pos = position_ids.iter().copied().max().unwrap_or(0);
+                        self.max_position_id
+                            .set(self.max_position_id.get().max(max_pos));
+                    }
+                    _ => past = Some(input.try_into()?),
+                }
+            }
+            let token_ids = token_ids.ok_or("missing input_ids")?;
+            self.tokens_processed
+                .set(self.tokens_processed.get() + token_ids.size(1));
+
+            // Extend the KV cache with the token IDs of the inputs.
+            let cached_ids: Vec<f32> = past
+                .iter()
+                .flat_map(|past| past.slice((0, 0, .., 0)).to_vec())
+                .chain(token_ids.iter().map(|id| *id as f32))
+                .collect();
+            self.max_seq_len
+                .set(self.max_seq_len.get().max(cached_ids.len()));
+            let present = NdTensor::from_fn(
+                [1, Self::N_HEADS, cached_ids.len(), Self::N_EMBED],
+                |[_, _, pos, _]| cached_ids[pos],
+            );
+
+            let next_ids: Vec<u32> = token_ids
+                .iter()
+                .map(|id| self.next_token[*id as usize])
+                .collect();
+            let logits = generate_logits(self.next_token.len(), &next_ids);
+
+            let result = outputs
+                .iter()
+                .map(|id| match self.node_info(*id).unwrap().name() {
+                    "logits" => Output::FloatTensor(logits.clone().into()),
+                    _ => Output::FloatTensor(present.clone().into()),
+                })
+                .collect();
+            Ok(result)
+        }
+
+        fn partial_run(
+            &self,
+            _inputs: Vec<(NodeId, InputOrOutput)>,
+            _outputs: &[NodeId],
+            _opts: Option<RunOptions>,
+        ) -> Result<Vec<(NodeId, Output)>, Box<dyn Error>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn test_speculative_decoding() -> Result<(), Box<dyn Error>> {
+        #[derive(Debug)]
+        struct Case {
+            kv_cache: bool,
+            draft_next_token: [u32; 6],
+            n_draft_tokens: usize,
+            expected_accepted: usize,
+        }
+
+        let target_next_token = [1, 2, 3, 4, 5, 0];
+        let prompt = [0, 1];
+        let n_tokens = 12;
+
+        // Sequence generated by the target model alone.
+        let expected_tokens: Vec<u32> = (0..n_tokens).map(|i| (i as u32 + 2) % 6).collect();
+
+        let cases = [
+            // Draft model agrees with target model.
+            Case {
+                kv_cache: true,
+                draft_next_token: target_next_token,
+                n_draft_tokens: 3,
+                expected_accepted: 9,
+            },
+            Case {
+                kv_cache: false,
+                draft_next_token: target_next_token,
+                n_draft_tokens: 3,
+                expected_accepted: 9,
+            },
+            // Draft model disagrees with target model after token 3.
+            Case {
+                kv_cache: true,
+                draft_next_token: [1, 2, 3, 0, 5, 0],
+                n_draft_tokens: 4,
+                expected_accepted: 10,
+            },
+            // Draft model always predicts token 0.
+            Case {
+                kv_cache: true,
+                draft_next_token: [0; 6],
+                n_draft_tokens: 4,
+                expected_accepted: 2,
+            },
+        ];
+
+        for Case {
+            kv_cache,
+            draft_next_token,
+            n_draft_tokens,
+            expected_accepted,
+        } in cases
+        {
+            let model = LookupModel::new(&target_next_token, kv_cache);
+            let draft_model = LookupModel::new(&draft_next_token, kv_cache);
+
+            let mut generator = Generator::from_model(&model)?
+                .with_prompt(&prompt)
+                .with_draft(Generator::from_model(&draft_model)?, n_draft_tokens)?;
+
+            let output_tokens: Vec<_> = generator
+                .by_ref()
+                .take(n_tokens)
+                .map(|id| id.expect("generation failed"))
+                .collect();
+            assert_eq!(output_tokens, expected_tokens);
+
+            let stats = generator.speculative_stats().unwrap();
+            assert_eq!(stats.target_runs, model.runs.get());
+            assert_eq!(stats.draft_tokens, stats.target_runs * n_draft_tokens);
+            assert_eq!(stats.accepted_tokens, expected_accepted);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_restore() -> Result<(), Box<dyn Error>> {
+        let next_token = [1, 2, 3, 4, 5, 0];
+        let prompt = [0, 1, 2, 3];
+        let model = LookupModel::new(&next_token, true /* kv_cache */);
+
+        // Process the prompt and save a snapshot.
+        let mut generator = Generator::from_model(&model)?.with_prompt(&prompt);
+        generator.prefill()?;
+        assert_eq!(model.tokens_processed.get(), prompt.len() - 1);
+
+        let snapshot = generator.snapshot();
+        assert_eq!(snapshot.tokens(), prompt);
+        assert_eq!(snapshot.cached_len(), prompt.len() - 1);
+
+        let expected: Vec<_> = generator.by_ref().take(4).collect::<Result<_, _>>()?;
+        assert_eq!(expected, [4, 5, 0, 1]);
+
+        // Restore the snapshot into a new generator. The prompt prefix should
+        // not be processed again.
+        let serialized = {
+            let mut buf = Vec::new();
+            snapshot.write(&mut buf)?;
+            buf
+        };
+        for snapshot in [
+            snapshot.clone(),
+            GeneratorSnapshot::read(&mut serialized.as_slice())?,
+        ] {
+            let model = LookupModel::new(&next_token, true /* kv_cache */);
+            let mut generator = Generator::from_model(&model)?;
+            generator.restore_snapshot(&snapshot)?;
+            let output: Vec<_> = generator.by_ref().take(4).collect::<Result<_, _>>()?;
+            assert_eq!(output, expected);
+            assert_eq!(model.tokens_processed.get(), 4);
+
+            // Restore into the same generator and append more input.
+            generator.restore_snapshot(&snapshot)?;
+            generator.append_prompt(&[0]);
+            let output: Vec<_> = generator.take(2).collect::<Result<_, _>>()?;
+            assert_eq!(output, [1, 2]);
+        }
+
+        // Snapshots cannot be restored into models with different KV caches.
+        let model = LookupModel::new(&next_token, false /* kv_cache */);
+        let mut generator = Generator::from_model(&model)?;
+        let result = generator.restore_snapshot(&snapshot);
+        assert!(matches!(result, Err(GeneratorError::InvalidSnapshot(_))));
+
+        // Snapshots cannot be restored if the KV cache shape doesn't match
+        // the model.
+        let mut wrong_shape = snapshot.clone();
+        for entry in &mut wrong_shape.kv_cache {
+            if let KvCacheData::BatchHeadSeqChans(data) = &entry.data {
+                let [batch, heads, seq, chans] = data.shape();
+                entry.data =
+                    KvCacheData::BatchHeadSeqChans(NdTensor::zeros([batch, heads + 1, seq, chans]));
+            }
+        }
+        let model = LookupModel::new(&next_token, true /* kv_cache */);
+        let mut generator = Generator::from_model(&model)?;
+        let result = generator.restore_snapshot(&wrong_shape);
+        assert!(matches!(result, Err(GeneratorError::InvalidSnapshot(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_context_limit() {
+        #[derive(Debug)]
+        struct Case {
+            policy: OverflowPolicy,
+
+            // Token sequence retained after generation.
+            expected_tokens: Vec<u32>,
+
+            // Total number of tokens processed by the model.
+            expected_tokens_processed: usize,
+        }
+
+        let next_token = [1, 2, 3, 4, 5, 0];
+        let prompt = [0, 1, 2, 3];
+        let max_len = 6;
+        let n_tokens = 8;
+
+        let cases = [
+            Case {
+                policy: OverflowPolicy::SlidingWindow,
+                expected_tokens: [5, 0, 1, 2, 3, 4, 5].into(),
+                expected_tokens_processed: prompt.len() + n_tokens - 1,
+            },
+            Case {
+                policy: OverflowPolicy::AttentionSink { n_sink: 2 },
+                expected_tokens: [0, 1, 1, 2, 3, 4, 5].into(),
+                expected_tokens_processed: prompt.len() + n_tokens - 1,
+            },
+            Case {
+                policy: OverflowPolicy::Truncate { n_keep: 3 },
+                expected_tokens: [2, 3, 4, 5].into(),
+                // The limit is reached twice, and the 3 kept tokens are
+                // re-processed each time.
+                expected_tokens_processed: prompt.len() + n_tokens - 1 + 2 * 2,
+            },
+        ];
+
+        cases.test_each(|case| {
+            let model = LookupModel::new(&next_token, true /* kv_cache */);
+            let mut generator = Generator::from_model(&model)
+                .unwrap()
+                .with_prompt(&prompt)
+                .with_context_limit(max_len, case.policy)
+                .unwrap();
+
+            // Generation should continue past the limit, with the same
+            // output as without a limit, since the lookup model only uses
+            // the last token.
+            let output: Vec<_> = generator
+                .by_ref()
+                .take(n_tokens)
+                .map(|id| id.unwrap())
+                .collect();
+            assert_eq!(output, [4, 5, 0, 1, 2, 3, 4, 5]);
+
+            assert!(model.max_seq_len.get() <= max_len);
+            assert!(model.max_position_id.get() < max_len as i32);
+            assert_eq!(model.tokens_processed.get(), case.expected_tokens_processed);
+
+            // Check the retained tokens and that the KV cache entries match.
+            let snapshot = generator.snapshot();
+            assert_eq!(snapshot.tokens(), case.expected_tokens);
+            let cached_tokens = &snapshot.tokens()[..snapshot.cached_len()];
+            for entry in &snapshot.kv_cache {
+                let KvCacheData::BatchHeadSeqChans(data) = &entry.data else {
+                    panic!("unexpected KV cache layout");
+                };
+                let cached_ids: Vec<u32> = data
+                    .slice((0, 0, .., 0))
+                    .iter()
+                    .map(|x| *x as u32)
+                    .collect();
+                assert_eq!(cached_ids, cached_tokens);
+            }
+        })
+    }
+
+    #[test]
+    fn test_context_limit_invalid() {
+        let model = LookupModel::new(&[1, 2, 3, 4, 5, 0], true /* kv_cache */);
+        let max_len = 6;
+        for (max_len, policy) in [
+            (0, OverflowPolicy::SlidingWindow),
+            (max_len, OverflowPolicy::AttentionSink { n_sink: max_len }),
+            (max_len, OverflowPolicy::Truncate { n_keep: 0 }),
+            (max_len, OverflowPolicy::Truncate { n_keep: max_len }),
+        ] {
+            let result = Generator::from_model(&model)
+                .unwrap()
+                .with_context_limit(max_len, policy);
+            assert!(
+                matches!(result, Err(GeneratorError::InvalidConfig(_))),
+                "expected error for {:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn test_kv_cache_format() {
+        let next_token = [1, 2, 3, 4, 5, 0];
+        let prompt = [0, 1, 2, 3];
+        let formats = [
+            KvCacheFormat::F32,
+            KvCacheFormat::F16,
+            KvCacheFormat::Int8PerHead,
+            KvCacheFormat::Int8PerToken,
+        ];
+
+        formats.test_each(|&kv_cache_format| {
+            let model = LookupModel::new(&next_token, true /* kv_cache */);
+            let mut generator = Generator::from_model(&model)
+                .unwrap()
+                .with_kv_cache_format(kv_cache_format)
+                .with_prompt(&prompt)
+                .with_context_limit(8, OverflowPolicy::AttentionSink { n_sink: 1 })
+                .unwrap();
+
+            let output: Vec<_> = generator.by_ref().take(8).map(|id| id.unwrap()).collect();
+            assert_eq!(output, [4, 5, 0, 1, 2, 3, 4, 5]);
+
+            // The KV cache entries are the token IDs at each position, which
+            // should survive the round trip through the storage format.
+            let snapshot = generator.snapshot();
+            let cached_tokens = &snapshot.tokens()[..snapshot.cached_len()];
+            assert_eq!(cached_tokens, [0, 4, 5, 0, 1, 2, 3, 4]);
+            for entry in &snapshot.kv_cache {
+                let KvCacheData::BatchHeadSeqChans(data) = &entry.data else {
+                    panic!("unexpected KV cache layout");
+                };
+                let cached_ids: Vec<u32> = data
+                    .slice((0, 0, .., 0))
+                    .iter()
+                    .map(|x| x.round() as u32)
+                    .collect();
+                assert_eq!(cached_ids, cached_tokens);
+            }
+        })
+    }
+
+    #[test]
+    fn test_speculative_decoding_seeded() -> Result<(), Box<dyn Error>> {
+        let target_model = LookupModel::new(&[1, 2, 3, 4, 5, 0], true /* kv_cache */);
+        let draft_model = LookupModel::new(&[1, 2, 3, 0, 5, 0], true /* kv_cache */);
+        let n_tokens = 20;
+
+        let generate = || -> Result<_, Box<dyn Error>> {
+            let sampler = TopKSampler::with_rng(fastrand::Rng::with_seed(1234), 6, 1.0);
+            let mut metrics = Metrics::new();
+            let tokens: Vec<_> = Generator::from_model(&target_model)?
+                .with_prompt(&[0, 1])
+                .with_draft(Generator::from_model(&draft_model)?, 3)?
+                // The draft's random generator is derived from the sampler,
+                // even if the sampler is set afterwards.
+                .with_sampler(sampler)
+                .profile(&mut metrics)
+                .take(n_tokens)
+                .collect::<Result<_, _>>()?;
+            Ok((tokens, metrics))
+        };
+
+        // Output is reproducible if the sampler is seeded.
+        let (tokens, metrics) = generate()?;
+        let (tokens_2, _) = generate()?;
+        assert_eq!(tokens.len(), n_tokens);
+        assert_eq!(tokens, tokens_2);
+
+        // Speculative decoding statistics are recorded by `profile`.
+        let stats = metrics.speculative_stats().unwrap();
+        assert!(stats.target_runs > 0);
+        assert_eq!(stats.draft_tokens, stats.target_runs * 3);
+
+        // The number of draft tokens must be positive.
+        let result = Generator::from_model(&target_model)?
+            .with_draft(Generator::from_model(&draft_model)?, 0);
+        assert!(matches!(result, Err(GeneratorError::InvalidConfig(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_speculative_decoding_context_limit() {
+        #[derive(Debug)]
+        struct Case {
+            kv_cache: bool,
+            max_len: usize,
+            policy: OverflowPolicy,
+            n_draft_tokens: usize,
+            valid: bool,
+        }
+
+        let target_next_token = [1, 2, 3, 4, 5, 0];
+        let draft_next_token = [1, 2, 3, 0, 5, 0];
+        let prompt = [0, 1, 2, 3, 4, 5, 0, 1];
+        let n_tokens = 12;
+        let expected_tokens: Vec<u32> = (0..n_tokens).map(|i| (i as u32 + 2) % 6).collect();
+
+        let cases = [
+            Case {
+                kv_cache: true,
+                max_len: 6,
+                policy: OverflowPolicy::SlidingWindow,
+                n_draft_tokens: 3,
+                valid: true,
+            },
+            Case {
+                kv_cache: false,
+                max_len: 6,
+                policy: OverflowPolicy::SlidingWindow,
+                n_draft_tokens: 3,
+                valid: true,
+            },
+            Case {
+                kv_cache: true,
+                max_len: 6,
+                policy: OverflowPolicy::AttentionSink { n_sink: 2 },
+                n_draft_tokens: 3,
+                valid: true,
+            },
+            Case {
+                kv_cache: true,
+                max_len: 6,
+                policy: OverflowPolicy::Truncate { n_keep: 4 },
+                n_draft_tokens: 3,
+                valid: true,
+            },
+            // Pending draft tokens would be evicted.
+            Case {
+                kv_cache: true,
+                max_len: 3,
+                policy: OverflowPolicy::SlidingWindow,
+                n_draft_tokens: 3,
+                valid: false,
+            },
+            Case {
+                kv_cache: true,
+                max_len: 6,
+                policy: OverflowPolicy::AttentionSink { n_sink: 3 },
+                n_draft_tokens: 3,
+                valid: false,
+            },
+            Case {
+                kv_cache: true,
+                max_len: 6,
+                policy: OverflowPolicy::Truncate { n_keep: 2 },
+                n_draft_tokens: 3,
+                valid: false,
+            },
+        ];
+
+        cases.test_each(|case| {
+            let model = LookupModel::new(&target_next_token, case.kv_cache);
+            let draft_model = LookupModel::new(&draft_next_token, case.kv_cache);
+            let draft = || Generator::from_model(&draft_model).unwrap();
+
+            // The combination is checked regardless of which option is set
+            // first.
+            let limit_first = Generator::from_model(&model)
+                .unwrap()
+                .with_context_limit(case.max_len, case.policy)
+                .and_then(|g| g.with_draft(draft(), case.n_draft_tokens));
+            let draft_first = Generator::from_model(&model)
+                .unwrap()
+                .with_draft(draft(), case.n_draft_tokens)
+                .and_then(|g| g.with_context_limit(case.max_len, case.policy));
+
+            if !case.valid {
+                assert!(matches!(limit_first, Err(GeneratorError::InvalidConfig(_))));
+                assert!(matches!(draft_first, Err(GeneratorError::InvalidConfig(_))));
+                return;
+            }
+
+            for generator in [limit_first, draft_first] {
+                let output_tokens: Vec<_> = generator
+                    .unwrap()
+                    .with_prompt(&prompt)
+                    .take(n_tokens)
+                    .map(|id| id.expect("generation failed"))
+                    .collect();
+                assert_eq!(output_tokens, expected_tokens);
+            }
+        })
+    }
+
+    #[test]
+    fn test_logprobs() -> Result<(), Box<dyn Error>> {
+        let next_token = [1, 2, 3, 4, 5, 0];
+        let prompt = [0, 1];
+        let n_tokens = 6;
+
+        // The model's logits are 1 for the next token and 0 otherwise.
+        let n_vocab = next_token.len() as f32;
+        let expected_logprob = 1. - (1f32.exp() + n_vocab - 1.).ln();
+        let expected_other_logprob = expected_logprob - 1.;
+
+        let model = LookupModel::new(&next_token, true /* kv_cache */);
+        let logprobs: Vec<_> = Generator::from_model(&model)?
+            .with_prompt(&prompt)
+            .logprobs(2)
+            .take(n_tokens)
+            .map(|lp| lp.expect("generation failed"))
+            .collect();
+
+        let tokens: Vec<_> = logprobs.iter().map(|lp| lp.token).collect();
+        assert_eq!(tokens, [2, 3, 4, 5, 0, 1]);
+        for lp in &logprobs {
+            assert!((lp.logprob - expected_logprob).abs() < 1e-5);
+            assert_eq!(lp.top_logprobs.len(), 2);
+            assert_eq!(lp.top_logprobs[0].0, lp.token);
+            assert!((lp.top_logprobs[1].1 - expected_other_logprob).abs() < 1e-5);
+        }
+
+        // Log probabilities should be the same with speculative decoding,
+        // including for tokens which replace rejected draft tokens.
+        let target_model = LookupModel::new(&next_token, true /* kv_cache */);
+        let draft_model = LookupModel::new(&[1, 2, 3, 0, 5, 0], true /* kv_cache */);
+        let spec_logprobs: Vec<_> = Generator::from_model(&target_model)?
+            .with_prompt(&prompt)
+            .with_draft(Generator::from_model(&draft_model)?, 3)?
+            .logprobs(2)
+            .take(n_tokens)
+            .map(|lp| lp.expect("generation failed"))
+            .collect();
+        assert_eq!(spec_logprobs, logprobs);
+
+        // Enable log probabilities after speculative decoding has generated
+        // tokens which have not yet been returned.
+        let target_model = LookupModel::new(&next_token, true /* kv_cache */);
+        let draft_model = LookupModel::new(&next_token, true /* kv_cache */);
+        let mut generator = Generator::from_model(&target_model)?
+            .with_prompt(&prompt)
+            .with_draft(Generator::from_model(&draft_model)?, 3)?;
+        assert_eq!(generator.next().transpose()?, Some(2));
+        let late_logprobs: Vec<_> = generator
+            .logprobs(2)
+            .take(n_tokens - 1)
+            .map(|lp| lp.expect("generation failed"))
+            .collect();
+        assert_eq!(late_logprobs, logprobs[1..]);
+
+        Ok(())
+    }
 }
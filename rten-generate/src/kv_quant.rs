@@ -0,0 +1,569 @@
+//! Compact storage formats for key-value caches.
+
+use std::ops::Range;
+
+use rten_tensor::prelude::*;
+use rten_tensor::{NdTensor, NdTensorView};
+
+use crate::generator::KvCacheData;
+
+/// Format used to store self-attention key-value caches between runs of the
+/// model.
+///
+/// Formats other than [`F32`](KvCacheFormat::F32) reduce the memory used by
+/// the cache, at the cost of some precision and the time taken to convert
+/// entries. The cache is converted back to f32 when it is passed to the
+/// model, so the model's inputs and outputs are unchanged. Cross-attention
+/// caches are always stored as f32.
+///
+/// These formats only reduce the memory used to hold the cache between runs
+/// of the model, for example when keeping snapshots or several generators.
+/// The model takes all of its inputs at once, so before each run the caches
+/// for all layers are converted to f32 copies. The copy for each layer is
+/// freed as soon as the model's updated cache for that layer has been
+/// converted back after the run. Peak memory usage during a run is therefore
+/// slightly higher than with `F32`, and each run has the additional cost of
+/// converting the whole cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum KvCacheFormat {
+    /// Store entries as 32-bit floats.
+    #[default]
+    F32,
+
+    /// Store entries as 16-bit floats. This halves the cache size.
+    F16,
+
+    /// Store entries as 8-bit integers with a scale for each attention head.
+    ///
+    /// This reduces the cache size by approximately 4x. When new entries are
+    /// outside the range of the current scale, they are stored with a new,
+    /// larger scale. Existing entries keep their scale, so each entry is only
+    /// quantized once.
+    Int8PerHead,
+
+    /// Store entries as 8-bit integers with a scale for each head and
+    /// sequence position.
+    ///
+    /// This is more accurate than [`Int8PerHead`](KvCacheFormat::Int8PerHead)
+    /// and uses slightly more memory.
+    Int8PerToken,
+}
+
+/// Scale shared by a run of consecutive positions in a [`Block`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ScaleRun {
+    len: usize,
+    scale: f32,
+}
+
+/// Quantized entries for a single batch item and attention head, with
+/// shape `[seq, chans]`.
+#[derive(Clone)]
+enum Block {
+    F16(Vec<u16>),
+    Int8PerHead {
+        data: Vec<i8>,
+
+        /// Scales for consecutive runs of positions. A new run is started
+        /// when appended entries don't fit the scale of the last run.
+        runs: Vec<ScaleRun>,
+    },
+    Int8PerToken {
+        data: Vec<i8>,
+
+        /// Scale for each position.
+        scales: Vec<f32>,
+    },
+}
+
+/// Key-value cache stored in a compact format.
+///
+/// The cache has the same logical shape as a [`KvCacheData`], but each
+/// `[seq, chans]` block for a batch item and head is stored separately so
+/// that entries can be cheaply appended or removed along the sequence
+/// dimension.
+#[derive(Clone)]
+pub(crate) struct QuantizedKvCache {
+    format: KvCacheFormat,
+    batch_size: usize,
+
+    /// Number of heads, or `None` if the dequantized cache has shape
+    /// `[batch, seq, chans]`.
+    n_heads: Option<usize>,
+    chans: usize,
+    seq_len: usize,
+
+    /// Blocks for each `(batch, head)` pair in row-major order.
+    blocks: Vec<Block>,
+}
+
+impl QuantizedKvCache {
+    /// Create an empty cache.
+    ///
+    /// Panics if `format` is [`KvCacheFormat::F32`].
+    pub fn new(
+        format: KvCacheFormat,
+        batch_size: usize,
+        n_heads: Option<usize>,
+        chans: usize,
+    ) -> QuantizedKvCache {
+        let empty_block = match format {
+            KvCacheFormat::F32 => panic!("f32 caches should not be quantized"),
+            KvCacheFormat::F16 => Block::F16(Vec::new()),
+            KvCacheFormat::Int8PerHead => Block::Int8PerHead {
+                data: Vec::new(),
+                runs: Vec::new(),
+            },
+            KvCacheFormat::Int8PerToken => Block::Int8PerToken {
+                data: Vec::new(),
+                scales: Vec::new(),
+            },
+        };
+        QuantizedKvCache {
+            format,
+            batch_size,
+            n_heads,
+            chans,
+            seq_len: 0,
+            blocks: vec![empty_block; batch_size * n_heads.unwrap_or(1)],
+        }
+    }
+
+    /// Quantize an f32 cache.
+    pub fn from_data(format: KvCacheFormat, data: &KvCacheData) -> QuantizedKvCache {
+        let (batch_size, n_heads, chans) = data_dims(data);
+        let mut cache = Self::new(format, batch_size, n_heads, chans);
+        cache.append(data, 0);
+        cache
+    }
+
+    pub fn format(&self) -> KvCacheFormat {
+        self.format
+    }
+
+    pub fn sequence_len(&self) -> usize {
+        self.seq_len
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Return true if `data` has the same batch size, number of heads and
+    /// channels as this cache.
+    pub fn is_compatible(&self, data: &KvCacheData) -> bool {
+        data_dims(data) == (self.batch_size, self.n_heads, self.chans)
+    }
+
+    /// Return an empty cache with the same format and dimensions as this one,
+    /// for a given batch size.
+    pub fn empty_with_batch_size(&self, batch_size: usize) -> QuantizedKvCache {
+        Self::new(self.format, batch_size, self.n_heads, self.chans)
+    }
+
+    /// Quantize positions `start..` of `data` and append them to the cache.
+    ///
+    /// `data` must be compatible with this cache. See
+    /// [`is_compatible`](Self::is_compatible).
+    pub fn append(&mut self, data: &KvCacheData, start: usize) {
+        let n_heads = self.n_heads.unwrap_or(1);
+        let end = data.sequence_len();
+        for (i, block) in self.blocks.iter_mut().enumerate() {
+            let rows = block_view(data, i / n_heads, i % n_heads);
+            let rows = rows.slice(start..end);
+
+            match block {
+                Block::F16(block_data) => {
+                    block_data.extend(rows.iter().map(|x| f32_to_f16(*x)));
+                }
+                Block::Int8PerToken { data, scales } => {
+                    for row in rows.axis_iter(0) {
+                        let scale = abs_max(row.iter()) / 127.;
+                        scales.push(scale);
+                        data.extend(row.iter().map(|x| quantize_i8(*x, scale)));
+                    }
+                }
+                Block::Int8PerHead { data, runs } if rows.size(0) > 0 => {
+                    // Entries which don't fit the current scale start a new
+                    // run, instead of re-quantizing existing entries with a
+                    // larger scale, which would compound rounding errors.
+                    let max_scale = abs_max(rows.iter()) / 127.;
+                    match runs.last_mut() {
+                        Some(run) if max_scale <= run.scale => run.len += rows.size(0),
+                        _ => runs.push(ScaleRun {
+                            len: rows.size(0),
+                            scale: max_scale,
+                        }),
+                    }
+                    let scale = runs.last().unwrap().scale;
+                    data.extend(rows.iter().map(|x| quantize_i8(*x, scale)));
+                }
+                Block::Int8PerHead { .. } => {}
+            }
+        }
+        self.seq_len += end.saturating_sub(start);
+    }
+
+    /// Convert the cache to f32, with space in the new buffer to store
+    /// sequences of length `capacity`.
+    pub fn dequantize(&self, capacity: usize) -> KvCacheData {
+        let mut out = Vec::with_capacity(self.blocks.len() * self.seq_len * self.chans);
+        for block in &self.blocks {
+            match block {
+                Block::F16(data) => out.extend(data.iter().map(|x| f16_to_f32(*x))),
+                Block::Int8PerToken { data, scales } => {
+                    for (row, scale) in data.chunks(self.chans).zip(scales) {
+                        out.extend(row.iter().map(|x| *x as f32 * scale));
+                    }
+                }
+                Block::Int8PerHead { data, runs } => {
+                    let mut offset = 0;
+                    for run in runs {
+                        let run_len = run.len * self.chans;
+                        let run_data = &data[offset..offset + run_len];
+                        out.extend(run_data.iter().map(|x| *x as f32 * run.scale));
+                        offset += run_len;
+                    }
+                }
+            }
+        }
+
+        let data = if let Some(n_heads) = self.n_heads {
+            KvCacheData::BatchHeadSeqChans(NdTensor::from_data(
+                [self.batch_size, n_heads, self.seq_len, self.chans],
+                out,
+            ))
+        } else {
+            KvCacheData::BatchSeqChans(NdTensor::from_data(
+                [self.batch_size, self.seq_len, self.chans],
+                out,
+            ))
+        };
+        data.clone_with_capacity(capacity)
+    }
+
+    /// Discard entries after the first `seq_len` positions.
+    pub fn truncate(&mut self, seq_len: usize) {
+        let seq_len = seq_len.min(self.seq_len);
+        for block in self.blocks.iter_mut() {
+            match block {
+                Block::F16(data) => data.truncate(seq_len * self.chans),
+                Block::Int8PerHead { data, runs } => {
+                    data.truncate(seq_len * self.chans);
+                    remove_from_runs(runs, seq_len..usize::MAX);
+                }
+                Block::Int8PerToken { data, scales } => {
+                    data.truncate(seq_len * self.chans);
+                    scales.truncate(seq_len);
+                }
+            }
+        }
+        self.seq_len = seq_len;
+    }
+
+    /// Remove entries for positions in `range`.
+    pub fn remove_positions(&mut self, range: Range<usize>) {
+        let data_range = range.start * self.chans..range.end * self.chans;
+        for block in self.blocks.iter_mut() {
+            match block {
+                Block::F16(data) => {
+                    data.drain(data_range.clone());
+                }
+                Block::Int8PerHead { data, runs } => {
+                    data.drain(data_range.clone());
+                    remove_from_runs(runs, range.clone());
+                }
+                Block::Int8PerToken { data, scales } => {
+                    data.drain(data_range.clone());
+                    scales.drain(range.clone());
+                }
+            }
+        }
+        self.seq_len -= range.len();
+    }
+
+    /// Create a new cache by selecting entries along the batch dimension.
+    pub fn select_batch(&self, indices: &[usize]) -> QuantizedKvCache {
+        let n_heads = self.n_heads.unwrap_or(1);
+        let blocks = indices
+            .iter()
+            .flat_map(|&idx| {
+                self.blocks[idx * n_heads..(idx + 1) * n_heads]
+                    .iter()
+                    .cloned()
+            })
+            .collect();
+        QuantizedKvCache {
+            batch_size: indices.len(),
+            blocks,
+            ..*self
+        }
+    }
+
+    /// Return the number of bytes used to store cache entries and scales.
+    #[cfg(test)]
+    pub fn size_bytes(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| match block {
+                Block::F16(data) => data.len() * size_of::<u16>(),
+                Block::Int8PerHead { data, runs } => {
+                    data.len() + runs.len() * size_of::<ScaleRun>()
+                }
+                Block::Int8PerToken { data, scales } => {
+                    data.len() + scales.len() * size_of::<f32>()
+                }
+            })
+            .sum()
+    }
+}
+
+/// Return the `(batch_size, n_heads, chans)` dimensions of a cache.
+fn data_dims(data: &KvCacheData) -> (usize, Option<usize>, usize) {
+    match data {
+        KvCacheData::BatchSeqChans(data) => (data.size(0), None, data.size(2)),
+        KvCacheData::BatchHeadSeqChans(data) => (data.size(0), Some(data.size(1)), data.size(3)),
+    }
+}
+
+/// Return a `[seq, chans]` view of the entries for a batch item and head.
+fn block_view(data: &KvCacheData, batch: usize, head: usize) -> NdTensorView<'_, f32, 2> {
+    match data {
+        KvCacheData::BatchSeqChans(data) => data.slice(batch),
+        KvCacheData::BatchHeadSeqChans(data) => data.slice((batch, head)),
+    }
+}
+
+/// Remove the positions in `range` from a list of scale runs.
+fn remove_from_runs(runs: &mut Vec<ScaleRun>, range: Range<usize>) {
+    let mut start = 0;
+    for run in runs.iter_mut() {
+        let end = start + run.len;
+        let overlap = range.start.max(start)..range.end.min(end);
+        start = end;
+        run.len -= overlap.len();
+    }
+    runs.retain(|run| run.len > 0);
+}
+
+fn abs_max<'a>(values: impl Iterator<Item = &'a f32>) -> f32 {
+    values.fold(0., |max, x| max.max(x.abs()))
+}
+
+fn quantize_i8(x: f32, scale: f32) -> i8 {
+    if scale == 0. {
+        return 0;
+    }
+    (x / scale).round().clamp(-127., 127.) as i8
+}
+
+/// Convert an f32 value to the bits of the nearest f16 value.
+fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7f_ffff;
+
+    // Infinity or NaN
+    if exp == 0xff {
+        let nan_bit = if mant != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan_bit;
+    }
+
+    let exp = exp - 127 + 15;
+    if exp >= 0x1f {
+        // Overflow. Round to infinity.
+        return sign | 0x7c00;
+    }
+
+    if exp <= 0 {
+        // Subnormal or zero.
+        if exp < -10 {
+            return sign;
+        }
+        let mant = mant | 0x80_0000;
+        let shift = (14 - exp) as u32;
+        let round_up = (1 << (shift - 1)) - 1 + ((mant >> shift) & 1);
+        return sign | ((mant + round_up) >> shift) as u16;
+    }
+
+    // Round to nearest, ties to even. A carry out of the mantissa correctly
+    // increments the exponent.
+    let half = (sign as u32) | ((exp as u32) << 10) | (mant >> 13);
+    let round_bits = mant & 0x1fff;
+    let round_up = round_bits > 0x1000 || (round_bits == 0x1000 && (half & 1) == 1);
+    (half + round_up as u32) as u16
+}
+
+/// Convert the bits of an f16 value to f32.
+fn f16_to_f32(x: u16) -> f32 {
+    let sign = ((x & 0x8000) as u32) << 16;
+    let exp = ((x >> 10) & 0x1f) as u32;
+    let mant = (x & 0x3ff) as u32;
+
+    let bits = match exp {
+        0 => {
+            // Zero or subnormal.
+            let val = mant as f32 * (-24f32).exp2();
+            return f32::from_bits(sign | val.to_bits());
+        }
+        0x1f => sign | 0x7f80_0000 | (mant << 13),
+        _ => sign | ((exp + 112) << 23) | (mant << 13),
+    };
+    f32::from_bits(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_tensor::prelude::*;
+    use rten_tensor::NdTensor;
+    use rten_testing::TestCases;
+
+    use super::{f16_to_f32, f32_to_f16, KvCacheFormat, QuantizedKvCache};
+    use crate::generator::KvCacheData;
+
+    #[test]
+    fn test_f16_conversion() {
+        #[derive(Debug)]
+        struct Case {
+            value: f32,
+            bits: u16,
+        }
+
+        let cases = [
+            Case {
+                value: 0.,
+                bits: 0x0000,
+            },
+            Case {
+                value: -0.,
+                bits: 0x8000,
+            },
+            Case {
+                value: 1.,
+                bits: 0x3c00,
+            },
+            Case {
+                value: -2.5,
+                bits: 0xc100,
+            },
+            Case {
+                value: 65504.,
+                bits: 0x7bff,
+            },
+            Case {
+                value: f32::INFINITY,
+                bits: 0x7c00,
+            },
+            // Smallest subnormal
+            Case {
+                value: 5.960_464_5e-8,
+                bits: 0x0001,
+            },
+            // Largest subnormal
+            Case {
+                value: 6.097_555e-5,
+                bits: 0x03ff,
+            },
+        ];
+
+        cases.test_each(|case| {
+            assert_eq!(f32_to_f16(case.value), case.bits);
+            assert_eq!(f16_to_f32(case.bits), case.value);
+        });
+
+        // Values are rounded to the nearest f16, ties to even.
+        assert_eq!(f32_to_f16(1. + 1. / 4096.), 0x3c00);
+        assert_eq!(f32_to_f16(1. + 3. / 2048.), 0x3c02);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn test_quantized_kv_cache() {
+        #[derive(Debug)]
+        struct Case {
+            format: KvCacheFormat,
+            max_error: f32,
+            max_bytes_per_elem: f32,
+        }
+
+        let cases = [
+            Case {
+                format: KvCacheFormat::F16,
+                max_error: 1e-3,
+                max_bytes_per_elem: 2.,
+            },
+            Case {
+                format: KvCacheFormat::Int8PerHead,
+                max_error: 0.05,
+                // Each block has up to two scale runs, which is a noticeable
+                // overhead for the short sequences used here.
+                max_bytes_per_elem: 1.5,
+            },
+            Case {
+                format: KvCacheFormat::Int8PerToken,
+                max_error: 0.05,
+                max_bytes_per_elem: 1.5,
+            },
+        ];
+
+        let mut rng = fastrand::Rng::with_seed(1234);
+        let [batch, n_heads, seq, chans] = [2, 3, 10, 8];
+        let data =
+            NdTensor::<f32, 4>::from_fn([batch, n_heads, seq, chans], |_| rng.f32() * 4. - 2.);
+
+        cases.test_each(|case| {
+            let check_close = |cache: &QuantizedKvCache, expected: &NdTensor<f32, 4>| {
+                let KvCacheData::BatchHeadSeqChans(actual) = cache.dequantize(0) else {
+                    panic!("unexpected layout");
+                };
+                assert_eq!(actual.shape(), expected.shape());
+                for (x, y) in actual.iter().zip(expected.iter()) {
+                    assert!((x - y).abs() <= case.max_error, "{} != {}", x, y);
+                }
+            };
+
+            // Quantize the first half of the sequence, then append the rest.
+            // For per-head formats, the second half includes larger values
+            // that need a larger scale.
+            let mut data = data.clone();
+            data[[0, 0, seq - 1, 0]] = 4.;
+            let half = KvCacheData::BatchHeadSeqChans(data.slice((.., .., ..seq / 2)).to_tensor());
+            let mut cache = QuantizedKvCache::from_data(case.format, &half);
+            let KvCacheData::BatchHeadSeqChans(first_half) = cache.dequantize(0) else {
+                panic!("unexpected layout");
+            };
+            cache.append(&KvCacheData::BatchHeadSeqChans(data.clone()), seq / 2);
+            assert_eq!(cache.sequence_len(), seq);
+            check_close(&cache, &data);
+
+            // Existing entries are not re-quantized when new entries are
+            // appended.
+            let KvCacheData::BatchHeadSeqChans(all) = cache.dequantize(0) else {
+                panic!("unexpected layout");
+            };
+            assert_eq!(all.slice((.., .., ..seq / 2)), first_half);
+
+            let bytes_per_elem = cache.size_bytes() as f32 / data.len() as f32;
+            assert!(bytes_per_elem <= case.max_bytes_per_elem);
+
+            // Truncate and remove positions.
+            cache.truncate(seq - 2);
+            cache.remove_positions(1..3);
+            let kept: Vec<usize> = [0].into_iter().chain(3..seq - 2).collect();
+            let expected = NdTensor::from_fn([batch, n_heads, kept.len(), chans], |[b, h, p, c]| {
+                data[[b, h, kept[p], c]]
+            });
+            check_close(&cache, &expected);
+
+            // Select batch entries.
+            let selected = cache.select_batch(&[1, 1]);
+            assert_eq!(selected.batch_size(), 2);
+            let expected = NdTensor::from_fn([2, n_heads, kept.len(), chans], |[_, h, p, c]| {
+                data[[1, h, kept[p], c]]
+            });
+            check_close(&selected, &expected);
+        })
+    }
+}
@@ -7,15 +7,29 @@
 //! [rten]: https://github.com/robertknight/rten
 //! [rten-examples]: https://github.com/robertknight/rten/tree/main/rten-examples
 
+pub mod batch;
+pub mod beam_search;
 pub mod filter;
 pub mod generator;
+mod kv_quant;
+pub mod logprobs;
 pub mod metrics;
 pub mod model;
 pub mod sampler;
+pub mod snapshot;
+mod speculative;
+
+#[cfg(feature = "text-decoder")]
+pub mod constrained;
 
 #[cfg(feature = "text-decoder")]
 pub mod text_decoder;
 
+pub use batch::BatchGenerator;
+pub use beam_search::{BeamHypothesis, BeamSearch, BeamSearchConfig};
 pub use generator::{
-    Generator, GeneratorConfig, GeneratorError, GeneratorUtils, ModelInputsConfig,
+    Generator, GeneratorConfig, GeneratorError, GeneratorUtils, ModelInputsConfig, OverflowPolicy,
 };
+pub use kv_quant::KvCacheFormat;
+pub use logprobs::TokenLogprobs;
+pub use snapshot::GeneratorSnapshot;
@@ -0,0 +1,144 @@
+//! Log probabilities of generated tokens.
+
+use rten_tensor::NdTensorView;
+
+use crate::generator::{Generator, GeneratorError, TokenId};
+
+/// A generated token and its log probability.
+///
+/// Log probabilities are computed by applying log-softmax to the model's
+/// outputs after they have been processed by the generator's
+/// [`LogitsFilter`](crate::filter::LogitsFilter), so tokens that were masked
+/// by a filter have a log probability of `-inf`. They do not depend on the
+/// sampler, so the chosen token is not necessarily the most likely one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenLogprobs {
+    /// The generated token.
+    pub token: TokenId,
+
+    /// Natural log of the probability of `token`.
+    pub logprob: f32,
+
+    /// The most likely tokens and their log probabilities, in descending
+    /// order of probability. This may include `token`. Tokens with a
+    /// probability of zero are excluded.
+    pub top_logprobs: Vec<(TokenId, f32)>,
+}
+
+impl TokenLogprobs {
+    /// Compute the log probabilities for a token from logits, returning the
+    /// `top_n` most likely alternatives.
+    pub(crate) fn from_logits(
+        logits: NdTensorView<f32, 1>,
+        token: TokenId,
+        top_n: usize,
+    ) -> TokenLogprobs {
+        let max_logit = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let log_sum_exp = if max_logit.is_finite() {
+            max_logit
+                + logits
+                    .iter()
+                    .map(|x| (x - max_logit).exp())
+                    .sum::<f32>()
+                    .ln()
+        } else {
+            max_logit
+        };
+        let logprob = |x: f32| x - log_sum_exp;
+
+        let mut candidates: Vec<(TokenId, f32)> = logits
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| **x > f32::NEG_INFINITY)
+            .map(|(id, x)| (id as TokenId, *x))
+            .collect();
+        let by_logit_desc = |a: &(TokenId, f32), b: &(TokenId, f32)| b.1.total_cmp(&a.1);
+        if top_n < candidates.len() {
+            candidates.select_nth_unstable_by(top_n, by_logit_desc);
+            candidates.truncate(top_n);
+        }
+        candidates.sort_by(by_logit_desc);
+
+        TokenLogprobs {
+            token,
+            logprob: logits
+                .get([token as usize])
+                .map(|x| logprob(*x))
+                .unwrap_or(f32::NEG_INFINITY),
+            top_logprobs: candidates
+                .into_iter()
+                .map(|(id, x)| (id, logprob(x)))
+                .collect(),
+        }
+    }
+}
+
+/// Iterator which yields generated tokens with their log probabilities.
+///
+/// This is created by [`Generator::logprobs`].
+pub struct Logprobs<'a> {
+    generator: Generator<'a>,
+}
+
+impl<'a> Logprobs<'a> {
+    pub(crate) fn new(generator: Generator<'a>) -> Logprobs<'a> {
+        Logprobs { generator }
+    }
+}
+
+impl Iterator for Logprobs<'_> {
+    type Item = Result<TokenLogprobs, GeneratorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.generator.generate_next_token_logprobs())
+    }
+}
+
+/// Compute the perplexity of a sequence from the log probabilities of its
+/// tokens.
+///
+/// This is the exponential of the negative mean log probability. Returns
+/// `NaN` if `logprobs` is empty.
+pub fn perplexity<I: IntoIterator<Item = f32>>(logprobs: I) -> f32 {
+    let (sum, count) = logprobs
+        .into_iter()
+        .fold((0., 0), |(sum, count), lp| (sum + lp, count + 1));
+    (-sum / count as f32).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_tensor::prelude::*;
+    use rten_tensor::NdTensor;
+
+    use super::{perplexity, TokenLogprobs};
+
+    #[test]
+    fn test_token_logprobs() {
+        let logits = NdTensor::from([2., 1., f32::NEG_INFINITY, 3., 0.]);
+        let logprobs = TokenLogprobs::from_logits(logits.view(), 1, 3);
+
+        let log_sum_exp = [2f32, 1., 3., 0.].iter().map(|x| x.exp()).sum::<f32>().ln();
+        assert_eq!(logprobs.token, 1);
+        assert!((logprobs.logprob - (1. - log_sum_exp)).abs() < 1e-6);
+
+        let top_ids: Vec<_> = logprobs.top_logprobs.iter().map(|(id, _)| *id).collect();
+        assert_eq!(top_ids, [3, 0, 1]);
+        for &(id, lp) in &logprobs.top_logprobs {
+            assert!((lp - (logits[[id as usize]] - log_sum_exp)).abs() < 1e-6);
+        }
+
+        // Masked tokens have zero probability and are excluded from the top
+        // alternatives.
+        let logprobs = TokenLogprobs::from_logits(logits.view(), 2, 10);
+        assert_eq!(logprobs.logprob, f32::NEG_INFINITY);
+        assert_eq!(logprobs.top_logprobs.len(), 4);
+    }
+
+    #[test]
+    fn test_perplexity() {
+        let uniform = [0.25f32.ln(); 4];
+        assert!((perplexity(uniform) - 4.).abs() < 1e-5);
+        assert!(perplexity([]).is_nan());
+    }
+}
@@ -11,6 +11,10 @@ use std::time::Duration;
 /// first step can be much slower.
 ///
 /// This is used via [`GeneratorUtils::profile`](crate::GeneratorUtils::profile).
+///
+/// When using speculative decoding, each token returned by the generator is
+/// recorded as a step. Statistics about accepted draft tokens are recorded
+/// when using [`Generator::profile`](crate::Generator::profile).
 #[derive(Clone)]
 pub struct Metrics {
     /// Duration times for each step in microseconds, excluding the warmup.
@@ -18,6 +22,9 @@ pub struct Metrics {
 
     /// Duration for the warmup step.
     warmup_duration: Option<Duration>,
+
+    /// Draft token statistics for speculative decoding.
+    speculative_stats: Option<SpeculativeStats>,
 }
 
 impl Metrics {
@@ -26,6 +33,7 @@ impl Metrics {
         Metrics {
             durations: Vec::new(),
             warmup_duration: None,
+            speculative_stats: None,
         }
     }
 
@@ -72,6 +80,57 @@ impl Metrics {
     pub fn tokens_per_second(&self) -> f32 {
         self.durations.len() as f32 / self.total_main_duration().as_secs_f32()
     }
+
+    /// Record statistics about draft tokens from speculative decoding.
+    ///
+    /// This is done automatically by
+    /// [`Generator::profile`](crate::Generator::profile). Use this to record
+    /// statistics from
+    /// [`Generator::speculative_stats`](crate::Generator::speculative_stats)
+    /// when `profile` is used on a wrapper around the generator.
+    pub fn set_speculative_stats(&mut self, stats: SpeculativeStats) {
+        self.speculative_stats = Some(stats);
+    }
+
+    /// Return statistics about draft tokens from speculative decoding.
+    pub fn speculative_stats(&self) -> Option<SpeculativeStats> {
+        self.speculative_stats
+    }
+}
+
+/// Statistics about the tokens proposed by a draft model during speculative
+/// decoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SpeculativeStats {
+    /// Number of tokens proposed by the draft model.
+    pub draft_tokens: usize,
+
+    /// Number of draft tokens that were accepted by the target model.
+    pub accepted_tokens: usize,
+
+    /// Number of verification runs of the target model.
+    pub target_runs: usize,
+}
+
+impl SpeculativeStats {
+    /// Return the fraction of draft tokens which were accepted.
+    pub fn acceptance_rate(&self) -> f32 {
+        if self.draft_tokens == 0 {
+            return 0.;
+        }
+        self.accepted_tokens as f32 / self.draft_tokens as f32
+    }
+
+    /// Return the mean number of tokens generated for each run of the
+    /// target model.
+    pub fn tokens_per_target_run(&self) -> f32 {
+        if self.target_runs == 0 {
+            return 0.;
+        }
+        // Each run produces the accepted draft tokens plus one token sampled
+        // from the target model.
+        (self.accepted_tokens + self.target_runs) as f32 / self.target_runs as f32
+    }
 }
 
 impl Default for Metrics {
@@ -84,7 +143,7 @@ impl Default for Metrics {
 mod tests {
     use std::time::Duration;
 
-    use super::Metrics;
+    use super::{Metrics, SpeculativeStats};
 
     macro_rules! assert_approx_eq {
         ($a:expr, $b:expr, $threshold: expr) => {
@@ -120,4 +179,22 @@ mod tests {
         assert_approx_eq!(metrics.mean_duration(), 100.0, 1e-5);
         assert_approx_eq!(metrics.tokens_per_second(), 10.0, 1e-5);
     }
+
+    #[test]
+    fn test_speculative_stats() {
+        let mut metrics = Metrics::new();
+        assert_eq!(metrics.speculative_stats(), None);
+
+        let stats = SpeculativeStats {
+            draft_tokens: 12,
+            accepted_tokens: 9,
+            target_runs: 3,
+        };
+        metrics.set_speculative_stats(stats);
+
+        let stats = metrics.speculative_stats().unwrap();
+        assert_approx_eq!(stats.acceptance_rate(), 0.75, 1e-5);
+        assert_approx_eq!(stats.tokens_per_target_run(), 4.0, 1e-5);
+        assert_eq!(SpeculativeStats::default().acceptance_rate(), 0.);
+    }
 }
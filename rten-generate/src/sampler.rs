@@ -4,7 +4,7 @@ use std::cell::RefCell;
 
 use rten::{FloatOperators, Operators};
 use rten_tensor::prelude::*;
-use rten_tensor::NdTensorView;
+use rten_tensor::{NdTensor, NdTensorView};
 
 use crate::generator::TokenId;
 
@@ -14,6 +14,29 @@ pub trait Sampler {
     ///
     /// `logits` has shape `[n_vocab]`.
     fn sample(&self, logits: NdTensorView<f32, 1>) -> TokenId;
+
+    /// Return the probability distribution over token IDs which
+    /// [`sample`](Self::sample) draws from, given the same logits.
+    ///
+    /// This is used by speculative decoding to decide whether to accept
+    /// tokens proposed by a draft model. The default implementation puts all
+    /// of the probability mass on the token returned by `sample`, which is
+    /// exact for deterministic samplers.
+    fn probs(&self, logits: NdTensorView<f32, 1>) -> NdTensor<f32, 1> {
+        let mut probs = NdTensor::zeros(logits.shape());
+        probs[[self.sample(logits) as usize]] = 1.;
+        probs
+    }
+
+    /// Return a random number generator derived from this sampler's
+    /// generator.
+    ///
+    /// This is used for the random choices made by speculative decoding, so
+    /// that output is reproducible when a seeded sampler is used. The default
+    /// implementation returns an unseeded generator.
+    fn fork_rng(&self) -> fastrand::Rng {
+        fastrand::Rng::new()
+    }
 }
 
 /// A [`Sampler`] which always chooses the token ID with the highest probability.
@@ -107,6 +130,284 @@ impl Sampler for TopKSampler {
         let token_id = topk_indices.slice(topk_index).item().copied().unwrap();
         token_id as TokenId
     }
+
+    fn probs(&self, logits: NdTensorView<f32, 1>) -> NdTensor<f32, 1> {
+        if self.temperature == 0. || self.k == 1 {
+            return ArgMaxSampler::new().probs(logits);
+        }
+
+        let mut probs = sorted_probs(logits, self.temperature);
+        probs.truncate(self.k);
+        candidate_probs(logits.size(0), &probs)
+    }
+
+    fn fork_rng(&self) -> fastrand::Rng {
+        self.rng.borrow_mut().fork()
+    }
+}
+
+/// Return the probabilities of each token after applying temperature
+/// scaling, as `(token_id, prob)` pairs sorted in descending order of
+/// probability.
+fn sorted_probs(logits: NdTensorView<f32, 1>, temperature: f32) -> Vec<(TokenId, f32)> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut probs: Vec<(TokenId, f32)> = logits
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as TokenId, ((x - max) / temperature).exp()))
+        .collect();
+    let sum: f32 = probs.iter().map(|(_, p)| p).sum();
+    for (_, p) in probs.iter_mut() {
+        *p /= sum;
+    }
+    probs.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    probs
+}
+
+/// Return a probability distribution over a vocabulary of size `n_vocab`
+/// which samples from `candidates` with probability proportional to their
+/// weights.
+fn candidate_probs(n_vocab: usize, candidates: &[(TokenId, f32)]) -> NdTensor<f32, 1> {
+    let total: f32 = candidates.iter().map(|(_, w)| w).sum();
+    let mut probs = NdTensor::zeros([n_vocab]);
+    for &(token_id, weight) in candidates {
+        probs[[token_id as usize]] = weight / total;
+    }
+    probs
+}
+
+/// Sample a token from a list of `(token_id, weight)` candidates, with
+/// probability proportional to the weight.
+///
+/// The weights do not need to sum to 1.
+fn sample_weighted(rng: &mut fastrand::Rng, candidates: &[(TokenId, f32)]) -> TokenId {
+    let total: f32 = candidates.iter().map(|(_, w)| w).sum();
+    let target = rng.f32() * total;
+
+    let mut cum_weight = 0.;
+    for &(token_id, weight) in candidates {
+        cum_weight += weight;
+        if target <= cum_weight {
+            return token_id;
+        }
+    }
+
+    // Fallback in case of rounding errors.
+    candidates.last().expect("candidates should be non-empty").0
+}
+
+/// A [`Sampler`] which samples from the smallest set of tokens whose
+/// cumulative probability exceeds a threshold `p`.
+///
+/// This is also known as nucleus sampling. See
+/// <https://arxiv.org/abs/1904.09751>.
+pub struct TopPSampler {
+    p: f32,
+    temperature: f32,
+    rng: RefCell<fastrand::Rng>,
+}
+
+impl TopPSampler {
+    /// Create a sampler which samples from the top tokens with cumulative
+    /// probability `p`, with a given temperature.
+    ///
+    /// `p` must be in the range (0, 1] and temperature must be >= 0.0.
+    pub fn new(p: f32, temperature: f32) -> TopPSampler {
+        Self::with_rng(fastrand::Rng::new(), p, temperature)
+    }
+
+    /// Create a top-p sampler using a seeded random number generator.
+    pub fn with_rng(rng: fastrand::Rng, p: f32, temperature: f32) -> TopPSampler {
+        assert!(temperature >= 0.);
+        assert!(p > 0. && p <= 1.);
+
+        TopPSampler {
+            rng: RefCell::new(rng),
+            p,
+            temperature,
+        }
+    }
+}
+
+impl TopPSampler {
+    /// Return the tokens which may be sampled and their probabilities.
+    fn candidates(&self, logits: NdTensorView<f32, 1>) -> Vec<(TokenId, f32)> {
+        let mut probs = sorted_probs(logits, self.temperature);
+        let mut cum_prob = 0.;
+        let n_keep = probs
+            .iter()
+            .position(|(_, prob)| {
+                cum_prob += prob;
+                cum_prob >= self.p
+            })
+            .map(|pos| pos + 1)
+            .unwrap_or(probs.len());
+        probs.truncate(n_keep);
+        probs
+    }
+}
+
+impl Sampler for TopPSampler {
+    fn sample(&self, logits: NdTensorView<f32, 1>) -> TokenId {
+        if self.temperature == 0. {
+            return ArgMaxSampler::new().sample(logits);
+        }
+        sample_weighted(&mut self.rng.borrow_mut(), &self.candidates(logits))
+    }
+
+    fn probs(&self, logits: NdTensorView<f32, 1>) -> NdTensor<f32, 1> {
+        if self.temperature == 0. {
+            return ArgMaxSampler::new().probs(logits);
+        }
+        candidate_probs(logits.size(0), &self.candidates(logits))
+    }
+
+    fn fork_rng(&self) -> fastrand::Rng {
+        self.rng.borrow_mut().fork()
+    }
+}
+
+/// A [`Sampler`] which samples from tokens whose probability is at least
+/// `min_p` times the probability of the most likely token.
+///
+/// See <https://arxiv.org/abs/2407.01082>.
+pub struct MinPSampler {
+    min_p: f32,
+    temperature: f32,
+    rng: RefCell<fastrand::Rng>,
+}
+
+impl MinPSampler {
+    /// Create a min-p sampler with a given temperature.
+    ///
+    /// `min_p` must be in the range [0, 1] and temperature must be >= 0.0.
+    pub fn new(min_p: f32, temperature: f32) -> MinPSampler {
+        Self::with_rng(fastrand::Rng::new(), min_p, temperature)
+    }
+
+    /// Create a min-p sampler using a seeded random number generator.
+    pub fn with_rng(rng: fastrand::Rng, min_p: f32, temperature: f32) -> MinPSampler {
+        assert!(temperature >= 0.);
+        assert!((0. ..=1.).contains(&min_p));
+
+        MinPSampler {
+            rng: RefCell::new(rng),
+            min_p,
+            temperature,
+        }
+    }
+}
+
+impl MinPSampler {
+    /// Return the tokens which may be sampled and their probabilities.
+    fn candidates(&self, logits: NdTensorView<f32, 1>) -> Vec<(TokenId, f32)> {
+        let mut probs = sorted_probs(logits, self.temperature);
+        let threshold = probs.first().map(|(_, p)| p * self.min_p).unwrap_or(0.);
+        let n_keep = probs.iter().take_while(|(_, p)| *p >= threshold).count();
+        probs.truncate(n_keep);
+        probs
+    }
+}
+
+impl Sampler for MinPSampler {
+    fn sample(&self, logits: NdTensorView<f32, 1>) -> TokenId {
+        if self.temperature == 0. {
+            return ArgMaxSampler::new().sample(logits);
+        }
+        sample_weighted(&mut self.rng.borrow_mut(), &self.candidates(logits))
+    }
+
+    fn probs(&self, logits: NdTensorView<f32, 1>) -> NdTensor<f32, 1> {
+        if self.temperature == 0. {
+            return ArgMaxSampler::new().probs(logits);
+        }
+        candidate_probs(logits.size(0), &self.candidates(logits))
+    }
+
+    fn fork_rng(&self) -> fastrand::Rng {
+        self.rng.borrow_mut().fork()
+    }
+}
+
+/// A [`Sampler`] which implements locally typical sampling.
+///
+/// This samples from the tokens whose information content (negative log
+/// probability) is closest to the entropy of the distribution, up to a
+/// cumulative probability of `mass`. See <https://arxiv.org/abs/2202.00666>.
+pub struct TypicalSampler {
+    mass: f32,
+    temperature: f32,
+    rng: RefCell<fastrand::Rng>,
+}
+
+impl TypicalSampler {
+    /// Create a typical sampler with a given temperature.
+    ///
+    /// `mass` must be in the range (0, 1] and temperature must be >= 0.0.
+    pub fn new(mass: f32, temperature: f32) -> TypicalSampler {
+        Self::with_rng(fastrand::Rng::new(), mass, temperature)
+    }
+
+    /// Create a typical sampler using a seeded random number generator.
+    pub fn with_rng(rng: fastrand::Rng, mass: f32, temperature: f32) -> TypicalSampler {
+        assert!(temperature >= 0.);
+        assert!(mass > 0. && mass <= 1.);
+
+        TypicalSampler {
+            rng: RefCell::new(rng),
+            mass,
+            temperature,
+        }
+    }
+}
+
+impl TypicalSampler {
+    /// Return the tokens which may be sampled and their probabilities.
+    fn candidates(&self, logits: NdTensorView<f32, 1>) -> Vec<(TokenId, f32)> {
+        let mut probs = sorted_probs(logits, self.temperature);
+        let entropy: f32 = probs
+            .iter()
+            .filter(|(_, p)| *p > 0.)
+            .map(|(_, p)| -p * p.ln())
+            .sum();
+
+        // Order tokens by how close their information content is to the
+        // entropy.
+        let surprise_diff = |p: f32| (-p.ln() - entropy).abs();
+        probs.sort_by(|(_, a), (_, b)| surprise_diff(*a).total_cmp(&surprise_diff(*b)));
+
+        let mut cum_prob = 0.;
+        let n_keep = probs
+            .iter()
+            .position(|(_, prob)| {
+                cum_prob += prob;
+                cum_prob >= self.mass
+            })
+            .map(|pos| pos + 1)
+            .unwrap_or(probs.len());
+        probs.truncate(n_keep);
+        probs
+    }
+}
+
+impl Sampler for TypicalSampler {
+    fn sample(&self, logits: NdTensorView<f32, 1>) -> TokenId {
+        if self.temperature == 0. {
+            return ArgMaxSampler::new().sample(logits);
+        }
+        sample_weighted(&mut self.rng.borrow_mut(), &self.candidates(logits))
+    }
+
+    fn probs(&self, logits: NdTensorView<f32, 1>) -> NdTensor<f32, 1> {
+        if self.temperature == 0. {
+            return ArgMaxSampler::new().probs(logits);
+        }
+        candidate_probs(logits.size(0), &self.candidates(logits))
+    }
+
+    fn fork_rng(&self) -> fastrand::Rng {
+        self.rng.borrow_mut().fork()
+    }
 }
 
 /// Sample an item from a vector of probabilities.
@@ -133,7 +434,7 @@ mod tests {
     use rten_tensor::NdTensor;
     use rten_testing::TestCases;
 
-    use super::{ArgMaxSampler, Sampler, TopKSampler};
+    use super::{ArgMaxSampler, MinPSampler, Sampler, TopKSampler, TopPSampler, TypicalSampler};
 
     #[test]
     fn test_argmax_sampler() {
@@ -218,4 +519,149 @@ mod tests {
             assert_eq!(counts[logits.size(vocab_dim) - k..], *expected);
         })
     }
+
+    /// Sample 100 tokens and return the number of times each token was
+    /// sampled.
+    fn sample_counts(sampler: &dyn Sampler, logits: &NdTensor<f32, 1>) -> Vec<usize> {
+        let mut counts = vec![0; logits.size(0)];
+        for _ in 0..100 {
+            counts[sampler.sample(logits.view()) as usize] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_topp_sampler() {
+        // Probabilities are approximately [0.64, 0.24, 0.09, 0.03].
+        let logits = NdTensor::from([3., 2., 1., 0.]);
+
+        #[derive(Debug)]
+        struct Case {
+            p: f32,
+            temperature: f32,
+            // Number of tokens that may be sampled.
+            n_allowed: usize,
+        }
+
+        let cases = [
+            Case {
+                p: 0.5,
+                temperature: 1.,
+                n_allowed: 1,
+            },
+            Case {
+                p: 0.8,
+                temperature: 1.,
+                n_allowed: 2,
+            },
+            Case {
+                p: 0.95,
+                temperature: 1.,
+                n_allowed: 3,
+            },
+            Case {
+                p: 1.0,
+                temperature: 1.,
+                n_allowed: 4,
+            },
+            Case {
+                p: 1.0,
+                temperature: 0.,
+                n_allowed: 1,
+            },
+        ];
+
+        cases.test_each(|case| {
+            let rng = fastrand::Rng::with_seed(1234);
+            let sampler = TopPSampler::with_rng(rng, case.p, case.temperature);
+            let counts = sample_counts(&sampler, &logits);
+            assert!(counts[case.n_allowed..].iter().all(|c| *c == 0));
+            assert!(counts[..case.n_allowed].iter().all(|c| *c > 0));
+        })
+    }
+
+    #[test]
+    fn test_minp_sampler() {
+        // Probabilities are approximately [0.64, 0.24, 0.09, 0.03].
+        let logits = NdTensor::from([3., 2., 1., 0.]);
+
+        #[derive(Debug)]
+        struct Case {
+            min_p: f32,
+            n_allowed: usize,
+        }
+
+        let cases = [
+            Case {
+                min_p: 0.5,
+                n_allowed: 1,
+            },
+            Case {
+                min_p: 0.3,
+                n_allowed: 2,
+            },
+            Case {
+                min_p: 0.1,
+                n_allowed: 3,
+            },
+            Case {
+                min_p: 0.,
+                n_allowed: 4,
+            },
+        ];
+
+        cases.test_each(|case| {
+            let rng = fastrand::Rng::with_seed(1234);
+            let sampler = MinPSampler::with_rng(rng, case.min_p, 1.0);
+            let counts = sample_counts(&sampler, &logits);
+            assert!(counts[case.n_allowed..].iter().all(|c| *c == 0));
+            assert!(counts[..case.n_allowed].iter().all(|c| *c > 0));
+        })
+    }
+
+    #[test]
+    fn test_typical_sampler() {
+        // Probabilities are approximately [0.64, 0.24, 0.09, 0.03]. The
+        // entropy is ~0.95 and the information content of each token is
+        // [0.44, 1.44, 2.44, 3.44]. The distances from the entropy are
+        // [0.51, 0.49, 1.49, 2.49], so tokens are ranked [1, 0, 2, 3] by
+        // typicality. Tokens 1 and 0 have a cumulative probability of ~0.88,
+        // which reaches the mass of 0.8, so tokens 2 and 3 are excluded.
+        let logits = NdTensor::from([3., 2., 1., 0.]);
+
+        let rng = fastrand::Rng::with_seed(1234);
+        let sampler = TypicalSampler::with_rng(rng, 0.8, 1.0);
+        let counts = sample_counts(&sampler, &logits);
+        assert!(counts[0] > 0 && counts[1] > 0);
+        assert_eq!(counts[2..], [0, 0]);
+
+        // With a uniform distribution, all tokens are equally typical.
+        let logits = NdTensor::from([1., 1., 1., 1.]);
+        let rng = fastrand::Rng::with_seed(1234);
+        let sampler = TypicalSampler::with_rng(rng, 1.0, 1.0);
+        let counts = sample_counts(&sampler, &logits);
+        assert!(counts.iter().all(|c| *c > 0));
+    }
+
+    #[test]
+    fn test_sampler_probs() {
+        // Probabilities are approximately [0.64, 0.24, 0.09, 0.03].
+        let logits = NdTensor::from([3., 2., 1., 0.]);
+
+        let samplers: [(Box<dyn Sampler>, usize); 5] = [
+            (Box::new(ArgMaxSampler::new()), 1),
+            (Box::new(TopKSampler::new(2, 1.0)), 2),
+            (Box::new(TopPSampler::new(0.9, 1.0)), 3),
+            (Box::new(MinPSampler::new(0.1, 1.0)), 3),
+            (Box::new(TypicalSampler::new(1.0, 1.0)), 4),
+        ];
+
+        for (sampler, n_candidates) in samplers {
+            let probs = sampler.probs(logits.view());
+            let sum: f32 = probs.iter().sum();
+            assert!((sum - 1.).abs() < 1e-5);
+            assert!(probs.iter().take(n_candidates).all(|p| *p > 0.));
+            assert!(probs.iter().skip(n_candidates).all(|p| *p == 0.));
+        }
+    }
 }
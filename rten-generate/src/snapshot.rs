@@ -0,0 +1,326 @@
+//! Saved generator state for prefix caching.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use rten_tensor::prelude::*;
+use rten_tensor::NdTensor;
+
+use crate::generator::{KvCacheData, TokenId};
+
+/// Magic bytes at the start of a serialized snapshot.
+const MAGIC: &[u8; 4] = b"RTKV";
+
+/// Current version of the serialization format.
+const VERSION: u32 = 1;
+
+/// KV cache buffer for one model input.
+#[derive(Clone)]
+pub(crate) struct KvCacheEntry {
+    /// Name of the model input for this cache entry.
+    pub name: String,
+
+    /// True if this is a cross-attention cache.
+    pub encoder: bool,
+
+    pub data: KvCacheData,
+}
+
+/// Saved KV cache and token history of a [`Generator`](crate::Generator).
+///
+/// Snapshots are created using
+/// [`Generator::snapshot`](crate::Generator::snapshot) and restored using
+/// [`Generator::restore_snapshot`](crate::Generator::restore_snapshot). A
+/// snapshot can be restored any number of times and into multiple generators,
+/// as long as they use the same model.
+///
+/// Snapshots can be saved to and loaded from files using [`save`](Self::save)
+/// and [`load`](Self::load). The format is a simple binary encoding of the
+/// tokens and KV cache tensors, which is only intended to be read by the same
+/// version of this library and on machines with the same model.
+///
+/// ```no_run
+/// use rten_generate::{Generator, GeneratorSnapshot};
+/// # use rten_generate::model::Model;
+/// # fn example(model: &dyn Model, system_prompt: &[u32], user_prompt: &[u32]) -> Result<(), Box<dyn std::error::Error>> {
+///
+/// // Process the system prompt once and save the result.
+/// let mut generator = Generator::from_model(model)?.with_prompt(system_prompt);
+/// generator.prefill()?;
+/// let snapshot = generator.snapshot();
+/// snapshot.save("system_prompt.kv")?;
+///
+/// // Later, restore the snapshot and continue from the end of the system
+/// // prompt.
+/// let snapshot = GeneratorSnapshot::load("system_prompt.kv")?;
+/// let mut generator = Generator::from_model(model)?;
+/// generator.restore_snapshot(&snapshot)?;
+/// generator.append_prompt(user_prompt);
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct GeneratorSnapshot {
+    /// Token sequence processed by the generator, including tokens which have
+    /// not yet been passed to the model.
+    pub(crate) tokens: Vec<TokenId>,
+
+    /// Number of tokens at the start of `tokens` which are stored in the KV
+    /// cache.
+    pub(crate) n_cached_tokens: usize,
+
+    /// Token history used by logits filters.
+    pub(crate) prev_tokens: Vec<TokenId>,
+
+    pub(crate) kv_cache: Vec<KvCacheEntry>,
+}
+
+impl GeneratorSnapshot {
+    /// Return the token sequence saved in this snapshot.
+    ///
+    /// This can be compared against a new prompt to find out whether the
+    /// snapshot can be used as its prefix.
+    pub fn tokens(&self) -> &[TokenId] {
+        &self.tokens
+    }
+
+    /// Return the number of tokens whose keys and values are stored in the
+    /// snapshot's KV cache.
+    pub fn cached_len(&self) -> usize {
+        self.n_cached_tokens
+    }
+
+    /// Save the snapshot to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Load a snapshot from a file created by [`save`](Self::save).
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<GeneratorSnapshot> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::read(&mut reader)
+    }
+
+    /// Serialize the snapshot to a writer.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+        write_tokens(writer, &self.tokens)?;
+        write_u32(writer, self.n_cached_tokens as u32)?;
+        write_tokens(writer, &self.prev_tokens)?;
+
+        write_u32(writer, self.kv_cache.len() as u32)?;
+        for entry in &self.kv_cache {
+            write_u32(writer, entry.name.len() as u32)?;
+            writer.write_all(entry.name.as_bytes())?;
+            writer.write_all(&[entry.encoder as u8])?;
+
+            let (shape, data) = match &entry.data {
+                KvCacheData::BatchSeqChans(data) => (data.shape().to_vec(), data.to_vec()),
+                KvCacheData::BatchHeadSeqChans(data) => (data.shape().to_vec(), data.to_vec()),
+            };
+            write_u32(writer, shape.len() as u32)?;
+            for size in shape {
+                write_u32(writer, size as u32)?;
+            }
+            for x in data {
+                writer.write_all(&x.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deserialize a snapshot from a reader.
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<GeneratorSnapshot> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a generator snapshot"));
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+
+        let tokens = read_tokens(reader)?;
+        let n_cached_tokens = read_u32(reader)? as usize;
+        if n_cached_tokens > tokens.len() {
+            return Err(invalid_data("cached token count exceeds sequence length"));
+        }
+        let prev_tokens = read_tokens(reader)?;
+
+        let n_entries = read_u32(reader)?;
+        let mut kv_cache = Vec::new();
+        for _ in 0..n_entries {
+            let name_len = read_u32(reader)? as usize;
+            let mut name = vec![0u8; name_len];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| invalid_data("invalid entry name"))?;
+
+            let mut encoder = [0u8];
+            reader.read_exact(&mut encoder)?;
+            let encoder = encoder[0] != 0;
+
+            let ndim = read_u32(reader)? as usize;
+            if !(3..=4).contains(&ndim) {
+                return Err(invalid_data(format!(
+                    "KV cache has {} dims, expected 3 or 4",
+                    ndim
+                )));
+            }
+            let mut shape = Vec::with_capacity(ndim);
+            for _ in 0..ndim {
+                shape.push(read_u32(reader)? as usize);
+            }
+
+            // Read the data without pre-allocating a buffer based on the
+            // shape, so that a corrupt file fails with an error instead of a
+            // huge allocation.
+            let n_bytes = shape
+                .iter()
+                .try_fold(size_of::<f32>(), |n_bytes, &size| n_bytes.checked_mul(size))
+                .ok_or_else(|| invalid_data("KV cache is too large"))?;
+            let mut bytes = Vec::new();
+            reader.take(n_bytes as u64).read_to_end(&mut bytes)?;
+            if bytes.len() != n_bytes {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let data: Vec<f32> = bytes
+                .chunks_exact(size_of::<f32>())
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+
+            let data = match shape[..] {
+                [batch, seq, chans] => {
+                    KvCacheData::BatchSeqChans(NdTensor::from_data([batch, seq, chans], data))
+                }
+                [batch, heads, seq, chans] => KvCacheData::BatchHeadSeqChans(NdTensor::from_data(
+                    [batch, heads, seq, chans],
+                    data,
+                )),
+                _ => unreachable!(),
+            };
+            kv_cache.push(KvCacheEntry {
+                name,
+                encoder,
+                data,
+            });
+        }
+
+        Ok(GeneratorSnapshot {
+            tokens,
+            n_cached_tokens,
+            prev_tokens,
+            kv_cache,
+        })
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn write_u32<W: Write>(writer: &mut W, val: u32) -> io::Result<()> {
+    writer.write_all(&val.to_le_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn write_tokens<W: Write>(writer: &mut W, tokens: &[TokenId]) -> io::Result<()> {
+    write_u32(writer, tokens.len() as u32)?;
+    for &token in tokens {
+        write_u32(writer, token)?;
+    }
+    Ok(())
+}
+
+fn read_tokens<R: Read>(reader: &mut R) -> io::Result<Vec<TokenId>> {
+    let len = read_u32(reader)?;
+    (0..len).map(|_| read_u32(reader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_tensor::NdTensor;
+
+    use super::{GeneratorSnapshot, KvCacheEntry};
+    use crate::generator::KvCacheData;
+
+    #[test]
+    fn test_write_read() {
+        let snapshot = GeneratorSnapshot {
+            tokens: vec![1, 2, 3],
+            n_cached_tokens: 2,
+            prev_tokens: vec![1, 2],
+            kv_cache: vec![
+                KvCacheEntry {
+                    name: "past.0.key".to_string(),
+                    encoder: false,
+                    data: KvCacheData::BatchHeadSeqChans(
+                        NdTensor::arange(0., 16., None).into_shape([1, 2, 2, 4]),
+                    ),
+                },
+                KvCacheEntry {
+                    name: "past.0.encoder.key".to_string(),
+                    encoder: true,
+                    data: KvCacheData::BatchSeqChans(
+                        NdTensor::arange(0., 12., None).into_shape([1, 3, 4]),
+                    ),
+                },
+            ],
+        };
+
+        let mut buf = Vec::new();
+        snapshot.write(&mut buf).unwrap();
+        let loaded = GeneratorSnapshot::read(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(loaded.tokens(), snapshot.tokens());
+        assert_eq!(loaded.cached_len(), snapshot.cached_len());
+        assert_eq!(loaded.prev_tokens, snapshot.prev_tokens);
+        assert_eq!(loaded.kv_cache.len(), snapshot.kv_cache.len());
+        for (actual, expected) in loaded.kv_cache.iter().zip(&snapshot.kv_cache) {
+            assert_eq!(actual.name, expected.name);
+            assert_eq!(actual.encoder, expected.encoder);
+            match (&actual.data, &expected.data) {
+                (KvCacheData::BatchSeqChans(a), KvCacheData::BatchSeqChans(b)) => assert_eq!(a, b),
+                (KvCacheData::BatchHeadSeqChans(a), KvCacheData::BatchHeadSeqChans(b)) => {
+                    assert_eq!(a, b)
+                }
+                _ => panic!("KV cache layout mismatch"),
+            }
+        }
+
+        // Truncated data
+        let result = GeneratorSnapshot::read(&mut &buf[..buf.len() - 1]);
+        assert!(result.is_err());
+
+        // Invalid header
+        let result = GeneratorSnapshot::read(&mut &b"invalid"[..]);
+        assert!(result.is_err());
+
+        // KV cache size which overflows
+        let shape: Vec<u8> = [4u32, 1, 2, 2, 4]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let shape_pos = buf.windows(shape.len()).position(|w| w == shape).unwrap();
+        let mut overflow_buf = buf.clone();
+        for dim in 1..5 {
+            let pos = shape_pos + dim * 4;
+            overflow_buf[pos..pos + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+        let err = GeneratorSnapshot::read(&mut overflow_buf.as_slice())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
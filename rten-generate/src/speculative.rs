@@ -0,0 +1,158 @@
+//! Helpers for speculative decoding.
+//!
+//! See <https://arxiv.org/abs/2211.17192> for a description of the
+//! algorithm.
+
+use rten_tensor::prelude::*;
+use rten_tensor::NdTensorView;
+
+use crate::generator::{Generator, TokenId};
+use crate::metrics::SpeculativeStats;
+
+/// Draft model and state used for speculative decoding.
+pub(crate) struct Draft<'a> {
+    /// Generator which runs the draft model.
+    pub generator: Generator<'a>,
+
+    /// Number of tokens proposed by the draft model in each step.
+    pub n_tokens: usize,
+
+    /// Random number generator used for sampling and rejection.
+    ///
+    /// This is derived from the target generator's sampler on first use, so
+    /// that the draft and sampler can be configured in any order.
+    pub rng: Option<fastrand::Rng>,
+
+    pub stats: SpeculativeStats,
+}
+
+/// Sample a token ID from a probability distribution.
+pub(crate) fn sample_probs(rng: &mut fastrand::Rng, probs: NdTensorView<f32, 1>) -> TokenId {
+    let total: f32 = probs.iter().sum();
+    let target = rng.f32() * total;
+
+    let mut cum_prob = 0.;
+    let mut last_nonzero = 0;
+    for (idx, &prob) in probs.iter().enumerate() {
+        if prob <= 0. {
+            continue;
+        }
+        cum_prob += prob;
+        last_nonzero = idx;
+        if target < cum_prob {
+            return idx as TokenId;
+        }
+    }
+
+    // Fallback in case of rounding errors.
+    last_nonzero as TokenId
+}
+
+/// Decide whether to accept a `token` which was sampled from the draft
+/// model's distribution `draft_probs`, given the target model's distribution
+/// `target_probs`.
+///
+/// The token is accepted with probability `min(1, p(token) / q(token))`,
+/// where `p` and `q` are the target and draft distributions. If rejected, a
+/// replacement is sampled from the normalized residual distribution
+/// `max(0, p - q)`. This ensures that the resulting tokens follow the target
+/// model's distribution.
+///
+/// Returns `None` if the token is accepted or `Some(replacement)` otherwise.
+/// The distributions may have different sizes if the models' vocabularies
+/// differ, in which case missing entries are treated as zero.
+pub(crate) fn verify_draft_token(
+    rng: &mut fastrand::Rng,
+    token: TokenId,
+    target_probs: NdTensorView<f32, 1>,
+    draft_probs: NdTensorView<f32, 1>,
+) -> Option<TokenId> {
+    let p = target_probs.get([token as usize]).copied().unwrap_or(0.);
+    let q = draft_probs.get([token as usize]).copied().unwrap_or(0.);
+
+    if p >= q || rng.f32() * q < p {
+        return None;
+    }
+
+    let mut residual = target_probs.to_tensor();
+    for (i, r) in residual.iter_mut().enumerate() {
+        let q = draft_probs.get([i]).copied().unwrap_or(0.);
+        *r = (*r - q).max(0.);
+    }
+
+    // The residual can be zero if the distributions are equal, up to
+    // rounding errors. In that case fall back to the target distribution.
+    let replacement = if residual.iter().any(|r| *r > 0.) {
+        sample_probs(rng, residual.view())
+    } else {
+        sample_probs(rng, target_probs)
+    };
+    Some(replacement)
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_tensor::prelude::*;
+    use rten_tensor::NdTensor;
+
+    use super::{sample_probs, verify_draft_token};
+
+    #[test]
+    fn test_sample_probs() {
+        let mut rng = fastrand::Rng::with_seed(1234);
+        let probs = NdTensor::from([0., 0.25, 0., 0.75]);
+        let mut counts = [0; 4];
+        for _ in 0..1000 {
+            counts[sample_probs(&mut rng, probs.view()) as usize] += 1;
+        }
+        assert_eq!(counts[0], 0);
+        assert_eq!(counts[2], 0);
+        assert!(counts[1] > 200 && counts[1] < 300);
+    }
+
+    #[test]
+    fn test_verify_draft_token() {
+        let mut rng = fastrand::Rng::with_seed(1234);
+
+        // Deterministic distributions. The token is accepted iff it matches
+        // and the replacement is the target's token.
+        let target = NdTensor::from([0., 1., 0.]);
+        let draft = NdTensor::from([0., 0., 1.]);
+        assert_eq!(
+            verify_draft_token(&mut rng, 2, target.view(), draft.view()),
+            Some(1)
+        );
+        assert_eq!(
+            verify_draft_token(&mut rng, 1, target.view(), target.view()),
+            None
+        );
+
+        // Check that the output distribution matches the target distribution
+        // when tokens are sampled from the draft distribution.
+        let target = NdTensor::from([0.5, 0.3, 0.2]);
+        let draft = NdTensor::from([0.2, 0.2, 0.6]);
+        let n_samples = 20_000;
+        let mut counts = [0; 3];
+        for _ in 0..n_samples {
+            let token = sample_probs(&mut rng, draft.view());
+            let output =
+                verify_draft_token(&mut rng, token, target.view(), draft.view()).unwrap_or(token);
+            counts[output as usize] += 1;
+        }
+        for (count, expected) in counts.iter().zip(target.iter()) {
+            let actual = *count as f32 / n_samples as f32;
+            assert!(
+                (actual - expected).abs() < 0.02,
+                "expected {} got {}",
+                expected,
+                actual
+            );
+        }
+
+        // Draft tokens outside of the target vocabulary are rejected.
+        let target = NdTensor::from([0.5, 0.5]);
+        let draft = NdTensor::from([0., 0., 1.]);
+        let replacement = verify_draft_token(&mut rng, 2, target.view(), draft.view());
+        assert!(matches!(replacement, Some(0 | 1)));
+    }
+}
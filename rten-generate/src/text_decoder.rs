@@ -1,18 +1,151 @@
 //! Iterator adapters to decode token IDs into text using `rten-text`.
 
 use rten_text::models::DecodeError;
-use rten_text::{Tokenizer, TokenizerError};
+use rten_text::{TokenId, Tokenizer, TokenizerError};
 
 use crate::generator::{GeneratorError, GeneratorItem};
 
+/// Incrementally decodes a stream of token IDs into text.
+///
+/// Decoding tokens one at a time with [`Tokenizer::decode`] does not always
+/// produce the same text as decoding the whole sequence. A single token may
+/// encode only part of a UTF-8 character (eg. byte-level BPE and byte-fallback
+/// tokens), and the decoded form of a token can depend on its neighbors (eg.
+/// spaces inserted between words). `StreamDecoder` handles this by decoding
+/// each new token together with a window of preceding tokens and returning
+/// only the newly added text, once it forms complete UTF-8 characters.
+///
+/// Concatenating the outputs of [`add_token`](Self::add_token) produces the
+/// same text as decoding all of the tokens at once.
+pub struct StreamDecoder<'a> {
+    tokenizer: &'a Tokenizer,
+
+    /// Tokens which are still needed to decode new text. Tokens before
+    /// `read_offset` are decoded as context for new tokens.
+    tokens: Vec<TokenId>,
+
+    /// Offset in `tokens` of the first token whose text has not been
+    /// returned yet.
+    read_offset: usize,
+}
+
+impl<'a> StreamDecoder<'a> {
+    /// Create a decoder which decodes tokens using `tokenizer`.
+    pub fn new(tokenizer: &'a Tokenizer) -> StreamDecoder<'a> {
+        StreamDecoder {
+            tokenizer,
+            tokens: Vec::new(),
+            read_offset: 0,
+        }
+    }
+
+    /// Add a token to the stream and return the text that it completes.
+    ///
+    /// Returns `Ok(None)` if more tokens are needed before new text can be
+    /// returned, eg. because the buffered tokens end with an incomplete UTF-8
+    /// sequence. Invalid UTF-8 sequences which are followed by other text are
+    /// replaced by U+FFFD. If the token cannot be decoded, an error is
+    /// returned and the token is discarded.
+    pub fn add_token(&mut self, token: TokenId) -> Result<Option<String>, TokenizerError> {
+        self.tokens.push(token);
+
+        let new_text = match self.decode_new_text() {
+            Ok(text) => text,
+            Err(err) => {
+                self.tokens.pop();
+                return Err(err);
+            }
+        };
+
+        match new_text {
+            // An incomplete sequence at the end is replaced by U+FFFD. Wait
+            // for the tokens which may complete it.
+            Some(text) if !text.is_empty() && !text.ends_with(char::REPLACEMENT_CHARACTER) => {
+                // Keep the tokens for the text just returned as context for
+                // the next token, and discard earlier ones.
+                self.tokens.drain(..self.read_offset);
+                self.read_offset = self.tokens.len();
+
+                Ok(Some(text))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Return the text for buffered tokens which has not been returned yet,
+    /// and reset the decoder.
+    ///
+    /// This should be called after the last token has been added. Unlike
+    /// [`add_token`](Self::add_token), an incomplete UTF-8 sequence at the end
+    /// is returned, replaced by U+FFFD.
+    pub fn flush(&mut self) -> Result<Option<String>, TokenizerError> {
+        if self.read_offset == self.tokens.len() {
+            self.tokens.clear();
+            self.read_offset = 0;
+            return Ok(None);
+        }
+
+        let text = self.decode_new_text()?;
+
+        self.tokens.clear();
+        self.read_offset = 0;
+
+        Ok(text.filter(|t| !t.is_empty()))
+    }
+
+    /// Decode the text for buffered tokens after `read_offset`, with invalid
+    /// UTF-8 sequences replaced by U+FFFD.
+    ///
+    /// Returns `None` if the text for the tokens before `read_offset` is not a
+    /// prefix of the text for all buffered tokens.
+    fn decode_new_text(&self) -> Result<Option<String>, TokenizerError> {
+        match self.decode(..) {
+            Ok(text) => {
+                let prefix = self.decode(..self.read_offset)?;
+                Ok(text.strip_prefix(&prefix).map(|t| t.to_string()))
+            }
+            Err(TokenizerError::DecodeError(DecodeError::InvalidUtf8)) => {
+                // Fall back to decoding the bytes of each token with the
+                // model, so that invalid sequences can be replaced.
+                let model = self.tokenizer.model();
+                let bytes = model.decode_bytes(&self.tokens)?;
+                let prefix = model.decode_bytes(&self.tokens[..self.read_offset])?;
+                Ok(bytes
+                    .strip_prefix(prefix.as_slice())
+                    .map(|b| String::from_utf8_lossy(b).into_owned()))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn decode<R>(&self, range: R) -> Result<String, TokenizerError>
+    where
+        R: std::slice::SliceIndex<[TokenId], Output = [TokenId]>,
+    {
+        self.tokenizer.decode(&self.tokens[range])
+    }
+}
+
 /// Wraps a [`Generator`](crate::Generator) to decode the output token IDs from
 /// the model into text using a [`Tokenizer`].
 ///
 /// This is normally created by calling [`decode`](crate::GeneratorUtils::decode)
-/// on a `Generator`.
+/// on a `Generator`. Tokens are decoded using a [`StreamDecoder`], so
+/// concatenating the output chunks produces the same text as decoding all of
+/// the generated tokens at once.
 pub struct TextDecoder<'a, G: Iterator<Item = GeneratorItem>> {
     generator: G,
-    tokenizer: &'a Tokenizer,
+    decoder: StreamDecoder<'a>,
+
+    /// Strings which end generation when they appear in the output.
+    stop_strings: Vec<String>,
+
+    /// Decoded text which has not been returned yet because it might be the
+    /// start of a stop string.
+    pending: String,
+
+    /// True if a stop string has been found.
+    stopped: bool,
 }
 
 impl<'a, G> TextDecoder<'a, G>
@@ -23,9 +156,64 @@ where
     pub fn wrap(generator: G, tokenizer: &'a Tokenizer) -> TextDecoder<'a, G> {
         TextDecoder {
             generator,
-            tokenizer,
+            decoder: StreamDecoder::new(tokenizer),
+            stop_strings: Vec::new(),
+            pending: String::new(),
+            stopped: false,
         }
     }
+
+    /// Stop generation when the decoded text contains any of `stop_strings`.
+    ///
+    /// Matches can span multiple tokens. The text up to the start of the
+    /// first match is returned, and the stop string itself is not. Text which
+    /// might be the start of a stop string is held back until enough tokens
+    /// have been generated to tell whether it matches.
+    pub fn stop_on_strings<I>(mut self, stop_strings: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.stop_strings = stop_strings
+            .into_iter()
+            .map(|s| s.into())
+            .filter(|s: &String| !s.is_empty())
+            .collect();
+        self
+    }
+}
+
+impl<G: Iterator<Item = GeneratorItem>> TextDecoder<'_, G> {
+    /// Add newly decoded text to the output and return the text which can be
+    /// returned now.
+    ///
+    /// Text which might be the start of a stop string is held back. If a
+    /// stop string is found, the text before it is returned and `stopped` is
+    /// set.
+    fn push_text(&mut self, text: String) -> Option<String> {
+        if self.stop_strings.is_empty() {
+            return Some(text);
+        }
+        self.pending.push_str(&text);
+
+        let stop_pos = self
+            .stop_strings
+            .iter()
+            .filter_map(|s| self.pending.find(s.as_str()))
+            .min();
+        if let Some(pos) = stop_pos {
+            self.stopped = true;
+            self.pending.truncate(pos);
+            let text = std::mem::take(&mut self.pending);
+            return (!text.is_empty()).then_some(text);
+        }
+
+        let n_ready = self.pending.len() - partial_match_len(&self.pending, &self.stop_strings);
+        (n_ready > 0).then(|| {
+            let rest = self.pending.split_off(n_ready);
+            std::mem::replace(&mut self.pending, rest)
+        })
+    }
 }
 
 impl<G: Iterator<Item = GeneratorItem>> Iterator for TextDecoder<'_, G> {
@@ -39,44 +227,78 @@ impl<G: Iterator<Item = GeneratorItem>> Iterator for TextDecoder<'_, G> {
     /// occurs during generation or `None` if the end of output has been
     /// reached.
     fn next(&mut self) -> Option<Self::Item> {
-        // Buffer that holds model output tokens until it forms a valid UTF-8
-        // sequence.
-        let mut token_buf = Vec::new();
+        if self.stopped {
+            return None;
+        }
 
-        for token in self.generator.by_ref() {
+        while let Some(token) = self.generator.next() {
             let token = match token {
                 Ok(tok) => tok,
                 Err(err) => return Some(Err(err)),
             };
 
-            token_buf.push(token);
+            let text = match self.decoder.add_token(token) {
+                Ok(Some(text)) => text,
+                // If the current token sequence doesn't correspond to a
+                // complete UTF-8 sequence, add more tokens until it does.
+                Ok(None) => continue,
+                Err(err) => return Some(Err(GeneratorError::DecodeError(err))),
+            };
+
+            if let Some(text) = self.push_text(text) {
+                return Some(Ok(text));
+            }
+            if self.stopped {
+                return None;
+            }
+        }
 
-            let text = self.tokenizer.decode(&token_buf);
-            match text {
-                Ok(text) => return Some(Ok(text)),
-                Err(TokenizerError::DecodeError(DecodeError::InvalidUtf8)) => {
-                    // If the current token sequence doesn't correspond to a
-                    // complete UTF-8 sequence, add more tokens until it does.
-                    continue;
+        // Decode tokens left over at the end of the output, which may not
+        // form complete UTF-8 characters.
+        match self.decoder.flush() {
+            Ok(Some(text)) => {
+                if let Some(text) = self.push_text(text) {
+                    return Some(Ok(text));
                 }
-                Err(err) => {
-                    return Some(Err(GeneratorError::DecodeError(err)));
+                if self.stopped {
+                    return None;
                 }
             }
+            Ok(None) => {}
+            Err(err) => return Some(Err(GeneratorError::DecodeError(err))),
+        }
+
+        // Return any text held back as a possible stop string prefix.
+        if !self.pending.is_empty() {
+            return Some(Ok(std::mem::take(&mut self.pending)));
         }
 
         None
     }
 }
 
+/// Return the length of the longest suffix of `text` which is a prefix of
+/// any of `stop_strings`.
+fn partial_match_len(text: &str, stop_strings: &[String]) -> usize {
+    text.char_indices()
+        .map(|(i, _)| i)
+        .find(|&i| stop_strings.iter().any(|s| s.starts_with(&text[i..])))
+        .map(|i| text.len() - i)
+        .unwrap_or(0)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
+    use rten_text::decoders::{ByteFallback, Fuse, Sequence};
     use rten_text::models::{Bpe, BpeOptions, WordPiece};
     use rten_text::pre_tokenizers::Split;
     use rten_text::{TokenId, Tokenizer};
 
+    use rten_testing::TestCases;
+
+    use super::StreamDecoder;
     use crate::{GeneratorError, GeneratorUtils};
 
     /// Create a simple WordPiece tokenizer. This is essentially just a lookup
@@ -105,7 +327,10 @@ mod tests {
             .decode(&tokenizer)
             .map(|tok| tok.map_err(|e| e.to_string()))
             .collect();
-        assert_eq!(tokens, ["one", "two", "three"].map(|s| Ok(s.to_string())));
+        // WordPiece inserts spaces between tokens when decoding. These should
+        // be included in the output so that the chunks form the same text as
+        // decoding all tokens at once.
+        assert_eq!(tokens, ["one", " two", " three"].map(|s| Ok(s.to_string())));
     }
 
     #[test]
@@ -147,7 +372,7 @@ mod tests {
             [
                 Ok("one".to_string()),
                 Err("generation error: oh no".to_string()),
-                Ok("three".to_string())
+                Ok(" three".to_string())
             ]
         );
     }
@@ -167,8 +392,208 @@ mod tests {
             [
                 Ok("one".to_string()),
                 Err("decode error: decoding failed: cannot decode unknown token ID 5".to_string()),
-                Ok("three".to_string())
+                Ok(" three".to_string())
             ]
         );
     }
+
+    #[test]
+    fn test_stream_decoder() {
+        let tokenizer = create_bpe_tokenizer();
+        let text = "Hi 😊 there";
+        let token_ids = tokenizer.encode(text, None).unwrap().into_token_ids();
+
+        let mut decoder = StreamDecoder::new(&tokenizer);
+        let mut chunks = Vec::new();
+        let mut n_pending = 0;
+        for &id in &token_ids {
+            match decoder.add_token(id).unwrap() {
+                Some(chunk) => {
+                    chunks.push(chunk);
+                    n_pending = 0;
+                }
+                None => n_pending += 1,
+            }
+            // The emoji is encoded as 4 byte tokens, so up to 3 tokens are
+            // buffered before it is returned.
+            assert!(n_pending < 4);
+        }
+
+        assert!(chunks.contains(&"😊".to_string()));
+        assert_eq!(chunks.concat(), text);
+
+        // Invalid tokens are reported and discarded.
+        let mut decoder = StreamDecoder::new(&tokenizer);
+        assert!(decoder.add_token(100_000).is_err());
+        assert_eq!(
+            decoder.add_token(token_ids[0]).unwrap().as_deref(),
+            Some("H")
+        );
+        assert_eq!(decoder.flush().unwrap(), None);
+
+        // Flushing returns buffered text, replacing incomplete characters.
+        let emoji_ids = tokenizer.encode("😊", None).unwrap().into_token_ids();
+        let mut decoder = StreamDecoder::new(&tokenizer);
+        assert_eq!(
+            decoder.add_token(token_ids[0]).unwrap().as_deref(),
+            Some("H")
+        );
+        for &id in &emoji_ids[..2] {
+            assert_eq!(decoder.add_token(id).unwrap(), None);
+        }
+        assert_eq!(decoder.flush().unwrap().as_deref(), Some("\u{FFFD}"));
+        assert_eq!(decoder.flush().unwrap(), None);
+    }
+
+    #[test]
+    fn test_stream_decoder_invalid_byte_fallback() {
+        let vocab: HashMap<String, TokenId> = [("a", 1), ("b", 2), ("<0xFF>", 3)]
+            .into_iter()
+            .map(|(s, id)| (s.to_string(), id))
+            .collect();
+        let model = WordPiece::from_vocab(vocab, Default::default());
+        let tokenizer = Tokenizer::new(model, Default::default()).with_decoder(Box::new(
+            Sequence::from_vec(vec![Box::new(ByteFallback::new()), Box::new(Fuse::new())]),
+        ));
+
+        // A byte token which is not part of a valid UTF-8 sequence is
+        // replaced, and does not stop later tokens from being decoded.
+        let mut decoder = StreamDecoder::new(&tokenizer);
+        assert_eq!(decoder.add_token(1).unwrap().as_deref(), Some("a"));
+        assert_eq!(decoder.add_token(3).unwrap(), None);
+        assert_eq!(decoder.add_token(2).unwrap().as_deref(), Some("\u{FFFD}b"));
+        assert_eq!(decoder.add_token(3).unwrap(), None);
+        assert_eq!(decoder.flush().unwrap().as_deref(), Some("\u{FFFD}"));
+    }
+
+    #[test]
+    fn test_stream_decoder_invalid_utf8() {
+        let tokenizer = create_bpe_tokenizer();
+        let hi_ids = tokenizer.encode("Hi", None).unwrap().into_token_ids();
+        let there_ids = tokenizer.encode(" there", None).unwrap().into_token_ids();
+        let emoji_ids = tokenizer.encode("😊", None).unwrap().into_token_ids();
+
+        // A continuation byte on its own is invalid. Once it is followed by
+        // other text, it should be replaced rather than causing all the
+        // remaining tokens to be buffered.
+        let mut decoder = StreamDecoder::new(&tokenizer);
+        let mut chunks = Vec::new();
+        for &id in &hi_ids {
+            chunks.extend(decoder.add_token(id).unwrap());
+        }
+        assert_eq!(decoder.add_token(emoji_ids[1]).unwrap(), None);
+        for &id in &there_ids {
+            let chunk = decoder.add_token(id).unwrap();
+            assert!(chunk.is_some());
+            chunks.extend(chunk);
+        }
+        assert_eq!(decoder.flush().unwrap(), None);
+        assert_eq!(chunks.concat(), "Hi\u{FFFD} there");
+
+        // An invalid sequence followed by an incomplete one should wait for
+        // the incomplete sequence to be completed.
+        let mut decoder = StreamDecoder::new(&tokenizer);
+        assert_eq!(decoder.add_token(emoji_ids[1]).unwrap(), None);
+        for &id in &emoji_ids[..3] {
+            assert_eq!(decoder.add_token(id).unwrap(), None);
+        }
+        assert_eq!(
+            decoder.add_token(emoji_ids[3]).unwrap().as_deref(),
+            Some("\u{FFFD}😊")
+        );
+    }
+
+    #[test]
+    fn test_decode_incomplete_utf8_at_end() {
+        let tokenizer = create_bpe_tokenizer();
+        let mut token_ids = tokenizer.encode("Hi ", None).unwrap().into_token_ids();
+        let emoji_ids = tokenizer.encode("😊", None).unwrap().into_token_ids();
+        token_ids.extend(&emoji_ids[..3]);
+
+        // Bytes of an incomplete character at the end of the output are
+        // returned as a replacement character.
+        let chunks: Vec<_> = token_ids
+            .iter()
+            .copied()
+            .map(Ok)
+            .decode(&tokenizer)
+            .map(|chunk| chunk.unwrap())
+            .collect();
+        assert_eq!(chunks.concat(), "Hi \u{FFFD}");
+
+        // Flushed text is still checked for stop strings.
+        let chunks: Vec<_> = token_ids
+            .iter()
+            .copied()
+            .map(Ok)
+            .decode(&tokenizer)
+            .stop_on_strings(["\u{FFFD}"])
+            .map(|chunk| chunk.unwrap())
+            .collect();
+        assert_eq!(chunks.concat(), "Hi ");
+    }
+
+    #[test]
+    fn test_stop_on_strings() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            text: &'a str,
+            stop_strings: &'a [&'a str],
+            expected: &'a str,
+        }
+
+        let cases = [
+            // No stop strings
+            Case {
+                text: "one two three",
+                stop_strings: &[],
+                expected: "one two three",
+            },
+            // Stop string spanning multiple tokens
+            Case {
+                text: "one two three",
+                stop_strings: &["two"],
+                expected: "one ",
+            },
+            // Earliest match wins
+            Case {
+                text: "one two three",
+                stop_strings: &["three", "o t"],
+                expected: "one tw",
+            },
+            // Partial match which is released when it fails to match
+            Case {
+                text: "one two three",
+                stop_strings: &["two four"],
+                expected: "one two three",
+            },
+            // Partial match at the end of the output
+            Case {
+                text: "one two thr",
+                stop_strings: &["three"],
+                expected: "one two thr",
+            },
+            // Multi-byte characters
+            Case {
+                text: "Hi 😊 there",
+                stop_strings: &["😊"],
+                expected: "Hi ",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let tokenizer = create_bpe_tokenizer();
+            let token_ids = tokenizer.encode(case.text, None).unwrap().into_token_ids();
+            let generator = token_ids.into_iter().map(Ok);
+
+            let chunks: Vec<_> = generator
+                .decode(&tokenizer)
+                .stop_on_strings(case.stop_strings.iter().copied())
+                .map(|chunk| chunk.unwrap())
+                .collect();
+
+            assert_eq!(chunks.concat(), case.expected);
+            assert!(chunks.iter().all(|chunk| !chunk.is_empty()));
+        })
+    }
 }
@@ -14,6 +14,9 @@ use std::arch::wasm32::{
 };
 use std::mem::transmute;
 
+#[cfg(target_feature = "relaxed-simd")]
+use std::arch::wasm32::f32x4_relaxed_madd;
+
 use super::{lanes, simd_type};
 use crate::ops::{
     Extend, FloatOps, IntOps, Interleave, MaskOps, NarrowSaturate, NumOps, SignedIntOps,
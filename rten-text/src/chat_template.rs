@@ -0,0 +1,955 @@
+//! Chat templates for formatting conversations as prompts for
+//! instruction-tuned models.
+//!
+//! Instruction-tuned models expect conversations to be formatted in a
+//! model-specific way, using special tokens to mark the start and end of
+//! each message. Hugging Face models specify this format using a
+//! [Jinja](https://jinja.palletsprojects.com/) template in the `chat_template`
+//! field of `tokenizer_config.json`. See the Hugging Face
+//! [chat templating guide](https://huggingface.co/docs/transformers/main/en/chat_templating).
+//!
+//! [`ChatTemplate`] implements the subset of Jinja used by chat templates,
+//! including `if`, `for` and `set` statements, `namespace` objects, common
+//! filters such as `tojson` and `trim`, string methods and the
+//! `raise_exception` function.
+//!
+//! ```no_run
+//! use rten_text::chat_template::{ChatMessage, ChatTemplate, ChatTemplateOptions};
+//! use rten_text::Tokenizer;
+//!
+//! let tokenizer = Tokenizer::from_file("qwen2/tokenizer.json")?;
+//! let template = ChatTemplate::from_tokenizer_config_file("qwen2/tokenizer_config.json")?;
+//!
+//! let messages = [
+//!     ChatMessage::system("You are a helpful assistant."),
+//!     ChatMessage::user("What is the capital of France?"),
+//! ];
+//! let options = ChatTemplateOptions {
+//!     add_generation_prompt: true,
+//!     ..Default::default()
+//! };
+//! let prompt = template.encode(&tokenizer, &messages, options)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tokenizer::{EncodeOptions, TokenId, Tokenizer, TokenizerError};
+
+mod filters;
+mod render;
+mod syntax;
+mod value;
+
+use syntax::Node;
+use value::Value;
+
+/// Errors returned when loading or rendering a [`ChatTemplate`].
+#[derive(Debug)]
+pub enum ChatTemplateError {
+    /// There was an error reading the tokenizer config from a file.
+    IoError(std::io::Error),
+    /// There was an error decoding the tokenizer config.
+    JsonError(serde_json::Error),
+    /// The tokenizer config does not have a `chat_template` field.
+    MissingTemplate,
+    /// The template is not valid, or uses Jinja syntax which is not
+    /// supported.
+    SyntaxError { line: usize, message: String },
+    /// There was an error evaluating the template, such as calling an
+    /// unknown filter or applying an operator to unsupported types.
+    RenderError(String),
+    /// The template raised an error using `raise_exception`. Templates use
+    /// this to reject conversations they cannot format, such as ones where
+    /// roles do not alternate between user and assistant.
+    Exception(String),
+    /// Encoding the rendered template failed.
+    TokenizerError(TokenizerError),
+}
+
+impl fmt::Display for ChatTemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IoError(err) => fmt::Display::fmt(err, f),
+            Self::JsonError(err) => write!(f, "JSON error {}", err),
+            Self::MissingTemplate => write!(f, "tokenizer config has no chat template"),
+            Self::SyntaxError { line, message } => {
+                write!(f, "template syntax error on line {}: {}", line, message)
+            }
+            Self::RenderError(err) => write!(f, "template rendering failed: {}", err),
+            Self::Exception(msg) => write!(f, "template raised exception: {}", msg),
+            Self::TokenizerError(err) => write!(f, "tokenizer error: {}", err),
+        }
+    }
+}
+
+impl Error for ChatTemplateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IoError(err) => Some(err),
+            Self::JsonError(err) => Some(err),
+            Self::TokenizerError(err) => Some(err),
+            Self::MissingTemplate
+            | Self::SyntaxError { .. }
+            | Self::RenderError(_)
+            | Self::Exception(_) => None,
+        }
+    }
+}
+
+impl From<TokenizerError> for ChatTemplateError {
+    fn from(val: TokenizerError) -> Self {
+        ChatTemplateError::TokenizerError(val)
+    }
+}
+
+/// A message in a conversation.
+///
+/// [`ChatTemplate::render`] accepts any messages which can be serialized to
+/// JSON objects. This type covers the common case of messages which have
+/// only a role and text content. Other fields used by some templates, such as
+/// `tool_calls`, can be passed using [`serde_json::Value`] messages.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChatMessage {
+    /// The author of the message. This is usually one of "system", "user",
+    /// "assistant" or "tool".
+    pub role: String,
+
+    /// The text content of the message.
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    /// Create a message with the "system" role.
+    pub fn system(content: &str) -> ChatMessage {
+        Self::new("system", content)
+    }
+
+    /// Create a message with the "user" role.
+    pub fn user(content: &str) -> ChatMessage {
+        Self::new("user", content)
+    }
+
+    /// Create a message with the "assistant" role.
+    pub fn assistant(content: &str) -> ChatMessage {
+        Self::new("assistant", content)
+    }
+}
+
+/// Options for [`ChatTemplate::render`] and [`ChatTemplate::encode`].
+#[derive(Clone, Default)]
+pub struct ChatTemplateOptions<'a> {
+    /// Append the tokens that start a new assistant message. This should be
+    /// set when generating a response to the conversation.
+    pub add_generation_prompt: bool,
+
+    /// Definitions of tools which the model can call, in the JSON schema
+    /// format used by the Hugging Face
+    /// [tool use guide](https://huggingface.co/docs/transformers/main/en/chat_templating#advanced-tool-use--function-calling).
+    pub tools: Option<&'a [serde_json::Value]>,
+}
+
+/// Special token fields of `tokenizer_config.json` which are made available
+/// to templates.
+const SPECIAL_TOKEN_FIELDS: [&str; 7] = [
+    "bos_token",
+    "eos_token",
+    "unk_token",
+    "sep_token",
+    "pad_token",
+    "cls_token",
+    "mask_token",
+];
+
+/// A chat template which formats a conversation as a prompt for a model.
+///
+/// See the [module-level documentation](self) for an overview.
+pub struct ChatTemplate {
+    /// Template used by default.
+    template: Vec<Node>,
+
+    /// Template used when tools are specified, if different from the
+    /// default.
+    tool_use_template: Option<Vec<Node>>,
+
+    /// Variables available to the template, in addition to the messages and
+    /// options.
+    variables: Vec<(String, Value)>,
+}
+
+/// Format of the `chat_template` field in `tokenizer_config.json`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TemplateConfig {
+    Single(String),
+    Named(Vec<NamedTemplate>),
+}
+
+#[derive(Deserialize)]
+struct NamedTemplate {
+    name: String,
+    template: String,
+}
+
+impl ChatTemplate {
+    /// Parse a chat template from Jinja source.
+    pub fn new(source: &str) -> Result<ChatTemplate, ChatTemplateError> {
+        Ok(ChatTemplate {
+            template: syntax::parse(source)?,
+            tool_use_template: None,
+            variables: Vec::new(),
+        })
+    }
+
+    /// Load the chat template from a `tokenizer_config.json` file.
+    ///
+    /// See [`from_tokenizer_config`](Self::from_tokenizer_config).
+    pub fn from_tokenizer_config_file<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<ChatTemplate, ChatTemplateError> {
+        let json = std::fs::read_to_string(path).map_err(ChatTemplateError::IoError)?;
+        Self::from_tokenizer_config(&json)
+    }
+
+    /// Load the chat template from the JSON content of a Hugging Face
+    /// `tokenizer_config.json` file.
+    ///
+    /// Special tokens such as `bos_token` and `eos_token` are read from the
+    /// config and made available to the template. If the config has several
+    /// named templates, the "default" template is used, or the "tool_use"
+    /// template when tools are specified.
+    pub fn from_tokenizer_config(json: &str) -> Result<ChatTemplate, ChatTemplateError> {
+        let config: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(json).map_err(ChatTemplateError::JsonError)?;
+
+        let template_config = config
+            .get("chat_template")
+            .filter(|template| !template.is_null())
+            .ok_or(ChatTemplateError::MissingTemplate)?;
+        let template_config: TemplateConfig = serde_json::from_value(template_config.clone())
+            .map_err(ChatTemplateError::JsonError)?;
+
+        let mut template = match template_config {
+            TemplateConfig::Single(source) => ChatTemplate::new(&source)?,
+            TemplateConfig::Named(templates) => {
+                let find_template = |name: &str| {
+                    templates
+                        .iter()
+                        .find(|t| t.name == name)
+                        .map(|t| syntax::parse(&t.template))
+                        .transpose()
+                };
+                let default =
+                    find_template("default")?.ok_or(ChatTemplateError::MissingTemplate)?;
+                ChatTemplate {
+                    template: default,
+                    tool_use_template: find_template("tool_use")?,
+                    variables: Vec::new(),
+                }
+            }
+        };
+
+        for field in SPECIAL_TOKEN_FIELDS {
+            // Special tokens are either strings or objects in the same
+            // format as added tokens in `tokenizer.json`.
+            let token = match config.get(field) {
+                Some(serde_json::Value::String(token)) => Some(token.as_str()),
+                Some(serde_json::Value::Object(token)) => {
+                    token.get("content").and_then(|content| content.as_str())
+                }
+                _ => None,
+            };
+            if let Some(token) = token {
+                template = template.with_variable(field, token.into());
+            }
+        }
+
+        Ok(template)
+    }
+
+    /// Set a variable which is available to the template.
+    ///
+    /// This can be used to set special tokens such as `bos_token` when the
+    /// template is not loaded from a tokenizer config, or to pass additional
+    /// variables that a particular template uses.
+    pub fn with_variable(mut self, name: &str, value: serde_json::Value) -> Self {
+        let value = Value::from_json(&value);
+        match self.variables.iter_mut().find(|(var, _)| var == name) {
+            Some(var) => var.1 = value,
+            None => self.variables.push((name.to_string(), value)),
+        }
+        self
+    }
+
+    /// Render a conversation as text.
+    ///
+    /// `messages` are usually [`ChatMessage`]s, but can be any type that
+    /// serializes to a JSON object.
+    pub fn render<M: Serialize>(
+        &self,
+        messages: &[M],
+        options: ChatTemplateOptions,
+    ) -> Result<String, ChatTemplateError> {
+        let messages = serde_json::to_value(messages).map_err(ChatTemplateError::JsonError)?;
+        let tools = match options.tools {
+            Some(tools) => Value::list(tools.iter().map(Value::from_json).collect()),
+            None => Value::None,
+        };
+
+        let template = match (&self.tool_use_template, options.tools) {
+            (Some(tool_use_template), Some(_)) => tool_use_template,
+            _ => &self.template,
+        };
+
+        let mut globals = self.variables.clone();
+        globals.extend([
+            ("messages".to_string(), Value::from_json(&messages)),
+            ("tools".to_string(), tools),
+            (
+                "add_generation_prompt".to_string(),
+                Value::Bool(options.add_generation_prompt),
+            ),
+        ]);
+
+        render::render(template, globals)
+    }
+
+    /// Render a conversation and encode it into token IDs.
+    ///
+    /// Special tokens in the rendered text, such as `<|im_start|>`, are
+    /// encoded as their token IDs, provided they are added tokens in the
+    /// tokenizer. The tokenizer's post-processor is not applied, since the
+    /// template is responsible for adding any special tokens.
+    pub fn encode<M: Serialize>(
+        &self,
+        tokenizer: &Tokenizer,
+        messages: &[M],
+        options: ChatTemplateOptions,
+    ) -> Result<Vec<TokenId>, ChatTemplateError> {
+        let text = self.render(messages, options)?;
+        let encoded = tokenizer.encode(
+            text.as_str(),
+            Some(EncodeOptions {
+                skip_post_processor: true,
+                ..Default::default()
+            }),
+        )?;
+        Ok(encoded.into_token_ids())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rten_testing::TestCases;
+    use serde_json::json;
+
+    use super::{ChatMessage, ChatTemplate, ChatTemplateError, ChatTemplateOptions};
+    use crate::models::WordPiece;
+    use crate::pre_tokenizers;
+    use crate::tokenizer::{AddedToken, Tokenizer, TokenizerOptions};
+
+    // Chat template from Qwen2.5's `tokenizer_config.json`.
+    const QWEN2_5_TEMPLATE: &str = r#"{%- if tools %}
+    {{- '<|im_start|>system\n' }}
+    {%- if messages[0]['role'] == 'system' %}
+        {{- messages[0]['content'] }}
+    {%- else %}
+        {{- 'You are Qwen, created by Alibaba Cloud. You are a helpful assistant.' }}
+    {%- endif %}
+    {{- "\n\n# Tools\n\nYou may call one or more functions to assist with the user query.\n\nYou are provided with function signatures within <tools></tools> XML tags:\n<tools>" }}
+    {%- for tool in tools %}
+        {{- "\n" }}
+        {{- tool | tojson }}
+    {%- endfor %}
+    {{- "\n</tools>\n\nFor each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:\n<tool_call>\n{\"name\": <function-name>, \"arguments\": <args-json-object>}\n</tool_call><|im_end|>\n" }}
+{%- else %}
+    {%- if messages[0]['role'] == 'system' %}
+        {{- '<|im_start|>system\n' + messages[0]['content'] + '<|im_end|>\n' }}
+    {%- else %}
+        {{- '<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a helpful assistant.<|im_end|>\n' }}
+    {%- endif %}
+{%- endif %}
+{%- for message in messages %}
+    {%- if (message.role == "user") or (message.role == "system" and not loop.first) or (message.role == "assistant" and not message.tool_calls) %}
+        {{- '<|im_start|>' + message.role + '\n' + message.content + '<|im_end|>' + '\n' }}
+    {%- elif message.role == "assistant" %}
+        {{- '<|im_start|>' + message.role }}
+        {%- if message.content %}
+            {{- '\n' + message.content }}
+        {%- endif %}
+        {%- for tool_call in message.tool_calls %}
+            {%- if tool_call.function is defined %}
+                {%- set tool_call = tool_call.function %}
+            {%- endif %}
+            {{- '\n<tool_call>\n{"name": "' }}
+            {{- tool_call.name }}
+            {{- '", "arguments": ' }}
+            {{- tool_call.arguments | tojson }}
+            {{- '}\n</tool_call>' }}
+        {%- endfor %}
+        {{- '<|im_end|>\n' }}
+    {%- elif message.role == "tool" %}
+        {%- if (loop.index0 == 0) or (messages[loop.index0 - 1].role != "tool") %}
+            {{- '<|im_start|>user' }}
+        {%- endif %}
+        {{- '\n<tool_response>\n' }}
+        {{- message.content }}
+        {{- '\n</tool_response>' }}
+        {%- if loop.last or (messages[loop.index0 + 1].role != "tool") %}
+            {{- '<|im_end|>\n' }}
+        {%- endif %}
+    {%- endif %}
+{%- endfor %}
+{%- if add_generation_prompt %}
+    {{- '<|im_start|>assistant\n' }}
+{%- endif %}
+"#;
+
+    // Simplified version of the Llama 3 chat template, which uses
+    // `namespace`, `raise_exception` and the `bos_token` variable.
+    const LLAMA3_TEMPLATE: &str = r#"{{- bos_token }}
+{%- set ns = namespace(expect_user=true) %}
+{%- for message in messages %}
+    {%- if (message.role == 'user') != ns.expect_user and message.role != 'system' %}
+        {{- raise_exception('Conversation roles must alternate user/assistant/user/assistant/...') }}
+    {%- endif %}
+    {%- if message.role != 'system' %}
+        {%- set ns.expect_user = not ns.expect_user %}
+    {%- endif %}
+    {{- '<|start_header_id|>' + message['role'] + '<|end_header_id|>\n\n' + message['content'] | trim + '<|eot_id|>' }}
+{%- endfor %}
+{%- if add_generation_prompt %}
+    {{- '<|start_header_id|>assistant<|end_header_id|>\n\n' }}
+{%- endif %}"#;
+
+    fn qwen_messages() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::system("You are a helpful assistant."),
+            ChatMessage::user("Hello"),
+            ChatMessage::assistant("Hi there"),
+            ChatMessage::user("How are you?"),
+        ]
+    }
+
+    fn generation_prompt() -> ChatTemplateOptions<'static> {
+        ChatTemplateOptions {
+            add_generation_prompt: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_qwen() {
+        let template = ChatTemplate::new(QWEN2_5_TEMPLATE).unwrap();
+
+        let text = template
+            .render(&qwen_messages(), generation_prompt())
+            .unwrap();
+        assert_eq!(
+            text,
+            "<|im_start|>system\nYou are a helpful assistant.<|im_end|>\n\
+             <|im_start|>user\nHello<|im_end|>\n\
+             <|im_start|>assistant\nHi there<|im_end|>\n\
+             <|im_start|>user\nHow are you?<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+
+        // Default system prompt, no generation prompt.
+        let text = template
+            .render(&[ChatMessage::user("Hello")], Default::default())
+            .unwrap();
+        assert_eq!(
+            text,
+            "<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a helpful assistant.<|im_end|>\n\
+             <|im_start|>user\nHello<|im_end|>\n"
+        );
+    }
+
+    #[test]
+    fn test_render_qwen_tools() {
+        let template = ChatTemplate::new(QWEN2_5_TEMPLATE).unwrap();
+
+        // Keys of tools and messages are rendered in their original order.
+        let tools = [json!({
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "parameters": {"type": "object"},
+            }
+        })];
+        let messages = [
+            json!({"role": "user", "content": "Weather in Paris?"}),
+            json!({
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": {"unit": "celsius", "city": "Paris"}}
+                }]
+            }),
+            json!({"role": "tool", "content": "Sunny"}),
+        ];
+
+        let text = template
+            .render(
+                &messages,
+                ChatTemplateOptions {
+                    add_generation_prompt: true,
+                    tools: Some(&tools),
+                },
+            )
+            .unwrap();
+
+        assert_eq!(
+            text,
+            "<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a helpful assistant.\n\n\
+             # Tools\n\nYou may call one or more functions to assist with the user query.\n\n\
+             You are provided with function signatures within <tools></tools> XML tags:\n<tools>\n\
+             {\"type\": \"function\", \"function\": {\"name\": \"get_weather\", \"parameters\": {\"type\": \"object\"}}}\n\
+             </tools>\n\nFor each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:\n\
+             <tool_call>\n{\"name\": <function-name>, \"arguments\": <args-json-object>}\n</tool_call><|im_end|>\n\
+             <|im_start|>user\nWeather in Paris?<|im_end|>\n\
+             <|im_start|>assistant\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"unit\": \"celsius\", \"city\": \"Paris\"}}\n</tool_call><|im_end|>\n\
+             <|im_start|>user\n<tool_response>\nSunny\n</tool_response><|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn test_render_llama() {
+        let template = ChatTemplate::new(LLAMA3_TEMPLATE)
+            .unwrap()
+            .with_variable("bos_token", "<|begin_of_text|>".into());
+
+        let text = template
+            .render(
+                &[
+                    ChatMessage::system("Be brief."),
+                    ChatMessage::user("  Hi  "),
+                ],
+                generation_prompt(),
+            )
+            .unwrap();
+        assert_eq!(
+            text,
+            "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+
+        let err = template
+            .render(
+                &[ChatMessage::user("Hi"), ChatMessage::user("Hi again")],
+                Default::default(),
+            )
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, ChatTemplateError::Exception(msg) if msg.starts_with("Conversation roles must alternate"))
+        );
+    }
+
+    #[test]
+    fn test_from_tokenizer_config() {
+        let config = json!({
+            "bos_token": {"content": "<s>", "special": true},
+            "eos_token": "</s>",
+            "chat_template": [
+                {"name": "default", "template": "{{ bos_token }}{{ messages[0].content }}{{ eos_token }}"},
+                {"name": "tool_use", "template": "{{ tools | length }} tools"},
+            ],
+        });
+        let template = ChatTemplate::from_tokenizer_config(&config.to_string()).unwrap();
+
+        let messages = [ChatMessage::user("Hi")];
+        let text = template.render(&messages, Default::default()).unwrap();
+        assert_eq!(text, "<s>Hi</s>");
+
+        let tools = [json!({"type": "function"})];
+        let options = ChatTemplateOptions {
+            tools: Some(&tools),
+            ..Default::default()
+        };
+        let text = template.render(&messages, options).unwrap();
+        assert_eq!(text, "1 tools");
+
+        let err = ChatTemplate::from_tokenizer_config("{}").err().unwrap();
+        assert!(matches!(err, ChatTemplateError::MissingTemplate));
+    }
+
+    #[test]
+    fn test_encode() {
+        let vocab: HashMap<_, _> = ["[UNK]", "[CLS]", "[SEP]", "user", "hello", "world"]
+            .iter()
+            .enumerate()
+            .map(|(i, token)| (token.to_string(), i as u32))
+            .collect();
+        let tokenizer = Tokenizer::new(
+            WordPiece::from_vocab(vocab, Default::default()),
+            TokenizerOptions {
+                cls_token: Some("[CLS]"),
+                sep_token: Some("[SEP]"),
+            },
+        )
+        .with_pre_tokenizer(Box::new(pre_tokenizers::Bert::new()))
+        .with_added_tokens(vec![
+            AddedToken::special(10, "<|im_start|>"),
+            AddedToken::special(11, "<|im_end|>"),
+        ]);
+        let template = ChatTemplate::new(
+            "{% for message in messages %}<|im_start|>{{ message.role }} {{ message.content }}<|im_end|>{% endfor %}",
+        )
+        .unwrap();
+
+        let token_ids = template
+            .encode(
+                &tokenizer,
+                &[ChatMessage::user("hello world")],
+                Default::default(),
+            )
+            .unwrap();
+
+        // The special tokens in the template are encoded, but the `[CLS]` and
+        // `[SEP]` tokens from the post-processor are not added.
+        assert_eq!(token_ids, [10, 3, 4, 5, 11]);
+    }
+
+    #[test]
+    fn test_whitespace_control() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            template: &'a str,
+            expected: &'a str,
+        }
+
+        let cases = [
+            // Newline after a statement is removed (`trim_blocks`).
+            Case {
+                template: "{% if true %}\nyes\n{% endif %}\n",
+                expected: "yes\n",
+            },
+            // Indentation before a statement is removed (`lstrip_blocks`).
+            Case {
+                template: "a\n    {% if true %}b{% endif %}",
+                expected: "a\nb",
+            },
+            // Whitespace before a statement which doesn't start a line is
+            // kept.
+            Case {
+                template: "a  {% if true %}b{% endif %}",
+                expected: "a  b",
+            },
+            // Newline after an expression is kept.
+            Case {
+                template: "{{ 'a' }}\nb",
+                expected: "a\nb",
+            },
+            // `-` strips all whitespace.
+            Case {
+                template: "a  \n  {{- 'b' -}}  \n  c",
+                expected: "abc",
+            },
+            // `+` disables `lstrip_blocks` and `trim_blocks`.
+            Case {
+                template: "  {%+ if true +%}\na{% endif %}",
+                expected: "  \na",
+            },
+            // Comments.
+            Case {
+                template: "a\n  {# comment #}\nb",
+                expected: "a\nb",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let template = ChatTemplate::new(case.template).unwrap();
+            let messages: &[ChatMessage] = &[];
+            let output = template.render(messages, Default::default()).unwrap();
+            assert_eq!(output, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_expressions() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            expr: &'a str,
+            expected: &'a str,
+        }
+
+        let cases = [
+            // Literals
+            Case {
+                expr: "'a' \"b\"",
+                expected: "ab",
+            },
+            Case {
+                expr: "[1, 'two', none, true, 1.5]",
+                expected: "[1, 'two', None, True, 1.5]",
+            },
+            Case {
+                expr: "{'a': 1}",
+                expected: "{'a': 1}",
+            },
+            // Arithmetic
+            Case {
+                expr: "1 + 2 * 3 - 4",
+                expected: "3",
+            },
+            Case {
+                expr: "[7 // 2, 7 % 3, -7 // 2, 7 / 2, 2 ** 10]",
+                expected: "[3, 1, -4, 3.5, 1024]",
+            },
+            Case {
+                expr: "'ab' * 2 ~ 1",
+                expected: "abab1",
+            },
+            // Comparisons and logic
+            Case {
+                expr: "1 < 2 and 'a' == 'a' and not (1 > 2)",
+                expected: "True",
+            },
+            Case {
+                expr: "'' or 'default'",
+                expected: "default",
+            },
+            Case {
+                expr: "['b' in 'abc', 2 in [1, 2], 'x' not in {'x': 1}]",
+                expected: "[True, True, False]",
+            },
+            // Conditional expressions
+            Case {
+                expr: "'yes' if 1 else 'no'",
+                expected: "yes",
+            },
+            Case {
+                expr: "'yes' if 0",
+                expected: "",
+            },
+            // Indexing and slicing
+            Case {
+                expr: "[1, 2, 3][-1]",
+                expected: "3",
+            },
+            Case {
+                expr: "[1, 2, 3][1:]",
+                expected: "[2, 3]",
+            },
+            Case {
+                expr: "'hello'[::-1]",
+                expected: "olleh",
+            },
+            Case {
+                expr: "{'a': {'b': 'c'}}.a.b",
+                expected: "c",
+            },
+            // Undefined values
+            Case {
+                expr: "missing",
+                expected: "",
+            },
+            Case {
+                expr: "missing.attr is defined",
+                expected: "False",
+            },
+            // Filters
+            Case {
+                expr: "'  Hi  ' | trim | upper",
+                expected: "HI",
+            },
+            Case {
+                expr: "[1, 2, 3] | length",
+                expected: "3",
+            },
+            Case {
+                expr: "{'b': [1, 'x'], 'a': none} | tojson",
+                expected: r#"{"b": [1, "x"], "a": null}"#,
+            },
+            Case {
+                expr: "{'a': [1]} | tojson(indent=2)",
+                expected: "{\n  \"a\": [\n    1\n  ]\n}",
+            },
+            Case {
+                expr: "missing | default('x')",
+                expected: "x",
+            },
+            Case {
+                expr: "['a', 'b'] | join(', ')",
+                expected: "a, b",
+            },
+            Case {
+                expr: "[{'r': 'a'}, {'r': 'b'}] | selectattr('r', 'equalto', 'b') | list",
+                expected: "[{'r': 'b'}]",
+            },
+            Case {
+                expr: "[{'r': 'a'}, {'r': 'b'}] | map(attribute='r') | join",
+                expected: "ab",
+            },
+            Case {
+                expr: "{'a': 1} | items | list",
+                expected: "[['a', 1]]",
+            },
+            // Tests
+            Case {
+                expr: "[none is none, 1 is string, 'a' is string, 3 is odd, [] is iterable]",
+                expected: "[True, False, True, True, True]",
+            },
+            Case {
+                expr: "1 is not none",
+                expected: "True",
+            },
+            // Methods
+            Case {
+                expr: "'  a b  '.strip().split(' ')",
+                expected: "['a', 'b']",
+            },
+            Case {
+                expr: "['<think>x'.startswith('<think>'), 'abc'.endswith(('x', 'c'))]",
+                expected: "[True, True]",
+            },
+            Case {
+                expr: "'a-b-c'.split('-', 1)",
+                expected: "['a', 'b-c']",
+            },
+            Case {
+                expr: "{'a': 1}.get('b', 2)",
+                expected: "2",
+            },
+            Case {
+                expr: "range(3)",
+                expected: "[0, 1, 2]",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let source = format!("{{{{ {} }}}}", case.expr);
+            let template = ChatTemplate::new(&source).unwrap();
+            let messages: &[ChatMessage] = &[];
+            let output = template.render(messages, Default::default()).unwrap();
+            assert_eq!(output, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_statements() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            template: &'a str,
+            expected: &'a str,
+        }
+
+        let cases = [
+            Case {
+                template: "{% if false %}a{% elif true %}b{% else %}c{% endif %}",
+                expected: "b",
+            },
+            Case {
+                template: "{% for x in [1, 2, 3] %}{{ loop.index }}{{ x }}{% if not loop.last %},{% endif %}{% endfor %}",
+                expected: "11,22,33",
+            },
+            Case {
+                template: "{% for k, v in {'a': 1, 'b': 2}.items() %}{{ k }}={{ v }};{% endfor %}",
+                expected: "a=1;b=2;",
+            },
+            Case {
+                template: "{% for x in [1, 2, 3, 4] if x is even %}{{ x }}{% endfor %}",
+                expected: "24",
+            },
+            Case {
+                template: "{% for x in [] %}a{% else %}empty{% endfor %}",
+                expected: "empty",
+            },
+            Case {
+                template: "{% for x in [1, 2, 3] %}{% if x == 2 %}{% continue %}{% endif %}{% if x == 3 %}{% break %}{% endif %}{{ x }}{% endfor %}",
+                expected: "1",
+            },
+            // Variables set in a loop are not visible outside it, unlike
+            // namespace attributes.
+            Case {
+                template: "{% set x = 0 %}{% set ns = namespace(x=0) %}{% for i in [1, 2] %}{% set x = i %}{% set ns.x = i %}{% endfor %}{{ x }} {{ ns.x }}",
+                expected: "0 2",
+            },
+            Case {
+                template: "{% set a, b = [1, 2] %}{{ b }}{{ a }}",
+                expected: "21",
+            },
+            Case {
+                template: "{% set x %}block {{ 1 }}{% endset %}{{ x | upper }}",
+                expected: "BLOCK 1",
+            },
+            Case {
+                template: "{% generation %}text{% endgeneration %}",
+                expected: "text",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let template = ChatTemplate::new(case.template).unwrap();
+            let messages: &[ChatMessage] = &[];
+            let output = template.render(messages, Default::default()).unwrap();
+            assert_eq!(output, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_errors() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            template: &'a str,
+            expected: &'a str,
+        }
+
+        let cases = [
+            Case {
+                template: "{% if true %}",
+                expected: "template syntax error on line 1: unexpected end of template, expected \"endif\"",
+            },
+            Case {
+                template: "line 1\n{{ 1 + }}",
+                expected: "template syntax error on line 2: unexpected \"}}\"",
+            },
+            Case {
+                template: "{% macro foo() %}{% endmacro %}",
+                expected: "template syntax error on line 1: unsupported statement \"macro\"",
+            },
+            Case {
+                template: "{{ 'a' + 1 }}",
+                expected: "template rendering failed: unsupported operand types for Add: string and integer",
+            },
+            Case {
+                template: "{{ 1 | nonexistent }}",
+                expected: "template rendering failed: unknown filter \"nonexistent\"",
+            },
+            Case {
+                template: "{{ foo() }}",
+                expected: "template rendering failed: unknown function \"foo\"",
+            },
+            Case {
+                template: "{{ raise_exception('Bad input') }}",
+                expected: "template raised exception: Bad input",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let messages: &[ChatMessage] = &[];
+            let err = ChatTemplate::new(case.template)
+                .and_then(|template| template.render(messages, Default::default()))
+                .err()
+                .unwrap();
+            assert_eq!(err.to_string(), case.expected);
+        })
+    }
+}
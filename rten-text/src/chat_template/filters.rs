@@ -0,0 +1,745 @@
+//! Filters, tests and methods available to chat templates.
+
+use super::render::{contains, get_attr, render_error, repeat_len, CallArgs};
+use super::value::Value;
+use super::ChatTemplateError;
+
+/// Return the length of a string, list or map.
+fn length(value: &Value) -> Result<usize, ChatTemplateError> {
+    match value {
+        Value::Undefined => Ok(0),
+        Value::Str(s) => Ok(s.chars().count()),
+        Value::List(items) => Ok(items.len()),
+        Value::Map(map) => Ok(map.len()),
+        other => Err(render_error(format!("{} has no length", other.type_name()))),
+    }
+}
+
+fn iter_items(value: &Value) -> Result<Vec<Value>, ChatTemplateError> {
+    value
+        .iter_items()
+        .ok_or_else(|| render_error(format!("{} is not iterable", value.type_name())))
+}
+
+fn expect_str<'a>(value: &'a Value, context: &str) -> Result<&'a str, ChatTemplateError> {
+    value.as_str().ok_or_else(|| {
+        render_error(format!(
+            "{} expects a string, found {}",
+            context,
+            value.type_name()
+        ))
+    })
+}
+
+/// Uppercase the first character of a string and lowercase the rest.
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Uppercase the first letter of each word and lowercase the rest.
+fn title(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut prev_is_letter = false;
+    for ch in s.chars() {
+        if prev_is_letter {
+            out.extend(ch.to_lowercase());
+        } else {
+            out.extend(ch.to_uppercase());
+        }
+        prev_is_letter = ch.is_alphabetic();
+    }
+    out
+}
+
+/// Strip characters from one or both ends of a string, as in Python's
+/// `str.strip`. If `chars` is `None`, whitespace is stripped.
+fn strip<'a>(s: &'a str, chars: Option<&str>, start: bool, end: bool) -> &'a str {
+    let should_strip = |ch: char| match chars {
+        Some(chars) => chars.contains(ch),
+        None => ch.is_whitespace(),
+    };
+    let s = if start {
+        s.trim_start_matches(should_strip)
+    } else {
+        s
+    };
+    if end {
+        s.trim_end_matches(should_strip)
+    } else {
+        s
+    }
+}
+
+/// Split a string as in Python's `str.split`.
+fn split(
+    s: &str,
+    sep: Option<&str>,
+    maxsplit: Option<i64>,
+) -> Result<Vec<Value>, ChatTemplateError> {
+    let maxsplit = maxsplit.filter(|n| *n >= 0).map(|n| n as usize);
+    let parts: Vec<&str> = match sep {
+        Some("") => return Err(render_error("empty separator")),
+        Some(sep) => match maxsplit {
+            Some(n) => s.splitn(n + 1, sep).collect(),
+            None => s.split(sep).collect(),
+        },
+        None => {
+            let mut parts = Vec::new();
+            let mut rest = s.trim_start();
+            while !rest.is_empty() {
+                if maxsplit == Some(parts.len()) {
+                    parts.push(rest);
+                    break;
+                }
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                parts.push(&rest[..end]);
+                rest = rest[end..].trim_start();
+            }
+            parts
+        }
+    };
+    Ok(parts.into_iter().map(Value::str).collect())
+}
+
+/// Replace occurrences of `old` with `new`, up to `count` times if given.
+fn replace(s: &str, old: &str, new: &str, count: Option<i64>) -> String {
+    match count.filter(|n| *n >= 0) {
+        Some(n) => s.replacen(old, new, n as usize),
+        None => s.replace(old, new),
+    }
+}
+
+/// Indent each line of a string after the first, as in Jinja's `indent`
+/// filter.
+fn indent(s: &str, indention: &str, first: bool, blank: bool) -> String {
+    let mut out = String::new();
+    if first {
+        out.push_str(indention);
+    }
+    for (i, line) in s.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+            if blank || !line.is_empty() {
+                out.push_str(indention);
+            }
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+/// Apply a test to each item in a sequence and keep those where the result
+/// matches `keep`.
+///
+/// If `attr` is set, the test is applied to that attribute of each item.
+/// If `test_name` is `None`, the truthiness of the item is tested.
+fn select(
+    items: Vec<Value>,
+    attr: Option<&str>,
+    test_name: Option<&str>,
+    test_args: &CallArgs,
+    keep: bool,
+) -> Result<Value, ChatTemplateError> {
+    let mut selected = Vec::new();
+    for item in items {
+        let tested = match attr {
+            Some(attr) => get_attr(&item, attr),
+            None => item.clone(),
+        };
+        let result = match test_name {
+            Some(name) => apply_test(name, &tested, test_args)?,
+            None => tested.is_truthy(),
+        };
+        if result == keep {
+            selected.push(item);
+        }
+    }
+    Ok(Value::list(selected))
+}
+
+/// Apply a filter (`value | name(args)`).
+pub fn apply_filter(name: &str, value: Value, args: &CallArgs) -> Result<Value, ChatTemplateError> {
+    let result = match name {
+        "abs" => match value {
+            Value::Int(i) => Value::Int(
+                i.checked_abs()
+                    .ok_or_else(|| render_error("integer overflow"))?,
+            ),
+            Value::Float(f) => Value::Float(f.abs()),
+            other => {
+                return Err(render_error(format!(
+                    "abs expects a number, found {}",
+                    other.type_name()
+                )))
+            }
+        },
+        "capitalize" => Value::str(&capitalize(expect_str(&value, name)?)),
+        "count" | "length" => Value::Int(length(&value)? as i64),
+        "default" | "d" => {
+            let use_default = match args.get(1, "boolean") {
+                Some(boolean) if boolean.is_truthy() => !value.is_truthy(),
+                _ => matches!(value, Value::Undefined),
+            };
+            if use_default {
+                args.get(0, "default_value")
+                    .cloned()
+                    .unwrap_or(Value::str(""))
+            } else {
+                value
+            }
+        }
+        "first" => iter_items(&value)?
+            .into_iter()
+            .next()
+            .unwrap_or(Value::Undefined),
+        "last" => iter_items(&value)?
+            .into_iter()
+            .next_back()
+            .unwrap_or(Value::Undefined),
+        "float" => match &value {
+            Value::Int(i) => Value::Float(*i as f64),
+            Value::Float(_) => value,
+            Value::Bool(b) => Value::Float(if *b { 1. } else { 0. }),
+            Value::Str(s) => Value::Float(s.trim().parse().unwrap_or(0.)),
+            _ => Value::Float(0.),
+        },
+        "int" => match &value {
+            Value::Int(_) => value,
+            Value::Float(f) => Value::Int(f.trunc() as i64),
+            Value::Bool(b) => Value::Int(*b as i64),
+            Value::Str(s) => {
+                let s = s.trim();
+                Value::Int(
+                    s.parse()
+                        .or_else(|_| s.parse::<f64>().map(|f| f.trunc() as i64))
+                        .unwrap_or(0),
+                )
+            }
+            _ => Value::Int(0),
+        },
+        "indent" => {
+            let indention = match args.get(0, "width") {
+                Some(Value::Str(s)) => s.to_string(),
+                Some(Value::Int(n)) => " ".repeat(repeat_len(1, *n)?),
+                _ => " ".repeat(4),
+            };
+            let first = args.get(1, "first").is_some_and(|v| v.is_truthy());
+            let blank = args.get(2, "blank").is_some_and(|v| v.is_truthy());
+            Value::str(&indent(&value.to_string(), &indention, first, blank))
+        }
+        "items" => match &value {
+            Value::Map(map) => Value::list(
+                map.iter()
+                    .map(|(k, v)| Value::list(vec![Value::Str(k.clone()), v.clone()]))
+                    .collect(),
+            ),
+            Value::Undefined => Value::list(Vec::new()),
+            other => {
+                return Err(render_error(format!(
+                    "items expects a dict, found {}",
+                    other.type_name()
+                )))
+            }
+        },
+        "join" => {
+            let sep = args.get_str(0, "d")?.unwrap_or("");
+            let attr = args.get_str(1, "attribute")?;
+            let parts: Vec<String> = iter_items(&value)?
+                .iter()
+                .map(|item| match attr {
+                    Some(attr) => get_attr(item, attr).to_string(),
+                    None => item.to_string(),
+                })
+                .collect();
+            Value::str(&parts.join(sep))
+        }
+        "list" => Value::list(iter_items(&value)?),
+        "lower" => Value::str(&value.to_string().to_lowercase()),
+        "map" => {
+            let items = iter_items(&value)?;
+            if let Some(attr) = args.get_keyword("attribute") {
+                let attr = expect_str(attr, "map attribute")?;
+                let default = args.get_keyword("default");
+                Value::list(
+                    items
+                        .iter()
+                        .map(|item| match (get_attr(item, attr), default) {
+                            (Value::Undefined, Some(default)) => default.clone(),
+                            (value, _) => value,
+                        })
+                        .collect(),
+                )
+            } else {
+                let filter_name = args
+                    .get_str(0, "name")?
+                    .ok_or_else(|| render_error("map expects a filter name or attribute"))?;
+                let filter_args = args.skip(1);
+                Value::list(
+                    items
+                        .into_iter()
+                        .map(|item| apply_filter(filter_name, item, &filter_args))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+        }
+        "reject" | "select" => {
+            let test_name = args.get_str(0, "name")?;
+            select(
+                iter_items(&value)?,
+                None,
+                test_name,
+                &args.skip(1),
+                name == "select",
+            )?
+        }
+        "rejectattr" | "selectattr" => {
+            let attr = args
+                .get_str(0, "attribute")?
+                .ok_or_else(|| render_error(format!("{} expects an attribute name", name)))?;
+            let test_name = args.get_str(1, "name")?;
+            select(
+                iter_items(&value)?,
+                Some(attr),
+                test_name,
+                &args.skip(2),
+                name == "selectattr",
+            )?
+        }
+        "replace" => {
+            let s = value.to_string();
+            let old = args.get_str(0, "old")?.unwrap_or("");
+            let new = args.get_str(1, "new")?.unwrap_or("");
+            let count = args.get_int(2, "count")?;
+            Value::str(&replace(&s, old, new, count))
+        }
+        "reverse" => match &value {
+            Value::Str(s) => Value::str(&s.chars().rev().collect::<String>()),
+            _ => Value::list(iter_items(&value)?.into_iter().rev().collect()),
+        },
+        "safe" => value,
+        "sort" => {
+            let reverse = args.get(0, "reverse").is_some_and(|v| v.is_truthy());
+            let attr = args.get_str(2, "attribute")?;
+            let mut items = iter_items(&value)?;
+            let key = |item: &Value| match attr {
+                Some(attr) => get_attr(item, attr),
+                None => item.clone(),
+            };
+            items.sort_by(|a, b| {
+                key(a)
+                    .partial_cmp(&key(b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            if reverse {
+                items.reverse();
+            }
+            Value::list(items)
+        }
+        "string" => Value::str(&value.to_string()),
+        "title" => Value::str(&title(&value.to_string())),
+        "tojson" => {
+            let indent = args.get_int(0, "indent")?.map(|n| n.max(0) as usize);
+            let json = value.to_json(indent).map_err(render_error)?;
+            Value::str(&json)
+        }
+        "trim" => {
+            let chars = args.get_str(0, "chars")?;
+            Value::str(strip(&value.to_string(), chars, true, true))
+        }
+        "unique" => {
+            let mut unique: Vec<Value> = Vec::new();
+            for item in iter_items(&value)? {
+                if !unique.contains(&item) {
+                    unique.push(item);
+                }
+            }
+            Value::list(unique)
+        }
+        "upper" => Value::str(&value.to_string().to_uppercase()),
+        _ => return Err(render_error(format!("unknown filter \"{}\"", name))),
+    };
+    Ok(result)
+}
+
+/// Apply a test (`value is name(args)`).
+pub fn apply_test(name: &str, value: &Value, args: &CallArgs) -> Result<bool, ChatTemplateError> {
+    let arg = || {
+        args.get(0, "value")
+            .ok_or_else(|| render_error(format!("test \"{}\" expects an argument", name)))
+    };
+    let compare = |arg: &Value| {
+        value.partial_cmp(arg).ok_or_else(|| {
+            render_error(format!(
+                "cannot compare {} and {}",
+                value.type_name(),
+                arg.type_name()
+            ))
+        })
+    };
+
+    let result = match name {
+        "boolean" => matches!(value, Value::Bool(_)),
+        "callable" => matches!(value, Value::Function(_)),
+        "defined" => !matches!(value, Value::Undefined),
+        "divisibleby" => match (value, arg()?) {
+            (Value::Int(a), Value::Int(b)) if *b != 0 => a.wrapping_rem(*b) == 0,
+            _ => return Err(render_error("divisibleby expects non-zero integers")),
+        },
+        "eq" | "equalto" | "==" => value == arg()?,
+        "even" => matches!(value, Value::Int(i) if i % 2 == 0),
+        "false" => matches!(value, Value::Bool(false)),
+        "float" => matches!(value, Value::Float(_)),
+        "ge" | ">=" => compare(arg()?)?.is_ge(),
+        "gt" | "greaterthan" | ">" => compare(arg()?)?.is_gt(),
+        "in" => contains(arg()?, value)?,
+        "integer" => matches!(value, Value::Int(_)),
+        "iterable" => matches!(
+            value,
+            Value::Str(_) | Value::List(_) | Value::Map(_) | Value::Undefined
+        ),
+        "le" | "<=" => compare(arg()?)?.is_le(),
+        "lower" => value.as_str().is_some_and(|s| s.to_lowercase() == s),
+        "lt" | "lessthan" | "<" => compare(arg()?)?.is_lt(),
+        "mapping" => matches!(value, Value::Map(_)),
+        "ne" | "!=" => value != arg()?,
+        "none" => matches!(value, Value::None),
+        "number" => matches!(value, Value::Int(_) | Value::Float(_)),
+        "odd" => matches!(value, Value::Int(i) if i % 2 != 0),
+        "sequence" => matches!(value, Value::Str(_) | Value::List(_) | Value::Map(_)),
+        "string" => matches!(value, Value::Str(_)),
+        "true" => matches!(value, Value::Bool(true)),
+        "undefined" => matches!(value, Value::Undefined),
+        "upper" => value.as_str().is_some_and(|s| s.to_uppercase() == s),
+        _ => return Err(render_error(format!("unknown test \"{}\"", name))),
+    };
+    Ok(result)
+}
+
+/// Call a method of a string or dict (`obj.name(args)`).
+///
+/// Returns `None` if `obj` does not have a method called `name`.
+pub fn call_method(
+    obj: &Value,
+    name: &str,
+    args: &CallArgs,
+) -> Result<Option<Value>, ChatTemplateError> {
+    let result = match obj {
+        Value::Str(s) => match name {
+            "capitalize" => Value::str(&capitalize(s)),
+            "count" => {
+                let substr = args.get_str(0, "sub")?.unwrap_or("");
+                Value::Int(s.matches(substr).count() as i64)
+            }
+            "endswith" | "startswith" => {
+                let affixes = match args.get(0, "prefix") {
+                    Some(Value::Str(affix)) => vec![Value::Str(affix.clone())],
+                    Some(Value::List(affixes)) => affixes.as_ref().clone(),
+                    _ => {
+                        return Err(render_error(format!(
+                            "{} expects a string or list of strings",
+                            name
+                        )))
+                    }
+                };
+                let mut matched = false;
+                for affix in affixes {
+                    let affix = expect_str(&affix, name)?;
+                    matched |= if name == "startswith" {
+                        s.starts_with(affix)
+                    } else {
+                        s.ends_with(affix)
+                    };
+                }
+                Value::Bool(matched)
+            }
+            "find" => {
+                let substr = args.get_str(0, "sub")?.unwrap_or("");
+                Value::Int(
+                    s.find(substr)
+                        .map(|pos| s[..pos].chars().count() as i64)
+                        .unwrap_or(-1),
+                )
+            }
+            "join" => {
+                let items = iter_items(args.get(0, "iterable").unwrap_or(&Value::Undefined))?;
+                let parts: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                Value::str(&parts.join(s))
+            }
+            "lower" => Value::str(&s.to_lowercase()),
+            "lstrip" | "rstrip" | "strip" => {
+                let chars = args.get_str(0, "chars")?;
+                Value::str(strip(s, chars, name != "rstrip", name != "lstrip"))
+            }
+            "replace" => {
+                let old = args.get_str(0, "old")?.unwrap_or("");
+                let new = args.get_str(1, "new")?.unwrap_or("");
+                let count = args.get_int(2, "count")?;
+                Value::str(&replace(s, old, new, count))
+            }
+            "split" => {
+                let sep = args.get_str(0, "sep")?;
+                let maxsplit = args.get_int(1, "maxsplit")?;
+                Value::list(split(s, sep, maxsplit)?)
+            }
+            "title" => Value::str(&title(s)),
+            "upper" => Value::str(&s.to_uppercase()),
+            _ => return Ok(None),
+        },
+        Value::Map(map) => match name {
+            "get" => {
+                let key = args.get_str(0, "key")?.unwrap_or("");
+                map.get(key)
+                    .or(args.get(1, "default"))
+                    .cloned()
+                    .unwrap_or(Value::None)
+            }
+            "items" => apply_filter("items", obj.clone(), &CallArgs::default())?,
+            "keys" => Value::list(map.iter().map(|(k, _)| Value::Str(k.clone())).collect()),
+            "values" => Value::list(map.iter().map(|(_, v)| v.clone()).collect()),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+
+    use super::{apply_filter, apply_test, call_method, indent, split, title};
+    use crate::chat_template::render::CallArgs;
+    use crate::chat_template::value::{Map, Value};
+
+    fn positional(args: &[Value]) -> CallArgs {
+        CallArgs {
+            positional: args.to_vec(),
+            keyword: Vec::new(),
+        }
+    }
+
+    fn str_list(items: &[&str]) -> Value {
+        Value::list(items.iter().map(|s| Value::str(s)).collect())
+    }
+
+    fn message(role: &str) -> Value {
+        let mut map = Map::new();
+        map.insert("role", Value::str(role));
+        Value::map(map)
+    }
+
+    #[test]
+    fn test_string_helpers() {
+        assert_eq!(title("hello wORLD it's 2x"), "Hello World It'S 2X");
+        assert_eq!(indent("a\n\nb", "  ", false, false), "a\n\n  b");
+        assert_eq!(indent("a\n\nb", "  ", true, true), "  a\n  \n  b");
+
+        let parts = split("  a  b c ", None, Some(1)).unwrap();
+        assert_eq!(Value::list(parts), str_list(&["a", "b c "]));
+        let parts = split("a,b,,c", Some(","), None).unwrap();
+        assert_eq!(Value::list(parts), str_list(&["a", "b", "", "c"]));
+        assert!(split("a", Some(""), None).is_err());
+    }
+
+    #[test]
+    fn test_apply_filter() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            name: &'a str,
+            value: &'a str,
+            args: &'a [&'a str],
+            expected: &'a str,
+        }
+
+        // Values and arguments are strings here. Filters on other types are
+        // covered by `test_apply_filter_values`.
+        let cases = [
+            Case {
+                name: "capitalize",
+                value: "hELLO",
+                args: &[],
+                expected: "Hello",
+            },
+            Case {
+                name: "length",
+                value: "héllo",
+                args: &[],
+                expected: "5",
+            },
+            Case {
+                name: "replace",
+                value: "aaa",
+                args: &["a", "b"],
+                expected: "bbb",
+            },
+            Case {
+                name: "reverse",
+                value: "abc",
+                args: &[],
+                expected: "cba",
+            },
+            Case {
+                name: "trim",
+                value: "--a-b--",
+                args: &["-"],
+                expected: "a-b",
+            },
+            Case {
+                name: "tojson",
+                value: "a\"b",
+                args: &[],
+                expected: "\"a\\\"b\"",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let args = positional(&case.args.iter().map(|s| Value::str(s)).collect::<Vec<_>>());
+            let result = apply_filter(case.name, Value::str(case.value), &args).unwrap();
+            assert_eq!(result.to_string(), case.expected);
+        })
+    }
+
+    #[test]
+    fn test_apply_filter_values() {
+        let messages = Value::list(vec![message("user"), message("system"), message("user")]);
+
+        let roles = apply_filter(
+            "map",
+            messages.clone(),
+            &CallArgs {
+                positional: Vec::new(),
+                keyword: vec![("attribute".to_string(), Value::str("role"))],
+            },
+        )
+        .unwrap();
+        assert_eq!(roles, str_list(&["user", "system", "user"]));
+
+        let users = apply_filter(
+            "selectattr",
+            messages.clone(),
+            &positional(&[Value::str("role"), Value::str("eq"), Value::str("user")]),
+        )
+        .unwrap();
+        assert_eq!(users, Value::list(vec![message("user"), message("user")]));
+
+        let unique = apply_filter("unique", roles.clone(), &CallArgs::default()).unwrap();
+        assert_eq!(unique, str_list(&["user", "system"]));
+
+        let sorted =
+            apply_filter("sort", roles.clone(), &positional(&[Value::Bool(true)])).unwrap();
+        assert_eq!(sorted, str_list(&["user", "user", "system"]));
+
+        let joined = apply_filter("join", roles, &positional(&[Value::str(", ")])).unwrap();
+        assert_eq!(joined, Value::str("user, system, user"));
+
+        let upper = apply_filter(
+            "map",
+            str_list(&["a", "b"]),
+            &positional(&[Value::str("upper")]),
+        )
+        .unwrap();
+        assert_eq!(upper, str_list(&["A", "B"]));
+
+        assert_eq!(
+            apply_filter("abs", Value::Int(-3), &CallArgs::default()).unwrap(),
+            Value::Int(3)
+        );
+        assert!(apply_filter("abs", Value::Int(i64::MIN), &CallArgs::default()).is_err());
+        assert!(apply_filter(
+            "indent",
+            Value::str("a"),
+            &positional(&[Value::Int(i64::MAX)])
+        )
+        .is_err());
+
+        let err = apply_filter("nonexistent", Value::None, &CallArgs::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "template rendering failed: unknown filter \"nonexistent\""
+        );
+    }
+
+    #[test]
+    fn test_apply_test() {
+        let cases = [
+            ("defined", Value::Undefined, None, false),
+            ("none", Value::None, None, true),
+            ("string", Value::str("a"), None, true),
+            ("number", Value::Float(1.5), None, true),
+            ("odd", Value::Int(3), None, true),
+            ("even", Value::Int(3), None, false),
+            ("divisibleby", Value::Int(9), Some(Value::Int(3)), true),
+            (
+                "divisibleby",
+                Value::Int(i64::MIN),
+                Some(Value::Int(-1)),
+                true,
+            ),
+            ("eq", Value::Int(1), Some(Value::Float(1.)), true),
+            ("gt", Value::Int(1), Some(Value::Int(2)), false),
+            ("in", Value::str("b"), Some(str_list(&["a", "b"])), true),
+            ("lower", Value::str("abc"), None, true),
+            ("mapping", message("user"), None, true),
+        ];
+
+        for (name, value, arg, expected) in cases {
+            let args = positional(arg.as_slice());
+            assert_eq!(
+                apply_test(name, &value, &args).unwrap(),
+                expected,
+                "{} {:?}",
+                name,
+                value
+            );
+        }
+
+        assert!(apply_test("gt", &Value::Int(1), &positional(&[Value::str("a")])).is_err());
+        assert!(apply_test("nonexistent", &Value::None, &CallArgs::default()).is_err());
+    }
+
+    #[test]
+    fn test_call_method() {
+        let call = |obj: &Value, name: &str, args: &[Value]| {
+            call_method(obj, name, &positional(args)).unwrap()
+        };
+
+        let s = Value::str("  Hello world  ");
+        assert_eq!(call(&s, "strip", &[]), Some(Value::str("Hello world")));
+        assert_eq!(call(&s, "lstrip", &[]), Some(Value::str("Hello world  ")));
+        assert_eq!(call(&s, "split", &[]), Some(str_list(&["Hello", "world"])));
+        assert_eq!(
+            call(&s, "find", &[Value::str("world")]),
+            Some(Value::Int(8))
+        );
+        assert_eq!(
+            call(
+                &Value::str("a.txt"),
+                "endswith",
+                &[str_list(&[".md", ".txt"])]
+            ),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(
+            call(&Value::str(", "), "join", &[str_list(&["a", "b"])]),
+            Some(Value::str("a, b"))
+        );
+        assert_eq!(call(&s, "nonexistent", &[]), None);
+
+        let map = message("user");
+        assert_eq!(call(&map, "keys", &[]), Some(str_list(&["role"])));
+        assert_eq!(
+            call(&map, "get", &[Value::str("name"), Value::str("anon")]),
+            Some(Value::str("anon"))
+        );
+        assert_eq!(call(&Value::Int(1), "get", &[]), None);
+    }
+}
@@ -0,0 +1,900 @@
+//! Evaluation of parsed chat templates.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use super::filters::{apply_filter, apply_test, call_method};
+use super::syntax::{Args, BinaryOp, Expr, Node, SetTarget};
+use super::value::{Function, Map, Value};
+use super::ChatTemplateError;
+
+/// Create an error for a failure while rendering.
+pub fn render_error(message: impl Into<String>) -> ChatTemplateError {
+    ChatTemplateError::RenderError(message.into())
+}
+
+/// Maximum length of a string or list created by `range` or repetition.
+///
+/// This prevents templates from allocating unbounded amounts of memory.
+pub const MAX_SEQUENCE_LEN: usize = 1 << 20;
+
+/// Return the length of a sequence of length `len` repeated `n` times, or an
+/// error if it exceeds [`MAX_SEQUENCE_LEN`].
+pub fn repeat_len(len: usize, n: i64) -> Result<usize, ChatTemplateError> {
+    let n = usize::try_from(n.max(0)).unwrap_or(usize::MAX);
+    len.checked_mul(n)
+        .filter(|&total| total <= MAX_SEQUENCE_LEN)
+        .ok_or_else(|| render_error("repeated sequence is too long"))
+}
+
+/// Evaluated arguments for a function, method, filter or test.
+#[derive(Clone, Default)]
+pub struct CallArgs {
+    pub positional: Vec<Value>,
+    pub keyword: Vec<(String, Value)>,
+}
+
+impl CallArgs {
+    /// Get an argument by keyword, or by position if it was not passed by
+    /// keyword.
+    pub fn get(&self, index: usize, name: &str) -> Option<&Value> {
+        self.get_keyword(name)
+            .or_else(|| self.positional.get(index))
+    }
+
+    /// Get an argument which can only be passed by keyword.
+    pub fn get_keyword(&self, name: &str) -> Option<&Value> {
+        self.keyword
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, val)| val)
+    }
+
+    /// Get an optional string argument. `none` is treated as missing.
+    pub fn get_str(&self, index: usize, name: &str) -> Result<Option<&str>, ChatTemplateError> {
+        match self.get(index, name) {
+            None | Some(Value::None | Value::Undefined) => Ok(None),
+            Some(Value::Str(s)) => Ok(Some(s)),
+            Some(val) => Err(render_error(format!(
+                "argument \"{}\" must be a string, found {}",
+                name,
+                val.type_name()
+            ))),
+        }
+    }
+
+    /// Get an optional integer argument. `none` is treated as missing.
+    pub fn get_int(&self, index: usize, name: &str) -> Result<Option<i64>, ChatTemplateError> {
+        match self.get(index, name) {
+            None | Some(Value::None | Value::Undefined) => Ok(None),
+            Some(Value::Int(i)) => Ok(Some(*i)),
+            Some(val) => Err(render_error(format!(
+                "argument \"{}\" must be an integer, found {}",
+                name,
+                val.type_name()
+            ))),
+        }
+    }
+
+    /// Return the arguments after the first `n` positional arguments.
+    pub fn skip(&self, n: usize) -> CallArgs {
+        CallArgs {
+            positional: self.positional.iter().skip(n).cloned().collect(),
+            keyword: self.keyword.clone(),
+        }
+    }
+}
+
+/// Control flow after executing a statement.
+#[derive(PartialEq)]
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+}
+
+/// Render a parsed template using the given global variables.
+pub fn render(nodes: &[Node], globals: Vec<(String, Value)>) -> Result<String, ChatTemplateError> {
+    let mut root_scope: HashMap<String, Value> = [
+        Function::Dict,
+        Function::Namespace,
+        Function::RaiseException,
+        Function::Range,
+    ]
+    .into_iter()
+    .map(|func| (func.name().to_string(), Value::Function(func)))
+    .collect();
+    root_scope.extend(globals);
+
+    let mut renderer = Renderer {
+        scopes: vec![root_scope],
+    };
+    let mut output = String::new();
+    renderer.exec(nodes, &mut output)?;
+    Ok(output)
+}
+
+struct Renderer {
+    /// Stack of variable scopes. A new scope is created for each iteration of
+    /// a loop.
+    scopes: Vec<HashMap<String, Value>>,
+}
+
+impl Renderer {
+    fn lookup(&self, name: &str) -> Value {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .unwrap_or(Value::Undefined)
+    }
+
+    fn set_var(&mut self, name: &str, value: Value) {
+        self.scopes
+            .last_mut()
+            .expect("should have a scope")
+            .insert(name.to_string(), value);
+    }
+
+    /// Assign `value` to one or more variable names, unpacking the value if
+    /// there are several names.
+    fn set_vars(&mut self, names: &[String], value: Value) -> Result<(), ChatTemplateError> {
+        if let [name] = names {
+            self.set_var(name, value);
+            return Ok(());
+        }
+
+        let items = match &value {
+            Value::List(items) => items.as_ref().clone(),
+            _ => value.iter_items().unwrap_or_default(),
+        };
+        if items.len() != names.len() {
+            return Err(render_error(format!(
+                "cannot unpack {} values into {} variables",
+                items.len(),
+                names.len()
+            )));
+        }
+        for (name, item) in names.iter().zip(items) {
+            self.set_var(name, item);
+        }
+        Ok(())
+    }
+
+    fn exec(&mut self, nodes: &[Node], out: &mut String) -> Result<Flow, ChatTemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Output(expr) => {
+                    let value = self.eval(expr)?;
+                    write!(out, "{}", value).unwrap();
+                }
+                Node::If {
+                    branches,
+                    else_body,
+                } => {
+                    let mut body = else_body;
+                    for (cond, branch_body) in branches {
+                        if self.eval(cond)?.is_truthy() {
+                            body = branch_body;
+                            break;
+                        }
+                    }
+                    let flow = self.exec(body, out)?;
+                    if flow != Flow::Normal {
+                        return Ok(flow);
+                    }
+                }
+                Node::For {
+                    targets,
+                    iter,
+                    filter,
+                    body,
+                    else_body,
+                } => {
+                    self.exec_for(targets, iter, filter.as_ref(), body, else_body, out)?;
+                }
+                Node::Set { target, value } => {
+                    let value = self.eval(value)?;
+                    match target {
+                        SetTarget::Name(name) => self.set_var(name, value),
+                        SetTarget::Names(names) => self.set_vars(names, value)?,
+                        SetTarget::Attr(name, attr) => match self.lookup(name) {
+                            Value::Namespace(ns) => ns.borrow_mut().insert(attr, value),
+                            other => {
+                                return Err(render_error(format!(
+                                    "cannot assign attribute of {}",
+                                    other.type_name()
+                                )))
+                            }
+                        },
+                    }
+                }
+                Node::SetBlock { name, body } => {
+                    let mut block_out = String::new();
+                    self.exec(body, &mut block_out)?;
+                    self.set_var(name, Value::str(&block_out));
+                }
+                Node::Break => return Ok(Flow::Break),
+                Node::Continue => return Ok(Flow::Continue),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_for(
+        &mut self,
+        targets: &[String],
+        iter: &Expr,
+        filter: Option<&Expr>,
+        body: &[Node],
+        else_body: &[Node],
+        out: &mut String,
+    ) -> Result<(), ChatTemplateError> {
+        let iter = self.eval(iter)?;
+        let mut items = iter
+            .iter_items()
+            .ok_or_else(|| render_error(format!("{} is not iterable", iter.type_name())))?;
+
+        if let Some(filter) = filter {
+            let mut selected = Vec::new();
+            for item in items {
+                self.scopes.push(HashMap::new());
+                self.set_vars(targets, item.clone())?;
+                let keep = self.eval(filter)?.is_truthy();
+                self.scopes.pop();
+                if keep {
+                    selected.push(item);
+                }
+            }
+            items = selected;
+        }
+
+        if items.is_empty() {
+            self.exec(else_body, out)?;
+            return Ok(());
+        }
+
+        let len = items.len();
+        for (i, item) in items.iter().enumerate() {
+            let mut loop_info = Map::new();
+            loop_info.insert("index", Value::Int(i as i64 + 1));
+            loop_info.insert("index0", Value::Int(i as i64));
+            loop_info.insert("revindex", Value::Int((len - i) as i64));
+            loop_info.insert("revindex0", Value::Int((len - i - 1) as i64));
+            loop_info.insert("first", Value::Bool(i == 0));
+            loop_info.insert("last", Value::Bool(i == len - 1));
+            loop_info.insert("length", Value::Int(len as i64));
+            if i > 0 {
+                loop_info.insert("previtem", items[i - 1].clone());
+            }
+            if i + 1 < len {
+                loop_info.insert("nextitem", items[i + 1].clone());
+            }
+
+            self.scopes.push(HashMap::new());
+            self.set_var("loop", Value::map(loop_info));
+            self.set_vars(targets, item.clone())?;
+            let flow = self.exec(body, out)?;
+            self.scopes.pop();
+
+            if flow == Flow::Break {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn eval_args(&self, args: &Args) -> Result<CallArgs, ChatTemplateError> {
+        Ok(CallArgs {
+            positional: args
+                .positional
+                .iter()
+                .map(|arg| self.eval(arg))
+                .collect::<Result<_, _>>()?,
+            keyword: args
+                .keyword
+                .iter()
+                .map(|(name, arg)| Ok((name.clone(), self.eval(arg)?)))
+                .collect::<Result<_, ChatTemplateError>>()?,
+        })
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<Value, ChatTemplateError> {
+        let value = match expr {
+            Expr::Literal(value) => value.clone(),
+            Expr::Var(name) => self.lookup(name),
+            Expr::List(items) => Value::list(
+                items
+                    .iter()
+                    .map(|item| self.eval(item))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Dict(entries) => {
+                let mut map = Map::new();
+                for (key, value) in entries {
+                    let key = self.eval(key)?;
+                    let Value::Str(key) = key else {
+                        return Err(render_error(format!(
+                            "dict keys must be strings, found {}",
+                            key.type_name()
+                        )));
+                    };
+                    map.insert(&key, self.eval(value)?);
+                }
+                Value::map(map)
+            }
+            Expr::Attr(obj, name) => get_attr(&self.eval(obj)?, name),
+            Expr::Index(obj, index) => get_item(&self.eval(obj)?, &self.eval(index)?),
+            Expr::Slice {
+                expr,
+                start,
+                stop,
+                step,
+            } => {
+                let value = self.eval(expr)?;
+                let eval_index = |index: &Option<Box<Expr>>| -> Result<_, ChatTemplateError> {
+                    match index {
+                        Some(index) => match self.eval(index)? {
+                            Value::Int(i) => Ok(Some(i)),
+                            Value::None => Ok(None),
+                            other => Err(render_error(format!(
+                                "slice indices must be integers, found {}",
+                                other.type_name()
+                            ))),
+                        },
+                        None => Ok(None),
+                    }
+                };
+                let start = eval_index(start)?;
+                let stop = eval_index(stop)?;
+                let step = eval_index(step)?;
+                slice(&value, start, stop, step)?
+            }
+            Expr::Call { callee, args } => self.eval_call(callee, args)?,
+            Expr::Filter { expr, name, args } => {
+                let value = self.eval(expr)?;
+                let args = self.eval_args(args)?;
+                apply_filter(name, value, &args)?
+            }
+            Expr::Test {
+                expr,
+                name,
+                args,
+                negated,
+            } => {
+                let value = self.eval(expr)?;
+                let args = self.eval_args(args)?;
+                Value::Bool(apply_test(name, &value, &args)? != *negated)
+            }
+            Expr::Not(expr) => Value::Bool(!self.eval(expr)?.is_truthy()),
+            Expr::Neg(expr) => match self.eval(expr)? {
+                Value::Int(i) => Value::Int(-i),
+                Value::Float(f) => Value::Float(-f),
+                other => return Err(render_error(format!("cannot negate {}", other.type_name()))),
+            },
+            Expr::Binary(BinaryOp::And, lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                if lhs.is_truthy() {
+                    self.eval(rhs)?
+                } else {
+                    lhs
+                }
+            }
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                if lhs.is_truthy() {
+                    lhs
+                } else {
+                    self.eval(rhs)?
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => binary_op(*op, self.eval(lhs)?, self.eval(rhs)?)?,
+            Expr::Cond { cond, then, else_ } => {
+                if self.eval(cond)?.is_truthy() {
+                    self.eval(then)?
+                } else if let Some(else_) = else_ {
+                    self.eval(else_)?
+                } else {
+                    Value::Undefined
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    fn eval_call(&self, callee: &Expr, args: &Args) -> Result<Value, ChatTemplateError> {
+        let args = self.eval_args(args)?;
+
+        if let Expr::Attr(obj, method) = callee {
+            let obj = self.eval(obj)?;
+            if let Some(result) = call_method(&obj, method, &args)? {
+                return Ok(result);
+            }
+            return match get_attr(&obj, method) {
+                Value::Function(func) => call_function(func, &args),
+                _ => Err(render_error(format!(
+                    "{} has no method \"{}\"",
+                    obj.type_name(),
+                    method
+                ))),
+            };
+        }
+
+        match self.eval(callee)? {
+            Value::Function(func) => call_function(func, &args),
+            Value::Undefined => match callee {
+                Expr::Var(name) => Err(render_error(format!("unknown function \"{}\"", name))),
+                _ => Err(render_error("undefined value is not callable")),
+            },
+            other => Err(render_error(format!(
+                "{} is not callable",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
+fn call_function(func: Function, args: &CallArgs) -> Result<Value, ChatTemplateError> {
+    let keyword_map = || -> Map {
+        args.keyword
+            .iter()
+            .map(|(key, value)| (key.as_str().into(), value.clone()))
+            .collect()
+    };
+
+    match func {
+        Function::Dict => Ok(Value::map(keyword_map())),
+        Function::Namespace => {
+            let mut map = match args.positional.first() {
+                Some(Value::Map(init)) => init.as_ref().clone(),
+                _ => Map::new(),
+            };
+            for (key, value) in args.keyword.iter() {
+                map.insert(key, value.clone());
+            }
+            Ok(Value::Namespace(Rc::new(RefCell::new(map))))
+        }
+        Function::RaiseException => {
+            let message = args.get(0, "message").map(|msg| msg.to_string());
+            Err(ChatTemplateError::Exception(message.unwrap_or_default()))
+        }
+        Function::Range => {
+            let ints = args
+                .positional
+                .iter()
+                .map(|arg| match arg {
+                    Value::Int(i) => Ok(*i),
+                    other => Err(render_error(format!(
+                        "range arguments must be integers, found {}",
+                        other.type_name()
+                    ))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let (start, stop, step) = match ints[..] {
+                [stop] => (0, stop, 1),
+                [start, stop] => (start, stop, 1),
+                [start, stop, step] if step != 0 => (start, stop, step),
+                _ => return Err(render_error("invalid arguments for range")),
+            };
+            let mut items = Vec::new();
+            let mut i = Some(start);
+            while let Some(val) = i.filter(|&i| (step > 0 && i < stop) || (step < 0 && i > stop)) {
+                if items.len() == MAX_SEQUENCE_LEN {
+                    return Err(render_error("range is too long"));
+                }
+                items.push(Value::Int(val));
+                i = val.checked_add(step);
+            }
+            Ok(Value::list(items))
+        }
+    }
+}
+
+/// Get an attribute of an object (`obj.name`).
+pub fn get_attr(obj: &Value, name: &str) -> Value {
+    match obj {
+        Value::Map(map) => map.get(name).cloned().unwrap_or(Value::Undefined),
+        Value::Namespace(map) => map.borrow().get(name).cloned().unwrap_or(Value::Undefined),
+        _ => Value::Undefined,
+    }
+}
+
+/// Get an item of a list, map or string (`obj[index]`).
+fn get_item(obj: &Value, index: &Value) -> Value {
+    match (obj, index) {
+        (Value::Map(_) | Value::Namespace(_), Value::Str(key)) => get_attr(obj, key),
+        (Value::List(items), Value::Int(i)) => resolve_index(items.len(), *i)
+            .map(|i| items[i].clone())
+            .unwrap_or(Value::Undefined),
+        (Value::Str(s), Value::Int(i)) => {
+            let chars: Vec<char> = s.chars().collect();
+            resolve_index(chars.len(), *i)
+                .map(|i| Value::str(chars[i].encode_utf8(&mut [0; 4])))
+                .unwrap_or(Value::Undefined)
+        }
+        _ => Value::Undefined,
+    }
+}
+
+/// Resolve a possibly negative index into a sequence of length `len`.
+fn resolve_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (index >= 0 && (index as usize) < len).then_some(index as usize)
+}
+
+/// Slice a list or string using Python's slicing rules.
+fn slice(
+    value: &Value,
+    start: Option<i64>,
+    stop: Option<i64>,
+    step: Option<i64>,
+) -> Result<Value, ChatTemplateError> {
+    let step = step.unwrap_or(1);
+    if step == 0 {
+        return Err(render_error("slice step cannot be zero"));
+    }
+
+    let indices = |len: usize| -> Vec<usize> {
+        let len = len as i64;
+        let clamp = |index: i64, min: i64, max: i64| {
+            let index = if index < 0 { index + len } else { index };
+            index.clamp(min, max)
+        };
+        let mut indices = Vec::new();
+        if step > 0 {
+            let mut i = start.map(|i| clamp(i, 0, len)).unwrap_or(0);
+            let stop = stop.map(|i| clamp(i, 0, len)).unwrap_or(len);
+            while i < stop {
+                indices.push(i as usize);
+                let Some(next) = i.checked_add(step) else {
+                    break;
+                };
+                i = next;
+            }
+        } else {
+            let mut i = start.map(|i| clamp(i, -1, len - 1)).unwrap_or(len - 1);
+            let stop = stop.map(|i| clamp(i, -1, len - 1)).unwrap_or(-1);
+            while i > stop {
+                indices.push(i as usize);
+                let Some(next) = i.checked_add(step) else {
+                    break;
+                };
+                i = next;
+            }
+        }
+        indices
+    };
+
+    match value {
+        Value::List(items) => Ok(Value::list(
+            indices(items.len())
+                .into_iter()
+                .map(|i| items[i].clone())
+                .collect(),
+        )),
+        Value::Str(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let sliced: String = indices(chars.len()).into_iter().map(|i| chars[i]).collect();
+            Ok(Value::str(&sliced))
+        }
+        Value::Undefined => Ok(Value::Undefined),
+        other => Err(render_error(format!("cannot slice {}", other.type_name()))),
+    }
+}
+
+/// Return true if `container` contains `item`, for the `in` operator.
+pub fn contains(container: &Value, item: &Value) -> Result<bool, ChatTemplateError> {
+    match (container, item) {
+        (Value::List(items), item) => Ok(items.contains(item)),
+        (Value::Str(s), Value::Str(substr)) => Ok(s.contains(substr.as_ref())),
+        (Value::Map(map), Value::Str(key)) => Ok(map.get(key).is_some()),
+        (Value::Map(_), _) => Ok(false),
+        (container, item) => Err(render_error(format!(
+            "cannot test if {} contains {}",
+            container.type_name(),
+            item.type_name()
+        ))),
+    }
+}
+
+fn binary_op(op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value, ChatTemplateError> {
+    let type_error = |lhs: &Value, rhs: &Value| {
+        render_error(format!(
+            "unsupported operand types for {:?}: {} and {}",
+            op,
+            lhs.type_name(),
+            rhs.type_name()
+        ))
+    };
+    let overflow = || render_error("integer overflow");
+
+    let value = match op {
+        BinaryOp::Concat => Value::str(&format!("{}{}", lhs, rhs)),
+        BinaryOp::Eq => Value::Bool(lhs == rhs),
+        BinaryOp::Ne => Value::Bool(lhs != rhs),
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            let ord = lhs
+                .partial_cmp(&rhs)
+                .ok_or_else(|| type_error(&lhs, &rhs))?;
+            Value::Bool(match op {
+                BinaryOp::Lt => ord.is_lt(),
+                BinaryOp::Le => ord.is_le(),
+                BinaryOp::Gt => ord.is_gt(),
+                _ => ord.is_ge(),
+            })
+        }
+        BinaryOp::In => Value::Bool(contains(&rhs, &lhs)?),
+        BinaryOp::NotIn => Value::Bool(!contains(&rhs, &lhs)?),
+        BinaryOp::Add => match (&lhs, &rhs) {
+            (Value::Str(a), Value::Str(b)) => Value::str(&[a.as_ref(), b.as_ref()].concat()),
+            (Value::List(a), Value::List(b)) => {
+                Value::list(a.iter().chain(b.iter()).cloned().collect())
+            }
+            (Value::Int(a), Value::Int(b)) => Value::Int(a.checked_add(*b).ok_or_else(overflow)?),
+            _ => float_op(&lhs, &rhs, |a, b| a + b).ok_or_else(|| type_error(&lhs, &rhs))?,
+        },
+        BinaryOp::Sub => match (&lhs, &rhs) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(a.checked_sub(*b).ok_or_else(overflow)?),
+            _ => float_op(&lhs, &rhs, |a, b| a - b).ok_or_else(|| type_error(&lhs, &rhs))?,
+        },
+        BinaryOp::Mul => match (&lhs, &rhs) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(a.checked_mul(*b).ok_or_else(overflow)?),
+            (Value::Str(s), Value::Int(n)) | (Value::Int(n), Value::Str(s)) => {
+                repeat_len(s.len(), *n)?;
+                Value::str(&s.repeat((*n).max(0) as usize))
+            }
+            (Value::List(items), Value::Int(n)) | (Value::Int(n), Value::List(items)) => {
+                repeat_len(items.len(), *n)?;
+                Value::list(
+                    (0..(*n).max(0))
+                        .flat_map(|_| items.iter().cloned())
+                        .collect(),
+                )
+            }
+            _ => float_op(&lhs, &rhs, |a, b| a * b).ok_or_else(|| type_error(&lhs, &rhs))?,
+        },
+        BinaryOp::Div | BinaryOp::FloorDiv | BinaryOp::Mod => {
+            if rhs.as_f64() == Some(0.) {
+                return Err(render_error("division by zero"));
+            }
+            match (op, &lhs, &rhs) {
+                (BinaryOp::FloorDiv, Value::Int(a), Value::Int(b)) => {
+                    let quotient = a.checked_div(*b).ok_or_else(overflow)?;
+                    let floor = if a % b != 0 && ((*a < 0) != (*b < 0)) {
+                        quotient - 1
+                    } else {
+                        quotient
+                    };
+                    Some(Value::Int(floor))
+                }
+                (BinaryOp::Mod, Value::Int(a), Value::Int(b)) => {
+                    let rem = a.checked_rem(*b).ok_or_else(overflow)?;
+                    Some(Value::Int(if rem != 0 && ((rem < 0) != (*b < 0)) {
+                        rem + b
+                    } else {
+                        rem
+                    }))
+                }
+                (BinaryOp::Div, ..) => float_op(&lhs, &rhs, |a, b| a / b),
+                (BinaryOp::FloorDiv, ..) => float_op(&lhs, &rhs, |a, b| (a / b).floor()),
+                _ => float_op(&lhs, &rhs, |a, b| a - b * (a / b).floor()),
+            }
+            .ok_or_else(|| type_error(&lhs, &rhs))?
+        }
+        BinaryOp::Pow => match (&lhs, &rhs) {
+            (Value::Int(a), Value::Int(b)) if *b >= 0 => {
+                let exp = u32::try_from(*b).map_err(|_| overflow())?;
+                Value::Int(a.checked_pow(exp).ok_or_else(overflow)?)
+            }
+            _ => float_op(&lhs, &rhs, f64::powf).ok_or_else(|| type_error(&lhs, &rhs))?,
+        },
+        BinaryOp::And | BinaryOp::Or => unreachable!("logical operators are short-circuiting"),
+    };
+    Ok(value)
+}
+
+/// Apply an arithmetic operator to numeric values, producing a float.
+fn float_op(lhs: &Value, rhs: &Value, op: impl Fn(f64, f64) -> f64) -> Option<Value> {
+    Some(Value::Float(op(lhs.as_f64()?, rhs.as_f64()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+
+    use super::{binary_op, call_function, render, slice, CallArgs, MAX_SEQUENCE_LEN};
+    use crate::chat_template::syntax::{parse, BinaryOp};
+    use crate::chat_template::value::{Function, Value};
+
+    fn ints(values: &[i64]) -> Value {
+        Value::list(values.iter().map(|&i| Value::Int(i)).collect())
+    }
+
+    fn range(args: &[i64]) -> Result<Value, String> {
+        let args = CallArgs {
+            positional: args.iter().map(|&i| Value::Int(i)).collect(),
+            keyword: Vec::new(),
+        };
+        call_function(Function::Range, &args).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_render() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            template: &'a str,
+            expected: &'a str,
+        }
+
+        let cases = [
+            // Loop variables and scoping.
+            Case {
+                template: "{% for x in [1, 2, 3] %}{{ loop.index }}{{ x }}{% if not loop.last %},{% endif %}{% endfor %}",
+                expected: "11,22,33",
+            },
+            Case {
+                template: "{% set x = 1 %}{% for i in range(2) %}{% set x = i %}{% endfor %}{{ x }}",
+                expected: "1",
+            },
+            Case {
+                template: "{% set ns = namespace(n=0) %}{% for i in range(3) %}{% set ns.n = ns.n + i %}{% endfor %}{{ ns.n }}",
+                expected: "3",
+            },
+            Case {
+                template: "{% for x in [] %}a{% else %}empty{% endfor %}",
+                expected: "empty",
+            },
+            Case {
+                template: "{% for x in range(10) %}{% if x == 2 %}{% break %}{% endif %}{{ x }}{% endfor %}",
+                expected: "01",
+            },
+            Case {
+                template: "{% set greeting %}Hi {{ name }}{% endset %}{{ greeting | upper }}",
+                expected: "HI BOB",
+            },
+            // Undefined values.
+            Case {
+                template: "[{{ missing }}][{{ missing.attr }}][{{ name[10] }}]",
+                expected: "[][][]",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let nodes = parse(case.template).unwrap();
+            let output = render(&nodes, vec![("name".to_string(), Value::str("Bob"))]).unwrap();
+            assert_eq!(output, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(range(&[3]).unwrap(), ints(&[0, 1, 2]));
+        assert_eq!(range(&[5, 0, -2]).unwrap(), ints(&[5, 3, 1]));
+        assert_eq!(range(&[2, 1]).unwrap(), ints(&[]));
+        assert_eq!(
+            range(&[0, 1, 0]).err().unwrap(),
+            "template rendering failed: invalid arguments for range"
+        );
+
+        // Stepping past the maximum integer ends the range.
+        assert_eq!(range(&[0, i64::MAX, i64::MAX]).unwrap(), ints(&[0]));
+        assert_eq!(
+            range(&[i64::MIN + 1, i64::MIN, i64::MIN]).unwrap(),
+            ints(&[i64::MIN + 1])
+        );
+
+        assert_eq!(
+            range(&[i64::MAX]).err().unwrap(),
+            "template rendering failed: range is too long"
+        );
+    }
+
+    #[test]
+    fn test_slice() {
+        let list = ints(&[0, 1, 2, 3, 4]);
+        let sliced = |start, stop, step| slice(&list, start, stop, step).unwrap();
+
+        assert_eq!(sliced(Some(1), Some(3), None), ints(&[1, 2]));
+        assert_eq!(sliced(Some(-2), None, None), ints(&[3, 4]));
+        assert_eq!(sliced(None, None, Some(-2)), ints(&[4, 2, 0]));
+        assert_eq!(sliced(None, None, Some(i64::MAX)), ints(&[0]));
+        assert_eq!(sliced(None, None, Some(i64::MIN)), ints(&[4]));
+        assert!(slice(&list, None, None, Some(0)).is_err());
+
+        let s = slice(&Value::str("héllo"), Some(1), Some(-1), None).unwrap();
+        assert_eq!(s, Value::str("éll"));
+    }
+
+    #[test]
+    fn test_binary_op() {
+        #[derive(Debug)]
+        struct Case {
+            op: BinaryOp,
+            lhs: i64,
+            rhs: i64,
+            expected: Result<i64, &'static str>,
+        }
+
+        let overflow = "template rendering failed: integer overflow";
+        let cases = [
+            Case {
+                op: BinaryOp::FloorDiv,
+                lhs: -7,
+                rhs: 2,
+                expected: Ok(-4),
+            },
+            Case {
+                op: BinaryOp::Mod,
+                lhs: -7,
+                rhs: 3,
+                expected: Ok(2),
+            },
+            Case {
+                op: BinaryOp::Pow,
+                lhs: 2,
+                rhs: 10,
+                expected: Ok(1024),
+            },
+            Case {
+                op: BinaryOp::Add,
+                lhs: i64::MAX,
+                rhs: 1,
+                expected: Err(overflow),
+            },
+            Case {
+                op: BinaryOp::Mul,
+                lhs: i64::MAX,
+                rhs: 2,
+                expected: Err(overflow),
+            },
+            Case {
+                op: BinaryOp::FloorDiv,
+                lhs: i64::MIN,
+                rhs: -1,
+                expected: Err(overflow),
+            },
+            Case {
+                op: BinaryOp::Mod,
+                lhs: 1,
+                rhs: 0,
+                expected: Err("template rendering failed: division by zero"),
+            },
+        ];
+
+        cases.test_each(|case| {
+            let result = binary_op(case.op, Value::Int(case.lhs), Value::Int(case.rhs))
+                .map_err(|err| err.to_string());
+            match (result, case.expected) {
+                (Ok(value), Ok(expected)) => assert_eq!(value, Value::Int(expected)),
+                (Err(err), Err(expected)) => assert_eq!(err, expected),
+                (result, _) => panic!("unexpected result {:?}", result),
+            }
+        })
+    }
+
+    #[test]
+    fn test_repeat() {
+        let repeated = binary_op(BinaryOp::Mul, Value::str("ab"), Value::Int(3)).unwrap();
+        assert_eq!(repeated, Value::str("ababab"));
+        let repeated = binary_op(BinaryOp::Mul, Value::Int(2), ints(&[1])).unwrap();
+        assert_eq!(repeated, ints(&[1, 1]));
+        let repeated = binary_op(BinaryOp::Mul, Value::str("ab"), Value::Int(-1)).unwrap();
+        assert_eq!(repeated, Value::str(""));
+
+        for n in [(MAX_SEQUENCE_LEN + 1) as i64, i64::MAX] {
+            let err = binary_op(BinaryOp::Mul, Value::str("a"), Value::Int(n))
+                .err()
+                .unwrap();
+            assert_eq!(
+                err.to_string(),
+                "template rendering failed: repeated sequence is too long"
+            );
+            assert!(binary_op(BinaryOp::Mul, ints(&[1]), Value::Int(n)).is_err());
+        }
+    }
+}
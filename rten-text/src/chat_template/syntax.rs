@@ -0,0 +1,1204 @@
+//! Lexer and parser for the subset of Jinja syntax used by chat templates.
+
+use super::value::Value;
+use super::ChatTemplateError;
+
+/// Statement in a parsed template.
+#[derive(Clone, Debug)]
+pub enum Node {
+    /// Text which is copied to the output.
+    Text(String),
+    /// `{{ expr }}`
+    Output(Expr),
+    /// `{% if cond %}...{% elif cond %}...{% else %}...{% endif %}`
+    If {
+        branches: Vec<(Expr, Vec<Node>)>,
+        else_body: Vec<Node>,
+    },
+    /// `{% for targets in iter if filter %}...{% else %}...{% endfor %}`
+    For {
+        targets: Vec<String>,
+        iter: Expr,
+        filter: Option<Expr>,
+        body: Vec<Node>,
+        else_body: Vec<Node>,
+    },
+    /// `{% set target = value %}`
+    Set { target: SetTarget, value: Expr },
+    /// `{% set name %}...{% endset %}`
+    SetBlock { name: String, body: Vec<Node> },
+    /// `{% break %}`
+    Break,
+    /// `{% continue %}`
+    Continue,
+}
+
+/// Target of a `{% set %}` statement.
+#[derive(Clone, Debug)]
+pub enum SetTarget {
+    /// `{% set x = ... %}`
+    Name(String),
+    /// `{% set x, y = ... %}`
+    Names(Vec<String>),
+    /// `{% set ns.attr = ... %}`
+    Attr(String, String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    NotIn,
+    And,
+    Or,
+}
+
+/// Expression in a parsed template.
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Literal(Value),
+    Var(String),
+    List(Vec<Expr>),
+    Dict(Vec<(Expr, Expr)>),
+    /// `expr.name`
+    Attr(Box<Expr>, String),
+    /// `expr[index]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `expr[start:stop:step]`
+    Slice {
+        expr: Box<Expr>,
+        start: Option<Box<Expr>>,
+        stop: Option<Box<Expr>>,
+        step: Option<Box<Expr>>,
+    },
+    /// `callee(args, name=kwarg)`
+    Call {
+        callee: Box<Expr>,
+        args: Args,
+    },
+    /// `expr | name(args)`
+    Filter {
+        expr: Box<Expr>,
+        name: String,
+        args: Args,
+    },
+    /// `expr is [not] name(args)`
+    Test {
+        expr: Box<Expr>,
+        name: String,
+        args: Args,
+        negated: bool,
+    },
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `then if cond else else_`
+    Cond {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        else_: Option<Box<Expr>>,
+    },
+}
+
+/// Positional and keyword arguments for a call, filter or test.
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    pub positional: Vec<Expr>,
+    pub keyword: Vec<(String, Expr)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Text(String),
+    /// `{{`
+    ExprStart,
+    /// `}}`
+    ExprEnd,
+    /// `{%`
+    StmtStart,
+    /// `%}`
+    StmtEnd,
+    Name(String),
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Op(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Self::Text(_) => "template text".to_string(),
+            Self::ExprStart => "\"{{\"".to_string(),
+            Self::ExprEnd => "\"}}\"".to_string(),
+            Self::StmtStart => "\"{%\"".to_string(),
+            Self::StmtEnd => "\"%}\"".to_string(),
+            Self::Name(name) => format!("\"{}\"", name),
+            Self::Str(_) => "string".to_string(),
+            Self::Int(_) | Self::Float(_) => "number".to_string(),
+            Self::Op(op) => format!("\"{}\"", op),
+        }
+    }
+}
+
+fn syntax_error(line: usize, message: impl Into<String>) -> ChatTemplateError {
+    ChatTemplateError::SyntaxError {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Operators, ordered so that longer operators are matched first.
+const OPERATORS: [&str; 25] = [
+    "==", "!=", "<=", ">=", "//", "**", "+", "-", "*", "/", "%", "~", "|", ".", ",", ":", "(", ")",
+    "[", "]", "{", "}", "=", "<", ">",
+];
+
+/// Whitespace handling to apply to the start of the text after a tag.
+#[derive(Clone, Copy, PartialEq)]
+enum TrimNext {
+    None,
+    /// Remove all leading whitespace (`-%}`).
+    Whitespace,
+    /// Remove a single leading newline (`%}` with `trim_blocks`).
+    Newline,
+}
+
+/// Split a template into tokens.
+///
+/// Whitespace control is applied during lexing. This follows the Hugging Face
+/// environment for chat templates, which enables the `trim_blocks` and
+/// `lstrip_blocks` options. This means that the first newline after a
+/// statement or comment is removed, and whitespace before a statement or
+/// comment at the start of a line is removed.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ChatTemplateError> {
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut pos = 0;
+    let mut trim_next = TrimNext::None;
+    let line_at = |pos: usize| source[..pos].matches('\n').count() + 1;
+
+    while pos < source.len() {
+        let tag_start = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| source[pos..].find(open).map(|i| pos + i))
+            .min();
+
+        // Extract the text before the next tag and apply whitespace control.
+        let text_end = tag_start.unwrap_or(source.len());
+        let mut text = &source[pos..text_end];
+        let mut at_line_start = pos == 0 || source[..pos].ends_with('\n');
+        match trim_next {
+            TrimNext::None => {}
+            TrimNext::Whitespace => {
+                let trimmed = text.trim_start();
+                at_line_start |= text[..text.len() - trimmed.len()].contains('\n');
+                text = trimmed;
+            }
+            TrimNext::Newline => {
+                if let Some(rest) = text.strip_prefix('\n').or(text.strip_prefix("\r\n")) {
+                    text = rest;
+                    at_line_start = true;
+                }
+            }
+        }
+
+        let Some(tag_start) = tag_start else {
+            if !text.is_empty() {
+                tokens.push((Token::Text(text.to_string()), line_at(pos)));
+            }
+            break;
+        };
+
+        let kind = source.as_bytes()[tag_start + 1];
+        let modifier = source.as_bytes().get(tag_start + 2).copied();
+        if modifier == Some(b'-') {
+            text = text.trim_end();
+        } else if kind != b'{' && modifier != Some(b'+') {
+            // lstrip_blocks
+            let line_start = match text.rfind('\n') {
+                Some(nl) => Some(nl + 1),
+                None if at_line_start => Some(0),
+                None => None,
+            };
+            if let Some(line_start) = line_start {
+                if text[line_start..].chars().all(|c| c == ' ' || c == '\t') {
+                    text = &text[..line_start];
+                }
+            }
+        }
+        if !text.is_empty() {
+            tokens.push((Token::Text(text.to_string()), line_at(pos)));
+        }
+
+        let mut tag_pos = tag_start + 2;
+        if matches!(modifier, Some(b'-' | b'+')) {
+            tag_pos += 1;
+        }
+
+        if kind == b'#' {
+            let Some(end) = source[tag_pos..].find("#}").map(|i| tag_pos + i) else {
+                return Err(syntax_error(line_at(tag_start), "unclosed comment"));
+            };
+            trim_next = match source.as_bytes()[end - 1] {
+                b'-' if end > tag_pos => TrimNext::Whitespace,
+                b'+' if end > tag_pos => TrimNext::None,
+                _ => TrimNext::Newline,
+            };
+            pos = end + 2;
+            continue;
+        }
+
+        let is_stmt = kind == b'%';
+        tokens.push((
+            if is_stmt {
+                Token::StmtStart
+            } else {
+                Token::ExprStart
+            },
+            line_at(tag_start),
+        ));
+        let (end_pos, trim) = tokenize_tag(source, tag_pos, is_stmt, &mut tokens)?;
+        pos = end_pos;
+        trim_next = trim;
+    }
+
+    Ok(tokens)
+}
+
+/// Tokenize the contents of an `{{ ... }}` or `{% ... %}` tag, starting at
+/// `pos`.
+///
+/// Returns the position after the end of the tag and the whitespace control
+/// to apply to the following text.
+fn tokenize_tag(
+    source: &str,
+    mut pos: usize,
+    is_stmt: bool,
+    tokens: &mut Vec<(Token, usize)>,
+) -> Result<(usize, TrimNext), ChatTemplateError> {
+    let tag_line = source[..pos].matches('\n').count() + 1;
+    let mut line = tag_line;
+
+    // Depth of nested `{` brackets, used to distinguish the end of a tag
+    // from the end of a dict literal.
+    let mut brace_depth = 0;
+
+    loop {
+        let rest = &source[pos..];
+        let Some(ch) = rest.chars().next() else {
+            let kind = if is_stmt { "statement" } else { "expression" };
+            return Err(syntax_error(tag_line, format!("unclosed {}", kind)));
+        };
+
+        if ch.is_whitespace() {
+            if ch == '\n' {
+                line += 1;
+            }
+            pos += ch.len_utf8();
+            continue;
+        }
+
+        if brace_depth == 0 {
+            let close = if is_stmt { "%}" } else { "}}" };
+            let end_token = if is_stmt {
+                Token::StmtEnd
+            } else {
+                Token::ExprEnd
+            };
+            let trim = if rest.starts_with(close) {
+                Some((
+                    close.len(),
+                    if is_stmt {
+                        TrimNext::Newline
+                    } else {
+                        TrimNext::None
+                    },
+                ))
+            } else if rest.starts_with('-') && rest[1..].starts_with(close) {
+                Some((close.len() + 1, TrimNext::Whitespace))
+            } else if is_stmt && rest.starts_with('+') && rest[1..].starts_with(close) {
+                Some((close.len() + 1, TrimNext::None))
+            } else {
+                None
+            };
+            if let Some((len, trim)) = trim {
+                tokens.push((end_token, line));
+                return Ok((pos + len, trim));
+            }
+        }
+
+        if ch.is_ascii_alphabetic() || ch == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push((Token::Name(rest[..len].to_string()), line));
+            pos += len;
+        } else if ch.is_ascii_digit() {
+            let (token, len) = lex_number(rest)
+                .ok_or_else(|| syntax_error(line, format!("invalid number \"{}\"", rest)))?;
+            tokens.push((token, line));
+            pos += len;
+        } else if ch == '\'' || ch == '"' {
+            let (value, len) =
+                lex_string(rest).ok_or_else(|| syntax_error(line, "unclosed string"))?;
+            line += rest[..len].matches('\n').count();
+            tokens.push((Token::Str(value), line));
+            pos += len;
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            match *op {
+                "{" => brace_depth += 1,
+                "}" => brace_depth -= 1,
+                _ => {}
+            }
+            tokens.push((Token::Op(op), line));
+            pos += op.len();
+        } else {
+            return Err(syntax_error(line, format!("unexpected character '{}'", ch)));
+        }
+    }
+}
+
+/// Lex an integer or float literal at the start of `s`.
+fn lex_number(s: &str) -> Option<(Token, usize)> {
+    let bytes = s.as_bytes();
+    let digits_end = |start: usize| {
+        start
+            + bytes[start..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit() || **b == b'_')
+                .count()
+    };
+
+    let mut end = digits_end(0);
+    let mut is_float = false;
+    if bytes.get(end) == Some(&b'.') && bytes.get(end + 1).is_some_and(|b| b.is_ascii_digit()) {
+        end = digits_end(end + 1);
+        is_float = true;
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exp_start = end + 1;
+        if matches!(bytes.get(exp_start), Some(b'+' | b'-')) {
+            exp_start += 1;
+        }
+        if bytes.get(exp_start).is_some_and(|b| b.is_ascii_digit()) {
+            end = digits_end(exp_start);
+            is_float = true;
+        }
+    }
+
+    let text = s[..end].replace('_', "");
+    let token = if is_float {
+        Token::Float(text.parse().ok()?)
+    } else {
+        Token::Int(text.parse().ok()?)
+    };
+    Some((token, end))
+}
+
+/// Lex a single or double-quoted string literal at the start of `s`.
+///
+/// Returns the unescaped value and length of the literal.
+fn lex_string(s: &str) -> Option<(String, usize)> {
+    let mut chars = s.char_indices();
+    let (_, quote) = chars.next()?;
+    let mut value = String::new();
+
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            ch if ch == quote => return Some((value, i + 1)),
+            '\\' => {
+                let (_, escaped) = chars.next()?;
+                match escaped {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    '0' => value.push('\0'),
+                    '\\' | '\'' | '"' => value.push(escaped),
+                    'u' => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let code = u32::from_str_radix(&hex, 16).ok()?;
+                        value.push(char::from_u32(code)?);
+                    }
+                    // Unknown escapes are kept as-is, as in Python.
+                    other => {
+                        value.push('\\');
+                        value.push(other);
+                    }
+                }
+            }
+            ch => value.push(ch),
+        }
+    }
+    None
+}
+
+/// Parse a template into a list of nodes.
+pub fn parse(source: &str) -> Result<Vec<Node>, ChatTemplateError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let (nodes, end_tag) = parser.parse_nodes(&[])?;
+    debug_assert!(end_tag.is_none());
+    Ok(nodes)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n).map(|(token, _)| token)
+    }
+
+    /// Return the line number of the current token.
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map(|(_, line)| *line)
+            .unwrap_or(1)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, message: impl Into<String>) -> ChatTemplateError {
+        syntax_error(self.line(), message)
+    }
+
+    fn unexpected(&self) -> ChatTemplateError {
+        match self.peek() {
+            Some(token) => self.error(format!("unexpected {}", token.describe())),
+            None => self.error("unexpected end of template"),
+        }
+    }
+
+    /// Consume the next token if it is the operator `op`.
+    fn eat_op(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(tok_op)) if *tok_op == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume the next token if it is the name `name`.
+    fn eat_name(&mut self, name: &str) -> bool {
+        if self.is_name(name) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(tok_name)) if tok_name == name)
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), ChatTemplateError> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn expect_token(&mut self, token: Token) -> Result<(), ChatTemplateError> {
+        if self.peek() == Some(&token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<String, ChatTemplateError> {
+        match self.peek() {
+            Some(Token::Name(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Parse nodes until the end of the template or a statement whose
+    /// keyword is in `end_tags`.
+    ///
+    /// Returns the nodes and the keyword of the end tag. The parser is left
+    /// positioned after the end tag's keyword.
+    fn parse_nodes(
+        &mut self,
+        end_tags: &[&str],
+    ) -> Result<(Vec<Node>, Option<String>), ChatTemplateError> {
+        let mut nodes = Vec::new();
+        while let Some(token) = self.next() {
+            match token {
+                Token::Text(text) => nodes.push(Node::Text(text)),
+                Token::ExprStart => {
+                    let expr = self.parse_expr()?;
+                    self.expect_token(Token::ExprEnd)?;
+                    nodes.push(Node::Output(expr));
+                }
+                Token::StmtStart => {
+                    let keyword = self.expect_name()?;
+                    if end_tags.contains(&keyword.as_str()) {
+                        return Ok((nodes, Some(keyword)));
+                    }
+                    self.parse_statement(&keyword, &mut nodes)?;
+                }
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.unexpected());
+                }
+            }
+        }
+
+        if !end_tags.is_empty() {
+            return Err(self.error(format!(
+                "unexpected end of template, expected \"{}\"",
+                end_tags.last().unwrap()
+            )));
+        }
+        Ok((nodes, None))
+    }
+
+    /// Parse the body of a block statement, up to and including its end tag.
+    fn parse_body(&mut self, end_tags: &[&str]) -> Result<(Vec<Node>, String), ChatTemplateError> {
+        let (nodes, end_tag) = self.parse_nodes(end_tags)?;
+        Ok((nodes, end_tag.expect("should have end tag")))
+    }
+
+    /// Parse a statement after its keyword and append the result to `nodes`.
+    fn parse_statement(
+        &mut self,
+        keyword: &str,
+        nodes: &mut Vec<Node>,
+    ) -> Result<(), ChatTemplateError> {
+        match keyword {
+            "if" => {
+                let mut branches = Vec::new();
+                let mut cond = self.parse_expr()?;
+                let else_body = loop {
+                    self.expect_token(Token::StmtEnd)?;
+                    let (body, end_tag) = self.parse_body(&["elif", "else", "endif"])?;
+                    branches.push((cond, body));
+                    match end_tag.as_str() {
+                        "elif" => cond = self.parse_expr()?,
+                        "else" => {
+                            self.expect_token(Token::StmtEnd)?;
+                            let (else_body, _) = self.parse_body(&["endif"])?;
+                            break else_body;
+                        }
+                        _ => break Vec::new(),
+                    }
+                };
+                self.expect_token(Token::StmtEnd)?;
+                nodes.push(Node::If {
+                    branches,
+                    else_body,
+                });
+            }
+            "for" => {
+                let mut targets = vec![self.expect_name()?];
+                while self.eat_op(",") {
+                    targets.push(self.expect_name()?);
+                }
+                if !self.eat_name("in") {
+                    return Err(self.unexpected());
+                }
+                let iter = self.parse_or()?;
+                let filter = if self.eat_name("if") {
+                    Some(self.parse_or()?)
+                } else {
+                    None
+                };
+                if self.is_name("recursive") {
+                    return Err(self.error("recursive loops are not supported"));
+                }
+                self.expect_token(Token::StmtEnd)?;
+                let (body, end_tag) = self.parse_body(&["else", "endfor"])?;
+                let else_body = if end_tag == "else" {
+                    self.expect_token(Token::StmtEnd)?;
+                    self.parse_body(&["endfor"])?.0
+                } else {
+                    Vec::new()
+                };
+                self.expect_token(Token::StmtEnd)?;
+                nodes.push(Node::For {
+                    targets,
+                    iter,
+                    filter,
+                    body,
+                    else_body,
+                });
+            }
+            "set" => {
+                let name = self.expect_name()?;
+                let target = if self.eat_op(".") {
+                    SetTarget::Attr(name, self.expect_name()?)
+                } else if self.is_op(",") {
+                    let mut names = vec![name];
+                    while self.eat_op(",") {
+                        names.push(self.expect_name()?);
+                    }
+                    SetTarget::Names(names)
+                } else {
+                    SetTarget::Name(name)
+                };
+
+                if self.eat_op("=") {
+                    let value = self.parse_expr()?;
+                    self.expect_token(Token::StmtEnd)?;
+                    nodes.push(Node::Set { target, value });
+                } else {
+                    let SetTarget::Name(name) = target else {
+                        return Err(self.unexpected());
+                    };
+                    self.expect_token(Token::StmtEnd)?;
+                    let (body, _) = self.parse_body(&["endset"])?;
+                    self.expect_token(Token::StmtEnd)?;
+                    nodes.push(Node::SetBlock { name, body });
+                }
+            }
+            "break" | "continue" => {
+                self.expect_token(Token::StmtEnd)?;
+                nodes.push(if keyword == "break" {
+                    Node::Break
+                } else {
+                    Node::Continue
+                });
+            }
+            // Hugging Face extension which marks assistant-generated text.
+            // This doesn't affect the output.
+            "generation" => {
+                self.expect_token(Token::StmtEnd)?;
+                let (body, _) = self.parse_body(&["endgeneration"])?;
+                self.expect_token(Token::StmtEnd)?;
+                nodes.extend(body);
+            }
+            _ => {
+                return Err(self.error(format!("unsupported statement \"{}\"", keyword)));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Some(Token::Op(tok_op)) if *tok_op == op)
+    }
+
+    /// Parse an expression, including conditional expressions.
+    fn parse_expr(&mut self) -> Result<Expr, ChatTemplateError> {
+        let mut expr = self.parse_or()?;
+        while self.eat_name("if") {
+            let cond = self.parse_or()?;
+            let else_ = if self.eat_name("else") {
+                Some(Box::new(self.parse_expr()?))
+            } else {
+                None
+            };
+            expr = Expr::Cond {
+                cond: Box::new(cond),
+                then: Box::new(expr),
+                else_,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ChatTemplateError> {
+        let mut expr = self.parse_and()?;
+        while self.eat_name("or") {
+            let rhs = self.parse_and()?;
+            expr = Expr::Binary(BinaryOp::Or, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ChatTemplateError> {
+        let mut expr = self.parse_not()?;
+        while self.eat_name("and") {
+            let rhs = self.parse_not()?;
+            expr = Expr::Binary(BinaryOp::And, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ChatTemplateError> {
+        if self.eat_name("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_compare()
+    }
+
+    fn parse_compare(&mut self) -> Result<Expr, ChatTemplateError> {
+        let mut expr = self.parse_math1()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op("==")) => BinaryOp::Eq,
+                Some(Token::Op("!=")) => BinaryOp::Ne,
+                Some(Token::Op("<")) => BinaryOp::Lt,
+                Some(Token::Op("<=")) => BinaryOp::Le,
+                Some(Token::Op(">")) => BinaryOp::Gt,
+                Some(Token::Op(">=")) => BinaryOp::Ge,
+                Some(Token::Name(name)) if name == "in" => BinaryOp::In,
+                Some(Token::Name(name))
+                    if name == "not"
+                        && matches!(self.peek_nth(1), Some(Token::Name(n)) if n == "in") =>
+                {
+                    self.pos += 1;
+                    BinaryOp::NotIn
+                }
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.parse_math1()?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    /// Parse a sequence of left-associative binary operators.
+    fn parse_binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        parse_operand: fn(&mut Self) -> Result<Expr, ChatTemplateError>,
+    ) -> Result<Expr, ChatTemplateError> {
+        let mut expr = parse_operand(self)?;
+        'outer: loop {
+            for (op_str, op) in ops {
+                if self.eat_op(op_str) {
+                    let rhs = parse_operand(self)?;
+                    expr = Expr::Binary(*op, Box::new(expr), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            break;
+        }
+        Ok(expr)
+    }
+
+    fn parse_math1(&mut self) -> Result<Expr, ChatTemplateError> {
+        self.parse_binary(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            Self::parse_concat,
+        )
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr, ChatTemplateError> {
+        self.parse_binary(&[("~", BinaryOp::Concat)], Self::parse_math2)
+    }
+
+    fn parse_math2(&mut self) -> Result<Expr, ChatTemplateError> {
+        self.parse_binary(
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("//", BinaryOp::FloorDiv),
+                ("%", BinaryOp::Mod),
+            ],
+            Self::parse_pow,
+        )
+    }
+
+    fn parse_pow(&mut self) -> Result<Expr, ChatTemplateError> {
+        self.parse_binary(&[("**", BinaryOp::Pow)], Self::parse_unary)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ChatTemplateError> {
+        let expr = if self.eat_op("-") {
+            Expr::Neg(Box::new(self.parse_unary()?))
+        } else if self.eat_op("+") {
+            self.parse_unary()?
+        } else {
+            let primary = self.parse_primary()?;
+            self.parse_postfix(primary)?
+        };
+        self.parse_filters(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ChatTemplateError> {
+        let Some(token) = self.next() else {
+            return Err(self.unexpected());
+        };
+        let expr = match token {
+            Token::Name(name) => match name.as_str() {
+                "true" | "True" => Expr::Literal(Value::Bool(true)),
+                "false" | "False" => Expr::Literal(Value::Bool(false)),
+                "none" | "None" => Expr::Literal(Value::None),
+                _ => Expr::Var(name),
+            },
+            Token::Str(mut s) => {
+                // Adjacent string literals are concatenated.
+                while let Some(Token::Str(next)) = self.peek() {
+                    s.push_str(next);
+                    self.pos += 1;
+                }
+                Expr::Literal(Value::str(&s))
+            }
+            Token::Int(i) => Expr::Literal(Value::Int(i)),
+            Token::Float(f) => Expr::Literal(Value::Float(f)),
+            Token::Op("(") => {
+                let expr = self.parse_expr()?;
+                if self.is_op(",") {
+                    // Tuples are represented as lists.
+                    let mut items = vec![expr];
+                    while self.eat_op(",") && !self.is_op(")") {
+                        items.push(self.parse_expr()?);
+                    }
+                    self.expect_op(")")?;
+                    Expr::List(items)
+                } else {
+                    self.expect_op(")")?;
+                    expr
+                }
+            }
+            Token::Op("[") => {
+                let mut items = Vec::new();
+                while !self.is_op("]") {
+                    items.push(self.parse_expr()?);
+                    if !self.eat_op(",") {
+                        break;
+                    }
+                }
+                self.expect_op("]")?;
+                Expr::List(items)
+            }
+            Token::Op("{") => {
+                let mut items = Vec::new();
+                while !self.is_op("}") {
+                    let key = self.parse_expr()?;
+                    self.expect_op(":")?;
+                    let value = self.parse_expr()?;
+                    items.push((key, value));
+                    if !self.eat_op(",") {
+                        break;
+                    }
+                }
+                self.expect_op("}")?;
+                Expr::Dict(items)
+            }
+            _ => {
+                self.pos -= 1;
+                return Err(self.unexpected());
+            }
+        };
+        Ok(expr)
+    }
+
+    /// Parse attribute accesses, subscripts and calls following an
+    /// expression.
+    fn parse_postfix(&mut self, mut expr: Expr) -> Result<Expr, ChatTemplateError> {
+        loop {
+            if self.eat_op(".") {
+                expr = match self.next() {
+                    Some(Token::Name(name)) => Expr::Attr(Box::new(expr), name),
+                    Some(Token::Int(index)) => {
+                        Expr::Index(Box::new(expr), Box::new(Expr::Literal(Value::Int(index))))
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.unexpected());
+                    }
+                };
+            } else if self.eat_op("[") {
+                expr = self.parse_subscript(expr)?;
+            } else if self.eat_op("(") {
+                let args = self.parse_args()?;
+                expr = Expr::Call {
+                    callee: Box::new(expr),
+                    args,
+                };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Parse an index or slice after the opening `[`.
+    fn parse_subscript(&mut self, expr: Expr) -> Result<Expr, ChatTemplateError> {
+        let mut parts: Vec<Option<Box<Expr>>> = Vec::new();
+        let mut is_slice = false;
+        loop {
+            if self.is_op(":") || self.is_op("]") {
+                parts.push(None);
+            } else {
+                parts.push(Some(Box::new(self.parse_expr()?)));
+            }
+            if self.eat_op(":") {
+                is_slice = true;
+                if parts.len() == 3 {
+                    return Err(self.unexpected());
+                }
+            } else {
+                break;
+            }
+        }
+        self.expect_op("]")?;
+
+        if !is_slice {
+            let index = parts.pop().flatten().ok_or_else(|| self.unexpected())?;
+            return Ok(Expr::Index(Box::new(expr), index));
+        }
+
+        let mut parts = parts.into_iter();
+        Ok(Expr::Slice {
+            expr: Box::new(expr),
+            start: parts.next().flatten(),
+            stop: parts.next().flatten(),
+            step: parts.next().flatten(),
+        })
+    }
+
+    /// Parse call arguments after the opening `(`.
+    fn parse_args(&mut self) -> Result<Args, ChatTemplateError> {
+        let mut args = Args::default();
+        while !self.is_op(")") {
+            let is_keyword = matches!(self.peek(), Some(Token::Name(_)))
+                && matches!(self.peek_nth(1), Some(Token::Op("=")));
+            if is_keyword {
+                let name = self.expect_name()?;
+                self.pos += 1;
+                args.keyword.push((name, self.parse_expr()?));
+            } else {
+                args.positional.push(self.parse_expr()?);
+            }
+            if !self.eat_op(",") {
+                break;
+            }
+        }
+        self.expect_op(")")?;
+        Ok(args)
+    }
+
+    /// Parse filters (`expr | name`) and tests (`expr is name`) following an
+    /// expression.
+    fn parse_filters(&mut self, mut expr: Expr) -> Result<Expr, ChatTemplateError> {
+        loop {
+            if self.eat_op("|") {
+                let name = self.expect_name()?;
+                let args = if self.eat_op("(") {
+                    self.parse_args()?
+                } else {
+                    Args::default()
+                };
+                expr = Expr::Filter {
+                    expr: Box::new(expr),
+                    name,
+                    args,
+                };
+            } else if self.eat_name("is") {
+                let negated = self.eat_name("not");
+                let name = self.expect_name()?;
+                let args = if self.eat_op("(") {
+                    self.parse_args()?
+                } else if self.starts_test_arg() {
+                    // Tests can take a single argument without parentheses,
+                    // eg. `x is divisibleby 3`.
+                    let primary = self.parse_primary()?;
+                    Args {
+                        positional: vec![self.parse_postfix(primary)?],
+                        keyword: Vec::new(),
+                    }
+                } else {
+                    Args::default()
+                };
+                expr = Expr::Test {
+                    expr: Box::new(expr),
+                    name,
+                    args,
+                    negated,
+                };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Return true if the next token starts an argument to a test which is
+    /// used without parentheses.
+    fn starts_test_arg(&self) -> bool {
+        match self.peek() {
+            Some(Token::Name(name)) => !matches!(
+                name.as_str(),
+                "and" | "or" | "else" | "if" | "in" | "is" | "not"
+            ),
+            Some(Token::Str(_) | Token::Int(_) | Token::Float(_)) => true,
+            Some(Token::Op(op)) => matches!(*op, "[" | "{"),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+
+    use super::{lex_number, lex_string, parse, tokenize, BinaryOp, Expr, Node, SetTarget, Token};
+    use crate::chat_template::value::Value;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = tokenize("a {{- x + 'y' }}\n{# comment #}\n{% if n > 1.5 -%}  b").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::Text("a".to_string()), 1),
+                (Token::ExprStart, 1),
+                (Token::Name("x".to_string()), 1),
+                (Token::Op("+"), 1),
+                (Token::Str("y".to_string()), 1),
+                (Token::ExprEnd, 1),
+                (Token::Text("\n".to_string()), 1),
+                (Token::StmtStart, 3),
+                (Token::Name("if".to_string()), 3),
+                (Token::Name("n".to_string()), 3),
+                (Token::Op(">"), 3),
+                (Token::Float(1.5), 3),
+                (Token::StmtEnd, 3),
+                (Token::Text("b".to_string()), 3),
+            ]
+        );
+
+        // A `}` in a dict literal does not end the expression.
+        let tokens = tokenize("{{ {'a': 1} }}").unwrap();
+        assert_eq!(tokens.len(), 7);
+        assert_eq!(tokens.last(), Some(&(Token::ExprEnd, 1)));
+    }
+
+    #[test]
+    fn test_lex_literals() {
+        assert_eq!(lex_number("1_000 "), Some((Token::Int(1000), 5)));
+        assert_eq!(lex_number("2.5e-1]"), Some((Token::Float(0.25), 6)));
+        assert_eq!(lex_number("3.x"), Some((Token::Int(3), 1)));
+        assert_eq!(lex_number("99999999999999999999"), None);
+
+        assert_eq!(
+            lex_string(r#"'a\n\'bé\d' rest"#),
+            Some(("a\n'bé\\d".to_string(), 12))
+        );
+        assert_eq!(lex_string(r#""it's""#), Some(("it's".to_string(), 6)));
+        assert_eq!(lex_string("'unclosed"), None);
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let nodes = parse("{{ not a or b and 1 + 2 * 3 ** 2 }}").unwrap();
+        let [Node::Output(expr)] = &nodes[..] else {
+            panic!("expected output node");
+        };
+
+        // `or` binds most loosely, then `and`, `not`, `+`, `*` and `**`.
+        let Expr::Binary(BinaryOp::Or, lhs, rhs) = expr else {
+            panic!("expected `or`");
+        };
+        assert!(
+            matches!(lhs.as_ref(), Expr::Not(var) if matches!(var.as_ref(), Expr::Var(name) if name == "a"))
+        );
+        let Expr::Binary(BinaryOp::And, _, sum) = rhs.as_ref() else {
+            panic!("expected `and`");
+        };
+        let Expr::Binary(BinaryOp::Add, one, product) = sum.as_ref() else {
+            panic!("expected `+`");
+        };
+        assert!(matches!(one.as_ref(), Expr::Literal(Value::Int(1))));
+        let Expr::Binary(BinaryOp::Mul, _, power) = product.as_ref() else {
+            panic!("expected `*`");
+        };
+        assert!(matches!(power.as_ref(), Expr::Binary(BinaryOp::Pow, ..)));
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let nodes = parse(
+            "{% for k, v in items if v %}{{ k }}{% else %}none{% endfor %}\
+             {% set ns.count = 1 %}{% set a, b = pair %}",
+        )
+        .unwrap();
+
+        let [Node::For {
+            targets,
+            filter,
+            body,
+            else_body,
+            ..
+        }, Node::Set {
+            target: SetTarget::Attr(obj, attr),
+            ..
+        }, Node::Set {
+            target: SetTarget::Names(names),
+            ..
+        }] = &nodes[..]
+        else {
+            panic!("unexpected nodes {:?}", nodes);
+        };
+        assert_eq!(targets, &["k", "v"]);
+        assert!(filter.is_some());
+        assert!(matches!(&body[..], [Node::Output(Expr::Var(name))] if name == "k"));
+        assert!(matches!(&else_body[..], [Node::Text(text)] if text == "none"));
+        assert_eq!((obj.as_str(), attr.as_str()), ("ns", "count"));
+        assert_eq!(names, &["a", "b"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            template: &'a str,
+            expected: &'a str,
+        }
+
+        let cases = [
+            Case {
+                template: "{{ 'abc }}",
+                expected: "template syntax error on line 1: unclosed string",
+            },
+            Case {
+                template: "\n{{ a",
+                expected: "template syntax error on line 2: unclosed expression",
+            },
+            Case {
+                template: "{# comment",
+                expected: "template syntax error on line 1: unclosed comment",
+            },
+            Case {
+                template: "{{ a ; b }}",
+                expected: "template syntax error on line 1: unexpected character ';'",
+            },
+            Case {
+                template: "{% for x in y %}\n{% endif %}",
+                expected: "template syntax error on line 2: unsupported statement \"endif\"",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let err = parse(case.template).err().unwrap();
+            assert_eq!(err.to_string(), case.expected);
+        })
+    }
+}
@@ -0,0 +1,570 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// A value produced when evaluating a template expression.
+///
+/// Values follow the semantics of the Python types that Jinja uses. For
+/// example the string form of a list is its Python `repr`.
+#[derive(Clone, Debug)]
+pub enum Value {
+    /// A variable, attribute or item that does not exist.
+    Undefined,
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Rc<str>),
+    List(Rc<Vec<Value>>),
+    Map(Rc<Map>),
+    /// Mutable object created by `namespace()`, whose attributes can be
+    /// assigned using `{% set ns.attr = value %}`.
+    Namespace(Rc<RefCell<Map>>),
+    Function(Function),
+}
+
+/// Global functions available to templates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Function {
+    Dict,
+    Namespace,
+    RaiseException,
+    Range,
+}
+
+impl Function {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Dict => "dict",
+            Self::Namespace => "namespace",
+            Self::RaiseException => "raise_exception",
+            Self::Range => "range",
+        }
+    }
+}
+
+/// Map with string keys which preserves insertion order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Map {
+    entries: Vec<(Rc<str>, Value)>,
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.as_ref() == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Insert or replace the value for a key.
+    pub fn insert(&mut self, key: &str, value: Value) {
+        match self.entries.iter_mut().find(|(k, _)| k.as_ref() == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.into(), value)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Rc<str>, &Value)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+}
+
+impl FromIterator<(Rc<str>, Value)> for Map {
+    fn from_iter<I: IntoIterator<Item = (Rc<str>, Value)>>(iter: I) -> Self {
+        let mut map = Map::new();
+        for (key, value) in iter {
+            map.insert(&key, value);
+        }
+        map
+    }
+}
+
+impl Value {
+    pub fn str(s: &str) -> Value {
+        Value::Str(s.into())
+    }
+
+    pub fn list(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(items))
+    }
+
+    pub fn map(map: Map) -> Value {
+        Value::Map(Rc::new(map))
+    }
+
+    /// Convert a JSON value into a template value.
+    pub fn from_json(json: &serde_json::Value) -> Value {
+        match json {
+            serde_json::Value::Null => Value::None,
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Value::str(s),
+            serde_json::Value::Array(items) => {
+                Value::list(items.iter().map(Value::from_json).collect())
+            }
+            serde_json::Value::Object(obj) => Value::map(
+                obj.iter()
+                    .map(|(k, v)| (k.as_str().into(), Value::from_json(v)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Return the name of this value's type, for use in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Undefined => "undefined",
+            Self::None => "none",
+            Self::Bool(_) => "boolean",
+            Self::Int(_) => "integer",
+            Self::Float(_) => "float",
+            Self::Str(_) => "string",
+            Self::List(_) => "list",
+            Self::Map(_) => "dict",
+            Self::Namespace(_) => "namespace",
+            Self::Function(_) => "function",
+        }
+    }
+
+    /// Return true if this value is considered true in a boolean context.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::Undefined | Self::None => false,
+            Self::Bool(b) => *b,
+            Self::Int(i) => *i != 0,
+            Self::Float(f) => *f != 0.,
+            Self::Str(s) => !s.is_empty(),
+            Self::List(items) => !items.is_empty(),
+            Self::Map(map) => map.len() > 0,
+            Self::Namespace(_) | Self::Function(_) => true,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Return the numeric value of an integer or float.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(i) => Some(*i as f64),
+            Self::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Return the items produced by iterating over this value, or `None` if
+    /// the value is not iterable.
+    ///
+    /// Iterating over a map produces its keys. Iterating over a string
+    /// produces its characters.
+    pub fn iter_items(&self) -> Option<Vec<Value>> {
+        match self {
+            Self::Undefined => Some(Vec::new()),
+            Self::List(items) => Some(items.as_ref().clone()),
+            Self::Map(map) => Some(map.iter().map(|(k, _)| Value::Str(k.clone())).collect()),
+            Self::Str(s) => Some(
+                s.chars()
+                    .map(|ch| Value::str(ch.encode_utf8(&mut [0; 4])))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Compare two values using the `<`, `<=`, `>` and `>=` operators.
+    pub fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Str(a), Self::Str(b)) => Some(a.cmp(b)),
+            (Self::List(a), Self::List(b)) => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    match a.partial_cmp(b)? {
+                        Ordering::Equal => continue,
+                        ord => return Some(ord),
+                    }
+                }
+                Some(a.len().cmp(&b.len()))
+            }
+            (Self::Bool(a), Self::Bool(b)) => Some(a.cmp(b)),
+            (a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        }
+    }
+
+    /// Return the Python `repr` of this value.
+    pub fn repr(&self) -> String {
+        let mut out = String::new();
+        self.write_repr(&mut out);
+        out
+    }
+
+    fn write_repr(&self, out: &mut String) {
+        match self {
+            Self::Str(s) => {
+                // Python uses single quotes unless the string contains single
+                // quotes but not double quotes.
+                let quote = if s.contains('\'') && !s.contains('"') {
+                    '"'
+                } else {
+                    '\''
+                };
+                out.push(quote);
+                for ch in s.chars() {
+                    match ch {
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        '\t' => out.push_str("\\t"),
+                        ch if ch == quote => {
+                            out.push('\\');
+                            out.push(ch);
+                        }
+                        ch if ch.is_control() => {
+                            write!(out, "\\x{:02x}", ch as u32).unwrap();
+                        }
+                        ch => out.push(ch),
+                    }
+                }
+                out.push(quote);
+            }
+            Self::List(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    item.write_repr(out);
+                }
+                out.push(']');
+            }
+            Self::Map(map) => write_map_repr(map, out),
+            Self::Namespace(map) => {
+                out.push_str("<Namespace ");
+                write_map_repr(&map.borrow(), out);
+                out.push('>');
+            }
+            other => write!(out, "{}", other).unwrap(),
+        }
+    }
+
+    /// Serialize this value as JSON in the same way as Python's `json.dumps`.
+    ///
+    /// If `indent` is `None` the output is on one line, with a space after
+    /// each `,` and `:` separator. Otherwise each item in a list or map is
+    /// on a new line, indented by `indent` spaces per level of nesting.
+    pub fn to_json(&self, indent: Option<usize>) -> Result<String, String> {
+        let mut out = String::new();
+        self.write_json(&mut out, indent, 0)?;
+        Ok(out)
+    }
+
+    fn write_json(
+        &self,
+        out: &mut String,
+        indent: Option<usize>,
+        depth: usize,
+    ) -> Result<(), String> {
+        let write_newline = |out: &mut String, depth: usize| {
+            if let Some(indent) = indent {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent * depth));
+            }
+        };
+        let item_sep = if indent.is_some() { "," } else { ", " };
+
+        match self {
+            Self::None => out.push_str("null"),
+            Self::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Self::Int(i) => write!(out, "{}", i).unwrap(),
+            Self::Float(f) if f.is_nan() => out.push_str("NaN"),
+            Self::Float(f) if f.is_infinite() => {
+                out.push_str(if *f > 0. { "Infinity" } else { "-Infinity" })
+            }
+            Self::Float(f) => write_float(*f, out),
+            Self::Str(s) => write_json_str(s, out),
+            Self::List(items) => {
+                if items.is_empty() {
+                    out.push_str("[]");
+                    return Ok(());
+                }
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(item_sep);
+                    }
+                    write_newline(out, depth + 1);
+                    item.write_json(out, indent, depth + 1)?;
+                }
+                write_newline(out, depth);
+                out.push(']');
+            }
+            Self::Map(map) => {
+                if map.len() == 0 {
+                    out.push_str("{}");
+                    return Ok(());
+                }
+                out.push('{');
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(item_sep);
+                    }
+                    write_newline(out, depth + 1);
+                    write_json_str(key, out);
+                    out.push_str(": ");
+                    value.write_json(out, indent, depth + 1)?;
+                }
+                write_newline(out, depth);
+                out.push('}');
+            }
+            Self::Undefined | Self::Namespace(_) | Self::Function(_) => {
+                return Err(format!(
+                    "value of type {} is not JSON serializable",
+                    self.type_name()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_map_repr(map: &Map, out: &mut String) {
+    out.push('{');
+    for (i, (key, value)) in map.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        Value::Str(key.clone()).write_repr(out);
+        out.push_str(": ");
+        value.write_repr(out);
+    }
+    out.push('}');
+}
+
+/// Format a float in the same way as Python's `repr`, which always includes
+/// a decimal point or exponent.
+fn write_float(val: f64, out: &mut String) {
+    if val.is_finite() && val.fract() == 0. && val.abs() < 1e16 {
+        write!(out, "{:.1}", val).unwrap();
+    } else {
+        write!(out, "{}", val).unwrap();
+    }
+}
+
+/// Write a JSON string literal. Non-ASCII characters are written as-is,
+/// as with `json.dumps(..., ensure_ascii=False)`.
+fn write_json_str(s: &str, out: &mut String) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            ch if (ch as u32) < 0x20 => write!(out, "\\u{:04x}", ch as u32).unwrap(),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Self::Undefined, Self::Undefined) => true,
+            (Self::None, Self::None) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Map(a), Self::Map(b)) => a == b,
+            (Self::Namespace(a), Self::Namespace(b)) => Rc::ptr_eq(a, b),
+            (Self::Function(a), Self::Function(b)) => a == b,
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Format a value as it appears in template output. This matches Python's
+/// `str` function, except that undefined values produce an empty string.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Undefined => Ok(()),
+            Self::None => write!(f, "None"),
+            Self::Bool(b) => write!(f, "{}", if *b { "True" } else { "False" }),
+            Self::Int(i) => write!(f, "{}", i),
+            Self::Float(x) => {
+                let mut out = String::new();
+                if x.is_nan() {
+                    out.push_str("nan");
+                } else if x.is_infinite() {
+                    out.push_str(if *x > 0. { "inf" } else { "-inf" });
+                } else {
+                    write_float(*x, &mut out);
+                }
+                write!(f, "{}", out)
+            }
+            Self::Str(s) => write!(f, "{}", s),
+            Self::Function(func) => write!(f, "<function {}>", func.name()),
+            Self::List(_) | Self::Map(_) | Self::Namespace(_) => write!(f, "{}", self.repr()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use serde_json::json;
+
+    use super::{Map, Value};
+
+    #[test]
+    fn test_repr_and_display() {
+        // Tuples of (value, repr, display).
+        let cases = [
+            (Value::Undefined, "", ""),
+            (Value::None, "None", "None"),
+            (Value::Bool(true), "True", "True"),
+            (Value::Float(2.), "2.0", "2.0"),
+            (Value::Float(f64::NEG_INFINITY), "-inf", "-inf"),
+            (Value::str("it's"), "\"it's\"", "it's"),
+            (Value::str("a\n'b\""), "'a\\n\\'b\"'", "a\n'b\""),
+            (
+                Value::list(vec![Value::Int(1), Value::str("a"), Value::None]),
+                "[1, 'a', None]",
+                "[1, 'a', None]",
+            ),
+            (
+                Value::from_json(&json!({"a": [1.5, false]})),
+                "{'a': [1.5, False]}",
+                "{'a': [1.5, False]}",
+            ),
+        ];
+
+        for (value, repr, display) in cases {
+            assert_eq!(value.repr(), repr, "{:?}", value);
+            assert_eq!(value.to_string(), display, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let value = Value::from_json(&json!({
+            "args": [1, 2.5, null, true],
+            "empty": {},
+            "name": "say \"hi\"",
+        }));
+
+        assert_eq!(
+            value.to_json(None).unwrap(),
+            r#"{"args": [1, 2.5, null, true], "empty": {}, "name": "say \"hi\""}"#
+        );
+        assert_eq!(
+            value.to_json(Some(2)).unwrap(),
+            "{\n  \"args\": [\n    1,\n    2.5,\n    null,\n    true\n  ],\n  \"empty\": {},\n  \"name\": \"say \\\"hi\\\"\"\n}"
+        );
+
+        let err = Value::Undefined.to_json(None).err().unwrap();
+        assert_eq!(err, "value of type undefined is not JSON serializable");
+    }
+
+    #[test]
+    fn test_is_truthy() {
+        let truthy = [
+            Value::Bool(true),
+            Value::Int(-1),
+            Value::Float(0.5),
+            Value::str(" "),
+            Value::list(vec![Value::None]),
+            Value::from_json(&json!({"a": null})),
+        ];
+        for value in truthy {
+            assert!(value.is_truthy(), "{:?}", value);
+        }
+
+        let falsy = [
+            Value::Undefined,
+            Value::None,
+            Value::Bool(false),
+            Value::Int(0),
+            Value::Float(0.),
+            Value::str(""),
+            Value::list(Vec::new()),
+            Value::map(Map::new()),
+        ];
+        for value in falsy {
+            assert!(!value.is_truthy(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(Value::Int(1), Value::Float(1.));
+        assert_ne!(Value::Int(1), Value::str("1"));
+        assert_eq!(
+            Value::Int(1).partial_cmp(&Value::Float(1.5)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            Value::str("b").partial_cmp(&Value::str("ab")),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            Value::list(vec![Value::Int(1)])
+                .partial_cmp(&Value::list(vec![Value::Int(1), Value::Int(0)])),
+            Some(Ordering::Less)
+        );
+        assert_eq!(Value::Int(1).partial_cmp(&Value::str("a")), None);
+    }
+
+    #[test]
+    fn test_iter_items() {
+        let items = Value::str("hé").iter_items().unwrap();
+        assert_eq!(items, [Value::str("h"), Value::str("é")]);
+
+        // Map keys are produced in insertion order.
+        let mut map = Map::new();
+        map.insert("b", Value::Int(1));
+        map.insert("a", Value::Int(2));
+        let items = Value::map(map).iter_items().unwrap();
+        assert_eq!(items, [Value::str("b"), Value::str("a")]);
+
+        assert_eq!(Value::Undefined.iter_items(), Some(Vec::new()));
+        assert_eq!(Value::Int(1).iter_items(), None);
+    }
+
+    #[test]
+    fn test_map_insert() {
+        let mut map = Map::new();
+        map.insert("a", Value::Int(1));
+        map.insert("b", Value::Int(2));
+        map.insert("a", Value::Int(3));
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("a"), Some(&Value::Int(3)));
+        let keys: Vec<_> = map.iter().map(|(k, _)| k.as_ref()).collect();
+        assert_eq!(keys, ["a", "b"]);
+    }
+}
@@ -0,0 +1,628 @@
+//! Decoders which convert the string representations of tokens into text.
+//!
+//! Decoders are applied by [`Tokenizer::decode`](crate::Tokenizer::decode) to
+//! undo transformations made during encoding, such as replacing spaces with
+//! `▁` or marking word continuations with `##`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use fancy_regex::{NoExpand, Regex};
+use serde_json::json;
+
+use crate::models::char_to_byte;
+
+/// Errors occuring while constructing a [`Decoder`] or decoding tokens.
+#[derive(Clone, Debug)]
+pub enum DecoderError {
+    RegexError(Box<fancy_regex::Error>),
+
+    /// The decoded byte sequence does not form a valid UTF-8 string.
+    ///
+    /// This can arise when decoding tokens which represent bytes rather than
+    /// whole Unicode characters. If this error is encountered in the middle
+    /// of a process that generates tokens, the solution is to accumulate more
+    /// tokens and then try decoding again.
+    InvalidUtf8,
+}
+
+impl fmt::Display for DecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RegexError(err) => write!(f, "regex failed {}", err),
+            Self::InvalidUtf8 => write!(f, "decoded tokens do not form valid UTF-8 text"),
+        }
+    }
+}
+
+impl Error for DecoderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::RegexError(err) => Some(err),
+            Self::InvalidUtf8 => None,
+        }
+    }
+}
+
+impl From<fancy_regex::Error> for DecoderError {
+    fn from(val: fancy_regex::Error) -> Self {
+        Self::RegexError(Box::new(val))
+    }
+}
+
+/// A decoder transforms the string representations of tokens, as returned
+/// by [`Model::get_token_str`](crate::models::Model::get_token_str), into
+/// text.
+///
+/// Decoders operate on a list of strings and return a new list. The decoded
+/// text is the concatenation of the output.
+pub trait Decoder: Send + Sync {
+    /// Transform a list of token strings.
+    fn decode_chain(&self, tokens: Vec<String>) -> Result<Vec<String>, DecoderError>;
+
+    /// Return the configuration of this decoder in the format used by
+    /// Hugging Face `tokenizer.json` files, or `None` if it cannot be
+    /// represented in that format.
+    ///
+    /// The default implementation returns `None`.
+    fn to_json(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Decoder for byte-level BPE tokenizers such as GPT-2.
+///
+/// This maps the printable characters used to represent bytes in the
+/// vocabulary back to bytes and decodes them as UTF-8.
+pub struct ByteLevel {
+    char_to_byte: HashMap<char, u8>,
+    add_prefix_space: bool,
+    trim_offsets: bool,
+    use_regex: bool,
+}
+
+impl ByteLevel {
+    /// Create a byte-level decoder.
+    ///
+    /// The options correspond to those of the `ByteLevel` pre-tokenizer and
+    /// post-processor. They do not affect decoding, but are preserved when
+    /// the decoder is serialized with [`to_json`](Decoder::to_json).
+    pub fn new(add_prefix_space: bool, trim_offsets: bool, use_regex: bool) -> Self {
+        ByteLevel {
+            char_to_byte: char_to_byte(),
+            add_prefix_space,
+            trim_offsets,
+            use_regex,
+        }
+    }
+}
+
+impl Default for ByteLevel {
+    fn default() -> Self {
+        Self::new(true, true, true)
+    }
+}
+
+impl Decoder for ByteLevel {
+    fn decode_chain(&self, tokens: Vec<String>) -> Result<Vec<String>, DecoderError> {
+        let mut bytes = Vec::new();
+        for token in &tokens {
+            let token_bytes: Option<Vec<u8>> = token
+                .chars()
+                .map(|ch| self.char_to_byte.get(&ch).copied())
+                .collect();
+
+            // Tokens which are not byte-level encoded, such as added tokens,
+            // are kept as-is.
+            match token_bytes {
+                Some(token_bytes) => bytes.extend(token_bytes),
+                None => bytes.extend(token.as_bytes()),
+            }
+        }
+        let text = String::from_utf8(bytes).map_err(|_| DecoderError::InvalidUtf8)?;
+        Ok(vec![text])
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "ByteLevel",
+            "add_prefix_space": self.add_prefix_space,
+            "trim_offsets": self.trim_offsets,
+            "use_regex": self.use_regex,
+        }))
+    }
+}
+
+/// Decoder which replaces a marker character (usually `▁`) with spaces.
+///
+/// This reverses the replacement of spaces with `▁` by SentencePiece-style
+/// tokenizers.
+pub struct Metaspace {
+    replacement: char,
+    strip_prefix_space: bool,
+    split: bool,
+}
+
+impl Metaspace {
+    /// Create a decoder which replaces `replacement` with spaces.
+    ///
+    /// If `strip_prefix_space` is true, a leading space at the start of the
+    /// first token is removed. This should be set if a space was added to
+    /// the start of the input when encoding.
+    pub fn new(replacement: char, strip_prefix_space: bool) -> Self {
+        Metaspace {
+            replacement,
+            strip_prefix_space,
+            split: true,
+        }
+    }
+
+    /// Set whether the corresponding pre-tokenizer splits the input on
+    /// `replacement`.
+    ///
+    /// This does not affect decoding, but is preserved when the decoder is
+    /// serialized with [`to_json`](Decoder::to_json).
+    pub fn with_split(mut self, split: bool) -> Self {
+        self.split = split;
+        self
+    }
+}
+
+impl Decoder for Metaspace {
+    fn decode_chain(&self, tokens: Vec<String>) -> Result<Vec<String>, DecoderError> {
+        Ok(tokens
+            .into_iter()
+            .enumerate()
+            .map(|(i, token)| {
+                let token = token.replace(self.replacement, " ");
+                match token.strip_prefix(' ') {
+                    Some(stripped) if i == 0 && self.strip_prefix_space => stripped.to_string(),
+                    _ => token,
+                }
+            })
+            .collect())
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Metaspace",
+            "replacement": self.replacement,
+            "prepend_scheme": if self.strip_prefix_space { "always" } else { "never" },
+            "split": self.split,
+        }))
+    }
+}
+
+/// Decoder for WordPiece tokenizers.
+///
+/// This removes the prefix (usually `##`) from tokens which continue a word
+/// and adds spaces before other tokens.
+pub struct WordPiece {
+    prefix: String,
+    cleanup: bool,
+}
+
+impl WordPiece {
+    /// Create a WordPiece decoder.
+    ///
+    /// If `cleanup` is true, spaces which were added before punctuation and
+    /// English contractions (eg. "n't") are removed.
+    pub fn new(prefix: String, cleanup: bool) -> Self {
+        WordPiece { prefix, cleanup }
+    }
+}
+
+impl Default for WordPiece {
+    fn default() -> Self {
+        Self::new("##".to_string(), true)
+    }
+}
+
+/// Remove spaces introduced by tokenization around punctuation and English
+/// contractions.
+fn cleanup(text: &str) -> String {
+    [
+        (" .", "."),
+        (" ?", "?"),
+        (" !", "!"),
+        (" ,", ","),
+        (" ' ", "'"),
+        (" n't", "n't"),
+        (" 'm", "'m"),
+        (" do not", " don't"),
+        (" 's", "'s"),
+        (" 've", "'ve"),
+        (" 're", "'re"),
+    ]
+    .into_iter()
+    .fold(text.to_string(), |text, (from, to)| text.replace(from, to))
+}
+
+impl Decoder for WordPiece {
+    fn decode_chain(&self, tokens: Vec<String>) -> Result<Vec<String>, DecoderError> {
+        Ok(tokens
+            .into_iter()
+            .enumerate()
+            .map(|(i, token)| {
+                let token = if i == 0 {
+                    token
+                } else if let Some(rest) = token.strip_prefix(&self.prefix) {
+                    rest.to_string()
+                } else {
+                    format!(" {}", token)
+                };
+                if self.cleanup {
+                    cleanup(&token)
+                } else {
+                    token
+                }
+            })
+            .collect())
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "WordPiece",
+            "prefix": self.prefix,
+            "cleanup": self.cleanup,
+        }))
+    }
+}
+
+/// Decoder which converts byte tokens of the form `<0xXX>` into text.
+///
+/// This is used by tokenizers with byte fallback, which encode characters
+/// that are not in the vocabulary as a sequence of UTF-8 bytes. If a run of
+/// byte tokens does not form valid UTF-8, each token in the run is replaced
+/// with U+FFFD.
+#[derive(Default)]
+pub struct ByteFallback {}
+
+impl ByteFallback {
+    pub fn new() -> Self {
+        ByteFallback {}
+    }
+}
+
+/// Parse a token of the form `<0xXX>` into a byte.
+fn parse_byte_token(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+/// Convert a run of byte tokens to text and append it to `output`.
+///
+/// As in Hugging Face tokenizers, an invalid sequence produces one U+FFFD
+/// for each byte.
+fn push_bytes(output: &mut Vec<String>, bytes: Vec<u8>) {
+    match String::from_utf8(bytes) {
+        Ok(text) => output.push(text),
+        Err(err) => output.extend(std::iter::repeat_n(
+            char::REPLACEMENT_CHARACTER.to_string(),
+            err.as_bytes().len(),
+        )),
+    }
+}
+
+impl Decoder for ByteFallback {
+    fn decode_chain(&self, tokens: Vec<String>) -> Result<Vec<String>, DecoderError> {
+        let mut output = Vec::with_capacity(tokens.len());
+        let mut bytes = Vec::new();
+
+        for token in tokens {
+            if let Some(byte) = parse_byte_token(&token) {
+                bytes.push(byte);
+                continue;
+            }
+            if !bytes.is_empty() {
+                push_bytes(&mut output, std::mem::take(&mut bytes));
+            }
+            output.push(token);
+        }
+
+        if !bytes.is_empty() {
+            push_bytes(&mut output, bytes);
+        }
+
+        Ok(output)
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({ "type": "ByteFallback" }))
+    }
+}
+
+/// Decoder which concatenates all tokens into a single string.
+#[derive(Default)]
+pub struct Fuse {}
+
+impl Fuse {
+    pub fn new() -> Self {
+        Fuse {}
+    }
+}
+
+impl Decoder for Fuse {
+    fn decode_chain(&self, tokens: Vec<String>) -> Result<Vec<String>, DecoderError> {
+        Ok(vec![tokens.concat()])
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({ "type": "Fuse" }))
+    }
+}
+
+/// Decoder which removes up to a given number of occurrences of a character
+/// from the start and end of each token.
+pub struct Strip {
+    content: char,
+    start: usize,
+    stop: usize,
+}
+
+impl Strip {
+    /// Create a decoder which strips up to `start` occurrences of `content`
+    /// from the start of each token and up to `stop` from the end.
+    pub fn new(content: char, start: usize, stop: usize) -> Self {
+        Strip {
+            content,
+            start,
+            stop,
+        }
+    }
+}
+
+impl Decoder for Strip {
+    fn decode_chain(&self, tokens: Vec<String>) -> Result<Vec<String>, DecoderError> {
+        Ok(tokens
+            .into_iter()
+            .map(|token| {
+                let mut token = token.as_str();
+                for _ in 0..self.start {
+                    match token.strip_prefix(self.content) {
+                        Some(rest) => token = rest,
+                        None => break,
+                    }
+                }
+                for _ in 0..self.stop {
+                    match token.strip_suffix(self.content) {
+                        Some(rest) => token = rest,
+                        None => break,
+                    }
+                }
+                token.to_string()
+            })
+            .collect())
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Strip",
+            "content": self.content,
+            "start": self.start,
+            "stop": self.stop,
+        }))
+    }
+}
+
+/// Decoder which replaces matches of a pattern in each token.
+pub struct Replace {
+    regex: Regex,
+    content: String,
+}
+
+impl Replace {
+    /// Replaces occurrences of `pattern` with `content`.
+    ///
+    /// `pattern` is a regex pattern. See the
+    /// [fancy-regex](https://docs.rs/fancy-regex/) docs for supported syntax.
+    pub fn new(pattern: &str, content: String) -> Result<Replace, DecoderError> {
+        Ok(Replace {
+            regex: Regex::new(pattern)?,
+            content,
+        })
+    }
+}
+
+impl Decoder for Replace {
+    fn decode_chain(&self, tokens: Vec<String>) -> Result<Vec<String>, DecoderError> {
+        tokens
+            .into_iter()
+            .map(|token| {
+                let replaced = self
+                    .regex
+                    .try_replacen(&token, 0, NoExpand(&self.content))?;
+                Ok(replaced.into_owned())
+            })
+            .collect()
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Replace",
+            "pattern": { "Regex": self.regex.as_str() },
+            "content": self.content,
+        }))
+    }
+}
+
+/// Run a series of decoders in sequence.
+pub struct Sequence {
+    decoders: Vec<Box<dyn Decoder>>,
+}
+
+impl Sequence {
+    pub fn from_vec(decoders: Vec<Box<dyn Decoder>>) -> Self {
+        Sequence { decoders }
+    }
+}
+
+impl Decoder for Sequence {
+    fn decode_chain(&self, mut tokens: Vec<String>) -> Result<Vec<String>, DecoderError> {
+        for decoder in &self.decoders {
+            tokens = decoder.decode_chain(tokens)?;
+        }
+        Ok(tokens)
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        let decoders = self
+            .decoders
+            .iter()
+            .map(|decoder| decoder.to_json())
+            .collect::<Option<Vec<_>>>()?;
+        Some(json!({
+            "type": "Sequence",
+            "decoders": decoders,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+    use serde_json::json;
+
+    use super::{
+        ByteFallback, ByteLevel, Decoder, DecoderError, Fuse, Metaspace, Replace, Sequence, Strip,
+        WordPiece,
+    };
+
+    fn to_strings(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_decoders() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            decoder: &'a str,
+            tokens: &'a [&'a str],
+            expected: &'a [&'a str],
+        }
+
+        let cases = [
+            Case {
+                decoder: "byte_level",
+                tokens: &["Hello", "Ġworld", "Ċ"],
+                expected: &["Hello world\n"],
+            },
+            Case {
+                decoder: "metaspace",
+                tokens: &["▁Hello", "▁wor", "ld"],
+                expected: &["Hello", " wor", "ld"],
+            },
+            Case {
+                decoder: "wordpiece",
+                tokens: &["hello", "wor", "##ld", "!", "don", "'", "t"],
+                expected: &["hello", " wor", "ld", " !", " don", " '", " t"],
+            },
+            Case {
+                decoder: "byte_fallback",
+                tokens: &["a", "<0xC3>", "<0xA9>", "<0x0A>", "b", "<0x0>"],
+                expected: &["a", "é\n", "b", "<0x0>"],
+            },
+            Case {
+                decoder: "byte_fallback",
+                tokens: &["<0xFF>"],
+                expected: &["\u{FFFD}"],
+            },
+            Case {
+                decoder: "byte_fallback",
+                tokens: &["a", "<0xE2>", "<0x82>", "b"],
+                expected: &["a", "\u{FFFD}", "\u{FFFD}", "b"],
+            },
+            Case {
+                decoder: "fuse",
+                tokens: &["a", "b", "c"],
+                expected: &["abc"],
+            },
+            Case {
+                decoder: "strip",
+                tokens: &["  a ", " b", "c  "],
+                expected: &[" a", "b", "c "],
+            },
+            Case {
+                decoder: "replace",
+                tokens: &["▁a▁b", "c"],
+                expected: &[" a b", "c"],
+            },
+            Case {
+                decoder: "sequence",
+                tokens: &["▁a", "<0x21>", "▁b"],
+                expected: &["a! b"],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let decoder: Box<dyn Decoder> = match case.decoder {
+                "byte_level" => Box::new(ByteLevel::default()),
+                "metaspace" => Box::new(Metaspace::new('▁', true)),
+                "wordpiece" => Box::new(WordPiece::new("##".to_string(), false)),
+                "byte_fallback" => Box::new(ByteFallback::new()),
+                "fuse" => Box::new(Fuse::new()),
+                "strip" => Box::new(Strip::new(' ', 1, 1)),
+                "replace" => Box::new(Replace::new("▁", " ".to_string()).unwrap()),
+                "sequence" => Box::new(Sequence::from_vec(vec![
+                    Box::new(Replace::new("▁", " ".to_string()).unwrap()),
+                    Box::new(ByteFallback::new()),
+                    Box::new(Fuse::new()),
+                    Box::new(Strip::new(' ', 1, 0)),
+                ])),
+                _ => panic!("unknown decoder"),
+            };
+            let output = decoder.decode_chain(to_strings(case.tokens)).unwrap();
+            assert_eq!(output, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_wordpiece_cleanup() {
+        let decoder = WordPiece::default();
+        let output = decoder
+            .decode_chain(to_strings(&[
+                "i", "know", ",", "you", "##'", "re", "right", ".",
+            ]))
+            .unwrap()
+            .concat();
+        assert_eq!(output, "i know, you' re right.");
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        // "é" is encoded as "Ã©" in byte-level BPE vocabularies.
+        let result = ByteLevel::default().decode_chain(to_strings(&["Ã"]));
+        assert!(matches!(result, Err(DecoderError::InvalidUtf8)));
+        let result = ByteLevel::default().decode_chain(to_strings(&["Ã©"]));
+        assert_eq!(result.unwrap(), ["é"]);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let decoder = ByteLevel::new(false, true, false);
+        assert_eq!(
+            decoder.to_json().unwrap(),
+            json!({
+                "type": "ByteLevel",
+                "add_prefix_space": false,
+                "trim_offsets": true,
+                "use_regex": false,
+            })
+        );
+
+        let decoder = Metaspace::new('▁', false).with_split(false);
+        assert_eq!(
+            decoder.to_json().unwrap(),
+            json!({
+                "type": "Metaspace",
+                "replacement": "▁",
+                "prepend_scheme": "never",
+                "split": false,
+            })
+        );
+    }
+}
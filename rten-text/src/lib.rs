@@ -13,8 +13,9 @@
 //! [`tokenizers`](https://github.com/huggingface/tokenizers) crate. The main
 //! differences compared to that crate are:
 //!
-//! - rten-text focuses on inference only and does not support training
-//!   tokenizers.
+//! - rten-text focuses on inference. The [`trainers`] module supports
+//!   learning BPE and WordPiece vocabularies from a corpus, but offers fewer
+//!   training options than _tokenizers_.
 //! - rten-text is a pure Rust library with no dependencies written in C/C++.
 //!   This means it is easy to build for WebAssembly and other targets where
 //!   non-Rust dependencies may cause difficulties.
@@ -31,7 +32,10 @@
 //!
 //! The main entry point is the [`Tokenizer`] type. Use [`Tokenizer::from_file`]
 //! or [`Tokenizer::from_json`] to construct a tokenizer from a `tokenizer.json`
-//! file.
+//! file. Tokenizers can also be loaded from SentencePiece `.model` files using
+//! [`Tokenizer::from_sentencepiece_file`]. Tokenizers can be written back to
+//! the `tokenizer.json` format using [`Tokenizer::to_json`] or
+//! [`Tokenizer::save`].
 //!
 //! ## Encoding text
 //!
@@ -62,6 +66,12 @@
 //! # Ok::<_, Box<dyn std::error::Error>>(())
 //! ```
 //!
+//! ## Chat templates
+//!
+//! Instruction-tuned models expect conversations to be formatted using a
+//! model-specific template. The [`chat_template`] module can render the
+//! templates found in Hugging Face `tokenizer_config.json` files.
+//!
 //! ## More examples
 //!
 //! See the
@@ -69,10 +79,14 @@
 //! crate for various examples showing how to use this crate as part of an
 //! end-to-end pipeline.
 
+pub mod chat_template;
+pub mod decoders;
 pub mod models;
 pub mod normalizers;
+pub mod post_processors;
 pub mod pre_tokenizers;
 pub mod tokenizer;
+pub mod trainers;
 
 mod split;
 
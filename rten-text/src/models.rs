@@ -1,15 +1,18 @@
-//! Implementations of popular tokenization models including WordPiece and
-//! Byte Pair Encoding (BPE).
+//! Implementations of popular tokenization models including WordPiece,
+//! Byte Pair Encoding (BPE) and Unigram.
 
 use std::error::Error;
 use std::fmt;
 
 mod bpe;
+mod lru_cache;
+mod unigram;
 mod wordpiece;
 
 pub use bpe::{
     char_to_byte, merge_pairs_from_lines, Bpe, BpeError, BpeOptions, EncodedByteSlice, EncodedBytes,
 };
+pub use unigram::{Unigram, UnigramError, UnigramOptions};
 pub use wordpiece::{WordPiece, WordPieceOptions};
 
 use crate::tokenizer::TokenId;
@@ -64,7 +67,7 @@ impl Error for DecodeError {}
 ///
 /// Models are not generally used directly but instead via a wrapping
 /// [`Tokenizer`](crate::tokenizer::Tokenizer).
-pub trait Model {
+pub trait Model: Send + Sync {
     /// Look up the numeric ID for a token given its canonical string
     /// representation. This is used eg. for looking up the IDs of special
     /// tokens.
@@ -128,4 +131,36 @@ pub trait Model {
     /// Special tokens are decoded into their canonical string representations
     /// as returned by [`get_token_str`](Self::get_token_str).
     fn decode(&self, ids: &[TokenId]) -> Result<String, DecodeError>;
+
+    /// Decode a sequence of token IDs to bytes.
+    ///
+    /// Unlike [`decode`](Self::decode) this does not fail if the tokens do not
+    /// correspond to a complete UTF-8 sequence. The default implementation
+    /// returns the UTF-8 encoding of the output of `decode`.
+    fn decode_bytes(&self, ids: &[TokenId]) -> Result<Vec<u8>, DecodeError> {
+        self.decode(ids).map(|text| text.into_bytes())
+    }
+
+    /// Return the configuration and vocabulary of this model in the format
+    /// used by Hugging Face `tokenizer.json` files, or `None` if it cannot be
+    /// represented in that format.
+    ///
+    /// The default implementation returns `None`.
+    fn to_json(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Convert a vocabulary to a JSON object mapping token strings to IDs.
+///
+/// Entries are ordered by token ID, as in Hugging Face `tokenizer.json`
+/// files.
+fn vocab_to_json<'a>(vocab: impl Iterator<Item = (&'a str, TokenId)>) -> serde_json::Value {
+    let mut entries: Vec<_> = vocab.collect();
+    entries.sort_by_key(|&(token, id)| (id, token));
+    entries
+        .into_iter()
+        .map(|(token, id)| (token.to_string(), id.into()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
 }
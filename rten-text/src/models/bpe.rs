@@ -3,7 +3,10 @@ use std::error::Error;
 use std::fmt;
 use std::fmt::{Debug, Display};
 
-use super::{DecodeError, EncodeError, Model};
+use serde_json::json;
+
+use super::lru_cache::ShardedLruCache;
+use super::{vocab_to_json, DecodeError, EncodeError, Model};
 use crate::tokenizer::TokenId;
 
 /// Errors that can occur when building a [`Bpe`] tokenizer or encoding or
@@ -47,6 +50,13 @@ pub type EncodedByteSlice<'a> = &'a str;
 /// Like [`EncodedByteSlice`], but owned.
 pub type EncodedBytes = String;
 
+/// Default number of pieces whose tokens are cached by [`Bpe`].
+const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+/// Maximum length in bytes of pieces whose tokens are cached. Longer pieces
+/// are rare and so unlikely to benefit from caching.
+const MAX_CACHED_PIECE_LEN: usize = 256;
+
 /// Return true if `c` is considered a printable character.
 ///
 /// This matches the output of Python's `str.isprintable` for code points < 256,
@@ -316,6 +326,11 @@ pub struct Bpe {
     /// This was originally introduced for CLIP's tokenizer.
     /// See <https://github.com/openai/CLIP/blob/main/clip/simple_tokenizer.py>.
     end_of_word_suffix: Option<String>,
+
+    /// Cache of recently encoded pieces. Natural language text repeats the
+    /// same words often, so this avoids repeating the merge process for
+    /// most pieces.
+    cache: Option<ShardedLruCache<String, Vec<TokenId>>>,
 }
 
 impl Bpe {
@@ -359,9 +374,21 @@ impl Bpe {
             added_tokens,
             token_id_to_encoded_bytes,
             end_of_word_suffix,
+            cache: Some(ShardedLruCache::new(DEFAULT_CACHE_CAPACITY)),
         })
     }
 
+    /// Set the maximum number of pieces whose tokens are cached.
+    ///
+    /// Encoding results for recently seen pieces are cached to speed up
+    /// encoding of text where the same words occur many times. The default
+    /// capacity is 10,000 pieces. Setting the capacity to zero disables the
+    /// cache.
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache = (capacity > 0).then(|| ShardedLruCache::new(capacity));
+        self
+    }
+
     /// Decode a token ID to a byte sequence. Be aware that the returned bytes
     /// may end in the middle of a UTF-8 character.
     fn get_token_bytes(&self, id: TokenId) -> Option<Vec<u8>> {
@@ -429,6 +456,23 @@ impl Bpe {
             tokens
         }
     }
+
+    /// Encode a piece that occurs at the end of a word, using the cache if
+    /// enabled.
+    fn encode_piece_cached(&self, piece: &str) -> Vec<TokenId> {
+        let cache = match &self.cache {
+            Some(cache) if piece.len() <= MAX_CACHED_PIECE_LEN => cache,
+            _ => return self.encode_piece(piece, true /* end_of_word */),
+        };
+
+        if let Some(tokens) = cache.get(piece) {
+            return tokens;
+        }
+
+        let tokens = self.encode_piece(piece, true /* end_of_word */);
+        cache.insert(piece.to_string(), tokens.clone());
+        tokens
+    }
 }
 
 impl Model for Bpe {
@@ -499,13 +543,18 @@ impl Model for Bpe {
         if piece.is_empty() {
             return Ok(());
         }
-        for token in self.encode_piece(piece, true /* end_of_word */) {
+        for token in self.encode_piece_cached(piece) {
             on_token(0, token)
         }
         Ok(())
     }
 
     fn decode(&self, ids: &[TokenId]) -> Result<String, DecodeError> {
+        let bytes = self.decode_bytes(ids)?;
+        String::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    fn decode_bytes(&self, ids: &[TokenId]) -> Result<Vec<u8>, DecodeError> {
         let char_to_byte = char_to_byte();
 
         let mut bytes = Vec::new();
@@ -529,7 +578,68 @@ impl Model for Bpe {
                 bytes.extend(token_bytes);
             }
         }
-        String::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8)
+        Ok(bytes)
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        let byte_to_char: HashMap<u8, char> = char_to_byte()
+            .into_iter()
+            .map(|(ch, byte)| (byte, ch))
+            .collect();
+
+        // Reconstruct the encoded string for each rank. Single bytes come
+        // first, followed by merges in rank order, whose parts always have a
+        // lower rank.
+        let mut rank_strs: HashMap<Rank, EncodedBytes> = HashMap::new();
+        for (byte, &rank) in self.byte_to_rank.iter().enumerate() {
+            let ch = byte_to_char[&(byte as u8)];
+            rank_strs.insert(rank, ch.to_string());
+            if let Some(suffix) = self.end_of_word_suffix.as_deref() {
+                rank_strs.insert(rank + 256, format!("{}{}", ch, suffix));
+            }
+        }
+
+        let mut merges: Vec<_> = self
+            .merges
+            .iter()
+            .map(|(&pair, &rank)| (rank, pair))
+            .collect();
+        merges.sort();
+
+        let mut merge_pairs = Vec::with_capacity(merges.len());
+        for (rank, (first, second)) in merges {
+            let first = rank_strs.get(&first)?.clone();
+            let second = rank_strs.get(&second)?.clone();
+            rank_strs.insert(rank, [first.as_str(), second.as_str()].concat());
+            merge_pairs.push((first, second));
+        }
+
+        let mut vocab: HashMap<&str, TokenId> = match &self.token_id_to_encoded_bytes {
+            Some(id_to_str) => id_to_str
+                .iter()
+                .map(|(&id, token)| (token.as_str(), id))
+                .collect(),
+            None => rank_strs
+                .iter()
+                .map(|(&rank, token)| (token.as_str(), rank))
+                .collect(),
+        };
+        for (&id, token) in &self.added_tokens {
+            vocab.entry(token.as_str()).or_insert(id);
+        }
+
+        Some(json!({
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": self.end_of_word_suffix,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "ignore_merges": false,
+            "vocab": vocab_to_json(vocab.into_iter()),
+            "merges": merge_pairs,
+        }))
     }
 }
 
@@ -540,6 +650,7 @@ mod tests {
     use rten_testing::TestCases;
 
     use super::{merge_pairs_from_lines, Bpe, BpeOptions, EncodedBytes};
+    use crate::models::{DecodeError, Model};
     use crate::pre_tokenizers::Split;
     use crate::tokenizer::{TokenId, Tokenizer};
 
@@ -692,6 +803,39 @@ ba r",
         })
     }
 
+    #[test]
+    fn test_encode_cache() {
+        let merges: Vec<&str> = MINI_GPT2.lines().collect();
+        let merge_pairs = merge_pairs_from_lines(&merges);
+        let text = "the cat is in the bed, the cat is in the bed";
+
+        let encode = |cache_capacity: Option<usize>| {
+            let bpe_opts = BpeOptions {
+                merges: &merge_pairs,
+                ..Default::default()
+            };
+            let mut model = Bpe::new(bpe_opts).unwrap();
+            if let Some(capacity) = cache_capacity {
+                model = model.with_cache_capacity(capacity);
+            }
+            let tokenizer = Tokenizer::new(model, Default::default())
+                .with_pre_tokenizer(Box::new(Split::gpt2()));
+
+            // Encode twice so that the second pass uses cached tokens.
+            let first = tokenizer.encode(text, None).unwrap();
+            let second = tokenizer.encode(text, None).unwrap();
+            assert_eq!(first.token_ids(), second.token_ids());
+            first.into_token_ids()
+        };
+
+        let expected = encode(Some(0));
+        // Default capacity.
+        assert_eq!(encode(None), expected);
+        // Capacity smaller than the number of distinct pieces, so that
+        // entries are evicted.
+        assert_eq!(encode(Some(2)), expected);
+    }
+
     #[test]
     fn test_get_token_str() {
         #[derive(Debug)]
@@ -807,4 +951,28 @@ ba r",
             assert_eq!(decoded, *expected);
         })
     }
+
+    #[test]
+    fn test_decode_bytes() {
+        let merges: Vec<&str> = MINI_GPT2.lines().collect();
+        let merge_pairs = merge_pairs_from_lines(&merges);
+        let bpe_opts = BpeOptions {
+            merges: &merge_pairs,
+            ..Default::default()
+        };
+        let model = Bpe::new(bpe_opts).unwrap();
+
+        // Encode a multi-byte character, which produces one token per byte.
+        let token_ids = model.encode("é").unwrap();
+        assert_eq!(token_ids.len(), 2);
+
+        // Decoding part of the character as text fails, but decoding as
+        // bytes succeeds.
+        assert_eq!(model.decode(&token_ids[..1]), Err(DecodeError::InvalidUtf8));
+        assert_eq!(
+            model.decode_bytes(&token_ids[..1]).unwrap(),
+            "é".as_bytes()[..1]
+        );
+        assert_eq!(model.decode_bytes(&token_ids).unwrap(), "é".as_bytes());
+    }
 }
@@ -0,0 +1,252 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::sync::Mutex;
+
+/// Index of an entry in [`LruCache::entries`].
+type EntryIndex = usize;
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+
+    /// Next most recently used entry.
+    prev: Option<EntryIndex>,
+
+    /// Next least recently used entry.
+    next: Option<EntryIndex>,
+}
+
+/// Fixed-capacity map which evicts the least recently used entry when full.
+///
+/// Entries are stored in a vector and linked together in order of use, so
+/// that lookups, insertions and evictions are all O(1).
+pub struct LruCache<K, V> {
+    capacity: usize,
+    index: HashMap<K, EntryIndex>,
+    entries: Vec<Entry<K, V>>,
+
+    /// Most recently used entry.
+    head: Option<EntryIndex>,
+
+    /// Least recently used entry.
+    tail: Option<EntryIndex>,
+}
+
+impl<K: Clone + Eq + Hash, V> LruCache<K, V> {
+    /// Create a cache which holds up to `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            index: HashMap::new(),
+            entries: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Look up an entry and mark it as the most recently used.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let idx = *self.index.get(key)?;
+        self.move_to_front(idx);
+        Some(&self.entries[idx].value)
+    }
+
+    /// Insert or replace an entry, evicting the least recently used entry if
+    /// the cache is full.
+    pub fn insert(&mut self, key: K, value: V) {
+        if let Some(&idx) = self.index.get(&key) {
+            self.entries[idx].value = value;
+            self.move_to_front(idx);
+            return;
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        let idx = if self.entries.len() < self.capacity {
+            self.entries.push(Entry {
+                key: key.clone(),
+                value,
+                prev: None,
+                next: None,
+            });
+            self.entries.len() - 1
+        } else {
+            // Re-use the slot of the least recently used entry.
+            let idx = self.tail.expect("full cache should have a tail");
+            self.unlink(idx);
+            let entry = &mut self.entries[idx];
+            self.index.remove(&entry.key);
+            entry.key = key.clone();
+            entry.value = value;
+            idx
+        };
+
+        self.index.insert(key, idx);
+        self.push_front(idx);
+    }
+
+    fn move_to_front(&mut self, idx: EntryIndex) {
+        if self.head != Some(idx) {
+            self.unlink(idx);
+            self.push_front(idx);
+        }
+    }
+
+    /// Remove an entry from the usage list.
+    fn unlink(&mut self, idx: EntryIndex) {
+        let Entry { prev, next, .. } = self.entries[idx];
+        match prev {
+            Some(prev) => self.entries[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.entries[next].prev = prev,
+            None => self.tail = prev,
+        }
+        self.entries[idx].prev = None;
+        self.entries[idx].next = None;
+    }
+
+    /// Add an unlinked entry to the front of the usage list.
+    fn push_front(&mut self, idx: EntryIndex) {
+        self.entries[idx].next = self.head;
+        if let Some(head) = self.head {
+            self.entries[head].prev = Some(idx);
+        }
+        self.head = Some(idx);
+        if self.tail.is_none() {
+            self.tail = Some(idx);
+        }
+    }
+}
+
+/// Maximum number of shards used by [`ShardedLruCache`].
+const MAX_SHARDS: usize = 16;
+
+/// Thread-safe LRU cache which is split into independently locked shards.
+///
+/// Each key is assigned to a shard based on its hash, so threads which
+/// access different keys rarely wait for each other. Entries are evicted
+/// from each shard separately, so eviction order is only approximately
+/// least recently used across the whole cache.
+pub struct ShardedLruCache<K, V> {
+    shards: Vec<Mutex<LruCache<K, V>>>,
+    hasher: RandomState,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> ShardedLruCache<K, V> {
+    /// Create a cache which holds up to about `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        let n_shards = capacity.clamp(1, MAX_SHARDS);
+        let shard_capacity = capacity.div_ceil(n_shards);
+        ShardedLruCache {
+            shards: (0..n_shards)
+                .map(|_| Mutex::new(LruCache::new(shard_capacity)))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &Mutex<LruCache<K, V>>
+    where
+        Q: Hash + ?Sized,
+    {
+        let idx = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[idx]
+    }
+
+    /// Look up an entry and mark it as the most recently used in its shard.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.shard(key).lock().unwrap().get(key).cloned()
+    }
+
+    /// Insert or replace an entry, evicting the least recently used entry in
+    /// its shard if the shard is full.
+    pub fn insert(&self, key: K, value: V) {
+        self.shard(&key).lock().unwrap().insert(key, value);
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LruCache, ShardedLruCache};
+
+    #[test]
+    fn test_lru_cache() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a".to_string(), 1);
+        cache.insert("b".to_string(), 2);
+        assert_eq!(cache.get("a"), Some(&1));
+
+        // "b" is the least recently used entry, so it is evicted.
+        cache.insert("c".to_string(), 3);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.get("c"), Some(&3));
+
+        // Replacing an entry doesn't evict anything.
+        cache.insert("a".to_string(), 4);
+        assert_eq!(cache.get("a"), Some(&4));
+        assert_eq!(cache.get("c"), Some(&3));
+
+        cache.insert("d".to_string(), 5);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("c"), Some(&3));
+        assert_eq!(cache.get("d"), Some(&5));
+    }
+
+    #[test]
+    fn test_lru_cache_zero_capacity() {
+        let mut cache = LruCache::new(0);
+        cache.insert("a".to_string(), 1);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_sharded_lru_cache() {
+        let cache = ShardedLruCache::new(100);
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let cache = &cache;
+                s.spawn(move || {
+                    for i in 0..50 {
+                        let key = format!("{}-{}", t, i);
+                        cache.insert(key.clone(), i);
+                        assert_eq!(cache.get(key.as_str()), Some(i));
+                    }
+                });
+            }
+        });
+
+        // Each shard holds its share of the total capacity.
+        assert!(cache.len() <= 112);
+        assert!(cache.len() >= 50);
+
+        let cache = ShardedLruCache::new(0);
+        cache.insert("a".to_string(), 1);
+        assert_eq!(cache.get("a"), None);
+    }
+}
@@ -0,0 +1,634 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+
+use serde_json::json;
+
+use super::{DecodeError, EncodeError, Model};
+use crate::tokenizer::TokenId;
+
+/// Errors that can occur when building a [`Unigram`] tokenizer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnigramError {
+    /// The vocabulary is empty.
+    EmptyVocab,
+
+    /// The ID of the unknown token is not in the vocabulary.
+    InvalidUnkId(TokenId),
+}
+
+impl fmt::Display for UnigramError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnigramError::EmptyVocab => write!(fmt, "vocabulary is empty"),
+            UnigramError::InvalidUnkId(id) => {
+                write!(fmt, "unknown token ID {} is not in the vocabulary", id)
+            }
+        }
+    }
+}
+
+impl Error for UnigramError {}
+
+/// Penalty subtracted from the lowest token score to get the score of the
+/// unknown token. This matches SentencePiece and Hugging Face Tokenizers.
+const UNK_PENALTY: f32 = 10.0;
+
+/// Configuration for a [`Unigram`] tokenizer.
+#[derive(Clone, Debug, Default)]
+pub struct UnigramOptions {
+    /// ID of the token used for characters which are not covered by the
+    /// vocabulary. If not set, encoding such characters fails.
+    pub unk_id: Option<TokenId>,
+
+    /// Encode characters which are not covered by the vocabulary as a
+    /// sequence of UTF-8 byte tokens (`<0x00>` to `<0xFF>`) instead of the
+    /// unknown token.
+    pub byte_fallback: bool,
+
+    /// Enable subword regularization [^1] by sampling segmentations instead
+    /// of always using the most likely one.
+    ///
+    /// The value is the smoothing parameter for the sampling distribution.
+    /// Smaller values produce more diverse segmentations. Values close to 1
+    /// sample segmentations according to their likelihood under the model.
+    ///
+    /// [^1]: Kudo, Taku. "Subword regularization: Improving neural network
+    ///       translation models with multiple subword candidates." arXiv
+    ///       preprint arXiv:1804.10959 (2018). <https://arxiv.org/abs/1804.10959>
+    pub alpha: Option<f32>,
+
+    /// Seed for the random number generator used when `alpha` is set.
+    pub seed: Option<u64>,
+
+    /// IDs of tokens which are never produced when segmenting text, such as
+    /// control tokens (eg. `</s>`) and byte fallback tokens. These tokens can
+    /// still be decoded and looked up by string.
+    pub excluded_ids: Vec<TokenId>,
+}
+
+/// Unigram language model tokenizer [^1] used by SentencePiece and models
+/// such as T5, XLM-RoBERTa, ALBERT and mBART.
+///
+/// Each token in the vocabulary has a score, which is its log probability
+/// under a unigram language model. Text is split into the sequence of tokens
+/// with the highest total score, using the Viterbi algorithm.
+///
+/// Unlike [`WordPiece`](super::WordPiece) and [`Bpe`](super::Bpe), this model
+/// does not rely on pre-tokenization to split text into words. SentencePiece
+/// tokenizers instead replace spaces with `▁` (U+2581) during normalization
+/// and include it in tokens. When decoding, `▁` is converted back to a space.
+///
+/// [^1]: Kudo, Taku. "Subword regularization: Improving neural network
+///       translation models with multiple subword candidates." arXiv preprint
+///       arXiv:1804.10959 (2018). <https://arxiv.org/abs/1804.10959>
+pub struct Unigram {
+    /// Token strings and scores, indexed by token ID.
+    vocab: Vec<(String, f32)>,
+
+    token_to_id: HashMap<String, TokenId>,
+
+    /// Map from token string to ID for tokens which can be produced when
+    /// segmenting text.
+    pieces: HashMap<String, TokenId>,
+
+    /// Length of the longest token in `pieces` in bytes.
+    max_token_len: usize,
+
+    unk_id: Option<TokenId>,
+
+    /// Score used for unknown characters.
+    unk_score: f32,
+
+    /// Map from byte value to the ID of the corresponding `<0xXX>` token, if
+    /// byte fallback is enabled.
+    byte_to_token: Option<Box<[Option<TokenId>; 256]>>,
+
+    /// Map from ID of `<0xXX>` tokens to the byte value they represent.
+    token_to_byte: HashMap<TokenId, u8>,
+
+    /// Smoothing parameter for sampling, if enabled.
+    alpha: Option<f32>,
+
+    rng: Mutex<fastrand::Rng>,
+}
+
+/// Parse the string representation of a byte fallback token (eg. `<0x0A>`).
+fn parse_byte_token(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+/// Edge in the lattice of possible segmentations of a string.
+#[derive(Clone, Copy)]
+struct Edge {
+    /// Byte offset of the start of the token.
+    start: usize,
+
+    /// Token ID, or `None` for an unknown character.
+    token: Option<TokenId>,
+
+    score: f32,
+}
+
+impl Unigram {
+    /// Construct a Unigram tokenizer from a vocabulary.
+    ///
+    /// `vocab` is a list of `(token, score)` pairs, where the token ID is the
+    /// index in the list and the score is the token's log probability.
+    pub fn from_vocab(
+        vocab: Vec<(String, f32)>,
+        options: UnigramOptions,
+    ) -> Result<Unigram, UnigramError> {
+        if vocab.is_empty() {
+            return Err(UnigramError::EmptyVocab);
+        }
+        if let Some(unk_id) = options.unk_id {
+            if unk_id as usize >= vocab.len() {
+                return Err(UnigramError::InvalidUnkId(unk_id));
+            }
+        }
+
+        let mut token_to_id = HashMap::with_capacity(vocab.len());
+        let mut token_to_byte = HashMap::new();
+        for (id, (token, _score)) in vocab.iter().enumerate() {
+            // If a token appears more than once, the first occurrence wins.
+            token_to_id.entry(token.clone()).or_insert(id as TokenId);
+            if let Some(byte) = parse_byte_token(token) {
+                token_to_byte.insert(id as TokenId, byte);
+            }
+        }
+
+        let byte_to_token = options.byte_fallback.then(|| {
+            let mut byte_to_token = Box::new([None; 256]);
+            for (&id, &byte) in &token_to_byte {
+                byte_to_token[byte as usize] = Some(id);
+            }
+            byte_to_token
+        });
+
+        let mut pieces = token_to_id.clone();
+        for id in &options.excluded_ids {
+            if let Some((token, _)) = vocab.get(*id as usize) {
+                pieces.remove(token);
+            }
+        }
+
+        let min_score = pieces
+            .values()
+            .map(|&id| vocab[id as usize].1)
+            .fold(f32::INFINITY, f32::min);
+        let max_token_len = pieces.keys().map(|token| token.len()).max().unwrap_or(0);
+
+        let rng = match options.seed {
+            Some(seed) => fastrand::Rng::with_seed(seed),
+            None => fastrand::Rng::new(),
+        };
+
+        Ok(Unigram {
+            vocab,
+            token_to_id,
+            pieces,
+            max_token_len,
+            unk_id: options.unk_id,
+            unk_score: min_score - UNK_PENALTY,
+            byte_to_token,
+            token_to_byte,
+            alpha: options.alpha,
+            rng: Mutex::new(rng),
+        })
+    }
+
+    /// Build the lattice of possible segmentations of `text`.
+    ///
+    /// Returns a list of edges that end at each byte offset in `text`.
+    fn lattice(&self, text: &str) -> Vec<Vec<Edge>> {
+        let mut ends: Vec<Vec<Edge>> = vec![Vec::new(); text.len() + 1];
+
+        for (start, ch) in text.char_indices() {
+            let char_end = start + ch.len_utf8();
+            let mut has_single_char = false;
+
+            let max_end = text.len().min(start + self.max_token_len);
+            for end in (char_end..=max_end).filter(|end| text.is_char_boundary(*end)) {
+                if let Some(&id) = self.pieces.get(&text[start..end]) {
+                    ends[end].push(Edge {
+                        start,
+                        token: Some(id),
+                        score: self.vocab[id as usize].1,
+                    });
+                    has_single_char |= end == char_end;
+                }
+            }
+
+            // Ensure there is always a path through the lattice.
+            if !has_single_char {
+                ends[char_end].push(Edge {
+                    start,
+                    token: None,
+                    score: self.unk_score,
+                });
+            }
+        }
+
+        ends
+    }
+
+    /// Find the segmentation of `text` with the highest score.
+    ///
+    /// Returns the edges of the best path in reverse order.
+    fn best_path(&self, text: &str, lattice: &[Vec<Edge>]) -> Vec<Edge> {
+        let mut best: Vec<Option<(f32, Edge)>> = vec![None; text.len() + 1];
+        let path_score = |best: &[Option<(f32, Edge)>], pos: usize| {
+            if pos == 0 {
+                Some(0.)
+            } else {
+                best[pos].map(|(score, _)| score)
+            }
+        };
+
+        for end in 1..=text.len() {
+            for edge in &lattice[end] {
+                let Some(start_score) = path_score(&best, edge.start) else {
+                    continue;
+                };
+                let score = start_score + edge.score;
+                if best[end].is_none_or(|(best_score, _)| score > best_score) {
+                    best[end] = Some((score, *edge));
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut pos = text.len();
+        while pos > 0 {
+            let (_, edge) = best[pos].expect("lattice should be connected");
+            path.push(edge);
+            pos = edge.start;
+        }
+        path
+    }
+
+    /// Sample a segmentation of `text`, with probability proportional to
+    /// its likelihood raised to the power of `alpha`.
+    ///
+    /// Returns the edges of the path in reverse order.
+    fn sample_path(&self, text: &str, lattice: &[Vec<Edge>], alpha: f32) -> Vec<Edge> {
+        // Forward pass: compute the log of the total (smoothed) probability of
+        // all paths ending at each position.
+        let mut forward = vec![f32::NEG_INFINITY; text.len() + 1];
+        forward[0] = 0.;
+        for end in 1..=text.len() {
+            forward[end] = log_sum_exp(
+                lattice[end]
+                    .iter()
+                    .map(|edge| forward[edge.start] + alpha * edge.score),
+            );
+        }
+
+        // Backward pass: sample edges from the end of the text.
+        let mut rng = self.rng.lock().unwrap();
+        let mut path = Vec::new();
+        let mut pos = text.len();
+        while pos > 0 {
+            let edges = &lattice[pos];
+            let mut target = rng.f32();
+            let mut chosen = *edges.last().expect("lattice should be connected");
+            for edge in edges {
+                let prob = (forward[edge.start] + alpha * edge.score - forward[pos]).exp();
+                if target < prob {
+                    chosen = *edge;
+                    break;
+                }
+                target -= prob;
+            }
+            path.push(chosen);
+            pos = chosen.start;
+        }
+        path
+    }
+}
+
+/// Compute `log(sum(exp(x)))` for a sequence of log values.
+fn log_sum_exp<I: Iterator<Item = f32> + Clone>(values: I) -> f32 {
+    let max = values.clone().fold(f32::NEG_INFINITY, f32::max);
+    if max == f32::NEG_INFINITY {
+        return max;
+    }
+    max + values.map(|x| (x - max).exp()).sum::<f32>().ln()
+}
+
+impl Model for Unigram {
+    fn get_token_str(&self, id: TokenId) -> Option<String> {
+        self.vocab.get(id as usize).map(|(token, _)| token.clone())
+    }
+
+    fn get_token_id(&self, tok: &str) -> Option<TokenId> {
+        self.token_to_id.get(tok).copied()
+    }
+
+    fn encode_with_offsets(
+        &self,
+        piece: &str,
+        on_token: &mut dyn FnMut(usize, TokenId),
+    ) -> Result<(), EncodeError> {
+        if piece.is_empty() {
+            return Ok(());
+        }
+
+        let lattice = self.lattice(piece);
+        let mut path = match self.alpha {
+            Some(alpha) => self.sample_path(piece, &lattice, alpha),
+            None => self.best_path(piece, &lattice),
+        };
+        path.reverse();
+
+        let mut i = 0;
+        while i < path.len() {
+            let edge = path[i];
+            i += 1;
+
+            if let Some(id) = edge.token {
+                on_token(edge.start, id);
+                continue;
+            }
+
+            // Merge consecutive unknown characters.
+            let mut end = piece.len();
+            while let Some(next) = path.get(i) {
+                if next.token.is_some() {
+                    end = next.start;
+                    break;
+                }
+                i += 1;
+            }
+            let unknown = &piece[edge.start..end];
+
+            let byte_tokens: Option<Vec<TokenId>> =
+                self.byte_to_token.as_ref().and_then(|byte_to_token| {
+                    unknown
+                        .bytes()
+                        .map(|byte| byte_to_token[byte as usize])
+                        .collect()
+                });
+            if let Some(byte_tokens) = byte_tokens {
+                for id in byte_tokens {
+                    on_token(edge.start, id);
+                }
+            } else {
+                let unk_id = self
+                    .unk_id
+                    .ok_or_else(|| EncodeError::TokenIdNotFound(unknown.to_string()))?;
+                on_token(edge.start, unk_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decode(&self, ids: &[TokenId]) -> Result<String, DecodeError> {
+        let bytes = self.decode_bytes(ids)?;
+        String::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    fn decode_bytes(&self, ids: &[TokenId]) -> Result<Vec<u8>, DecodeError> {
+        let mut bytes = Vec::new();
+        for &id in ids {
+            if let Some(&byte) = self.token_to_byte.get(&id) {
+                bytes.push(byte);
+                continue;
+            }
+            let (token, _) = self
+                .vocab
+                .get(id as usize)
+                .ok_or(DecodeError::InvalidTokenId(id))?;
+            bytes.extend(token.replace('▁', " ").into_bytes());
+        }
+        Ok(bytes)
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Unigram",
+            "unk_id": self.unk_id,
+            "vocab": self.vocab,
+            "byte_fallback": self.byte_to_token.is_some(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rten_testing::TestCases;
+
+    use super::{Unigram, UnigramError, UnigramOptions};
+    use crate::models::{DecodeError, EncodeError, Model};
+
+    fn create_vocab(tokens: &[(&str, f32)]) -> Vec<(String, f32)> {
+        tokens
+            .iter()
+            .map(|(token, score)| (token.to_string(), *score))
+            .collect()
+    }
+
+    fn test_vocab() -> Vec<(String, f32)> {
+        let mut vocab = create_vocab(&[
+            ("<unk>", 0.),
+            ("▁", -2.),
+            ("▁h", -3.),
+            ("▁hell", -4.),
+            ("▁hello", -6.5),
+            ("h", -3.),
+            ("e", -3.),
+            ("l", -3.),
+            ("o", -3.),
+            ("ll", -3.),
+            ("llo", -3.5),
+            ("o▁", -4.),
+            ("w", -3.),
+            ("r", -3.),
+            ("d", -3.),
+            ("▁wor", -4.),
+            ("ld", -3.),
+        ]);
+        vocab.extend((0..=255u8).map(|b| (format!("<0x{:02X}>", b), 0.)));
+        vocab
+    }
+
+    fn token_strs(model: &Unigram, ids: &[u32]) -> Vec<String> {
+        ids.iter()
+            .map(|id| model.get_token_str(*id).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_encode() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            text: &'a str,
+            byte_fallback: bool,
+            expected: &'a [&'a str],
+        }
+
+        let cases = [
+            Case {
+                text: "",
+                byte_fallback: false,
+                expected: &[],
+            },
+            // Single token with a higher score than "▁hell" + "o".
+            Case {
+                text: "▁hello",
+                byte_fallback: false,
+                expected: &["▁hello"],
+            },
+            Case {
+                text: "▁hellod",
+                byte_fallback: false,
+                expected: &["▁hello", "d"],
+            },
+            Case {
+                text: "▁hello▁world",
+                byte_fallback: false,
+                expected: &["▁hello", "▁wor", "ld"],
+            },
+            // Consecutive unknown characters are merged.
+            Case {
+                text: "▁hexyzo",
+                byte_fallback: false,
+                expected: &["▁h", "e", "<unk>", "o"],
+            },
+            // Unknown characters are encoded as bytes with byte fallback.
+            Case {
+                text: "▁hé",
+                byte_fallback: true,
+                expected: &["▁h", "<0xC3>", "<0xA9>"],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let model = Unigram::from_vocab(
+                test_vocab(),
+                UnigramOptions {
+                    unk_id: Some(0),
+                    byte_fallback: case.byte_fallback,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let ids = model.encode(case.text).unwrap();
+            assert_eq!(token_strs(&model, &ids), case.expected);
+        })
+    }
+
+    #[test]
+    fn test_encode_offsets() {
+        let model = Unigram::from_vocab(test_vocab(), Default::default()).unwrap();
+        let mut offsets = Vec::new();
+        model
+            .encode_with_offsets("▁hello▁world", &mut |offset, _id| offsets.push(offset))
+            .unwrap();
+        assert_eq!(offsets, [0, 8, 14]);
+    }
+
+    #[test]
+    fn test_encode_without_unk() {
+        let model = Unigram::from_vocab(test_vocab(), Default::default()).unwrap();
+        assert_eq!(
+            model.encode("▁hxy"),
+            Err(EncodeError::TokenIdNotFound("xy".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_excluded_ids() {
+        let vocab = create_vocab(&[("<unk>", 0.), ("</s>", 0.), ("▁a", -1.)]);
+        let model = Unigram::from_vocab(
+            vocab,
+            UnigramOptions {
+                unk_id: Some(0),
+                excluded_ids: vec![0, 1],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Excluded tokens are not matched in the text, but can still be
+        // looked up and decoded.
+        let ids = model.encode("▁a</s>").unwrap();
+        assert_eq!(token_strs(&model, &ids), ["▁a", "<unk>"]);
+        assert_eq!(model.get_token_id("</s>"), Some(1));
+        assert_eq!(model.decode(&[2, 1]).as_deref(), Ok(" a</s>"));
+    }
+
+    #[test]
+    fn test_invalid_vocab() {
+        assert_eq!(
+            Unigram::from_vocab(Vec::new(), Default::default()).err(),
+            Some(UnigramError::EmptyVocab)
+        );
+        assert_eq!(
+            Unigram::from_vocab(
+                create_vocab(&[("a", 0.)]),
+                UnigramOptions {
+                    unk_id: Some(1),
+                    ..Default::default()
+                }
+            )
+            .err(),
+            Some(UnigramError::InvalidUnkId(1))
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        let model = Unigram::from_vocab(test_vocab(), Default::default()).unwrap();
+        let id = |tok: &str| model.get_token_id(tok).unwrap();
+
+        let ids = [
+            id("▁hello"),
+            id("▁wor"),
+            id("ld"),
+            id("<0xC3>"),
+            id("<0xA9>"),
+        ];
+        assert_eq!(model.decode(&ids).as_deref(), Ok(" hello worldé"));
+        assert_eq!(
+            model.decode(&ids[..ids.len() - 1]),
+            Err(DecodeError::InvalidUtf8)
+        );
+        assert_eq!(
+            model.decode(&[1000]),
+            Err(DecodeError::InvalidTokenId(1000))
+        );
+    }
+
+    #[test]
+    fn test_sampling() {
+        let model = Unigram::from_vocab(
+            test_vocab(),
+            UnigramOptions {
+                alpha: Some(0.1),
+                seed: Some(1234),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let text = "▁hello▁world";
+        let mut segmentations = HashSet::new();
+        for _ in 0..50 {
+            let ids = model.encode(text).unwrap();
+            let tokens = token_strs(&model, &ids);
+            assert_eq!(tokens.concat(), text);
+            segmentations.insert(tokens);
+        }
+        assert!(segmentations.len() > 1);
+    }
+}
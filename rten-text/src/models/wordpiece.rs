@@ -1,6 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
 
-use super::{DecodeError, EncodeError, Model};
+use serde_json::json;
+
+use super::{vocab_to_json, DecodeError, EncodeError, Model};
 use crate::tokenizer::TokenId;
 
 /// WordPiece tokenizer [^1] used by BERT [^2] models.
@@ -19,6 +22,7 @@ pub struct WordPiece {
     id_to_token: HashMap<TokenId, String>,
     subword_prefix: String,
     max_word_len: usize,
+    trie: Trie,
 }
 
 /// Configuration for a [`WordPiece`] tokenizer.
@@ -40,13 +44,199 @@ impl WordPiece {
             vocab.iter().map(|(k, v)| (*v, k.to_string())).collect();
 
         let subword_prefix = "##".to_string();
+        let trie = Trie::new(&vocab, &subword_prefix);
 
         WordPiece {
             token_to_id: vocab,
             subword_prefix,
             max_word_len: options.max_word_len.unwrap_or(100),
             id_to_token,
+            trie,
+        }
+    }
+
+    fn unknown_token_id(&self) -> Result<TokenId, EncodeError> {
+        let unknown_token = "[UNK]";
+        self.get_token_id(unknown_token)
+            .ok_or_else(|| EncodeError::TokenIdNotFound(unknown_token.to_string()))
+    }
+}
+
+/// Index of a node in [`Trie::nodes`].
+type NodeId = u32;
+
+const ROOT: NodeId = 0;
+
+/// A token matched by a trie node.
+#[derive(Clone, Copy)]
+struct MatchedToken {
+    id: TokenId,
+
+    /// Length of the token string in bytes, including any subword prefix.
+    len: usize,
+}
+
+#[derive(Clone, Default)]
+struct TrieNode {
+    /// Token whose string is the path from the root to this node.
+    token: Option<MatchedToken>,
+
+    /// Node to continue matching from if the next character has no edge
+    /// from this node. If `None`, the word cannot be tokenized.
+    fail: Option<NodeId>,
+
+    /// Range in [`Trie::fail_pops`] of the tokens which are produced when
+    /// following `fail`.
+    fail_pops: Range<usize>,
+}
+
+/// Trie of vocabulary entries with failure links, used to tokenize words in
+/// time linear in the word length.
+///
+/// This implements the LinMaxMatch algorithm from [^1], which produces the
+/// same output as the greedy longest-match-first algorithm used by BERT's
+/// tokenizer, without backtracking. The trie is similar to an Aho-Corasick
+/// automaton. When the next character of a word has no edge from the current
+/// node, the tokens that longest-match-first would have produced are emitted
+/// and matching continues from a node below the subword prefix.
+///
+/// [^1]: Song, Xinying, et al. "Fast WordPiece Tokenization." arXiv preprint
+///       arXiv:2012.15524 (2020). <https://arxiv.org/abs/2012.15524>
+#[derive(Clone)]
+struct Trie {
+    nodes: Vec<TrieNode>,
+    edges: HashMap<(NodeId, char), NodeId>,
+    fail_pops: Vec<MatchedToken>,
+
+    /// Root node for matching the rest of a word after its first token. The
+    /// paths below it are the tokens which start with the subword prefix,
+    /// with the prefix removed.
+    suffix_root: NodeId,
+
+    /// Length of the subword prefix in bytes.
+    prefix_len: usize,
+}
+
+impl Trie {
+    fn new(vocab: &HashMap<String, TokenId>, subword_prefix: &str) -> Trie {
+        // The root for matching continuations of a word is a separate node,
+        // rather than the node reached from `ROOT` by the subword prefix.
+        // This is because a word which starts with the subword prefix is
+        // matched against whole tokens, the same as any other word.
+        let mut trie = Trie {
+            nodes: vec![TrieNode::default(); 2],
+            edges: HashMap::new(),
+            fail_pops: Vec::new(),
+            suffix_root: ROOT + 1,
+            prefix_len: subword_prefix.len(),
+        };
+        let mut children: Vec<Vec<(char, NodeId)>> = vec![Vec::new(); 2];
+
+        let mut insert = |trie: &mut Trie, root: NodeId, text: &str, token: MatchedToken| {
+            let mut node = root;
+            for ch in text.chars() {
+                node = *trie.edges.entry((node, ch)).or_insert_with(|| {
+                    let child = trie.nodes.len() as NodeId;
+                    trie.nodes.push(TrieNode::default());
+                    children.push(Vec::new());
+                    children[node as usize].push((ch, child));
+                    child
+                });
+            }
+            if node != root {
+                trie.nodes[node as usize].token = Some(token);
+            }
+        };
+
+        let suffix_root = trie.suffix_root;
+        for (token, &id) in vocab {
+            let matched = MatchedToken {
+                id,
+                len: token.len(),
+            };
+            insert(&mut trie, ROOT, token, matched);
+            if let Some(suffix) = token.strip_prefix(subword_prefix) {
+                insert(&mut trie, suffix_root, suffix, matched);
+            }
+        }
+
+        // Compute failure links in breadth-first order. Failure links always
+        // point to nodes below the suffix root, so those are processed first.
+        for start in [trie.suffix_root, ROOT] {
+            let mut queue = VecDeque::from([start]);
+            while let Some(parent) = queue.pop_front() {
+                for &(ch, child) in &children[parent as usize] {
+                    trie.set_failure_link(parent, ch, child);
+                    queue.push_back(child);
+                }
+            }
+        }
+
+        trie
+    }
+
+    /// Compute the failure link and failure pops for a node, given those of
+    /// its parent.
+    fn set_failure_link(&mut self, parent: NodeId, ch: char, child: NodeId) {
+        let pops_start = self.fail_pops.len();
+
+        if let Some(token) = self.nodes[child as usize].token {
+            // If the node matches a token, emit it and match the rest of the
+            // word as a subword.
+            self.fail_pops.push(token);
+            self.nodes[child as usize].fail = Some(self.suffix_root);
+        } else {
+            // Otherwise emit the tokens the parent emits on failure, then
+            // follow failure links until we find a node that can match `ch`.
+            let parent_node = &self.nodes[parent as usize];
+            let mut fail = parent_node.fail;
+            self.fail_pops
+                .extend_from_within(parent_node.fail_pops.clone());
+            while let Some(node) = fail {
+                if let Some(&next) = self.edges.get(&(node, ch)) {
+                    fail = Some(next);
+                    break;
+                }
+                let node = &self.nodes[node as usize];
+                self.fail_pops.extend_from_within(node.fail_pops.clone());
+                fail = node.fail;
+            }
+            if fail.is_none() {
+                self.fail_pops.truncate(pops_start);
+            }
+            self.nodes[child as usize].fail = fail;
         }
+
+        self.nodes[child as usize].fail_pops = pops_start..self.fail_pops.len();
+    }
+
+    /// Follow the failure link of `node`, adding the tokens it produces to
+    /// `tokens`.
+    fn follow_failure_link(&self, node: NodeId, tokens: &mut Vec<MatchedToken>) -> Option<NodeId> {
+        let node = &self.nodes[node as usize];
+        let fail = node.fail?;
+        tokens.extend_from_slice(&self.fail_pops[node.fail_pops.clone()]);
+        Some(fail)
+    }
+
+    /// Tokenize a word using longest-match-first, adding the matched tokens
+    /// to `tokens`.
+    ///
+    /// Returns `None` if the word cannot be tokenized.
+    fn tokenize(&self, word: &str, tokens: &mut Vec<MatchedToken>) -> Option<()> {
+        let mut node = ROOT;
+        for ch in word.chars() {
+            node = loop {
+                if let Some(&next) = self.edges.get(&(node, ch)) {
+                    break next;
+                }
+                node = self.follow_failure_link(node, tokens)?;
+            };
+        }
+        while node != self.suffix_root {
+            node = self.follow_failure_link(node, tokens)?;
+        }
+        Some(())
     }
 }
 
@@ -56,59 +246,26 @@ impl Model for WordPiece {
         word: &str,
         on_token: &mut dyn FnMut(usize, TokenId),
     ) -> Result<(), EncodeError> {
-        let mut tmp_buf = String::with_capacity(self.max_word_len);
-        let mut offset = 0;
-
-        macro_rules! add_unknown_token {
-            () => {
-                let unknown_token = "[UNK]";
-                let unknown_token_id = self
-                    .get_token_id(unknown_token)
-                    .ok_or_else(|| EncodeError::TokenIdNotFound(unknown_token.to_string()))?;
-                on_token(offset, unknown_token_id);
-            };
-        }
-
         if word.trim().is_empty() {
             return Ok(());
         }
 
-        if word.chars().count() > self.max_word_len {
-            add_unknown_token!();
+        let mut tokens = Vec::new();
+        if word.chars().count() > self.max_word_len
+            || self.trie.tokenize(word, &mut tokens).is_none()
+        {
+            on_token(0, self.unknown_token_id()?);
             return Ok(());
         }
 
-        let mut remainder = word;
-        let mut word_tokens = 0;
-        while !remainder.is_empty() {
-            // Find longest prefix of `remainder` that is in the vocab.
-            let mut len = remainder.len();
-            while len > 0 {
-                let prefix = if word_tokens > 0 {
-                    tmp_buf.clear();
-                    tmp_buf.push_str(&self.subword_prefix);
-                    tmp_buf.push_str(&remainder[..len]);
-                    &tmp_buf[..]
-                } else {
-                    &remainder[..len]
-                };
-
-                if let Some(id) = self.token_to_id.get(prefix) {
-                    on_token(offset, *id);
-                    offset += prefix.len();
-                    remainder = remainder.split_at(len).1;
-                    word_tokens += 1;
-                    break;
-                } else {
-                    let last_char_bytes = prefix.chars().next_back().unwrap().len_utf8();
-                    len -= last_char_bytes;
-                }
-            }
-
-            if len == 0 {
-                add_unknown_token!();
-                break;
-            }
+        let mut offset = 0;
+        for (i, token) in tokens.into_iter().enumerate() {
+            on_token(offset, token.id);
+            offset += if i == 0 {
+                token.len
+            } else {
+                token.len - self.trie.prefix_len
+            };
         }
 
         Ok(())
@@ -126,6 +283,16 @@ impl Model for WordPiece {
         let token_strings = self.get_tokens(ids)?;
         Ok(token_strings.join(" "))
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "WordPiece",
+            "unk_token": "[UNK]",
+            "continuing_subword_prefix": self.subword_prefix,
+            "max_input_chars_per_word": self.max_word_len,
+            "vocab": vocab_to_json(self.token_to_id.iter().map(|(token, &id)| (token.as_str(), id))),
+        }))
+    }
 }
 
 #[cfg(test)]
@@ -134,9 +301,9 @@ mod tests {
 
     use rten_testing::TestCases;
 
-    use crate::models::{WordPiece, WordPieceOptions};
+    use crate::models::{Model, WordPiece, WordPieceOptions};
     use crate::normalizers::Normalizer;
-    use crate::tokenizer::{Tokenizer, TokenizerOptions};
+    use crate::tokenizer::{EncodeOptions, Tokenizer, TokenizerOptions};
     use crate::{normalizers, pre_tokenizers};
 
     fn create_tokenizer(
@@ -236,6 +403,108 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_wordpiece_longest_match() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            text: &'a str,
+            tokens: &'a [&'a str],
+            offsets: &'a [usize],
+        }
+
+        // Vocab where matching the longest token often fails part way
+        // through, requiring several failure links to be followed.
+        let vocab = &[
+            "[CLS]", "[SEP]", "[UNK]", "a", "abcdx", "##b", "##c", "##cdy", "##d", "##dz", "é",
+            "##éé",
+        ];
+
+        let cases = [
+            Case {
+                text: "abcdx",
+                tokens: &["abcdx"],
+                offsets: &[0],
+            },
+            Case {
+                text: "abcdy",
+                tokens: &["a", "##b", "##cdy"],
+                offsets: &[0, 1, 2],
+            },
+            Case {
+                text: "abcdz",
+                tokens: &["a", "##b", "##c", "##dz"],
+                offsets: &[0, 1, 2, 3],
+            },
+            Case {
+                text: "abcd",
+                tokens: &["a", "##b", "##c", "##d"],
+                offsets: &[0, 1, 2, 3],
+            },
+            // Multi-byte characters.
+            Case {
+                text: "ééé",
+                tokens: &["é", "##éé"],
+                offsets: &[0, 2],
+            },
+            // If any part of a word cannot be matched, the whole word is
+            // unknown.
+            Case {
+                text: "abe",
+                tokens: &["[UNK]"],
+                offsets: &[0],
+            },
+            Case {
+                text: "abe a",
+                tokens: &["[UNK]", "a"],
+                offsets: &[0, 4],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let &Case {
+                text,
+                tokens,
+                offsets,
+            } = case;
+
+            let tokenizer = create_tokenizer(vocab, None, Default::default());
+
+            let encoded = tokenizer
+                .encode(
+                    text,
+                    Some(EncodeOptions {
+                        skip_post_processor: true,
+                        ..Default::default()
+                    }),
+                )
+                .unwrap();
+            assert_eq!(
+                tokenizer.model().get_tokens(encoded.token_ids()).unwrap(),
+                tokens
+            );
+            assert_eq!(encoded.token_offsets(), offsets);
+        });
+
+        // Words which start with the subword prefix are matched against whole
+        // tokens, like other words. These are encoded using the model
+        // directly, as the BERT pre-tokenizer splits "#" into its own word.
+        let vocab: HashMap<_, _> = ["[UNK]", "#", "###", "##b"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, token)| (token.to_string(), i as u32))
+            .collect();
+        let model = WordPiece::from_vocab(vocab, Default::default());
+        let cases: [(&str, &[&str]); 3] = [
+            ("##", &["#", "###"]),
+            ("#b", &["#", "##b"]),
+            ("##b", &["##b"]),
+        ];
+        cases.test_each(|(word, tokens)| {
+            let ids = model.encode(word).unwrap();
+            assert_eq!(model.get_tokens(&ids).unwrap(), *tokens);
+        })
+    }
+
     #[test]
     fn test_wordpiece_max_word_len() {
         let vocab = &["[CLS]", "[SEP]", "[UNK]", "foo", "##bar", "##foo"];
@@ -3,7 +3,9 @@
 use std::error::Error;
 use std::fmt;
 
+use base64::prelude::*;
 use fancy_regex::Regex;
+use serde_json::json;
 use unicode_categories::UnicodeCategories;
 use unicode_normalization::char::{compose, decompose_canonical, decompose_compatible};
 
@@ -69,12 +71,16 @@ impl CharNormalizer {
 #[derive(Clone, Debug)]
 pub enum NormalizeError {
     RegexError(Box<fancy_regex::Error>),
+
+    /// The data for a [`Precompiled`] normalizer is invalid.
+    InvalidCharsMap,
 }
 
 impl fmt::Display for NormalizeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::RegexError(err) => write!(f, "regex failed {}", err),
+            Self::InvalidCharsMap => write!(f, "invalid precompiled charsmap"),
         }
     }
 }
@@ -83,6 +89,7 @@ impl Error for NormalizeError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             Self::RegexError(err) => Some(err),
+            Self::InvalidCharsMap => None,
         }
     }
 }
@@ -100,13 +107,22 @@ impl From<fancy_regex::Error> for NormalizeError {
 /// from positions in the normalized string back to the original string. This
 /// is useful for post-processing in NLP tasks to map machine learning model
 /// outputs back to the location in the original text.
-pub trait Normalizer: std::fmt::Debug {
+pub trait Normalizer: std::fmt::Debug + Send + Sync {
     /// Apply normalization to a string.
     ///
     /// Returns a tuple of `(normalized_string, offset_map)` where `offset_map`
     /// is a mapping from byte offsets in the normalized string to corresponding
     /// offsets in the original string.
     fn normalize(&self, text: &str) -> Result<(String, Vec<usize>), NormalizeError>;
+
+    /// Return the configuration of this normalizer in the format used by
+    /// Hugging Face `tokenizer.json` files, or `None` if it cannot be
+    /// represented in that format.
+    ///
+    /// The default implementation returns `None`.
+    fn to_json(&self) -> Option<serde_json::Value> {
+        None
+    }
 }
 
 /// A [`Normalizer`] that implements normalization used by BERT and BERT-derived
@@ -174,6 +190,18 @@ impl Normalizer for Bert {
 
         Ok((normalized, offsets))
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        // `clean_text` and `handle_chinese_chars` are not configurable here.
+        // Use the values from the original BERT tokenizer.
+        Some(json!({
+            "type": "BertNormalizer",
+            "clean_text": true,
+            "handle_chinese_chars": true,
+            "strip_accents": self.strip_accents,
+            "lowercase": self.lowercase,
+        }))
+    }
 }
 
 /// Replaces occurrences of a pattern with a given string.
@@ -220,6 +248,14 @@ impl Normalizer for Replace {
 
         Ok((normalized, offsets))
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Replace",
+            "pattern": { "Regex": self.regex.as_str() },
+            "content": self.content,
+        }))
+    }
 }
 
 /// Run a series of normalizers in sequence.
@@ -250,6 +286,18 @@ impl Normalizer for Sequence {
 
         Ok((normalized, offsets))
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        let normalizers = self
+            .normalizers
+            .iter()
+            .map(|normalizer| normalizer.to_json())
+            .collect::<Option<Vec<_>>>()?;
+        Some(json!({
+            "type": "Sequence",
+            "normalizers": normalizers,
+        }))
+    }
 }
 
 /// Temporary buffer used while normalizing text.
@@ -352,13 +400,303 @@ impl Normalizer for Unicode {
 
         Ok(tmp.into_string_with_byte_offsets())
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        let kind = match self {
+            Self::Nfc => "NFC",
+            Self::Nfd => "NFD",
+            Self::Nfkc => "NFKC",
+            Self::Nfkd => "NFKD",
+        };
+        Some(json!({ "type": kind }))
+    }
+}
+
+/// Normalize text by replacing each character with zero or more characters.
+///
+/// `map` is called with each character of `text` and appends the replacement
+/// to the output.
+fn map_chars(text: &str, mut map: impl FnMut(char, &mut String)) -> (String, Vec<usize>) {
+    let mut normalized = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len());
+
+    for (offset, ch) in text.char_indices() {
+        let prev_len = normalized.len();
+        map(ch, &mut normalized);
+        offsets.extend(std::iter::repeat(offset).take(normalized.len() - prev_len));
+    }
+
+    (normalized, offsets)
+}
+
+/// Convert text to lowercase using [`char::to_lowercase`].
+#[derive(Clone, Debug, Default)]
+pub struct Lowercase {}
+
+impl Lowercase {
+    pub fn new() -> Self {
+        Lowercase {}
+    }
+}
+
+impl Normalizer for Lowercase {
+    fn normalize(&self, text: &str) -> Result<(String, Vec<usize>), NormalizeError> {
+        Ok(map_chars(text, |ch, out| out.extend(ch.to_lowercase())))
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({ "type": "Lowercase" }))
+    }
+}
+
+/// Remove combining marks (characters in the Unicode "M" categories).
+///
+/// This is typically used after a [`Unicode::Nfd`] normalizer, to remove
+/// accents from decomposed characters.
+#[derive(Clone, Debug, Default)]
+pub struct StripAccents {}
+
+impl StripAccents {
+    pub fn new() -> Self {
+        StripAccents {}
+    }
+}
+
+impl Normalizer for StripAccents {
+    fn normalize(&self, text: &str) -> Result<(String, Vec<usize>), NormalizeError> {
+        Ok(map_chars(text, |ch, out| {
+            if !ch.is_mark() {
+                out.push(ch);
+            }
+        }))
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({ "type": "StripAccents" }))
+    }
+}
+
+/// Remove whitespace from the start and/or end of the text.
+#[derive(Clone, Debug)]
+pub struct Strip {
+    left: bool,
+    right: bool,
+}
+
+impl Strip {
+    /// Create a normalizer which removes leading whitespace if `left` is true
+    /// and trailing whitespace if `right` is true.
+    pub fn new(left: bool, right: bool) -> Self {
+        Strip { left, right }
+    }
+}
+
+impl Normalizer for Strip {
+    fn normalize(&self, text: &str) -> Result<(String, Vec<usize>), NormalizeError> {
+        let start = if self.left {
+            text.len() - text.trim_start().len()
+        } else {
+            0
+        };
+        let end = if self.right {
+            text.trim_end().len()
+        } else {
+            text.len()
+        };
+        let (start, end) = (start, end.max(start));
+        Ok((text[start..end].to_string(), (start..end).collect()))
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Strip",
+            "strip_left": self.left,
+            "strip_right": self.right,
+        }))
+    }
+}
+
+/// Add a prefix to the start of non-empty text.
+#[derive(Clone, Debug)]
+pub struct Prepend {
+    prefix: String,
+}
+
+impl Prepend {
+    pub fn new(prefix: String) -> Self {
+        Prepend { prefix }
+    }
+}
+
+impl Normalizer for Prepend {
+    fn normalize(&self, text: &str) -> Result<(String, Vec<usize>), NormalizeError> {
+        if text.is_empty() {
+            return Ok((String::new(), Vec::new()));
+        }
+
+        let mut normalized = String::with_capacity(self.prefix.len() + text.len());
+        normalized.push_str(&self.prefix);
+        normalized.push_str(text);
+
+        let mut offsets = Vec::with_capacity(normalized.len());
+        offsets.extend(std::iter::repeat(0).take(self.prefix.len()));
+        offsets.extend(0..text.len());
+
+        Ok((normalized, offsets))
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Prepend",
+            "prepend": self.prefix,
+        }))
+    }
+}
+
+/// Normalizer which applies rules from a SentencePiece "precompiled charsmap".
+///
+/// The charsmap is found in the `precompiled_charsmap` field of SentencePiece
+/// models and `Precompiled` normalizers in `tokenizer.json` files. It
+/// consists of a little-endian `u32` specifying the size in bytes of a
+/// [Darts-clone](https://github.com/s-yata/darts-clone) double-array trie,
+/// followed by the trie, followed by a sequence of NUL-terminated
+/// replacement strings. Values in the trie are offsets into the replacement
+/// strings.
+///
+/// At each position in the input, the longest key in the trie which matches
+/// is replaced. Text which does not match any key is left as-is.
+#[derive(Clone, Debug)]
+pub struct Precompiled {
+    trie: Vec<u32>,
+    replacements: String,
+}
+
+impl Precompiled {
+    /// Create a normalizer from a serialized charsmap.
+    pub fn new(charsmap: &[u8]) -> Result<Self, NormalizeError> {
+        let (size, rest) = charsmap
+            .split_first_chunk::<4>()
+            .ok_or(NormalizeError::InvalidCharsMap)?;
+        let trie_size = u32::from_le_bytes(*size) as usize;
+        if !trie_size.is_multiple_of(4) || trie_size > rest.len() {
+            return Err(NormalizeError::InvalidCharsMap);
+        }
+        let (trie_data, replacements) = rest.split_at(trie_size);
+
+        let trie = trie_data
+            .chunks_exact(4)
+            .map(|unit| u32::from_le_bytes(unit.try_into().unwrap()))
+            .collect();
+        let replacements = std::str::from_utf8(replacements)
+            .map_err(|_| NormalizeError::InvalidCharsMap)?
+            .to_string();
+
+        Ok(Precompiled { trie, replacements })
+    }
+
+    /// Find the longest key in the trie which is a prefix of `text`.
+    ///
+    /// Returns the length of the key and the value associated with it.
+    fn longest_prefix(&self, text: &[u8]) -> Option<(usize, usize)> {
+        fn has_leaf(unit: u32) -> bool {
+            (unit >> 8) & 1 == 1
+        }
+        fn value(unit: u32) -> usize {
+            (unit & ((1 << 31) - 1)) as usize
+        }
+        fn label(unit: u32) -> u32 {
+            unit & ((1 << 31) | 0xFF)
+        }
+        fn offset(unit: u32) -> usize {
+            ((unit >> 10) << ((unit & (1 << 9)) >> 6)) as usize
+        }
+
+        let mut longest = None;
+        let mut pos = offset(*self.trie.first()?);
+
+        for (i, &byte) in text.iter().enumerate() {
+            if byte == 0 {
+                break;
+            }
+            pos ^= byte as usize;
+            let unit = *self.trie.get(pos)?;
+            if label(unit) != byte as u32 {
+                break;
+            }
+            pos ^= offset(unit);
+            if has_leaf(unit) {
+                let value_unit = *self.trie.get(pos)?;
+                longest = Some((i + 1, value(value_unit)));
+            }
+        }
+
+        longest
+    }
+
+    /// Return the replacement string which starts at `offset`.
+    fn replacement(&self, offset: usize) -> Result<&str, NormalizeError> {
+        let replacement = self
+            .replacements
+            .get(offset..)
+            .ok_or(NormalizeError::InvalidCharsMap)?;
+        Ok(replacement.split('\0').next().unwrap_or_default())
+    }
+}
+
+impl Normalizer for Precompiled {
+    fn normalize(&self, text: &str) -> Result<(String, Vec<usize>), NormalizeError> {
+        let mut normalized = String::with_capacity(text.len());
+        let mut offsets = Vec::with_capacity(text.len());
+        let mut pos = 0;
+
+        while pos < text.len() {
+            let matched = self
+                .longest_prefix(&text.as_bytes()[pos..])
+                .filter(|(len, _)| text.is_char_boundary(pos + len));
+
+            if let Some((len, value)) = matched {
+                let replacement = self.replacement(value)?;
+                normalized.push_str(replacement);
+                offsets.extend(std::iter::repeat(pos).take(replacement.len()));
+                pos += len;
+            } else {
+                let ch = text[pos..].chars().next().unwrap();
+                normalized.push(ch);
+                offsets.extend(pos..pos + ch.len_utf8());
+                pos += ch.len_utf8();
+            }
+        }
+
+        Ok((normalized, offsets))
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        // Re-assemble the charsmap in the format accepted by `new`.
+        let trie_size = (self.trie.len() * 4) as u32;
+        let mut charsmap = Vec::with_capacity(4 + trie_size as usize + self.replacements.len());
+        charsmap.extend(trie_size.to_le_bytes());
+        charsmap.extend(self.trie.iter().flat_map(|unit| unit.to_le_bytes()));
+        charsmap.extend(self.replacements.as_bytes());
+
+        Some(json!({
+            "type": "Precompiled",
+            "precompiled_charsmap": BASE64_STANDARD.encode(charsmap),
+        }))
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use std::collections::BTreeMap;
+
+    use base64::prelude::*;
+
     use rten_testing::TestCases;
 
-    use super::{Bert, BertOptions, Normalizer, Replace, Sequence, Unicode};
+    use super::{
+        Bert, BertOptions, Lowercase, Normalizer, Precompiled, Prepend, Replace, Sequence, Strip,
+        StripAccents, Unicode,
+    };
 
     #[test]
     fn test_bert_noop() {
@@ -528,6 +866,180 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_lowercase() {
+        let (normalized, offsets) = Lowercase::new().normalize("FOO İ").unwrap();
+        assert_eq!(normalized, "foo i\u{307}");
+        assert_eq!(offsets, [0, 1, 2, 3, 4, 4, 4]);
+    }
+
+    #[test]
+    fn test_strip_accents() {
+        let (normalized, offsets) = StripAccents::new().normalize("Mo\u{308}tor").unwrap();
+        assert_eq!(normalized, "Motor");
+        assert_eq!(offsets, [0, 1, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_strip() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            input: &'a str,
+            left: bool,
+            right: bool,
+            expected: &'a str,
+            expected_offsets: Vec<usize>,
+        }
+
+        let cases = [
+            Case {
+                input: "  foo ",
+                left: true,
+                right: true,
+                expected: "foo",
+                expected_offsets: [2, 3, 4].into(),
+            },
+            Case {
+                input: "  foo ",
+                left: true,
+                right: false,
+                expected: "foo ",
+                expected_offsets: [2, 3, 4, 5].into(),
+            },
+            Case {
+                input: "  foo ",
+                left: false,
+                right: true,
+                expected: "  foo",
+                expected_offsets: [0, 1, 2, 3, 4].into(),
+            },
+            Case {
+                input: "   ",
+                left: true,
+                right: true,
+                expected: "",
+                expected_offsets: [].into(),
+            },
+        ];
+
+        cases.test_each(|case| {
+            let normalizer = Strip::new(case.left, case.right);
+            let (normalized, offsets) = normalizer.normalize(case.input).unwrap();
+            assert_eq!(normalized, case.expected);
+            assert_eq!(offsets, case.expected_offsets);
+        })
+    }
+
+    #[test]
+    fn test_prepend() {
+        let normalizer = Prepend::new("▁".to_string());
+
+        let (normalized, offsets) = normalizer.normalize("ab").unwrap();
+        assert_eq!(normalized, "▁ab");
+        assert_eq!(offsets, [0, 0, 0, 0, 1]);
+
+        let (normalized, offsets) = normalizer.normalize("").unwrap();
+        assert_eq!(normalized, "");
+        assert!(offsets.is_empty());
+    }
+
+    /// Encode `(key, replacement)` rules as a SentencePiece precompiled
+    /// charsmap.
+    pub fn encode_charsmap(rules: &[(&str, &str)]) -> Vec<u8> {
+        // Build a trie where each node has children and an optional offset
+        // into the replacements.
+        let mut nodes: Vec<(BTreeMap<u8, usize>, Option<u32>)> = vec![Default::default()];
+        let mut replacements = Vec::new();
+        for (key, replacement) in rules {
+            let mut node = 0;
+            for &byte in key.as_bytes() {
+                node = match nodes[node].0.get(&byte) {
+                    Some(&child) => child,
+                    None => {
+                        nodes.push(Default::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].0.insert(byte, child);
+                        child
+                    }
+                };
+            }
+            nodes[node].1 = Some(replacements.len() as u32);
+            replacements.extend(replacement.as_bytes());
+            replacements.push(0);
+        }
+
+        // Place the nodes in a double array, using the first offset at which
+        // all of a node's children fit.
+        let mut units = vec![0u32];
+        let mut used = vec![true];
+        let mut pending = vec![(0, 0)];
+        while let Some((node, pos)) = pending.pop() {
+            let (children, value) = &nodes[node];
+            let labels: Vec<usize> = value
+                .iter()
+                .map(|_| 0)
+                .chain(children.keys().map(|&byte| byte as usize))
+                .collect();
+            let offset = (1..)
+                .find(|offset| {
+                    labels
+                        .iter()
+                        .all(|label| !used.get(pos ^ offset ^ label).copied().unwrap_or(false))
+                })
+                .unwrap();
+            let max_pos = labels.iter().map(|label| pos ^ offset ^ label).max();
+            if let Some(max_pos) = max_pos.filter(|max_pos| *max_pos >= units.len()) {
+                units.resize(max_pos + 1, 0);
+                used.resize(max_pos + 1, false);
+            }
+
+            units[pos] |= (offset as u32) << 10;
+            if let Some(value) = value {
+                units[pos] |= 1 << 8;
+                units[pos ^ offset] = (1 << 31) | value;
+                used[pos ^ offset] = true;
+            }
+            for (&byte, &child) in children {
+                let child_pos = pos ^ offset ^ byte as usize;
+                units[child_pos] = byte as u32;
+                used[child_pos] = true;
+                pending.push((child, child_pos));
+            }
+        }
+
+        let mut charsmap = Vec::new();
+        charsmap.extend(((units.len() * 4) as u32).to_le_bytes());
+        for unit in units {
+            charsmap.extend(unit.to_le_bytes());
+        }
+        charsmap.extend(replacements);
+        charsmap
+    }
+
+    #[test]
+    fn test_precompiled() {
+        let charsmap = encode_charsmap(&[("Ⅰ", "I"), ("ﬁ", "fi"), ("ab", "X"), ("a", "A")]);
+        let normalizer = Precompiled::new(&charsmap).unwrap();
+
+        // Longest matches are replaced, other text is kept as-is.
+        let (normalized, offsets) = normalizer.normalize("ﬁⅠ abc a").unwrap();
+        assert_eq!(normalized, "fiI Xc A");
+        assert_eq!(offsets, [0, 0, 3, 6, 7, 9, 10, 11]);
+
+        let (normalized, offsets) = normalizer.normalize("").unwrap();
+        assert_eq!(normalized, "");
+        assert!(offsets.is_empty());
+
+        // Serializing produces the original charsmap.
+        let json = normalizer.to_json().unwrap();
+        let encoded = json["precompiled_charsmap"].as_str().unwrap();
+        assert_eq!(BASE64_STANDARD.decode(encoded).unwrap(), charsmap);
+
+        // Invalid charsmaps
+        assert!(Precompiled::new(&[1, 2]).is_err());
+        assert!(Precompiled::new(&charsmap[..charsmap.len() / 2]).is_err());
+    }
+
     fn lowercase_normalizer() -> Box<dyn Normalizer> {
         Box::new(Bert::new(BertOptions {
             lowercase: true,
@@ -0,0 +1,757 @@
+//! Post-processors which combine encoded sequences into model inputs and add
+//! special tokens such as `[CLS]` and `[SEP]`.
+
+use std::error::Error;
+use std::fmt;
+use std::iter::repeat;
+
+use serde_json::json;
+
+use crate::tokenizer::TokenId;
+
+/// Errors occuring while constructing a [`PostProcessor`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum PostProcessError {
+    /// A template is invalid. For example a template for a single sequence
+    /// refers to the second sequence, or a template refers to a special token
+    /// which is not defined.
+    InvalidTemplate(String),
+}
+
+impl fmt::Display for PostProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidTemplate(err) => write!(f, "invalid template: {}", err),
+        }
+    }
+}
+
+impl Error for PostProcessError {}
+
+/// Token IDs and associated information for an encoded sequence.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TokenSequence {
+    /// Token IDs.
+    pub ids: Vec<TokenId>,
+
+    /// Byte offset in the input text of the start of each token.
+    pub offsets: Vec<usize>,
+
+    /// Token type ID (also called segment ID) for each token.
+    pub type_ids: Vec<usize>,
+
+    /// Byte offset in the input text of the end of the sequence. This is used
+    /// as the offset for special tokens which are added after the sequence.
+    pub end_offset: usize,
+}
+
+impl TokenSequence {
+    /// Return the offset to use for special tokens added before this sequence.
+    fn start_offset(&self) -> usize {
+        self.offsets.first().copied().unwrap_or(self.end_offset)
+    }
+
+    /// Append the tokens from another sequence to this one.
+    fn extend(&mut self, other: &TokenSequence) {
+        self.ids.extend_from_slice(&other.ids);
+        self.offsets.extend_from_slice(&other.offsets);
+        self.type_ids.extend_from_slice(&other.type_ids);
+        self.end_offset = other.end_offset;
+    }
+
+    /// Concatenate a list of sequences into one.
+    pub(crate) fn concat(sequences: &[TokenSequence]) -> TokenSequence {
+        let mut output = TokenSequence::default();
+        for seq in sequences {
+            output.extend(seq);
+        }
+        output
+    }
+}
+
+/// A post-processor combines the encoded input sequences after tokenization,
+/// adding any special tokens that the model expects.
+///
+/// Post-processors operate on a list of one or two sequences (for single
+/// inputs and input pairs respectively) and return a list of sequences.
+/// The tokenizer concatenates the output to produce the final encoding.
+pub trait PostProcessor: Send + Sync {
+    /// Return the number of special tokens that are added to a single
+    /// sequence (`is_pair = false`) or a pair of sequences.
+    fn added_tokens(&self, is_pair: bool) -> usize;
+
+    /// Combine sequences and add special tokens.
+    ///
+    /// `token_str` returns the string representation of a token ID. This is
+    /// used by post-processors which adjust offsets based on the content of
+    /// tokens.
+    fn process(
+        &self,
+        sequences: Vec<TokenSequence>,
+        token_str: &dyn Fn(TokenId) -> Option<String>,
+    ) -> Vec<TokenSequence>;
+
+    /// Return the configuration of this post-processor in the format used by
+    /// Hugging Face `tokenizer.json` files, or `None` if it cannot be
+    /// represented in that format.
+    ///
+    /// `token_str` returns the string representation of a token ID. This is
+    /// used to name special tokens in the output.
+    ///
+    /// The default implementation returns `None`.
+    fn to_json(&self, token_str: &dyn Fn(TokenId) -> Option<String>) -> Option<serde_json::Value> {
+        let _ = token_str;
+        None
+    }
+}
+
+/// Identifies an input sequence in a [`Template`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SequenceId {
+    /// The first sequence.
+    A,
+    /// The second sequence.
+    B,
+}
+
+/// Element of a template used by [`TemplateProcessing`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Piece {
+    /// Tokens from an input sequence, with a given type ID.
+    Sequence { id: SequenceId, type_id: usize },
+
+    /// Special token(s) with a given type ID.
+    SpecialToken { ids: Vec<TokenId>, type_id: usize },
+}
+
+/// A list of pieces which make up the output of [`TemplateProcessing`].
+pub type Template = Vec<Piece>;
+
+/// Post-processor which combines sequences and special tokens according to
+/// a template.
+///
+/// For example BERT models use the template `[CLS] $A [SEP]` for single
+/// sequences and `[CLS] $A [SEP] $B:1 [SEP]:1` for pairs, where `:1` is the
+/// token type ID.
+///
+/// Special tokens are assigned the offset of the end of the preceding
+/// sequence, or the start of the following sequence if they appear at the
+/// start of the template.
+#[derive(Clone, Debug)]
+pub struct TemplateProcessing {
+    single: Template,
+    pair: Template,
+}
+
+impl TemplateProcessing {
+    /// Create a post-processor with templates for single sequences and pairs.
+    pub fn new(single: Template, pair: Template) -> Result<Self, PostProcessError> {
+        let has_sequence = |template: &Template, seq_id| {
+            template
+                .iter()
+                .any(|piece| matches!(piece, Piece::Sequence { id, .. } if *id == seq_id))
+        };
+        if !has_sequence(&single, SequenceId::A) || has_sequence(&single, SequenceId::B) {
+            return Err(PostProcessError::InvalidTemplate(
+                "single sequence template must contain $A and not $B".to_string(),
+            ));
+        }
+        if !has_sequence(&pair, SequenceId::A) || !has_sequence(&pair, SequenceId::B) {
+            return Err(PostProcessError::InvalidTemplate(
+                "pair template must contain $A and $B".to_string(),
+            ));
+        }
+        Ok(TemplateProcessing { single, pair })
+    }
+
+    /// Create a post-processor for BERT-style models.
+    ///
+    /// This uses the template `cls $A sep` for single sequences and
+    /// `cls $A sep $B:1 sep:1` for pairs. Either token may be omitted.
+    pub fn bert(cls: Option<TokenId>, sep: Option<TokenId>) -> Self {
+        let special = |id: Option<TokenId>, type_id| {
+            id.map(|id| Piece::SpecialToken {
+                ids: vec![id],
+                type_id,
+            })
+        };
+        let seq = |id, type_id| Some(Piece::Sequence { id, type_id });
+
+        let single = [special(cls, 0), seq(SequenceId::A, 0), special(sep, 0)];
+        let pair = [
+            special(cls, 0),
+            seq(SequenceId::A, 0),
+            special(sep, 0),
+            seq(SequenceId::B, 1),
+            special(sep, 1),
+        ];
+        TemplateProcessing {
+            single: single.into_iter().flatten().collect(),
+            pair: pair.into_iter().flatten().collect(),
+        }
+    }
+
+    fn apply(template: &Template, sequences: &[TokenSequence]) -> TokenSequence {
+        let get_seq = |id| match id {
+            SequenceId::A => &sequences[0],
+            SequenceId::B => &sequences[1],
+        };
+
+        let mut output = TokenSequence::default();
+        let mut prev_seq: Option<&TokenSequence> = None;
+
+        for (i, piece) in template.iter().enumerate() {
+            match piece {
+                Piece::Sequence { id, type_id } => {
+                    let seq = get_seq(*id);
+                    output.ids.extend_from_slice(&seq.ids);
+                    output.offsets.extend_from_slice(&seq.offsets);
+                    output.type_ids.extend(repeat(*type_id).take(seq.ids.len()));
+                    output.end_offset = seq.end_offset;
+                    prev_seq = Some(seq);
+                }
+                Piece::SpecialToken { ids, type_id } => {
+                    let offset = match prev_seq {
+                        Some(seq) => seq.end_offset,
+                        None => template[i + 1..]
+                            .iter()
+                            .find_map(|piece| match piece {
+                                Piece::Sequence { id, .. } => Some(get_seq(*id).start_offset()),
+                                _ => None,
+                            })
+                            .unwrap_or(0),
+                    };
+                    output.ids.extend_from_slice(ids);
+                    output.offsets.extend(repeat(offset).take(ids.len()));
+                    output.type_ids.extend(repeat(*type_id).take(ids.len()));
+                }
+            }
+        }
+
+        output
+    }
+}
+
+impl PostProcessor for TemplateProcessing {
+    fn added_tokens(&self, is_pair: bool) -> usize {
+        let template = if is_pair { &self.pair } else { &self.single };
+        template
+            .iter()
+            .map(|piece| match piece {
+                Piece::SpecialToken { ids, .. } => ids.len(),
+                Piece::Sequence { .. } => 0,
+            })
+            .sum()
+    }
+
+    fn process(
+        &self,
+        sequences: Vec<TokenSequence>,
+        _token_str: &dyn Fn(TokenId) -> Option<String>,
+    ) -> Vec<TokenSequence> {
+        let template = match sequences.len() {
+            1 => &self.single,
+            2 => &self.pair,
+            _ => return sequences,
+        };
+        vec![Self::apply(template, &sequences)]
+    }
+
+    fn to_json(&self, token_str: &dyn Fn(TokenId) -> Option<String>) -> Option<serde_json::Value> {
+        let mut special_tokens = serde_json::Map::new();
+        let mut template_json = |template: &Template| {
+            template
+                .iter()
+                .map(|piece| match piece {
+                    Piece::Sequence { id, type_id } => {
+                        let id = match id {
+                            SequenceId::A => "A",
+                            SequenceId::B => "B",
+                        };
+                        Some(json!({ "Sequence": { "id": id, "type_id": type_id } }))
+                    }
+                    Piece::SpecialToken { ids, type_id } => {
+                        let tokens = ids
+                            .iter()
+                            .map(|&id| token_str(id))
+                            .collect::<Option<Vec<_>>>()?;
+                        let name = tokens.concat();
+                        special_tokens.insert(
+                            name.clone(),
+                            json!({ "id": name, "ids": ids, "tokens": tokens }),
+                        );
+                        Some(json!({ "SpecialToken": { "id": name, "type_id": type_id } }))
+                    }
+                })
+                .collect::<Option<Vec<_>>>()
+        };
+        let single = template_json(&self.single)?;
+        let pair = template_json(&self.pair)?;
+
+        Some(json!({
+            "type": "TemplateProcessing",
+            "single": single,
+            "pair": pair,
+            "special_tokens": special_tokens,
+        }))
+    }
+}
+
+/// Post-processor for byte-level BPE models such as GPT-2.
+///
+/// This does not add any tokens. If `trim_offsets` is enabled, the offsets of
+/// tokens which start with whitespace (eg. `Ġhello`) are adjusted to exclude
+/// it. Since offsets only record the start of each token, trailing
+/// whitespace is not trimmed.
+///
+/// `add_prefix_space` indicates that the pre-tokenizer adds a space to the
+/// start of the input. In that case a single leading space on the first
+/// token is not trimmed, as it does not occur in the input text.
+#[derive(Clone, Debug)]
+pub struct ByteLevel {
+    add_prefix_space: bool,
+    trim_offsets: bool,
+}
+
+impl ByteLevel {
+    pub fn new(add_prefix_space: bool, trim_offsets: bool) -> Self {
+        ByteLevel {
+            add_prefix_space,
+            trim_offsets,
+        }
+    }
+
+    /// Move the offset of each token past any leading whitespace.
+    fn trim_offsets(&self, seq: &mut TokenSequence, token_str: &dyn Fn(TokenId) -> Option<String>) {
+        for i in 0..seq.ids.len() {
+            let Some(token) = token_str(seq.ids[i]) else {
+                continue;
+            };
+
+            // `Ġ` is the byte-level encoding of a space.
+            let mut leading: usize = token
+                .chars()
+                .take_while(|&ch| ch == 'Ġ' || ch.is_whitespace())
+                .map(|ch| if ch == 'Ġ' { 1 } else { ch.len_utf8() })
+                .sum();
+            if leading == 0 {
+                continue;
+            }
+
+            let is_first = i == 0 || seq.offsets[i] == 0;
+            if is_first && self.add_prefix_space && leading == 1 {
+                leading = 0;
+            }
+
+            let end = seq
+                .offsets
+                .get(i + 1)
+                .copied()
+                .unwrap_or(seq.end_offset)
+                .max(seq.offsets[i]);
+            seq.offsets[i] = (seq.offsets[i] + leading).min(end);
+        }
+    }
+}
+
+impl Default for ByteLevel {
+    fn default() -> Self {
+        Self::new(true, true)
+    }
+}
+
+impl PostProcessor for ByteLevel {
+    fn added_tokens(&self, _is_pair: bool) -> usize {
+        0
+    }
+
+    fn process(
+        &self,
+        mut sequences: Vec<TokenSequence>,
+        token_str: &dyn Fn(TokenId) -> Option<String>,
+    ) -> Vec<TokenSequence> {
+        if self.trim_offsets {
+            for seq in &mut sequences {
+                self.trim_offsets(seq, token_str);
+            }
+        }
+        sequences
+    }
+
+    fn to_json(&self, _token_str: &dyn Fn(TokenId) -> Option<String>) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "ByteLevel",
+            "add_prefix_space": self.add_prefix_space,
+            "trim_offsets": self.trim_offsets,
+            "use_regex": true,
+        }))
+    }
+}
+
+/// Post-processor for RoBERTa models.
+///
+/// This uses the template `cls $A sep` for single sequences and
+/// `cls $A sep sep $B sep` for pairs. All tokens have type ID 0, as RoBERTa
+/// does not use token types. Before special tokens are added, offsets are
+/// adjusted in the same way as the [`ByteLevel`] post-processor, using the
+/// `add_prefix_space` and `trim_offsets` settings.
+#[derive(Clone, Debug)]
+pub struct Roberta {
+    cls: TokenId,
+    sep: TokenId,
+    byte_level: ByteLevel,
+    template: TemplateProcessing,
+}
+
+impl Roberta {
+    pub fn new(cls: TokenId, sep: TokenId, add_prefix_space: bool, trim_offsets: bool) -> Self {
+        let special = |id| Piece::SpecialToken {
+            ids: vec![id],
+            type_id: 0,
+        };
+        let seq = |id| Piece::Sequence { id, type_id: 0 };
+        let template = TemplateProcessing {
+            single: vec![special(cls), seq(SequenceId::A), special(sep)],
+            pair: vec![
+                special(cls),
+                seq(SequenceId::A),
+                special(sep),
+                special(sep),
+                seq(SequenceId::B),
+                special(sep),
+            ],
+        };
+        Roberta {
+            cls,
+            sep,
+            byte_level: ByteLevel::new(add_prefix_space, trim_offsets),
+            template,
+        }
+    }
+}
+
+impl PostProcessor for Roberta {
+    fn added_tokens(&self, is_pair: bool) -> usize {
+        self.template.added_tokens(is_pair)
+    }
+
+    fn process(
+        &self,
+        sequences: Vec<TokenSequence>,
+        token_str: &dyn Fn(TokenId) -> Option<String>,
+    ) -> Vec<TokenSequence> {
+        let sequences = self.byte_level.process(sequences, token_str);
+        self.template.process(sequences, token_str)
+    }
+
+    fn to_json(&self, token_str: &dyn Fn(TokenId) -> Option<String>) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "RobertaProcessing",
+            "sep": [token_str(self.sep)?, self.sep],
+            "cls": [token_str(self.cls)?, self.cls],
+            "trim_offsets": self.byte_level.trim_offsets,
+            "add_prefix_space": self.byte_level.add_prefix_space,
+        }))
+    }
+}
+
+/// Run a series of post-processors in sequence.
+pub struct Sequence {
+    processors: Vec<Box<dyn PostProcessor>>,
+}
+
+impl Sequence {
+    pub fn from_vec(processors: Vec<Box<dyn PostProcessor>>) -> Self {
+        Sequence { processors }
+    }
+}
+
+impl PostProcessor for Sequence {
+    fn added_tokens(&self, is_pair: bool) -> usize {
+        self.processors
+            .iter()
+            .map(|pp| pp.added_tokens(is_pair))
+            .sum()
+    }
+
+    fn process(
+        &self,
+        mut sequences: Vec<TokenSequence>,
+        token_str: &dyn Fn(TokenId) -> Option<String>,
+    ) -> Vec<TokenSequence> {
+        for processor in &self.processors {
+            sequences = processor.process(sequences, token_str);
+        }
+        sequences
+    }
+
+    fn to_json(&self, token_str: &dyn Fn(TokenId) -> Option<String>) -> Option<serde_json::Value> {
+        let processors = self
+            .processors
+            .iter()
+            .map(|processor| processor.to_json(token_str))
+            .collect::<Option<Vec<_>>>()?;
+        Some(json!({
+            "type": "Sequence",
+            "processors": processors,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+
+    use super::{
+        ByteLevel, Piece, PostProcessError, PostProcessor, Roberta, Sequence, SequenceId,
+        TemplateProcessing, TokenSequence,
+    };
+
+    fn token_seq(ids: &[u32], start_offset: usize, type_id: usize) -> TokenSequence {
+        TokenSequence {
+            ids: ids.to_vec(),
+            offsets: (0..ids.len()).map(|i| start_offset + i * 2).collect(),
+            type_ids: vec![type_id; ids.len()],
+            end_offset: start_offset + ids.len() * 2,
+        }
+    }
+
+    #[test]
+    fn test_template_processing() {
+        #[derive(Debug)]
+        struct Case {
+            processor: TemplateProcessing,
+            pair: bool,
+            expected: TokenSequence,
+        }
+
+        let cls = 101;
+        let sep = 102;
+        let cases = [
+            Case {
+                processor: TemplateProcessing::bert(Some(cls), Some(sep)),
+                pair: false,
+                expected: TokenSequence {
+                    ids: vec![cls, 1, 2, sep],
+                    offsets: vec![0, 0, 2, 4],
+                    type_ids: vec![0, 0, 0, 0],
+                    end_offset: 4,
+                },
+            },
+            Case {
+                processor: TemplateProcessing::bert(Some(cls), Some(sep)),
+                pair: true,
+                expected: TokenSequence {
+                    ids: vec![cls, 1, 2, sep, 3, sep],
+                    offsets: vec![0, 0, 2, 4, 4, 6],
+                    type_ids: vec![0, 0, 0, 0, 1, 1],
+                    end_offset: 6,
+                },
+            },
+            Case {
+                processor: TemplateProcessing::bert(None, Some(sep)),
+                pair: false,
+                expected: TokenSequence {
+                    ids: vec![1, 2, sep],
+                    offsets: vec![0, 2, 4],
+                    type_ids: vec![0, 0, 0],
+                    end_offset: 4,
+                },
+            },
+        ];
+
+        cases.test_each(|case| {
+            let mut sequences = vec![token_seq(&[1, 2], 0, 0)];
+            if case.pair {
+                sequences.push(token_seq(&[3], 4, 1));
+            }
+            let n_input_tokens: usize = sequences.iter().map(|seq| seq.ids.len()).sum();
+
+            let output = case.processor.process(sequences, &|_| None);
+            assert_eq!(output, std::slice::from_ref(&case.expected));
+            assert_eq!(
+                case.processor.added_tokens(case.pair),
+                case.expected.ids.len() - n_input_tokens
+            );
+        })
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        let seq = |id| Piece::Sequence { id, type_id: 0 };
+        let result = TemplateProcessing::new(
+            vec![seq(SequenceId::A), seq(SequenceId::B)],
+            vec![seq(SequenceId::A), seq(SequenceId::B)],
+        );
+        assert!(matches!(result, Err(PostProcessError::InvalidTemplate(_))));
+
+        let result = TemplateProcessing::new(vec![seq(SequenceId::A)], vec![seq(SequenceId::A)]);
+        assert!(matches!(result, Err(PostProcessError::InvalidTemplate(_))));
+    }
+
+    #[test]
+    fn test_sequence() {
+        let template = TemplateProcessing::new(
+            vec![
+                Piece::SpecialToken {
+                    ids: vec![1000],
+                    type_id: 0,
+                },
+                Piece::Sequence {
+                    id: SequenceId::A,
+                    type_id: 0,
+                },
+            ],
+            vec![
+                Piece::SpecialToken {
+                    ids: vec![1000],
+                    type_id: 0,
+                },
+                Piece::Sequence {
+                    id: SequenceId::A,
+                    type_id: 0,
+                },
+                Piece::Sequence {
+                    id: SequenceId::B,
+                    type_id: 1,
+                },
+            ],
+        )
+        .unwrap();
+        let processor =
+            Sequence::from_vec(vec![Box::new(ByteLevel::default()), Box::new(template)]);
+
+        let output = processor.process(
+            vec![token_seq(&[1, 2], 0, 0), token_seq(&[3], 4, 1)],
+            &|_| None,
+        );
+        assert_eq!(
+            output,
+            [TokenSequence {
+                ids: vec![1000, 1, 2, 3],
+                offsets: vec![0, 0, 2, 4],
+                type_ids: vec![0, 0, 0, 1],
+                end_offset: 6,
+            }]
+        );
+        assert_eq!(processor.added_tokens(true), 1);
+    }
+
+    #[test]
+    fn test_byte_level() {
+        #[derive(Debug)]
+        struct Case {
+            add_prefix_space: bool,
+            trim_offsets: bool,
+            expected_offsets: Vec<usize>,
+        }
+
+        // Tokens for " hello  world" with a space added to the start.
+        let tokens = ["Ġhello", "Ġ", "Ġworld"];
+        let token_str = |id: u32| tokens.get(id as usize).map(|s| s.to_string());
+        let seq = TokenSequence {
+            ids: vec![0, 1, 2],
+            offsets: vec![0, 5, 6],
+            type_ids: vec![0; 3],
+            end_offset: 12,
+        };
+
+        let cases = [
+            Case {
+                add_prefix_space: true,
+                trim_offsets: true,
+                expected_offsets: vec![0, 6, 7],
+            },
+            Case {
+                add_prefix_space: false,
+                trim_offsets: true,
+                expected_offsets: vec![1, 6, 7],
+            },
+            Case {
+                add_prefix_space: true,
+                trim_offsets: false,
+                expected_offsets: vec![0, 5, 6],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let processor = ByteLevel::new(case.add_prefix_space, case.trim_offsets);
+            let output = processor.process(vec![seq.clone()], &token_str);
+            assert_eq!(output.len(), 1);
+            assert_eq!(output[0].offsets, case.expected_offsets);
+            assert_eq!(output[0].ids, seq.ids);
+
+            let json = processor.to_json(&token_str).unwrap();
+            assert_eq!(json["add_prefix_space"], case.add_prefix_space);
+            assert_eq!(json["trim_offsets"], case.trim_offsets);
+        })
+    }
+
+    #[test]
+    fn test_roberta() {
+        #[derive(Debug)]
+        struct Case {
+            add_prefix_space: bool,
+            trim_offsets: bool,
+            expected_offsets: Vec<usize>,
+        }
+
+        // Tokens for " hello" and " world" with the special tokens `<s>`
+        // and `</s>`.
+        let tokens = ["<s>", "</s>", "Ġhello", "Ġworld"];
+        let token_str = |id: u32| tokens.get(id as usize).map(|s| s.to_string());
+        let seq_a = TokenSequence {
+            ids: vec![2],
+            offsets: vec![0],
+            type_ids: vec![0],
+            end_offset: 6,
+        };
+        let seq_b = TokenSequence {
+            ids: vec![3],
+            offsets: vec![6],
+            type_ids: vec![1],
+            end_offset: 12,
+        };
+
+        let cases = [
+            Case {
+                add_prefix_space: false,
+                trim_offsets: true,
+                expected_offsets: vec![1, 1, 6, 6, 7, 12],
+            },
+            Case {
+                // The first token of each sequence keeps the added space.
+                add_prefix_space: true,
+                trim_offsets: true,
+                expected_offsets: vec![0, 0, 6, 6, 6, 12],
+            },
+            Case {
+                add_prefix_space: false,
+                trim_offsets: false,
+                expected_offsets: vec![0, 0, 6, 6, 6, 12],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let processor = Roberta::new(0, 1, case.add_prefix_space, case.trim_offsets);
+            let output = processor.process(vec![seq_a.clone(), seq_b.clone()], &token_str);
+            assert_eq!(
+                output,
+                [TokenSequence {
+                    ids: vec![0, 2, 1, 1, 3, 1],
+                    offsets: case.expected_offsets.clone(),
+                    type_ids: vec![0; 6],
+                    end_offset: 12,
+                }]
+            );
+            assert_eq!(processor.added_tokens(false), 2);
+            assert_eq!(processor.added_tokens(true), 4);
+
+            let json = processor.to_json(&token_str).unwrap();
+            assert_eq!(json["cls"], serde_json::json!(["<s>", 0]));
+            assert_eq!(json["sep"], serde_json::json!(["</s>", 1]));
+            assert_eq!(json["add_prefix_space"], case.add_prefix_space);
+            assert_eq!(json["trim_offsets"], case.trim_offsets);
+        })
+    }
+}
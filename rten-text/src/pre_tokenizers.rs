@@ -5,8 +5,11 @@ use std::error::Error;
 use std::fmt;
 
 use fancy_regex::Regex;
+use serde_json::json;
 use unicode_categories::UnicodeCategories;
+use unicode_script::{Script, UnicodeScript};
 
+use crate::normalizers::{self, Normalizer};
 use crate::split::{SliceExt, SplitExt};
 
 /// Errors occuring while constructing a [`PreTokenizer`] or splitting input
@@ -42,14 +45,34 @@ impl From<fancy_regex::Error> for PreTokenizeError {
 
 /// A pre-tokenizer splits input text into chunks ("words") which are then
 /// tokenized by a [`Model`](crate::models::Model) individually.
-pub trait PreTokenizer {
+pub trait PreTokenizer: Send + Sync {
     /// Split `text` into chunks and return a vector of sub-slices.
     fn pre_tokenize<'a>(&self, text: &'a str) -> Result<Vec<&'a str>, PreTokenizeError>;
+
+    /// Create a normalizer which transforms text before it is split by
+    /// [`pre_tokenize`](Self::pre_tokenize).
+    ///
+    /// Most pre-tokenizers only split text and return `None`. Pre-tokenizers
+    /// which also modify the text, such as [`Metaspace`], return a
+    /// normalizer that performs the modification.
+    fn normalizer(&self) -> Option<Box<dyn Normalizer>> {
+        None
+    }
+
+    /// Return the configuration of this pre-tokenizer in the format used by
+    /// Hugging Face `tokenizer.json` files, or `None` if it cannot be
+    /// represented in that format.
+    ///
+    /// The default implementation returns `None`.
+    fn to_json(&self) -> Option<serde_json::Value> {
+        None
+    }
 }
 
 /// Split into tokens containing either digits or non-digits.
 pub struct Digits {
     split: Split,
+    individual_digits: bool,
 }
 
 impl Digits {
@@ -71,6 +94,7 @@ impl Digits {
                 delimiter: SplitDelimiterBehavior::Remove,
             })
             .expect("pattern should be valid"),
+            individual_digits,
         }
     }
 }
@@ -79,6 +103,13 @@ impl PreTokenizer for Digits {
     fn pre_tokenize<'a>(&self, text: &'a str) -> Result<Vec<&'a str>, PreTokenizeError> {
         self.split.pre_tokenize(text)
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Digits",
+            "individual_digits": self.individual_digits,
+        }))
+    }
 }
 
 /// Tokenization regex used by GPT-2.
@@ -207,6 +238,87 @@ impl PreTokenizer for Split {
 
         Ok(chunks)
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Split",
+            "pattern": { "Regex": self.regex.as_str() },
+            "behavior": delimiter_json(self.delimiter),
+            "invert": self.invert,
+        }))
+    }
+}
+
+/// Return the `tokenizer.json` name for a delimiter behavior.
+fn delimiter_json(delimiter: SplitDelimiterBehavior) -> &'static str {
+    match delimiter {
+        SplitDelimiterBehavior::Remove => "Removed",
+        SplitDelimiterBehavior::Isolate => "Isolated",
+    }
+}
+
+/// Pre-tokenizer for byte-level BPE models such as GPT-2.
+///
+/// If `use_regex` is true, this splits text using the [`GPT2_REGEX`] pattern,
+/// otherwise text is not split. Mapping bytes to the printable characters
+/// used in the vocabulary is handled by the [`Bpe`](crate::models::Bpe)
+/// model.
+pub struct ByteLevel {
+    split: Option<Split>,
+    add_prefix_space: bool,
+}
+
+impl ByteLevel {
+    pub fn new(use_regex: bool) -> Self {
+        ByteLevel {
+            split: use_regex.then(Split::gpt2),
+            add_prefix_space: false,
+        }
+    }
+
+    /// Set whether a space is added to the start of text which does not
+    /// already start with one. This makes the first word encode the same
+    /// way as words which follow a space. Defaults to false.
+    pub fn with_prefix_space(mut self, add_prefix_space: bool) -> Self {
+        self.add_prefix_space = add_prefix_space;
+        self
+    }
+}
+
+impl Default for ByteLevel {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl PreTokenizer for ByteLevel {
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Result<Vec<&'a str>, PreTokenizeError> {
+        match &self.split {
+            Some(split) => split.pre_tokenize(text),
+            None if text.is_empty() => Ok(Vec::new()),
+            None => Ok(vec![text]),
+        }
+    }
+
+    fn normalizer(&self) -> Option<Box<dyn Normalizer>> {
+        if !self.add_prefix_space {
+            return None;
+        }
+        // Match the start of non-empty strings which don't already start
+        // with a space.
+        let normalizer = normalizers::Replace::new("^(?! |$)", " ".to_string())
+            .expect("pattern should be valid");
+        Some(Box::new(normalizer))
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "ByteLevel",
+            "add_prefix_space": self.add_prefix_space,
+            "trim_offsets": true,
+            "use_regex": self.split.is_some(),
+        }))
+    }
 }
 
 /// Pre-tokenizer that implements the pre-tokenization rules used by BERT.
@@ -234,6 +346,261 @@ impl PreTokenizer for Bert {
         let words = text.split_keep_delimeters(is_punc_or_space).collect();
         Ok(words)
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({ "type": "BertPreTokenizer" }))
+    }
+}
+
+/// Split text into words by matching the pattern `\w+|[^\w\s]+`.
+///
+/// This splits the input into runs of word characters and runs of
+/// punctuation, removing whitespace.
+pub struct Whitespace {
+    split: Split,
+}
+
+impl Whitespace {
+    pub fn new() -> Self {
+        Whitespace {
+            split: Split::new(SplitOptions {
+                pattern: r"\w+|[^\w\s]+",
+                invert: true,
+                delimiter: SplitDelimiterBehavior::Remove,
+            })
+            .expect("pattern should be valid"),
+        }
+    }
+}
+
+impl Default for Whitespace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PreTokenizer for Whitespace {
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Result<Vec<&'a str>, PreTokenizeError> {
+        self.split.pre_tokenize(text)
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({ "type": "Whitespace" }))
+    }
+}
+
+/// Split text on whitespace, removing the whitespace.
+pub struct WhitespaceSplit {}
+
+impl WhitespaceSplit {
+    pub fn new() -> Self {
+        WhitespaceSplit {}
+    }
+}
+
+impl Default for WhitespaceSplit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PreTokenizer for WhitespaceSplit {
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Result<Vec<&'a str>, PreTokenizeError> {
+        Ok(text.split_whitespace().collect())
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({ "type": "WhitespaceSplit" }))
+    }
+}
+
+/// Split text on punctuation characters.
+pub struct Punctuation {
+    delimiter: SplitDelimiterBehavior,
+}
+
+impl Punctuation {
+    /// Create a pre-tokenizer which splits on punctuation characters.
+    ///
+    /// `delimiter` specifies whether each punctuation character is returned
+    /// as a separate chunk or removed.
+    pub fn new(delimiter: SplitDelimiterBehavior) -> Self {
+        Punctuation { delimiter }
+    }
+}
+
+impl Default for Punctuation {
+    fn default() -> Self {
+        Self::new(SplitDelimiterBehavior::Isolate)
+    }
+}
+
+impl PreTokenizer for Punctuation {
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Result<Vec<&'a str>, PreTokenizeError> {
+        let is_punc = |ch: char| ch.is_ascii_punctuation() || ch.is_punctuation();
+        let chunks = text.split_keep_delimeters(is_punc);
+        let chunks = match self.delimiter {
+            SplitDelimiterBehavior::Isolate => chunks.collect(),
+            SplitDelimiterBehavior::Remove => chunks
+                .filter(|chunk| !chunk.chars().next().is_some_and(is_punc))
+                .collect(),
+        };
+        Ok(chunks)
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Punctuation",
+            "behavior": delimiter_json(self.delimiter),
+        }))
+    }
+}
+
+/// Replace spaces with a replacement character (`▁` by default) and split
+/// text into chunks which start with the replacement character.
+///
+/// This is the pre-tokenizer used by SentencePiece-based tokenizers. The
+/// replacement of spaces is performed by the normalizer returned from
+/// [`normalizer`](PreTokenizer::normalizer), which [`Tokenizer`](crate::Tokenizer)
+/// applies before splitting.
+pub struct Metaspace {
+    replacement: char,
+    split: bool,
+    prepend: bool,
+}
+
+impl Metaspace {
+    /// Create a pre-tokenizer which splits text before each occurrence of
+    /// `replacement`. If `split` is false the text is not split.
+    pub fn new(replacement: char, split: bool) -> Self {
+        Metaspace {
+            replacement,
+            split,
+            prepend: true,
+        }
+    }
+
+    /// Set whether the replacement character is added to the start of text
+    /// which does not already start with it. Defaults to true.
+    pub fn with_prepend(mut self, prepend: bool) -> Self {
+        self.prepend = prepend;
+        self
+    }
+}
+
+impl Default for Metaspace {
+    fn default() -> Self {
+        Self::new('▁', true)
+    }
+}
+
+impl PreTokenizer for Metaspace {
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Result<Vec<&'a str>, PreTokenizeError> {
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !self.split {
+            return Ok(vec![text]);
+        }
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for (offset, ch) in text.char_indices() {
+            if ch == self.replacement && offset > start {
+                chunks.push(&text[start..offset]);
+                start = offset;
+            }
+        }
+        chunks.push(&text[start..]);
+
+        Ok(chunks)
+    }
+
+    fn normalizer(&self) -> Option<Box<dyn Normalizer>> {
+        let replacement = self.replacement.to_string();
+        let mut steps: Vec<Box<dyn Normalizer>> = vec![Box::new(
+            normalizers::Replace::new(" ", replacement.clone()).expect("pattern should be valid"),
+        )];
+        if self.prepend {
+            // Match the start of non-empty strings which don't already start
+            // with the replacement.
+            let pattern = format!("^(?!{}|$)", fancy_regex::escape(&replacement));
+            steps.push(Box::new(
+                normalizers::Replace::new(&pattern, replacement).expect("pattern should be valid"),
+            ));
+        }
+        Some(Box::new(normalizers::Sequence::from_vec(steps)))
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "Metaspace",
+            "replacement": self.replacement,
+            "prepend_scheme": if self.prepend { "always" } else { "never" },
+            "split": self.split,
+        }))
+    }
+}
+
+/// Split text where the Unicode script changes.
+///
+/// Spaces are treated as belonging to any script, and Hiragana and Katakana
+/// are treated as part of the Han script, so that Japanese text is not
+/// split.
+pub struct UnicodeScripts {}
+
+impl UnicodeScripts {
+    pub fn new() -> Self {
+        UnicodeScripts {}
+    }
+
+    /// Return the script for a character, or `None` if it can be combined
+    /// with any script.
+    fn script(ch: char) -> Option<Script> {
+        match ch {
+            ' ' => None,
+            // Katakana-Hiragana prolonged sound mark.
+            '\u{30FC}' => Some(Script::Han),
+            _ => match ch.script() {
+                Script::Hiragana | Script::Katakana => Some(Script::Han),
+                script => Some(script),
+            },
+        }
+    }
+}
+
+impl Default for UnicodeScripts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PreTokenizer for UnicodeScripts {
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Result<Vec<&'a str>, PreTokenizeError> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut prev_script = None;
+
+        for (offset, ch) in text.char_indices() {
+            let Some(script) = Self::script(ch) else {
+                continue;
+            };
+            if prev_script.is_some_and(|prev| prev != script) {
+                chunks.push(&text[start..offset]);
+                start = offset;
+            }
+            prev_script = Some(script);
+        }
+        if start < text.len() {
+            chunks.push(&text[start..]);
+        }
+
+        Ok(chunks)
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        Some(json!({ "type": "UnicodeScripts" }))
+    }
 }
 
 /// Compose a sequence of pre-tokenizers.
@@ -260,6 +627,31 @@ impl PreTokenizer for Sequence {
         }
         Ok(chunks)
     }
+
+    fn normalizer(&self) -> Option<Box<dyn Normalizer>> {
+        let normalizers: Vec<_> = self
+            .pre_tokenizers
+            .iter()
+            .filter_map(|pre_tokenizer| pre_tokenizer.normalizer())
+            .collect();
+        if normalizers.is_empty() {
+            None
+        } else {
+            Some(Box::new(normalizers::Sequence::from_vec(normalizers)))
+        }
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        let pre_tokenizers = self
+            .pre_tokenizers
+            .iter()
+            .map(|pre_tokenizer| pre_tokenizer.to_json())
+            .collect::<Option<Vec<_>>>()?;
+        Some(json!({
+            "type": "Sequence",
+            "pretokenizers": pre_tokenizers,
+        }))
+    }
 }
 
 #[cfg(test)]
@@ -267,7 +659,8 @@ mod tests {
     use rten_testing::TestCases;
 
     use super::{
-        Bert, Digits, PreTokenizer, Sequence, Split, SplitDelimiterBehavior, SplitOptions,
+        Bert, ByteLevel, Digits, Metaspace, PreTokenizer, Punctuation, Sequence, Split,
+        SplitDelimiterBehavior, SplitOptions, UnicodeScripts, Whitespace, WhitespaceSplit,
     };
 
     #[test]
@@ -378,6 +771,213 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_whitespace() {
+        let chunks = Whitespace::new()
+            .pre_tokenize("Hey friend!     How are you?!?")
+            .unwrap();
+        assert_eq!(chunks, ["Hey", "friend", "!", "How", "are", "you", "?!?"]);
+
+        let chunks = WhitespaceSplit::new()
+            .pre_tokenize("Hey friend!     How are you?!?")
+            .unwrap();
+        assert_eq!(chunks, ["Hey", "friend!", "How", "are", "you?!?"]);
+    }
+
+    #[test]
+    fn test_punctuation() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            delimiter: SplitDelimiterBehavior,
+            input: &'a str,
+            expected: Vec<&'a str>,
+        }
+
+        let cases = [
+            Case {
+                delimiter: SplitDelimiterBehavior::Isolate,
+                input: "Hey friend!? How are you?",
+                expected: ["Hey friend", "!", "?", " How are you", "?"].into(),
+            },
+            Case {
+                delimiter: SplitDelimiterBehavior::Remove,
+                input: "Hey friend!? How are you?",
+                expected: ["Hey friend", " How are you"].into(),
+            },
+        ];
+
+        cases.test_each(|case| {
+            let punctuation = Punctuation::new(case.delimiter);
+            let chunks = punctuation.pre_tokenize(case.input).unwrap();
+            assert_eq!(chunks, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_metaspace() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            split: bool,
+            input: &'a str,
+            expected: Vec<&'a str>,
+        }
+
+        let cases = [
+            Case {
+                split: true,
+                input: "▁Hey▁▁friend!",
+                expected: ["▁Hey", "▁", "▁friend!"].into(),
+            },
+            Case {
+                split: true,
+                input: "Hey▁friend",
+                expected: ["Hey", "▁friend"].into(),
+            },
+            Case {
+                split: false,
+                input: "▁Hey▁friend",
+                expected: ["▁Hey▁friend"].into(),
+            },
+            Case {
+                split: true,
+                input: "",
+                expected: [].into(),
+            },
+        ];
+
+        cases.test_each(|case| {
+            let metaspace = Metaspace::new('▁', case.split);
+            let chunks = metaspace.pre_tokenize(case.input).unwrap();
+            assert_eq!(chunks, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_metaspace_normalizer() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            prepend: bool,
+            input: &'a str,
+            expected: &'a str,
+        }
+
+        let cases = [
+            Case {
+                prepend: true,
+                input: "Hey friend",
+                expected: "▁Hey▁friend",
+            },
+            Case {
+                prepend: true,
+                input: " Hey",
+                expected: "▁Hey",
+            },
+            Case {
+                prepend: false,
+                input: "Hey friend",
+                expected: "Hey▁friend",
+            },
+            Case {
+                prepend: true,
+                input: "",
+                expected: "",
+            },
+        ];
+
+        cases.test_each(|case| {
+            let metaspace = Metaspace::new('▁', true).with_prepend(case.prepend);
+            let normalizer = metaspace.normalizer().unwrap();
+            let (normalized, _offsets) = normalizer.normalize(case.input).unwrap();
+            assert_eq!(normalized, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_byte_level() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            use_regex: bool,
+            add_prefix_space: bool,
+            input: &'a str,
+            expected: Vec<&'a str>,
+        }
+
+        let cases = [
+            Case {
+                use_regex: true,
+                add_prefix_space: false,
+                input: "Hello world!",
+                expected: ["Hello", " world", "!"].into(),
+            },
+            Case {
+                use_regex: false,
+                add_prefix_space: false,
+                input: "Hello world!\n",
+                expected: ["Hello world!\n"].into(),
+            },
+            Case {
+                use_regex: false,
+                add_prefix_space: false,
+                input: "",
+                expected: [].into(),
+            },
+            Case {
+                use_regex: true,
+                add_prefix_space: true,
+                input: "Hello world!",
+                expected: [" Hello", " world", "!"].into(),
+            },
+            Case {
+                use_regex: true,
+                add_prefix_space: true,
+                input: " Hello",
+                expected: [" Hello"].into(),
+            },
+            Case {
+                use_regex: true,
+                add_prefix_space: true,
+                input: "",
+                expected: [].into(),
+            },
+        ];
+
+        cases.test_each(|case| {
+            let byte_level =
+                ByteLevel::new(case.use_regex).with_prefix_space(case.add_prefix_space);
+            let normalized = match byte_level.normalizer() {
+                Some(normalizer) => normalizer.normalize(case.input).unwrap().0,
+                None => case.input.to_string(),
+            };
+            let chunks = byte_level.pre_tokenize(&normalized).unwrap();
+            assert_eq!(chunks, case.expected);
+        })
+    }
+
+    #[test]
+    fn test_unicode_scripts() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            input: &'a str,
+            expected: Vec<&'a str>,
+        }
+
+        let cases = [
+            Case {
+                input: "どこで生れ。Yes",
+                expected: ["どこで生れ", "。", "Yes"].into(),
+            },
+            Case {
+                input: "Hello world, Привет мир",
+                expected: ["Hello world", ", ", "Привет мир"].into(),
+            },
+        ];
+
+        cases.test_each(|case| {
+            let chunks = UnicodeScripts::new().pre_tokenize(case.input).unwrap();
+            assert_eq!(chunks, case.expected);
+        })
+    }
+
     #[test]
     fn test_sequence() {
         let split_space: Box<dyn PreTokenizer> = Box::new(
@@ -4,7 +4,8 @@
 //!
 //! 1. Load a preconfigured tokenizer from JSON, using [`Tokenizer::from_json`].
 //!    This crate supports a subset of the `tokenizer.json` format that
-//!    Hugging Face Tokenizers generates.
+//!    Hugging Face Tokenizers generates. SentencePiece `.model` files can be
+//!    loaded using [`Tokenizer::from_sentencepiece`].
 //!
 //! 2. Manually configure a [`Tokenizer`] by creating an [`Model`] implementation,
 //!    such as [`WordPiece`] and then wrap it with a tokenizer using
@@ -18,15 +19,34 @@ use std::iter::repeat;
 use std::ops::Range;
 use std::path::Path;
 
+use base64::prelude::*;
+use rayon::prelude::*;
+use serde_json::json;
+
+use crate::decoders::{Decoder, DecoderError};
 use crate::models::{
-    merge_pairs_from_lines, Bpe, BpeError, BpeOptions, DecodeError, EncodeError, Model, WordPiece,
+    merge_pairs_from_lines, Bpe, BpeError, BpeOptions, DecodeError, EncodeError, Model, Unigram,
+    UnigramError, UnigramOptions, WordPiece, WordPieceOptions,
 };
 use crate::normalizers::{NormalizeError, Normalizer};
+use crate::post_processors::{PostProcessError, PostProcessor, TemplateProcessing, TokenSequence};
 use crate::pre_tokenizers::{PreTokenizeError, PreTokenizer};
 use crate::split::SliceExt;
-use crate::{normalizers, pre_tokenizers};
+use crate::{decoders, normalizers, post_processors, pre_tokenizers};
 
+mod added_tokens;
+mod batch;
 mod json;
+mod sentencepiece;
+
+use added_tokens::{AddedVocab, Segment};
+use sentencepiece::PieceType;
+
+pub use added_tokens::AddedToken;
+pub use batch::{
+    BatchEncodeOptions, BatchEncoded, PaddingLength, PaddingOptions, PaddingSide,
+    TruncationOptions, TruncationStrategy,
+};
 
 /// Input sequences for [`Tokenizer::encode`].
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -72,10 +92,8 @@ pub struct Encoded<'a> {
     input: EncoderInput<'a>,
     token_ids: Vec<TokenId>,
 
-    /// Number of tokens in `token_ids` that were generated from the first
-    /// sequence in the input. This includes the `[CLS]` and `[SEP]` tokens
-    /// which come before and after the sequence respectively.
-    first_seq_tokens: usize,
+    /// Token type ID for each token in `token_ids`.
+    type_ids: Vec<usize>,
 
     /// Offsets of text corresponding to tokens in the input string. When the
     /// input contains two sentences, the offsets are relative to the string
@@ -84,17 +102,12 @@ pub struct Encoded<'a> {
 }
 
 impl<'a> Encoded<'a> {
-    fn new(
-        input: EncoderInput<'a>,
-        ids: Vec<TokenId>,
-        offsets: Vec<usize>,
-        first_seq_tokens: usize,
-    ) -> Encoded<'a> {
+    fn new(input: EncoderInput<'a>, seq: TokenSequence) -> Encoded<'a> {
         Encoded {
             input,
-            token_ids: ids,
-            token_offsets: offsets,
-            first_seq_tokens,
+            token_ids: seq.ids,
+            token_offsets: seq.offsets,
+            type_ids: seq.type_ids,
         }
     }
 
@@ -120,11 +133,10 @@ impl<'a> Encoded<'a> {
 
     /// Return an iterator of the inputs for the `token_type_ids` input field
     /// in the model, if it has one.
-    pub fn token_type_ids(&self) -> impl Iterator<Item = usize> {
-        let second_seq_tokens = self.token_ids.len() - self.first_seq_tokens;
-        repeat(0)
-            .take(self.first_seq_tokens)
-            .chain(repeat(1).take(second_seq_tokens))
+    ///
+    /// The type IDs are assigned by the tokenizer's [`PostProcessor`].
+    pub fn token_type_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.type_ids.iter().copied()
     }
 
     /// Return the text from the input sequence(s) that corresponds to a range
@@ -167,6 +179,22 @@ pub struct EncodeOptions {
 
     /// The number of tokens that a chunk will overlap with the previous chunk.
     pub overlap: usize,
+
+    /// Skip the post-processor, so that special tokens such as `[CLS]` and
+    /// `[SEP]` are not added around the encoded sequences.
+    ///
+    /// This is equivalent to `add_special_tokens=False` in the Hugging Face
+    /// tokenizers library. Added tokens which appear in the input text are
+    /// still encoded as their token IDs.
+    pub skip_post_processor: bool,
+}
+
+/// Options that control decoding by [`Tokenizer::decode_with_options`].
+#[derive(Clone, Default)]
+pub struct DecodeOptions {
+    /// Omit added tokens which are marked as
+    /// [`special`](AddedToken::special) from the output.
+    pub skip_special_tokens: bool,
 }
 
 /// Errors returned by [`Tokenizer::from_json`].
@@ -182,6 +210,12 @@ pub enum FromJsonError {
     PreTokenizerError(PreTokenizeError),
     /// There was an error loading a BPE tokenizer.
     BpeError(BpeError),
+    /// There was an error loading a Unigram tokenizer.
+    UnigramError(UnigramError),
+    /// Could not instantiate a post-processor.
+    PostProcessorError(PostProcessError),
+    /// Could not instantiate a decoder.
+    DecoderError(DecoderError),
     /// The model type isn't supported by this crate.
     UnsupportedModel,
 }
@@ -194,6 +228,11 @@ impl fmt::Display for FromJsonError {
             Self::NormalizerError(err) => write!(f, "failed to construct normalizer: {}", err),
             Self::PreTokenizerError(err) => write!(f, "failed to construct pre-tokenizer: {}", err),
             Self::BpeError(err) => write!(f, "BPE tokenizer error: {}", err),
+            Self::UnigramError(err) => write!(f, "Unigram tokenizer error: {}", err),
+            Self::PostProcessorError(err) => {
+                write!(f, "failed to construct post-processor: {}", err)
+            }
+            Self::DecoderError(err) => write!(f, "failed to construct decoder: {}", err),
             Self::UnsupportedModel => write!(f, "unsupported model type"),
         }
     }
@@ -211,6 +250,18 @@ impl From<PreTokenizeError> for FromJsonError {
     }
 }
 
+impl From<PostProcessError> for FromJsonError {
+    fn from(val: PostProcessError) -> Self {
+        FromJsonError::PostProcessorError(val)
+    }
+}
+
+impl From<DecoderError> for FromJsonError {
+    fn from(val: DecoderError) -> Self {
+        FromJsonError::DecoderError(val)
+    }
+}
+
 impl Error for FromJsonError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
@@ -219,12 +270,94 @@ impl Error for FromJsonError {
             Self::NormalizerError(err) => Some(err),
             Self::PreTokenizerError(err) => Some(err),
             Self::BpeError(err) => Some(err),
+            Self::UnigramError(err) => Some(err),
+            Self::PostProcessorError(err) => Some(err),
+            Self::DecoderError(err) => Some(err),
+            Self::UnsupportedModel => None,
+        }
+    }
+}
+
+/// Errors returned by [`Tokenizer::to_json`] and [`Tokenizer::save`].
+#[derive(Debug)]
+pub enum ToJsonError {
+    /// There was an error writing the JSON data to a file.
+    IoError(std::io::Error),
+    /// A component of the tokenizer cannot be represented in the
+    /// `tokenizer.json` format. The value is the kind of component (eg.
+    /// "normalizer").
+    UnsupportedComponent(&'static str),
+}
+
+impl fmt::Display for ToJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IoError(err) => fmt::Display::fmt(err, f),
+            Self::UnsupportedComponent(kind) => {
+                write!(f, "{} does not support serialization", kind)
+            }
+        }
+    }
+}
+
+impl Error for ToJsonError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IoError(err) => Some(err),
+            Self::UnsupportedComponent(_) => None,
+        }
+    }
+}
+
+/// Errors returned by [`Tokenizer::from_sentencepiece`].
+#[derive(Debug)]
+pub enum FromSentencePieceError {
+    /// There was an error reading the model from a file.
+    IoError(std::io::Error),
+    /// The model data is not a valid SentencePiece model.
+    ParseError(String),
+    /// Could not instantiate a normalizer.
+    NormalizerError(NormalizeError),
+    /// There was an error loading a Unigram tokenizer.
+    UnigramError(UnigramError),
+    /// The model type isn't supported by this crate.
+    UnsupportedModel,
+}
+
+impl fmt::Display for FromSentencePieceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IoError(err) => fmt::Display::fmt(err, f),
+            Self::ParseError(err) => write!(f, "failed to parse model: {}", err),
+            Self::NormalizerError(err) => write!(f, "failed to construct normalizer: {}", err),
+            Self::UnigramError(err) => write!(f, "Unigram tokenizer error: {}", err),
+            Self::UnsupportedModel => write!(f, "unsupported model type"),
+        }
+    }
+}
+
+impl From<NormalizeError> for FromSentencePieceError {
+    fn from(val: NormalizeError) -> Self {
+        FromSentencePieceError::NormalizerError(val)
+    }
+}
+
+impl Error for FromSentencePieceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IoError(err) => Some(err),
+            Self::ParseError(_) => None,
+            Self::NormalizerError(err) => Some(err),
+            Self::UnigramError(err) => Some(err),
             Self::UnsupportedModel => None,
         }
     }
 }
 
 /// Configuration for a [`Tokenizer`].
+///
+/// The `cls_token` and `sep_token` options are used to add special tokens
+/// to the output if the tokenizer has no [`PostProcessor`].
 #[derive(Clone, Default)]
 pub struct TokenizerOptions<'a> {
     /// Token added at the start of the output. For BERT models, this is the
@@ -246,7 +379,22 @@ pub struct TokenizerOptions<'a> {
 pub struct Tokenizer {
     normalizer: Option<Box<dyn Normalizer>>,
     pre_tokenizer: Option<Box<dyn PreTokenizer>>,
+
+    /// Normalizer created by the pre-tokenizer using
+    /// [`PreTokenizer::normalizer`]. This is applied to text between added
+    /// tokens before it is split by the pre-tokenizer.
+    pre_tokenizer_normalizer: Option<Box<dyn Normalizer>>,
+
     model: Box<dyn Model>,
+    post_processor: Option<Box<dyn PostProcessor>>,
+
+    /// Tokens which are matched in the input before it is encoded by the
+    /// model.
+    added_tokens: AddedVocab,
+
+    /// Converts token strings to text when decoding. If not set, decoding is
+    /// handled by the model.
+    decoder: Option<Box<dyn Decoder>>,
 
     /// Token added at start of output.
     cls_token: Option<String>,
@@ -261,7 +409,11 @@ impl Tokenizer {
         Tokenizer {
             model: Box::new(model),
             pre_tokenizer: None,
+            pre_tokenizer_normalizer: None,
             normalizer: None,
+            post_processor: None,
+            added_tokens: AddedVocab::default(),
+            decoder: None,
             cls_token: options.cls_token.map(|t| t.to_string()),
             sep_token: options.sep_token.map(|t| t.to_string()),
         }
@@ -270,15 +422,53 @@ impl Tokenizer {
     /// Configure the normalizer used by this tokenizer.
     pub fn with_normalizer(mut self, normalizer: Box<dyn Normalizer>) -> Self {
         self.normalizer = Some(normalizer);
-        self
+
+        // Re-normalize the content of added tokens.
+        let added_tokens = self.added_tokens.tokens().to_vec();
+        self.with_added_tokens(added_tokens)
     }
 
     /// Configure the pre-tokenizer used by this tokenizer.
     pub fn with_pre_tokenizer(mut self, pre_tokenizer: Box<dyn PreTokenizer>) -> Self {
+        self.pre_tokenizer_normalizer = pre_tokenizer.normalizer();
         self.pre_tokenizer = Some(pre_tokenizer);
         self
     }
 
+    /// Add tokens which are matched in the input text before it is normalized,
+    /// pre-tokenized and encoded by the model.
+    ///
+    /// This replaces any previously added tokens.
+    pub fn with_added_tokens(mut self, tokens: Vec<AddedToken>) -> Self {
+        self.added_tokens = AddedVocab::new(tokens, self.normalizer.as_deref());
+        self
+    }
+
+    /// Return the tokens added with [`with_added_tokens`](Self::with_added_tokens).
+    pub fn added_tokens(&self) -> &[AddedToken] {
+        self.added_tokens.tokens()
+    }
+
+    /// Configure the post-processor used by this tokenizer.
+    ///
+    /// This replaces the `cls_token` and `sep_token` specified in the
+    /// [`TokenizerOptions`].
+    pub fn with_post_processor(mut self, post_processor: Box<dyn PostProcessor>) -> Self {
+        self.post_processor = Some(post_processor);
+        self
+    }
+
+    /// Configure the decoder used by this tokenizer.
+    ///
+    /// When a decoder is set, [`decode`](Self::decode) converts token IDs to
+    /// their string representations using [`Model::get_token_str`] and then
+    /// transforms them using the decoder, instead of using
+    /// [`Model::decode`].
+    pub fn with_decoder(mut self, decoder: Box<dyn Decoder>) -> Self {
+        self.decoder = Some(decoder);
+        self
+    }
+
     /// Load a tokenizer from the contents of a Hugging Face `tokenizer.json`
     /// file.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Tokenizer, FromJsonError> {
@@ -311,16 +501,28 @@ impl Tokenizer {
                         strip_accents: bert_norm.strip_accents.unwrap_or(bert_norm.lowercase),
                     }))
                 }
-                json::Normalizer::Lowercase => {
-                    Box::new(normalizers::Bert::new(normalizers::BertOptions {
-                        lowercase: true,
-                        strip_accents: false,
-                    }))
-                }
+                json::Normalizer::Lowercase => Box::new(normalizers::Lowercase::new()),
                 json::Normalizer::Nfc => Box::new(normalizers::Unicode::Nfc),
                 json::Normalizer::Nfd => Box::new(normalizers::Unicode::Nfd),
                 json::Normalizer::Nfkc => Box::new(normalizers::Unicode::Nfkc),
                 json::Normalizer::Nfkd => Box::new(normalizers::Unicode::Nfkd),
+                json::Normalizer::Precompiled(precompiled) => {
+                    match precompiled
+                        .precompiled_charsmap
+                        .filter(|map| !map.is_empty())
+                    {
+                        Some(charsmap) => {
+                            let charsmap = BASE64_STANDARD
+                                .decode(charsmap)
+                                .map_err(|_| NormalizeError::InvalidCharsMap)?;
+                            Box::new(normalizers::Precompiled::new(&charsmap)?)
+                        }
+                        None => Box::new(normalizers::Sequence::from_vec(Vec::new())),
+                    }
+                }
+                json::Normalizer::Prepend(prepend) => {
+                    Box::new(normalizers::Prepend::new(prepend.prepend))
+                }
                 json::Normalizer::Replace(replace) => {
                     let pattern = regex_pattern(&replace.pattern);
                     Box::new(normalizers::Replace::new(&pattern, replace.content)?)
@@ -333,6 +535,10 @@ impl Tokenizer {
                         .collect::<Result<Vec<_>, _>>()?;
                     Box::new(normalizers::Sequence::from_vec(normalizers))
                 }
+                json::Normalizer::Strip(strip) => {
+                    Box::new(normalizers::Strip::new(strip.strip_left, strip.strip_right))
+                }
+                json::Normalizer::StripAccents => Box::new(normalizers::StripAccents::new()),
             };
             Ok(normalizer)
         }
@@ -340,26 +546,44 @@ impl Tokenizer {
         let normalizer: Option<Box<dyn Normalizer>> =
             json.normalizer.map(create_normalizer).transpose()?;
 
+        fn split_delimiter(
+            delimiter: json::pre_tokenizers::SplitDelimiter,
+        ) -> pre_tokenizers::SplitDelimiterBehavior {
+            match delimiter {
+                json::pre_tokenizers::SplitDelimiter::Isolated => {
+                    pre_tokenizers::SplitDelimiterBehavior::Isolate
+                }
+                json::pre_tokenizers::SplitDelimiter::Removed => {
+                    pre_tokenizers::SplitDelimiterBehavior::Remove
+                }
+            }
+        }
+
         fn create_pre_tokenizer(
             config: json::PreTokenizer,
         ) -> Result<Box<dyn PreTokenizer>, FromJsonError> {
             let pre_tokenizer: Box<dyn PreTokenizer> = match config {
                 json::PreTokenizer::Bert => Box::new(pre_tokenizers::Bert::new()),
-                json::PreTokenizer::ByteLevel(byte_level) => {
-                    if byte_level.use_regex {
-                        Box::new(pre_tokenizers::Split::gpt2())
-                    } else {
-                        let noop_split = pre_tokenizers::SplitOptions {
-                            pattern: r".*",
-                            invert: true,
-                            ..Default::default()
-                        };
-                        Box::new(pre_tokenizers::Split::new(noop_split)?)
-                    }
-                }
+                json::PreTokenizer::ByteLevel(byte_level) => Box::new(
+                    pre_tokenizers::ByteLevel::new(byte_level.use_regex)
+                        .with_prefix_space(byte_level.add_prefix_space),
+                ),
                 json::PreTokenizer::Digits(digits) => {
                     Box::new(pre_tokenizers::Digits::new(digits.individual_digits))
                 }
+                json::PreTokenizer::Metaspace(metaspace) => {
+                    let prepend = match metaspace.prepend_scheme {
+                        Some(scheme) => scheme != json::PrependScheme::Never,
+                        None => metaspace.add_prefix_space.unwrap_or(true),
+                    };
+                    Box::new(
+                        pre_tokenizers::Metaspace::new(metaspace.replacement, metaspace.split)
+                            .with_prepend(prepend),
+                    )
+                }
+                json::PreTokenizer::Punctuation(punctuation) => Box::new(
+                    pre_tokenizers::Punctuation::new(split_delimiter(punctuation.behavior)),
+                ),
                 json::PreTokenizer::Sequence(seq) => {
                     let pre_tokenizers = seq
                         .pretokenizers
@@ -373,17 +597,17 @@ impl Tokenizer {
                     let opts = pre_tokenizers::SplitOptions {
                         pattern: &pattern,
                         invert: split.invert,
-                        delimiter: match split.behavior {
-                            json::pre_tokenizers::SplitDelimiter::Isolated => {
-                                pre_tokenizers::SplitDelimiterBehavior::Isolate
-                            }
-                            json::pre_tokenizers::SplitDelimiter::Removed => {
-                                pre_tokenizers::SplitDelimiterBehavior::Remove
-                            }
-                        },
+                        delimiter: split_delimiter(split.behavior),
                     };
                     Box::new(pre_tokenizers::Split::new(opts)?)
                 }
+                json::PreTokenizer::UnicodeScripts => {
+                    Box::new(pre_tokenizers::UnicodeScripts::new())
+                }
+                json::PreTokenizer::Whitespace => Box::new(pre_tokenizers::Whitespace::new()),
+                json::PreTokenizer::WhitespaceSplit => {
+                    Box::new(pre_tokenizers::WhitespaceSplit::new())
+                }
             };
             Ok(pre_tokenizer)
         }
@@ -391,6 +615,123 @@ impl Tokenizer {
         let pre_tokenizer: Option<Box<dyn PreTokenizer>> =
             json.pre_tokenizer.map(create_pre_tokenizer).transpose()?;
 
+        fn create_post_processor(
+            config: json::PostProcessor,
+        ) -> Result<Box<dyn PostProcessor>, FromJsonError> {
+            let post_processor: Box<dyn PostProcessor> = match config {
+                json::PostProcessor::TemplateProcessing(template) => {
+                    let create_template = |pieces: Vec<json::post_processors::TemplatePiece>| {
+                        pieces
+                            .into_iter()
+                            .map(|piece| match piece {
+                                json::post_processors::TemplatePiece::Sequence { id, type_id } => {
+                                    let id = match id {
+                                        json::post_processors::SequenceId::A => {
+                                            post_processors::SequenceId::A
+                                        }
+                                        json::post_processors::SequenceId::B => {
+                                            post_processors::SequenceId::B
+                                        }
+                                    };
+                                    Ok(post_processors::Piece::Sequence { id, type_id })
+                                }
+                                json::post_processors::TemplatePiece::SpecialToken {
+                                    id,
+                                    type_id,
+                                } => {
+                                    let ids = template
+                                        .special_tokens
+                                        .get(&id)
+                                        .map(|tok| tok.ids.clone())
+                                        .ok_or_else(|| {
+                                            PostProcessError::InvalidTemplate(format!(
+                                                "undefined special token \"{}\"",
+                                                id
+                                            ))
+                                        })?;
+                                    Ok(post_processors::Piece::SpecialToken { ids, type_id })
+                                }
+                            })
+                            .collect::<Result<Vec<_>, PostProcessError>>()
+                    };
+                    let single = create_template(template.single)?;
+                    let pair = create_template(template.pair)?;
+                    Box::new(TemplateProcessing::new(single, pair)?)
+                }
+                json::PostProcessor::Bert(bert) => {
+                    Box::new(TemplateProcessing::bert(Some(bert.cls.1), Some(bert.sep.1)))
+                }
+                json::PostProcessor::Roberta(roberta) => Box::new(post_processors::Roberta::new(
+                    roberta.cls.1,
+                    roberta.sep.1,
+                    roberta.add_prefix_space,
+                    roberta.trim_offsets,
+                )),
+                json::PostProcessor::ByteLevel(byte_level) => {
+                    Box::new(post_processors::ByteLevel::new(
+                        byte_level.add_prefix_space,
+                        byte_level.trim_offsets,
+                    ))
+                }
+                json::PostProcessor::Sequence(seq) => {
+                    let processors = seq
+                        .processors
+                        .into_iter()
+                        .map(create_post_processor)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Box::new(post_processors::Sequence::from_vec(processors))
+                }
+            };
+            Ok(post_processor)
+        }
+
+        let post_processor: Option<Box<dyn PostProcessor>> =
+            json.post_processor.map(create_post_processor).transpose()?;
+
+        fn create_decoder(config: json::Decoder) -> Result<Box<dyn Decoder>, FromJsonError> {
+            let decoder: Box<dyn Decoder> = match config {
+                json::Decoder::ByteLevel(byte_level) => Box::new(decoders::ByteLevel::new(
+                    byte_level.add_prefix_space,
+                    byte_level.trim_offsets,
+                    byte_level.use_regex,
+                )),
+                json::Decoder::Metaspace(metaspace) => {
+                    let strip_prefix_space = match metaspace.prepend_scheme {
+                        Some(scheme) => scheme != json::PrependScheme::Never,
+                        None => metaspace.add_prefix_space.unwrap_or(true),
+                    };
+                    Box::new(
+                        decoders::Metaspace::new(metaspace.replacement, strip_prefix_space)
+                            .with_split(metaspace.split),
+                    )
+                }
+                json::Decoder::WordPiece(wordpiece) => Box::new(decoders::WordPiece::new(
+                    wordpiece.prefix,
+                    wordpiece.cleanup,
+                )),
+                json::Decoder::ByteFallback => Box::new(decoders::ByteFallback::new()),
+                json::Decoder::Fuse => Box::new(decoders::Fuse::new()),
+                json::Decoder::Strip(strip) => {
+                    Box::new(decoders::Strip::new(strip.content, strip.start, strip.stop))
+                }
+                json::Decoder::Replace(replace) => {
+                    let pattern = regex_pattern(&replace.pattern);
+                    Box::new(decoders::Replace::new(&pattern, replace.content)?)
+                }
+                json::Decoder::Sequence(seq) => {
+                    let decoders = seq
+                        .decoders
+                        .into_iter()
+                        .map(create_decoder)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Box::new(decoders::Sequence::from_vec(decoders))
+                }
+            };
+            Ok(decoder)
+        }
+
+        let decoder: Option<Box<dyn Decoder>> = json.decoder.map(create_decoder).transpose()?;
+
         let mut tokenizer = match json.model {
             json::Model::Bpe(model) => {
                 let added_tokens: HashMap<TokenId, String> = json
@@ -428,8 +769,27 @@ impl Tokenizer {
 
                 Ok::<_, FromJsonError>(tokenizer)
             }
+            json::Model::Unigram(model) => {
+                let model = Unigram::from_vocab(
+                    model.vocab,
+                    UnigramOptions {
+                        unk_id: model.unk_id,
+                        byte_fallback: model.byte_fallback,
+                        ..Default::default()
+                    },
+                )
+                .map_err(FromJsonError::UnigramError)?;
+                let tokenizer = Tokenizer::new(model, Default::default());
+
+                Ok::<_, FromJsonError>(tokenizer)
+            }
             json::Model::WordPiece(model) => {
-                let model = WordPiece::from_vocab(model.vocab, Default::default());
+                let model = WordPiece::from_vocab(
+                    model.vocab,
+                    WordPieceOptions {
+                        max_word_len: model.max_input_chars_per_word,
+                    },
+                );
                 let tokenizer = Tokenizer::new(
                     model,
                     TokenizerOptions {
@@ -450,6 +810,218 @@ impl Tokenizer {
             tokenizer = tokenizer.with_pre_tokenizer(pre_tokenizer);
         }
 
+        if let Some(post_processor) = post_processor {
+            tokenizer = tokenizer.with_post_processor(post_processor);
+        }
+
+        if let Some(decoder) = decoder {
+            tokenizer = tokenizer.with_decoder(decoder);
+        }
+
+        if let Some(added_tokens) = json.added_tokens {
+            let added_tokens = added_tokens
+                .into_iter()
+                .map(|token| AddedToken {
+                    id: token.id,
+                    content: token.content,
+                    single_word: token.single_word,
+                    lstrip: token.lstrip,
+                    rstrip: token.rstrip,
+                    normalized: token.normalized,
+                    special: token.special,
+                })
+                .collect();
+            tokenizer = tokenizer.with_added_tokens(added_tokens);
+        }
+
+        Ok(tokenizer)
+    }
+
+    /// Save this tokenizer to a Hugging Face `tokenizer.json` file.
+    ///
+    /// See [`to_json`](Self::to_json).
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ToJsonError> {
+        let json = self.to_json()?;
+        std::fs::write(path, json).map_err(ToJsonError::IoError)
+    }
+
+    /// Serialize this tokenizer in the Hugging Face `tokenizer.json` format.
+    ///
+    /// The output can be loaded using [`from_json`](Self::from_json). This
+    /// fails if a component of the tokenizer does not support serialization
+    /// (see [`Normalizer::to_json`] and the equivalent methods of other
+    /// component traits).
+    pub fn to_json(&self) -> Result<String, ToJsonError> {
+        let unsupported = ToJsonError::UnsupportedComponent;
+
+        let added_tokens: Vec<_> = self
+            .added_tokens
+            .tokens()
+            .iter()
+            .map(|token| {
+                json!({
+                    "id": token.id,
+                    "content": token.content,
+                    "single_word": token.single_word,
+                    "lstrip": token.lstrip,
+                    "rstrip": token.rstrip,
+                    "normalized": token.normalized,
+                    "special": token.special,
+                })
+            })
+            .collect();
+
+        let normalizer = self
+            .normalizer
+            .as_ref()
+            .map(|normalizer| normalizer.to_json().ok_or(unsupported("normalizer")))
+            .transpose()?;
+        let pre_tokenizer = self
+            .pre_tokenizer
+            .as_ref()
+            .map(|pre_tokenizer| pre_tokenizer.to_json().ok_or(unsupported("pre-tokenizer")))
+            .transpose()?;
+
+        let token_str = |id| self.token_str(id);
+        let post_processor = match &self.post_processor {
+            Some(post_processor) => Some(post_processor.to_json(&token_str)),
+            None if self.cls_token.is_none() && self.sep_token.is_none() => None,
+            None => Some(
+                self.default_post_processor()
+                    .ok()
+                    .and_then(|post_processor| post_processor.to_json(&token_str)),
+            ),
+        }
+        .map(|post_processor| post_processor.ok_or(unsupported("post-processor")))
+        .transpose()?;
+
+        let decoder = self
+            .decoder
+            .as_ref()
+            .map(|decoder| decoder.to_json().ok_or(unsupported("decoder")))
+            .transpose()?;
+        let model = self.model.to_json().ok_or(unsupported("model"))?;
+
+        let json = json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": added_tokens,
+            "normalizer": normalizer,
+            "pre_tokenizer": pre_tokenizer,
+            "post_processor": post_processor,
+            "decoder": decoder,
+            "model": model,
+        });
+        Ok(serde_json::to_string_pretty(&json).expect("JSON value should serialize"))
+    }
+
+    /// Load a tokenizer from a SentencePiece `.model` file.
+    pub fn from_sentencepiece_file<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<Tokenizer, FromSentencePieceError> {
+        let data = std::fs::read(path).map_err(FromSentencePieceError::IoError)?;
+        Self::from_sentencepiece(&data)
+    }
+
+    /// Load a tokenizer from the contents of a SentencePiece `.model` file.
+    ///
+    /// Only Unigram models are currently supported. Normalization (the
+    /// precompiled Unicode normalization rules, adding a dummy prefix,
+    /// removing extra whitespace and replacing spaces with `▁`) follows the
+    /// model's normalization settings.
+    pub fn from_sentencepiece(data: &[u8]) -> Result<Tokenizer, FromSentencePieceError> {
+        let proto = sentencepiece::from_bytes(data)
+            .map_err(|err| FromSentencePieceError::ParseError(err.to_string()))?;
+        if proto.trainer_spec.model_type != sentencepiece::ModelType::Unigram {
+            return Err(FromSentencePieceError::UnsupportedModel);
+        }
+
+        let unk_id = proto
+            .pieces
+            .iter()
+            .position(|piece| piece.kind == PieceType::Unknown)
+            .map(|id| id as TokenId);
+
+        // Only normal and user-defined pieces are matched when segmenting
+        // text. Control pieces (eg. `<s>`, `</s>`) are matched as special
+        // tokens instead.
+        let mut excluded_ids = Vec::new();
+        let mut special_tokens = Vec::new();
+        for (id, piece) in proto.pieces.iter().enumerate() {
+            let id = id as TokenId;
+            match piece.kind {
+                PieceType::Normal | PieceType::UserDefined => {}
+                PieceType::Control => {
+                    excluded_ids.push(id);
+                    special_tokens.push(AddedToken::special(id, piece.piece.as_str()));
+                }
+                PieceType::Unknown | PieceType::Unused | PieceType::Byte => {
+                    excluded_ids.push(id);
+                }
+            }
+        }
+
+        let vocab = proto
+            .pieces
+            .into_iter()
+            .map(|piece| (piece.piece, piece.score))
+            .collect();
+        let model = Unigram::from_vocab(
+            vocab,
+            UnigramOptions {
+                unk_id,
+                byte_fallback: proto.trainer_spec.byte_fallback,
+                excluded_ids,
+                ..Default::default()
+            },
+        )
+        .map_err(FromSentencePieceError::UnigramError)?;
+
+        let spec = &proto.normalizer_spec;
+        let mut normalizers: Vec<Box<dyn Normalizer>> = Vec::new();
+        if !spec.precompiled_charsmap.is_empty() {
+            normalizers.push(Box::new(normalizers::Precompiled::new(
+                &spec.precompiled_charsmap,
+            )?));
+        }
+        if spec.remove_extra_whitespaces {
+            normalizers.push(Box::new(normalizers::Replace::new(
+                "^ +| +$",
+                String::new(),
+            )?));
+            normalizers.push(Box::new(normalizers::Replace::new(
+                " {2,}",
+                " ".to_string(),
+            )?));
+        }
+        if spec.add_dummy_prefix {
+            // Match the start of non-empty strings.
+            normalizers.push(Box::new(normalizers::Replace::new(
+                "^(?!$)",
+                " ".to_string(),
+            )?));
+        }
+        if spec.escape_whitespaces {
+            normalizers.push(Box::new(normalizers::Replace::new(" ", "▁".to_string())?));
+        }
+
+        let mut decoders: Vec<Box<dyn Decoder>> = Vec::new();
+        if proto.trainer_spec.byte_fallback {
+            decoders.push(Box::new(decoders::ByteFallback::new()));
+        }
+        decoders.push(Box::new(decoders::Fuse::new()));
+        if spec.escape_whitespaces {
+            decoders.push(Box::new(decoders::Metaspace::new(
+                '▁',
+                spec.add_dummy_prefix,
+            )));
+        }
+
+        let tokenizer = Tokenizer::new(model, Default::default())
+            .with_normalizer(Box::new(normalizers::Sequence::from_vec(normalizers)))
+            .with_decoder(Box::new(decoders::Sequence::from_vec(decoders)))
+            .with_added_tokens(special_tokens);
         Ok(tokenizer)
     }
 
@@ -470,8 +1042,9 @@ impl Tokenizer {
     /// This wraps [`Model::get_token_id`] but returns a `Result` rather than
     /// an `Option`, assuming the token is expected to be valid.
     pub fn get_token_id(&self, text: &str) -> Result<TokenId, TokenizerError> {
-        self.model
-            .get_token_id(text)
+        self.added_tokens
+            .get_id(text)
+            .or_else(|| self.model.get_token_id(text))
             .ok_or(TokenizerError::EncodeError(EncodeError::TokenIdNotFound(
                 text.to_string(),
             )))
@@ -491,6 +1064,37 @@ impl Tokenizer {
             .transpose()
     }
 
+    /// Return the post-processor used when none has been configured with
+    /// [`with_post_processor`](Tokenizer::with_post_processor). This adds the
+    /// `cls_token` and `sep_token` from the [`TokenizerOptions`].
+    fn default_post_processor(&self) -> Result<TemplateProcessing, TokenizerError> {
+        Ok(TemplateProcessing::bert(
+            self.cls_token()?,
+            self.sep_token()?,
+        ))
+    }
+
+    /// Apply the post-processor to encoded sequences and concatenate the
+    /// results.
+    /// Return the string representation of a token ID, for added tokens or
+    /// tokens in the model's vocabulary.
+    fn token_str(&self, id: TokenId) -> Option<String> {
+        self.added_tokens
+            .get(id)
+            .map(|token| token.content.clone())
+            .or_else(|| self.model.get_token_str(id))
+    }
+
+    fn post_process(&self, sequences: Vec<TokenSequence>) -> Result<TokenSequence, TokenizerError> {
+        let output = match &self.post_processor {
+            Some(post_processor) => post_processor.process(sequences, &|id| self.token_str(id)),
+            None => self
+                .default_post_processor()?
+                .process(sequences, &|id| self.token_str(id)),
+        };
+        Ok(TokenSequence::concat(&output))
+    }
+
     /// Encode one or two sequences into a sequence of tokens.
     ///
     /// The input can be an `&str` or tuple of `(&str, &str)`.
@@ -503,41 +1107,32 @@ impl Tokenizer {
         options: Option<EncodeOptions>,
     ) -> Result<Encoded<'a>, TokenizerError> {
         let options = options.unwrap_or_default();
+        let skip_post_processor = options.skip_post_processor;
         let input: EncoderInput = input.into();
 
-        let cls_token = self.cls_token()?;
-        let sep_token = self.sep_token()?;
-
         // To simplify the implementation, we tokenize the whole input and
         // just discard all chunks except the first. This could be optimized
         // to only generate one chunk.
         let chunks = self.encode_chunks(input, options)?;
 
-        let chunk = chunks.into_iter().next().unwrap_or_else(|| {
-            // If the input is empty after tokenization, generate a single
-            // empty chunk.
-            let mut tokens = Vec::new();
-            let mut offsets = Vec::new();
-            let mut first_seq_tokens = 0;
-
-            if let Some(cls_token) = cls_token {
-                tokens.push(cls_token);
-                offsets.push(0);
-                first_seq_tokens += 1;
-            }
-            if let Some(sep_token) = sep_token {
-                tokens.push(sep_token);
-                offsets.push(0);
-                first_seq_tokens += 1;
-
-                if matches!(input, EncoderInput::Pair(_)) {
-                    tokens.push(sep_token);
-                    offsets.push(0);
-                }
+        let chunk = match chunks.into_iter().next() {
+            Some(chunk) => chunk,
+            None => {
+                // If the input is empty after tokenization, generate a single
+                // empty chunk.
+                let n_sequences = match input {
+                    EncoderInput::Item(_) => 1,
+                    EncoderInput::Pair(_) => 2,
+                };
+                let sequences = vec![TokenSequence::default(); n_sequences];
+                let output = if skip_post_processor {
+                    TokenSequence::concat(&sequences)
+                } else {
+                    self.post_process(sequences)?
+                };
+                Encoded::new(input, output)
             }
-
-            Encoded::new(input, tokens, offsets, first_seq_tokens)
-        });
+        };
 
         Ok(chunk)
     }
@@ -549,6 +1144,38 @@ impl Tokenizer {
         text: &str,
         start_offset: usize,
     ) -> Result<(Vec<TokenId>, Vec<usize>), TokenizerError> {
+        let mut tokens = Vec::new();
+        let mut offsets = Vec::new();
+
+        // Match added tokens which are not normalized first, then encode the
+        // text between them.
+        for segment in self.added_tokens.split(text, false /* normalized */) {
+            match segment {
+                Segment::Token { id, offset } => {
+                    tokens.push(id);
+                    offsets.push(start_offset + offset);
+                }
+                Segment::Text(range) => self.encode_text(
+                    &text[range.clone()],
+                    start_offset + range.start,
+                    &mut tokens,
+                    &mut offsets,
+                )?,
+            }
+        }
+
+        Ok((tokens, offsets))
+    }
+
+    /// Normalize, pre-tokenize and encode a string which does not contain
+    /// any un-normalized added tokens.
+    fn encode_text(
+        &self,
+        text: &str,
+        start_offset: usize,
+        tokens: &mut Vec<TokenId>,
+        offsets: &mut Vec<usize>,
+    ) -> Result<(), TokenizerError> {
         let (normalized, offset_map) = match &self.normalizer {
             None => (text.to_string(), None),
             Some(normalizer) => {
@@ -557,14 +1184,6 @@ impl Tokenizer {
             }
         };
 
-        let chunks = self
-            .pre_tokenizer
-            .as_ref()
-            .map(|pt| pt.pre_tokenize(&normalized))
-            .transpose()
-            .map_err(TokenizerError::PreTokenizeError)?
-            .unwrap_or(Vec::from([normalized.as_str()]));
-
         // Map an offset into the normalized string into an offset in the source
         // string.
         let map_offset = |offset: usize| {
@@ -578,23 +1197,53 @@ impl Tokenizer {
             }
         };
 
-        let mut tokens = Vec::new();
-        let mut offsets = Vec::new();
+        for segment in self.added_tokens.split(&normalized, true /* normalized */) {
+            let range = match segment {
+                Segment::Token { id, offset } => {
+                    tokens.push(id);
+                    offsets.push(start_offset + map_offset(offset));
+                    continue;
+                }
+                Segment::Text(range) => range,
+            };
 
-        for chunk in chunks {
-            let base_offset = normalized
-                .as_bytes()
-                .subslice_offsets(chunk.as_bytes())
-                .expect("should be a subslice")
-                .start;
-            self.model
-                .encode_with_offsets(chunk, &mut |offset, token| {
-                    offsets.push(start_offset + base_offset + map_offset(offset));
-                    tokens.push(token);
-                })?;
+            let range_start = range.start;
+            let (segment, segment_offsets) = match &self.pre_tokenizer_normalizer {
+                Some(normalizer) => {
+                    let (segment, offsets) = normalizer.normalize(&normalized[range])?;
+                    (Cow::Owned(segment), Some(offsets))
+                }
+                None => (Cow::Borrowed(&normalized[range]), None),
+            };
+
+            let chunks = self
+                .pre_tokenizer
+                .as_ref()
+                .map(|pt| pt.pre_tokenize(&segment))
+                .transpose()
+                .map_err(TokenizerError::PreTokenizeError)?
+                .unwrap_or(Vec::from([segment.as_ref()]));
+
+            for chunk in chunks {
+                let base_offset = segment
+                    .as_bytes()
+                    .subslice_offsets(chunk.as_bytes())
+                    .expect("should be a subslice")
+                    .start;
+                self.model
+                    .encode_with_offsets(chunk, &mut |offset, token| {
+                        let segment_offset = base_offset + offset;
+                        let segment_offset = segment_offsets
+                            .as_ref()
+                            .map(|offsets| offsets[segment_offset])
+                            .unwrap_or(segment_offset);
+                        offsets.push(start_offset + map_offset(range_start + segment_offset));
+                        tokens.push(token);
+                    })?;
+            }
         }
 
-        Ok((tokens, offsets))
+        Ok(())
     }
 
     /// Encode one or two sequences into a sequence of tokens.
@@ -606,18 +1255,23 @@ impl Tokenizer {
         input: EncoderInput<'a>,
         options: EncodeOptions,
     ) -> Result<Vec<Encoded<'a>>, TokenizerError> {
-        let cls_token = self.cls_token()?;
-        let sep_token = self.sep_token()?;
-
-        let has_cls = cls_token.is_some() as usize;
-        let has_sep = sep_token.is_some() as usize;
+        let default_post_processor;
+        let post_processor: &dyn PostProcessor = match &self.post_processor {
+            // A template which passes sequences through unchanged.
+            _ if options.skip_post_processor => {
+                default_post_processor = TemplateProcessing::bert(None, None);
+                &default_post_processor
+            }
+            Some(post_processor) => post_processor.as_ref(),
+            None => {
+                default_post_processor = self.default_post_processor()?;
+                &default_post_processor
+            }
+        };
 
         // Number of non-content tokens added to each chunk.
-        let non_content_tokens_per_chunk = has_cls
-            + match input {
-                EncoderInput::Item(_) => has_sep,     // [CLS] .. [SEP]
-                EncoderInput::Pair(_) => has_sep * 2, // [CLS] .. [SEP] .. [SEP]
-            };
+        let non_content_tokens_per_chunk =
+            post_processor.added_tokens(matches!(input, EncoderInput::Pair(_)));
 
         // Encode the full input sequences.
         let mut tokens = Vec::new();
@@ -663,34 +1317,25 @@ impl Tokenizer {
                     .zip(offsets.chunks_with_overlap(max_tokens_per_chunk, options.overlap))
                     .enumerate()
                 {
-                    let mut tokens = Vec::new();
-                    let mut offsets = Vec::new();
-
-                    if let Some(cls_token) = cls_token {
-                        tokens.push(cls_token);
-                        offsets.push(offsets_chunk.first().copied().unwrap());
-                    }
-
-                    tokens.extend_from_slice(tokens_chunk);
-                    offsets.extend_from_slice(offsets_chunk);
-
-                    if let Some(sep_token) = sep_token {
-                        tokens.push(sep_token);
-                    }
-
-                    // The offset for the final token is the offset of the first
-                    // token in the next chunk, or the input length if this
-                    // is the final chunk.
+                    // The end offset is the offset of the first token in the
+                    // next chunk, or the input length if this is the final
+                    // chunk.
                     let chunk_start = chunk_idx * max_tokens_per_chunk;
-                    offsets.push(
-                        all_offsets
-                            .get(chunk_start + offsets_chunk.len())
-                            .copied()
-                            .unwrap_or(item.len()),
+                    let end_offset = all_offsets
+                        .get(chunk_start + offsets_chunk.len())
+                        .copied()
+                        .unwrap_or(item.len());
+
+                    let seq = TokenSequence {
+                        ids: tokens_chunk.to_vec(),
+                        offsets: offsets_chunk.to_vec(),
+                        type_ids: vec![0; tokens_chunk.len()],
+                        end_offset,
+                    };
+                    let output = TokenSequence::concat(
+                        &post_processor.process(vec![seq], &|id| self.token_str(id)),
                     );
-
-                    let n_tokens = tokens.len();
-                    chunks.push(Encoded::new(input, tokens, offsets, n_tokens));
+                    chunks.push(Encoded::new(input, output));
                 }
             }
 
@@ -716,44 +1361,34 @@ impl Tokenizer {
                     .zip(second_offsets.chunks_with_overlap(second_len, options.overlap))
                     .enumerate()
                 {
-                    let mut tokens = Vec::new();
-                    let mut offsets = Vec::new();
-
-                    // Add the first sequence. This is the same for every chunk.
-                    if let Some(cls_token) = cls_token {
-                        tokens.push(cls_token);
-                        offsets.push(0);
-                    }
-
-                    tokens.extend_from_slice(&first_tokens[..first_len]);
-                    offsets.extend_from_slice(&first_offsets[..first_len]);
-
-                    if let Some(sep_token) = sep_token {
-                        tokens.push(sep_token);
-                        offsets.push(first.len());
-                    }
-
-                    let first_seq_len = tokens.len();
-
-                    // Add the second sequence, which changes in each chunk.
-                    tokens.extend_from_slice(tokens_chunk);
-                    offsets.extend_from_slice(offsets_chunk);
+                    // The first sequence is the same for every chunk.
+                    let first_seq = TokenSequence {
+                        ids: first_tokens[..first_len].to_vec(),
+                        offsets: first_offsets[..first_len].to_vec(),
+                        type_ids: vec![0; first_len],
+                        end_offset: first.len(),
+                    };
 
-                    // The offset for the final token is the offset of the first
-                    // token from the second sequence in the next chunk, or
-                    // the concatenated input length if this is the final chunk.
-                    if let Some(sep_token) = sep_token {
-                        tokens.push(sep_token);
-                    }
+                    // The second sequence changes in each chunk. Its end
+                    // offset is the offset of the first token from the second
+                    // sequence in the next chunk, or the concatenated input
+                    // length if this is the final chunk.
                     let chunk_start = chunk_idx * second_len;
-                    offsets.push(
-                        second_offsets
+                    let second_seq = TokenSequence {
+                        ids: tokens_chunk.to_vec(),
+                        offsets: offsets_chunk.to_vec(),
+                        type_ids: vec![1; tokens_chunk.len()],
+                        end_offset: second_offsets
                             .get(chunk_start + offsets_chunk.len())
                             .copied()
                             .unwrap_or(first.len() + second.len()),
-                    );
+                    };
 
-                    chunks.push(Encoded::new(input, tokens, offsets, first_seq_len));
+                    let output = TokenSequence::concat(
+                        &post_processor
+                            .process(vec![first_seq, second_seq], &|id| self.token_str(id)),
+                    );
+                    chunks.push(Encoded::new(input, output));
                 }
             }
         }
@@ -761,6 +1396,93 @@ impl Tokenizer {
         Ok(chunks)
     }
 
+    /// Encode a batch of inputs into padded tensors that can be fed to a
+    /// model.
+    ///
+    /// Each input can be a single sequence or a pair of sequences. Inputs are
+    /// encoded in parallel, truncated and passed to the post-processor to add
+    /// special tokens. The results are then padded to the same length.
+    ///
+    /// When [`TruncationOptions::return_overflowing`] is set, an input may
+    /// produce several rows in the output. Use [`BatchEncoded::sample_map`] to
+    /// map rows back to inputs.
+    pub fn encode_batch<'a, I: Into<EncoderInput<'a>>>(
+        &self,
+        inputs: impl IntoIterator<Item = I>,
+        options: BatchEncodeOptions,
+    ) -> Result<BatchEncoded, TokenizerError> {
+        let inputs: Vec<EncoderInput> = inputs.into_iter().map(|input| input.into()).collect();
+
+        let default_post_processor;
+        let post_processor: &dyn PostProcessor = match &self.post_processor {
+            Some(post_processor) => post_processor.as_ref(),
+            None => {
+                default_post_processor = self.default_post_processor()?;
+                &default_post_processor
+            }
+        };
+
+        let rows: Vec<Vec<TokenSequence>> = inputs
+            .par_iter()
+            .map(|input| {
+                let (first, second) = match *input {
+                    EncoderInput::Item(first) => (first, None),
+                    EncoderInput::Pair((first, second)) => (first, Some(second)),
+                };
+
+                let encode = |text: &str, start_offset: usize, type_id: usize| {
+                    self.encode_str(text, start_offset)
+                        .map(|(ids, offsets)| TokenSequence {
+                            type_ids: vec![type_id; ids.len()],
+                            ids,
+                            offsets,
+                            end_offset: start_offset + text.len(),
+                        })
+                };
+                let mut sequences = vec![encode(first, 0, 0)?];
+                if let Some(second) = second {
+                    sequences.push(encode(second, first.len(), 1)?);
+                }
+
+                let truncated = match &options.truncation {
+                    Some(truncation) => {
+                        let added_tokens = post_processor.added_tokens(second.is_some());
+                        let max_len =
+                            truncation
+                                .max_length
+                                .checked_sub(added_tokens)
+                                .ok_or_else(|| {
+                                    TokenizerError::TruncationError(format!(
+                                        "max length {} is less than the {} added special tokens",
+                                        truncation.max_length, added_tokens
+                                    ))
+                                })?;
+                        batch::truncate(sequences, max_len, truncation)?
+                    }
+                    None => vec![sequences],
+                };
+
+                Ok(truncated
+                    .into_iter()
+                    .map(|sequences| {
+                        TokenSequence::concat(
+                            &post_processor.process(sequences, &|id| self.token_str(id)),
+                        )
+                    })
+                    .collect())
+            })
+            .collect::<Result<_, TokenizerError>>()?;
+
+        let sample_map = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(i, input_rows)| repeat(i).take(input_rows.len()))
+            .collect();
+        let rows: Vec<_> = rows.into_iter().flatten().collect();
+
+        Ok(batch::pad(&rows, sample_map, &options.padding))
+    }
+
     /// Decode a sequence of token IDs to a text string.
     ///
     /// For tokenizers which operate on byte sequences (eg. [`Bpe`]) this can
@@ -769,9 +1491,65 @@ impl Tokenizer {
     /// retry decoding.
     ///
     /// Special tokens are decoded into their canonical string representations
-    /// as returned by [`Model::get_token_str`].
+    /// as returned by [`Model::get_token_str`]. Use
+    /// [`decode_with_options`](Self::decode_with_options) to skip them
+    /// instead.
     pub fn decode(&self, ids: &[TokenId]) -> Result<String, TokenizerError> {
-        self.model.decode(ids).map_err(TokenizerError::DecodeError)
+        self.decode_with_options(ids, DecodeOptions::default())
+    }
+
+    /// Decode a sequence of token IDs to a text string, with options.
+    ///
+    /// See [`decode`](Self::decode) for details.
+    pub fn decode_with_options(
+        &self,
+        ids: &[TokenId],
+        options: DecodeOptions,
+    ) -> Result<String, TokenizerError> {
+        let is_skipped = |id| {
+            options.skip_special_tokens && self.added_tokens.get(id).is_some_and(|tok| tok.special)
+        };
+
+        if let Some(decoder) = &self.decoder {
+            let tokens = ids
+                .iter()
+                .copied()
+                .filter(|&id| !is_skipped(id))
+                .map(|id| {
+                    self.added_tokens
+                        .get(id)
+                        .map(|tok| tok.content.clone())
+                        .or_else(|| self.model.get_token_str(id))
+                        .ok_or(DecodeError::InvalidTokenId(id))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(decoder.decode_chain(tokens)?.concat());
+        }
+
+        // Decode runs of tokens that are in the model's vocabulary, and
+        // replace added tokens that are not with their content.
+        let mut text = String::new();
+        let mut model_ids = Vec::new();
+        for &id in ids {
+            if is_skipped(id) {
+                continue;
+            }
+            match self.added_tokens.get(id) {
+                Some(token) if self.model.get_token_str(id).is_none() => {
+                    if !model_ids.is_empty() {
+                        text.push_str(&self.model.decode(&model_ids)?);
+                        model_ids.clear();
+                    }
+                    text.push_str(&token.content);
+                }
+                _ => model_ids.push(id),
+            }
+        }
+        if !model_ids.is_empty() {
+            text.push_str(&self.model.decode(&model_ids)?);
+        }
+
+        Ok(text)
     }
 }
 
@@ -788,6 +1566,13 @@ pub enum TokenizerError {
 
     /// Decoding token IDs into text failed.
     DecodeError(DecodeError),
+
+    /// The tokenizer's [`Decoder`] failed.
+    DecoderError(DecoderError),
+
+    /// The input could not be truncated according to the
+    /// [`TruncationOptions`].
+    TruncationError(String),
 }
 
 impl fmt::Display for TokenizerError {
@@ -797,6 +1582,8 @@ impl fmt::Display for TokenizerError {
             Self::PreTokenizeError(err) => write!(f, "pretokenization error: {}", err),
             Self::EncodeError(err) => write!(f, "encoding with model failed: {}", err),
             Self::DecodeError(err) => write!(f, "decoding failed: {}", err),
+            Self::DecoderError(err) => write!(f, "decoder failed: {}", err),
+            Self::TruncationError(err) => write!(f, "truncation failed: {}", err),
         }
     }
 }
@@ -813,6 +1600,23 @@ impl From<EncodeError> for TokenizerError {
     }
 }
 
+impl From<DecodeError> for TokenizerError {
+    fn from(err: DecodeError) -> Self {
+        TokenizerError::DecodeError(err)
+    }
+}
+
+impl From<DecoderError> for TokenizerError {
+    fn from(err: DecoderError) -> Self {
+        match err {
+            // Report incomplete UTF-8 sequences in the same way as models do,
+            // so callers can handle them by decoding more tokens.
+            DecoderError::InvalidUtf8 => TokenizerError::DecodeError(DecodeError::InvalidUtf8),
+            err => TokenizerError::DecoderError(err),
+        }
+    }
+}
+
 impl Error for TokenizerError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
@@ -820,6 +1624,8 @@ impl Error for TokenizerError {
             Self::PreTokenizeError(e) => Some(e),
             Self::EncodeError(e) => Some(e),
             Self::DecodeError(e) => Some(e),
+            Self::DecoderError(e) => Some(e),
+            Self::TruncationError(_) => None,
         }
     }
 }
@@ -834,8 +1640,17 @@ mod tests {
 
     use rten_testing::TestCases;
 
-    use super::{EncodeOptions, EncoderInput, TokenId, Tokenizer, TokenizerOptions, WordPiece};
-    use crate::normalizers::Normalizer;
+    use super::sentencepiece::tests::{encode_model, MessageWriter};
+    use crate::normalizers::tests::encode_charsmap;
+    use rten_tensor::NdTensor;
+
+    use super::{
+        AddedToken, BatchEncodeOptions, DecodeOptions, EncodeOptions, EncoderInput,
+        FromSentencePieceError, PaddingLength, PaddingOptions, PaddingSide, ToJsonError, TokenId,
+        Tokenizer, TokenizerError, TokenizerOptions, TruncationOptions, TruncationStrategy,
+        WordPiece,
+    };
+    use crate::normalizers::{NormalizeError, Normalizer};
     use crate::{normalizers, pre_tokenizers};
     use serde::Deserialize;
 
@@ -884,6 +1699,21 @@ mod tests {
 
         let token_type_ids: Vec<_> = encoded.token_type_ids().collect();
         assert_eq!(token_type_ids, &[0, 0, 0, 0, 1, 1, 1, 1]);
+
+        // Two sequences, without special tokens.
+        let options = EncodeOptions {
+            skip_post_processor: true,
+            ..Default::default()
+        };
+        let encoded = tokenizer
+            .encode(("This is", "a test sequence"), Some(options.clone()))
+            .unwrap();
+        assert_eq!(
+            tokenizer.model().get_tokens(encoded.token_ids()).unwrap(),
+            &["This", "is", "a", "test", "sequence"]
+        );
+        let encoded = tokenizer.encode("", Some(options)).unwrap();
+        assert!(encoded.token_ids().is_empty());
     }
 
     #[test]
@@ -1106,6 +1936,7 @@ mod tests {
             let options = EncodeOptions {
                 max_chunk_len: *max_chunk_len,
                 overlap: *overlap,
+                ..Default::default()
             };
             let chunks = tokenizer.encode_chunks((*text).into(), options).unwrap();
             let chunk_tokens: Vec<_> = chunks
@@ -1336,10 +2167,217 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_encode_batch() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            inputs: Vec<EncoderInput<'a>>,
+            options: BatchEncodeOptions,
+            expected_ids: NdTensor<i32, 2>,
+            expected_mask: Option<NdTensor<i32, 2>>,
+            expected_type_ids: Option<NdTensor<i32, 2>>,
+            expected_sample_map: Vec<usize>,
+        }
+
+        let vocab = &["[CLS]", "[SEP]", "[PAD]", "[UNK]", "a", "b", "c", "d", "e"];
+        let padding = PaddingOptions {
+            pad_id: 2,
+            ..Default::default()
+        };
+
+        let cases = [
+            // Pad to longest sequence.
+            Case {
+                inputs: vec!["a b c".into(), "a".into()],
+                options: BatchEncodeOptions {
+                    padding: padding.clone(),
+                    truncation: None,
+                },
+                expected_ids: NdTensor::from([[0, 4, 5, 6, 1], [0, 4, 1, 2, 2]]),
+                expected_mask: Some(NdTensor::from([[1, 1, 1, 1, 1], [1, 1, 1, 0, 0]])),
+                expected_type_ids: Some(NdTensor::zeros([2, 5])),
+                expected_sample_map: vec![0, 1],
+            },
+            // Left padding, with fixed length rounded to a multiple.
+            Case {
+                inputs: vec!["a b".into(), "a".into()],
+                options: BatchEncodeOptions {
+                    padding: PaddingOptions {
+                        length: PaddingLength::Fixed(5),
+                        multiple_of: Some(3),
+                        side: PaddingSide::Left,
+                        ..padding.clone()
+                    },
+                    truncation: None,
+                },
+                expected_ids: NdTensor::from([[2, 2, 0, 4, 5, 1], [2, 2, 2, 0, 4, 1]]),
+                expected_mask: Some(NdTensor::from([[0, 0, 1, 1, 1, 1], [0, 0, 0, 1, 1, 1]])),
+                expected_type_ids: None,
+                expected_sample_map: vec![0, 1],
+            },
+            // Longest-first truncation of a pair.
+            Case {
+                inputs: vec![("a b c d", "e").into(), ("a", "b").into()],
+                options: BatchEncodeOptions {
+                    padding: padding.clone(),
+                    truncation: Some(TruncationOptions {
+                        max_length: 6,
+                        ..Default::default()
+                    }),
+                },
+                expected_ids: NdTensor::from([[0, 4, 5, 1, 8, 1], [0, 4, 1, 5, 1, 2]]),
+                expected_mask: None,
+                expected_type_ids: Some(NdTensor::from([[0, 0, 0, 0, 1, 1], [0, 0, 0, 1, 1, 0]])),
+                expected_sample_map: vec![0, 1],
+            },
+            // Only truncate second sequence of a pair.
+            Case {
+                inputs: vec![("a", "b c d e").into()],
+                options: BatchEncodeOptions {
+                    padding: padding.clone(),
+                    truncation: Some(TruncationOptions {
+                        max_length: 6,
+                        strategy: TruncationStrategy::OnlySecond,
+                        ..Default::default()
+                    }),
+                },
+                expected_ids: NdTensor::from([[0, 4, 1, 5, 6, 1]]),
+                expected_mask: None,
+                expected_type_ids: None,
+                expected_sample_map: vec![0],
+            },
+            // Truncation with overflowing rows and stride.
+            Case {
+                inputs: vec!["a b c d e".into(), "a".into()],
+                options: BatchEncodeOptions {
+                    padding: padding.clone(),
+                    truncation: Some(TruncationOptions {
+                        max_length: 5,
+                        return_overflowing: true,
+                        stride: 1,
+                        ..Default::default()
+                    }),
+                },
+                expected_ids: NdTensor::from([[0, 4, 5, 6, 1], [0, 6, 7, 8, 1], [0, 4, 1, 2, 2]]),
+                expected_mask: None,
+                expected_type_ids: None,
+                expected_sample_map: vec![0, 0, 1],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let tokenizer = Tokenizer::new(
+                make_wordpiece(vocab),
+                TokenizerOptions {
+                    cls_token: Some("[CLS]"),
+                    sep_token: Some("[SEP]"),
+                },
+            )
+            .with_pre_tokenizer(Box::new(pre_tokenizers::Bert::new()));
+
+            let encoded = tokenizer
+                .encode_batch(case.inputs.clone(), case.options.clone())
+                .unwrap();
+
+            assert_eq!(encoded.input_ids, case.expected_ids);
+            if let Some(mask) = &case.expected_mask {
+                assert_eq!(encoded.attention_mask, *mask);
+            }
+            if let Some(type_ids) = &case.expected_type_ids {
+                assert_eq!(encoded.token_type_ids, *type_ids);
+            }
+            assert_eq!(encoded.sample_map, case.expected_sample_map);
+        });
+
+        // Offsets of tokens in the second sequence of a pair start at the
+        // length of the first.
+        let tokenizer = Tokenizer::new(
+            make_wordpiece(vocab),
+            TokenizerOptions {
+                cls_token: Some("[CLS]"),
+                sep_token: Some("[SEP]"),
+            },
+        )
+        .with_pre_tokenizer(Box::new(pre_tokenizers::Bert::new()));
+        let encoded = tokenizer
+            .encode_batch([("a", "b c")], Default::default())
+            .unwrap();
+        assert_eq!(encoded.offsets, [vec![0, 0, 1, 1, 3, 4]]);
+    }
+
+    #[test]
+    fn test_encode_batch_truncation_error() {
+        let tokenizer = Tokenizer::new(
+            make_wordpiece(&["[CLS]", "[SEP]", "a"]),
+            TokenizerOptions {
+                cls_token: Some("[CLS]"),
+                sep_token: Some("[SEP]"),
+            },
+        )
+        .with_pre_tokenizer(Box::new(pre_tokenizers::Bert::new()));
+        let truncate = |max_length, strategy| BatchEncodeOptions {
+            truncation: Some(TruncationOptions {
+                max_length,
+                strategy,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // Max length is less than the number of special tokens.
+        let result = tokenizer.encode_batch(["a"], truncate(1, TruncationStrategy::LongestFirst));
+        assert!(matches!(result, Err(TokenizerError::TruncationError(_))));
+
+        // Truncating the second sequence of a single-sequence input.
+        let result = tokenizer.encode_batch(["a a a"], truncate(3, TruncationStrategy::OnlySecond));
+        assert!(matches!(result, Err(TokenizerError::TruncationError(_))));
+
+        // Not enough space for the first sequence.
+        let result =
+            tokenizer.encode_batch([("a", "a a a")], truncate(6, TruncationStrategy::OnlyFirst));
+        assert!(matches!(result, Err(TokenizerError::TruncationError(_))));
+    }
+
+    #[test]
+    fn test_added_tokens() {
+        let vocab = &["[CLS]", "[SEP]", "[UNK]", "<", "|", ">", "hello", "world"];
+        let tokenizer = Tokenizer::new(make_wordpiece(vocab), Default::default())
+            .with_pre_tokenizer(Box::new(pre_tokenizers::Bert::new()))
+            .with_added_tokens(vec![
+                AddedToken::special(100, "<|im_start|>"),
+                AddedToken::special(101, "<|im_end|>"),
+                AddedToken::new(102, "<|sep|>"),
+            ]);
+
+        let encoded = tokenizer
+            .encode("<|im_start|>hello<|sep|>world<|im_end|>", None)
+            .unwrap();
+        assert_eq!(encoded.token_ids(), [100, 6, 102, 7, 101]);
+        assert_eq!(encoded.token_offsets(), [0, 12, 17, 24, 29]);
+        assert_eq!(tokenizer.get_token_id("<|im_end|>").unwrap(), 101);
+
+        let text = tokenizer.decode(encoded.token_ids()).unwrap();
+        assert_eq!(text, "<|im_start|>hello<|sep|>world<|im_end|>");
+
+        let text = tokenizer
+            .decode_with_options(
+                encoded.token_ids(),
+                DecodeOptions {
+                    skip_special_tokens: true,
+                },
+            )
+            .unwrap();
+        assert_eq!(text, "hello<|sep|>world");
+    }
+
     #[derive(Deserialize)]
     struct TokenizerJsonCase {
         text: String,
+        pair: Option<String>,
         token_ids: Vec<TokenId>,
+        type_ids: Option<Vec<usize>>,
+        offsets: Option<Vec<usize>>,
+        decoded: Option<String>,
     }
 
     #[derive(Deserialize)]
@@ -1359,16 +2397,185 @@ mod tests {
 
     #[test]
     fn test_from_json() {
-        let paths = ["wordpiece.json", "wordpiece-lower.json"];
+        let paths = [
+            "wordpiece.json",
+            "wordpiece-lower.json",
+            "unigram.json",
+            "template-processing.json",
+            "roberta-processing.json",
+            "added-tokens.json",
+            "decoders.json",
+            "metaspace.json",
+            "pre-tokenizers.json",
+        ];
 
         for path in paths.iter() {
             let config = read_test_json(path).unwrap();
 
             let tokenizer = Tokenizer::from_parsed_json(config.tokenizer).unwrap();
-            for case in config.cases {
-                let encoded = tokenizer.encode(case.text.as_str(), None).unwrap();
-                assert_eq!(encoded.token_ids(), case.token_ids);
+
+            // Serialize the tokenizer and check that the re-loaded tokenizer
+            // behaves the same and serializes to the same output.
+            let json = tokenizer.to_json().unwrap();
+            let reloaded = Tokenizer::from_json(&json).unwrap();
+            assert_eq!(reloaded.to_json().unwrap(), json, "{}", path);
+
+            for tokenizer in [&tokenizer, &reloaded] {
+                for case in &config.cases {
+                    let input = match &case.pair {
+                        Some(pair) => EncoderInput::Pair((&case.text, pair)),
+                        None => EncoderInput::Item(&case.text),
+                    };
+                    let encoded = tokenizer.encode(input, None).unwrap();
+                    assert_eq!(encoded.token_ids(), case.token_ids);
+                    if let Some(type_ids) = &case.type_ids {
+                        assert_eq!(&encoded.token_type_ids().collect::<Vec<_>>(), type_ids);
+                    }
+                    if let Some(offsets) = &case.offsets {
+                        assert_eq!(&encoded.token_offsets(), offsets);
+                    }
+                    if let Some(decoded) = &case.decoded {
+                        assert_eq!(&tokenizer.decode(encoded.token_ids()).unwrap(), decoded);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_json_unsupported_component() {
+        #[derive(Debug)]
+        struct CustomNormalizer;
+
+        impl Normalizer for CustomNormalizer {
+            fn normalize(&self, text: &str) -> Result<(String, Vec<usize>), NormalizeError> {
+                Ok((text.to_string(), (0..text.len()).collect()))
             }
         }
+
+        let tokenizer = Tokenizer::new(make_wordpiece(&["[UNK]"]), Default::default())
+            .with_normalizer(Box::new(CustomNormalizer));
+        let err = tokenizer.to_json().err().unwrap();
+        assert!(matches!(
+            err,
+            ToJsonError::UnsupportedComponent("normalizer")
+        ));
+        assert_eq!(err.to_string(), "normalizer does not support serialization");
+    }
+
+    #[test]
+    fn test_save() {
+        let vocab = &["[CLS]", "[SEP]", "[UNK]", "hello", "world"];
+        let tokenizer = Tokenizer::new(
+            make_wordpiece(vocab),
+            TokenizerOptions {
+                cls_token: Some("[CLS]"),
+                sep_token: Some("[SEP]"),
+            },
+        )
+        .with_normalizer(lowercase_normalizer())
+        .with_pre_tokenizer(Box::new(pre_tokenizers::Bert::new()));
+        let path =
+            std::env::temp_dir().join(format!("rten-text-test-save-{}.json", std::process::id()));
+        tokenizer.save(&path).unwrap();
+        let loaded = Tokenizer::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        let text = "Hello world";
+        assert_eq!(
+            loaded.encode(text, None).unwrap().token_ids(),
+            tokenizer.encode(text, None).unwrap().token_ids()
+        );
+    }
+
+    #[test]
+    fn test_from_sentencepiece() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            text: &'a str,
+            expected: &'a [&'a str],
+        }
+
+        let pieces = [
+            ("<unk>", 0., 2 /* unknown */),
+            ("</s>", 0., 3 /* control */),
+            ("▁", -2., 1),
+            ("▁hell", -4., 1),
+            ("▁hello", -6.5, 1),
+            ("o", -3., 1),
+            ("▁wor", -4., 1),
+            ("ld", -3., 1),
+            ("<0x78>", 0., 6 /* byte */),
+        ];
+        let data = encode_model(
+            &pieces,
+            1, /* unigram */
+            false,
+            MessageWriter::default(),
+        );
+
+        let cases = [
+            Case {
+                text: "hello world",
+                expected: &["▁hello", "▁wor", "ld"],
+            },
+            // Extra whitespace is removed.
+            Case {
+                text: "  hello   world ",
+                expected: &["▁hello", "▁wor", "ld"],
+            },
+            Case {
+                text: "hello x",
+                expected: &["▁hello", "▁", "<unk>"],
+            },
+            // Control pieces are matched as special tokens.
+            Case {
+                text: "hello </s>",
+                expected: &["▁hello", "</s>"],
+            },
+            // Unknown and byte pieces are not matched in the text.
+            Case {
+                text: "<unk><0x78>",
+                expected: &["▁", "<unk>"],
+            },
+            Case {
+                text: "",
+                expected: &[],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let tokenizer = Tokenizer::from_sentencepiece(&data).unwrap();
+            let encoded = tokenizer.encode(case.text, None).unwrap();
+            let tokens = tokenizer.model().get_tokens(encoded.token_ids()).unwrap();
+            assert_eq!(tokens, case.expected);
+        });
+
+        let tokenizer = Tokenizer::from_sentencepiece(&data).unwrap();
+        let encoded = tokenizer.encode("hello world", None).unwrap();
+        let text = tokenizer.decode(encoded.token_ids()).unwrap();
+        assert_eq!(text, "hello world");
+        assert_eq!(tokenizer.added_tokens().len(), 1);
+        assert!(tokenizer.added_tokens()[0].special);
+
+        // Precompiled normalization rules.
+        let data = encode_model(
+            &pieces,
+            1, /* unigram */
+            false,
+            MessageWriter::default().bytes(2, &encode_charsmap(&[("ｈ", "h")])),
+        );
+        let tokenizer = Tokenizer::from_sentencepiece(&data).unwrap();
+        let encoded = tokenizer.encode("ｈello world", None).unwrap();
+        let tokens = tokenizer.model().get_tokens(encoded.token_ids()).unwrap();
+        assert_eq!(tokens, ["▁hello", "▁wor", "ld"]);
+
+        // BPE models are not supported.
+        let data = encode_model(&pieces, 2 /* bpe */, false, MessageWriter::default());
+        assert!(matches!(
+            Tokenizer::from_sentencepiece(&data),
+            Err(FromSentencePieceError::UnsupportedModel)
+        ));
     }
 }
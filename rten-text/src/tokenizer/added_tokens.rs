@@ -0,0 +1,408 @@
+//! Matching of added tokens (eg. special tokens such as `<|endoftext|>`) in
+//! input text.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::TokenId;
+use crate::normalizers::Normalizer;
+
+/// A token which is matched in the input text before it is split into pieces
+/// and encoded by the model.
+///
+/// Added tokens are typically special tokens such as `[MASK]` or
+/// `<|im_start|>` which would otherwise be split into several tokens by the
+/// model. See the `added_tokens` field in Hugging Face `tokenizer.json` files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddedToken {
+    /// ID of the token.
+    pub id: TokenId,
+
+    /// Text which is replaced with this token.
+    pub content: String,
+
+    /// Only match the token if it is not part of a larger word. A word is a
+    /// sequence of alphanumeric characters or underscores.
+    pub single_word: bool,
+
+    /// Remove whitespace to the left of the token.
+    pub lstrip: bool,
+
+    /// Remove whitespace to the right of the token.
+    pub rstrip: bool,
+
+    /// Match the token in the normalized text rather than the original text.
+    pub normalized: bool,
+
+    /// Whether this is a special token. Special tokens can be skipped when
+    /// decoding.
+    pub special: bool,
+}
+
+impl AddedToken {
+    /// Create a non-special added token with the default options.
+    pub fn new(id: TokenId, content: impl Into<String>) -> Self {
+        AddedToken {
+            id,
+            content: content.into(),
+            single_word: false,
+            lstrip: false,
+            rstrip: false,
+            normalized: true,
+            special: false,
+        }
+    }
+
+    /// Create a special token with the default options.
+    ///
+    /// Unlike [`AddedToken::new`], special tokens are matched in the original
+    /// text rather than the normalized text.
+    pub fn special(id: TokenId, content: impl Into<String>) -> Self {
+        AddedToken {
+            normalized: false,
+            special: true,
+            ..Self::new(id, content)
+        }
+    }
+}
+
+/// Section of text produced by [`AddedVocab::split`].
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Segment {
+    /// A range of text which did not match any added token.
+    Text(Range<usize>),
+
+    /// An added token which starts at a given byte offset.
+    Token { id: TokenId, offset: usize },
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: HashMap<u8, usize>,
+
+    /// Index of the token which ends at this node.
+    token: Option<usize>,
+}
+
+/// Finds occurrences of a set of added tokens in text.
+///
+/// This uses a byte-level trie to find the longest token starting at each
+/// position, skipping positions whose first byte does not start any token.
+struct Matcher {
+    nodes: Vec<TrieNode>,
+
+    /// Whether each byte value is the first byte of a pattern.
+    first_bytes: [bool; 256],
+}
+
+impl Matcher {
+    /// Create a matcher for a list of `(pattern, token_index)` pairs.
+    fn new(patterns: impl Iterator<Item = (String, usize)>) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+        let mut first_bytes = [false; 256];
+
+        for (pattern, token) in patterns {
+            let Some(&first) = pattern.as_bytes().first() else {
+                continue;
+            };
+            first_bytes[first as usize] = true;
+
+            let mut node = 0;
+            for &byte in pattern.as_bytes() {
+                node = match nodes[node].children.get(&byte) {
+                    Some(&child) => child,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].children.insert(byte, child);
+                        child
+                    }
+                };
+            }
+            // If several tokens have the same pattern, the first one wins.
+            nodes[node].token.get_or_insert(token);
+        }
+
+        Matcher { nodes, first_bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    /// Return the `(end, token_index)` of all patterns which start at `pos`,
+    /// in order of increasing length.
+    fn matches_at(&self, text: &[u8], pos: usize) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        let mut node = 0;
+        for (i, byte) in text[pos..].iter().enumerate() {
+            let Some(&child) = self.nodes[node].children.get(byte) else {
+                break;
+            };
+            node = child;
+            if let Some(token) = self.nodes[node].token {
+                matches.push((pos + i + 1, token));
+            }
+        }
+        matches
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Collection of added tokens used by a [`Tokenizer`](super::Tokenizer).
+pub(crate) struct AddedVocab {
+    tokens: Vec<AddedToken>,
+
+    /// Map of token ID to index in `tokens`.
+    id_to_index: HashMap<TokenId, usize>,
+
+    /// Matcher for tokens which are matched against the original text.
+    raw: Matcher,
+
+    /// Matcher for tokens which are matched against the normalized text.
+    normalized: Matcher,
+}
+
+impl AddedVocab {
+    /// Create a vocabulary of added tokens.
+    ///
+    /// `normalizer` is used to normalize the content of tokens which are
+    /// matched against normalized text.
+    pub fn new(tokens: Vec<AddedToken>, normalizer: Option<&dyn Normalizer>) -> Self {
+        let id_to_index = tokens
+            .iter()
+            .enumerate()
+            .map(|(i, tok)| (tok.id, i))
+            .collect();
+
+        let raw = Matcher::new(
+            tokens
+                .iter()
+                .enumerate()
+                .filter(|(_, tok)| !tok.normalized)
+                .map(|(i, tok)| (tok.content.clone(), i)),
+        );
+
+        let normalized = Matcher::new(
+            tokens
+                .iter()
+                .enumerate()
+                .filter(|(_, tok)| tok.normalized)
+                .map(|(i, tok)| {
+                    let content = normalizer
+                        .and_then(|n| n.normalize(&tok.content).ok())
+                        .map(|(normalized, _offsets)| normalized)
+                        .unwrap_or_else(|| tok.content.clone());
+                    (content, i)
+                }),
+        );
+
+        AddedVocab {
+            tokens,
+            id_to_index,
+            raw,
+            normalized,
+        }
+    }
+
+    /// Return the list of added tokens.
+    pub fn tokens(&self) -> &[AddedToken] {
+        &self.tokens
+    }
+
+    /// Look up an added token by ID.
+    pub fn get(&self, id: TokenId) -> Option<&AddedToken> {
+        self.id_to_index.get(&id).map(|&idx| &self.tokens[idx])
+    }
+
+    /// Look up the ID of an added token by its content.
+    pub fn get_id(&self, content: &str) -> Option<TokenId> {
+        self.tokens
+            .iter()
+            .find(|tok| tok.content == content)
+            .map(|tok| tok.id)
+    }
+
+    /// Split `text` into ranges of text and added tokens.
+    ///
+    /// If `normalized` is true, this matches tokens whose
+    /// [`normalized`](AddedToken::normalized) flag is set, and `text` should
+    /// be the normalized text. Otherwise it matches the other tokens.
+    ///
+    /// At each position the longest matching token is used. Whitespace
+    /// around tokens with the `lstrip` or `rstrip` flags is removed from the
+    /// adjacent text segments.
+    pub fn split(&self, text: &str, normalized: bool) -> Vec<Segment> {
+        let matcher = if normalized {
+            &self.normalized
+        } else {
+            &self.raw
+        };
+        if matcher.is_empty() {
+            return vec![Segment::Text(0..text.len())];
+        }
+
+        let bytes = text.as_bytes();
+        let mut segments = Vec::new();
+        let mut text_start = 0;
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            if !matcher.first_bytes[bytes[pos] as usize] {
+                pos += 1;
+                continue;
+            }
+
+            // Patterns always start with the first byte of a UTF-8 character,
+            // so matches start and end on character boundaries.
+            let matched = matcher
+                .matches_at(bytes, pos)
+                .into_iter()
+                .rev()
+                .map(|(end, idx)| (end, &self.tokens[idx]))
+                .find(|(end, token)| {
+                    !token.single_word
+                        || (!text[..pos].chars().next_back().is_some_and(is_word_char)
+                            && !text[*end..].chars().next().is_some_and(is_word_char))
+                });
+
+            let Some((mut end, token)) = matched else {
+                pos += 1;
+                continue;
+            };
+
+            let mut start = pos;
+            if token.lstrip {
+                start = text_start + text[text_start..start].trim_end().len();
+            }
+            if token.rstrip {
+                end = text.len() - text[end..].trim_start().len();
+            }
+
+            if start > text_start {
+                segments.push(Segment::Text(text_start..start));
+            }
+            segments.push(Segment::Token {
+                id: token.id,
+                offset: start,
+            });
+            text_start = end;
+            pos = end;
+        }
+
+        if text_start < text.len() {
+            segments.push(Segment::Text(text_start..text.len()));
+        }
+
+        segments
+    }
+}
+
+impl Default for AddedVocab {
+    fn default() -> Self {
+        AddedVocab::new(Vec::new(), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+
+    use super::{AddedToken, AddedVocab, Segment};
+    use crate::normalizers;
+
+    #[test]
+    fn test_split() {
+        #[derive(Debug)]
+        struct Case<'a> {
+            text: &'a str,
+            expected: Vec<Segment>,
+        }
+
+        let tokens = vec![
+            AddedToken::special(1, "<|im_start|>"),
+            AddedToken::special(2, "<|im"),
+            AddedToken {
+                lstrip: true,
+                rstrip: true,
+                ..AddedToken::special(3, "<mask>")
+            },
+            AddedToken {
+                single_word: true,
+                ..AddedToken::special(4, "word")
+            },
+        ];
+
+        let cases = [
+            Case {
+                text: "no tokens",
+                expected: vec![Segment::Text(0..9)],
+            },
+            // Longest match is preferred.
+            Case {
+                text: "<|im_start|>user",
+                expected: vec![Segment::Token { id: 1, offset: 0 }, Segment::Text(12..16)],
+            },
+            Case {
+                text: "<|im_end|>",
+                expected: vec![Segment::Token { id: 2, offset: 0 }, Segment::Text(4..10)],
+            },
+            // Whitespace stripping.
+            Case {
+                text: "a  <mask>  b",
+                expected: vec![
+                    Segment::Text(0..1),
+                    Segment::Token { id: 3, offset: 1 },
+                    Segment::Text(11..12),
+                ],
+            },
+            // Single-word matching.
+            Case {
+                text: "words word, word_",
+                expected: vec![
+                    Segment::Text(0..6),
+                    Segment::Token { id: 4, offset: 6 },
+                    Segment::Text(10..17),
+                ],
+            },
+            // Non-ASCII text.
+            Case {
+                text: "é<|im_start|>é",
+                expected: vec![
+                    Segment::Text(0..2),
+                    Segment::Token { id: 1, offset: 2 },
+                    Segment::Text(14..16),
+                ],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let vocab = AddedVocab::new(tokens.clone(), None);
+            assert_eq!(vocab.split(case.text, false), case.expected);
+            assert_eq!(
+                vocab.split(case.text, true),
+                [Segment::Text(0..case.text.len())]
+            );
+        })
+    }
+
+    #[test]
+    fn test_split_normalized() {
+        let normalizer = normalizers::Bert::new(normalizers::BertOptions {
+            lowercase: true,
+            ..Default::default()
+        });
+        let vocab = AddedVocab::new(vec![AddedToken::new(5, "[MASK]")], Some(&normalizer));
+
+        assert_eq!(
+            vocab.split("a [mask]", true),
+            [Segment::Text(0..2), Segment::Token { id: 5, offset: 2 }]
+        );
+        assert_eq!(vocab.split("a [mask]", false), [Segment::Text(0..8)]);
+        assert_eq!(vocab.get_id("[MASK]"), Some(5));
+        assert_eq!(vocab.get(5).map(|t| t.special), Some(false));
+    }
+}
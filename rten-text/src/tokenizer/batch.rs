@@ -0,0 +1,298 @@
+//! Padding and truncation for [`Tokenizer::encode_batch`](super::Tokenizer::encode_batch).
+
+use rten_tensor::NdTensor;
+
+use super::{TokenId, TokenizerError};
+use crate::post_processors::TokenSequence;
+
+/// Length that sequences in a batch are padded to.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PaddingLength {
+    /// Pad to the length of the longest sequence in the batch.
+    #[default]
+    Longest,
+
+    /// Pad to a fixed length. Sequences which are longer than this are not
+    /// truncated, use [`TruncationOptions`] for that.
+    Fixed(usize),
+}
+
+/// Side of a sequence where padding tokens are added.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PaddingSide {
+    #[default]
+    Right,
+    Left,
+}
+
+/// Options that control padding in [`Tokenizer::encode_batch`](super::Tokenizer::encode_batch).
+#[derive(Clone, Debug, Default)]
+pub struct PaddingOptions {
+    /// Length to pad sequences to.
+    pub length: PaddingLength,
+
+    /// Round the padded length up to a multiple of this value.
+    pub multiple_of: Option<usize>,
+
+    /// Whether to add padding at the start or end of sequences.
+    pub side: PaddingSide,
+
+    /// Token ID used for padding.
+    pub pad_id: TokenId,
+
+    /// Token type ID used for padding.
+    pub pad_type_id: usize,
+}
+
+/// Strategies for truncating inputs which exceed the maximum length.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TruncationStrategy {
+    /// Remove tokens from the longest sequence in a pair until both are the
+    /// same length, then remove tokens from both sequences equally.
+    #[default]
+    LongestFirst,
+
+    /// Only truncate the first sequence of a pair.
+    OnlyFirst,
+
+    /// Only truncate the second sequence of a pair.
+    OnlySecond,
+}
+
+/// Options that control truncation in [`Tokenizer::encode_batch`](super::Tokenizer::encode_batch).
+#[derive(Clone, Debug, Default)]
+pub struct TruncationOptions {
+    /// Maximum number of tokens in each row of the output, including special
+    /// tokens added by the post-processor.
+    pub max_length: usize,
+
+    /// Which sequences to truncate.
+    pub strategy: TruncationStrategy,
+
+    /// If true, tokens which are removed by truncation are returned as
+    /// additional rows in the output, instead of being discarded.
+    pub return_overflowing: bool,
+
+    /// Number of tokens which each overflowing row repeats from the end of
+    /// the previous row. This must be less than the number of tokens that the
+    /// truncated sequence is allowed to contain.
+    pub stride: usize,
+}
+
+/// Options for [`Tokenizer::encode_batch`](super::Tokenizer::encode_batch).
+#[derive(Clone, Debug, Default)]
+pub struct BatchEncodeOptions {
+    pub padding: PaddingOptions,
+
+    /// Truncation options. If `None`, inputs are not truncated.
+    pub truncation: Option<TruncationOptions>,
+}
+
+/// Output of [`Tokenizer::encode_batch`](super::Tokenizer::encode_batch).
+///
+/// The tensors have shape `[rows, seq_len]` and can be used directly as the
+/// `input_ids`, `attention_mask` and `token_type_ids` inputs of transformer
+/// models.
+#[derive(Clone, Debug)]
+pub struct BatchEncoded {
+    /// Token IDs, including padding.
+    pub input_ids: NdTensor<i32, 2>,
+
+    /// Mask which is 1 for tokens from the input and 0 for padding.
+    pub attention_mask: NdTensor<i32, 2>,
+
+    /// Token type IDs.
+    pub token_type_ids: NdTensor<i32, 2>,
+
+    /// Index of the input that each row was generated from. Inputs produce
+    /// more than one row if [`TruncationOptions::return_overflowing`] is set.
+    pub sample_map: Vec<usize>,
+
+    /// Byte offset in the input of the start of each token in each row,
+    /// excluding padding. For inputs which are pairs of sequences, offsets
+    /// in the second sequence start at the length of the first, as in
+    /// [`Encoded::token_offsets`](super::Encoded::token_offsets).
+    pub offsets: Vec<Vec<usize>>,
+}
+
+impl TokenSequence {
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Return the tokens in `start..end`.
+    fn slice(&self, start: usize, end: usize) -> TokenSequence {
+        TokenSequence {
+            ids: self.ids[start..end].to_vec(),
+            offsets: self.offsets[start..end].to_vec(),
+            type_ids: self.type_ids[start..end].to_vec(),
+            end_offset: self.offsets.get(end).copied().unwrap_or(self.end_offset),
+        }
+    }
+
+    /// Split a sequence into windows of at most `max_len` tokens. If
+    /// `overflow` is false, only the first window is returned.
+    fn windows(
+        &self,
+        max_len: usize,
+        stride: usize,
+        overflow: bool,
+    ) -> Result<Vec<TokenSequence>, TokenizerError> {
+        if self.len() <= max_len {
+            return Ok(vec![self.clone()]);
+        }
+        if !overflow {
+            return Ok(vec![self.slice(0, max_len)]);
+        }
+        if stride >= max_len {
+            return Err(TokenizerError::TruncationError(format!(
+                "stride {} must be less than truncated length {}",
+                stride, max_len
+            )));
+        }
+
+        let step = max_len - stride;
+        let mut windows = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + max_len).min(self.len());
+            windows.push(self.slice(start, end));
+            if end == self.len() {
+                break;
+            }
+            start += step;
+        }
+        Ok(windows)
+    }
+}
+
+/// Truncate encoded sequences so that their combined length is at most
+/// `max_len`.
+///
+/// Returns a list of truncated sequences. The first entry contains the
+/// start of each sequence and subsequent entries contain overflowing tokens.
+pub(crate) fn truncate(
+    sequences: Vec<TokenSequence>,
+    max_len: usize,
+    options: &TruncationOptions,
+) -> Result<Vec<Vec<TokenSequence>>, TokenizerError> {
+    let total_len: usize = sequences.iter().map(|seq| seq.len()).sum();
+    if total_len <= max_len {
+        return Ok(vec![sequences]);
+    }
+
+    let too_short = || {
+        TokenizerError::TruncationError(
+            "sequence to truncate is too short to respect max length".to_string(),
+        )
+    };
+    let TruncationOptions {
+        strategy,
+        stride,
+        return_overflowing: overflow,
+        ..
+    } = *options;
+
+    match sequences.as_slice() {
+        [seq] => {
+            if strategy == TruncationStrategy::OnlySecond {
+                return Err(TokenizerError::TruncationError(
+                    "second sequence not provided".to_string(),
+                ));
+            }
+            let windows = seq.windows(max_len, stride, overflow)?;
+            Ok(windows.into_iter().map(|seq| vec![seq]).collect())
+        }
+        [first, second] => {
+            let (first_len, second_len) = match strategy {
+                TruncationStrategy::LongestFirst => {
+                    // Keep the shorter sequence if it fits in half the
+                    // available space, and give the rest to the longer one.
+                    let half = max_len / 2;
+                    if first.len() <= second.len() {
+                        let first_len = first.len().min(half);
+                        (first_len, max_len - first_len)
+                    } else {
+                        let second_len = second.len().min(half);
+                        (max_len - second_len, second_len)
+                    }
+                }
+                TruncationStrategy::OnlyFirst => {
+                    let first_len = max_len
+                        .checked_sub(second.len())
+                        .filter(|len| *len > 0)
+                        .ok_or_else(too_short)?;
+                    (first_len, second.len())
+                }
+                TruncationStrategy::OnlySecond => {
+                    let second_len = max_len
+                        .checked_sub(first.len())
+                        .filter(|len| *len > 0)
+                        .ok_or_else(too_short)?;
+                    (first.len(), second_len)
+                }
+            };
+
+            let first_windows = first.windows(first_len, stride, overflow)?;
+            let second_windows = second.windows(second_len, stride, overflow)?;
+
+            let mut output = Vec::new();
+            for first in &first_windows {
+                for second in &second_windows {
+                    output.push(vec![first.clone(), second.clone()]);
+                }
+            }
+            Ok(output)
+        }
+        _ => Ok(vec![sequences]),
+    }
+}
+
+/// Pad encoded sequences to the same length and convert them to tensors.
+pub(crate) fn pad(
+    rows: &[TokenSequence],
+    sample_map: Vec<usize>,
+    options: &PaddingOptions,
+) -> BatchEncoded {
+    let longest = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut seq_len = match options.length {
+        PaddingLength::Longest => longest,
+        PaddingLength::Fixed(len) => len.max(longest),
+    };
+    if let Some(multiple_of) = options.multiple_of.filter(|m| *m > 0) {
+        seq_len = seq_len.next_multiple_of(multiple_of);
+    }
+
+    let shape = [rows.len(), seq_len];
+    let mut input_ids = NdTensor::full(shape, options.pad_id as i32);
+    let mut attention_mask = NdTensor::zeros(shape);
+    let mut token_type_ids = NdTensor::full(shape, options.pad_type_id as i32);
+
+    for (i, row) in rows.iter().enumerate() {
+        let start = match options.side {
+            PaddingSide::Right => 0,
+            PaddingSide::Left => seq_len - row.len(),
+        };
+        let range = (i, start..start + row.len());
+
+        for (dst, src) in input_ids.slice_mut(range.clone()).iter_mut().zip(&row.ids) {
+            *dst = *src as i32;
+        }
+        for (dst, src) in token_type_ids
+            .slice_mut(range.clone())
+            .iter_mut()
+            .zip(&row.type_ids)
+        {
+            *dst = *src as i32;
+        }
+        attention_mask.slice_mut(range).fill(1);
+    }
+
+    BatchEncoded {
+        input_ids,
+        attention_mask,
+        token_type_ids,
+        sample_map,
+        offsets: rows.iter().map(|row| row.offsets.clone()).collect(),
+    }
+}
@@ -8,6 +8,20 @@ use serde::Deserialize;
 pub(crate) struct AddedToken {
     pub content: String,
     pub id: TokenId,
+    #[serde(default)]
+    pub single_word: bool,
+    #[serde(default)]
+    pub lstrip: bool,
+    #[serde(default)]
+    pub rstrip: bool,
+    #[serde(default = "default_true")]
+    pub normalized: bool,
+    #[serde(default)]
+    pub special: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Deserialize)]
@@ -16,6 +30,16 @@ pub(crate) enum Pattern {
     String(String),
 }
 
+/// Specifies when a replacement character is added to the start of the input
+/// by Metaspace pre-tokenizers.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PrependScheme {
+    First,
+    Never,
+    Always,
+}
+
 pub mod normalizers {
     use serde::Deserialize;
 
@@ -27,6 +51,17 @@ pub mod normalizers {
         pub strip_accents: Option<bool>,
     }
 
+    #[derive(Deserialize)]
+    pub(crate) struct Prepend {
+        pub prepend: String,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Precompiled {
+        /// Base64-encoded SentencePiece charsmap.
+        pub precompiled_charsmap: Option<String>,
+    }
+
     #[derive(Deserialize)]
     pub(crate) struct Replace {
         pub pattern: Pattern,
@@ -37,6 +72,12 @@ pub mod normalizers {
     pub(crate) struct Sequence {
         pub normalizers: Vec<Normalizer>,
     }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Strip {
+        pub strip_left: bool,
+        pub strip_right: bool,
+    }
 }
 
 #[derive(Deserialize)]
@@ -53,17 +94,23 @@ pub(crate) enum Normalizer {
     Nfkc,
     #[serde(rename = "NFKD")]
     Nfkd,
+    Precompiled(normalizers::Precompiled),
+    Prepend(normalizers::Prepend),
     Replace(normalizers::Replace),
     Sequence(normalizers::Sequence),
+    Strip(normalizers::Strip),
+    StripAccents,
 }
 
 pub mod pre_tokenizers {
     use serde::Deserialize;
 
-    use super::{Pattern, PreTokenizer};
+    use super::{Pattern, PreTokenizer, PrependScheme};
 
     #[derive(Deserialize)]
     pub(crate) struct ByteLevel {
+        #[serde(default = "super::default_true")]
+        pub add_prefix_space: bool,
         pub use_regex: bool,
     }
 
@@ -72,6 +119,30 @@ pub mod pre_tokenizers {
         pub individual_digits: bool,
     }
 
+    #[derive(Deserialize)]
+    pub(crate) struct Metaspace {
+        pub replacement: char,
+
+        /// Whether a `replacement` character is added to the start of the
+        /// input. This is used in older versions of the format.
+        pub add_prefix_space: Option<bool>,
+
+        pub prepend_scheme: Option<PrependScheme>,
+
+        #[serde(default = "super::default_true")]
+        pub split: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Punctuation {
+        #[serde(default = "default_punctuation_behavior")]
+        pub behavior: SplitDelimiter,
+    }
+
+    fn default_punctuation_behavior() -> SplitDelimiter {
+        SplitDelimiter::Isolated
+    }
+
     #[derive(Deserialize)]
     pub(crate) struct Sequence {
         pub pretokenizers: Vec<PreTokenizer>,
@@ -102,8 +173,13 @@ pub(crate) enum PreTokenizer {
     #[serde(rename = "ByteLevel")]
     ByteLevel(pre_tokenizers::ByteLevel),
     Digits(pre_tokenizers::Digits),
+    Metaspace(pre_tokenizers::Metaspace),
+    Punctuation(pre_tokenizers::Punctuation),
     Sequence(pre_tokenizers::Sequence),
     Split(pre_tokenizers::Split),
+    UnicodeScripts,
+    Whitespace,
+    WhitespaceSplit,
 }
 
 pub mod models {
@@ -117,6 +193,10 @@ pub mod models {
     pub(crate) struct WordPiece {
         /// Mapping from token text to token ID.
         pub vocab: HashMap<String, TokenId>,
+
+        /// Maximum length of words, in characters, which are tokenized.
+        /// Longer words are replaced by the unknown token.
+        pub max_input_chars_per_word: Option<usize>,
     }
 
     #[derive(Debug, Deserialize)]
@@ -128,6 +208,19 @@ pub mod models {
         Legacy(Vec<String>),
     }
 
+    #[derive(Deserialize)]
+    pub(crate) struct Unigram {
+        /// List of `(token, score)` pairs, indexed by token ID.
+        pub vocab: Vec<(String, f32)>,
+
+        /// ID of the token used for unknown characters.
+        pub unk_id: Option<TokenId>,
+
+        /// Whether to encode unknown characters as byte tokens.
+        #[serde(default)]
+        pub byte_fallback: bool,
+    }
+
     #[derive(Deserialize)]
     pub(crate) struct Bpe {
         /// Mapping from token text to token ID.
@@ -150,9 +243,160 @@ pub mod models {
 pub(crate) enum Model {
     #[serde(rename = "BPE")]
     Bpe(models::Bpe),
+    Unigram(models::Unigram),
     WordPiece(models::WordPiece),
 }
 
+pub mod post_processors {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::PostProcessor;
+    use crate::TokenId;
+
+    #[derive(Clone, Copy, Deserialize)]
+    pub(crate) enum SequenceId {
+        A,
+        B,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) enum TemplatePiece {
+        Sequence { id: SequenceId, type_id: usize },
+        SpecialToken { id: String, type_id: usize },
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct SpecialToken {
+        /// Token IDs which the special token is replaced with.
+        pub ids: Vec<TokenId>,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct TemplateProcessing {
+        pub single: Vec<TemplatePiece>,
+        pub pair: Vec<TemplatePiece>,
+
+        /// Map of special token name to token IDs.
+        pub special_tokens: HashMap<String, SpecialToken>,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Bert {
+        pub cls: (String, TokenId),
+        pub sep: (String, TokenId),
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Roberta {
+        pub cls: (String, TokenId),
+        pub sep: (String, TokenId),
+        #[serde(default = "super::default_true")]
+        pub trim_offsets: bool,
+        #[serde(default = "super::default_true")]
+        pub add_prefix_space: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct ByteLevel {
+        #[serde(default = "super::default_true")]
+        pub add_prefix_space: bool,
+        #[serde(default = "super::default_true")]
+        pub trim_offsets: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Sequence {
+        pub processors: Vec<PostProcessor>,
+    }
+}
+
+/// Configuration for post-processing.
+///
+/// See https://huggingface.co/docs/tokenizers/en/api/post-processors.
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub(crate) enum PostProcessor {
+    TemplateProcessing(post_processors::TemplateProcessing),
+    #[serde(rename = "BertProcessing")]
+    Bert(post_processors::Bert),
+    #[serde(rename = "RobertaProcessing")]
+    Roberta(post_processors::Roberta),
+    ByteLevel(post_processors::ByteLevel),
+    Sequence(post_processors::Sequence),
+}
+
+pub mod decoders {
+    use serde::Deserialize;
+
+    use super::{Decoder, Pattern, PrependScheme};
+
+    #[derive(Deserialize)]
+    pub(crate) struct ByteLevel {
+        #[serde(default = "super::default_true")]
+        pub add_prefix_space: bool,
+        #[serde(default = "super::default_true")]
+        pub trim_offsets: bool,
+        #[serde(default = "super::default_true")]
+        pub use_regex: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Metaspace {
+        pub replacement: char,
+
+        /// Whether a `replacement` character is added to the start of the
+        /// input when encoding. This is used in older versions of the format.
+        pub add_prefix_space: Option<bool>,
+
+        pub prepend_scheme: Option<PrependScheme>,
+
+        #[serde(default = "super::default_true")]
+        pub split: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct WordPiece {
+        pub prefix: String,
+        pub cleanup: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Strip {
+        pub content: char,
+        pub start: usize,
+        pub stop: usize,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Replace {
+        pub pattern: Pattern,
+        pub content: String,
+    }
+
+    #[derive(Deserialize)]
+    pub(crate) struct Sequence {
+        pub decoders: Vec<Decoder>,
+    }
+}
+
+/// Configuration for decoding.
+///
+/// See https://huggingface.co/docs/tokenizers/en/api/decoders.
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub(crate) enum Decoder {
+    ByteLevel(decoders::ByteLevel),
+    Metaspace(decoders::Metaspace),
+    WordPiece(decoders::WordPiece),
+    ByteFallback,
+    Fuse,
+    Strip(decoders::Strip),
+    Replace(decoders::Replace),
+    Sequence(decoders::Sequence),
+}
+
 /// Structure of the `tokenizers.json` files generated by Hugging Face
 /// tokenizers [^1].
 ///
@@ -162,6 +406,8 @@ pub(crate) struct Tokenizer {
     pub added_tokens: Option<Vec<AddedToken>>,
     pub normalizer: Option<Normalizer>,
     pub pre_tokenizer: Option<PreTokenizer>,
+    pub post_processor: Option<PostProcessor>,
+    pub decoder: Option<Decoder>,
     pub model: Model,
 }
 
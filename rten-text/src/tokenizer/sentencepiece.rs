@@ -0,0 +1,394 @@
+//! Parser for the subset of the SentencePiece model format (`.model` files)
+//! needed to construct a tokenizer.
+//!
+//! The format is a serialized `ModelProto` protobuf message, defined in
+//! <https://github.com/google/sentencepiece/blob/master/src/sentencepiece_model.proto>.
+
+use std::error::Error;
+use std::fmt;
+
+/// Error encountered while parsing a protobuf message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ParseError {}
+
+fn parse_error<T>(msg: &str) -> Result<T, ParseError> {
+    Err(ParseError(msg.to_string()))
+}
+
+/// Value of a field in a protobuf message.
+enum Value<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    fn as_bool(&self) -> Result<bool, ParseError> {
+        self.as_u64().map(|x| x != 0)
+    }
+
+    fn as_u64(&self) -> Result<u64, ParseError> {
+        match self {
+            Value::Varint(x) => Ok(*x),
+            _ => parse_error("expected varint field"),
+        }
+    }
+
+    fn as_f32(&self) -> Result<f32, ParseError> {
+        match self {
+            Value::Fixed32(x) => Ok(f32::from_bits(*x)),
+            _ => parse_error("expected float field"),
+        }
+    }
+
+    fn as_bytes(&self) -> Result<&'a [u8], ParseError> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => parse_error("expected length-delimited field"),
+        }
+    }
+
+    fn as_str(&self) -> Result<&'a str, ParseError> {
+        std::str::from_utf8(self.as_bytes()?).or(parse_error("invalid UTF-8 string"))
+    }
+}
+
+/// Reader which iterates over the fields of a protobuf message.
+struct FieldReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> FieldReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        FieldReader { data }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, ParseError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some((&byte, rest)) = self.data.split_first() else {
+                return parse_error("unexpected end of data");
+            };
+            self.data = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        parse_error("varint is too long")
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        if len > self.data.len() {
+            return parse_error("unexpected end of data");
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Read the next field and return its number and value.
+    fn read_field(&mut self) -> Result<Option<(u32, Value<'a>)>, ParseError> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let key = self.read_varint()?;
+        let field = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.read_varint()?),
+            1 => {
+                self.read_bytes(8)?;
+                Value::Fixed64
+            }
+            2 => {
+                let len = self.read_varint()? as usize;
+                Value::Bytes(self.read_bytes(len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap())),
+            _ => return parse_error("unsupported wire type"),
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+/// Type of a piece in the vocabulary.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PieceType {
+    Normal,
+    Unknown,
+    Control,
+    UserDefined,
+    Unused,
+    Byte,
+}
+
+/// A piece (token) in the vocabulary.
+pub(crate) struct Piece {
+    pub piece: String,
+    pub score: f32,
+    pub kind: PieceType,
+}
+
+/// Segmentation algorithm used by a model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ModelType {
+    Unigram,
+    Bpe,
+    Word,
+    Char,
+}
+
+/// Subset of the `TrainerSpec` message.
+pub(crate) struct TrainerSpec {
+    pub model_type: ModelType,
+    pub byte_fallback: bool,
+}
+
+/// Subset of the `NormalizerSpec` message.
+pub(crate) struct NormalizerSpec {
+    /// Compiled normalization rules.
+    pub precompiled_charsmap: Vec<u8>,
+
+    /// Add a `▁` to the start of the text.
+    pub add_dummy_prefix: bool,
+
+    /// Remove leading, trailing and duplicate spaces.
+    pub remove_extra_whitespaces: bool,
+
+    /// Replace spaces with `▁`.
+    pub escape_whitespaces: bool,
+}
+
+/// Subset of the `ModelProto` message.
+pub(crate) struct ModelProto {
+    pub pieces: Vec<Piece>,
+    pub trainer_spec: TrainerSpec,
+    pub normalizer_spec: NormalizerSpec,
+}
+
+fn parse_piece(data: &[u8]) -> Result<Piece, ParseError> {
+    let mut piece = Piece {
+        piece: String::new(),
+        score: 0.,
+        kind: PieceType::Normal,
+    };
+    let mut reader = FieldReader::new(data);
+    while let Some((field, value)) = reader.read_field()? {
+        match field {
+            1 => piece.piece = value.as_str()?.to_string(),
+            2 => piece.score = value.as_f32()?,
+            3 => {
+                piece.kind = match value.as_u64()? {
+                    1 => PieceType::Normal,
+                    2 => PieceType::Unknown,
+                    3 => PieceType::Control,
+                    4 => PieceType::UserDefined,
+                    5 => PieceType::Unused,
+                    6 => PieceType::Byte,
+                    _ => return parse_error("unknown piece type"),
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(piece)
+}
+
+fn parse_trainer_spec(data: &[u8]) -> Result<TrainerSpec, ParseError> {
+    let mut spec = TrainerSpec {
+        model_type: ModelType::Unigram,
+        byte_fallback: false,
+    };
+    let mut reader = FieldReader::new(data);
+    while let Some((field, value)) = reader.read_field()? {
+        match field {
+            3 => {
+                spec.model_type = match value.as_u64()? {
+                    1 => ModelType::Unigram,
+                    2 => ModelType::Bpe,
+                    3 => ModelType::Word,
+                    4 => ModelType::Char,
+                    _ => return parse_error("unknown model type"),
+                }
+            }
+            35 => spec.byte_fallback = value.as_bool()?,
+            _ => {}
+        }
+    }
+    Ok(spec)
+}
+
+fn parse_normalizer_spec(data: &[u8]) -> Result<NormalizerSpec, ParseError> {
+    let mut spec = NormalizerSpec {
+        precompiled_charsmap: Vec::new(),
+        add_dummy_prefix: true,
+        remove_extra_whitespaces: true,
+        escape_whitespaces: true,
+    };
+    let mut reader = FieldReader::new(data);
+    while let Some((field, value)) = reader.read_field()? {
+        match field {
+            2 => spec.precompiled_charsmap = value.as_bytes()?.to_vec(),
+            3 => spec.add_dummy_prefix = value.as_bool()?,
+            4 => spec.remove_extra_whitespaces = value.as_bool()?,
+            5 => spec.escape_whitespaces = value.as_bool()?,
+            _ => {}
+        }
+    }
+    Ok(spec)
+}
+
+/// Deserialize a SentencePiece `.model` file.
+pub fn from_bytes(data: &[u8]) -> Result<ModelProto, ParseError> {
+    let mut pieces = Vec::new();
+    let mut trainer_spec = None;
+    let mut normalizer_spec = None;
+
+    let mut reader = FieldReader::new(data);
+    while let Some((field, value)) = reader.read_field()? {
+        match field {
+            1 => pieces.push(parse_piece(value.as_bytes()?)?),
+            2 => trainer_spec = Some(parse_trainer_spec(value.as_bytes()?)?),
+            3 => normalizer_spec = Some(parse_normalizer_spec(value.as_bytes()?)?),
+            _ => {}
+        }
+    }
+
+    Ok(ModelProto {
+        pieces,
+        trainer_spec: match trainer_spec {
+            Some(spec) => spec,
+            None => parse_trainer_spec(&[])?,
+        },
+        normalizer_spec: match normalizer_spec {
+            Some(spec) => spec,
+            None => parse_normalizer_spec(&[])?,
+        },
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{from_bytes, ModelType, PieceType};
+
+    /// Minimal protobuf encoder used to construct test models.
+    #[derive(Default)]
+    pub struct MessageWriter {
+        buf: Vec<u8>,
+    }
+
+    impl MessageWriter {
+        fn write_varint(&mut self, mut value: u64) {
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    self.buf.push(byte);
+                    break;
+                }
+                self.buf.push(byte | 0x80);
+            }
+        }
+
+        pub fn varint(mut self, field: u32, value: u64) -> Self {
+            self.write_varint((field as u64) << 3);
+            self.write_varint(value);
+            self
+        }
+
+        pub fn float(mut self, field: u32, value: f32) -> Self {
+            self.write_varint(((field as u64) << 3) | 5);
+            self.buf.extend(value.to_le_bytes());
+            self
+        }
+
+        pub fn bytes(mut self, field: u32, value: &[u8]) -> Self {
+            self.write_varint(((field as u64) << 3) | 2);
+            self.write_varint(value.len() as u64);
+            self.buf.extend(value);
+            self
+        }
+
+        pub fn message(self, field: u32, value: MessageWriter) -> Self {
+            self.bytes(field, &value.buf)
+        }
+
+        pub fn finish(self) -> Vec<u8> {
+            self.buf
+        }
+    }
+
+    /// Encode a `ModelProto` with the given pieces and options.
+    pub fn encode_model(
+        pieces: &[(&str, f32, u64)],
+        model_type: u64,
+        byte_fallback: bool,
+        normalizer: MessageWriter,
+    ) -> Vec<u8> {
+        let mut model = MessageWriter::default();
+        for &(piece, score, kind) in pieces {
+            model = model.message(
+                1,
+                MessageWriter::default()
+                    .bytes(1, piece.as_bytes())
+                    .float(2, score)
+                    .varint(3, kind),
+            );
+        }
+        model
+            .message(
+                2,
+                MessageWriter::default()
+                    .varint(3, model_type)
+                    .varint(35, byte_fallback as u64)
+                    // Unknown field, which should be skipped.
+                    .bytes(100, b"ignored"),
+            )
+            .message(3, normalizer)
+            .finish()
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let data = encode_model(
+            &[("<unk>", 0., 2), ("<s>", 0., 3), ("▁a", -1.5, 1)],
+            1, /* unigram */
+            true,
+            MessageWriter::default()
+                .bytes(1, b"nmt_nfkc")
+                .varint(3, 0 /* add_dummy_prefix */),
+        );
+        let model = from_bytes(&data).unwrap();
+
+        let pieces: Vec<_> = model
+            .pieces
+            .iter()
+            .map(|p| (p.piece.as_str(), p.score, p.kind))
+            .collect();
+        assert_eq!(
+            pieces,
+            [
+                ("<unk>", 0., PieceType::Unknown),
+                ("<s>", 0., PieceType::Control),
+                ("▁a", -1.5, PieceType::Normal)
+            ]
+        );
+        assert_eq!(model.trainer_spec.model_type, ModelType::Unigram);
+        assert!(model.trainer_spec.byte_fallback);
+        assert!(!model.normalizer_spec.add_dummy_prefix);
+        assert!(model.normalizer_spec.remove_extra_whitespaces);
+        assert!(model.normalizer_spec.escape_whitespaces);
+
+        // Truncated data
+        assert!(from_bytes(&data[..data.len() - 1]).is_err());
+    }
+}
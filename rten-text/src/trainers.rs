@@ -0,0 +1,929 @@
+//! Trainers which learn the vocabulary of a tokenization model from a corpus.
+//!
+//! Training follows the same pipeline as encoding: each text in the corpus is
+//! normalized and split into words using a [`Normalizer`] and
+//! [`PreTokenizer`], and the vocabulary is then learned from the frequencies
+//! of the words.
+//!
+//! ```
+//! use rten_text::pre_tokenizers::Split;
+//! use rten_text::trainers::{BpeTrainer, BpeTrainerOptions};
+//!
+//! let mut trainer = BpeTrainer::new(BpeTrainerOptions {
+//!     vocab_size: 300,
+//!     special_tokens: vec!["<|endoftext|>".to_string()],
+//!     ..Default::default()
+//! })
+//! .with_pre_tokenizer(Box::new(Split::gpt2()));
+//! trainer.feed(["a small corpus", "of example texts"])?;
+//!
+//! let trained = trainer.train();
+//! let merges = trained.merge_lines(); // eg. for a `merges.txt` file
+//! let json = trained.to_json(); // eg. for a `tokenizer.json` file
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use crate::models::{
+    char_to_byte, Bpe, BpeError, BpeOptions, EncodedBytes, WordPiece, WordPieceOptions,
+};
+use crate::normalizers::{NormalizeError, Normalizer};
+use crate::post_processors::TemplateProcessing;
+use crate::pre_tokenizers::{PreTokenizeError, PreTokenizer};
+use crate::tokenizer::{AddedToken, TokenId, Tokenizer, TokenizerOptions};
+use crate::{decoders, pre_tokenizers};
+
+/// Errors that occur while processing a corpus for training.
+#[derive(Clone, Debug)]
+pub enum TrainError {
+    /// Normalizing a text in the corpus failed.
+    NormalizeError(NormalizeError),
+
+    /// Splitting a text in the corpus into words failed.
+    PreTokenizeError(PreTokenizeError),
+}
+
+impl fmt::Display for TrainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NormalizeError(err) => write!(f, "normalization failed: {}", err),
+            Self::PreTokenizeError(err) => write!(f, "pre-tokenization failed: {}", err),
+        }
+    }
+}
+
+impl Error for TrainError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::NormalizeError(err) => Some(err),
+            Self::PreTokenizeError(err) => Some(err),
+        }
+    }
+}
+
+impl From<NormalizeError> for TrainError {
+    fn from(err: NormalizeError) -> Self {
+        TrainError::NormalizeError(err)
+    }
+}
+
+impl From<PreTokenizeError> for TrainError {
+    fn from(err: PreTokenizeError) -> Self {
+        TrainError::PreTokenizeError(err)
+    }
+}
+
+/// Counts occurrences of words in a corpus, after normalization and
+/// pre-tokenization.
+#[derive(Default)]
+struct WordCounter {
+    normalizer: Option<Box<dyn Normalizer>>,
+    pre_tokenizer: Option<Box<dyn PreTokenizer>>,
+
+    /// Normalizer created by the pre-tokenizer. See [`PreTokenizer::normalizer`].
+    pre_tokenizer_normalizer: Option<Box<dyn Normalizer>>,
+
+    counts: HashMap<String, u64>,
+}
+
+impl WordCounter {
+    fn set_pre_tokenizer(&mut self, pre_tokenizer: Box<dyn PreTokenizer>) {
+        self.pre_tokenizer_normalizer = pre_tokenizer.normalizer();
+        self.pre_tokenizer = Some(pre_tokenizer);
+    }
+
+    fn feed<S: AsRef<str>>(
+        &mut self,
+        corpus: impl IntoIterator<Item = S>,
+    ) -> Result<(), TrainError> {
+        for text in corpus {
+            let text = text.as_ref();
+            let mut normalized = match &self.normalizer {
+                Some(normalizer) => normalizer.normalize(text)?.0,
+                None => text.to_string(),
+            };
+            if let Some(normalizer) = &self.pre_tokenizer_normalizer {
+                normalized = normalizer.normalize(&normalized)?.0;
+            }
+            let words = match &self.pre_tokenizer {
+                Some(pre_tokenizer) => pre_tokenizer.pre_tokenize(&normalized)?,
+                None => vec![normalized.as_str()],
+            };
+            for word in words.into_iter().filter(|word| !word.is_empty()) {
+                if let Some(count) = self.counts.get_mut(word) {
+                    *count += 1;
+                } else {
+                    self.counts.insert(word.to_string(), 1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the words and their counts, sorted by word.
+    ///
+    /// Sorting makes the output of training deterministic.
+    fn sorted_words(&self) -> Vec<(&str, u64)> {
+        let mut words: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(word, count)| (word.as_str(), *count))
+            .collect();
+        words.sort();
+        words
+    }
+}
+
+/// A word from the corpus, split into symbols.
+struct Word {
+    symbols: Vec<u32>,
+    count: u64,
+}
+
+/// Create a table of symbols from a set of initial symbols, and convert
+/// words from strings to sequences of symbol IDs.
+fn symbolize(
+    alphabet: BTreeSet<String>,
+    words: Vec<(Vec<String>, u64)>,
+) -> (Vec<String>, Vec<Word>) {
+    let symbols: Vec<String> = alphabet.into_iter().collect();
+    let symbol_ids: HashMap<&str, u32> = symbols
+        .iter()
+        .enumerate()
+        .map(|(id, sym)| (sym.as_str(), id as u32))
+        .collect();
+    let words = words
+        .into_iter()
+        .map(|(word, count)| Word {
+            symbols: word.iter().map(|sym| symbol_ids[sym.as_str()]).collect(),
+            count,
+        })
+        .collect();
+    (symbols, words)
+}
+
+/// Learn merges of pairs of symbols using Byte Pair Encoding.
+///
+/// At each step the most frequent pair of adjacent symbols is merged into a
+/// new symbol, until there are `max_symbols` symbols or no pairs occur at
+/// least `min_frequency` times. Ties are broken in favor of pairs of earlier
+/// symbols. `concat` creates the string for a merged symbol.
+///
+/// Returns the merged pairs in the order they were learned. New symbols are
+/// appended to `symbols`.
+fn learn_merges(
+    symbols: &mut Vec<String>,
+    words: &mut [Word],
+    max_symbols: usize,
+    min_frequency: u64,
+    concat: impl Fn(&str, &str) -> String,
+) -> Vec<(u32, u32)> {
+    type Pair = (u32, u32);
+
+    let mut pair_counts: HashMap<Pair, u64> = HashMap::new();
+    let mut pair_words: HashMap<Pair, HashSet<usize>> = HashMap::new();
+    for (i, word) in words.iter().enumerate() {
+        for pair in word.symbols.windows(2) {
+            let pair = (pair[0], pair[1]);
+            *pair_counts.entry(pair).or_default() += word.count;
+            pair_words.entry(pair).or_default().insert(i);
+        }
+    }
+
+    // Queue of pairs ordered by count. Entries become stale when the count
+    // for a pair changes, in which case a new entry is added.
+    let mut queue: BinaryHeap<(u64, Reverse<Pair>)> = pair_counts
+        .iter()
+        .map(|(pair, count)| (*count, Reverse(*pair)))
+        .collect();
+
+    let mut existing: HashSet<String> = symbols.iter().cloned().collect();
+    let mut rejected: HashSet<Pair> = HashSet::new();
+    let mut merges = Vec::new();
+
+    while symbols.len() < max_symbols {
+        let Some((count, Reverse(pair))) = queue.pop() else {
+            break;
+        };
+        if pair_counts.get(&pair) != Some(&count) || rejected.contains(&pair) {
+            continue;
+        }
+        if count < min_frequency.max(1) {
+            break;
+        }
+
+        // Merges are identified by the string they produce, so each string
+        // can only be produced by one merge.
+        let merged = concat(&symbols[pair.0 as usize], &symbols[pair.1 as usize]);
+        if !existing.insert(merged.clone()) {
+            rejected.insert(pair);
+            continue;
+        }
+
+        let new_symbol = symbols.len() as u32;
+        symbols.push(merged);
+        merges.push(pair);
+
+        let mut changed = HashSet::new();
+        let mut affected: Vec<usize> = pair_words
+            .remove(&pair)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        affected.sort();
+
+        for i in affected {
+            let word = &mut words[i];
+            let mut merged_symbols = Vec::with_capacity(word.symbols.len());
+            let mut j = 0;
+            while j < word.symbols.len() {
+                if j + 1 < word.symbols.len() && (word.symbols[j], word.symbols[j + 1]) == pair {
+                    merged_symbols.push(new_symbol);
+                    j += 2;
+                } else {
+                    merged_symbols.push(word.symbols[j]);
+                    j += 1;
+                }
+            }
+            if merged_symbols.len() == word.symbols.len() {
+                continue;
+            }
+
+            for old_pair in word.symbols.windows(2) {
+                let old_pair = (old_pair[0], old_pair[1]);
+                *pair_counts.get_mut(&old_pair).unwrap() -= word.count;
+                changed.insert(old_pair);
+            }
+            for new_pair in merged_symbols.windows(2) {
+                let new_pair = (new_pair[0], new_pair[1]);
+                *pair_counts.entry(new_pair).or_default() += word.count;
+                pair_words.entry(new_pair).or_default().insert(i);
+                changed.insert(new_pair);
+            }
+            word.symbols = merged_symbols;
+        }
+
+        for pair in changed {
+            if let Some(&count) = pair_counts.get(&pair).filter(|count| **count > 0) {
+                queue.push((count, Reverse(pair)));
+            }
+        }
+    }
+
+    merges
+}
+
+/// Assign IDs to special tokens and then to other tokens, skipping tokens
+/// which are the same as a special token.
+fn build_vocab(special_tokens: &[String], tokens: &[String]) -> HashMap<String, TokenId> {
+    let mut vocab = HashMap::with_capacity(special_tokens.len() + tokens.len());
+    for token in special_tokens.iter().chain(tokens) {
+        if !vocab.contains_key(token) {
+            vocab.insert(token.clone(), vocab.len() as TokenId);
+        }
+    }
+    vocab
+}
+
+/// Create added tokens for the special tokens in a trained vocabulary.
+fn special_added_tokens(
+    special_tokens: &[String],
+    vocab: &HashMap<String, TokenId>,
+) -> Vec<AddedToken> {
+    special_tokens
+        .iter()
+        .map(|token| AddedToken::special(vocab[token], token.as_str()))
+        .collect()
+}
+
+/// Return token strings in the order of their IDs.
+fn tokens_by_id(vocab: &HashMap<String, TokenId>) -> Vec<&str> {
+    let mut tokens: Vec<_> = vocab.iter().collect();
+    tokens.sort_by_key(|(_token, id)| **id);
+    tokens
+        .into_iter()
+        .map(|(token, _id)| token.as_str())
+        .collect()
+}
+
+/// Options for a [`BpeTrainer`].
+#[derive(Clone, Debug)]
+pub struct BpeTrainerOptions {
+    /// Maximum number of tokens in the vocabulary, including special tokens
+    /// and the initial alphabet.
+    pub vocab_size: usize,
+
+    /// Minimum number of occurrences of a pair of tokens in the corpus for it
+    /// to be merged.
+    pub min_frequency: u64,
+
+    /// Special tokens (eg. `<|endoftext|>`). These are assigned the first
+    /// IDs in the vocabulary.
+    pub special_tokens: Vec<String>,
+
+    /// If true, the initial alphabet contains tokens for all 256 byte values.
+    /// Otherwise it only contains bytes which occur in the corpus, and other
+    /// bytes cannot be encoded by the trained model.
+    pub full_byte_alphabet: bool,
+
+    /// A suffix which is appended to the last byte of each word. See
+    /// [`BpeOptions::end_of_word_suffix`].
+    pub end_of_word_suffix: Option<String>,
+}
+
+impl Default for BpeTrainerOptions {
+    fn default() -> Self {
+        BpeTrainerOptions {
+            vocab_size: 30_000,
+            min_frequency: 0,
+            special_tokens: Vec::new(),
+            full_byte_alphabet: true,
+            end_of_word_suffix: None,
+        }
+    }
+}
+
+/// Learns merges and a vocabulary for a byte-level [`Bpe`] model.
+pub struct BpeTrainer {
+    options: BpeTrainerOptions,
+    counter: WordCounter,
+}
+
+impl BpeTrainer {
+    pub fn new(options: BpeTrainerOptions) -> Self {
+        BpeTrainer {
+            options,
+            counter: WordCounter::default(),
+        }
+    }
+
+    /// Configure the normalizer applied to texts before they are split into
+    /// words.
+    pub fn with_normalizer(mut self, normalizer: Box<dyn Normalizer>) -> Self {
+        self.counter.normalizer = Some(normalizer);
+        self
+    }
+
+    /// Configure the pre-tokenizer used to split texts into words. Merges
+    /// are not learned across word boundaries.
+    ///
+    /// If the pre-tokenizer has a [normalizer](PreTokenizer::normalizer),
+    /// such as the space replacement of [`Metaspace`](crate::pre_tokenizers::Metaspace),
+    /// it is applied to texts before they are split, as in [`Tokenizer`].
+    pub fn with_pre_tokenizer(mut self, pre_tokenizer: Box<dyn PreTokenizer>) -> Self {
+        self.counter.set_pre_tokenizer(pre_tokenizer);
+        self
+    }
+
+    /// Add texts from a corpus to the training data.
+    ///
+    /// This can be called multiple times to process a corpus in batches.
+    pub fn feed<S: AsRef<str>>(
+        &mut self,
+        corpus: impl IntoIterator<Item = S>,
+    ) -> Result<(), TrainError> {
+        self.counter.feed(corpus)
+    }
+
+    /// Learn a vocabulary from the texts passed to [`feed`](Self::feed).
+    pub fn train(&self) -> TrainedBpe {
+        let mut byte_to_char = [char::default(); 256];
+        for (ch, byte) in char_to_byte() {
+            byte_to_char[byte as usize] = ch;
+        }
+        let suffix = self
+            .options
+            .end_of_word_suffix
+            .as_deref()
+            .filter(|suffix| !suffix.is_empty());
+        let encode_byte = |byte: u8, last: bool| {
+            let mut symbol = byte_to_char[byte as usize].to_string();
+            if let Some(suffix) = suffix.filter(|_| last) {
+                symbol.push_str(suffix);
+            }
+            symbol
+        };
+
+        let mut alphabet = BTreeSet::new();
+        if self.options.full_byte_alphabet {
+            for byte in 0..=255u8 {
+                alphabet.insert(encode_byte(byte, false));
+                if suffix.is_some() {
+                    alphabet.insert(encode_byte(byte, true));
+                }
+            }
+        }
+
+        let words: Vec<(Vec<String>, u64)> = self
+            .counter
+            .sorted_words()
+            .into_iter()
+            .map(|(word, count)| {
+                let bytes = word.as_bytes();
+                let symbols: Vec<String> = bytes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, byte)| encode_byte(*byte, i == bytes.len() - 1))
+                    .collect();
+                alphabet.extend(symbols.iter().cloned());
+                (symbols, count)
+            })
+            .collect();
+
+        let (mut symbols, mut words) = symbolize(alphabet, words);
+        let max_symbols = self
+            .options
+            .vocab_size
+            .saturating_sub(self.options.special_tokens.len());
+        let merges = learn_merges(
+            &mut symbols,
+            &mut words,
+            max_symbols,
+            self.options.min_frequency,
+            |a, b| [a, b].concat(),
+        );
+
+        let merges = merges
+            .into_iter()
+            .map(|(a, b)| (symbols[a as usize].clone(), symbols[b as usize].clone()))
+            .collect();
+
+        TrainedBpe {
+            vocab: build_vocab(&self.options.special_tokens, &symbols),
+            merges,
+            special_tokens: self.options.special_tokens.clone(),
+            end_of_word_suffix: suffix.map(|s| s.to_string()),
+        }
+    }
+}
+
+/// Vocabulary and merges learned by a [`BpeTrainer`].
+#[derive(Clone, Debug)]
+pub struct TrainedBpe {
+    /// Mapping from token strings to IDs.
+    ///
+    /// Token strings represent sequences of bytes, encoded using the
+    /// scheme described in [`char_to_byte`].
+    pub vocab: HashMap<EncodedBytes, TokenId>,
+
+    /// Ordered entries of the merge list.
+    pub merges: Vec<(EncodedBytes, EncodedBytes)>,
+
+    /// Special tokens. These are also included in `vocab`.
+    pub special_tokens: Vec<String>,
+
+    /// Suffix appended to the last byte of each word.
+    pub end_of_word_suffix: Option<String>,
+}
+
+impl TrainedBpe {
+    /// Return the merge list as `<token_a> [SPACE] <token_b>` lines, as used
+    /// in `merges.txt` files.
+    ///
+    /// The result can be parsed using
+    /// [`merge_pairs_from_lines`](crate::models::merge_pairs_from_lines).
+    pub fn merge_lines(&self) -> Vec<String> {
+        self.merges
+            .iter()
+            .map(|(a, b)| format!("{} {}", a, b))
+            .collect()
+    }
+
+    /// Create a [`Bpe`] model from the trained vocabulary.
+    pub fn to_model(&self) -> Result<Bpe, BpeError> {
+        let merges: Vec<(&str, &str)> = self
+            .merges
+            .iter()
+            .map(|(a, b)| (a.as_str(), b.as_str()))
+            .collect();
+        let added_tokens = self
+            .special_tokens
+            .iter()
+            .map(|token| (self.vocab[token], token.clone()))
+            .collect();
+        Bpe::new(BpeOptions {
+            merges: &merges,
+            vocab: Some(self.vocab.clone()),
+            added_tokens,
+            end_of_word_suffix: self.end_of_word_suffix.clone(),
+        })
+    }
+
+    /// Create a [`Tokenizer`] which uses the trained model.
+    ///
+    /// The tokenizer has the special tokens as added tokens, plus the
+    /// byte-level pre-tokenizer and decoder that byte-level BPE models
+    /// require. The normalizer and pre-tokenizer used for training are not
+    /// included.
+    pub fn to_tokenizer(&self) -> Result<Tokenizer, BpeError> {
+        let tokenizer = Tokenizer::new(self.to_model()?, TokenizerOptions::default())
+            .with_pre_tokenizer(Box::new(pre_tokenizers::ByteLevel::new(false)))
+            .with_decoder(Box::new(decoders::ByteLevel::default()))
+            .with_added_tokens(special_added_tokens(&self.special_tokens, &self.vocab));
+        Ok(tokenizer)
+    }
+
+    /// Serialize the tokenizer returned by [`to_tokenizer`](Self::to_tokenizer)
+    /// in the Hugging Face `tokenizer.json` format.
+    pub fn to_json(&self) -> String {
+        self.to_tokenizer()
+            .expect("trained merges should be valid")
+            .to_json()
+            .expect("tokenizer should be serializable")
+    }
+}
+
+/// Options for a [`WordPieceTrainer`].
+#[derive(Clone, Debug)]
+pub struct WordPieceTrainerOptions {
+    /// Maximum number of tokens in the vocabulary, including special tokens
+    /// and the initial alphabet.
+    pub vocab_size: usize,
+
+    /// Minimum number of occurrences of a pair of tokens in the corpus for it
+    /// to be merged.
+    pub min_frequency: u64,
+
+    /// Special tokens (eg. `[UNK]`, `[CLS]`, `[SEP]`). These are assigned the
+    /// first IDs in the vocabulary. [`WordPiece`] models encode unknown
+    /// characters as `[UNK]`, so this should usually be included.
+    pub special_tokens: Vec<String>,
+
+    /// Maximum number of distinct characters in the initial alphabet. If the
+    /// corpus has more characters, the least frequent ones are omitted.
+    pub limit_alphabet: Option<usize>,
+}
+
+impl Default for WordPieceTrainerOptions {
+    fn default() -> Self {
+        WordPieceTrainerOptions {
+            vocab_size: 30_000,
+            min_frequency: 0,
+            special_tokens: ["[PAD]", "[UNK]", "[CLS]", "[SEP]", "[MASK]"]
+                .map(String::from)
+                .into(),
+            limit_alphabet: None,
+        }
+    }
+}
+
+/// Learns a vocabulary for a [`WordPiece`] model.
+///
+/// Like Hugging Face's WordPiece trainer, this learns merges of characters
+/// using Byte Pair Encoding and uses the resulting tokens as the vocabulary.
+/// Tokens which continue a word have a `##` prefix.
+pub struct WordPieceTrainer {
+    options: WordPieceTrainerOptions,
+    counter: WordCounter,
+}
+
+/// Prefix for tokens that continue a word.
+const SUBWORD_PREFIX: &str = "##";
+
+impl WordPieceTrainer {
+    pub fn new(options: WordPieceTrainerOptions) -> Self {
+        WordPieceTrainer {
+            options,
+            counter: WordCounter::default(),
+        }
+    }
+
+    /// Configure the normalizer applied to texts before they are split into
+    /// words.
+    pub fn with_normalizer(mut self, normalizer: Box<dyn Normalizer>) -> Self {
+        self.counter.normalizer = Some(normalizer);
+        self
+    }
+
+    /// Configure the pre-tokenizer used to split texts into words.
+    ///
+    /// If the pre-tokenizer has a [normalizer](PreTokenizer::normalizer), it
+    /// is applied to texts before they are split, as in [`Tokenizer`].
+    pub fn with_pre_tokenizer(mut self, pre_tokenizer: Box<dyn PreTokenizer>) -> Self {
+        self.counter.set_pre_tokenizer(pre_tokenizer);
+        self
+    }
+
+    /// Add texts from a corpus to the training data.
+    ///
+    /// This can be called multiple times to process a corpus in batches.
+    pub fn feed<S: AsRef<str>>(
+        &mut self,
+        corpus: impl IntoIterator<Item = S>,
+    ) -> Result<(), TrainError> {
+        self.counter.feed(corpus)
+    }
+
+    /// Learn a vocabulary from the texts passed to [`feed`](Self::feed).
+    pub fn train(&self) -> TrainedWordPiece {
+        let sorted_words = self.counter.sorted_words();
+
+        // Choose the alphabet, keeping the most frequent characters if it is
+        // limited.
+        let mut char_counts: HashMap<char, u64> = HashMap::new();
+        for (word, count) in &sorted_words {
+            for ch in word.chars() {
+                *char_counts.entry(ch).or_default() += count;
+            }
+        }
+        let mut chars: Vec<(char, u64)> = char_counts.into_iter().collect();
+        chars.sort_by_key(|(ch, count)| (Reverse(*count), *ch));
+        if let Some(limit) = self.options.limit_alphabet {
+            chars.truncate(limit);
+        }
+        let allowed_chars: HashSet<char> = chars.iter().map(|(ch, _count)| *ch).collect();
+
+        let mut alphabet = BTreeSet::new();
+        let words: Vec<(Vec<String>, u64)> = sorted_words
+            .into_iter()
+            // Words with characters outside the alphabet are encoded as
+            // `[UNK]`, so they are excluded from training.
+            .filter(|(word, _count)| word.chars().all(|ch| allowed_chars.contains(&ch)))
+            .map(|(word, count)| {
+                let symbols: Vec<String> = word
+                    .chars()
+                    .enumerate()
+                    .map(|(i, ch)| {
+                        if i == 0 {
+                            ch.to_string()
+                        } else {
+                            format!("{}{}", SUBWORD_PREFIX, ch)
+                        }
+                    })
+                    .collect();
+                alphabet.extend(symbols.iter().cloned());
+                (symbols, count)
+            })
+            .collect();
+
+        let (mut symbols, mut words) = symbolize(alphabet, words);
+        let max_symbols = self
+            .options
+            .vocab_size
+            .saturating_sub(self.options.special_tokens.len());
+        learn_merges(
+            &mut symbols,
+            &mut words,
+            max_symbols,
+            self.options.min_frequency,
+            |a, b| [a, b.strip_prefix(SUBWORD_PREFIX).unwrap_or(b)].concat(),
+        );
+
+        TrainedWordPiece {
+            vocab: build_vocab(&self.options.special_tokens, &symbols),
+            special_tokens: self.options.special_tokens.clone(),
+        }
+    }
+}
+
+/// Vocabulary learned by a [`WordPieceTrainer`].
+#[derive(Clone, Debug)]
+pub struct TrainedWordPiece {
+    /// Mapping from word pieces to token IDs.
+    pub vocab: HashMap<String, TokenId>,
+
+    /// Special tokens. These are also included in `vocab`.
+    pub special_tokens: Vec<String>,
+}
+
+impl TrainedWordPiece {
+    /// Return the tokens in the vocabulary in order of ID, as used in
+    /// `vocab.txt` files.
+    pub fn vocab_lines(&self) -> Vec<&str> {
+        tokens_by_id(&self.vocab)
+    }
+
+    /// Create a [`WordPiece`] model from the trained vocabulary.
+    pub fn to_model(&self) -> WordPiece {
+        WordPiece::from_vocab(self.vocab.clone(), WordPieceOptions::default())
+    }
+
+    /// Create a [`Tokenizer`] which uses the trained model.
+    ///
+    /// The tokenizer has the special tokens as added tokens and a WordPiece
+    /// decoder. If `[CLS]` and `[SEP]` are in the vocabulary, they are added
+    /// around encoded sequences as in BERT. The normalizer and pre-tokenizer
+    /// used for training are not included.
+    pub fn to_tokenizer(&self) -> Tokenizer {
+        let post_processor = match (self.vocab.get("[CLS]"), self.vocab.get("[SEP]")) {
+            (Some(&cls), Some(&sep)) => TemplateProcessing::bert(Some(cls), Some(sep)),
+            _ => TemplateProcessing::bert(None, None),
+        };
+        Tokenizer::new(self.to_model(), TokenizerOptions::default())
+            .with_post_processor(Box::new(post_processor))
+            .with_decoder(Box::new(decoders::WordPiece::default()))
+            .with_added_tokens(special_added_tokens(&self.special_tokens, &self.vocab))
+    }
+
+    /// Serialize the tokenizer returned by [`to_tokenizer`](Self::to_tokenizer)
+    /// in the Hugging Face `tokenizer.json` format.
+    pub fn to_json(&self) -> String {
+        self.to_tokenizer()
+            .to_json()
+            .expect("tokenizer should be serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_testing::TestCases;
+
+    use super::{BpeTrainer, BpeTrainerOptions, WordPieceTrainer, WordPieceTrainerOptions};
+    use crate::models::{merge_pairs_from_lines, Model};
+    use crate::pre_tokenizers::{Metaspace, WhitespaceSplit};
+    use crate::Tokenizer;
+
+    const CORPUS: &[&str] = &["hug hug pug pun", "bun hug hugs"];
+
+    fn bpe_trainer(options: BpeTrainerOptions) -> BpeTrainer {
+        let mut trainer =
+            BpeTrainer::new(options).with_pre_tokenizer(Box::new(WhitespaceSplit::new()));
+        trainer.feed(CORPUS).unwrap();
+        trainer
+    }
+
+    #[test]
+    fn test_bpe_trainer() {
+        #[derive(Debug)]
+        struct Case {
+            vocab_size: usize,
+            min_frequency: u64,
+            expected_merges: Vec<(&'static str, &'static str)>,
+        }
+
+        // The alphabet is "bghnpsu" and there is one special token.
+        let cases = [
+            Case {
+                vocab_size: 11,
+                min_frequency: 0,
+                expected_merges: [("u", "g"), ("h", "ug"), ("u", "n")].into(),
+            },
+            Case {
+                vocab_size: 9,
+                min_frequency: 0,
+                expected_merges: [("u", "g")].into(),
+            },
+            Case {
+                vocab_size: 100,
+                min_frequency: 3,
+                expected_merges: [("u", "g"), ("h", "ug")].into(),
+            },
+        ];
+
+        cases.test_each(|case| {
+            let trainer = bpe_trainer(BpeTrainerOptions {
+                vocab_size: case.vocab_size,
+                min_frequency: case.min_frequency,
+                special_tokens: vec!["<s>".to_string()],
+                full_byte_alphabet: false,
+                ..Default::default()
+            });
+            let trained = trainer.train();
+
+            let merges: Vec<_> = trained
+                .merges
+                .iter()
+                .map(|(a, b)| (a.as_str(), b.as_str()))
+                .collect();
+            assert_eq!(merges, case.expected_merges);
+            assert_eq!(trained.vocab.len(), 8 + merges.len());
+            assert_eq!(trained.vocab.get("<s>"), Some(&0));
+
+            let lines = trained.merge_lines();
+            assert_eq!(merge_pairs_from_lines(&lines), merges);
+        })
+    }
+
+    #[test]
+    fn test_bpe_trainer_model() {
+        let trainer = bpe_trainer(BpeTrainerOptions {
+            vocab_size: 300,
+            special_tokens: vec!["<s>".to_string()],
+            ..Default::default()
+        });
+        let trained = trainer.train();
+
+        // The vocab contains the special token, all bytes and the merges.
+        assert_eq!(trained.vocab.len(), 1 + 256 + trained.merges.len());
+
+        let model = trained.to_model().unwrap();
+        let ids = model.encode("hugs").unwrap();
+        assert_eq!(model.get_tokens(&ids).unwrap(), ["hugs"]);
+        let ids = model.encode("bugs").unwrap();
+        assert_eq!(model.get_tokens(&ids).unwrap(), ["b", "ug", "s"]);
+        let ids = model.encode("é").unwrap();
+        assert_eq!(model.decode(&ids).unwrap(), "é");
+
+        let tokenizer = Tokenizer::from_json(&trained.to_json()).unwrap();
+        let encoded = tokenizer.encode("<s>hug bun", None).unwrap();
+        let tokens = tokenizer.model().get_tokens(encoded.token_ids()).unwrap();
+        assert_eq!(tokens, ["<s>", "hug", "Ġ", "bun"]);
+        assert_eq!(tokenizer.decode(encoded.token_ids()).unwrap(), "<s>hug bun");
+    }
+
+    #[test]
+    fn test_bpe_trainer_end_of_word_suffix() {
+        let trainer = bpe_trainer(BpeTrainerOptions {
+            vocab_size: 100,
+            min_frequency: 2,
+            full_byte_alphabet: false,
+            end_of_word_suffix: Some("</w>".to_string()),
+            ..Default::default()
+        });
+        let trained = trainer.train();
+        let merges: Vec<_> = trained
+            .merges
+            .iter()
+            .map(|(a, b)| (a.as_str(), b.as_str()))
+            .collect();
+        assert_eq!(merges, [("h", "u"), ("hu", "g</w>"), ("p", "u")]);
+
+        let model = trained.to_model().unwrap();
+        let ids = model.encode("hug").unwrap();
+        assert_eq!(model.get_tokens(&ids).unwrap(), ["hug</w>"]);
+    }
+
+    #[test]
+    fn test_bpe_trainer_metaspace() {
+        let mut trainer = BpeTrainer::new(BpeTrainerOptions {
+            vocab_size: 100,
+            full_byte_alphabet: false,
+            ..Default::default()
+        })
+        .with_pre_tokenizer(Box::new(Metaspace::new('▁', true)));
+        trainer.feed(["hug hug", "pug"]).unwrap();
+        let trained = trainer.train();
+
+        // The pre-tokenizer's normalizer replaces spaces and adds a prefix
+        // before splitting, so words are learned with the `▁` marker. The
+        // marker is encoded as "âĸģ" in the byte-level vocabulary.
+        let merges: Vec<_> = trained
+            .merges
+            .iter()
+            .map(|(a, b)| (a.as_str(), b.as_str()))
+            .collect();
+        assert_eq!(
+            merges,
+            [
+                ("u", "g"),
+                ("â", "ĸ"),
+                ("âĸ", "ģ"),
+                ("h", "ug"),
+                ("âĸģ", "hug"),
+                ("p", "ug"),
+                ("âĸģ", "pug"),
+            ]
+        );
+        assert!(!trained.vocab.contains_key("Ġ"));
+    }
+
+    #[test]
+    fn test_wordpiece_trainer() {
+        let mut trainer = WordPieceTrainer::new(WordPieceTrainerOptions {
+            vocab_size: 11,
+            special_tokens: vec!["[UNK]".to_string()],
+            ..Default::default()
+        })
+        .with_pre_tokenizer(Box::new(WhitespaceSplit::new()));
+        trainer.feed(CORPUS).unwrap();
+        let trained = trainer.train();
+
+        assert_eq!(
+            trained.vocab_lines(),
+            ["[UNK]", "##g", "##n", "##s", "##u", "b", "h", "p", "##ug", "hug", "##un"]
+        );
+
+        let model = trained.to_model();
+        let ids = model.encode("hugs").unwrap();
+        assert_eq!(model.get_tokens(&ids).unwrap(), ["hug", "##s"]);
+        let ids = model.encode("bun").unwrap();
+        assert_eq!(model.get_tokens(&ids).unwrap(), ["b", "##un"]);
+
+        let tokenizer = Tokenizer::from_json(&trained.to_json()).unwrap();
+        let encoded = tokenizer.encode("hugs", None).unwrap();
+        let tokens = tokenizer.model().get_tokens(encoded.token_ids()).unwrap();
+        assert_eq!(tokens, ["hug", "##s"]);
+        assert_eq!(tokenizer.decode(encoded.token_ids()).unwrap(), "hugs");
+    }
+
+    #[test]
+    fn test_wordpiece_trainer_limit_alphabet() {
+        let mut trainer = WordPieceTrainer::new(WordPieceTrainerOptions {
+            special_tokens: vec!["[UNK]".to_string()],
+            limit_alphabet: Some(2),
+            ..Default::default()
+        });
+        trainer.feed(["aab", "aab", "c"]).unwrap();
+        let trained = trainer.train();
+
+        // "c" is the least frequent character, so it is omitted.
+        assert_eq!(
+            trained.vocab_lines(),
+            ["[UNK]", "##a", "##b", "a", "##ab", "aab"]
+        );
+    }
+}
@@ -4,6 +4,7 @@ use std::fs::read_to_string;
 use std::io;
 use std::path::PathBuf;
 
+use rten_tensor::prelude::*;
 use rten_text::models::{merge_pairs_from_lines, Bpe, BpeOptions, WordPiece};
 use rten_text::tokenizer::{TokenId, Tokenizer, TokenizerOptions};
 use rten_text::{normalizers, pre_tokenizers};
@@ -72,6 +73,34 @@ fn compare_tokens(actual: &[TokenId], expected: &[TokenId]) -> Result<(), Box<dy
     Ok(())
 }
 
+/// Encode each non-empty line of `text` individually and as a batch, and
+/// return an error if the results differ.
+///
+/// Inputs in a batch are encoded in parallel, so this checks that parallel
+/// encoding and caches shared between threads don't affect the output.
+fn compare_batch_encoding(tokenizer: &Tokenizer, text: &str) -> Result<(), Box<dyn Error>> {
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let batch = tokenizer.encode_batch(lines.iter().copied(), Default::default())?;
+
+    for (i, line) in lines.into_iter().enumerate() {
+        let expected = tokenizer.encode(line, None)?;
+        let actual: Vec<TokenId> = batch
+            .input_ids
+            .slice(i)
+            .iter()
+            .zip(batch.attention_mask.slice(i).iter())
+            .filter(|(_id, mask)| **mask == 1)
+            .map(|(id, _mask)| *id as TokenId)
+            .collect();
+        compare_tokens(&actual, expected.token_ids())?;
+    }
+
+    Ok(())
+}
+
 fn wordpiece_tokenizer_opts() -> TokenizerOptions<'static> {
     TokenizerOptions {
         cls_token: Some("[CLS]"),
@@ -92,6 +121,7 @@ fn test_wordpiece_bert_cased() -> Result<(), Box<dyn Error>> {
     let encoded = tokenizer.encode(text.as_str(), None)?;
 
     compare_tokens(encoded.token_ids(), &expected.token_ids)?;
+    compare_batch_encoding(&tokenizer, &text)?;
 
     Ok(())
 }
@@ -139,6 +169,7 @@ fn test_wordpiece_bert_uncased() -> Result<(), Box<dyn Error>> {
         let encoded = tokenizer.encode(text.as_str(), None)?;
 
         compare_tokens(encoded.token_ids(), &expected.token_ids)?;
+        compare_batch_encoding(&tokenizer, &text)?;
     }
 
     Ok(())
@@ -168,10 +199,22 @@ fn test_bpe_gpt2() -> Result<(), Box<dyn Error>> {
     let tokenizer = Tokenizer::new(model, Default::default())
         .with_pre_tokenizer(Box::new(pre_tokenizers::Split::gpt2()));
 
+    // Create tokenizer with encoding cache disabled.
+    let uncached_model = Bpe::new(BpeOptions {
+        merges: &merge_pairs,
+        ..Default::default()
+    })?
+    .with_cache_capacity(0);
+    let uncached_tokenizer = Tokenizer::new(uncached_model, Default::default())
+        .with_pre_tokenizer(Box::new(pre_tokenizers::Split::gpt2()));
+
     // Create tokenizer from a `tokenizers.json` file.
     let tokenizer_path = test_file_path("models/gpt2/tokenizer.json");
     let tokenizer_from_json = Tokenizer::from_file(&tokenizer_path)?;
 
+    // Serialize the tokenizer and load it again.
+    let tokenizer_round_trip = Tokenizer::from_json(&tokenizer_from_json.to_json()?)?;
+
     for Case { text, reference } in cases {
         let text = read_test_file(text)?;
         let expected = ReferenceTokenization::from_file(reference)?;
@@ -179,8 +222,25 @@ fn test_bpe_gpt2() -> Result<(), Box<dyn Error>> {
         let encoded = tokenizer.encode(text.as_str(), None)?;
         compare_tokens(encoded.token_ids(), &expected.token_ids)?;
 
+        // Encode again, using tokens cached from the first pass.
+        let encoded = tokenizer.encode(text.as_str(), None)?;
+        compare_tokens(encoded.token_ids(), &expected.token_ids)?;
+
+        let encoded = uncached_tokenizer.encode(text.as_str(), None)?;
+        compare_tokens(encoded.token_ids(), &expected.token_ids)?;
+
+        compare_batch_encoding(&tokenizer, &text)?;
+        compare_batch_encoding(&uncached_tokenizer, &text)?;
+
         let encoded = tokenizer_from_json.encode(text.as_str(), None)?;
         compare_tokens(encoded.token_ids(), &expected.token_ids)?;
+
+        let encoded = tokenizer_round_trip.encode(text.as_str(), None)?;
+        compare_tokens(encoded.token_ids(), &expected.token_ids)?;
+        assert_eq!(
+            tokenizer_round_trip.decode(encoded.token_ids())?,
+            tokenizer_from_json.decode(encoded.token_ids())?
+        );
     }
 
     Ok(())
@@ -104,6 +104,13 @@ impl<T> ArcSlice<T> {
             phantom: PhantomData,
         })
     }
+
+    /// Return the referenced data as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        // Safety: We checked the data range was in-bounds and aligned when
+        // the ArcSlice was constructed.
+        unsafe { std::slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
 }
 
 unsafe impl<T> Storage for ArcSlice<T> {
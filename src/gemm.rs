@@ -2,8 +2,43 @@
 //!
 //! This module provides a subset of BLAS-like functions that are used by neural
 //! network operators. The primary functionality is general matrix
-//! multiplication (gemm) with ML-oriented additions, but there are also
-//! operations like vector-scalar products.
+//! multiplication (gemm) with ML-oriented additions such as bias addition,
+//! fused activations and int8 quantized inputs.
+//!
+//! Matrix multiplications are performed using a [`GemmExecutor`], which selects
+//! the best available kernel for the current system at runtime. A specific
+//! kernel can be requested using [`WithKernel::with_kernel`]. Inputs and outputs
+//! are [rten-tensor](rten_tensor) views or slices.
+//!
+//! ## Example
+//!
+//! ```
+//! use rten::gemm::{BiasVector, GemmExecutor, GemmInputA, GemmInputB};
+//! use rten_tensor::prelude::*;
+//! use rten_tensor::NdTensor;
+//!
+//! let a = NdTensor::from([[1., 2.], [3., 4.]]);
+//! let b = NdTensor::from([[5., 6.], [7., 8.]]);
+//!
+//! let gemm = GemmExecutor::<f32>::new();
+//!
+//! // Pack `b` once if it will be re-used for several multiplications.
+//! let packed_b = gemm.prepack_b(b.view());
+//!
+//! let bias = [1., 2.];
+//! let output = gemm
+//!     .matmul(
+//!         GemmInputA::Unpacked(a.view()),
+//!         GemmInputB::Packed(&packed_b),
+//!         1.0,                          /* alpha */
+//!         Some(BiasVector::Row(&bias)), /* bias */
+//!         None,                         /* a_quant */
+//!         None,                         /* b_quant */
+//!     )
+//!     .unwrap();
+//!
+//! assert_eq!(output, NdTensor::from([[20., 24.], [44., 52.]]));
+//! ```
 
 use std::cell::RefCell;
 use std::marker::PhantomData;
@@ -13,13 +48,16 @@ use std::ops::{Add, Mul, Range};
 use rayon::prelude::*;
 use rten_tensor::prelude::*;
 use rten_tensor::{
-    Alloc, AssumeInit, GlobalAlloc, Matrix, MatrixLayout, MatrixMut, NdTensorView, Storage,
+    Alloc, AssumeInit, GlobalAlloc, Matrix, MatrixLayout, MatrixMut, NdTensor, NdTensorView,
+    Storage,
 };
 
+use crate::constant_storage::ArcSlice;
 use crate::iter_util::{range_chunks, MaybeParIter};
 use crate::number::Identities;
 use crate::slice_cast::Pod;
 
+mod activation;
 mod errors;
 mod im2col;
 mod kernels;
@@ -27,11 +65,13 @@ mod packing;
 mod prepack;
 mod tiles;
 
+pub use activation::Activation;
 pub use errors::GemmError;
-pub use im2col::{ColOffsets, Im2Col, RowOffsets};
+pub(crate) use im2col::{ColOffsets, Im2Col, RowOffsets};
 use kernels::generic::GenericKernel;
 pub use kernels::QuantParams;
 use kernels::{Kernel, MatVecOutput};
+pub(crate) use packing::PackElem;
 use packing::PackingBuffer;
 pub use prepack::{PackedAMatrix, PackedBMatrix};
 use tiles::OutputTiles;
@@ -94,13 +134,37 @@ pub enum GemmInputB<'a, T> {
 
     /// A matrix which has been pre-packed by [`GemmExecutor::prepack_b`].
     Packed(&'a PackedBMatrix<T>),
+}
+
+impl<T> GemmInputB<'_, T> {
+    pub fn rows(&self) -> usize {
+        match self {
+            Self::Unpacked(m) => m.rows(),
+            Self::Packed(pm) => pm.rows(),
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        match self {
+            Self::Unpacked(m) => m.cols(),
+            Self::Packed(pm) => pm.cols(),
+        }
+    }
+}
+
+/// Right-hand or "B" input for a GEMM operation, including input types which
+/// are only used internally.
+#[derive(Copy, Clone)]
+enum GemmRhs<'a, T> {
+    Unpacked(Matrix<'a, T>),
+    Packed(&'a PackedBMatrix<T>),
 
     /// An image which is transformed into a matrix using an im2col transformation.
     Im2Col(&'a Im2Col<'a, T>),
 }
 
-impl<T: Copy + Default> GemmInputB<'_, T> {
-    pub fn rows(&self) -> usize {
+impl<T: Copy + Default> GemmRhs<'_, T> {
+    fn rows(&self) -> usize {
         match self {
             Self::Unpacked(m) => m.rows(),
             Self::Packed(pm) => pm.rows(),
@@ -108,7 +172,7 @@ impl<T: Copy + Default> GemmInputB<'_, T> {
         }
     }
 
-    pub fn cols(&self) -> usize {
+    fn cols(&self) -> usize {
         match self {
             Self::Unpacked(m) => m.cols(),
             Self::Packed(pm) => pm.cols(),
@@ -117,6 +181,15 @@ impl<T: Copy + Default> GemmInputB<'_, T> {
     }
 }
 
+impl<'a, T> From<GemmInputB<'a, T>> for GemmRhs<'a, T> {
+    fn from(b: GemmInputB<'a, T>) -> Self {
+        match b {
+            GemmInputB::Unpacked(m) => Self::Unpacked(m),
+            GemmInputB::Packed(pm) => Self::Packed(pm),
+        }
+    }
+}
+
 /// A bias to add to the output of a matrix multiplication.
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum BiasVector<'a, T> {
@@ -129,11 +202,12 @@ pub enum BiasVector<'a, T> {
     Row(&'a [T]),
 }
 
-/// Argument for [`GemmExecutor::with_kernel`] specifying which kernel to use.
-#[derive(Clone, Copy, Debug)]
-enum F32KernelType {
+/// Argument for [`WithKernel::with_kernel`] specifying which f32 kernel to
+/// use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum F32KernelType {
     /// Use the fallback/generic kernel. Always available.
-    #[allow(unused)]
     Generic,
 
     /// Use the AVX 2 + FMA kernel. Intel x64 only.
@@ -155,22 +229,32 @@ enum F32KernelType {
     Wasm,
 }
 
-#[derive(Clone, Copy, Debug)]
-enum Int8KernelType {
-    #[allow(unused)]
+/// Argument for [`WithKernel::with_kernel`] specifying which int8 kernel to
+/// use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Int8KernelType {
+    /// Use the fallback/generic kernel. Always available.
     Generic,
 
+    /// Use the AVX 2 kernel. Intel x64 only.
     #[cfg(target_arch = "x86_64")]
     Avx2,
+
+    /// Use the AVX 512 kernel. Intel x64 only.
     #[cfg(target_arch = "x86_64")]
     #[cfg(feature = "avx512")]
     Avx512,
 
+    /// Use the ARM kernel using dot product instructions. ARM 64 only.
     #[cfg(target_arch = "aarch64")]
     ArmDot,
+
+    /// Use the ARM NEON kernel. ARM 64 only.
     #[cfg(target_arch = "aarch64")]
     ArmNeon,
 
+    /// Use the WASM SIMD kernel. WASM only.
     #[cfg(target_arch = "wasm32")]
     #[cfg(target_feature = "simd128")]
     Wasm,
@@ -178,9 +262,13 @@ enum Int8KernelType {
 
 /// Executes matrix multiplication operations.
 ///
-/// For simple use cases, the standalone [`gemm`] function can be used.
-/// GemmExecutor provides a more advanced API that enables features such as
-/// performing matrix multiplications with pre-packed inputs.
+/// The type parameters specify the element types of the LHS / "A" input, the
+/// RHS / "B" input and the output. Supported combinations are
+/// `GemmExecutor<f32>` and `GemmExecutor<u8, i8, i32>`.
+///
+/// For simple use cases, [`matmul`](GemmExecutor::matmul) returns the result
+/// as a new tensor. [`gemm`](GemmExecutor::gemm) provides more control by
+/// writing into an existing buffer.
 ///
 /// ## Prepacking
 ///
@@ -201,13 +289,11 @@ impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT> GemmExecutor<LhsT, RhsT, OutT
     }
 
     /// Return the name of the kernel that this executor is using.
-    #[allow(dead_code)]
     pub fn kernel_name(&self) -> &str {
         self.kernel.name()
     }
 
     /// Prepack a matrix for use as the left-hand or "A" input.
-    #[allow(unused)]
     pub fn prepack_a(&self, a: Matrix<LhsT>) -> PackedAMatrix<LhsT> {
         self.prepack_a_in(GlobalAlloc::new(), a)
     }
@@ -221,19 +307,18 @@ impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT> GemmExecutor<LhsT, RhsT, OutT
     /// Return column count step for building an [`Im2Col`] input.
     ///
     /// The number of columns in [`ColOffsets`] must be a multiple of this.
-    pub fn im2col_col_count_step(&self) -> usize {
+    pub(crate) fn im2col_col_count_step(&self) -> usize {
         self.kernel.im2col_col_count_step()
     }
 
     /// Return row count step for building an [`Im2Col`] input.
     ///
     /// The number of rows in [`RowOffsets`] must be a multiple of this.
-    pub fn im2col_row_count_step(&self) -> usize {
+    pub(crate) fn im2col_row_count_step(&self) -> usize {
         self.kernel.im2col_row_count_step()
     }
 
     /// Prepack a matrix for use as the right-hand or "B" matrix input.
-    #[allow(unused)]
     pub fn prepack_b(&self, b: Matrix<RhsT>) -> PackedBMatrix<RhsT> {
         self.prepack_b_in(GlobalAlloc::new(), b)
     }
@@ -244,6 +329,24 @@ impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT> GemmExecutor<LhsT, RhsT, OutT
         prepack::prepack_b(&*self.kernel, alloc, b)
     }
 
+    /// Reconstruct a matrix prepacked by [`prepack_b`](GemmExecutor::prepack_b)
+    /// from its packed data.
+    ///
+    /// `kernel_name` is the name of the kernel the data was packed for, as
+    /// returned by [`PackedBMatrix::kernel_name`], and `rows` and `cols` are
+    /// the size of the original matrix. Returns `None` if the data was packed
+    /// for a different kernel than the one this executor uses, or the data
+    /// length doesn't match the expected layout.
+    pub(crate) fn restore_packed_b(
+        &self,
+        kernel_name: &str,
+        rows: usize,
+        cols: usize,
+        data: ArcSlice<PackElem>,
+    ) -> Option<PackedBMatrix<RhsT>> {
+        prepack::restore_b(&*self.kernel, kernel_name, rows, cols, data)
+    }
+
     /// Perform a General Matrix Multiplication ("gemm").
     ///
     /// This computes `output = alpha * (a @ b) + beta * output` where `@` is
@@ -270,10 +373,11 @@ impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT> GemmExecutor<LhsT, RhsT, OutT
             unsafe { std::mem::transmute::<&mut [OutT], &mut [MaybeUninit<OutT>]>(out_data) },
             out_row_stride,
             a,
-            b,
+            b.into(),
             alpha,
             beta,
             bias,
+            None, // epilogue
             a_quant,
             b_quant,
         )
@@ -299,15 +403,107 @@ impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT> GemmExecutor<LhsT, RhsT, OutT
             out_data,
             out_row_stride,
             a,
-            b,
+            b.into(),
             alpha,
             OutT::zero(),
             bias,
+            None, // epilogue
             a_quant,
             b_quant,
         )
     }
 
+    /// Perform a General Matrix Multiplication where the "B" input is an
+    /// image transformed using an im2col transformation.
+    ///
+    /// This is the same as [`GemmExecutor::gemm_uninit`] but takes an
+    /// [`Im2Col`] input. This is used to implement convolution.
+    pub(crate) fn gemm_uninit_im2col(
+        &self,
+        out_data: &mut [MaybeUninit<OutT>],
+        out_row_stride: usize,
+        a: GemmInputA<LhsT>,
+        b: &Im2Col<RhsT>,
+        alpha: f32,
+        bias: Option<BiasVector<OutT>>,
+        a_quant: Option<QuantParams<LhsT>>,
+        b_quant: Option<QuantParams<RhsT>>,
+    ) -> GemmResult {
+        gemm_impl(
+            &*self.kernel,
+            out_data,
+            out_row_stride,
+            a,
+            GemmRhs::Im2Col(b),
+            alpha,
+            OutT::zero(),
+            bias,
+            None, // epilogue
+            a_quant,
+            b_quant,
+        )
+    }
+
+    /// Multiply matrices and return the result as a new matrix.
+    ///
+    /// This computes `alpha * (a @ b) + bias` where `@` is matrix
+    /// multiplication. See [`gemm`](GemmExecutor::gemm) for details of the
+    /// parameters.
+    pub fn matmul(
+        &self,
+        a: GemmInputA<LhsT>,
+        b: GemmInputB<RhsT>,
+        alpha: f32,
+        bias: Option<BiasVector<OutT>>,
+        a_quant: Option<QuantParams<LhsT>>,
+        b_quant: Option<QuantParams<RhsT>>,
+    ) -> Result<NdTensor<OutT, 2>, GemmError> {
+        let mut output = NdTensor::uninit([a.rows(), b.cols()]);
+        let out_row_stride = output.stride(0);
+        self.gemm_uninit(
+            output.data_mut().unwrap(),
+            out_row_stride,
+            a,
+            b,
+            alpha,
+            bias,
+            a_quant,
+            b_quant,
+        )?;
+
+        // Safety: `gemm_uninit` initialized all output elements.
+        Ok(unsafe { output.assume_init() })
+    }
+
+    /// Compute a vector-matrix product ("gemv") and return the result as a
+    /// new vector.
+    ///
+    /// This computes `alpha * (a @ b) + bias`. The length of `bias` must
+    /// match the number of columns in `b`. If `a_quant` is specified, its
+    /// zero point must have a single element.
+    pub fn gemv(
+        &self,
+        a: NdTensorView<LhsT, 1>,
+        b: GemmInputB<RhsT>,
+        alpha: f32,
+        bias: Option<&[OutT]>,
+        a_quant: Option<QuantParams<LhsT>>,
+        b_quant: Option<QuantParams<RhsT>>,
+    ) -> Result<NdTensor<OutT, 1>, GemmError> {
+        let a = a.to_contiguous();
+        let a_mat = a.reshaped([1, a.size(0)]);
+        let output = self.matmul(
+            GemmInputA::Unpacked(a_mat.view()),
+            b,
+            alpha,
+            bias.map(BiasVector::Row),
+            a_quant,
+            b_quant,
+        )?;
+        let len = output.size(1);
+        Ok(output.into_shape([len]))
+    }
+
     /// Return true if the GEMM kernel may encounter saturation in a data type
     /// that is smaller than the output.
     ///
@@ -327,6 +523,42 @@ impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT> GemmExecutor<LhsT, RhsT, OutT
     }
 }
 
+impl GemmExecutor<f32> {
+    /// Multiply matrices and apply an activation function to the result.
+    ///
+    /// This computes `act(alpha * (a @ b) + bias)`. The result is the same as
+    /// calling [`matmul`](GemmExecutor::matmul) followed by
+    /// [`Activation::apply`], but the activation is applied to each output
+    /// tile as soon as it has been computed, while it is still in cache.
+    pub fn matmul_act(
+        &self,
+        a: GemmInputA<f32>,
+        b: GemmInputB<f32>,
+        alpha: f32,
+        bias: Option<BiasVector<f32>>,
+        activation: Activation,
+    ) -> Result<NdTensor<f32, 2>, GemmError> {
+        let mut output = NdTensor::uninit([a.rows(), b.cols()]);
+        let out_row_stride = output.stride(0);
+        gemm_impl(
+            &*self.kernel,
+            output.data_mut().unwrap(),
+            out_row_stride,
+            a,
+            b.into(),
+            alpha,
+            0., // beta
+            bias,
+            Some(&|x: &mut [f32]| activation.apply_chunk(x)),
+            None, // a_quant
+            None, // b_quant
+        )?;
+
+        // Safety: `gemm_impl` initialized all output elements.
+        Ok(unsafe { output.assume_init() })
+    }
+}
+
 /// Try to construct a [`GemmExecutor`] with a given kernel type.
 macro_rules! try_kernel {
     ($hint:expr) => {
@@ -338,9 +570,10 @@ macro_rules! try_kernel {
 
 /// Trait for instantiating a [`GemmExecutor`] with a particular kernel.
 ///
-/// This primarily exists to support creating tests which are run against
-/// all available kernels.
-trait WithKernel: Sized {
+/// [`GemmExecutor::new`] selects the fastest kernel supported by the current
+/// system. This trait can be used to override that choice, for example to
+/// compare results or performance across kernels.
+pub trait WithKernel: Sized {
     /// Enum specifying kernel to use.
     type KernelType;
 
@@ -351,8 +584,8 @@ trait WithKernel: Sized {
     /// Instantiate this executor with the generic/fallback kernel.
     fn with_generic_kernel() -> Self;
 
-    /// Return all the kernel types supported on the current system.
-    #[allow(unused)]
+    /// Return all the kernel types supported on the current system, in
+    /// order of preference.
     fn kernel_types() -> Vec<Self::KernelType>;
 }
 
@@ -361,7 +594,6 @@ impl WithKernel for GemmExecutor<f32, f32, f32> {
 
     /// Create a [`GemmExecutor`] using the given kernel. Returns `None` if the
     /// kernel is not supported.
-    #[allow(dead_code)] // Currently only used in tests
     fn with_kernel(hint: F32KernelType) -> Option<Self> {
         match hint {
             #[cfg(feature = "avx512")]
@@ -539,6 +771,12 @@ fn row_block_size(a_rows: usize, mr: usize) -> usize {
     64.min(a_rows).next_multiple_of(mr)
 }
 
+/// Function applied to each row of an output tile after its final update.
+///
+/// This is used to fuse operations such as activations into a matrix
+/// multiplication. The function is applied after `beta` and `bias`.
+type Epilogue<'a, T> = &'a (dyn Fn(&mut [T]) + Sync);
+
 /// Compute a vector-matrix product.
 ///
 /// This operation is called "gemv" in BLAS APIs.
@@ -550,6 +788,7 @@ fn gemv<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
     alpha: f32,
     beta: OutT,
     bias: Option<BiasVector<OutT>>,
+    epilogue: Option<Epilogue<OutT>>,
     a_quant: Option<QuantParams<LhsT>>,
     b_quant: Option<QuantParams<RhsT>>,
 ) {
@@ -607,7 +846,7 @@ fn gemv<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
             match bias {
                 Some(BiasVector::Column(bias)) => {
                     let bias = bias[0];
-                    for x in out_chunk {
+                    for x in out_chunk.iter_mut() {
                         *x = *x + bias;
                     }
                 }
@@ -619,6 +858,10 @@ fn gemv<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
                 }
                 None => {}
             }
+
+            if let Some(epilogue) = epilogue {
+                epilogue(out_chunk);
+            }
         });
 }
 
@@ -654,10 +897,11 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
     out_data: &mut [MaybeUninit<OutT>],
     out_row_stride: usize,
     a: GemmInputA<LhsT>,
-    b: GemmInputB<RhsT>,
+    b: GemmRhs<RhsT>,
     alpha: f32,
     beta: OutT,
     bias: Option<BiasVector<OutT>>,
+    epilogue: Option<Epilogue<OutT>>,
     a_quant: Option<QuantParams<LhsT>>,
     b_quant: Option<QuantParams<RhsT>>,
 ) -> GemmResult {
@@ -730,11 +974,18 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
                 }
             }
         }
+
+        if let Some(epilogue) = epilogue {
+            for r in 0..a.rows() {
+                // Output rows are contiguous, as the column stride is 1.
+                epilogue(output_mat.slice_mut(r).data_mut().unwrap());
+            }
+        }
         return Ok(());
     }
 
     // Use optimized path for vector-matrix products.
-    if let (1, GemmInputA::Unpacked(a), GemmInputB::Unpacked(b)) = (a.rows(), a, b) {
+    if let (1, GemmInputA::Unpacked(a), GemmRhs::Unpacked(b)) = (a.rows(), a, b) {
         gemv(
             kernel,
             a.slice(0),
@@ -745,6 +996,7 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
             // nb. We checked above that, if present, the bias length matches
             // `a.rows()` or `b.cols()` as appropriate.
             bias,
+            epilogue,
             a_quant,
             b_quant,
         );
@@ -767,7 +1019,7 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
     if let GemmInputA::Packed(packed) = &a {
         packed.validate(kernel, kc)?;
     }
-    if let GemmInputB::Packed(packed) = &b {
+    if let GemmRhs::Packed(packed) = &b {
         packed.validate(kernel, kc)?;
     }
 
@@ -800,7 +1052,7 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
                 let mut thread_local_packed_b: Option<PackingBuffer> = None;
 
                 let rhs_block = match b {
-                    GemmInputB::Unpacked(_) | GemmInputB::Im2Col(_) => PACKED_B.with(|cell| {
+                    GemmRhs::Unpacked(_) | GemmRhs::Im2Col(_) => PACKED_B.with(|cell| {
                         let mut packed_b = cell.take();
 
                         let layout =
@@ -808,20 +1060,20 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
                         let packed_uninit = packed_b.alloc(layout.size(), layout.align());
 
                         match b {
-                            GemmInputB::Unpacked(b) => kernel.pack_b_block(
+                            GemmRhs::Unpacked(b) => kernel.pack_b_block(
                                 packed_uninit,
                                 b,
                                 depth_range.clone(),
                                 col_start..col_end,
                                 b_quant,
                             ),
-                            GemmInputB::Im2Col(im) => kernel.pack_im2col(
+                            GemmRhs::Im2Col(im) => kernel.pack_im2col(
                                 packed_uninit,
                                 im,
                                 depth_range.clone(),
                                 col_start..col_end,
                             ),
-                            GemmInputB::Packed(_) => unreachable!(),
+                            GemmRhs::Packed(_) => unreachable!(),
                         }
 
                         // Safety: `pack_b_block` will have initialized `layout.size()` bytes.
@@ -835,7 +1087,7 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
                             _marker: PhantomData,
                         }
                     }),
-                    GemmInputB::Packed(pm) => pm.block(col_range.clone(), depth_block_idx),
+                    GemmRhs::Packed(pm) => pm.block(col_range.clone(), depth_block_idx),
                 };
 
                 // Only use provided `beta` on the first write to this output
@@ -846,6 +1098,13 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
                     OutT::one()
                 };
 
+                // Apply the epilogue after the last update to each output tile.
+                let effective_epilogue = if depth_range.end == a.cols() {
+                    epilogue
+                } else {
+                    None
+                };
+
                 // Loop over row blocks.
                 (0..n_row_blocks)
                     .maybe_par_iter(parallel)
@@ -905,6 +1164,7 @@ fn gemm_impl<LhsT: GemmInT, RhsT: GemmInT, OutT: GemmOutT>(
                             alpha,
                             effective_beta,
                             bias,
+                            effective_epilogue,
                             a_quant,
                             b_quant,
                         );
@@ -955,7 +1215,8 @@ struct RhsBlock<'a, T> {
 ///
 /// `col_tiles` and `row_tiles` specifies the range of output tiles to update.
 /// `a` and `b` are the inputs. `depth_range` specifies the range along the K
-/// dimension.
+/// dimension. `epilogue` is applied to each updated tile and should only be
+/// set for the last block along the K dimension.
 fn gemm_block<LhsT, RhsT, OutT: GemmOutT>(
     kernel: &dyn Kernel<LhsT, RhsT, OutT>,
     output: &OutputTiles<MaybeUninit<OutT>>,
@@ -967,6 +1228,7 @@ fn gemm_block<LhsT, RhsT, OutT: GemmOutT>(
     alpha: f32,
     beta: OutT,
     bias: Option<BiasVector<OutT>>,
+    epilogue: Option<Epilogue<OutT>>,
     a_quant: Option<QuantParams<LhsT>>,
     b_quant: Option<QuantParams<RhsT>>,
 ) {
@@ -1048,11 +1310,12 @@ fn gemm_block<LhsT, RhsT, OutT: GemmOutT>(
                     );
                 }
 
+                // After the kernel is called, all elements of the output
+                // tile are now initialized.
+                let out_ptr = out_tile.ptr as *mut OutT;
+
                 // Add bias vector on first write to an output tile.
                 if depth_range.start == 0 {
-                    // After the kernel is called, all elements of the output
-                    // tile are now initialized.
-                    let out_ptr = out_tile.ptr as *mut OutT;
                     match bias {
                         Some(BiasVector::Column(bias)) => {
                             for row in 0..out_tile.used_rows {
@@ -1085,6 +1348,21 @@ fn gemm_block<LhsT, RhsT, OutT: GemmOutT>(
                         None => {}
                     }
                 }
+
+                if let Some(epilogue) = epilogue {
+                    for row in 0..out_tile.used_rows {
+                        // Safety:
+                        //  - Row index and column count are valid for current tile
+                        //  - The output tile is initialized
+                        let out_row = unsafe {
+                            std::slice::from_raw_parts_mut(
+                                out_ptr.add(row * out_tile.row_stride),
+                                out_tile.used_cols,
+                            )
+                        };
+                        epilogue(out_row);
+                    }
+                }
             }
         });
 }
@@ -1098,18 +1376,20 @@ pub use reduced_range_rng::ReducedRangeRng;
 #[cfg(test)]
 mod tests {
     use std::error::Error;
+    use std::f32::consts::SQRT_2;
     use std::time::Instant;
 
     use rten_bench::run_bench;
     use rten_tensor::prelude::*;
     use rten_tensor::rng::XorShiftRng;
-    use rten_tensor::test_util::{expect_equal, ApproxEq};
+    use rten_tensor::test_util::{expect_equal, expect_equal_with_tolerance, ApproxEq};
     use rten_tensor::{Matrix, MatrixLayout, MatrixMut, NdTensor, NdTensorView, RandomSource};
     use rten_testing::TestCases;
 
     use super::{
-        BiasVector, ColOffsets, F32KernelType, GemmError, GemmExecutor, GemmInT, GemmInputA,
-        GemmInputB, GemmOutT, Im2Col, QuantParams, ReducedRangeRng, RowOffsets, WithKernel,
+        Activation, BiasVector, ColOffsets, F32KernelType, GemmError, GemmExecutor, GemmInT,
+        GemmInputA, GemmInputB, GemmOutT, Im2Col, Int8KernelType, QuantParams, ReducedRangeRng,
+        RowOffsets, WithKernel,
     };
 
     /// Scale a possibly non-float value by a float.
@@ -1888,7 +2168,7 @@ mod tests {
     // This builds a mapping between elements of an image and a
     // `[chans, height x width]` matrix where `image[c, y, x]` maps to
     // `im2col_matrix[c, y / width, y % width]`.
-    fn build_im2col<T: Copy>(
+    fn build_im2col<T: Copy + Default>(
         image: NdTensorView<T, 3>,
         col_count_step: usize,
         row_count_step: usize,
@@ -1942,6 +2222,7 @@ mod tests {
             n_rows: rows,
             max_y_offset: max_y_offset as i32,
             max_x_offset: max_x_offset as i32,
+            pad_value: T::default(),
         }
     }
 
@@ -1964,21 +2245,21 @@ mod tests {
         );
 
         let kernel_mat = NdTensor::<f32, 2>::rand([kernel_chans, img_chans], &mut rng);
-        let mut output_mat = NdTensor::<f32, 2>::zeros([kernel_chans, img_h * img_w]);
+        let mut output_mat = NdTensor::<f32, 2>::uninit([kernel_chans, img_h * img_w]);
         let out_row_stride = output_mat.row_stride();
 
-        gemm.gemm(
+        gemm.gemm_uninit_im2col(
             output_mat.data_mut().unwrap(),
             out_row_stride,
             GemmInputA::Unpacked(kernel_mat.view()),
-            GemmInputB::Im2Col(&im2col),
+            &im2col,
             1.,   // alpha
-            0.,   // beta
             None, // bias
             None, // a_quant
             None, // b_quant
         )
         .unwrap();
+        let output_mat = unsafe { output_mat.assume_init() };
 
         let mut expected = NdTensor::<f32, 2>::zeros([kernel_chans, im2col.cols()]);
         for i in 0..expected.rows() {
@@ -2014,21 +2295,21 @@ mod tests {
                 gemm.im2col_row_count_step(),
             );
             let kernel_mat = NdTensor::<u8, 2>::rand([kernel_chans, img_chans], &mut rng);
-            let mut output_mat = NdTensor::<i32, 2>::zeros([kernel_chans, img_h * img_w]);
+            let mut output_mat = NdTensor::<i32, 2>::uninit([kernel_chans, img_h * img_w]);
             let out_row_stride = output_mat.row_stride();
 
-            gemm.gemm(
+            gemm.gemm_uninit_im2col(
                 output_mat.data_mut().unwrap(),
                 out_row_stride,
                 GemmInputA::Unpacked(kernel_mat.view()),
-                GemmInputB::Im2Col(&im2col),
+                &im2col,
                 1.,   // alpha
-                0,    // beta
                 None, // bias
                 None, // a_quant
                 None, // b_quant
             )
             .unwrap();
+            let output_mat = unsafe { output_mat.assume_init() };
 
             let mut expected = NdTensor::<i32, 2>::zeros([kernel_chans, im2col.cols()]);
             for i in 0..expected.rows() {
@@ -2046,6 +2327,168 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_matmul() {
+        let mut rng = XorShiftRng::new(1234);
+        let a = NdTensor::<f32, 2>::rand([10, 20], &mut rng);
+        let b = NdTensor::<f32, 2>::rand([20, 30], &mut rng);
+        let bias: Vec<f32> = (0..30).map(|x| x as f32).collect();
+        let opts = GemmOpts {
+            alpha: 0.5,
+            bias: Some(BiasVector::Row(&bias)),
+            ..Default::default()
+        };
+        let expected = reference_matmul(a.view(), b.view(), Some(opts));
+
+        for gemm in all_gemms::<f32, f32, f32>() {
+            let packed_b = gemm.prepack_b(b.view());
+            for b_input in [
+                GemmInputB::Unpacked(b.view()),
+                GemmInputB::Packed(&packed_b),
+            ] {
+                let result = gemm
+                    .matmul(
+                        GemmInputA::Unpacked(a.view()),
+                        b_input,
+                        0.5,
+                        Some(BiasVector::Row(&bias)),
+                        None,
+                        None,
+                    )
+                    .unwrap();
+                expect_equal(&result, &expected).unwrap();
+            }
+        }
+
+        let gemm = GemmExecutor::<f32>::new();
+        let result = gemm.matmul(
+            GemmInputA::Unpacked(a.view()),
+            GemmInputB::Unpacked(a.view()),
+            1.,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(result.err(), Some(GemmError::KSizeMismatch));
+    }
+
+    #[test]
+    fn test_matmul_act() {
+        #[derive(Debug)]
+        struct Case {
+            m: usize,
+            n: usize,
+            k: usize,
+        }
+
+        let cases = [
+            Case { m: 5, n: 6, k: 8 },
+            // Depth larger than the depth block size. The activation must only
+            // be applied after the last depth block.
+            Case {
+                m: 20,
+                n: 30,
+                k: 600,
+            },
+            // Vector-matrix product
+            Case { m: 1, n: 30, k: 20 },
+            // Zero depth
+            Case { m: 5, n: 6, k: 0 },
+        ];
+
+        let reference = |activation, x: f32| match activation {
+            Activation::Relu => x.max(0.),
+            Activation::Sigmoid => 1. / (1. + (-x).exp()),
+            Activation::Silu => x / (1. + (-x).exp()),
+            Activation::Tanh => x.tanh(),
+            Activation::Gelu => 0.5 * x * (1. + libm::erff(x / SQRT_2)),
+        };
+
+        cases.test_each(|&Case { m, n, k }| {
+            let mut rng = XorShiftRng::new(1234);
+            let a = NdTensor::<f32, 2>::rand([m, k], &mut rng).map(|x| x - 0.5);
+            let b = NdTensor::<f32, 2>::rand([k, n], &mut rng).map(|x| x - 0.5);
+            let bias: Vec<f32> = (0..n).map(|x| x as f32 * 0.1 - 1.).collect();
+            let gemm = GemmExecutor::<f32>::new();
+            let output = gemm
+                .matmul(
+                    GemmInputA::Unpacked(a.view()),
+                    GemmInputB::Unpacked(b.view()),
+                    1.,
+                    Some(BiasVector::Row(&bias)),
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            for activation in [
+                Activation::Relu,
+                Activation::Gelu,
+                Activation::Sigmoid,
+                Activation::Silu,
+                Activation::Tanh,
+            ] {
+                let result = gemm
+                    .matmul_act(
+                        GemmInputA::Unpacked(a.view()),
+                        GemmInputB::Unpacked(b.view()),
+                        1.,
+                        Some(BiasVector::Row(&bias)),
+                        activation,
+                    )
+                    .unwrap();
+                // The vectorized activation functions are approximations.
+                let expected = output.map(|x| reference(activation, *x));
+                expect_equal_with_tolerance(&result, &expected, 1e-5, 1e-4).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn test_gemv_api() {
+        let mut rng = XorShiftRng::new(1234);
+        let a = NdTensor::<f32, 1>::rand([20], &mut rng);
+        let b = NdTensor::<f32, 2>::rand([20, 30], &mut rng);
+        let bias: Vec<f32> = (0..30).map(|x| x as f32).collect();
+        let opts = GemmOpts {
+            bias: Some(BiasVector::Row(&bias)),
+            ..Default::default()
+        };
+        let expected =
+            reference_matmul(a.reshaped([1, 20]).view(), b.view(), Some(opts)).into_shape([30]);
+
+        for gemm in all_gemms::<f32, f32, f32>() {
+            let result = gemm
+                .gemv(
+                    a.view(),
+                    GemmInputB::Unpacked(b.view()),
+                    1.,
+                    Some(&bias),
+                    None,
+                    None,
+                )
+                .unwrap();
+            assert_eq!(result.shape(), [30]);
+            expect_equal(&result, &expected).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_kernel_selection() {
+        // The default kernel should be the first supported kernel.
+        let kernel_types = GemmExecutor::<f32>::kernel_types();
+        let default_gemm = GemmExecutor::<f32>::new();
+        let preferred_gemm = kernel_types
+            .iter()
+            .find_map(|kt| GemmExecutor::<f32>::with_kernel(*kt))
+            .unwrap();
+        assert_eq!(default_gemm.kernel_name(), preferred_gemm.kernel_name());
+
+        // The generic kernels are always available.
+        assert!(GemmExecutor::<f32>::with_kernel(F32KernelType::Generic).is_some());
+        assert!(GemmExecutor::<u8, i8, i32>::with_kernel(Int8KernelType::Generic).is_some());
+    }
+
     #[test]
     fn test_gemv() {
         #[derive(Clone, Copy, Debug)]
@@ -0,0 +1,51 @@
+use rayon::prelude::*;
+use rten_simd::SimdUnaryOp;
+use rten_vecmath as vecmath;
+
+/// Activation function applied to the output of a matrix multiplication.
+///
+/// See [`GemmExecutor::matmul_act`](super::GemmExecutor::matmul_act).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Activation {
+    /// Rectified linear unit. Computes `max(x, 0)`.
+    Relu,
+
+    /// Gaussian error linear unit. Computes `0.5 * x * (1 + erf(x / sqrt(2)))`.
+    Gelu,
+
+    /// Logistic sigmoid. Computes `1 / (1 + exp(-x))`.
+    Sigmoid,
+
+    /// Sigmoid linear unit. Computes `x * sigmoid(x)`.
+    Silu,
+
+    /// Hyperbolic tangent.
+    Tanh,
+}
+
+impl Activation {
+    /// Apply the activation function to each element of `data`.
+    pub fn apply(&self, data: &mut [f32]) {
+        // Number of elements processed by each parallel task.
+        const CHUNK_SIZE: usize = 4096;
+
+        data.par_chunks_mut(CHUNK_SIZE)
+            .for_each(|chunk| self.apply_chunk(chunk));
+    }
+
+    /// Apply the activation function to each element of `data` on the
+    /// current thread.
+    pub(crate) fn apply_chunk(&self, data: &mut [f32]) {
+        match self {
+            Self::Relu => {
+                for x in data {
+                    *x = x.max(0.);
+                }
+            }
+            Self::Gelu => vecmath::Gelu {}.map_mut(data),
+            Self::Sigmoid => vecmath::Sigmoid {}.map_mut(data),
+            Self::Silu => vecmath::Silu {}.map_mut(data),
+            Self::Tanh => vecmath::Tanh {}.map_mut(data),
+        }
+    }
+}
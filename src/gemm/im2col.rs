@@ -75,6 +75,12 @@ pub struct Im2Col<'a, T> {
     /// Maximum valid sum of `row_offsets.x + col_offsets.x`. Values above this
     /// correspond to the padding region.
     pub max_x_offset: i32,
+
+    /// Value of elements in the padding region.
+    ///
+    /// For quantized inputs this is the zero point, so that padding elements
+    /// contribute zero to the output.
+    pub pad_value: T,
 }
 
 impl<T: Copy + Default> Im2Col<'_, T> {
@@ -140,11 +146,16 @@ impl<T: Copy + Default> Im2Col<'_, T> {
             let max_x_offset = ops.splat(self.max_x_offset);
             let max_y_offset = ops.splat(self.max_y_offset);
 
-            for ((&row_chan_offset, &row_y_offset), &row_x_offset) in row_chan_offsets
+            for (((&row_chan_offset, &row_y_offset), &row_x_offset), row) in row_chan_offsets
                 .iter()
                 .zip(row_y_offsets.iter())
                 .zip(row_x_offsets.iter())
+                .zip(rows.clone())
             {
+                // Rows added to pad the row count to a multiple of the step
+                // size are filled with zeros. The image offsets for these rows
+                // are not guaranteed to fall in the padding region.
+                let is_pad_row = row >= self.n_rows;
                 let row_chan_offset = ops.splat(row_chan_offset);
                 let row_y_offset = ops.splat(row_y_offset);
                 let row_x_offset = ops.splat(row_x_offset);
@@ -181,10 +192,12 @@ impl<T: Copy + Default> Im2Col<'_, T> {
                             unsafe { *img_data.get_unchecked(offsets_array[idx] as usize) };
 
                         // This should be compiled to a conditional move.
-                        let elem = if pad_mask_array[idx] {
+                        let elem = if is_pad_row {
+                            T::default()
+                        } else if pad_mask_array[idx] {
                             src_elem
                         } else {
-                            T::default()
+                            self.pad_value
                         };
 
                         // Safety: `out_offset + i` is valid for `i < ops.len()`.
@@ -285,6 +298,12 @@ impl Im2Col<'_, i8> {
             for start_row in rows.clone().step_by(4) {
                 for i in 0..K_TILE {
                     let k = start_row + i;
+
+                    // Rows added to pad the row count to a multiple of the
+                    // step size are filled with zeros. The image offsets for
+                    // these rows are not guaranteed to fall in the padding
+                    // region.
+                    let is_pad_row = k >= self.n_rows;
                     let row_x_offset = ops.splat(unsafe { *row_x_offsets.get_unchecked(k) });
                     let row_y_offset = ops.splat(unsafe { *row_y_offsets.get_unchecked(k) });
                     let row_chan_offset = ops.splat(unsafe { *row_chan_offsets.get_unchecked(k) });
@@ -315,12 +334,23 @@ impl Im2Col<'_, i8> {
                                 unsafe { *img_data.get_unchecked(offsets_array[idx] as usize) };
 
                             if CAST_B_U8 {
-                                let src_elem = shift_cast_i8_u8(src_elem);
-                                let elem = if pad_mask_array[idx] { src_elem } else { 0 };
+                                let elem = if is_pad_row {
+                                    0
+                                } else if pad_mask_array[idx] {
+                                    shift_cast_i8_u8(src_elem)
+                                } else {
+                                    shift_cast_i8_u8(self.pad_value)
+                                };
                                 col_sums[c_block][idx] += elem as i32;
                                 out_elem.write(elem as i8);
                             } else {
-                                let elem = if pad_mask_array[idx] { src_elem } else { 0 };
+                                let elem = if is_pad_row {
+                                    0
+                                } else if pad_mask_array[idx] {
+                                    src_elem
+                                } else {
+                                    self.pad_value
+                                };
                                 col_sums[c_block][idx] += elem as i32;
                                 out_elem.write(elem);
                             }
@@ -195,7 +195,11 @@ unsafe impl Kernel<u8, i8, i32> for WasmInt8Kernel {
     }
 
     fn name(&self) -> &'static str {
-        "wasm-int8"
+        if RELAXED_DOT {
+            "wasm-relaxed-int8"
+        } else {
+            "wasm-int8"
+        }
     }
 
     fn mr(&self) -> usize {
@@ -251,7 +255,13 @@ unsafe impl Kernel<u8, i8, i32> for WasmInt8Kernel {
         cols: Range<usize>,
         _quant: Option<QuantParams<i8>>,
     ) {
-        packing::int8::pack_b_cast_i8_u8::<{ Self::NR }>(out, b.slice((rows, cols)))
+        let b = b.slice((rows, cols));
+        if RELAXED_DOT {
+            let out = cast_pod_mut_slice(out).unwrap();
+            packing::int8::pack_b::<{ Self::NR }>(out, b)
+        } else {
+            packing::int8::pack_b_cast_i8_u8::<{ Self::NR }>(out, b)
+        }
     }
 
     fn pack_im2col(
@@ -262,7 +272,12 @@ unsafe impl Kernel<u8, i8, i32> for WasmInt8Kernel {
         cols: Range<usize>,
     ) {
         const NR_REGS: usize = WasmInt8Kernel::NR / X32_LANES;
-        image.pack_block_i8_dot_cast_u8::<_, NR_REGS>(self.isa, out, rows, cols);
+        if RELAXED_DOT {
+            let out = cast_pod_mut_slice(out).unwrap();
+            image.pack_block_i8_dot::<_, NR_REGS>(self.isa, out, rows, cols);
+        } else {
+            image.pack_block_i8_dot_cast_u8::<_, NR_REGS>(self.isa, out, rows, cols);
+        }
     }
 
     unsafe fn kernel(
@@ -285,7 +300,13 @@ unsafe impl Kernel<u8, i8, i32> for WasmInt8Kernel {
         };
 
         let a_zero_points = extract_zero_points(a_quant, used_rows, |x| x);
-        let b_zero_points = extract_zero_points(b_quant, used_cols, |x| x + I8_U8_SHIFT);
+        let b_zero_points = extract_zero_points(b_quant, used_cols, |x| {
+            if RELAXED_DOT {
+                x
+            } else {
+                x + I8_U8_SHIFT
+            }
+        });
         let (a_data, a_row_sums) = packing::int8::extract_packed_a::<{ Self::MR }>(a_data);
         let (b, b_col_sums) = packing::int8::extract_packed_b::<{ Self::NR }>(b);
 
@@ -320,7 +341,9 @@ unsafe impl Kernel<u8, i8, i32> for WasmInt8Kernel {
         let a_zero = a_quant.map(|aq| aq.zero_point[0]).unwrap_or(0);
         let b_zero = b_quant.map(|bq| bq.zero_point);
         let out = out.as_bool_beta();
-        simd_int8_gemv::<_, true /* CAST_B_U8 */>(self.isa, out, a, b, a_zero, b_zero, self.isa)
+        simd_int8_gemv::<_, { !RELAXED_DOT } /* CAST_B_U8 */>(
+            self.isa, out, a, b, a_zero, b_zero, self.isa,
+        )
     }
 }
 
@@ -328,6 +351,13 @@ unsafe impl Kernel<u8, i8, i32> for WasmInt8Kernel {
 /// was shifted from i8 to u8 when packing.
 const I8_U8_SHIFT: i32 = 128;
 
+/// True if the relaxed SIMD int8 dot product instruction is available.
+///
+/// When enabled, the RHS / B input is kept as `i8` when packing, as with the
+/// AVX2 kernel. Otherwise it is shifted to `u8` and the dot product operates
+/// on unsigned values.
+const RELAXED_DOT: bool = cfg!(target_feature = "relaxed-simd");
+
 // Safety: This module is only compiled if WASM SIMD is enabled at compile time.
 unsafe impl Int8DotProduct for Wasm32Isa {
     type X8 = <Wasm32Isa as Isa>::I8;
@@ -338,6 +368,7 @@ unsafe impl Int8DotProduct for Wasm32Isa {
     ///
     /// Adapted from the reference lowering of `i32x4.dot_i8x16_i7x16_add_s` given
     /// in https://github.com/WebAssembly/relaxed-simd/issues/52.
+    #[cfg(not(target_feature = "relaxed-simd"))]
     #[inline]
     fn dot_product(self, a: Self::X8, b: Self::X8, c: Self::I32) -> Self::I32 {
         use std::arch::wasm32::{
@@ -357,4 +388,80 @@ unsafe impl Int8DotProduct for Wasm32Isa {
         let quad_sum = i32x4_add(pair_sum_even, pair_sum_odd);
         i32x4_add(quad_sum, c.0).into()
     }
+
+    /// Compute i32 dot product of each group of 4 `u8` integers in `a` and
+    /// `i8` integers in `b` and add to i32x4 accumulator in `c`.
+    ///
+    /// This uses `i32x4.relaxed_dot_i8x16_i7x16_add_s`, which requires one
+    /// operand to be in the range [0, 127] for the result to be deterministic.
+    /// Values of `a` are split into the low 7 bits and the high bit, and a
+    /// separate dot product is computed for each part. This gives exact results
+    /// without the saturation that can happen with `VPMADDUBSW` on x64.
+    #[cfg(target_feature = "relaxed-simd")]
+    #[inline]
+    fn dot_product(self, a: Self::X8, b: Self::X8, c: Self::I32) -> Self::I32 {
+        use std::arch::wasm32::{
+            i32x4_add, i32x4_relaxed_dot_i8x16_i7x16_add, i32x4_shl, i32x4_splat, u8x16_shr,
+            u8x16_splat, v128_and,
+        };
+
+        let a_lo = v128_and(a.0, u8x16_splat(0x7f));
+        let a_hi = u8x16_shr(a.0, 7);
+
+        let dot_lo = i32x4_relaxed_dot_i8x16_i7x16_add(b.0, a_lo, c.0);
+        let dot_hi = i32x4_relaxed_dot_i8x16_i7x16_add(b.0, a_hi, i32x4_splat(0));
+
+        i32x4_add(dot_lo, i32x4_shl(dot_hi, 7)).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_simd::ops::NumOps;
+    use rten_simd::{isa::Wasm32Isa, Isa, Simd};
+
+    use super::{Int8DotProduct, RELAXED_DOT};
+
+    #[test]
+    fn test_dot_product() {
+        let isa = Wasm32Isa::new().unwrap();
+
+        // Cover the full range of `u8` values for `a` and `i8` values for `b`,
+        // including values which would saturate or be non-deterministic with
+        // a naive use of the relaxed dot product instruction.
+        for offset in (0..256).step_by(16) {
+            let a: Vec<u8> = (0..16).map(|i| ((offset + i * 17) % 256) as u8).collect();
+            let b: Vec<i8> = (0..16)
+                .map(|i| ((offset + i * 31) % 256) as u8 as i8)
+                .collect();
+            let c = [1, -2, 3, -4];
+
+            let a_vec = isa
+                .i8()
+                .load(&a.iter().map(|&x| x as i8).collect::<Vec<_>>());
+            let b_vec = isa.i8().load(&b);
+            let c_vec = isa.i32().load(&c);
+            let actual = isa.dot_product(a_vec, b_vec, c_vec).to_array();
+
+            let expected: Vec<i32> = (0..4)
+                .map(|lane| {
+                    let dot: i32 = (0..4)
+                        .map(|k| {
+                            let i = lane * 4 + k;
+                            // Without relaxed SIMD, `b` is shifted to `u8` when
+                            // packing.
+                            let b = if RELAXED_DOT {
+                                b[i] as i32
+                            } else {
+                                b[i] as u8 as i32
+                            };
+                            a[i] as i32 * b
+                        })
+                        .sum();
+                    c[lane] + dot
+                })
+                .collect();
+            assert_eq!(actual.as_ref(), expected.as_slice());
+        }
+    }
 }
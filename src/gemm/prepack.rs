@@ -3,11 +3,41 @@ use std::ops::Range;
 
 use rten_tensor::{Alloc, Matrix, MatrixLayout};
 
+use super::kernels::PackedLayout;
 use super::packing::{PackElem, PackingBuffer};
 use super::{depth_block_size, GemmError, Kernel, LhsBlock, RhsBlock};
+use crate::constant_storage::ArcSlice;
 use crate::iter_util::range_chunks;
+use crate::slice_cast::cast_pod_slice;
 use crate::tensor_pool::ExtractBuffer;
 
+/// Storage for the data of a packed matrix.
+#[derive(Clone)]
+enum PackedData {
+    /// Data packed into a buffer owned by the matrix.
+    Buffer(PackingBuffer),
+
+    /// Previously packed data which references a shared buffer, such as a
+    /// memory-mapped file.
+    Shared(ArcSlice<PackElem>),
+}
+
+impl PackedData {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            PackedData::Buffer(buf) => buf.as_bytes(),
+            PackedData::Shared(slice) => cast_pod_slice(slice.as_slice()).unwrap(),
+        }
+    }
+
+    fn into_vec(self) -> Option<Vec<PackElem>> {
+        match self {
+            PackedData::Buffer(buf) => Some(buf.into_vec()),
+            PackedData::Shared(_) => None,
+        }
+    }
+}
+
 /// Common data and logic for a pre-packed A or B matrix.
 ///
 /// The packed matrix is arranged as a series of blocks corresponding to slices
@@ -17,7 +47,7 @@ use crate::tensor_pool::ExtractBuffer;
 /// The layout and data type of elements within each panel depends upon the kernel.
 #[derive(Clone)]
 struct PackedMatrixBase {
-    data: PackingBuffer,
+    data: PackedData,
 
     /// Size of the short side of each panel. This will match the kernel's MR
     /// (for packed A) or NR (for packed B) dimensions.
@@ -98,7 +128,7 @@ impl<T> PackedAMatrix<T> {
 
     /// Check that the kernel and blocking parameters used when packing this
     /// matrix match.
-    pub fn validate<LhsT, RhsT, OutT>(
+    pub(super) fn validate<LhsT, RhsT, OutT>(
         &self,
         kernel: &dyn Kernel<LhsT, RhsT, OutT>,
         depth_block: usize,
@@ -117,7 +147,7 @@ impl<T> ExtractBuffer for PackedAMatrix<T> {
     type Elem = PackElem;
 
     fn extract_buffer(self) -> Option<Vec<Self::Elem>> {
-        Some(self.base.data.into_vec())
+        self.base.data.into_vec()
     }
 }
 
@@ -149,9 +179,22 @@ impl<T> PackedBMatrix<T> {
         self.base.depth_size
     }
 
+    /// Return the name of the kernel that this matrix was packed for.
+    pub fn kernel_name(&self) -> &str {
+        self.base.kernel_name
+    }
+
+    /// Return the packed data.
+    ///
+    /// The layout of the data depends on the kernel. It can be used to
+    /// reconstruct the packed matrix using [`restore_b`].
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.base.data.as_bytes()
+    }
+
     /// Check that the kernel and blocking parameters used when packing this
     /// matrix match.
-    pub fn validate<LhsT, RhsT, OutT>(
+    pub(super) fn validate<LhsT, RhsT, OutT>(
         &self,
         kernel: &dyn Kernel<LhsT, RhsT, OutT>,
         depth_block: usize,
@@ -170,7 +213,7 @@ impl<T> ExtractBuffer for PackedBMatrix<T> {
     type Elem = PackElem;
 
     fn extract_buffer(self) -> Option<Vec<Self::Elem>> {
-        Some(self.base.data.into_vec())
+        self.base.data.into_vec()
     }
 }
 
@@ -214,7 +257,7 @@ pub fn prepack_a<A: Alloc, LhsT, RhsT, OutT>(
 
     PackedAMatrix {
         base: PackedMatrixBase {
-            data,
+            data: PackedData::Buffer(data),
             nm_size: a.rows(),
             depth_size: a.cols(),
             panel_size: kernel.mr(),
@@ -230,17 +273,20 @@ pub fn prepack_a<A: Alloc, LhsT, RhsT, OutT>(
     }
 }
 
-/// Prepack a GEMM RHS input for use with a given kernel.
-pub fn prepack_b<A: Alloc, LhsT, RhsT, OutT>(
+/// Return the layout of blocks of a B matrix packed for a given kernel.
+///
+/// Returns `(layout, tail_layout)` where `layout` is the layout of each full
+/// block along the K dimension and `tail_layout` is the layout of the final
+/// block, if it is smaller.
+fn packed_b_block_layouts<LhsT, RhsT, OutT>(
     kernel: &dyn Kernel<LhsT, RhsT, OutT>,
-    alloc: A,
-    b: Matrix<RhsT>,
-) -> PackedBMatrix<RhsT> {
-    let depth_block = depth_block_size(b.rows());
-
-    let layout = kernel.packed_b_layout(depth_block, b.cols(), None);
-    let tail_layout = if b.rows() % depth_block != 0 {
-        Some(kernel.packed_b_layout(b.rows() % depth_block, b.cols(), None))
+    rows: usize,
+    cols: usize,
+    depth_block: usize,
+) -> (PackedLayout, Option<PackedLayout>) {
+    let layout = kernel.packed_b_layout(depth_block, cols, None);
+    let tail_layout = if !rows.is_multiple_of(depth_block) {
+        Some(kernel.packed_b_layout(rows % depth_block, cols, None))
     } else {
         None
     };
@@ -250,6 +296,43 @@ pub fn prepack_b<A: Alloc, LhsT, RhsT, OutT>(
     // after packing.
     assert_eq!(layout.size() % layout.align(), 0);
 
+    (layout, tail_layout)
+}
+
+/// Create the metadata for a B matrix packed for a given kernel.
+fn packed_b_base<LhsT, RhsT, OutT>(
+    kernel: &dyn Kernel<LhsT, RhsT, OutT>,
+    data: PackedData,
+    rows: usize,
+    cols: usize,
+    depth_block: usize,
+    layout: &PackedLayout,
+    tail_layout: Option<&PackedLayout>,
+) -> PackedMatrixBase {
+    PackedMatrixBase {
+        data,
+        depth_size: rows,
+        nm_size: cols,
+        panel_size: kernel.nr(),
+        depth_block,
+        panel_stride: layout.panel_stride(),
+        tail_panel_stride: tail_layout
+            .map(|tl| tl.panel_stride())
+            .unwrap_or(layout.panel_stride()),
+        depth_block_stride: layout.size(),
+        kernel_name: kernel.name(),
+    }
+}
+
+/// Prepack a GEMM RHS input for use with a given kernel.
+pub fn prepack_b<A: Alloc, LhsT, RhsT, OutT>(
+    kernel: &dyn Kernel<LhsT, RhsT, OutT>,
+    alloc: A,
+    b: Matrix<RhsT>,
+) -> PackedBMatrix<RhsT> {
+    let depth_block = depth_block_size(b.rows());
+    let (layout, tail_layout) = packed_b_block_layouts(kernel, b.rows(), b.cols(), depth_block);
+
     let n_blocks = b.rows() / depth_block;
     let total_size =
         (n_blocks * layout.size()) + tail_layout.as_ref().map(|l| l.size()).unwrap_or(0);
@@ -268,19 +351,55 @@ pub fn prepack_b<A: Alloc, LhsT, RhsT, OutT>(
     }
 
     PackedBMatrix {
-        base: PackedMatrixBase {
-            data,
-            depth_size: b.rows(),
-            nm_size: b.cols(),
-            panel_size: kernel.nr(),
+        base: packed_b_base(
+            kernel,
+            PackedData::Buffer(data),
+            b.rows(),
+            b.cols(),
             depth_block,
-            panel_stride: layout.panel_stride(),
-            tail_panel_stride: tail_layout
-                .map(|tl| tl.panel_stride())
-                .unwrap_or(layout.panel_stride()),
-            depth_block_stride: layout.size(),
-            kernel_name: kernel.name(),
-        },
+            &layout,
+            tail_layout.as_ref(),
+        ),
         _marker: PhantomData,
     }
 }
+
+/// Reconstruct a packed B matrix from data previously packed by [`prepack_b`].
+///
+/// `kernel_name`, `rows` and `cols` are the kernel name and size of the
+/// original matrix. Returns `None` if the data was packed for a different
+/// kernel or its size does not match the expected layout.
+pub fn restore_b<LhsT, RhsT, OutT>(
+    kernel: &dyn Kernel<LhsT, RhsT, OutT>,
+    kernel_name: &str,
+    rows: usize,
+    cols: usize,
+    data: ArcSlice<PackElem>,
+) -> Option<PackedBMatrix<RhsT>> {
+    if kernel_name != kernel.name() || rows == 0 || cols == 0 {
+        return None;
+    }
+
+    let depth_block = depth_block_size(rows);
+    let (layout, tail_layout) = packed_b_block_layouts(kernel, rows, cols, depth_block);
+
+    let n_blocks = rows / depth_block;
+    let total_size =
+        (n_blocks * layout.size()) + tail_layout.as_ref().map(|l| l.size()).unwrap_or(0);
+    if size_of_val(data.as_slice()) != total_size {
+        return None;
+    }
+
+    Some(PackedBMatrix {
+        base: packed_b_base(
+            kernel,
+            PackedData::Shared(data),
+            rows,
+            cols,
+            depth_block,
+            &layout,
+            tail_layout.as_ref(),
+        ),
+        _marker: PhantomData,
+    })
+}
@@ -250,7 +250,7 @@ impl Graph {
     /// from the parent graph.
     ///
     /// This does include transitive captures from subgraphs.
-    fn capture_names(&self) -> Vec<&str> {
+    pub(crate) fn capture_names(&self) -> Vec<&str> {
         let mut captures: Vec<&str> = self
             .captures()
             .iter()
@@ -399,16 +399,14 @@ impl Graph {
                 cache.insert(input_id, packed);
             }
 
-            let subgraph_caches: Vec<_> = op_node
-                .operator()
-                .subgraphs()
-                .into_iter()
-                .map(|subgraph| {
-                    let mut subgraph_cache = WeightCache::new();
-                    subgraph.prepack_weights(&mut subgraph_cache);
-                    subgraph_cache
-                })
-                .collect();
+            // Subgraph caches may already contain weights, eg. if they were
+            // loaded from a prepacked weights file.
+            let subgraphs = op_node.operator().subgraphs();
+            let mut subgraph_caches = cache.remove_subgraph_caches(op_node_id).unwrap_or_default();
+            subgraph_caches.resize_with(subgraphs.len(), WeightCache::new);
+            for (subgraph, subgraph_cache) in subgraphs.into_iter().zip(&mut subgraph_caches) {
+                subgraph.prepack_weights(subgraph_cache);
+            }
             cache.insert_subgraph_caches(op_node_id, subgraph_caches);
         }
     }
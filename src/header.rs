@@ -4,18 +4,26 @@ use std::fmt::{Display, Formatter};
 use crate::number::LeBytes;
 
 /// Read little-endian encoded primitive values from a byte buffer.
-struct ValueReader<'a> {
+pub(crate) struct ValueReader<'a> {
     pos: usize,
     buf: &'a [u8],
 }
 
 impl<'a> ValueReader<'a> {
-    fn new(buf: &'a [u8]) -> Self {
+    pub fn new(buf: &'a [u8]) -> Self {
         Self { pos: 0, buf }
     }
 
+    /// Return the next `len` bytes from the buffer, or None if there aren't
+    /// enough.
+    pub fn read_slice(&mut self, len: usize) -> Option<&'a [u8]> {
+        let chunk = self.buf.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(chunk)
+    }
+
     /// Return the next N bytes from the buffer, or None if there aren't enough.
-    fn read_n<const N: usize>(&mut self) -> Option<[u8; N]> {
+    pub fn read_n<const N: usize>(&mut self) -> Option<[u8; N]> {
         let chunk = self.buf.get(self.pos..self.pos + N)?;
         self.pos += N;
         Some(chunk.try_into().unwrap())
@@ -24,7 +32,7 @@ impl<'a> ValueReader<'a> {
     /// Read a little-endian encoded value.
     ///
     /// Returns None if there are not enough bytes left in the buffer.
-    fn read<T: LeBytes>(&mut self) -> Option<T> {
+    pub fn read<T: LeBytes>(&mut self) -> Option<T> {
         let chunk = self
             .buf
             .get(self.pos..self.pos + std::mem::size_of::<T>())?;
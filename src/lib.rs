@@ -121,7 +121,7 @@ use rten_tensor::{NdTensor, Tensor};
 mod constant_storage;
 mod downcast;
 mod env;
-mod gemm;
+pub mod gemm;
 mod graph;
 mod header;
 mod iter_util;
@@ -146,9 +146,10 @@ mod wasm_api;
 pub mod ctc;
 
 pub mod ops;
+pub mod quantization;
 
 pub use graph::{Dimension, NodeId, RunError, RunOptions};
-pub use model::{Model, ModelLoadError, ModelOptions, NodeInfo};
+pub use model::{Model, ModelLoadError, ModelOptions, ModelSaveError, NodeInfo};
 pub use model_metadata::ModelMetadata;
 pub use op_registry::{OpRegistry, ReadOp, ReadOpError};
 pub use ops::{DataType, FloatOperators, Input, InputOrOutput, Operators, Output};
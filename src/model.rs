@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::env;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[cfg(feature = "mmap")]
@@ -12,13 +12,15 @@ use std::fs::File;
 use memmap2::Mmap;
 
 use rten_tensor::Tensor;
+use xxhash_rust::xxh64::Xxh64;
 
 use crate::constant_storage::{ArcSlice, ArcTensorView, ConstantStorage};
 use crate::env::str_as_bool;
 use crate::graph::{
-    CaptureEnv, ConstantNodeData, Dimension, Graph, Node, NodeId, RunError, RunOptions,
+    CaptureEnv, Constant, ConstantNodeData, Dimension, Graph, Node, NodeId, RunError, RunOptions,
 };
 use crate::header::{Header, HeaderError};
+use crate::model_builder::{serialize_model, MetadataArgs, SerializeError};
 use crate::model_metadata::ModelMetadata;
 use crate::number::LeBytes;
 use crate::op_registry::{convert_dtype, OpLoadContext, OpRegistry, ReadOpError};
@@ -118,6 +120,10 @@ pub struct Model {
     graph: Graph,
     metadata: ModelMetadata,
     weight_cache: WeightCache,
+
+    /// Identifies the model file and the options used to load it. This is
+    /// saved in prepacked weights files and checked when they are loaded.
+    fingerprint: u64,
 }
 
 /// Provides access to metadata about a graph node.
@@ -198,6 +204,7 @@ pub struct ModelOptions {
     registry: OpRegistry,
     optimize: bool,
     prepack_weights: bool,
+    prepacked_weights_file: Option<PathBuf>,
 }
 
 impl ModelOptions {
@@ -215,6 +222,7 @@ impl ModelOptions {
             registry: ops,
             optimize: true,
             prepack_weights: false,
+            prepacked_weights_file: None,
         }
     }
 
@@ -234,6 +242,25 @@ impl ModelOptions {
         self
     }
 
+    /// Load prepacked weights from a file created by
+    /// [`Model::save_prepacked_weights`].
+    ///
+    /// This avoids the cost of packing weights when the model is loaded. The
+    /// file is memory-mapped if the model is loaded with
+    /// [`load_mmap`](ModelOptions::load_mmap), otherwise it is read into
+    /// memory. Weights in the file are ignored if they were packed for a
+    /// different kernel than the one used on the current system, or if the
+    /// file was created for a different model or with different load options.
+    ///
+    /// This also enables [`prepack_weights`](ModelOptions::prepack_weights),
+    /// so that weights which are not used from the file are packed at load
+    /// time.
+    pub fn prepacked_weights_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.prepacked_weights_file = Some(path.as_ref().to_path_buf());
+        self.prepack_weights = true;
+        self
+    }
+
     /// Load the model from a file. See [`Model::load_file`].
     pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Result<Model, ModelLoadError> {
         let data = std::fs::read(path).map_err(ModelLoadError::ReadFailed)?;
@@ -243,13 +270,37 @@ impl ModelOptions {
     /// Load the model from a data buffer. See [`Model::load`].
     pub fn load(&self, data: Vec<u8>) -> Result<Model, ModelLoadError> {
         let storage = Arc::new(ConstantStorage::Buffer(data));
-        Model::load_impl(storage, self)
+        Model::load_impl(storage, self, self.read_prepacked_weights()?)
     }
 
     /// Load the model from a static slice of bytes. See [`Model::load_static_slice`].
     pub fn load_static_slice(&self, data: &'static [u8]) -> Result<Model, ModelLoadError> {
         let storage = Arc::new(ConstantStorage::StaticSlice(data));
-        Model::load_impl(storage, self)
+        Model::load_impl(storage, self, self.read_prepacked_weights()?)
+    }
+
+    /// Read the prepacked weights file into memory, if one was specified.
+    fn read_prepacked_weights(&self) -> Result<Option<Arc<ConstantStorage>>, ModelLoadError> {
+        let Some(path) = self.prepacked_weights_file.as_ref() else {
+            return Ok(None);
+        };
+        let data = std::fs::read(path).map_err(ModelLoadError::ReadFailed)?;
+        Ok(Some(Arc::new(ConstantStorage::Buffer(data))))
+    }
+
+    /// Memory-map the prepacked weights file, if one was specified.
+    ///
+    /// # Safety
+    ///
+    /// See notes in [`Model::load_mmap`].
+    #[cfg(feature = "mmap")]
+    unsafe fn map_prepacked_weights(&self) -> Result<Option<Arc<ConstantStorage>>, ModelLoadError> {
+        let Some(path) = self.prepacked_weights_file.as_ref() else {
+            return Ok(None);
+        };
+        let file = File::open(path).map_err(ModelLoadError::ReadFailed)?;
+        let mmap = Mmap::map(&file).map_err(ModelLoadError::ReadFailed)?;
+        Ok(Some(Arc::new(ConstantStorage::Mmap(mmap))))
     }
 
     /// Load the model from a memory-mapped view of a file. See [`Model::load_mmap`].
@@ -262,7 +313,7 @@ impl ModelOptions {
         let file = File::open(path).map_err(ModelLoadError::ReadFailed)?;
         let mmap = Mmap::map(&file).map_err(ModelLoadError::ReadFailed)?;
         let storage = Arc::new(ConstantStorage::Mmap(mmap));
-        Model::load_impl(storage, self)
+        Model::load_impl(storage, self, self.map_prepacked_weights()?)
     }
 }
 
@@ -333,6 +384,7 @@ impl Model {
     fn load_impl(
         storage: Arc<ConstantStorage>,
         options: &ModelOptions,
+        prepacked_weights: Option<Arc<ConstantStorage>>,
     ) -> Result<Model, ModelLoadError> {
         let registry = &options.registry;
 
@@ -368,7 +420,13 @@ impl Model {
             None, /* capture_env */
         )?;
 
-        let mut weight_cache = WeightCache::new();
+        let fingerprint = model_fingerprint(&graph, Some(&storage), options.optimize);
+        let mut weight_cache = if let Some(prepacked_weights) = prepacked_weights {
+            WeightCache::deserialize(prepacked_weights, &graph, fingerprint)
+                .map_err(|err| ModelLoadError::InvalidPrepackedWeights(Box::new(err)))?
+        } else {
+            WeightCache::new()
+        };
         if options.prepack_weights {
             graph.prepack_weights(&mut weight_cache);
         }
@@ -382,6 +440,7 @@ impl Model {
             graph,
             metadata,
             weight_cache,
+            fingerprint,
         };
         Ok(model)
     }
@@ -673,6 +732,66 @@ impl Model {
         &self.metadata
     }
 
+    /// Return the model's main graph.
+    pub(crate) fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    /// Create a model from an un-optimized main graph and metadata.
+    pub(crate) fn from_parts(graph: Graph, metadata: ModelMetadata) -> Model {
+        let fingerprint = model_fingerprint(&graph, None, false);
+        Model {
+            graph,
+            metadata,
+            weight_cache: WeightCache::new(),
+            fingerprint,
+        }
+    }
+
+    /// Consume the model and return its main graph and metadata.
+    pub(crate) fn into_parts(self) -> (Graph, ModelMetadata) {
+        (self.graph, self.metadata)
+    }
+
+    /// Serialize the model to a buffer in the `.rten` format.
+    ///
+    /// The model's graph is saved in its current state, including the
+    /// effects of any graph optimizations applied when the model was loaded.
+    /// Operators created by fusing other operators are saved as the
+    /// operators they replaced. The result can be loaded using
+    /// [`Model::load`].
+    pub fn serialize(&self) -> Result<Vec<u8>, ModelSaveError> {
+        let metadata = MetadataArgs::from(&self.metadata);
+        Ok(serialize_model(&self.graph, metadata)?)
+    }
+
+    /// Serialize the model and write it to a `.rten` file.
+    ///
+    /// See [`Model::serialize`].
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ModelSaveError> {
+        let data = self.serialize()?;
+        std::fs::write(path, data).map_err(ModelSaveError::WriteFailed)
+    }
+
+    /// Write the model's prepacked weights to a file.
+    ///
+    /// The file can be used to skip packing weights when the model is loaded
+    /// again with the same options. See
+    /// [`ModelOptions::prepacked_weights_file`]. Weights are packed for the
+    /// matrix multiplication kernel used on the current system. If the model
+    /// was not loaded with [`ModelOptions::prepack_weights`] enabled, the
+    /// weights are packed before saving.
+    pub fn save_prepacked_weights<P: AsRef<Path>>(&self, path: P) -> Result<(), ModelSaveError> {
+        let data = if self.weight_cache.len() > 0 {
+            self.weight_cache.serialize(self.fingerprint)
+        } else {
+            let mut weight_cache = WeightCache::new();
+            self.graph.prepack_weights(&mut weight_cache);
+            weight_cache.serialize(self.fingerprint)
+        };
+        std::fs::write(path, data).map_err(ModelSaveError::WriteFailed)
+    }
+
     /// Return the IDs of input nodes.
     pub fn input_ids(&self) -> &[NodeId] {
         self.graph.input_ids()
@@ -775,6 +894,77 @@ impl Model {
     }
 }
 
+/// Compute a fingerprint which identifies a model file and the options used
+/// to load it.
+///
+/// This is used to check that a prepacked weights file matches the model. It
+/// uses a hash function which is stable across runs. To avoid reading all of
+/// the model's weights, constants are identified by their type, shape and
+/// location in the model file (`storage`) rather than by their values.
+fn model_fingerprint(graph: &Graph, storage: Option<&ConstantStorage>, optimized: bool) -> u64 {
+    let mut hasher = Xxh64::new(0);
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(&[optimized as u8]);
+    let file_len = storage.map(|s| s.data().len()).unwrap_or(0);
+    hasher.update(&(file_len as u64).to_le_bytes());
+    hash_graph(graph, storage, &mut hasher);
+    hasher.digest()
+}
+
+/// Add the structure and constants of `graph` and its subgraphs to a
+/// fingerprint.
+fn hash_graph(graph: &Graph, storage: Option<&ConstantStorage>, hasher: &mut Xxh64) {
+    for (id, node) in graph.iter() {
+        hasher.update(&id.as_u32().to_le_bytes());
+        hasher.update(node.name().unwrap_or_default().as_bytes());
+        match node {
+            Node::Operator(op_node) => {
+                hasher.update(b"operator");
+                hasher.update(op_node.operator().name().as_bytes());
+                hash_ids(hasher, op_node.input_ids().iter().copied());
+                hash_ids(hasher, op_node.output_ids().iter().copied());
+                for subgraph in op_node.operator().subgraphs() {
+                    hash_graph(subgraph, storage, hasher);
+                }
+            }
+            Node::Constant(constant) => {
+                hasher.update(b"constant");
+                for &size in constant.shape() {
+                    hasher.update(&(size as u64).to_le_bytes());
+                }
+                let (dtype, data_ptr) = match constant {
+                    Constant::Float(c) => (0, c.view().data_ptr() as *const u8),
+                    Constant::Int32(c) => (1, c.view().data_ptr() as *const u8),
+                    Constant::Int8(c) => (2, c.view().data_ptr() as *const u8),
+                    Constant::UInt8(c) => (3, c.view().data_ptr()),
+                };
+                hasher.update(&[dtype]);
+
+                // Constants which are not stored in the model file, such as
+                // those created by graph optimizations, are derived from the
+                // graph and other constants.
+                let offset = storage
+                    .map(|s| s.data().as_ptr_range())
+                    .filter(|range| range.contains(&data_ptr))
+                    .map(|range| data_ptr as usize - range.start as usize)
+                    .unwrap_or(usize::MAX);
+                hasher.update(&(offset as u64).to_le_bytes());
+            }
+            Node::Value(_) => hasher.update(b"value"),
+        }
+    }
+    hash_ids(hasher, graph.input_ids().iter().copied().map(Some));
+    hash_ids(hasher, graph.output_ids().iter().copied().map(Some));
+}
+
+/// Add a list of node IDs to a fingerprint.
+fn hash_ids(hasher: &mut Xxh64, ids: impl Iterator<Item = Option<NodeId>>) {
+    for id in ids {
+        let id = id.map(|id| id.as_u32()).unwrap_or(u32::MAX);
+        hasher.update(&id.to_le_bytes());
+    }
+}
+
 /// Errors reported by [`Model::load`].
 #[derive(Debug)]
 pub enum ModelLoadError {
@@ -801,6 +991,9 @@ pub enum ModelLoadError {
 
     /// The file's header is invalid.
     InvalidHeader(Box<dyn Error + Send + Sync>),
+
+    /// The prepacked weights file is invalid.
+    InvalidPrepackedWeights(Box<dyn Error + Send + Sync>),
 }
 
 impl Display for ModelLoadError {
@@ -813,12 +1006,47 @@ impl Display for ModelLoadError {
             ModelLoadError::GraphError(e) => write!(f, "graph error: {e}"),
             ModelLoadError::OptimizeError(e) => write!(f, "graph optimization error: {e}"),
             ModelLoadError::InvalidHeader(e) => write!(f, "invalid header: {e}"),
+            ModelLoadError::InvalidPrepackedWeights(e) => {
+                write!(f, "invalid prepacked weights: {e}")
+            }
         }
     }
 }
 
 impl Error for ModelLoadError {}
 
+/// Errors reported by [`Model::save`] and [`Model::serialize`].
+#[derive(Debug)]
+pub enum ModelSaveError {
+    /// The model contains an operator which cannot be represented in the
+    /// model format.
+    UnsupportedOperator(String),
+
+    /// An error occurred writing the file to disk.
+    WriteFailed(std::io::Error),
+}
+
+impl Display for ModelSaveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelSaveError::UnsupportedOperator(name) => {
+                write!(f, "operator {name} cannot be serialized")
+            }
+            ModelSaveError::WriteFailed(e) => write!(f, "write error: {e}"),
+        }
+    }
+}
+
+impl Error for ModelSaveError {}
+
+impl From<SerializeError> for ModelSaveError {
+    fn from(err: SerializeError) -> Self {
+        match err {
+            SerializeError::UnsupportedOperator(name) => ModelSaveError::UnsupportedOperator(name),
+        }
+    }
+}
+
 /// A model error which pertains to a specific node.
 #[derive(Debug)]
 struct NodeError<E: Display> {
@@ -911,10 +1139,11 @@ fn constant_data_from_flatbuffers_vec<'a, T: Pod + flatbuffers::Follow<'a, Inner
 #[cfg(test)]
 mod tests {
     use rten_tensor::prelude::*;
+    use rten_tensor::test_util::expect_equal;
     use rten_tensor::Tensor;
 
-    use crate::graph::{Dimension, NodeId, RunError};
-    use crate::model::{Model, ModelOptions};
+    use crate::graph::{Dimension, Node, NodeId, RunError};
+    use crate::model::{Model, ModelLoadError, ModelOptions};
     use crate::model_builder::{
         GraphBuilder, IfArgs, MetadataArgs, ModelBuilder, ModelFormat, OpType,
     };
@@ -958,6 +1187,7 @@ mod tests {
         builder.set_graph(graph);
         builder.add_metadata(MetadataArgs {
             onnx_hash: Some("abc".to_string()),
+            ..Default::default()
         });
 
         builder.finish()
@@ -1188,9 +1418,10 @@ mod tests {
     #[test]
     fn test_load_file() {
         let buffer = generate_model_buffer(ModelFormat::V2);
-        std::fs::write("model-load-file-test.rten", buffer).unwrap();
+        let path = std::env::temp_dir().join("model-load-file-test.rten");
+        std::fs::write(&path, buffer).unwrap();
 
-        let model = Model::load_file("model-load-file-test.rten").unwrap();
+        let model = Model::load_file(&path).unwrap();
         let input_id = model.input_ids()[0];
         let output_id = model.output_ids()[0];
 
@@ -1206,9 +1437,10 @@ mod tests {
     #[test]
     fn test_load_mmap() {
         let buffer = generate_model_buffer(ModelFormat::V2);
-        std::fs::write("model-load-mmap-test.rten", buffer).unwrap();
+        let path = std::env::temp_dir().join("model-load-mmap-test.rten");
+        std::fs::write(&path, buffer).unwrap();
 
-        let model = unsafe { Model::load_mmap("model-load-mmap-test.rten").unwrap() };
+        let model = unsafe { Model::load_mmap(&path).unwrap() };
         let input_id = model.input_ids()[0];
         let output_id = model.output_ids()[0];
 
@@ -1271,6 +1503,321 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_serialize() {
+        let buffer = generate_model_buffer(ModelFormat::V2);
+        let model = Model::load(buffer).unwrap();
+
+        let saved = model.serialize().unwrap();
+        let model = Model::load(saved).unwrap();
+
+        assert_eq!(model.metadata().onnx_hash(), Some("abc"));
+        let input_id = model.node_id("input").unwrap();
+        let output_id = model.node_id("output").unwrap();
+        assert_eq!(model.input_ids(), [input_id]);
+        assert_eq!(model.output_ids(), [output_id]);
+        assert_eq!(
+            model.input_shape(0).unwrap(),
+            [1, 2, 2].map(Dimension::Fixed)
+        );
+
+        let input = generate_input();
+        let result = model
+            .run(vec![(input_id, input.into())], &[output_id], None)
+            .unwrap();
+        check_output(result);
+    }
+
+    #[test]
+    fn test_save_file() {
+        let buffer = generate_model_buffer(ModelFormat::V2);
+        let model = Model::load(buffer).unwrap();
+
+        let path = std::env::temp_dir().join("rten-test-save-file.rten");
+        model.save(&path).unwrap();
+        let model = Model::load_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let input_id = model.node_id("input").unwrap();
+        let output_id = model.node_id("output").unwrap();
+        let input = generate_input();
+        let result = model
+            .run(vec![(input_id, input.into())], &[output_id], None)
+            .unwrap();
+        check_output(result);
+    }
+
+    #[test]
+    fn test_prepacked_weights_file() {
+        let build_model = |cols: usize| {
+            let mut builder = ModelBuilder::new(ModelFormat::V2);
+            let mut gb = builder.graph_builder();
+            let input = gb.add_value("input", None, Some(DataType::Float));
+            let output = gb.add_value("output", None, Some(DataType::Float));
+            gb.add_input(input);
+            gb.add_output(output);
+            let weights_val =
+                Tensor::arange(0., 300. * cols as f32, None).into_shape([300, cols].as_slice());
+            let weights = gb.add_constant(weights_val.view());
+            gb.add_operator(
+                "matmul",
+                OpType::MatMul,
+                &[Some(input), Some(weights)],
+                &[output],
+            );
+            let graph = gb.finish();
+            builder.set_graph(graph);
+            builder.finish()
+        };
+        let buffer = build_model(8);
+
+        let model = ModelOptions::with_all_ops()
+            .prepack_weights(true)
+            .load(buffer.clone())
+            .unwrap();
+        let path = std::env::temp_dir().join("rten-test-prepacked-weights.bin");
+        model.save_prepacked_weights(&path).unwrap();
+
+        let input_val = Tensor::full(&[4, 300], 0.25);
+        let run = |model: &Model| -> Tensor<f32> {
+            model
+                .run_one(input_val.view().into(), None)
+                .unwrap()
+                .try_into()
+                .unwrap()
+        };
+        let expected = run(&model);
+
+        // Load with weights from the file. Prepacking at load time is
+        // disabled so that only weights from the file are used.
+        let loaded = ModelOptions::with_all_ops()
+            .prepacked_weights_file(&path)
+            .prepack_weights(false)
+            .load(buffer.clone())
+            .unwrap();
+        assert_eq!(loaded.weight_cache.len(), 1);
+        assert_eq!(run(&loaded), expected);
+
+        // Load with different options. The file is ignored.
+        let loaded = ModelOptions::with_all_ops()
+            .enable_optimization(false)
+            .prepacked_weights_file(&path)
+            .prepack_weights(false)
+            .load(buffer.clone())
+            .unwrap();
+        assert_eq!(loaded.weight_cache.len(), 0);
+        assert_eq!(run(&loaded), expected);
+
+        // Load a model with the same structure, but different weight shapes.
+        // The file is ignored.
+        let loaded = ModelOptions::with_all_ops()
+            .prepacked_weights_file(&path)
+            .prepack_weights(false)
+            .load(build_model(16))
+            .unwrap();
+        assert_eq!(loaded.weight_cache.len(), 0);
+
+        // Load with an invalid file.
+        std::fs::write(&path, b"invalid").unwrap();
+        let result = ModelOptions::with_all_ops()
+            .prepacked_weights_file(&path)
+            .load(buffer);
+        assert!(matches!(
+            result,
+            Err(ModelLoadError::InvalidPrepackedWeights(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Generate a model containing subgraphs and patterns that the graph
+    /// optimizer replaces with fused operators.
+    fn generate_fusable_model_buffer() -> Vec<u8> {
+        let mut builder = ModelBuilder::new(ModelFormat::V2);
+        let mut gb = builder.graph_builder();
+
+        let x = gb.add_value("x", None, Some(DataType::Float));
+        let cond = gb.add_value("cond", None, Some(DataType::Int32));
+        let output = gb.add_value("output", None, Some(DataType::Float));
+        gb.add_input(x);
+        gb.add_input(cond);
+        gb.add_output(output);
+
+        // Add an operator with a single output to the graph.
+        let mut op_index = 0;
+        let mut add_op = |gb: &mut GraphBuilder, op: OpType, inputs: &[NodeId]| {
+            op_index += 1;
+            let out = gb.add_value(&format!("op_{op_index}_out"), None, None);
+            let inputs: Vec<_> = inputs.iter().copied().map(Some).collect();
+            gb.add_operator(&format!("op_{op_index}"), op, &inputs, &[out]);
+            out
+        };
+
+        let weights_val = Tensor::arange(-8., 8., None).into_shape([4, 4].as_slice());
+        let weights = gb.add_constant(weights_val.map(|x| x / 8.).view());
+        let bias = gb.add_constant(Tensor::from([0.1, 0.2, -0.3, 0.4]).view());
+        let scale = gb.add_constant(Tensor::from([1., 2., 3., 4.]).view());
+        let beta = gb.add_constant(Tensor::from(1.5).view());
+        let epsilon = gb.add_constant(Tensor::from(1e-5).view());
+        let one = gb.add_constant(Tensor::from(1.).view());
+        let two = gb.add_constant(Tensor::from(2.).view());
+        let half = gb.add_constant(Tensor::from(0.5).view());
+
+        // Add(MatMul(x, weights), bias) => FusedMatMul
+        let matmul = add_op(&mut gb, OpType::MatMul, &[x, weights]);
+        let biased = add_op(&mut gb, OpType::Add, &[matmul, bias]);
+
+        // x * Sigmoid(x) => Silu
+        let sigmoid = add_op(&mut gb, OpType::Sigmoid, &[biased]);
+        let silu = add_op(&mut gb, OpType::Mul, &[biased, sigmoid]);
+
+        // x * Sigmoid(beta * x) => Swish
+        let swish_scaled = add_op(&mut gb, OpType::Mul, &[beta, silu]);
+        let swish_sigmoid = add_op(&mut gb, OpType::Sigmoid, &[swish_scaled]);
+        let swish = add_op(&mut gb, OpType::Mul, &[silu, swish_sigmoid]);
+
+        // x * (1 / Sqrt(epsilon + ReduceMean(Pow(x, 2)))) * scale => RmsNormalization
+        let pow = add_op(&mut gb, OpType::Pow, &[swish, two]);
+        let mean = add_op(
+            &mut gb,
+            OpType::ReduceMean(ops::ReduceMean {
+                axes: Some(vec![-1]),
+                keep_dims: true,
+            }),
+            &[pow],
+        );
+        let mean_eps = add_op(&mut gb, OpType::Add, &[epsilon, mean]);
+        let rms = add_op(&mut gb, OpType::Sqrt, &[mean_eps]);
+        let inv_rms = add_op(&mut gb, OpType::Div, &[one, rms]);
+        let normalized = add_op(&mut gb, OpType::Mul, &[swish, inv_rms]);
+        let rms_norm = add_op(&mut gb, OpType::Mul, &[normalized, scale]);
+
+        // MatMul(Transpose(x), y) => FusedTranspose(MatMul)
+        let transposed = add_op(
+            &mut gb,
+            OpType::Transpose(ops::Transpose { perm: None }),
+            &[rms_norm],
+        );
+        let matmul_2 = add_op(&mut gb, OpType::MatMul, &[transposed, x]);
+
+        // MatMul(x, y) * 0.5 => FusedMatMul
+        let matmul_3 = add_op(&mut gb, OpType::MatMul, &[matmul_2, weights]);
+        let scaled = gb.add_value("scaled", None, None);
+        gb.add_operator(
+            "scale",
+            OpType::Mul,
+            &[Some(matmul_3), Some(half)],
+            &[scaled],
+        );
+
+        // If operator whose branches capture a value from the parent graph.
+        let mut then_gb = gb.subgraph_builder();
+        let then_in = then_gb.add_value("scaled", None, None);
+        let then_out = then_gb.add_value("then_out", None, None);
+        then_gb.add_capture(then_in);
+        then_gb.add_output(then_out);
+        then_gb.add_operator("then_relu", OpType::Relu, &[Some(then_in)], &[then_out]);
+        let then_branch = then_gb.finish();
+
+        let mut else_gb = gb.subgraph_builder();
+        let else_in = else_gb.add_value("scaled", None, None);
+        let else_out = else_gb.add_value("else_out", None, None);
+        else_gb.add_capture(else_in);
+        else_gb.add_output(else_out);
+        else_gb.add_operator("else_neg", OpType::Neg, &[Some(else_in)], &[else_out]);
+        let else_branch = else_gb.finish();
+
+        gb.add_operator(
+            "if",
+            OpType::If(IfArgs {
+                then_branch,
+                else_branch,
+            }),
+            &[Some(cond)],
+            &[output],
+        );
+
+        let graph = gb.finish();
+        builder.set_graph(graph);
+        builder.add_metadata(MetadataArgs {
+            description: Some("fusion test".to_string()),
+            license: Some("MIT".to_string()),
+            ..Default::default()
+        });
+        builder.finish()
+    }
+
+    /// Check that a model's main graph contains the fused operators created
+    /// from the model generated by `generate_fusable_model_buffer`.
+    fn check_fused_ops(model: &Model) {
+        let op_names: Vec<_> = model
+            .graph()
+            .iter()
+            .filter_map(|(_, node)| match node {
+                Node::Operator(op_node) => Some(op_node.operator().name()),
+                _ => None,
+            })
+            .collect();
+        for fused_op in [
+            "FusedMatMul",
+            "FusedTranspose(MatMul)",
+            "RmsNormalization",
+            "Silu",
+            "Swish",
+        ] {
+            assert!(
+                op_names.contains(&fused_op),
+                "missing {fused_op} in {op_names:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_serialize_optimized_model() {
+        let buffer = generate_fusable_model_buffer();
+        let reference = ModelOptions::with_all_ops()
+            .enable_optimization(false)
+            .load(buffer.clone())
+            .unwrap();
+        let optimized = Model::load(buffer).unwrap();
+
+        check_fused_ops(&optimized);
+
+        // Fused operators are saved as the operators they replaced, and fused
+        // again when the saved model is loaded.
+        let saved = optimized.serialize().unwrap();
+        let reloaded = Model::load(saved.clone()).unwrap();
+        check_fused_ops(&reloaded);
+
+        assert_eq!(reloaded.metadata().description(), Some("fusion test"));
+        assert_eq!(reloaded.metadata().license(), Some("MIT"));
+
+        let unoptimized = ModelOptions::with_all_ops()
+            .enable_optimization(false)
+            .load(saved)
+            .unwrap();
+
+        let x = Tensor::from([[0.5, -1., 2., 0.], [1., 1.5, -0.5, 3.]]);
+        for cond in [0, 1] {
+            let run = |model: &Model| -> Tensor<f32> {
+                let inputs = vec![
+                    (model.node_id("x").unwrap(), x.view().into()),
+                    (model.node_id("cond").unwrap(), Tensor::from(cond).into()),
+                ];
+                let output = model.node_id("output").unwrap();
+                model
+                    .run(inputs, &[output], None)
+                    .unwrap()
+                    .remove(0)
+                    .into_tensor()
+                    .unwrap()
+            };
+            let expected = run(&reference);
+            expect_equal(&run(&optimized), &expected).unwrap();
+            expect_equal(&run(&reloaded), &expected).unwrap();
+            expect_equal(&run(&unoptimized), &expected).unwrap();
+        }
+    }
+
     // This test exercises basic execution of all operators. It doesn't check
     // the results of operators, it just makes sure they can be deserialized and
     // executed successfully.
@@ -1392,6 +1939,9 @@ mod tests {
         });
         add_operator!(Cos, [input_node]);
 
+        let cumsum_axis = graph_builder.add_constant(Tensor::from(1).view());
+        add_operator!(CumSum, [input_node, cumsum_axis]);
+
         let const_u8_val = Tensor::from([0u8, 1, 2, 3, 4]);
         let const_u8 = graph_builder.add_constant(const_u8_val.view());
 
@@ -1598,6 +2148,10 @@ mod tests {
         );
 
         add_operator!(Reciprocal, [input_node]);
+        add_operator!(ReduceL2, [input_node], {
+            axes: None,
+            keep_dims: false,
+        });
         add_operator!(ReduceMean, [input_node], {
             axes: None,
             keep_dims: false,
@@ -1660,6 +2214,16 @@ mod tests {
             { axis: 0, reduction: None }
         );
 
+        let scatter_nd_indices_val = Tensor::from([[0]]);
+        let scatter_nd_indices = graph_builder.add_constant(scatter_nd_indices_val.view());
+        let scatter_nd_updates_val = Tensor::<f32>::zeros(&input_shape);
+        let scatter_nd_updates = graph_builder.add_constant(scatter_nd_updates_val.view());
+        add_operator!(
+            ScatterND,
+            [input_node, scatter_nd_indices, scatter_nd_updates],
+            { reduction: None }
+        );
+
         let const_0 = graph_builder.add_constant(Tensor::from([0]).view());
         let const_1 = graph_builder.add_constant(Tensor::from([1]).view());
         add_operator!(Slice, [input_node, const_0, const_1, const_0]);
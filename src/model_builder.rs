@@ -1,18 +1,28 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
 use flatbuffers::{FlatBufferBuilder, UnionWIPOffset, Vector, WIPOffset};
 use rten_tensor::prelude::*;
-use rten_tensor::TensorView;
+use rten_tensor::{Tensor, TensorView};
+use rustc_hash::{FxHashMap, FxHashSet};
 
-use crate::graph::{Dimension, NodeId};
+use crate::downcast::DowncastDyn;
+use crate::graph::{Constant, Dimension, Graph, Node, NodeId};
 use crate::header::Header;
+use crate::model_metadata::ModelMetadata;
 use crate::number::LeBytes;
+use crate::ops;
+use crate::ops::fused::FusedTranspose;
 use crate::ops::{
     ArgMax, ArgMin, AveragePool, BatchNormalization, BoxOrder, Cast, CastLike, Concat,
     ConstantOfShape, Conv, ConvInteger, ConvTranspose, CoordTransformMode, DataType, DepthToSpace,
-    DepthToSpaceMode, DequantizeLinear, Einsum, Elu, Flatten, Gather, GatherElements, GatherND,
-    Gelu, Gemm, HardSigmoid, InstanceNormalization, LayerNormalization, LeakyRelu, LogSoftmax,
-    MaxPool, Mod, NearestMode, NonMaxSuppression, OneHot, Padding, QuantizeLinear, ReduceMax,
-    ReduceMean, ReduceMin, ReduceProd, ReduceSum, ReduceSumSquare, Reshape, Resize, ResizeMode,
-    Scalar, ScatterElements, ScatterReduction, Shape, Softmax, Split, TopK, Transpose, Trilu,
+    DepthToSpaceMode, DequantizeLinear, Direction, Einsum, Elu, Flatten, FusedMatMul, Gather,
+    GatherElements, GatherND, Gelu, Gemm, HardSigmoid, InstanceNormalization, LayerNormalization,
+    LeakyRelu, LogSoftmax, MaxPool, Mod, NearestMode, NonMaxSuppression, OneHot, Operator, Padding,
+    QuantizeLinear, ReduceL2, ReduceMax, ReduceMean, ReduceMin, ReduceProd, ReduceSum,
+    ReduceSumSquare, Reshape, Resize, ResizeMode, RmsNormalization, Scalar, ScatterElements,
+    ScatterND, ScatterReduction, Shape, Silu, Softmax, Split, Swish, TopK, Transpose, Trilu, GRU,
+    LSTM,
 };
 use crate::schema_generated as sg;
 
@@ -48,6 +58,7 @@ pub enum OpType<'a> {
     ConvInteger(ConvInteger),
     ConvTranspose(ConvTranspose),
     Cos,
+    CumSum,
     DequantizeLinear(DequantizeLinear),
     DepthToSpace(DepthToSpace),
     Div,
@@ -70,6 +81,7 @@ pub enum OpType<'a> {
     GlobalAveragePool,
     Greater,
     GreaterOrEqual,
+    GRU(GRU),
     HardSigmoid(HardSigmoid),
     HardSwish,
     Identity,
@@ -81,6 +93,7 @@ pub enum OpType<'a> {
     LessOrEqual,
     Log,
     LogSoftmax(LogSoftmax),
+    LSTM(LSTM),
     MatMul,
     MatMulInteger,
     Max,
@@ -109,6 +122,7 @@ pub enum OpType<'a> {
 
     Range,
     Reciprocal,
+    ReduceL2(ReduceL2),
     ReduceMax(ReduceMax),
     ReduceMean(ReduceMean),
     ReduceMin(ReduceMin),
@@ -121,6 +135,7 @@ pub enum OpType<'a> {
     Round,
     QuantizeLinear(QuantizeLinear),
     ScatterElements(ScatterElements),
+    ScatterND(ScatterND),
     Shape(Shape),
     Sigmoid,
     Sign,
@@ -206,10 +221,55 @@ enum NodeData<'a> {
 }
 
 /// Arguments for [`ModelBuilder::add_metadata`].
+///
+/// See [`ModelMetadata`] for a description of each field.
+#[derive(Default)]
 pub struct MetadataArgs {
     pub onnx_hash: Option<String>,
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub commit: Option<String>,
+    pub code_repository: Option<String>,
+    pub model_repository: Option<String>,
+    pub run_id: Option<String>,
+    pub run_url: Option<String>,
+}
+
+impl From<&ModelMetadata> for MetadataArgs {
+    fn from(metadata: &ModelMetadata) -> Self {
+        let to_string = |s: Option<&str>| s.map(|s| s.to_string());
+        MetadataArgs {
+            onnx_hash: to_string(metadata.onnx_hash()),
+            description: to_string(metadata.description()),
+            license: to_string(metadata.license()),
+            commit: to_string(metadata.commit()),
+            code_repository: to_string(metadata.code_repository()),
+            model_repository: to_string(metadata.model_repository()),
+            run_id: to_string(metadata.run_id()),
+            run_url: to_string(metadata.run_url()),
+        }
+    }
+}
+
+/// Errors that occur when serializing a [`Graph`].
+#[derive(Debug)]
+pub(crate) enum SerializeError {
+    /// The graph contains an operator which cannot be serialized.
+    UnsupportedOperator(String),
 }
 
+impl Display for SerializeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedOperator(name) => {
+                write!(f, "operator {name} cannot be serialized")
+            }
+        }
+    }
+}
+
+impl Error for SerializeError {}
+
 struct PadArgs {
     auto_pad: sg::AutoPad,
     pads: Option<Vec<usize>>,
@@ -237,6 +297,24 @@ fn convert_dtype(dtype: DataType) -> sg::DataType {
     }
 }
 
+fn convert_direction(direction: Direction) -> sg::RNNDirection {
+    match direction {
+        Direction::Forward => sg::RNNDirection::Forward,
+        Direction::Reverse => sg::RNNDirection::Reverse,
+        Direction::Bidirectional => sg::RNNDirection::Bidirectional,
+    }
+}
+
+fn convert_reduction(reduction: Option<ScatterReduction>) -> sg::ScatterReduction {
+    match reduction {
+        None => sg::ScatterReduction::None,
+        Some(ScatterReduction::Add) => sg::ScatterReduction::Add,
+        Some(ScatterReduction::Mul) => sg::ScatterReduction::Mul,
+        Some(ScatterReduction::Min) => sg::ScatterReduction::Min,
+        Some(ScatterReduction::Max) => sg::ScatterReduction::Max,
+    }
+}
+
 /// Builder for serializing a graph or subgraph to FlatBuffers.
 pub struct GraphBuilder<'mb, 'a> {
     builder: &'mb mut FlatBufferBuilder<'a>,
@@ -245,6 +323,7 @@ pub struct GraphBuilder<'mb, 'a> {
     nodes: Vec<WIPOffset<sg::Node<'a>>>,
     input_ids: Vec<NodeId>,
     output_ids: Vec<NodeId>,
+    capture_ids: Vec<NodeId>,
 }
 
 impl<'mb, 'a> GraphBuilder<'mb, 'a> {
@@ -259,6 +338,7 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
             nodes: Vec::new(),
             input_ids: Vec::new(),
             output_ids: Vec::new(),
+            capture_ids: Vec::new(),
         }
     }
 
@@ -294,6 +374,15 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
     pub fn add_constant<T: Copy + LeBytes + ToConstantData>(
         &mut self,
         input: TensorView<T>,
+    ) -> NodeId {
+        self.add_named_constant(None, input)
+    }
+
+    /// Add a constant node with an optional name to the model.
+    fn add_named_constant<T: Copy + LeBytes + ToConstantData>(
+        &mut self,
+        name: Option<&str>,
+        input: TensorView<T>,
     ) -> NodeId {
         let shape: Vec<u32> = input.shape().iter().map(|&x| x as u32).collect();
         let shape_vec = self.builder.create_vector(&shape[..]);
@@ -324,7 +413,7 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
         };
 
         let const_node = sg::ConstantNode::create(self.builder, &args);
-        self.add_node(None, NodeData::Constant(const_node))
+        self.add_node(name, NodeData::Constant(const_node))
     }
 
     /// Add a value node to the model
@@ -333,6 +422,15 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
         id: &str,
         shape: Option<&[Dimension]>,
         dtype: Option<DataType>,
+    ) -> NodeId {
+        self.add_value_impl(Some(id), shape, dtype)
+    }
+
+    fn add_value_impl(
+        &mut self,
+        id: Option<&str>,
+        shape: Option<&[Dimension]>,
+        dtype: Option<DataType>,
     ) -> NodeId {
         let shape = shape.map(|shape| {
             let dim_vec: Vec<_> = shape
@@ -361,7 +459,7 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
         });
         let dtype = dtype.map(convert_dtype);
         let value_node = sg::ValueNode::create(self.builder, &sg::ValueNodeArgs { shape, dtype });
-        self.add_node(Some(id), NodeData::Value(value_node))
+        self.add_node(id, NodeData::Value(value_node))
     }
 
     /// Add an operator node to the model
@@ -371,6 +469,17 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
         op_info: OpType,
         inputs: &[Option<NodeId>],
         outputs: &[NodeId],
+    ) -> NodeId {
+        let outputs: Vec<_> = outputs.iter().copied().map(Some).collect();
+        self.add_operator_impl(Some(id), op_info, inputs, &outputs)
+    }
+
+    fn add_operator_impl(
+        &mut self,
+        id: Option<&str>,
+        op_info: OpType,
+        inputs: &[Option<NodeId>],
+        outputs: &[Option<NodeId>],
     ) -> NodeId {
         // Generate an (op_type, attr_type, attrs) tuple for an operator with
         // no attributes.
@@ -529,6 +638,7 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
                 }
             }),
             OpType::Cos => op!(Cos),
+            OpType::CumSum => op!(CumSum),
             OpType::DequantizeLinear(args) => op_with_attrs!(
                 DequantizeLinear,
                 DequantizeLinearAttrs,
@@ -615,6 +725,15 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
             OpType::GlobalAveragePool => op!(GlobalAveragePool),
             OpType::Greater => op!(Greater),
             OpType::GreaterOrEqual => op!(GreaterOrEqual),
+            OpType::GRU(args) => op_with_attrs!(
+                GRU,
+                GRUAttrs,
+                sg::GRUAttrsArgs {
+                    direction: convert_direction(args.direction),
+                    hidden_size: args.hidden_size as u32,
+                    linear_before_reset: args.linear_before_reset,
+                }
+            ),
             OpType::HardSigmoid(args) => op_with_attrs!(
                 HardSigmoid,
                 HardSigmoidAttrs,
@@ -663,6 +782,14 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
                     axis: args.axis as i32,
                 }
             ),
+            OpType::LSTM(args) => op_with_attrs!(
+                LSTM,
+                LSTMAttrs,
+                sg::LSTMAttrsArgs {
+                    direction: convert_direction(args.direction),
+                    hidden_size: args.hidden_size as u32,
+                }
+            ),
             OpType::MatMul => op!(MatMul),
             OpType::MatMulInteger => op!(MatMulInteger),
             OpType::Max => op!(Max),
@@ -771,6 +898,9 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
 
             OpType::Range => op!(Range),
             OpType::Reciprocal => op!(Reciprocal),
+            OpType::ReduceL2(args) => {
+                op_with_attrs!(ReduceL2, ReduceMeanAttrs, reduce_attrs!(args))
+            }
             OpType::ReduceMax(args) => {
                 op_with_attrs!(ReduceMax, ReduceMeanAttrs, reduce_attrs!(args))
             }
@@ -823,19 +953,19 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
             OpType::Round => op!(Round),
             OpType::ScatterElements(args) => {
                 op_with_attrs!(ScatterElements, ScatterElementsAttrs, {
-                    let reduction = match args.reduction {
-                        None => sg::ScatterReduction::None,
-                        Some(ScatterReduction::Add) => sg::ScatterReduction::Add,
-                        Some(ScatterReduction::Mul) => sg::ScatterReduction::Mul,
-                        Some(ScatterReduction::Min) => sg::ScatterReduction::Min,
-                        Some(ScatterReduction::Max) => sg::ScatterReduction::Max,
-                    };
                     sg::ScatterElementsAttrsArgs {
                         axis: args.axis as i32,
-                        reduction,
+                        reduction: convert_reduction(args.reduction),
                     }
                 })
             }
+            OpType::ScatterND(args) => op_with_attrs!(
+                ScatterND,
+                ScatterNDAttrs,
+                sg::ScatterNDAttrsArgs {
+                    reduction: convert_reduction(args.reduction),
+                }
+            ),
             OpType::Shape(args) => op_with_attrs!(Shape, ShapeAttrs, {
                 sg::ShapeAttrsArgs {
                     start: args.start,
@@ -894,7 +1024,13 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
                 None => -1,
             })
             .collect();
-        let output_ids: Vec<i32> = outputs.iter().map(|&id| id.as_u32() as i32).collect();
+        let output_ids: Vec<i32> = outputs
+            .iter()
+            .map(|&id| match id {
+                Some(id) => id.as_u32() as i32,
+                None => -1,
+            })
+            .collect();
 
         let input_vec = self.builder.create_vector(&input_ids);
         let output_vec = self.builder.create_vector(&output_ids);
@@ -908,7 +1044,7 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
                 outputs: Some(output_vec),
             },
         );
-        self.add_node(Some(id), NodeData::Operator(op_node))
+        self.add_node(id, NodeData::Operator(op_node))
     }
 
     /// Mark a node in the graph as an input.
@@ -921,6 +1057,396 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
         self.output_ids.push(node_id);
     }
 
+    /// Mark a node in the graph as a value captured from the parent graph.
+    pub fn add_capture(&mut self, node_id: NodeId) {
+        self.capture_ids.push(node_id);
+    }
+
+    /// Serialize the nodes of `graph` that are needed to compute its outputs.
+    ///
+    /// Nodes which are not reachable from the graph's outputs, such as
+    /// operators that were replaced after the graph was created, are omitted.
+    /// The graph's inputs, outputs and captures are marked as such in the
+    /// serialized graph.
+    pub(crate) fn add_graph(&mut self, graph: &Graph) -> Result<(), SerializeError> {
+        let live_ids = live_node_ids(graph);
+        let mut new_ids = FxHashMap::default();
+
+        // Add values and constants first, so that they precede the operators
+        // which reference them.
+        for (node_id, node) in graph.iter() {
+            if !live_ids.contains(&node_id) {
+                continue;
+            }
+            let new_id = match node {
+                Node::Operator(_) => continue,
+                Node::Value(_) => {
+                    self.add_value_impl(node.name(), node.shape().as_deref(), node.dtype())
+                }
+                Node::Constant(constant) => match constant {
+                    Constant::Float(c) => self.add_named_constant(constant.name(), c.view()),
+                    Constant::Int32(c) => self.add_named_constant(constant.name(), c.view()),
+                    Constant::Int8(c) => self.add_named_constant(constant.name(), c.view()),
+                    Constant::UInt8(c) => self.add_named_constant(constant.name(), c.view()),
+                },
+            };
+            new_ids.insert(node_id, new_id);
+        }
+
+        let map_ids = |ids: &[Option<NodeId>]| -> Vec<Option<NodeId>> {
+            ids.iter()
+                .map(|id| id.and_then(|id| new_ids.get(&id).copied()))
+                .collect()
+        };
+
+        for (node_id, node) in graph.iter() {
+            let Node::Operator(op_node) = node else {
+                continue;
+            };
+            if !live_ids.contains(&node_id) {
+                continue;
+            }
+            let inputs = map_ids(op_node.input_ids());
+            let outputs = map_ids(op_node.output_ids());
+            self.add_graph_operator(op_node.name(), op_node.operator(), &inputs, &outputs)?;
+        }
+
+        for input_id in graph.input_ids() {
+            self.add_input(new_ids[input_id]);
+        }
+        for output_id in graph.output_ids() {
+            self.add_output(new_ids[output_id]);
+        }
+        for capture_id in graph.captures() {
+            self.add_capture(new_ids[capture_id]);
+        }
+
+        Ok(())
+    }
+
+    /// Serialize an operator from a [`Graph`].
+    ///
+    /// Fused operators created by graph optimization have no representation
+    /// in the model format. These are decomposed into the standard operators
+    /// that they were created from, so that they can be fused again when the
+    /// model is loaded. The last operator in the decomposition is given the
+    /// fused operator's name.
+    fn add_graph_operator(
+        &mut self,
+        name: Option<&str>,
+        op: &dyn Operator,
+        inputs: &[Option<NodeId>],
+        outputs: &[Option<NodeId>],
+    ) -> Result<(), SerializeError> {
+        let output = match outputs {
+            [Some(output)] => Some(*output),
+            _ => None,
+        };
+
+        if let (Some(fused_op), Some(output)) = (op.downcast_ref::<FusedMatMul>(), output) {
+            let [a, b] = [inputs.first(), inputs.get(1)].map(|id| id.copied().flatten());
+            let bias = inputs.get(2).copied().flatten();
+
+            // MatMul(a, b) * alpha + bias
+            let mut steps = Vec::new();
+            if let Some(alpha) = fused_op.alpha {
+                steps.push((OpType::Mul, self.add_scalar(alpha)));
+            }
+            if let Some(bias) = bias {
+                steps.push((OpType::Add, bias));
+            }
+
+            if steps.is_empty() {
+                self.add_operator_impl(name, OpType::MatMul, &[a, b], &[Some(output)]);
+                return Ok(());
+            }
+            let mut prev = self.add_value_impl(None, None, None);
+            self.add_operator_impl(None, OpType::MatMul, &[a, b], &[Some(prev)]);
+            let n_steps = steps.len();
+            for (i, (op_type, operand)) in steps.into_iter().enumerate() {
+                let is_last = i == n_steps - 1;
+                let (step_name, step_out) = if is_last {
+                    (name, output)
+                } else {
+                    (None, self.add_value_impl(None, None, None))
+                };
+                self.add_operator_impl(
+                    step_name,
+                    op_type,
+                    &[Some(prev), Some(operand)],
+                    &[Some(step_out)],
+                );
+                prev = step_out;
+            }
+            return Ok(());
+        }
+
+        if let Some(fused_op) = op.downcast_ref::<FusedTranspose>() {
+            let index = fused_op.input_index();
+            let transposed = self.add_value_impl(None, None, None);
+            let transpose = Transpose {
+                perm: fused_op.permutation().map(|perm| perm.to_vec()),
+            };
+            self.add_operator_impl(
+                None,
+                OpType::Transpose(transpose),
+                &[inputs.get(index).copied().flatten()],
+                &[Some(transposed)],
+            );
+            let mut inner_inputs = inputs.to_vec();
+            if let Some(input) = inner_inputs.get_mut(index) {
+                *input = Some(transposed);
+            }
+            return self.add_graph_operator(name, fused_op.inner(), &inner_inputs, outputs);
+        }
+
+        if let (true, Some(output)) = (op.is::<Silu>(), output) {
+            let x = inputs.first().copied().flatten();
+            let sigmoid = self.add_value_impl(None, None, None);
+            self.add_operator_impl(None, OpType::Sigmoid, &[x], &[Some(sigmoid)]);
+            self.add_operator_impl(name, OpType::Mul, &[x, Some(sigmoid)], &[Some(output)]);
+            return Ok(());
+        }
+
+        if let (Some(swish), Some(output)) = (op.downcast_ref::<Swish>(), output) {
+            let x = inputs.first().copied().flatten();
+            let beta = self.add_scalar(swish.beta);
+            let scaled = self.add_value_impl(None, None, None);
+            self.add_operator_impl(None, OpType::Mul, &[Some(beta), x], &[Some(scaled)]);
+            let sigmoid = self.add_value_impl(None, None, None);
+            self.add_operator_impl(None, OpType::Sigmoid, &[Some(scaled)], &[Some(sigmoid)]);
+            self.add_operator_impl(name, OpType::Mul, &[x, Some(sigmoid)], &[Some(output)]);
+            return Ok(());
+        }
+
+        if let (Some(rms_norm), Some(output)) = (op.downcast_ref::<RmsNormalization>(), output) {
+            let x = inputs.first().copied().flatten();
+            let scale = inputs.get(1).copied().flatten();
+
+            // x * (1 / Sqrt(epsilon + ReduceMean(Pow(x, 2)))) * scale
+            let two = self.add_scalar(2.);
+            let squared = self.add_value_impl(None, None, None);
+            self.add_operator_impl(None, OpType::Pow, &[x, Some(two)], &[Some(squared)]);
+
+            let mean = self.add_value_impl(None, None, None);
+            let reduce_mean = ReduceMean {
+                axes: Some(vec![rms_norm.axis as i32]),
+                keep_dims: true,
+            };
+            self.add_operator_impl(
+                None,
+                OpType::ReduceMean(reduce_mean),
+                &[Some(squared)],
+                &[Some(mean)],
+            );
+
+            let epsilon = self.add_scalar(rms_norm.epsilon.unwrap_or(1e-5));
+            let mean_eps = self.add_value_impl(None, None, None);
+            self.add_operator_impl(
+                None,
+                OpType::Add,
+                &[Some(epsilon), Some(mean)],
+                &[Some(mean_eps)],
+            );
+
+            let rms = self.add_value_impl(None, None, None);
+            self.add_operator_impl(None, OpType::Sqrt, &[Some(mean_eps)], &[Some(rms)]);
+
+            let one = self.add_scalar(1.);
+            let inv_rms = self.add_value_impl(None, None, None);
+            self.add_operator_impl(None, OpType::Div, &[Some(one), Some(rms)], &[Some(inv_rms)]);
+
+            let normalized = self.add_value_impl(None, None, None);
+            self.add_operator_impl(None, OpType::Mul, &[x, Some(inv_rms)], &[Some(normalized)]);
+            self.add_operator_impl(
+                name,
+                OpType::Mul,
+                &[Some(normalized), scale],
+                &[Some(output)],
+            );
+            return Ok(());
+        }
+
+        let op_type = self
+            .op_type(op)
+            .ok_or_else(|| SerializeError::UnsupportedOperator(op.name().to_string()))??;
+        self.add_operator_impl(name, op_type, inputs, outputs);
+        Ok(())
+    }
+
+    /// Add an unnamed scalar float constant to the graph.
+    fn add_scalar(&mut self, value: f32) -> NodeId {
+        self.add_named_constant(None, Tensor::from_scalar(value).view())
+    }
+
+    /// Convert an operator to the corresponding [`OpType`].
+    ///
+    /// Returns `None` if the operator type is not supported by the model
+    /// format.
+    fn op_type(&mut self, op: &dyn Operator) -> Option<Result<OpType<'a>, SerializeError>> {
+        macro_rules! op {
+            ($($op_name:ident),* $(,)?) => {
+                $(
+                    if op.is::<ops::$op_name>() {
+                        return Some(Ok(OpType::$op_name));
+                    }
+                )*
+            };
+        }
+
+        macro_rules! op_with_attrs {
+            ($($op_name:ident),* $(,)?) => {
+                $(
+                    if let Some(op) = op.downcast_ref::<ops::$op_name>() {
+                        return Some(Ok(OpType::$op_name(op.clone())));
+                    }
+                )*
+            };
+        }
+
+        op!(
+            Abs,
+            Acos,
+            Add,
+            And,
+            Asin,
+            Atan,
+            Ceil,
+            Clip,
+            Cos,
+            CumSum,
+            Div,
+            DynamicQuantizeLinear,
+            Equal,
+            Erf,
+            Exp,
+            Expand,
+            Floor,
+            GlobalAveragePool,
+            Greater,
+            GreaterOrEqual,
+            HardSwish,
+            Identity,
+            Less,
+            LessOrEqual,
+            Log,
+            MatMul,
+            MatMulInteger,
+            Max,
+            Mean,
+            Min,
+            Mul,
+            Neg,
+            NonZero,
+            Not,
+            Or,
+            Pad,
+            Pow,
+            Range,
+            Reciprocal,
+            Relu,
+            Round,
+            Sigmoid,
+            Sign,
+            Sin,
+            Size,
+            Slice,
+            Softplus,
+            Sqrt,
+            Squeeze,
+            Sub,
+            Sum,
+            Tan,
+            Tanh,
+            Tile,
+            Unsqueeze,
+            Where,
+            Xor,
+        );
+
+        op_with_attrs!(
+            ArgMax,
+            ArgMin,
+            AveragePool,
+            BatchNormalization,
+            Cast,
+            CastLike,
+            Concat,
+            ConstantOfShape,
+            Conv,
+            ConvInteger,
+            ConvTranspose,
+            DequantizeLinear,
+            DepthToSpace,
+            Einsum,
+            Elu,
+            Flatten,
+            Gather,
+            GatherElements,
+            GatherND,
+            Gemm,
+            GRU,
+            HardSigmoid,
+            InstanceNormalization,
+            LayerNormalization,
+            LeakyRelu,
+            LogSoftmax,
+            LSTM,
+            MaxPool,
+            Mod,
+            NonMaxSuppression,
+            OneHot,
+            QuantizeLinear,
+            ReduceL2,
+            ReduceMax,
+            ReduceMean,
+            ReduceMin,
+            ReduceProd,
+            ReduceSum,
+            ReduceSumSquare,
+            Reshape,
+            Resize,
+            ScatterElements,
+            ScatterND,
+            Shape,
+            Softmax,
+            Split,
+            TopK,
+            Transpose,
+            Trilu,
+        );
+
+        #[cfg(feature = "random")]
+        op_with_attrs!(
+            Dropout,
+            RandomNormal,
+            RandomNormalLike,
+            RandomUniform,
+            RandomUniformLike,
+        );
+
+        if op.is::<ops::Gelu>() {
+            return Some(Ok(OpType::Gelu(Gelu {})));
+        }
+
+        if let Some(if_op) = op.downcast_ref::<ops::If>() {
+            let mut serialize_branch = |graph: &Graph| {
+                let mut builder = self.subgraph_builder();
+                builder.add_graph(graph).map(|_| builder.finish())
+            };
+            let args = serialize_branch(&if_op.then_branch).and_then(|then_branch| {
+                let else_branch = serialize_branch(&if_op.else_branch)?;
+                Ok(IfArgs {
+                    then_branch,
+                    else_branch,
+                })
+            });
+            return Some(args.map(OpType::If));
+        }
+
+        None
+    }
+
     /// Convert a `Vec<T>` of elements to a `Vec<U>` and add them to the model buffer
     fn create_vec<T: Copy, U: flatbuffers::Push + Copy, F: Fn(T) -> U>(
         &mut self,
@@ -941,6 +1467,10 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
         let inputs_vec = self.builder.create_vector(&input_ids);
         let outputs_vec = self.builder.create_vector(&output_ids);
         let nodes_vec = self.builder.create_vector(&self.nodes[..]);
+        let captures = (!self.capture_ids.is_empty()).then(|| {
+            let capture_ids: Vec<_> = self.capture_ids.iter().map(|id| id.as_u32()).collect();
+            self.builder.create_vector(&capture_ids)
+        });
 
         sg::Graph::create(
             self.builder,
@@ -948,15 +1478,74 @@ impl<'mb, 'a> GraphBuilder<'mb, 'a> {
                 nodes: Some(nodes_vec),
                 inputs: Some(inputs_vec),
                 outputs: Some(outputs_vec),
-                captures: None,
+                captures,
             },
         )
     }
 }
 
+/// Return the IDs of nodes in `graph` which are needed to compute its outputs.
+///
+/// This includes the graph's inputs and captures, even if unused.
+fn live_node_ids(graph: &Graph) -> FxHashSet<NodeId> {
+    let mut live_ids = FxHashSet::default();
+    live_ids.extend(graph.input_ids().iter().copied());
+    live_ids.extend(graph.captures().iter().copied());
+
+    let mut pending: Vec<NodeId> = graph.output_ids().to_vec();
+    while let Some(value_id) = pending.pop() {
+        live_ids.insert(value_id);
+        let Some((op_id, op_node)) = graph.get_source_node(value_id) else {
+            continue;
+        };
+        if !live_ids.insert(op_id) {
+            continue;
+        }
+
+        // Unused outputs of the operator are kept so that the serialized
+        // operator has the same number of outputs.
+        live_ids.extend(op_node.output_ids().iter().flatten().copied());
+
+        pending.extend(
+            op_node
+                .input_ids()
+                .iter()
+                .flatten()
+                .filter(|id| !live_ids.contains(id)),
+        );
+
+        for subgraph in op_node.operator().subgraphs() {
+            pending.extend(
+                subgraph
+                    .capture_names()
+                    .into_iter()
+                    .filter_map(|name| graph.get_node_id(name))
+                    .filter(|id| !live_ids.contains(id)),
+            );
+        }
+    }
+
+    live_ids
+}
+
+/// Serialize a graph and its metadata as a model in the V2 format.
+pub(crate) fn serialize_model(
+    graph: &Graph,
+    metadata: MetadataArgs,
+) -> Result<Vec<u8>, SerializeError> {
+    let mut builder = ModelBuilder::new(ModelFormat::V2);
+    let mut graph_builder = builder.graph_builder();
+    graph_builder.add_graph(graph)?;
+    let graph = graph_builder.finish();
+    builder.set_graph(graph);
+    builder.add_metadata(metadata);
+    Ok(builder.finish())
+}
+
 /// Serializes models to the RTen model format.
 ///
-/// This exists for use in model-loading tests. Models for deployment are
+/// [`Model::serialize`](crate::Model::serialize) uses this to save loaded
+/// models, and tests use it to construct models. Models for deployment are
 /// normally built by converting ONNX models using the Python scripts.
 pub struct ModelBuilder<'a> {
     builder: FlatBufferBuilder<'a>,
@@ -1000,15 +1589,30 @@ impl<'a> ModelBuilder<'a> {
 
     /// Add model metadata
     pub fn add_metadata(&mut self, metadata: MetadataArgs) {
-        let hash = metadata
-            .onnx_hash
-            .as_ref()
-            .map(|hash| self.builder.create_string(hash));
-        let mut meta_builder = sg::MetadataBuilder::new(&mut self.builder);
-        if let Some(hash) = hash {
-            meta_builder.add_onnx_hash(hash);
-        }
-        self.metadata = Some(meta_builder.finish());
+        let mut create_string = |s: Option<String>| s.map(|s| self.builder.create_string(&s));
+        let onnx_hash = create_string(metadata.onnx_hash);
+        let description = create_string(metadata.description);
+        let license = create_string(metadata.license);
+        let commit = create_string(metadata.commit);
+        let code_repository = create_string(metadata.code_repository);
+        let model_repository = create_string(metadata.model_repository);
+        let run_id = create_string(metadata.run_id);
+        let run_url = create_string(metadata.run_url);
+
+        let metadata = sg::Metadata::create(
+            &mut self.builder,
+            &sg::MetadataArgs {
+                onnx_hash,
+                description,
+                license,
+                commit,
+                code_repository,
+                model_repository,
+                run_id,
+                run_url,
+            },
+        );
+        self.metadata = Some(metadata);
     }
 
     /// Finish writing the model data to the buffer and return the buffer's contents.
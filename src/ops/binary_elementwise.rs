@@ -626,7 +626,7 @@ pub fn mod_op<
     )
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Mod {
     /// If true, use truncated division (see [`DivMode::TruncDiv`], otherwise
     /// use flooring division (see [`DivMode::FloorDiv`]).
@@ -100,7 +100,7 @@ pub fn concat_in_place<T: Copy>(
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Concat {
     pub axis: isize,
 }
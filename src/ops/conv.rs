@@ -315,6 +315,7 @@ where
                     in_item,
                     [k_h, k_w],
                     fixed_padding,
+                    input_zero.unwrap_or_default(),
                     [stride_y, stride_x],
                     [dilation_y, dilation_x],
                     gemm.im2col_col_count_step(),
@@ -324,13 +325,13 @@ where
                 let bias_vec = bias
                     .as_ref()
                     .map(|b| BiasVector::Column(&b.data().unwrap()[out_chans.clone()]));
-                gemm.gemm_uninit(
+                gemm.gemm_uninit_im2col(
                     out_mat.data_mut().unwrap(),
                     out_row_stride,
                     prepacked_kernel
                         .map(GemmInputA::Packed)
                         .unwrap_or(GemmInputA::Unpacked(kernel_mat.view())),
-                    GemmInputB::Im2Col(&im2col),
+                    &im2col,
                     1., // alpha
                     bias_vec,
                     kernel_quant,
@@ -350,7 +351,7 @@ where
     Ok(output.into())
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Conv {
     pub groups: usize,
     pub dilations: Vec<usize>,
@@ -484,7 +485,7 @@ where
     )
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ConvInteger {
     pub groups: usize,
     pub dilations: Vec<usize>,
@@ -786,7 +787,7 @@ pub fn conv_transpose(
     Ok(output.into_dyn())
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ConvTranspose {
     pub padding: Padding,
     pub strides: Vec<usize>,
@@ -1701,24 +1702,29 @@ mod tests {
 
                 cases.test_each(|case| {
                     let output_chans = case.kernel.size(0);
-                    check_conv_int8(
-                        case.input.view(),
-                        case.kernel.view(),
-                        Padding::zero::<2>(),
-                        case.groups,
-                        &[1, 1], // strides
-                        &[1, 1], // dilations
-                        case.input_zero
-                            .map(|zero| Tensor::from(zero))
-                            .as_ref()
-                            .map(|t| t.view()),
-                        case.kernel_zero
-                            .clone()
-                            .map(|zero| Tensor::from_data(&[output_chans], zero))
-                            .as_ref()
-                            .map(|t| t.view()),
-                    )
-                    .unwrap();
+
+                    // Padding elements should be treated as having the value
+                    // of the input zero point.
+                    for padding in [Padding::zero::<2>(), Padding::Fixed([1, 1, 1, 1].into())] {
+                        check_conv_int8(
+                            case.input.view(),
+                            case.kernel.view(),
+                            padding,
+                            case.groups,
+                            &[1, 1], // strides
+                            &[1, 1], // dilations
+                            case.input_zero
+                                .map(|zero| Tensor::from(zero))
+                                .as_ref()
+                                .map(|t| t.view()),
+                            case.kernel_zero
+                                .clone()
+                                .map(|zero| Tensor::from_data(&[output_chans], zero))
+                                .as_ref()
+                                .map(|t| t.view()),
+                        )
+                        .unwrap();
+                    }
                 })
             }
         };
@@ -8,10 +8,12 @@ use crate::ops::Padding;
 /// Build a virtual [`Im2Col`] matrix from an image and convolution parameters.
 ///
 /// The number of columns in the matrix is padded to a multiple of `col_count_step`.
+/// Elements in the padding region have the value `pad_value`.
 pub fn build_im2col<T>(
     image: NdTensorView<T, 3>,
     kernel: [usize; 2],
     padding: [usize; 4],
+    pad_value: T,
     strides: [usize; 2],
     dilations: [usize; 2],
     col_count_step: usize,
@@ -121,5 +123,6 @@ pub fn build_im2col<T>(
 
         max_y_offset,
         max_x_offset,
+        pad_value,
     }
 }
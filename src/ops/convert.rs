@@ -46,7 +46,7 @@ fn cast(pool: &TensorPool, input: Input, dtype: DataType) -> Result<Output, OpEr
     Ok(result)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Cast {
     pub to: DataType,
 }
@@ -81,7 +81,7 @@ impl Operator for Cast {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CastLike {}
 
 impl Operator for CastLike {
@@ -125,7 +125,7 @@ fn contains_repeated_chars(term: &str) -> bool {
         .any(|c1| term.chars().filter(|c2| c1 == *c2).count() > 1)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Einsum {
     pub equation: String,
 }
@@ -63,6 +63,22 @@ impl FusedTranspose {
             inner: op,
         }
     }
+
+    /// Return the operator which this operator wraps.
+    pub(crate) fn inner(&self) -> &(dyn Operator + Send + Sync) {
+        self.inner.as_ref()
+    }
+
+    /// Return the index of the input which is permuted.
+    pub(crate) fn input_index(&self) -> usize {
+        self.perm.index
+    }
+
+    /// Return the permutation applied to the input, or `None` if the order
+    /// of dimensions is reversed.
+    pub(crate) fn permutation(&self) -> Option<&[usize]> {
+        self.perm.perm.as_deref()
+    }
 }
 
 impl Operator for FusedTranspose {
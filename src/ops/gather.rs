@@ -124,7 +124,7 @@ pub fn gather<T: Copy + Default>(
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Gather {
     pub axis: isize,
 }
@@ -268,7 +268,7 @@ pub fn gather_elements<T: Copy + Default>(
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct GatherElements {
     pub axis: isize,
 }
@@ -386,7 +386,7 @@ pub fn gather_nd<T: Clone + Default>(
     Ok(unsafe { output.assume_init() })
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct GatherND {
     pub batch_dims: usize,
 }
@@ -494,7 +494,7 @@ pub fn scatter_elements<
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ScatterElements {
     pub axis: isize,
     pub reduction: Option<ScatterReduction>,
@@ -587,7 +587,7 @@ pub fn scatter_nd<
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ScatterND {
     pub reduction: Option<ScatterReduction>,
 }
@@ -18,7 +18,7 @@ pub fn constant_of_shape<T: Copy>(
     Tensor::full_in(pool, &shape, value)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ConstantOfShape {
     pub value: Scalar,
 }
@@ -77,7 +77,7 @@ fn extract_on_off_values<T: Copy>(values: NdTensorView<T, 1>) -> Result<(T, T),
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct OneHot {
     pub axis: isize,
 }
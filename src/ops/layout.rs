@@ -58,7 +58,7 @@ pub fn depth_to_space<T: Clone>(
     Ok(tmp)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DepthToSpace {
     pub block_size: u32,
     pub mode: DepthToSpaceMode,
@@ -218,7 +218,7 @@ pub fn flatten_in_place<T: Copy>(
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Flatten {
     pub axis: isize,
 }
@@ -348,7 +348,7 @@ pub fn reshape_in_place<T: Copy>(
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Reshape {
     pub allow_zero: bool,
 }
@@ -389,7 +389,7 @@ impl Operator for Reshape {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct Shape {
     pub start: Option<i32>,
     pub end: Option<i32>,
@@ -565,7 +565,7 @@ pub fn transpose<T: Copy>(
     Ok(output.init_from(&transposed))
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Transpose {
     /// The order of the transposed dimensions. If ommitted, the dimensions
     /// are reversed.
@@ -87,7 +87,7 @@ where
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Gemm {
     pub alpha: f32,
     pub beta: f32,
@@ -228,7 +228,7 @@ pub fn batch_norm(
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BatchNormalization {
     pub epsilon: f32,
 }
@@ -335,7 +335,7 @@ pub fn instance_normalization_in_place(
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct InstanceNormalization {
     pub epsilon: Option<f32>,
 }
@@ -487,7 +487,7 @@ fn layer_normalization_impl(
     Ok(Tensor::from_data(input.shape(), output))
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct LayerNormalization {
     pub axis: isize,
     pub epsilon: Option<f32>,
@@ -632,7 +632,7 @@ pub fn log_softmax_in_place(output: &mut Tensor, axis: isize) -> Result<(), OpEr
     })
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct LogSoftmax {
     pub axis: isize,
 }
@@ -678,7 +678,7 @@ pub fn softmax_in_place(output: &mut Tensor, axis: isize) -> Result<(), OpError>
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Softmax {
     pub axis: isize,
 }
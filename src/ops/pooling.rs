@@ -341,7 +341,7 @@ pub fn average_pool(
     )
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct AveragePool {
     pub kernel_size: SmallVec<[usize; 2]>,
     pub padding: Padding,
@@ -451,7 +451,7 @@ pub fn max_pool(
     )
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MaxPool {
     pub kernel_size: SmallVec<[usize; 2]>,
     pub padding: Padding,
@@ -88,7 +88,7 @@ pub fn dequantize_linear<T: Copy + Default + Dequantize<f32> + Scalar>(
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DequantizeLinear {
     pub axis: isize,
 }
@@ -249,7 +249,7 @@ where
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct QuantizeLinear {
     pub axis: isize,
     pub output_dtype: Option<DataType>,
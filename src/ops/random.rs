@@ -5,7 +5,7 @@ use rten_tensor::Tensor;
 
 use crate::ops::{IntoOpResult, OpError, OpRunContext, Operator, Output, OutputList};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RandomUniform {
     pub low: f32,
     pub high: f32,
@@ -40,7 +40,7 @@ impl Operator for RandomUniform {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RandomUniformLike {
     pub low: f32,
     pub high: f32,
@@ -70,7 +70,7 @@ impl Operator for RandomUniformLike {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RandomNormal {
     pub mean: f32,
     pub scale: f32,
@@ -106,7 +106,7 @@ impl Operator for RandomNormal {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RandomNormalLike {
     pub mean: f32,
     pub scale: f32,
@@ -136,7 +136,7 @@ impl Operator for RandomNormalLike {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Dropout {
     pub seed: Option<i32>,
 }
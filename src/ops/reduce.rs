@@ -83,7 +83,7 @@ pub fn arg_max<T: Copy + PartialOrd + IsNaN>(
     select_max_index(pool, input, axis, keep_dims, |a, b| cmp_nan_greater(*a, *b))
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ArgMax {
     pub axis: isize,
     pub keep_dims: bool,
@@ -119,7 +119,7 @@ pub fn arg_min<T: Copy + PartialOrd + IsNaN>(
     })
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ArgMin {
     pub axis: isize,
     pub keep_dims: bool,
@@ -167,7 +167,7 @@ pub fn cum_sum<T: Copy + Default + Identities + std::ops::AddAssign>(
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CumSum {}
 
 impl Operator for CumSum {
@@ -399,7 +399,7 @@ pub fn reduce_mean(
     reduce(pool, input, axes, keep_dims, &MeanKernel {})
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ReduceMean {
     pub axes: Option<Vec<i32>>,
     pub keep_dims: bool,
@@ -440,7 +440,7 @@ pub fn reduce_l2(
     reduce(pool, input, axes, keep_dims, &L2ReduceKernel {})
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ReduceL2 {
     pub axes: Option<Vec<i32>>,
     pub keep_dims: bool,
@@ -540,7 +540,7 @@ pub fn reduce_min<T: Copy + PartialOrd + IsNaN>(
     reduce_min_max(pool, input, axes, keep_dims, false /* max */)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ReduceMin {
     pub axes: Option<Vec<i32>>,
     pub keep_dims: bool,
@@ -570,7 +570,7 @@ pub fn reduce_max<T: Copy + PartialOrd + IsNaN>(
     reduce_min_max(pool, input, axes, keep_dims, true /* max */)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ReduceMax {
     pub axes: Option<Vec<i32>>,
     pub keep_dims: bool,
@@ -606,7 +606,7 @@ pub fn reduce_prod<T: Copy + std::iter::Product>(
     reduce(pool, input, axes, keep_dims, &ProdKernel {})
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ReduceProd {
     pub axes: Option<Vec<i32>>,
     pub keep_dims: bool,
@@ -642,7 +642,7 @@ pub fn reduce_sum<T: Copy + Default + std::ops::Add<T, Output = T>>(
     reduce(pool, input, axes, keep_dims, &SumKernel {})
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ReduceSum {
     pub axes: Option<Vec<i32>>,
     pub keep_dims: bool,
@@ -678,7 +678,7 @@ pub fn reduce_sum_square<T: Copy + std::ops::Mul<T, Output = T> + std::iter::Sum
     reduce(pool, input, axes, keep_dims, &SumSquareKernel {})
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ReduceSumSquare {
     pub axes: Option<Vec<i32>>,
     pub keep_dims: bool,
@@ -771,7 +771,7 @@ pub fn topk<T: Copy + Default + PartialOrd + IsNaN>(
     Ok((out_values, indices))
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TopK {
     pub axis: Option<isize>,
     pub largest: bool,
@@ -395,7 +395,7 @@ pub enum ResizeMode {
     Linear,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Resize {
     pub mode: ResizeMode,
     pub coord_mode: CoordTransformMode,
@@ -91,7 +91,7 @@ fn zip4<T1, T2, T3, T4>(
 const PREPACK_MIN_SEQ_LEN: usize = 5;
 
 /// Gated Recurrent Unit operator.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct GRU {
     pub direction: Direction,
     pub hidden_size: usize,
@@ -351,7 +351,7 @@ impl Operator for GRU {
 }
 
 /// Long Short-Term Memory operator.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct LSTM {
     pub direction: Direction,
     pub hidden_size: usize,
@@ -73,7 +73,7 @@ pub fn split<T: Copy>(
     Ok(outputs)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Split {
     pub axis: isize,
     pub num_outputs: Option<u32>,
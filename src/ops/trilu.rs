@@ -33,7 +33,7 @@ pub fn trilu<T: Copy + Default>(
     Ok(output)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Trilu {
     pub upper: bool,
 }
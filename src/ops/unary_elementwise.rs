@@ -368,7 +368,7 @@ impl Operator for Clip {
 
 unary_float_op!(Cos, cos, cos_in_place, |val: f32| val.cos());
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Elu {
     pub alpha: f32,
 }
@@ -409,7 +409,7 @@ unary_float_op!(Floor, floor, floor_in_place, |val: f32| val.floor());
 
 parallel_unary_float_op!(Gelu, gelu, gelu_in_place, vecmath::Gelu {});
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct HardSigmoid {
     pub alpha: f32,
     pub beta: f32,
@@ -458,7 +458,7 @@ pub fn leaky_relu_in_place(input: TensorViewMut, alpha: f32) {
     LeakyRelu { alpha }.apply(input)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct LeakyRelu {
     pub alpha: f32,
 }
@@ -0,0 +1,890 @@
+//! Post-training quantization of models.
+//!
+//! This module converts the weights of `MatMul` and `Conv` operators in a
+//! float model to int8, and rewrites the graph to use integer matrix
+//! multiplication and convolution. See [`QuantizeOptions`] for details.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use rten_tensor::prelude::*;
+use rten_tensor::{Tensor, TensorView};
+use rustc_hash::FxHashMap;
+
+use crate::downcast::DowncastDyn;
+use crate::graph::{Constant, Graph, Node, NodeId, RunError};
+use crate::model::Model;
+use crate::ops;
+use crate::ops::{DataType, InputOrOutput, Operator};
+
+/// Specifies how activations (the inputs to quantized operators) are
+/// quantized.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum QuantizeMode {
+    /// Compute the scale and zero point for activations during inference,
+    /// using `DynamicQuantizeLinear`.
+    #[default]
+    Dynamic,
+
+    /// Compute the scale and zero point for activations ahead of time, using
+    /// the value ranges observed when running calibration inputs through the
+    /// model.
+    Static,
+}
+
+/// Specifies which weight elements share a quantization scale.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Granularity {
+    /// Use a single scale for the whole weight tensor.
+    PerTensor,
+
+    /// Use one scale per output channel. This is the column of the weights
+    /// for `MatMul` and the first dimension of the kernel for `Conv`.
+    #[default]
+    PerChannel,
+
+    /// Use one scale per block of `N` consecutive rows in each column of
+    /// `MatMul` weights.
+    ///
+    /// This quantizes the weights only. The weights are dequantized during
+    /// inference and the matrix multiplication is performed in float. `Conv`
+    /// operators, and `MatMul` operators whose row count is not a multiple of
+    /// the block size, use per-channel quantization instead.
+    Block(usize),
+}
+
+/// Options which control how a model is quantized.
+///
+/// Weights are quantized symmetrically to int8 (the zero point is always
+/// zero). Activations are quantized asymmetrically to uint8. This is the
+/// combination which RTen is optimized for.
+///
+/// The model to be quantized should be loaded with graph optimization disabled
+/// (see [`ModelOptions::enable_optimization`](crate::ModelOptions::enable_optimization)),
+/// as only un-fused `MatMul` and `Conv` operators are quantized.
+///
+/// ```no_run
+/// use rten::ModelOptions;
+/// use rten::quantization::QuantizeOptions;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let model = ModelOptions::with_all_ops()
+///     .enable_optimization(false)
+///     .load_file("model.rten")?;
+/// let quantized = QuantizeOptions::default().quantize(model, &[])?;
+/// quantized.save("model.quant.rten")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct QuantizeOptions {
+    mode: QuantizeMode,
+    granularity: Granularity,
+    reduce_range: bool,
+    quantize_conv: bool,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        QuantizeOptions {
+            mode: QuantizeMode::default(),
+            granularity: Granularity::default(),
+            reduce_range: true,
+            quantize_conv: false,
+        }
+    }
+}
+
+impl QuantizeOptions {
+    /// Set whether activations are quantized dynamically or statically.
+    pub fn mode(&mut self, mode: QuantizeMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set which weight elements share a quantization scale.
+    pub fn granularity(&mut self, granularity: Granularity) -> &mut Self {
+        self.granularity = granularity;
+        self
+    }
+
+    /// Set whether weights are restricted to 7-bit values ([-63, 63]).
+    ///
+    /// This avoids saturation of intermediate results on x64 CPUs which do
+    /// not support VNNI. It is enabled by default.
+    pub fn reduce_range(&mut self, reduce_range: bool) -> &mut Self {
+        self.reduce_range = reduce_range;
+        self
+    }
+
+    /// Set whether `Conv` operators are quantized, in addition to `MatMul`
+    /// operators.
+    ///
+    /// This is disabled by default.
+    pub fn quantize_conv(&mut self, quantize_conv: bool) -> &mut Self {
+        self.quantize_conv = quantize_conv;
+        self
+    }
+
+    /// Quantize `model` and return the quantized model.
+    ///
+    /// The replaced float operators and weights are omitted when the result
+    /// is saved using [`Model::save`].
+    ///
+    /// `calibration` is a list of inputs to run through the model in order to
+    /// determine the range of activation values. It is required when using
+    /// [`QuantizeMode::Static`] and ignored otherwise.
+    ///
+    /// Only operators in the model's main graph are quantized. Subgraphs are
+    /// preserved as-is.
+    pub fn quantize(
+        &self,
+        model: Model,
+        calibration: &[Vec<(NodeId, InputOrOutput)>],
+    ) -> Result<Model, QuantizeError> {
+        let targets: Vec<Target> = find_targets(&model, self.quantize_conv);
+
+        let ranges = match self.mode {
+            QuantizeMode::Dynamic => FxHashMap::default(),
+            QuantizeMode::Static => {
+                if calibration.is_empty() {
+                    return Err(QuantizeError::MissingCalibrationData);
+                }
+                activation_ranges(&model, &targets, calibration)?
+            }
+        };
+
+        let (mut graph, metadata) = model.into_parts();
+
+        let mut quantizer = GraphQuantizer {
+            graph: &mut graph,
+            options: self,
+            ranges,
+            activations: FxHashMap::default(),
+        };
+        for target in targets {
+            quantizer.quantize_target(target);
+        }
+
+        Ok(Model::from_parts(graph, metadata))
+    }
+}
+
+/// Errors that occur when quantizing a model.
+#[derive(Debug)]
+pub enum QuantizeError {
+    /// Static quantization was requested but no calibration inputs were
+    /// provided.
+    MissingCalibrationData,
+
+    /// Running a calibration input through the model failed.
+    CalibrationFailed(RunError),
+}
+
+impl Display for QuantizeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingCalibrationData => {
+                write!(f, "static quantization requires calibration inputs")
+            }
+            Self::CalibrationFailed(err) => write!(f, "calibration failed: {}", err),
+        }
+    }
+}
+
+impl Error for QuantizeError {}
+
+/// Operator kinds that can be quantized.
+enum TargetKind {
+    MatMul,
+    Conv { op: ops::Conv, bias: Option<NodeId> },
+}
+
+/// An operator to be quantized.
+struct Target {
+    name: String,
+    kind: TargetKind,
+    input: NodeId,
+    weights: NodeId,
+    output: NodeId,
+}
+
+/// Return the float constant with a given ID, if there is one.
+fn float_constant(graph: &Graph, id: NodeId) -> Option<TensorView<'_, f32>> {
+    match graph.get_node(id) {
+        Some(Node::Constant(Constant::Float(constant))) => Some(constant.view()),
+        _ => None,
+    }
+}
+
+/// Find `MatMul` and `Conv` operators in the model's main graph which have
+/// constant float weights.
+fn find_targets(model: &Model, quantize_conv: bool) -> Vec<Target> {
+    let graph = model.graph();
+    let mut targets = Vec::new();
+
+    for (op_id, node) in graph.iter() {
+        let Node::Operator(op_node) = node else {
+            continue;
+        };
+        let (&[Some(input), Some(weights), ref rest @ ..], &[Some(output)]) =
+            (op_node.input_ids(), op_node.output_ids())
+        else {
+            continue;
+        };
+
+        // Skip operators which have been replaced.
+        if graph.get_source_node(output).map(|(id, _)| id) != Some(op_id) {
+            continue;
+        }
+
+        let Some(weight_val) = float_constant(graph, weights) else {
+            continue;
+        };
+        let op = op_node.operator();
+
+        let kind = if op.is::<ops::MatMul>() && rest.is_empty() && weight_val.ndim() == 2 {
+            TargetKind::MatMul
+        } else if let Some(conv) = op.downcast_ref::<ops::Conv>() {
+            let bias = rest.first().copied().flatten();
+            let bias_is_const = bias.is_none_or(|id| float_constant(graph, id).is_some());
+            if !quantize_conv || weight_val.ndim() < 3 || !bias_is_const {
+                continue;
+            }
+            TargetKind::Conv {
+                op: conv.clone(),
+                bias,
+            }
+        } else {
+            continue;
+        };
+
+        let name = op_node
+            .name()
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("node_{}", op_id.as_u32()));
+
+        targets.push(Target {
+            name,
+            kind,
+            input,
+            weights,
+            output,
+        });
+    }
+
+    targets
+}
+
+/// Run calibration inputs through the model and return the `(min, max)` range
+/// of each operator input which will be quantized.
+fn activation_ranges(
+    model: &Model,
+    targets: &[Target],
+    calibration: &[Vec<(NodeId, InputOrOutput)>],
+) -> Result<FxHashMap<NodeId, (f32, f32)>, QuantizeError> {
+    let mut activation_ids: Vec<NodeId> = targets.iter().map(|t| t.input).collect();
+    activation_ids.sort();
+    activation_ids.dedup();
+
+    let mut ranges = FxHashMap::default();
+    for inputs in calibration {
+        let outputs = model
+            .run(inputs.clone(), &activation_ids, None)
+            .map_err(QuantizeError::CalibrationFailed)?;
+
+        for (&id, output) in activation_ids.iter().zip(outputs) {
+            let Some(values) = output.as_tensor_view::<f32>() else {
+                continue;
+            };
+            let (min, max) = values
+                .iter()
+                .fold((0f32, 0f32), |(min, max), &x| (min.min(x), max.max(x)));
+            let range = ranges.entry(id).or_insert((min, max));
+            *range = (range.0.min(min), range.1.max(max));
+        }
+    }
+
+    Ok(ranges)
+}
+
+/// Compute the scale and zero point for a uint8 activation which has values in
+/// the range `[min, max]`.
+fn activation_scale_zero_point(min: f32, max: f32) -> (f32, u8) {
+    let (min, max) = (min.min(0.), max.max(0.));
+    let scale = if max > min { (max - min) / 255. } else { 1. };
+    let zero_point = (-min / scale).round().clamp(0., 255.) as u8;
+    (scale, zero_point)
+}
+
+/// Symmetrically quantize `values`.
+///
+/// `scale_index` maps the index of each element in `values` to the index of
+/// the scale it uses. Returns a tuple of `(quantized_values, scales)`.
+fn quantize_weights(
+    values: &[f32],
+    n_scales: usize,
+    scale_index: impl Fn(usize) -> usize,
+    q_max: i8,
+) -> (Vec<i8>, Vec<f32>) {
+    let mut max_abs = vec![0f32; n_scales];
+    for (i, &x) in values.iter().enumerate() {
+        let max = &mut max_abs[scale_index(i)];
+        *max = max.max(x.abs());
+    }
+
+    let scales: Vec<f32> = max_abs
+        .into_iter()
+        .map(|max| if max > 0. { max / q_max as f32 } else { 1. })
+        .collect();
+
+    let q_max = q_max as f32;
+    let quantized = values
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| (x / scales[scale_index(i)]).round().clamp(-q_max, q_max) as i8)
+        .collect();
+
+    (quantized, scales)
+}
+
+/// IDs of the nodes produced by quantizing an activation.
+#[derive(Copy, Clone)]
+struct QuantizedActivation {
+    quant: NodeId,
+    zero_point: NodeId,
+
+    /// Node containing the activation's scale.
+    scale: NodeId,
+
+    /// Value of the activation's scale, if it was computed ahead of time.
+    static_scale: Option<f32>,
+}
+
+/// Rewrites quantization targets in a graph.
+struct GraphQuantizer<'a> {
+    graph: &'a mut Graph,
+    options: &'a QuantizeOptions,
+
+    /// Ranges of activations observed during calibration.
+    ranges: FxHashMap<NodeId, (f32, f32)>,
+
+    /// Map of float activation ID to quantized activation. This enables the
+    /// quantized activation to be shared by operators with the same input.
+    activations: FxHashMap<NodeId, QuantizedActivation>,
+}
+
+impl GraphQuantizer<'_> {
+    fn quantize_target(&mut self, target: Target) {
+        let q_max = if self.options.reduce_range { 63 } else { 127 };
+        let weights = float_constant(self.graph, target.weights)
+            .expect("weights should be a float constant")
+            .to_tensor();
+
+        match target.kind {
+            TargetKind::MatMul => {
+                let [k, n] = weights.shape().try_into().unwrap();
+                match self.options.granularity {
+                    Granularity::Block(block_size)
+                        if block_size > 0 && k % block_size == 0 && block_size < k =>
+                    {
+                        let (quant, scales) = quantize_weights(
+                            weights.data().unwrap(),
+                            (k / block_size) * n,
+                            |i| (i / n / block_size) * n + i % n,
+                            q_max,
+                        );
+                        let quant = Tensor::from_data(&[k / block_size, block_size, n], quant);
+                        let scales = Tensor::from_data(&[k / block_size, 1, n], scales);
+                        self.add_block_matmul(&target, [k, n], quant, scales);
+                    }
+                    Granularity::PerTensor => {
+                        let (quant, scales) =
+                            quantize_weights(weights.data().unwrap(), 1, |_| 0, q_max);
+                        let quant = Tensor::from_data(&[k, n], quant);
+                        let scales = Tensor::from_data(&[], scales);
+                        self.add_integer_op(&target, quant, scales, None);
+                    }
+                    Granularity::PerChannel | Granularity::Block(_) => {
+                        let (quant, scales) =
+                            quantize_weights(weights.data().unwrap(), n, |i| i % n, q_max);
+                        let quant = Tensor::from_data(&[k, n], quant);
+                        let scales = Tensor::from_data(&[n], scales);
+                        self.add_integer_op(&target, quant, scales, None);
+                    }
+                }
+            }
+            TargetKind::Conv { ref op, bias } => {
+                let out_chans = weights.size(0);
+                let chan_len = weights.len() / out_chans;
+                let (quant, scales) = match self.options.granularity {
+                    Granularity::PerTensor => {
+                        quantize_weights(weights.data().unwrap(), 1, |_| 0, q_max)
+                    }
+                    Granularity::PerChannel | Granularity::Block(_) => quantize_weights(
+                        weights.data().unwrap(),
+                        out_chans,
+                        |i| i / chan_len,
+                        q_max,
+                    ),
+                };
+                let quant = Tensor::from_data(weights.shape(), quant);
+
+                // Reshape scales and bias so they broadcast over an NCHW
+                // output.
+                let spatial_dims = weights.ndim() - 2;
+                let mut scale_shape = vec![scales.len()];
+                scale_shape.extend(std::iter::repeat_n(1, spatial_dims));
+                let scales = Tensor::from_data(&scale_shape, scales);
+
+                let conv_integer = ops::ConvInteger {
+                    groups: op.groups,
+                    dilations: op.dilations.clone(),
+                    padding: op.padding.clone(),
+                    strides: op.strides.clone(),
+                };
+                let bias = bias.map(|bias_id| {
+                    let bias = float_constant(self.graph, bias_id)
+                        .expect("bias should be a float constant")
+                        .to_tensor();
+                    let mut bias_shape = vec![bias.len()];
+                    bias_shape.extend(std::iter::repeat_n(1, spatial_dims));
+                    bias.into_shape(bias_shape.as_slice())
+                });
+                self.add_integer_op(&target, quant, scales, Some((conv_integer, bias)));
+            }
+        }
+    }
+
+    /// Quantize an operator input, or return the existing quantized value if
+    /// it was quantized for another operator.
+    fn quantize_activation(&mut self, name: &str, input: NodeId) -> QuantizedActivation {
+        if let Some(activation) = self.activations.get(&input) {
+            return *activation;
+        }
+
+        let quant = self.add_value(&format!("{name}_quant"));
+        let activation = if let Some(&(min, max)) = self.ranges.get(&input) {
+            let (scale, zero_point) = activation_scale_zero_point(min, max);
+            let scale_id = self
+                .graph
+                .add_constant(Some(&format!("{name}_scale")), Tensor::from_scalar(scale));
+            let zero_point_id = self.graph.add_constant(
+                Some(&format!("{name}_zero_point")),
+                Tensor::from_scalar(zero_point),
+            );
+            self.graph.add_op(
+                Some(&format!("{name}_QuantizeLinear")),
+                Box::new(ops::QuantizeLinear {
+                    axis: 1,
+                    output_dtype: None,
+                }),
+                &[Some(input), Some(scale_id), Some(zero_point_id)],
+                &[Some(quant)],
+            );
+            QuantizedActivation {
+                quant,
+                zero_point: zero_point_id,
+                scale: scale_id,
+                static_scale: Some(scale),
+            }
+        } else {
+            let scale = self.add_value(&format!("{name}_scale"));
+            let zero_point = self.add_value(&format!("{name}_zero_point"));
+            self.graph.add_op(
+                Some(&format!("{name}_DynamicQuantizeLinear")),
+                Box::new(ops::DynamicQuantizeLinear {}),
+                &[Some(input)],
+                &[Some(quant), Some(scale), Some(zero_point)],
+            );
+            QuantizedActivation {
+                quant,
+                zero_point,
+                scale,
+                static_scale: None,
+            }
+        };
+
+        self.activations.insert(input, activation);
+        activation
+    }
+
+    /// Replace `target` with an integer `MatMulInteger` or `ConvInteger`
+    /// operator whose output is converted back to float.
+    ///
+    /// `conv` specifies the `ConvInteger` operator and optional bias to use,
+    /// or `None` to use `MatMulInteger`.
+    fn add_integer_op(
+        &mut self,
+        target: &Target,
+        weights: Tensor<i8>,
+        weight_scales: Tensor<f32>,
+        conv: Option<(ops::ConvInteger, Option<Tensor<f32>>)>,
+    ) {
+        let name = &target.name;
+        let activation = self.quantize_activation(&format!("{name}_input"), target.input);
+        let weights = self
+            .graph
+            .add_constant(Some(&format!("{name}_weights_quant")), weights);
+
+        let (op, op_name): (Box<dyn Operator + Send + Sync>, _) = match &conv {
+            Some((conv_op, _)) => (Box::new(conv_op.clone()), "ConvInteger"),
+            None => (Box::new(ops::MatMulInteger {}), "MatMulInteger"),
+        };
+        let int_out = self.add_value(&format!("{name}_{op_name}_out"));
+        self.graph.add_op(
+            Some(&format!("{name}_{op_name}")),
+            op,
+            &[
+                Some(activation.quant),
+                Some(weights),
+                Some(activation.zero_point),
+            ],
+            &[Some(int_out)],
+        );
+
+        let float_out = self.add_value(&format!("{name}_Cast_out"));
+        self.graph.add_op(
+            Some(&format!("{name}_Cast")),
+            Box::new(ops::Cast {
+                to: DataType::Float,
+            }),
+            &[Some(int_out)],
+            &[Some(float_out)],
+        );
+
+        // Combine the activation and weight scales. When the activation scale
+        // is known ahead of time, this can be done now.
+        let scale = if let Some(act_scale) = activation.static_scale {
+            self.graph.add_constant(
+                Some(&format!("{name}_scale")),
+                weight_scales.map(|s| s * act_scale),
+            )
+        } else {
+            let weight_scales = self
+                .graph
+                .add_constant(Some(&format!("{name}_weights_scale")), weight_scales);
+            let scale = self.add_value(&format!("{name}_scale"));
+            self.graph.add_op(
+                Some(&format!("{name}_scale_Mul")),
+                Box::new(ops::Mul {}),
+                &[Some(activation.scale), Some(weight_scales)],
+                &[Some(scale)],
+            );
+            scale
+        };
+
+        let bias = conv
+            .and_then(|(_, bias)| bias)
+            .map(|bias| self.graph.add_constant(Some(&format!("{name}_bias")), bias));
+        let scaled_out = if bias.is_some() {
+            self.add_value(&format!("{name}_Mul_out"))
+        } else {
+            target.output
+        };
+        self.graph.add_op(
+            Some(&format!("{name}_Mul")),
+            Box::new(ops::Mul {}),
+            &[Some(float_out), Some(scale)],
+            &[Some(scaled_out)],
+        );
+
+        if let Some(bias) = bias {
+            self.graph.add_op(
+                Some(&format!("{name}_Add")),
+                Box::new(ops::Add {}),
+                &[Some(scaled_out), Some(bias)],
+                &[Some(target.output)],
+            );
+        }
+    }
+
+    /// Replace a `MatMul` with one that uses block-quantized weights, which
+    /// are dequantized during inference.
+    fn add_block_matmul(
+        &mut self,
+        target: &Target,
+        weight_shape: [usize; 2],
+        weights: Tensor<i8>,
+        scales: Tensor<f32>,
+    ) {
+        let name = &target.name;
+        let weights = self
+            .graph
+            .add_constant(Some(&format!("{name}_weights_quant")), weights);
+        let scales = self
+            .graph
+            .add_constant(Some(&format!("{name}_weights_scale")), scales);
+        let shape = self.graph.add_constant(
+            Some(&format!("{name}_weights_shape")),
+            Tensor::from(weight_shape.map(|size| size as i32)),
+        );
+
+        let cast_out = self.add_value(&format!("{name}_weights_Cast_out"));
+        self.graph.add_op(
+            Some(&format!("{name}_weights_Cast")),
+            Box::new(ops::Cast {
+                to: DataType::Float,
+            }),
+            &[Some(weights)],
+            &[Some(cast_out)],
+        );
+
+        let dequant_out = self.add_value(&format!("{name}_weights_Mul_out"));
+        self.graph.add_op(
+            Some(&format!("{name}_weights_Mul")),
+            Box::new(ops::Mul {}),
+            &[Some(cast_out), Some(scales)],
+            &[Some(dequant_out)],
+        );
+
+        let reshape_out = self.add_value(&format!("{name}_weights_Reshape_out"));
+        self.graph.add_op(
+            Some(&format!("{name}_weights_Reshape")),
+            Box::new(ops::Reshape { allow_zero: false }),
+            &[Some(dequant_out), Some(shape)],
+            &[Some(reshape_out)],
+        );
+
+        self.graph.add_op(
+            Some(name),
+            Box::new(ops::MatMul {}),
+            &[Some(target.input), Some(reshape_out)],
+            &[Some(target.output)],
+        );
+    }
+
+    fn add_value(&mut self, name: &str) -> NodeId {
+        self.graph.add_value(Some(name), None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_tensor::prelude::*;
+    use rten_tensor::test_util::expect_equal_with_tolerance;
+    use rten_tensor::Tensor;
+    use rten_testing::TestCases;
+
+    use super::{
+        activation_scale_zero_point, quantize_weights, Granularity, QuantizeError, QuantizeMode,
+        QuantizeOptions,
+    };
+    use crate::graph::{Dimension, Node};
+    use crate::model_builder::{ModelBuilder, ModelFormat, OpType};
+    use crate::ops::{Conv, DataType, Padding};
+    use crate::{Model, ModelOptions};
+
+    /// Create a model with a `MatMul` operator followed by a `Conv` operator.
+    fn build_model() -> Vec<u8> {
+        let mut builder = ModelBuilder::new(ModelFormat::V2);
+        let mut graph_builder = builder.graph_builder();
+
+        let input_shape = [1, 1, 4, 8].map(Dimension::Fixed);
+        let input = graph_builder.add_value("input", Some(&input_shape), Some(DataType::Float));
+        graph_builder.add_input(input);
+
+        let matmul_weights =
+            Tensor::from_fn(&[8, 8], |idx| ((idx[0] * 8 + idx[1]) as f32 * 0.37).sin());
+        let matmul_weights = graph_builder.add_constant(matmul_weights.view());
+        let matmul_out = graph_builder.add_value("matmul_out", None, None);
+        graph_builder.add_operator(
+            "matmul",
+            OpType::MatMul,
+            &[Some(input), Some(matmul_weights)],
+            &[matmul_out],
+        );
+
+        let conv_weights = Tensor::from_fn(&[3, 1, 3, 3], |idx| {
+            ((idx[0] * 9 + idx[2] * 3 + idx[3]) as f32).cos()
+        });
+        let conv_weights = graph_builder.add_constant(conv_weights.view());
+        let conv_bias = graph_builder.add_constant(Tensor::from([0.5, -0.5, 1.0]).view());
+        let conv_out = graph_builder.add_value("output", None, None);
+        graph_builder.add_operator(
+            "conv",
+            OpType::Conv(Conv {
+                groups: 1,
+                dilations: vec![1, 1],
+                padding: Padding::Fixed([1, 1, 1, 1].into()),
+                strides: vec![1, 1],
+            }),
+            &[Some(matmul_out), Some(conv_weights), Some(conv_bias)],
+            &[conv_out],
+        );
+        graph_builder.add_output(conv_out);
+
+        let graph = graph_builder.finish();
+        builder.set_graph(graph);
+        builder.finish()
+    }
+
+    fn load_model(data: Vec<u8>) -> Model {
+        ModelOptions::with_all_ops()
+            .enable_optimization(false)
+            .load(data)
+            .unwrap()
+    }
+
+    fn run_model(model: &Model, input: &Tensor<f32>) -> Tensor<f32> {
+        model
+            .run_one(input.view().into(), None)
+            .unwrap()
+            .into_tensor::<f32>()
+            .unwrap()
+    }
+
+    /// Return the number of operators of a given type in a model.
+    ///
+    /// Operators which have been replaced are not counted.
+    fn count_ops(model: &Model, op_type: &str) -> usize {
+        let graph = model.graph();
+        graph
+            .iter()
+            .filter(|&(op_id, node)| match node {
+                Node::Operator(op) => {
+                    op.operator().name() == op_type
+                        && op.output_ids().iter().flatten().any(|&output| {
+                            graph.get_source_node(output).map(|(id, _)| id) == Some(op_id)
+                        })
+                }
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_quantize_weights() {
+        let (quant, scales) = quantize_weights(&[1., -2., 0.5, 4.], 2, |i| i % 2, 127);
+        assert_eq!(scales, [1. / 127., 4. / 127.]);
+        assert_eq!(quant, [127, -64, 64, 127]);
+
+        // With range reduction, values should be in [-63, 63].
+        let (quant, _) = quantize_weights(&[1., -3., 0.5, 4.], 1, |_| 0, 63);
+        assert_eq!(quant, [16, -47, 8, 63]);
+
+        // Zero weights should not produce a zero scale.
+        let (quant, scales) = quantize_weights(&[0., 0.], 1, |_| 0, 127);
+        assert_eq!(scales, [1.]);
+        assert_eq!(quant, [0, 0]);
+    }
+
+    #[test]
+    fn test_activation_scale_zero_point() {
+        assert_eq!(activation_scale_zero_point(0., 255.), (1., 0));
+        assert_eq!(activation_scale_zero_point(-255., 0.), (1., 255));
+        assert_eq!(activation_scale_zero_point(2., 2.), (2. / 255., 0));
+        assert_eq!(activation_scale_zero_point(0., 0.), (1., 0));
+    }
+
+    #[test]
+    fn test_quantize() {
+        #[derive(Debug)]
+        struct Case {
+            mode: QuantizeMode,
+            granularity: Granularity,
+            quantize_conv: bool,
+            expected_ops: &'static [(&'static str, usize)],
+        }
+
+        let cases = [
+            Case {
+                mode: QuantizeMode::Dynamic,
+                granularity: Granularity::PerChannel,
+                quantize_conv: false,
+                expected_ops: &[
+                    ("DynamicQuantizeLinear", 1),
+                    ("MatMulInteger", 1),
+                    ("MatMul", 0),
+                    ("Conv", 1),
+                ],
+            },
+            Case {
+                mode: QuantizeMode::Dynamic,
+                granularity: Granularity::PerTensor,
+                quantize_conv: true,
+                expected_ops: &[
+                    ("DynamicQuantizeLinear", 2),
+                    ("MatMulInteger", 1),
+                    ("ConvInteger", 1),
+                    ("Conv", 0),
+                ],
+            },
+            Case {
+                mode: QuantizeMode::Static,
+                granularity: Granularity::PerChannel,
+                quantize_conv: true,
+                expected_ops: &[
+                    ("DynamicQuantizeLinear", 0),
+                    ("QuantizeLinear", 2),
+                    ("MatMulInteger", 1),
+                    ("ConvInteger", 1),
+                ],
+            },
+            Case {
+                mode: QuantizeMode::Dynamic,
+                granularity: Granularity::Block(4),
+                quantize_conv: false,
+                expected_ops: &[("MatMulInteger", 0), ("MatMul", 1), ("Conv", 1)],
+            },
+        ];
+
+        cases.test_each(|case| {
+            let model = load_model(build_model());
+            let input = Tensor::from_fn(&[1, 1, 4, 8], |idx| {
+                ((idx[2] * 8 + idx[3]) as f32 * 0.71).sin() * 2.
+            });
+            let expected = run_model(&model, &input);
+
+            let calibration = vec![vec![(model.input_ids()[0], input.view().into())]];
+            let quant_model = QuantizeOptions::default()
+                .mode(case.mode)
+                .granularity(case.granularity)
+                .quantize_conv(case.quantize_conv)
+                .quantize(model, &calibration)
+                .unwrap();
+
+            for &(op_type, count) in case.expected_ops {
+                assert_eq!(count_ops(&quant_model, op_type), count, "{op_type} count");
+            }
+
+            let actual = run_model(&quant_model, &input);
+            assert_eq!(actual.shape(), expected.shape());
+
+            let max_abs = expected.iter().fold(0f32, |max, x| max.max(x.abs()));
+            expect_equal_with_tolerance(&actual, &expected, 0.1 * max_abs, 0.).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_quantize_static_requires_calibration() {
+        let model = load_model(build_model());
+        let result = QuantizeOptions::default()
+            .mode(QuantizeMode::Static)
+            .quantize(model, &[]);
+        assert!(matches!(result, Err(QuantizeError::MissingCalibrationData)));
+    }
+
+    #[test]
+    fn test_quantize_preserves_unquantized_ops() {
+        let model = load_model(build_model());
+        let model = QuantizeOptions::default().quantize(model, &[]).unwrap();
+
+        assert!(model.find_node("input").is_some());
+        assert!(model.find_node("output").is_some());
+        assert_eq!(
+            model.input_shape(0),
+            Some([1, 1, 4, 8].map(Dimension::Fixed).to_vec())
+        );
+
+        assert_eq!(count_ops(&model, "MatMul"), 0);
+        assert_eq!(count_ops(&model, "Conv"), 1);
+
+        // The replaced MatMul operator and its float weights should be
+        // removed when the model is saved.
+        let model = load_model(model.serialize().unwrap());
+        assert_eq!(count_ops(&model, "MatMul"), 0);
+        assert_eq!(count_ops(&model, "Conv"), 1);
+        assert_eq!(model.total_params(), 8 * 8 + 8 + 3 * 9 + 3);
+    }
+}
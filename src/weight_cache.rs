@@ -1,7 +1,15 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
 use rustc_hash::FxHashMap;
 
-use crate::graph::NodeId;
+use crate::constant_storage::{ArcSlice, ConstantStorage};
+use crate::gemm::{GemmExecutor, PackElem};
+use crate::graph::{Constant, Graph, Node, NodeId};
+use crate::header::ValueReader;
 use crate::ops::PrepackedInput;
+use crate::slice_cast::cast_pod_slice;
 
 /// A cache of prepacked weights for graph operators.
 ///
@@ -52,6 +60,21 @@ impl WeightCache {
             .map(|wcs| wcs.as_slice())
     }
 
+    /// Remove and return the caches for an operator's subgraphs.
+    pub fn remove_subgraph_caches(&mut self, operator_id: NodeId) -> Option<Vec<WeightCache>> {
+        self.subgraph_caches.remove(&operator_id)
+    }
+
+    /// Return the cache for the `index`th subgraph of an operator, creating
+    /// it if it does not exist.
+    fn subgraph_cache_mut(&mut self, operator_id: NodeId, index: usize) -> &mut WeightCache {
+        let caches = self.subgraph_caches.entry(operator_id).or_default();
+        if caches.len() <= index {
+            caches.resize_with(index + 1, WeightCache::new);
+        }
+        &mut caches[index]
+    }
+
     /// Return the total number of cached weights, including in subgraphs.
     pub fn len(&self) -> usize {
         self.cache.len()
@@ -69,3 +92,466 @@ impl Default for WeightCache {
         WeightCache::new()
     }
 }
+
+/// Magic bytes at the start of a prepacked weights file.
+const PREPACKED_MAGIC: &[u8; 4] = b"RTPW";
+
+/// Version of the prepacked weights file format.
+const PREPACKED_VERSION: u32 = 1;
+
+/// Alignment of packed data within a prepacked weights file.
+const PREPACKED_DATA_ALIGN: usize = 64;
+
+/// Element type identifiers for entries in a prepacked weights file.
+const DTYPE_FLOAT: u8 = 0;
+const DTYPE_INT8: u8 = 1;
+
+/// Errors produced when reading a prepacked weights file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrepackedWeightsError {
+    /// The file doesn't start with the magic bytes "RTPW".
+    InvalidMagic,
+
+    /// The file format version is unsupported.
+    UnsupportedVersion,
+
+    /// The file ended before all entries were read.
+    TooShort,
+}
+
+impl Display for PrepackedWeightsError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(fmt, "incorrect file magic"),
+            Self::UnsupportedVersion => write!(fmt, "unsupported file version"),
+            Self::TooShort => write!(fmt, "file is too short"),
+        }
+    }
+}
+
+impl Error for PrepackedWeightsError {}
+
+/// A prepacked weight in a prepacked weights file.
+struct PrepackedEntry<'a> {
+    /// Path of the constant node which was packed. This is a sequence of
+    /// `(operator_id, subgraph_index)` pairs that lead to the graph containing
+    /// the node, followed by the node ID.
+    path: Vec<u32>,
+    dtype: u8,
+    kernel_name: &'a str,
+    rows: usize,
+    cols: usize,
+    data: &'a [u8],
+}
+
+impl PrepackedEntry<'_> {
+    /// Return the size of the entry's metadata in the file.
+    fn header_len(&self) -> usize {
+        4 + self.path.len() * 4 + 1 + 4 + self.kernel_name.len() + 4 * 8
+    }
+}
+
+impl WeightCache {
+    /// Add entries for the weights in this cache and its subgraph caches to
+    /// `entries`.
+    fn collect_entries<'a>(&'a self, path: &mut Vec<u32>, entries: &mut Vec<PrepackedEntry<'a>>) {
+        for (node_id, packed) in &self.cache {
+            let mut entry_path = path.clone();
+            entry_path.push(node_id.as_u32());
+
+            let (dtype, kernel_name, rows, cols, data) = match packed {
+                PrepackedInput::FloatBMatrix(m) => (
+                    DTYPE_FLOAT,
+                    m.kernel_name(),
+                    m.rows(),
+                    m.cols(),
+                    m.as_bytes(),
+                ),
+                PrepackedInput::Int8BMatrix(m) => (
+                    DTYPE_INT8,
+                    m.kernel_name(),
+                    m.rows(),
+                    m.cols(),
+                    m.as_bytes(),
+                ),
+            };
+            entries.push(PrepackedEntry {
+                path: entry_path,
+                dtype,
+                kernel_name,
+                rows,
+                cols,
+                data,
+            });
+        }
+
+        for (op_id, caches) in &self.subgraph_caches {
+            for (index, cache) in caches.iter().enumerate() {
+                path.extend([op_id.as_u32(), index as u32]);
+                cache.collect_entries(path, entries);
+                path.truncate(path.len() - 2);
+            }
+        }
+    }
+
+    /// Serialize the weights in this cache to a prepacked weights file.
+    ///
+    /// `fingerprint` identifies the model and load options that the weights
+    /// were packed for. When the file is loaded using
+    /// [`deserialize`](WeightCache::deserialize), it is ignored if the
+    /// fingerprint does not match.
+    ///
+    /// The file consists of a header followed by metadata for each weight and
+    /// then the packed data for each weight. All values are little-endian.
+    pub fn serialize(&self, fingerprint: u64) -> Vec<u8> {
+        let mut entries = Vec::new();
+        self.collect_entries(&mut Vec::new(), &mut entries);
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let header_len = 4 + 4 + 8 + 4;
+        let entries_len: usize = entries.iter().map(|e| e.header_len()).sum();
+        let mut data_offset = (header_len + entries_len).next_multiple_of(PREPACKED_DATA_ALIGN);
+
+        let mut buf = Vec::new();
+        buf.extend(PREPACKED_MAGIC);
+        buf.extend(PREPACKED_VERSION.to_le_bytes());
+        buf.extend(fingerprint.to_le_bytes());
+        buf.extend((entries.len() as u32).to_le_bytes());
+
+        for entry in &entries {
+            buf.extend((entry.path.len() as u32).to_le_bytes());
+            for id in &entry.path {
+                buf.extend(id.to_le_bytes());
+            }
+            buf.push(entry.dtype);
+            buf.extend((entry.kernel_name.len() as u32).to_le_bytes());
+            buf.extend(entry.kernel_name.as_bytes());
+            buf.extend((entry.rows as u64).to_le_bytes());
+            buf.extend((entry.cols as u64).to_le_bytes());
+            buf.extend((data_offset as u64).to_le_bytes());
+            buf.extend((entry.data.len() as u64).to_le_bytes());
+            data_offset = (data_offset + entry.data.len()).next_multiple_of(PREPACKED_DATA_ALIGN);
+        }
+
+        for entry in &entries {
+            buf.resize(buf.len().next_multiple_of(PREPACKED_DATA_ALIGN), 0);
+            buf.extend(entry.data);
+        }
+
+        buf
+    }
+
+    /// Load prepacked weights for `graph` from a file created by
+    /// [`serialize`](WeightCache::serialize).
+    ///
+    /// The packed data is referenced from `storage` rather than copied.
+    /// Weights are skipped if they were packed for a different GEMM kernel
+    /// than the one that will be used on this system, or if they don't match
+    /// the corresponding constant in the graph. If `fingerprint` doesn't match
+    /// the file, an empty cache is returned. Callers can use
+    /// [`Graph::prepack_weights`] to pack any weights that were skipped.
+    pub fn deserialize(
+        storage: Arc<ConstantStorage>,
+        graph: &Graph,
+        fingerprint: u64,
+    ) -> Result<WeightCache, PrepackedWeightsError> {
+        let mut cache = WeightCache::new();
+        let mut reader = ValueReader::new(storage.data());
+
+        let magic = reader
+            .read_n::<4>()
+            .ok_or(PrepackedWeightsError::TooShort)?;
+        if &magic != PREPACKED_MAGIC {
+            return Err(PrepackedWeightsError::InvalidMagic);
+        }
+        let version: u32 = reader.read().ok_or(PrepackedWeightsError::TooShort)?;
+        if version != PREPACKED_VERSION {
+            return Err(PrepackedWeightsError::UnsupportedVersion);
+        }
+        let file_fingerprint: u64 = reader.read().ok_or(PrepackedWeightsError::TooShort)?;
+        if file_fingerprint != fingerprint {
+            return Ok(cache);
+        }
+        let n_entries: u32 = reader.read().ok_or(PrepackedWeightsError::TooShort)?;
+
+        let float_gemm = GemmExecutor::<f32>::default();
+        let int8_gemm = GemmExecutor::<u8, i8, i32>::default();
+
+        for _ in 0..n_entries {
+            let entry = read_entry(&mut reader, storage.data())?;
+            let Some(data) = cast_pod_slice::<u8, PackElem>(entry.data)
+                .and_then(|data| ArcSlice::new(storage.clone(), data))
+            else {
+                // Data is not correctly aligned.
+                continue;
+            };
+            let Some((target_cache, constant, node_id)) = cache.resolve_entry(graph, &entry) else {
+                continue;
+            };
+
+            let packed = match (entry.dtype, constant) {
+                (DTYPE_FLOAT, Constant::Float(_)) => float_gemm
+                    .restore_packed_b(entry.kernel_name, entry.rows, entry.cols, data)
+                    .map(PrepackedInput::FloatBMatrix),
+                (DTYPE_INT8, Constant::Int8(_)) => int8_gemm
+                    .restore_packed_b(entry.kernel_name, entry.rows, entry.cols, data)
+                    .map(PrepackedInput::Int8BMatrix),
+                _ => None,
+            };
+            if let Some(packed) = packed {
+                target_cache.insert(node_id, packed);
+            }
+        }
+
+        Ok(cache)
+    }
+
+    /// Find the cache and constant node that a prepacked weights file entry
+    /// refers to.
+    ///
+    /// Returns `None` if the path doesn't refer to a 2D constant node with
+    /// the same shape as the packed matrix.
+    fn resolve_entry<'a, 'g>(
+        &'a mut self,
+        graph: &'g Graph,
+        entry: &PrepackedEntry,
+    ) -> Option<(&'a mut WeightCache, &'g Constant, NodeId)> {
+        let (&node_id, subgraph_path) = entry.path.split_last()?;
+        let mut graph = graph;
+        let mut cache = self;
+        for step in subgraph_path.chunks(2) {
+            let &[op_id, index] = step else {
+                return None;
+            };
+            let op_id = NodeId::from_u32(op_id);
+            let Some(Node::Operator(op_node)) = graph.get_node(op_id) else {
+                return None;
+            };
+            graph = op_node
+                .operator()
+                .subgraphs()
+                .get(index as usize)
+                .copied()?;
+            cache = cache.subgraph_cache_mut(op_id, index as usize);
+        }
+
+        let node_id = NodeId::from_u32(node_id);
+        let Some(Node::Constant(constant)) = graph.get_node(node_id) else {
+            return None;
+        };
+        let &[rows, cols] = constant.shape() else {
+            return None;
+        };
+        if rows != entry.rows || cols != entry.cols {
+            return None;
+        }
+        Some((cache, constant, node_id))
+    }
+}
+
+/// Read the next entry from a prepacked weights file.
+fn read_entry<'a>(
+    reader: &mut ValueReader<'a>,
+    file_data: &'a [u8],
+) -> Result<PrepackedEntry<'a>, PrepackedWeightsError> {
+    let too_short = PrepackedWeightsError::TooShort;
+
+    let path_len: u32 = reader.read().ok_or(too_short.clone())?;
+    let path = (0..path_len)
+        .map(|_| reader.read::<u32>())
+        .collect::<Option<Vec<_>>>()
+        .ok_or(too_short.clone())?;
+    let dtype: u8 = reader.read().ok_or(too_short.clone())?;
+    let name_len: u32 = reader.read().ok_or(too_short.clone())?;
+    let kernel_name = reader
+        .read_slice(name_len as usize)
+        .and_then(|name| std::str::from_utf8(name).ok())
+        .ok_or(too_short.clone())?;
+    let rows: u64 = reader.read().ok_or(too_short.clone())?;
+    let cols: u64 = reader.read().ok_or(too_short.clone())?;
+    let data_offset: u64 = reader.read().ok_or(too_short.clone())?;
+    let data_len: u64 = reader.read().ok_or(too_short.clone())?;
+    let data = file_data
+        .get(data_offset as usize..(data_offset.saturating_add(data_len)) as usize)
+        .ok_or(too_short)?;
+
+    Ok(PrepackedEntry {
+        path,
+        dtype,
+        kernel_name,
+        rows: rows as usize,
+        cols: cols as usize,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rten_tensor::prelude::*;
+    use rten_tensor::Tensor;
+
+    use super::{PrepackedWeightsError, WeightCache};
+    use crate::constant_storage::ConstantStorage;
+    use crate::gemm::GemmExecutor;
+    use crate::graph::{Graph, NodeId};
+    use crate::ops::{If, MatMul, PrepackedInput};
+
+    /// Create a graph with weights in the main graph and a subgraph.
+    ///
+    /// Returns `(graph, weights_id, if_op_id, subgraph_weights_id)`.
+    fn create_graph() -> (Graph, NodeId, NodeId, NodeId) {
+        let mut graph = Graph::new();
+        let input = graph.add_value(Some("input"), None, None);
+        let cond = graph.add_value(Some("cond"), None, None);
+
+        // Use a size that is not a multiple of the depth block size, so the
+        // packed data includes a partial block.
+        let weights = graph.add_constant(None, Tensor::<f32>::full(&[300, 20], 0.5));
+        let (_, matmul_out) = graph.add_simple_op("MatMul", MatMul {}, &[input, weights]);
+
+        let mut subgraph = Graph::new();
+        let sg_input = subgraph.add_value(Some("MatMul_out"), None, None);
+        let sg_weights = subgraph.add_constant(None, Tensor::<i8>::full(&[20, 5], 1));
+        let (_, sg_out) = subgraph.add_simple_op("sg-MatMul", MatMul {}, &[sg_input, sg_weights]);
+        subgraph.set_captures(&[sg_input]);
+        subgraph.set_output_ids(&[sg_out]);
+
+        let (if_op, if_out) = graph.add_simple_op(
+            "If",
+            If {
+                then_branch: subgraph,
+                else_branch: Graph::new(),
+            },
+            &[cond],
+        );
+        graph.set_input_ids(&[input, cond]);
+        graph.set_output_ids(&[matmul_out, if_out]);
+
+        (graph, weights, if_op, sg_weights)
+    }
+
+    fn packed_data(packed: &PrepackedInput) -> &[u8] {
+        match packed {
+            PrepackedInput::FloatBMatrix(m) => m.as_bytes(),
+            PrepackedInput::Int8BMatrix(m) => m.as_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_serialize_deserialize() {
+        let (graph, weights, if_op, sg_weights) = create_graph();
+        let mut cache = WeightCache::new();
+        graph.prepack_weights(&mut cache);
+        assert_eq!(cache.len(), 1);
+
+        // Add the subgraph weights manually, as `MatMul` only prepacks f32
+        // weights.
+        let int8_gemm = GemmExecutor::<u8, i8, i32>::default();
+        let sg_packed = int8_gemm.prepack_b(Tensor::<i8>::full(&[20, 5], 1).nd_view());
+        let mut sg_cache = WeightCache::new();
+        sg_cache.insert(sg_weights, sg_packed.into());
+        cache.insert_subgraph_caches(if_op, vec![sg_cache, WeightCache::new()]);
+
+        let fingerprint = 1234;
+        let data = cache.serialize(fingerprint);
+        let storage = Arc::new(ConstantStorage::Buffer(data));
+        let loaded = WeightCache::deserialize(storage.clone(), &graph, fingerprint).unwrap();
+        assert_eq!(loaded.len(), 2);
+
+        // Check packed data matches and references the file data.
+        let file_range = storage.data().as_ptr_range();
+        let expected = packed_data(cache.get(weights).unwrap());
+        let actual = packed_data(loaded.get(weights).unwrap());
+        assert_eq!(actual, expected);
+        assert!(file_range.contains(&actual.as_ptr()));
+
+        let loaded_sg = &loaded.get_subgraph_caches(if_op).unwrap()[0];
+        let expected = packed_data(
+            cache.get_subgraph_caches(if_op).unwrap()[0]
+                .get(sg_weights)
+                .unwrap(),
+        );
+        let actual = packed_data(loaded_sg.get(sg_weights).unwrap());
+        assert_eq!(actual, expected);
+        assert!(file_range.contains(&actual.as_ptr()));
+
+        // If the fingerprint doesn't match, the file is ignored.
+        let loaded = WeightCache::deserialize(storage, &graph, fingerprint + 1).unwrap();
+        assert_eq!(loaded.len(), 0);
+    }
+
+    #[test]
+    fn test_deserialize_skips_mismatched_kernel() {
+        let (graph, weights, _, _) = create_graph();
+        let mut cache = WeightCache::new();
+        graph.prepack_weights(&mut cache);
+        let PrepackedInput::FloatBMatrix(packed) = cache.get(weights).unwrap() else {
+            panic!("incorrect packed type");
+        };
+        let kernel_name = packed.kernel_name().to_string();
+
+        // Replace the kernel name with one of the same length.
+        let mut data = cache.serialize(0);
+        let name_pos = data
+            .windows(kernel_name.len())
+            .position(|window| window == kernel_name.as_bytes())
+            .unwrap();
+        data[name_pos..name_pos + kernel_name.len()].fill(b'x');
+
+        let storage = Arc::new(ConstantStorage::Buffer(data));
+        let loaded = WeightCache::deserialize(storage, &graph, 0).unwrap();
+        assert_eq!(loaded.len(), 0);
+    }
+
+    #[test]
+    fn test_deserialize_skips_mismatched_shape() {
+        let (graph, weights, _, _) = create_graph();
+        let mut cache = WeightCache::new();
+        graph.prepack_weights(&mut cache);
+        let data = cache.serialize(0);
+
+        // Load the file for a graph where the weights have the same number of
+        // elements, but transposed.
+        let mut transposed = Graph::new();
+        let input = transposed.add_value(Some("input"), None, None);
+        transposed.add_value(Some("cond"), None, None);
+        let transposed_weights =
+            transposed.add_constant(None, Tensor::<f32>::full(&[20, 300], 0.5));
+        assert_eq!(transposed_weights, weights);
+        let (_, out) = transposed.add_simple_op("MatMul", MatMul {}, &[input, transposed_weights]);
+        transposed.set_input_ids(&[input]);
+        transposed.set_output_ids(&[out]);
+
+        let storage = Arc::new(ConstantStorage::Buffer(data));
+        let loaded = WeightCache::deserialize(storage, &transposed, 0).unwrap();
+        assert_eq!(loaded.len(), 0);
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        let (graph, ..) = create_graph();
+        let mut cache = WeightCache::new();
+        graph.prepack_weights(&mut cache);
+        let data = cache.serialize(0);
+
+        let mut invalid_magic = data.clone();
+        invalid_magic[0] = b'X';
+
+        let mut invalid_version = data.clone();
+        invalid_version[4] = 99;
+
+        let truncated = data[..data.len() - 1].to_vec();
+
+        for (data, expected) in [
+            (invalid_magic, PrepackedWeightsError::InvalidMagic),
+            (invalid_version, PrepackedWeightsError::UnsupportedVersion),
+            (truncated, PrepackedWeightsError::TooShort),
+        ] {
+            let storage = Arc::new(ConstantStorage::Buffer(data));
+            let result = WeightCache::deserialize(storage, &graph, 0);
+            assert_eq!(result.err(), Some(expected));
+        }
+    }
+}